@@ -71,6 +71,9 @@ fn main() {
     let socket = std::net::UdpSocket::bind(bind_addr).unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -109,12 +112,13 @@ fn main() {
 
     // Create a QUIC connection and initiate handshake.
     let mut conn =
-        quiche::connect(url.domain(), &scid, peer_addr, &mut config).unwrap();
+        quiche::connect(url.domain(), &scid, local_addr, peer_addr, &mut config)
+            .unwrap();
 
     info!(
         "connecting to {:} from {:} with scid {}",
         peer_addr,
-        socket.local_addr().unwrap(),
+        local_addr,
         hex_dump(&scid)
     );
 
@@ -168,7 +172,11 @@ fn main() {
 
             debug!("got {} bytes", len);
 
-            let recv_info = quiche::RecvInfo { from };
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
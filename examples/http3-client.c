@@ -53,6 +53,9 @@ struct conn_io {
 
     int sock;
 
+    struct sockaddr_storage local_addr;
+    socklen_t local_addr_len;
+
     quiche_conn *conn;
 
     quiche_h3_conn *http3;
@@ -135,8 +138,10 @@ static void recv_cb(EV_P_ ev_io *w, int revents) {
 
         quiche_recv_info recv_info = {
             (struct sockaddr *) &peer_addr,
-
             peer_addr_len,
+
+            (struct sockaddr *) &conn_io->local_addr,
+            conn_io->local_addr_len,
         };
 
         ssize_t done = quiche_conn_recv(conn_io->conn, buf, read, &recv_info);
@@ -293,6 +298,21 @@ static void recv_cb(EV_P_ ev_io *w, int revents) {
                     fprintf(stderr, "got GOAWAY\n");
                     break;
                 }
+
+                case QUICHE_H3_EVENT_PUSH_PROMISE:
+                    break;
+
+                case QUICHE_H3_EVENT_CANCEL_PUSH:
+                    break;
+
+                case QUICHE_H3_EVENT_PRIORITY_UPDATE:
+                    break;
+
+                case QUICHE_H3_EVENT_WEBTRANSPORT_STREAM:
+                    break;
+
+                case QUICHE_H3_EVENT_WEBTRANSPORT_SESSION_CLOSED:
+                    break;
             }
 
             quiche_h3_event_free(ev);
@@ -392,7 +412,22 @@ int main(int argc, char *argv[]) {
         return -1;
     }
 
+    struct conn_io *conn_io = malloc(sizeof(*conn_io));
+    if (conn_io == NULL) {
+        fprintf(stderr, "failed to allocate connection IO\n");
+        return -1;
+    }
+
+    conn_io->local_addr_len = sizeof(conn_io->local_addr);
+    if (getsockname(sock, (struct sockaddr *) &conn_io->local_addr,
+                    &conn_io->local_addr_len) != 0) {
+        perror("failed to get local address of socket");
+        return -1;
+    }
+
     quiche_conn *conn = quiche_connect(host, (const uint8_t*) scid, sizeof(scid),
+                                       (struct sockaddr *) &conn_io->local_addr,
+                                       conn_io->local_addr_len,
                                        peer->ai_addr, peer->ai_addrlen, config);
 
     if (conn == NULL) {
@@ -400,12 +435,6 @@ int main(int argc, char *argv[]) {
         return -1;
     }
 
-    struct conn_io *conn_io = malloc(sizeof(*conn_io));
-    if (conn_io == NULL) {
-        fprintf(stderr, "failed to allocate connection IO\n");
-        return -1;
-    }
-
     conn_io->sock = sock;
     conn_io->conn = conn;
     conn_io->host = host;
@@ -69,6 +69,9 @@ fn main() {
     let socket = std::net::UdpSocket::bind(bind_addr).unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -108,12 +111,13 @@ fn main() {
 
     // Create a QUIC connection and initiate handshake.
     let mut conn =
-        quiche::connect(url.domain(), &scid, peer_addr, &mut config).unwrap();
+        quiche::connect(url.domain(), &scid, local_addr, peer_addr, &mut config)
+            .unwrap();
 
     info!(
         "connecting to {:} from {:} with scid {}",
         peer_addr,
-        socket.local_addr().unwrap(),
+        local_addr,
         hex_dump(&scid)
     );
 
@@ -189,7 +193,11 @@ fn main() {
 
             debug!("got {} bytes", len);
 
-            let recv_info = quiche::RecvInfo { from };
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
@@ -281,6 +289,19 @@ fn main() {
                         info!("GOAWAY id={}", goaway_id);
                     },
 
+                    Ok((_stream_id, quiche::h3::Event::PushPromise { .. })) => (),
+
+                    Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                    Ok((_, quiche::h3::Event::PriorityUpdate(_))) => (),
+
+                    Ok((_, quiche::h3::Event::WebTransportStream { .. })) => (),
+
+                    Ok((
+                        _,
+                        quiche::h3::Event::WebTransportSessionClosed { .. },
+                    )) => (),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },
@@ -77,6 +77,9 @@ fn main() {
     let socket = net::UdpSocket::bind("127.0.0.1:4433").unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -266,9 +269,14 @@ fn main() {
 
                 debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, scid);
 
-                let conn =
-                    quiche::accept(&scid, odcid.as_ref(), from, &mut config)
-                        .unwrap();
+                let conn = quiche::accept(
+                    &scid,
+                    odcid.as_ref(),
+                    local_addr,
+                    from,
+                    &mut config,
+                )
+                .unwrap();
 
                 let client = Client {
                     conn,
@@ -287,7 +295,11 @@ fn main() {
                 }
             };
 
-            let recv_info = quiche::RecvInfo { from };
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
             // Process potentially coalesced packets.
             let read = match client.conn.recv(pkt_buf, recv_info) {
@@ -366,6 +378,33 @@ fn main() {
 
                         Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
 
+                        Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                        Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                        Ok((
+                            stream_id,
+                            quiche::h3::Event::PriorityUpdate(priority),
+                        )) => {
+                            http3_conn
+                                .set_stream_priority(
+                                    &mut client.conn,
+                                    stream_id,
+                                    priority,
+                                )
+                                .ok();
+                        },
+
+                        Ok((_, quiche::h3::Event::WebTransportStream { .. })) =>
+                            (),
+
+                        Ok((
+                            _,
+                            quiche::h3::Event::WebTransportSessionClosed {
+                                ..
+                            },
+                        )) => (),
+
                         Err(quiche::h3::Error::Done) => {
                             break;
                         },
@@ -31,8 +31,6 @@ use std::net;
 
 use std::collections::HashMap;
 
-use ring::rand::*;
-
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
 struct PartialResponse {
@@ -42,12 +40,10 @@ struct PartialResponse {
 }
 
 struct Client {
-    conn: std::pin::Pin<Box<quiche::Connection>>,
-
     partial_responses: HashMap<u64, PartialResponse>,
 }
 
-type ClientMap = HashMap<quiche::ConnectionId<'static>, Client>;
+type ClientMap = HashMap<quiche::ConnectionHandle, Client>;
 
 fn main() {
     let mut buf = [0; 65535];
@@ -71,6 +67,9 @@ fn main() {
     let socket = net::UdpSocket::bind("127.0.0.1:4433").unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -107,17 +106,15 @@ fn main() {
     config.set_disable_active_migration(true);
     config.enable_early_data();
 
-    let rng = SystemRandom::new();
-    let conn_id_seed =
-        ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
+    // The endpoint owns all the connections, and takes care of version
+    // negotiation and stateless retry.
+    let mut endpoint = quiche::Endpoint::new(config);
+    endpoint.set_retry(true);
 
     let mut clients = ClientMap::new();
 
     loop {
-        // Find the shorter timeout from all the active connections.
-        //
-        // TODO: use event loop that properly supports timers
-        let timeout = clients.values().filter_map(|c| c.conn.timeout()).min();
+        let timeout = endpoint.timeout();
 
         poll.poll(&mut events, timeout).unwrap();
 
@@ -130,7 +127,7 @@ fn main() {
             if events.is_empty() {
                 debug!("timed out");
 
-                clients.values_mut().for_each(|c| c.conn.on_timeout());
+                endpoint.on_timeout();
 
                 break 'read;
             }
@@ -152,292 +149,131 @@ fn main() {
 
             debug!("got {} bytes", len);
 
-            let pkt_buf = &mut buf[..len];
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
-            // Parse the QUIC packet's header.
-            let hdr = match quiche::Header::from_slice(
-                pkt_buf,
-                quiche::MAX_CONN_ID_LEN,
-            ) {
-                Ok(v) => v,
+            // Process potentially coalesced packets.
+            match endpoint.recv(&mut buf[..len], recv_info) {
+                Ok(v) => debug!("processed {} bytes", v),
 
-                Err(e) => {
-                    error!("Parsing packet header failed: {:?}", e);
-                    continue 'read;
-                },
+                Err(e) => error!("recv failed: {:?}", e),
             };
+        }
 
-            trace!("got packet {:?}", hdr);
+        // Handle the connections that were updated by incoming packets or
+        // timers.
+        while let Some(ev) = endpoint.event_next() {
+            match ev {
+                quiche::EndpointEvent::New(handle) => {
+                    debug!("New connection {:?}", handle);
 
-            let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
-            let conn_id = &conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN];
-            let conn_id = conn_id.to_vec().into();
+                    let client = Client {
+                        partial_responses: HashMap::new(),
+                    };
 
-            // Lookup a connection based on the packet's connection ID. If there
-            // is no connection matching, create a new one.
-            let client = if !clients.contains_key(&hdr.dcid) &&
-                !clients.contains_key(&conn_id)
-            {
-                if hdr.ty != quiche::Type::Initial {
-                    error!("Packet is not Initial");
-                    continue 'read;
-                }
+                    clients.insert(handle, client);
+                },
 
-                if !quiche::version_is_supported(hdr.version) {
-                    warn!("Doing version negotiation");
+                quiche::EndpointEvent::Updated(handle) => {
+                    let conn = match endpoint.conn_mut(handle) {
+                        Some(v) => v,
 
-                    let len =
-                        quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)
-                            .unwrap();
+                        None => continue,
+                    };
 
-                    let out = &out[..len];
+                    let client = clients.get_mut(&handle).unwrap();
 
-                    if let Err(e) = socket.send_to(out, &from) {
-                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                            debug!("send() would block");
-                            break;
+                    if conn.is_in_early_data() || conn.is_established() {
+                        // Handle writable streams.
+                        for stream_id in conn.writable() {
+                            handle_writable(conn, client, stream_id);
                         }
 
-                        panic!("send() failed: {:?}", e);
-                    }
-                    continue 'read;
-                }
-
-                let mut scid = [0; quiche::MAX_CONN_ID_LEN];
-                scid.copy_from_slice(&conn_id);
-
-                let scid = quiche::ConnectionId::from_ref(&scid);
-
-                // Token is always present in Initial packets.
-                let token = hdr.token.as_ref().unwrap();
-
-                // Do stateless retry if the client didn't send a token.
-                if token.is_empty() {
-                    warn!("Doing stateless retry");
-
-                    let new_token = mint_token(&hdr, &from);
-
-                    let len = quiche::retry(
-                        &hdr.scid,
-                        &hdr.dcid,
-                        &scid,
-                        &new_token,
-                        hdr.version,
-                        &mut out,
-                    )
-                    .unwrap();
-
-                    let out = &out[..len];
-
-                    if let Err(e) = socket.send_to(out, &from) {
-                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                            debug!("send() would block");
-                            break;
+                        // Process all readable streams.
+                        for s in conn.readable() {
+                            while let Ok((read, fin)) =
+                                conn.stream_recv(s, &mut buf)
+                            {
+                                debug!(
+                                    "{} received {} bytes",
+                                    conn.trace_id(),
+                                    read
+                                );
+
+                                let stream_buf = &buf[..read];
+
+                                debug!(
+                                    "{} stream {} has {} bytes (fin? {})",
+                                    conn.trace_id(),
+                                    s,
+                                    stream_buf.len(),
+                                    fin
+                                );
+
+                                handle_stream(
+                                    conn,
+                                    client,
+                                    s,
+                                    stream_buf,
+                                    "examples/root",
+                                );
+                            }
                         }
-
-                        panic!("send() failed: {:?}", e);
                     }
-                    continue 'read;
-                }
-
-                let odcid = validate_token(&from, token);
-
-                // The token was not valid, meaning the retry failed, so
-                // drop the packet.
-                if odcid.is_none() {
-                    error!("Invalid address validation token");
-                    continue 'read;
-                }
-
-                if scid.len() != hdr.dcid.len() {
-                    error!("Invalid destination connection ID");
-                    continue 'read;
-                }
-
-                // Reuse the source connection ID we sent in the Retry packet,
-                // instead of changing it again.
-                let scid = hdr.dcid.clone();
-
-                debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, scid);
-
-                let conn =
-                    quiche::accept(&scid, odcid.as_ref(), from, &mut config)
-                        .unwrap();
-
-                let client = Client {
-                    conn,
-                    partial_responses: HashMap::new(),
-                };
-
-                clients.insert(scid.clone(), client);
-
-                clients.get_mut(&scid).unwrap()
-            } else {
-                match clients.get_mut(&hdr.dcid) {
-                    Some(v) => v,
-
-                    None => clients.get_mut(&conn_id).unwrap(),
-                }
-            };
-
-            let recv_info = quiche::RecvInfo { from };
-
-            // Process potentially coalesced packets.
-            let read = match client.conn.recv(pkt_buf, recv_info) {
-                Ok(v) => v,
-
-                Err(e) => {
-                    error!("{} recv failed: {:?}", client.conn.trace_id(), e);
-                    continue 'read;
                 },
-            };
-
-            debug!("{} processed {} bytes", client.conn.trace_id(), read);
 
-            if client.conn.is_in_early_data() || client.conn.is_established() {
-                // Handle writable streams.
-                for stream_id in client.conn.writable() {
-                    handle_writable(client, stream_id);
-                }
+                quiche::EndpointEvent::Closed(handle, conn) => {
+                    info!(
+                        "{} connection collected {:?}",
+                        conn.trace_id(),
+                        conn.stats()
+                    );
 
-                // Process all readable streams.
-                for s in client.conn.readable() {
-                    while let Ok((read, fin)) =
-                        client.conn.stream_recv(s, &mut buf)
-                    {
-                        debug!(
-                            "{} received {} bytes",
-                            client.conn.trace_id(),
-                            read
-                        );
-
-                        let stream_buf = &buf[..read];
-
-                        debug!(
-                            "{} stream {} has {} bytes (fin? {})",
-                            client.conn.trace_id(),
-                            s,
-                            stream_buf.len(),
-                            fin
-                        );
-
-                        handle_stream(client, s, stream_buf, "examples/root");
-                    }
-                }
+                    clients.remove(&handle);
+                },
             }
         }
 
         // Generate outgoing QUIC packets for all active connections and send
         // them on the UDP socket, until quiche reports that there are no more
         // packets to be sent.
-        for client in clients.values_mut() {
-            loop {
-                let (write, send_info) = match client.conn.send(&mut out) {
-                    Ok(v) => v,
-
-                    Err(quiche::Error::Done) => {
-                        debug!("{} done writing", client.conn.trace_id());
-                        break;
-                    },
-
-                    Err(e) => {
-                        error!("{} send failed: {:?}", client.conn.trace_id(), e);
+        loop {
+            let (write, send_info) = match endpoint.send(&mut out) {
+                Ok(v) => v,
 
-                        client.conn.close(false, 0x1, b"fail").ok();
-                        break;
-                    },
-                };
+                Err(quiche::Error::Done) => {
+                    debug!("done writing");
+                    break;
+                },
 
-                if let Err(e) = socket.send_to(&out[..write], &send_info.to) {
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        debug!("send() would block");
-                        break;
-                    }
+                Err(e) => {
+                    error!("send failed: {:?}", e);
+                    break;
+                },
+            };
 
-                    panic!("send() failed: {:?}", e);
+            if let Err(e) = socket.send_to(&out[..write], &send_info.to) {
+                if e.kind() == std::io::ErrorKind::WouldBlock {
+                    debug!("send() would block");
+                    break;
                 }
 
-                debug!("{} written {} bytes", client.conn.trace_id(), write);
+                panic!("send() failed: {:?}", e);
             }
-        }
 
-        // Garbage collect closed connections.
-        clients.retain(|_, ref mut c| {
-            debug!("Collecting garbage");
-
-            if c.conn.is_closed() {
-                info!(
-                    "{} connection collected {:?}",
-                    c.conn.trace_id(),
-                    c.conn.stats()
-                );
-            }
-
-            !c.conn.is_closed()
-        });
-    }
-}
-
-/// Generate a stateless retry token.
-///
-/// The token includes the static string `"quiche"` followed by the IP address
-/// of the client and by the original destination connection ID generated by the
-/// client.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
-    let mut token = Vec::new();
-
-    token.extend_from_slice(b"quiche");
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    token.extend_from_slice(&addr);
-    token.extend_from_slice(&hdr.dcid);
-
-    token
-}
-
-/// Validates a stateless retry token.
-///
-/// This checks that the ticket includes the `"quiche"` static string, and that
-/// the client IP address matches the address stored in the ticket.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn validate_token<'a>(
-    src: &net::SocketAddr, token: &'a [u8],
-) -> Option<quiche::ConnectionId<'a>> {
-    if token.len() < 6 {
-        return None;
-    }
-
-    if &token[..6] != b"quiche" {
-        return None;
-    }
-
-    let token = &token[6..];
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    if token.len() < addr.len() || &token[..addr.len()] != addr.as_slice() {
-        return None;
+            debug!("written {} bytes", write);
+        }
     }
-
-    Some(quiche::ConnectionId::from_ref(&token[addr.len()..]))
 }
 
 /// Handles incoming HTTP/0.9 requests.
-fn handle_stream(client: &mut Client, stream_id: u64, buf: &[u8], root: &str) {
-    let conn = &mut client.conn;
-
+fn handle_stream(
+    conn: &mut quiche::Connection, client: &mut Client, stream_id: u64,
+    buf: &[u8], root: &str,
+) {
     if buf.len() > 4 && &buf[..4] == b"GET " {
         let uri = &buf[4..buf.len()];
         let uri = String::from_utf8(uri.to_vec()).unwrap();
@@ -487,9 +323,9 @@ fn handle_stream(client: &mut Client, stream_id: u64, buf: &[u8], root: &str) {
 }
 
 /// Handles newly writable streams.
-fn handle_writable(client: &mut Client, stream_id: u64) {
-    let conn = &mut client.conn;
-
+fn handle_writable(
+    conn: &mut quiche::Connection, client: &mut Client, stream_id: u64,
+) {
     debug!("{} stream {} is writable", conn.trace_id(), stream_id);
 
     if !client.partial_responses.contains_key(&stream_id) {
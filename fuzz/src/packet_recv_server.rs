@@ -39,13 +39,15 @@ static SCID: quiche::ConnectionId<'static> =
 
 fuzz_target!(|data: &[u8]| {
     let from: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+    let to: SocketAddr = "127.0.0.1:4321".parse().unwrap();
 
     let mut buf = data.to_vec();
 
     let mut conn =
-        quiche::accept(&SCID, None, from, &mut CONFIG.lock().unwrap()).unwrap();
+        quiche::accept(&SCID, None, to, from, &mut CONFIG.lock().unwrap())
+            .unwrap();
 
-    let info = quiche::RecvInfo { from };
+    let info = quiche::RecvInfo { from, to, ecn: 0 };
 
     conn.recv(&mut buf, info).ok();
 });
@@ -48,6 +48,9 @@ extern "C" {
 // The current QUIC wire version.
 #define QUICHE_PROTOCOL_VERSION 0x00000001
 
+// QUIC version 2.
+#define QUICHE_PROTOCOL_VERSION_V2 0x6b3343cf
+
 // The maximum length of a connection ID.
 #define QUICHE_MAX_CONN_ID_LEN 20
 
@@ -105,6 +108,26 @@ enum quiche_error {
 
     // Error in congestion control.
     QUICHE_ERR_CONGESTION_CONTROL = -14,
+
+    // Too many identifiers were provided.
+    QUICHE_ERR_ID_LIMIT = -17,
+
+    // Not enough available identifiers.
+    QUICHE_ERR_OUT_OF_IDENTIFIERS = -18,
+
+    // The peer initiated a key update before the previous one was
+    // acknowledged.
+    QUICHE_ERR_KEY_UPDATE = -19,
+
+    // The connection was reset by the peer with a stateless reset.
+    QUICHE_ERR_STATELESS_RESET = -20,
+
+    // The provided address validation token is invalid or expired.
+    QUICHE_ERR_INVALID_TOKEN = -21,
+
+    // The peer's version information is inconsistent with the version
+    // negotiation that took place.
+    QUICHE_ERR_VERSION_NEGOTIATION = -22,
 };
 
 // Returns a human readable string with the quiche version number.
@@ -186,9 +209,19 @@ void quiche_config_set_max_ack_delay(quiche_config *config, uint64_t v);
 // Sets the `disable_active_migration` transport parameter.
 void quiche_config_set_disable_active_migration(quiche_config *config, bool v);
 
+// Sets the addresses advertised in the `preferred_address` transport
+// parameter. Either address can be NULL.
+void quiche_config_set_preferred_address(quiche_config *config,
+                                         const struct sockaddr *v4, size_t v4_len,
+                                         const struct sockaddr *v6, size_t v6_len);
+
+// Sets the `active_connection_id_limit` transport parameter.
+void quiche_config_set_active_connection_id_limit(quiche_config *config, uint64_t v);
+
 enum quiche_cc_algorithm {
     QUICHE_CC_RENO = 0,
     QUICHE_CC_CUBIC = 1,
+    QUICHE_CC_BBR = 2,
 };
 
 // Sets the congestion control algorithm used.
@@ -197,11 +230,39 @@ void quiche_config_set_cc_algorithm(quiche_config *config, enum quiche_cc_algori
 // Configures whether to use HyStart++.
 void quiche_config_enable_hystart(quiche_config *config, bool v);
 
+// Configures whether to mark outgoing packets as ECN-capable.
+void quiche_config_enable_ecn(quiche_config *config, bool v);
+
+// Configures whether to probe the path for a larger MTU.
+void quiche_config_discover_pmtu(quiche_config *config, bool v);
+
 // Configures whether to enable receiving DATAGRAM frames.
 void quiche_config_enable_dgram(quiche_config *config, bool enabled,
                                 size_t recv_queue_len,
                                 size_t send_queue_len);
 
+// Sets the secret used to derive the stateless reset token of the initial
+// connection ID.
+void quiche_config_set_stateless_reset_secret(quiche_config *config,
+                                              const uint8_t *secret,
+                                              size_t secret_len);
+
+// Sets the number of packets protected with the same 1-RTT keys before a key
+// update is automatically initiated.
+void quiche_config_set_key_update_threshold(quiche_config *config, uint64_t v);
+
+// Sets the secret used to derive the key that issues and validates address
+// validation tokens.
+void quiche_config_set_address_token_secret(quiche_config *config,
+                                            const uint8_t *secret,
+                                            size_t secret_len);
+
+// Sets the QUIC versions that a connection can be switched to with compatible
+// version negotiation, in order of preference.
+int quiche_config_set_compatible_versions(quiche_config *config,
+                                          const uint32_t *versions,
+                                          size_t versions_len);
+
 // Frees the config object.
 void quiche_config_free(quiche_config *config);
 
@@ -219,15 +280,28 @@ typedef struct Connection quiche_conn;
 // Creates a new server-side connection.
 quiche_conn *quiche_accept(const uint8_t *scid, size_t scid_len,
                            const uint8_t *odcid, size_t odcid_len,
-                           const struct sockaddr *from, size_t from_len,
+                           const struct sockaddr *local, size_t local_len,
+                           const struct sockaddr *peer, size_t peer_len,
                            quiche_config *config);
 
 // Creates a new client-side connection.
 quiche_conn *quiche_connect(const char *server_name,
                             const uint8_t *scid, size_t scid_len,
-                            const struct sockaddr *to, size_t to_len,
+                            const struct sockaddr *local, size_t local_len,
+                            const struct sockaddr *peer, size_t peer_len,
                             quiche_config *config);
 
+// Creates a new client-side connection using an address validation token
+// previously received from the server.
+quiche_conn *quiche_connect_with_token(const char *server_name,
+                                       const uint8_t *scid, size_t scid_len,
+                                       const uint8_t *token, size_t token_len,
+                                       const struct sockaddr *local,
+                                       size_t local_len,
+                                       const struct sockaddr *peer,
+                                       size_t peer_len,
+                                       quiche_config *config);
+
 // Writes a version negotiation packet.
 ssize_t quiche_negotiate_version(const uint8_t *scid, size_t scid_len,
                                  const uint8_t *dcid, size_t dcid_len,
@@ -240,11 +314,22 @@ ssize_t quiche_retry(const uint8_t *scid, size_t scid_len,
                      const uint8_t *token, size_t token_len,
                      uint32_t version, uint8_t *out, size_t out_len);
 
+// Writes a stateless reset packet using the whole |out| buffer.
+ssize_t quiche_stateless_reset(const uint8_t *reset_token,
+                               uint8_t *out, size_t out_len);
+
+// Derives the stateless reset token of a connection ID from a static secret,
+// and writes it to |out|, which must be 16 bytes long.
+void quiche_stateless_reset_token(const uint8_t *secret, size_t secret_len,
+                                  const uint8_t *cid, size_t cid_len,
+                                  uint8_t *out);
+
 // Returns true if the given protocol version is supported.
 bool quiche_version_is_supported(uint32_t version);
 
 quiche_conn *quiche_conn_new_with_tls(const uint8_t *scid, size_t scid_len,
                                       const uint8_t *odcid, size_t odcid_len,
+                                      const struct sockaddr *local, size_t local_len,
                                       const struct sockaddr *peer, size_t peer_len,
                                       quiche_config *config, void *ssl,
                                       bool is_server);
@@ -267,8 +352,16 @@ void quiche_conn_set_qlog_fd(quiche_conn *conn, int fd, const char *log_title,
 int quiche_conn_set_session(quiche_conn *conn, const uint8_t *buf, size_t buf_len);
 
 typedef struct {
+    // The remote address the packet was received from.
     struct sockaddr *from;
     socklen_t from_len;
+
+    // The local address the packet was received on.
+    struct sockaddr *to;
+    socklen_t to_len;
+
+    // The ECN codepoint the packet was marked with.
+    uint8_t ecn;
 } quiche_recv_info;
 
 // Processes QUIC packets received from the peer.
@@ -276,18 +369,30 @@ ssize_t quiche_conn_recv(quiche_conn *conn, uint8_t *buf, size_t buf_len,
                          const quiche_recv_info *info);
 
 typedef struct {
+    // The local address the packet should be sent from.
+    struct sockaddr_storage from;
+    socklen_t from_len;
+
     // The address the packet should be sent to.
     struct sockaddr_storage to;
     socklen_t to_len;
 
     // The time to send the packet out.
     struct timespec at;
+
+    // The ECN codepoint the packet should be marked with.
+    uint8_t ecn;
 } quiche_send_info;
 
 // Writes a single QUIC packet to be sent to the peer.
 ssize_t quiche_conn_send(quiche_conn *conn, uint8_t *out, size_t out_len,
                          quiche_send_info *out_info);
 
+// Writes multiple QUIC packets of the same size to be sent to the peer at once.
+ssize_t quiche_conn_send_batch(quiche_conn *conn, uint8_t *out, size_t out_len,
+                               size_t max_segments, size_t *segment_size,
+                               quiche_send_info *out_info);
+
 // Reads contiguous data from a stream.
 ssize_t quiche_conn_stream_recv(quiche_conn *conn, uint64_t stream_id,
                                 uint8_t *out, size_t buf_len, bool *fin);
@@ -349,10 +454,53 @@ void quiche_conn_source_id(quiche_conn *conn, const uint8_t **out, size_t *out_l
 // Returns the destination connection ID.
 void quiche_conn_destination_id(quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
+// Provides a new source connection ID to the peer, along with its 16 bytes
+// stateless reset token. Returns the sequence number of the new ID.
+int64_t quiche_conn_new_source_cid(quiche_conn *conn,
+                                   const uint8_t *scid, size_t scid_len,
+                                   const uint8_t *reset_token,
+                                   bool retire_if_needed);
+
+// Returns the number of source connection IDs that can still be provided.
+size_t quiche_conn_source_cids_left(quiche_conn *conn);
+
+// Requests the validation of the path between the given local and peer
+// addresses. Returns the sequence number of the destination connection ID
+// used on that path.
+int64_t quiche_conn_probe_path(quiche_conn *conn,
+                               const struct sockaddr *local, size_t local_len,
+                               const struct sockaddr *peer, size_t peer_len);
+
+// Migrates the connection to the path between the given local and peer
+// addresses. Returns the sequence number of the destination connection ID
+// used on that path.
+int64_t quiche_conn_migrate(quiche_conn *conn,
+                            const struct sockaddr *local, size_t local_len,
+                            const struct sockaddr *peer, size_t peer_len);
+
+// Initiates a 1-RTT key update.
+int quiche_conn_initiate_key_update(quiche_conn *conn);
+
+// Schedules a NEW_TOKEN frame to be sent to the client.
+int quiche_conn_send_new_token(quiche_conn *conn);
+
+// Writes the next address validation token received from the server into the
+// provided buffer and returns its length.
+ssize_t quiche_conn_new_token_next(quiche_conn *conn, uint8_t *out,
+                                   size_t out_len);
+
+// Writes the oldest source connection ID retired by the peer into the
+// provided buffer and returns its length.
+ssize_t quiche_conn_retired_scid_next(quiche_conn *conn, uint8_t *out,
+                                      size_t out_len);
+
 // Returns the negotiated ALPN protocol.
 void quiche_conn_application_proto(quiche_conn *conn, const uint8_t **out,
                                    size_t *out_len);
 
+// Returns the QUIC version used by the connection.
+uint32_t quiche_conn_version(quiche_conn *conn);
+
 // Returns the serialized cryptographic session for the connection.
 void quiche_conn_session(quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
@@ -418,6 +566,10 @@ typedef struct {
     // The number of QUIC packets that were lost.
     size_t lost;
 
+    // The number of QUIC packets that were declared lost, but were
+    // acknowledged by the peer afterwards.
+    size_t spurious_lost;
+
     // The number of sent QUIC packets with retranmitted data.
     size_t retrans;
 
@@ -445,6 +597,22 @@ typedef struct {
     // The most recent data delivery rate estimate in bytes/s.
     uint64_t delivery_rate;
 
+    // The number of QUIC packets sent with the ECT(0) codepoint.
+    size_t ecn_sent_ect0;
+
+    // The number of QUIC packets received with the ECT(0) codepoint.
+    uint64_t ecn_recv_ect0;
+
+    // The number of QUIC packets received with the ECT(1) codepoint.
+    uint64_t ecn_recv_ect1;
+
+    // The number of QUIC packets received with the CE codepoint.
+    uint64_t ecn_recv_ce;
+
+    // The number of sent QUIC packets that the peer reported as received
+    // with the CE codepoint.
+    uint64_t ecn_peer_ce;
+
     // The maximum idle timeout.
     uint64_t peer_max_idle_timeout;
 
@@ -596,6 +764,14 @@ enum quiche_h3_error {
     /// The requested operation cannot be served over HTTP/3. Peer should retry
     /// over HTTP/1.1.
     QUICHE_H3_ERR_VERSION_FALLBACK = -20,
+
+    /// Error interpreting the instructions received on the QPACK encoder
+    /// stream.
+    QUICHE_H3_ERR_QPACK_ENCODER_STREAM_ERROR = -21,
+
+    /// Error interpreting the instructions received on the QPACK decoder
+    /// stream.
+    QUICHE_H3_ERR_QPACK_DECODER_STREAM_ERROR = -22,
 };
 
 // Stores configuration shared between multiple connections.
@@ -613,6 +789,13 @@ void quiche_h3_config_set_qpack_max_table_capacity(quiche_h3_config *config, uin
 // Sets the `SETTINGS_QPACK_BLOCKED_STREAMS` setting.
 void quiche_h3_config_set_qpack_blocked_streams(quiche_h3_config *config, uint64_t v);
 
+// Sets the `SETTINGS_ENABLE_CONNECT_PROTOCOL` setting.
+void quiche_h3_config_enable_extended_connect(quiche_h3_config *config, bool v);
+
+// Sets the `SETTINGS_ENABLE_WEBTRANSPORT` setting, and enables extended
+// CONNECT.
+void quiche_h3_config_enable_webtransport(quiche_h3_config *config, bool v);
+
 // Frees the HTTP/3 config object.
 void quiche_h3_config_free(quiche_h3_config *config);
 
@@ -634,6 +817,11 @@ enum quiche_h3_event_type {
     QUICHE_H3_EVENT_DATAGRAM,
     QUICHE_H3_EVENT_GOAWAY,
     QUICHE_H3_EVENT_RESET,
+    QUICHE_H3_EVENT_PUSH_PROMISE,
+    QUICHE_H3_EVENT_CANCEL_PUSH,
+    QUICHE_H3_EVENT_PRIORITY_UPDATE,
+    QUICHE_H3_EVENT_WEBTRANSPORT_STREAM,
+    QUICHE_H3_EVENT_WEBTRANSPORT_SESSION_CLOSED,
 };
 
 typedef struct Http3Event quiche_h3_event;
@@ -660,6 +848,22 @@ int quiche_h3_event_for_each_header(quiche_h3_event *ev,
 // Check whether data will follow the headers on the stream.
 bool quiche_h3_event_headers_has_body(quiche_h3_event *ev);
 
+// Returns the push ID of a PUSH_PROMISE event.
+uint64_t quiche_h3_event_push_promise_id(quiche_h3_event *ev);
+
+// Returns the urgency and incremental flag of a PRIORITY_UPDATE event.
+void quiche_h3_event_priority_update(quiche_h3_event *ev, uint8_t *urgency,
+                                     bool *incremental);
+
+// Returns the session ID of a WebTransport stream event.
+uint64_t quiche_h3_event_webtransport_session_id(quiche_h3_event *ev);
+
+// Returns the error code and reason of a WebTransport session closed event.
+void quiche_h3_event_webtransport_session_closed(quiche_h3_event *ev,
+                                                 uint32_t *error_code,
+                                                 const uint8_t **reason,
+                                                 size_t *reason_len);
+
 // Frees the HTTP/3 event object.
 void quiche_h3_event_free(quiche_h3_event *ev);
 
@@ -676,7 +880,14 @@ int64_t quiche_h3_send_request(quiche_h3_conn *conn, quiche_conn *quic_conn,
                                quiche_h3_header *headers, size_t headers_len,
                                bool fin);
 
-// Sends an HTTP/3 response on the specified stream with default priority.
+// Sends an HTTP/3 request with the specified priority.
+int64_t quiche_h3_send_request_with_priority(quiche_h3_conn *conn,
+                            quiche_conn *quic_conn, quiche_h3_header *headers,
+                            size_t headers_len, uint8_t urgency,
+                            bool incremental, bool fin);
+
+// Sends an HTTP/3 response on the specified stream with the priority signaled
+// by the client, or the default priority.
 int quiche_h3_send_response(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, quiche_h3_header *headers,
                             size_t headers_len, bool fin);
@@ -687,6 +898,21 @@ int quiche_h3_send_response_with_priority(quiche_h3_conn *conn,
                             quiche_h3_header *headers, size_t headers_len,
                             const char *priority, bool fin);
 
+// Applies the specified priority to the stream.
+int quiche_h3_set_stream_priority(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                                  uint64_t stream_id, uint8_t urgency,
+                                  bool incremental);
+
+// Sends a PRIORITY_UPDATE frame for the specified request stream.
+int quiche_h3_send_priority_update_for_request(quiche_h3_conn *conn,
+                            quiche_conn *quic_conn, uint64_t stream_id,
+                            uint8_t urgency, bool incremental);
+
+// Sends a PRIORITY_UPDATE frame for the specified push ID.
+int quiche_h3_send_priority_update_for_push(quiche_h3_conn *conn,
+                            quiche_conn *quic_conn, uint64_t push_id,
+                            uint8_t urgency, bool incremental);
+
 // Sends an HTTP/3 body chunk on the given stream.
 ssize_t quiche_h3_send_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, uint8_t *body, size_t body_len,
@@ -696,6 +922,64 @@ ssize_t quiche_h3_send_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
 ssize_t quiche_h3_recv_body(quiche_h3_conn *conn, quiche_conn *quic_conn,
                             uint64_t stream_id, uint8_t *out, size_t out_len);
 
+// Allows the server to push responses with push IDs up to `push_id`.
+int quiche_h3_send_max_push_id(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                               uint64_t push_id);
+
+// Sends a PUSH_PROMISE frame on the specified request stream.
+int64_t quiche_h3_send_push_promise(quiche_h3_conn *conn,
+                                    quiche_conn *quic_conn, uint64_t stream_id,
+                                    quiche_h3_header *headers,
+                                    size_t headers_len);
+
+// Sends a pushed response for a previously promised push ID.
+int64_t quiche_h3_send_push_response(quiche_h3_conn *conn,
+                                     quiche_conn *quic_conn, uint64_t push_id,
+                                     quiche_h3_header *headers,
+                                     size_t headers_len, bool fin);
+
+// Cancels a server push.
+int quiche_h3_cancel_push(quiche_h3_conn *conn, quiche_conn *quic_conn,
+                          uint64_t push_id);
+
+// Returns the push ID of the given push stream.
+int64_t quiche_h3_push_id(quiche_h3_conn *conn, uint64_t stream_id);
+
+// Returns whether the peer enabled extended CONNECT support.
+bool quiche_h3_extended_connect_enabled_by_peer(quiche_h3_conn *conn);
+
+// Returns whether the peer enabled WebTransport support.
+bool quiche_h3_webtransport_enabled_by_peer(quiche_h3_conn *conn,
+                                            quiche_conn *quic_conn);
+
+// Sends an extended CONNECT request establishing a WebTransport session.
+int64_t quiche_h3_send_webtransport_connect(quiche_h3_conn *conn,
+                                            quiche_conn *quic_conn,
+                                            quiche_h3_header *headers,
+                                            size_t headers_len);
+
+// Opens a unidirectional WebTransport stream for the given session.
+int64_t quiche_h3_open_webtransport_uni_stream(quiche_h3_conn *conn,
+                                               quiche_conn *quic_conn,
+                                               uint64_t session_id);
+
+// Opens a bidirectional WebTransport stream for the given session.
+int64_t quiche_h3_open_webtransport_bidi_stream(quiche_h3_conn *conn,
+                                                quiche_conn *quic_conn,
+                                                uint64_t session_id);
+
+// Closes a WebTransport session.
+int quiche_h3_close_webtransport_session(quiche_h3_conn *conn,
+                                         quiche_conn *quic_conn,
+                                         uint64_t session_id,
+                                         uint32_t error_code,
+                                         const uint8_t *reason,
+                                         size_t reason_len);
+
+// Returns the WebTransport session ID of the given stream.
+int64_t quiche_h3_webtransport_session_id(quiche_h3_conn *conn,
+                                          uint64_t stream_id);
+
 // Returns whether the peer enabled HTTP/3 DATAGRAM frame support.
 bool quiche_h3_dgram_enabled_by_peer(quiche_h3_conn *conn,
                                      quiche_conn *quic_conn);
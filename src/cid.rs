@@ -0,0 +1,669 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::Error;
+use crate::Result;
+
+use crate::packet::ConnectionId;
+
+use std::collections::VecDeque;
+
+/// A connection ID along with its associated metadata.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionIdEntry {
+    /// The connection ID.
+    pub cid: ConnectionId<'static>,
+
+    /// Its associated sequence number.
+    pub seq: u64,
+
+    /// Its associated stateless reset token, if any.
+    pub reset_token: Option<u128>,
+
+    /// The path this destination connection ID is used on, if any.
+    pub path_id: Option<usize>,
+}
+
+/// Keeps track of the connection IDs used by both endpoints.
+#[derive(Default)]
+pub struct ConnectionIdentifiers {
+    /// All the source connection IDs we provided to the peer and that were
+    /// not retired yet.
+    scids: VecDeque<ConnectionIdEntry>,
+
+    /// All the destination connection IDs provided by the peer that we did
+    /// not retire yet.
+    dcids: VecDeque<ConnectionIdEntry>,
+
+    /// Maximum number of source connection IDs the peer is willing to store,
+    /// as advertised by its `active_connection_id_limit` transport parameter.
+    source_conn_id_limit: usize,
+
+    /// Maximum number of destination connection IDs we are willing to store,
+    /// as advertised by our `active_connection_id_limit` transport parameter.
+    destination_conn_id_limit: usize,
+
+    /// Whether we use zero-length source connection IDs.
+    zero_length_scid: bool,
+
+    /// Whether the peer uses zero-length source connection IDs.
+    zero_length_dcid: bool,
+
+    /// Sequence number of the next source connection ID to be provided.
+    next_scid_seq: u64,
+
+    /// The "Retire Prior To" value we advertise to the peer.
+    retire_prior_to: u64,
+
+    /// The largest "Retire Prior To" value received from the peer.
+    largest_peer_retire_prior_to: u64,
+
+    /// Sequence numbers of source connection IDs that need to be advertised
+    /// in NEW_CONNECTION_ID frames.
+    advertise_new_scid_seqs: VecDeque<u64>,
+
+    /// Sequence numbers of destination connection IDs that need to be
+    /// retired in RETIRE_CONNECTION_ID frames.
+    retire_dcid_seqs: VecDeque<u64>,
+
+    /// Source connection IDs retired by the peer, to be reported to the
+    /// application.
+    retired_scids: VecDeque<ConnectionId<'static>>,
+}
+
+impl ConnectionIdentifiers {
+    /// Creates a new `ConnectionIdentifiers` with the given initial source
+    /// connection ID, whose sequence number is 0.
+    ///
+    /// The destination connection ID is initially unknown and needs to be set
+    /// using [`set_initial_dcid()`].
+    ///
+    /// [`set_initial_dcid()`]: struct.ConnectionIdentifiers.html#method.set_initial_dcid
+    pub fn new(
+        destination_conn_id_limit: u64, initial_scid: &ConnectionId,
+        reset_token: Option<u128>,
+    ) -> ConnectionIdentifiers {
+        let mut scids = VecDeque::new();
+
+        scids.push_back(ConnectionIdEntry {
+            cid: initial_scid.to_vec().into(),
+            seq: 0,
+            reset_token,
+            path_id: None,
+        });
+
+        let mut dcids = VecDeque::new();
+
+        dcids.push_back(ConnectionIdEntry {
+            cid: ConnectionId::default(),
+            seq: 0,
+            reset_token: None,
+            path_id: Some(0),
+        });
+
+        ConnectionIdentifiers {
+            scids,
+            dcids,
+            // The peer's limit defaults to 2 until its transport parameters
+            // are received.
+            source_conn_id_limit: 2,
+            destination_conn_id_limit: destination_conn_id_limit as usize,
+            zero_length_scid: initial_scid.is_empty(),
+            next_scid_seq: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the maximum number of source connection IDs the peer is willing
+    /// to store.
+    pub fn set_source_conn_id_limit(&mut self, v: u64) {
+        // Bound the limit to something sensible, as the peer could advertise
+        // an arbitrarily large value.
+        self.source_conn_id_limit = std::cmp::min(v, 64) as usize;
+    }
+
+    /// Sets the destination connection ID with sequence number 0, replacing
+    /// the one used so far.
+    ///
+    /// This is used during the handshake, as the initial destination
+    /// connection ID changes after the peer's first packet (or a Retry) is
+    /// received.
+    pub fn set_initial_dcid(
+        &mut self, cid: ConnectionId<'static>, reset_token: Option<u128>,
+    ) {
+        self.zero_length_dcid = cid.is_empty();
+
+        if let Some(e) = self.dcids.iter_mut().find(|e| e.seq == 0) {
+            e.cid = cid;
+            e.reset_token = reset_token;
+        }
+    }
+
+    /// Sets the stateless reset token associated with the destination
+    /// connection ID with sequence number 0.
+    pub fn set_initial_dcid_reset_token(&mut self, reset_token: u128) {
+        if let Some(e) = self.dcids.iter_mut().find(|e| e.seq == 0) {
+            e.reset_token = Some(reset_token);
+        }
+    }
+
+    /// Adds a new source connection ID, to be advertised to the peer.
+    ///
+    /// If the peer's limit on active connection IDs would be exceeded, the
+    /// oldest active connection ID is scheduled for retirement when
+    /// `retire_if_needed` is true, otherwise [`IdLimit`] is returned.
+    ///
+    /// On success the new connection ID's sequence number is returned.
+    ///
+    /// [`IdLimit`]: ../enum.Error.html#variant.IdLimit
+    pub fn new_scid(
+        &mut self, cid: ConnectionId<'static>, reset_token: u128,
+        retire_if_needed: bool,
+    ) -> Result<u64> {
+        if self.zero_length_scid {
+            return Err(Error::InvalidState);
+        }
+
+        // Adding the same connection ID twice is a no-op, as long as the
+        // stateless reset token matches.
+        if let Some(e) = self.scids.iter().find(|e| e.cid == cid) {
+            if e.reset_token != Some(reset_token) {
+                return Err(Error::InvalidState);
+            }
+
+            return Ok(e.seq);
+        }
+
+        if self.active_source_cids() >= self.source_conn_id_limit {
+            if !retire_if_needed {
+                return Err(Error::IdLimit);
+            }
+
+            // Ask the peer to retire the oldest active connection ID.
+            self.retire_prior_to = self
+                .scids
+                .iter()
+                .map(|e| e.seq)
+                .find(|&seq| seq >= self.retire_prior_to)
+                .map(|seq| seq + 1)
+                .unwrap_or(self.next_scid_seq);
+        }
+
+        let seq = self.next_scid_seq;
+
+        self.scids.push_back(ConnectionIdEntry {
+            cid,
+            seq,
+            reset_token: Some(reset_token),
+            path_id: None,
+        });
+
+        self.next_scid_seq += 1;
+
+        self.mark_advertise_new_scid_seq(seq, true);
+
+        Ok(seq)
+    }
+
+    /// Retires the source connection ID with the given sequence number, as
+    /// requested by the peer.
+    ///
+    /// The `pkt_dcid` parameter is the destination connection ID of the
+    /// packet carrying the RETIRE_CONNECTION_ID frame, which must not be the
+    /// connection ID being retired.
+    ///
+    /// Returns the retired connection ID, or `None` if it was already retired.
+    pub fn retire_scid(
+        &mut self, seq: u64, pkt_dcid: &ConnectionId,
+    ) -> Result<Option<ConnectionId<'static>>> {
+        if seq >= self.next_scid_seq {
+            return Err(Error::InvalidState);
+        }
+
+        let idx = match self.scids.iter().position(|e| e.seq == seq) {
+            Some(v) => v,
+
+            None => return Ok(None),
+        };
+
+        if &self.scids[idx].cid == pkt_dcid {
+            return Err(Error::InvalidState);
+        }
+
+        let e = self.scids.remove(idx).ok_or(Error::InvalidState)?;
+
+        self.mark_advertise_new_scid_seq(seq, false);
+
+        self.retired_scids.push_back(e.cid.clone());
+
+        Ok(Some(e.cid))
+    }
+
+    /// Adds a new destination connection ID, as provided by the peer in a
+    /// NEW_CONNECTION_ID frame.
+    ///
+    /// Returns the sequence numbers of the destination connection IDs that
+    /// got retired as a consequence of the `retire_prior_to` value.
+    pub fn new_dcid(
+        &mut self, cid: ConnectionId<'static>, seq: u64, reset_token: u128,
+        retire_prior_to: u64,
+    ) -> Result<Vec<u64>> {
+        // An endpoint using zero-length connection IDs can't be provided with
+        // new ones.
+        if self.zero_length_dcid {
+            return Err(Error::InvalidState);
+        }
+
+        let mut retired = Vec::new();
+
+        // The same connection ID can't be provided twice with different
+        // sequence numbers or reset tokens, nor can a sequence number be
+        // reused for a different connection ID.
+        if let Some(e) = self.dcids.iter().find(|e| e.cid == cid || e.seq == seq)
+        {
+            if e.cid != cid || e.seq != seq || e.reset_token != Some(reset_token)
+            {
+                return Err(Error::InvalidState);
+            }
+
+            // Duplicate frame, nothing to do.
+            return Ok(retired);
+        }
+
+        // A connection ID below the largest "Retire Prior To" value received
+        // so far needs to be retired immediately.
+        if seq < self.largest_peer_retire_prior_to {
+            if !self.retire_dcid_seqs.contains(&seq) {
+                self.mark_retire_dcid_seq(seq, true)?;
+            }
+
+            return Ok(retired);
+        }
+
+        if retire_prior_to > self.largest_peer_retire_prior_to {
+            self.largest_peer_retire_prior_to = retire_prior_to;
+
+            let mut i = 0;
+
+            while i < self.dcids.len() {
+                if self.dcids[i].seq < retire_prior_to {
+                    let e = self.dcids.remove(i).ok_or(Error::InvalidState)?;
+
+                    self.mark_retire_dcid_seq(e.seq, true)?;
+
+                    retired.push(e.seq);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+
+        if self.dcids.len() >= self.destination_conn_id_limit {
+            return Err(Error::IdLimit);
+        }
+
+        self.dcids.push_back(ConnectionIdEntry {
+            cid,
+            seq,
+            reset_token: Some(reset_token),
+            path_id: None,
+        });
+
+        Ok(retired)
+    }
+
+    /// Returns the source connection ID with the given sequence number.
+    pub fn get_scid(&self, seq: u64) -> Result<&ConnectionIdEntry> {
+        self.scids
+            .iter()
+            .find(|e| e.seq == seq)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the destination connection ID with the given sequence number.
+    pub fn get_dcid(&self, seq: u64) -> Result<&ConnectionIdEntry> {
+        self.dcids
+            .iter()
+            .find(|e| e.seq == seq)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the lowest sequence number among the source connection IDs
+    /// that the peer was not asked to retire.
+    pub fn lowest_active_scid_seq(&self) -> Option<u64> {
+        self.scids
+            .iter()
+            .map(|e| e.seq)
+            .filter(|&seq| seq >= self.retire_prior_to)
+            .min()
+    }
+
+    /// Returns the lowest sequence number among the destination connection
+    /// IDs that are not used on any path.
+    pub fn lowest_available_dcid_seq(&self) -> Option<u64> {
+        self.dcids
+            .iter()
+            .filter(|e| e.path_id.is_none())
+            .map(|e| e.seq)
+            .min()
+    }
+
+    /// Links the destination connection ID with the given sequence number to
+    /// a path, or unlinks it when `path_id` is `None`.
+    pub fn link_dcid_to_path(
+        &mut self, seq: u64, path_id: Option<usize>,
+    ) -> Result<()> {
+        let e = self
+            .dcids
+            .iter_mut()
+            .find(|e| e.seq == seq)
+            .ok_or(Error::InvalidState)?;
+
+        e.path_id = path_id;
+
+        Ok(())
+    }
+
+    /// Retires the destination connection ID with the given sequence number,
+    /// and schedules a RETIRE_CONNECTION_ID frame for it.
+    pub fn retire_dcid(&mut self, seq: u64) -> Result<()> {
+        // The zero-length connection ID can't be retired.
+        if self.zero_length_dcid {
+            return Err(Error::InvalidState);
+        }
+
+        let idx = self
+            .dcids
+            .iter()
+            .position(|e| e.seq == seq)
+            .ok_or(Error::InvalidState)?;
+
+        self.dcids.remove(idx);
+
+        self.mark_retire_dcid_seq(seq, true)
+    }
+
+    /// Returns whether the peer uses zero-length source connection IDs.
+    pub fn zero_length_dcid(&self) -> bool {
+        self.zero_length_dcid
+    }
+
+    /// Returns an iterator over the source connection IDs that were not
+    /// retired by the peer yet.
+    pub fn scids_iter(&self) -> impl Iterator<Item = &ConnectionId<'static>> {
+        self.scids.iter().map(|e| &e.cid)
+    }
+
+    /// Returns the number of source connection IDs that the peer was not
+    /// asked to retire.
+    pub fn active_source_cids(&self) -> usize {
+        self.scids
+            .iter()
+            .filter(|e| e.seq >= self.retire_prior_to)
+            .count()
+    }
+
+    /// Returns the number of source connection IDs that can still be
+    /// provided to the peer without exceeding its limit.
+    pub fn source_cids_left(&self) -> usize {
+        if self.zero_length_scid {
+            return 0;
+        }
+
+        self.source_conn_id_limit
+            .saturating_sub(self.active_source_cids())
+    }
+
+    /// Returns the number of destination connection IDs available.
+    pub fn available_dcids(&self) -> usize {
+        self.dcids.len()
+    }
+
+    /// Returns true if the given token is the stateless reset token of one of
+    /// the destination connection IDs.
+    pub fn is_stateless_reset(&self, token: Option<u128>) -> bool {
+        let token = match token {
+            Some(v) => v.to_be_bytes(),
+
+            None => return false,
+        };
+
+        // Tokens are compared in constant time, to avoid leaking them.
+        self.dcids.iter().filter_map(|e| e.reset_token).any(|t| {
+            ring::constant_time::verify_slices_are_equal(&t.to_be_bytes(), &token)
+                .is_ok()
+        })
+    }
+
+    /// Returns the "Retire Prior To" value to advertise to the peer.
+    pub fn retire_prior_to(&self) -> u64 {
+        self.retire_prior_to
+    }
+
+    /// Returns whether we use zero-length source connection IDs.
+    pub fn zero_length_scid(&self) -> bool {
+        self.zero_length_scid
+    }
+
+    /// Adds or removes the source connection ID with the given sequence
+    /// number to the set of IDs that need to be advertised to the peer.
+    ///
+    /// Connection IDs that were already retired are never advertised again.
+    pub fn mark_advertise_new_scid_seq(&mut self, seq: u64, advertise: bool) {
+        if advertise {
+            if self.get_scid(seq).is_ok() &&
+                !self.advertise_new_scid_seqs.contains(&seq)
+            {
+                self.advertise_new_scid_seqs.push_back(seq);
+            }
+        } else {
+            self.advertise_new_scid_seqs.retain(|&s| s != seq);
+        }
+    }
+
+    /// Adds or removes the destination connection ID with the given sequence
+    /// number to the set of IDs that need to be retired.
+    ///
+    /// To prevent the peer from forcing us to keep an unbounded number of
+    /// pending retirements, [`IdLimit`] is returned if too many are queued.
+    ///
+    /// [`IdLimit`]: ../enum.Error.html#variant.IdLimit
+    pub fn mark_retire_dcid_seq(&mut self, seq: u64, retire: bool) -> Result<()> {
+        if retire {
+            if self.retire_dcid_seqs.len() >= self.destination_conn_id_limit * 2 {
+                return Err(Error::IdLimit);
+            }
+
+            self.retire_dcid_seqs.push_back(seq);
+        } else {
+            self.retire_dcid_seqs.retain(|&s| s != seq);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the sequence number of the next source connection ID to be
+    /// advertised, if any.
+    pub fn next_advertise_new_scid_seq(&self) -> Option<u64> {
+        self.advertise_new_scid_seqs.front().copied()
+    }
+
+    /// Returns the sequence number of the next destination connection ID to
+    /// be retired, if any.
+    pub fn next_retire_dcid_seq(&self) -> Option<u64> {
+        self.retire_dcid_seqs.front().copied()
+    }
+
+    /// Returns whether there are source connection IDs to advertise.
+    pub fn has_new_scids(&self) -> bool {
+        !self.advertise_new_scid_seqs.is_empty()
+    }
+
+    /// Returns whether there are destination connection IDs to retire.
+    pub fn has_retire_dcids(&self) -> bool {
+        !self.retire_dcid_seqs.is_empty()
+    }
+
+    /// Returns the oldest source connection ID retired by the peer, if any.
+    pub fn pop_retired_scid(&mut self) -> Option<ConnectionId<'static>> {
+        self.retired_scids.pop_front()
+    }
+
+    /// Returns the number of source connection IDs retired by the peer that
+    /// were not yet reported to the application.
+    pub fn retired_scids(&self) -> usize {
+        self.retired_scids.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cid(v: u8) -> ConnectionId<'static> {
+        ConnectionId::from_vec(vec![v; 16])
+    }
+
+    #[test]
+    fn new_scid_limit() {
+        let mut ids = ConnectionIdentifiers::new(2, &cid(0), None);
+        ids.set_source_conn_id_limit(3);
+
+        assert_eq!(ids.active_source_cids(), 1);
+        assert_eq!(ids.source_cids_left(), 2);
+
+        assert_eq!(ids.new_scid(cid(1), 1, false), Ok(1));
+        assert_eq!(ids.new_scid(cid(2), 2, false), Ok(2));
+        assert_eq!(ids.source_cids_left(), 0);
+
+        assert_eq!(ids.new_scid(cid(3), 3, false), Err(Error::IdLimit));
+
+        // Adding an existing connection ID returns the same sequence number.
+        assert_eq!(ids.new_scid(cid(2), 2, false), Ok(2));
+        assert_eq!(ids.new_scid(cid(2), 3, false), Err(Error::InvalidState));
+
+        // Make room by asking the peer to retire the oldest one.
+        assert_eq!(ids.new_scid(cid(3), 3, true), Ok(3));
+        assert_eq!(ids.retire_prior_to(), 1);
+        assert_eq!(ids.active_source_cids(), 3);
+        assert_eq!(ids.lowest_active_scid_seq(), Some(1));
+
+        assert_eq!(ids.next_advertise_new_scid_seq(), Some(1));
+        ids.mark_advertise_new_scid_seq(1, false);
+        assert_eq!(ids.next_advertise_new_scid_seq(), Some(2));
+    }
+
+    #[test]
+    fn zero_length_scid() {
+        let mut ids =
+            ConnectionIdentifiers::new(2, &ConnectionId::default(), None);
+
+        assert_eq!(ids.source_cids_left(), 0);
+        assert_eq!(ids.new_scid(cid(1), 1, true), Err(Error::InvalidState));
+    }
+
+    #[test]
+    fn retire_scid() {
+        let mut ids = ConnectionIdentifiers::new(2, &cid(0), None);
+
+        assert_eq!(ids.new_scid(cid(1), 1, false), Ok(1));
+
+        // Unknown sequence number.
+        assert_eq!(ids.retire_scid(2, &cid(1)), Err(Error::InvalidState));
+
+        // Can't retire the connection ID the frame was sent to.
+        assert_eq!(ids.retire_scid(0, &cid(0)), Err(Error::InvalidState));
+
+        assert_eq!(ids.retire_scid(0, &cid(1)), Ok(Some(cid(0))));
+        assert_eq!(ids.retire_scid(0, &cid(1)), Ok(None));
+
+        assert_eq!(ids.retired_scids(), 1);
+        assert_eq!(ids.pop_retired_scid(), Some(cid(0)));
+        assert_eq!(ids.pop_retired_scid(), None);
+
+        assert_eq!(ids.active_source_cids(), 1);
+    }
+
+    #[test]
+    fn new_dcid() {
+        let mut ids = ConnectionIdentifiers::new(2, &cid(0), None);
+        ids.set_initial_dcid(cid(10), None);
+
+        assert_eq!(ids.new_dcid(cid(11), 1, 11, 0), Ok(vec![]));
+        assert_eq!(ids.available_dcids(), 2);
+
+        // Duplicate frames are ignored.
+        assert_eq!(ids.new_dcid(cid(11), 1, 11, 0), Ok(vec![]));
+
+        // Reusing a sequence number or connection ID is not allowed.
+        assert_eq!(ids.new_dcid(cid(12), 1, 12, 0), Err(Error::InvalidState));
+        assert_eq!(ids.new_dcid(cid(11), 2, 11, 0), Err(Error::InvalidState));
+
+        // Retire Prior To retires older connection IDs.
+        assert_eq!(ids.new_dcid(cid(12), 2, 12, 1), Ok(vec![0]));
+        assert_eq!(ids.available_dcids(), 2);
+        assert_eq!(ids.lowest_available_dcid_seq(), Some(1));
+        assert_eq!(ids.next_retire_dcid_seq(), Some(0));
+
+        // Connection IDs below Retire Prior To are retired immediately.
+        assert_eq!(ids.new_dcid(cid(13), 0, 13, 0), Ok(vec![]));
+        assert_eq!(ids.available_dcids(), 2);
+
+        // Exceeding the limit is an error.
+        assert_eq!(ids.new_dcid(cid(14), 3, 14, 1), Err(Error::IdLimit));
+        assert_eq!(ids.available_dcids(), 2);
+        assert_eq!(ids.get_dcid(3), Err(Error::InvalidState));
+    }
+
+    #[test]
+    fn dcid_path_linkage() {
+        let mut ids = ConnectionIdentifiers::new(3, &cid(0), None);
+        ids.set_initial_dcid(cid(10), None);
+
+        // The initial destination connection ID is used by the initial path.
+        assert_eq!(ids.lowest_available_dcid_seq(), None);
+
+        assert_eq!(ids.new_dcid(cid(11), 1, 11, 0), Ok(vec![]));
+        assert_eq!(ids.new_dcid(cid(12), 2, 12, 0), Ok(vec![]));
+        assert_eq!(ids.lowest_available_dcid_seq(), Some(1));
+
+        assert_eq!(ids.link_dcid_to_path(1, Some(1)), Ok(()));
+        assert_eq!(ids.lowest_available_dcid_seq(), Some(2));
+
+        assert_eq!(ids.retire_dcid(1), Ok(()));
+        assert_eq!(ids.next_retire_dcid_seq(), Some(1));
+        assert_eq!(ids.get_dcid(1), Err(Error::InvalidState));
+        assert_eq!(ids.retire_dcid(1), Err(Error::InvalidState));
+    }
+
+    #[test]
+    fn new_dcid_zero_length() {
+        let mut ids = ConnectionIdentifiers::new(2, &cid(0), None);
+        ids.set_initial_dcid(ConnectionId::default(), None);
+
+        assert_eq!(ids.new_dcid(cid(11), 1, 11, 0), Err(Error::InvalidState));
+    }
+}
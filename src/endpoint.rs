@@ -0,0 +1,1022 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Server endpoint.
+//!
+//! An [`Endpoint`] owns the connections accepted by a server, and takes care
+//! of the packet processing logic that is common to all servers: routing
+//! incoming packets to connections based on their destination connection ID,
+//! accepting new connections, version negotiation, stateless retry and
+//! stateless reset, as well as driving the timers of all connections.
+//!
+//! [`Endpoint`]: struct.Endpoint.html
+
+use std::cmp;
+use std::time;
+
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use std::pin::Pin;
+
+use crate::AddressToken;
+use crate::AddressTokenKey;
+use crate::Config;
+use crate::Connection;
+use crate::ConnectionId;
+use crate::Error;
+use crate::Header;
+use crate::RecvInfo;
+use crate::Result;
+use crate::SendInfo;
+use crate::Type;
+
+use crate::crypto;
+use crate::packet;
+use crate::rand;
+
+/// Maximum number of stateless packets (e.g. Retry) waiting to be sent.
+/// Packets that would exceed it are dropped.
+const MAX_STATELESS_QUEUE: usize = 1024;
+
+/// Identifies a connection owned by an [`Endpoint`].
+///
+/// Handles are never reused, even after the connection is closed.
+///
+/// [`Endpoint`]: struct.Endpoint.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionHandle(u64);
+
+/// An event about one of the connections owned by an [`Endpoint`].
+///
+/// [`Endpoint`]: struct.Endpoint.html
+pub enum EndpointEvent {
+    /// A new connection was accepted.
+    New(ConnectionHandle),
+
+    /// The connection processed incoming packets or timers, so its state
+    /// should be checked, e.g. for readable streams.
+    Updated(ConnectionHandle),
+
+    /// The connection was closed and removed from the endpoint. The closed
+    /// connection is returned so that e.g. its statistics can be inspected.
+    Closed(ConnectionHandle, Pin<Box<Connection>>),
+}
+
+impl std::fmt::Debug for EndpointEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EndpointEvent::New(h) => write!(f, "New({:?})", h),
+
+            EndpointEvent::Updated(h) => write!(f, "Updated({:?})", h),
+
+            EndpointEvent::Closed(h, conn) =>
+                write!(f, "Closed({:?}, {})", h, conn.trace_id()),
+        }
+    }
+}
+
+struct Entry {
+    conn: Pin<Box<Connection>>,
+
+    /// The source connection IDs routed to the connection.
+    cids: Vec<ConnectionId<'static>>,
+
+    /// The destination connection ID of the client's first Initial packet,
+    /// routed to the connection until the handshake completes.
+    initial_dcid: Option<ConnectionId<'static>>,
+
+    /// The timer last scheduled for the connection.
+    timeout: Option<time::Instant>,
+
+    /// Whether the connection is in the send queue.
+    sending: bool,
+
+    /// Whether an `Updated` event is pending for the connection.
+    updated: bool,
+}
+
+/// A server endpoint.
+///
+/// The endpoint accepts new connections and routes incoming packets to them,
+/// so that applications don't need to maintain their own connection table.
+/// Packets are fed with [`recv()`] and outgoing packets, including stateless
+/// ones, are generated with [`send()`]. Timers of all the connections are
+/// driven with [`timeout()`] and [`on_timeout()`].
+///
+/// Changes to the state of the connections are reported with
+/// [`event_next()`], and each connection can be accessed with [`conn()`] and
+/// [`conn_mut()`] using the [`ConnectionHandle`] reported in the events.
+///
+/// The source connection IDs used by connections are routed automatically,
+/// including the ones provided to the peer with
+/// [`Connection::new_source_cid()`], until the peer retires them. Those must
+/// be [`MAX_CONN_ID_LEN`] bytes long, like the ones generated by the endpoint,
+/// as the connection ID of short header packets is parsed assuming that
+/// length. Connection IDs of other lengths are not routed.
+///
+/// [`recv()`]: struct.Endpoint.html#method.recv
+/// [`send()`]: struct.Endpoint.html#method.send
+/// [`timeout()`]: struct.Endpoint.html#method.timeout
+/// [`on_timeout()`]: struct.Endpoint.html#method.on_timeout
+/// [`event_next()`]: struct.Endpoint.html#method.event_next
+/// [`conn()`]: struct.Endpoint.html#method.conn
+/// [`conn_mut()`]: struct.Endpoint.html#method.conn_mut
+/// [`ConnectionHandle`]: struct.ConnectionHandle.html
+/// [`Connection::new_source_cid()`]:
+///     struct.Connection.html#method.new_source_cid
+/// [`MAX_CONN_ID_LEN`]: constant.MAX_CONN_ID_LEN.html
+pub struct Endpoint {
+    config: Config,
+
+    retry: bool,
+
+    token_key: AddressTokenKey,
+
+    next_handle: u64,
+
+    conns: HashMap<ConnectionHandle, Entry>,
+
+    routes: HashMap<ConnectionId<'static>, ConnectionHandle>,
+
+    timers: BinaryHeap<cmp::Reverse<(time::Instant, ConnectionHandle)>>,
+
+    send_queue: VecDeque<ConnectionHandle>,
+
+    stateless: VecDeque<(Vec<u8>, SendInfo)>,
+
+    events: VecDeque<EndpointEvent>,
+}
+
+impl Endpoint {
+    /// Creates a new endpoint that accepts connections with the given
+    /// configuration.
+    ///
+    /// Address validation tokens are issued and validated with the key set
+    /// with [`Config::set_address_token_key()`], or with a random key if none
+    /// was set. Stateless resets are only sent when a secret was set with
+    /// [`Config::set_stateless_reset_secret()`].
+    ///
+    /// [`Config::set_address_token_key()`]:
+    ///     struct.Config.html#method.set_address_token_key
+    /// [`Config::set_stateless_reset_secret()`]:
+    ///     struct.Config.html#method.set_stateless_reset_secret
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// let config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// let mut endpoint = quiche::Endpoint::new(config);
+    /// endpoint.set_retry(true);
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn new(config: Config) -> Endpoint {
+        let token_key = config.address_token_key.clone().unwrap_or_else(|| {
+            let mut secret = [0; 32];
+            rand::rand_bytes(&mut secret);
+
+            AddressTokenKey::new(&secret)
+        });
+
+        Endpoint {
+            config,
+
+            retry: false,
+
+            token_key,
+
+            next_handle: 0,
+
+            conns: HashMap::new(),
+
+            routes: HashMap::new(),
+
+            timers: BinaryHeap::new(),
+
+            send_queue: VecDeque::new(),
+
+            stateless: VecDeque::new(),
+
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Configures whether clients need to validate their address with a
+    /// stateless retry before a connection is created.
+    ///
+    /// The default value is `false`.
+    pub fn set_retry(&mut self, v: bool) {
+        self.retry = v;
+    }
+
+    /// Processes QUIC packets received from the peer.
+    ///
+    /// The packets are passed to the connection their destination connection
+    /// ID is routed to. When there is no such connection, a new one is
+    /// created if the packet is a valid client Initial packet, or a stateless
+    /// packet (e.g. Version Negotiation or Retry) is queued in response.
+    ///
+    /// On success the number of bytes processed from the input buffer is
+    /// returned. Packets that don't belong to any connection, and can't start
+    /// a new one, are dropped and [`InvalidPacket`] is returned. Errors
+    /// returned by [`Connection::recv()`] are forwarded as is.
+    ///
+    /// [`InvalidPacket`]: enum.Error.html#variant.InvalidPacket
+    /// [`Connection::recv()`]: struct.Connection.html#method.recv
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 65535];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let mut endpoint = quiche::Endpoint::new(config);
+    /// let local = socket.local_addr().unwrap();
+    ///
+    /// loop {
+    ///     let (read, from) = socket.recv_from(&mut buf).unwrap();
+    ///
+    ///     let recv_info = quiche::RecvInfo {
+    ///         from,
+    ///         to: local,
+    ///         ecn: 0,
+    ///     };
+    ///
+    ///     // Invalid packets are simply dropped.
+    ///     endpoint.recv(&mut buf[..read], recv_info).ok();
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
+        let len = buf.len();
+
+        let hdr = Header::from_slice(buf, crate::MAX_CONN_ID_LEN)
+            .map_err(|_| Error::InvalidPacket)?;
+
+        let handle = match self.routes.get(&hdr.dcid) {
+            Some(v) => *v,
+
+            None => match self.accept(&hdr, len, info)? {
+                Some(v) => v,
+
+                // A stateless packet was sent in response.
+                None => return Ok(len),
+            },
+        };
+
+        let entry = self.conns.get_mut(&handle).ok_or(Error::InvalidState)?;
+
+        let res = entry.conn.recv(buf, info);
+
+        self.mark_sending(handle);
+        self.sync(handle, true);
+
+        res
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer.
+    ///
+    /// Queued stateless packets are returned first, followed by packets of
+    /// the connections that might have something to send, e.g. after
+    /// receiving packets or processing timers. Connections take turns, so
+    /// that a busy one doesn't prevent others from sending.
+    ///
+    /// On success the number of bytes written to the output buffer is
+    /// returned, along with the [`SendInfo`] of the packet. When there is
+    /// nothing more to send [`Done`] is returned.
+    ///
+    /// Connections that fail to generate packets are closed with an
+    /// `INTERNAL_ERROR` error code.
+    ///
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 1350];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let mut endpoint = quiche::Endpoint::new(config);
+    /// loop {
+    ///     let (write, send_info) = match endpoint.send(&mut out) {
+    ///         Ok(v) => v,
+    ///
+    ///         Err(quiche::Error::Done) => break,
+    ///
+    ///         Err(e) => {
+    ///             // An error occurred, handle it.
+    ///             break;
+    ///         },
+    ///     };
+    ///
+    ///     socket.send_to(&out[..write], &send_info.to).unwrap();
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        if let Some((pkt, info)) = self.stateless.pop_front() {
+            if out.len() < pkt.len() {
+                self.stateless.push_front((pkt, info));
+
+                return Err(Error::BufferTooShort);
+            }
+
+            out[..pkt.len()].copy_from_slice(&pkt);
+
+            return Ok((pkt.len(), info));
+        }
+
+        while let Some(handle) = self.send_queue.pop_front() {
+            let entry = match self.conns.get_mut(&handle) {
+                Some(v) => v,
+
+                None => continue,
+            };
+
+            match entry.conn.send(out) {
+                Ok(v) => {
+                    // Move on to the next connection, so that all of them
+                    // get a chance to send.
+                    self.send_queue.push_back(handle);
+                    self.sync(handle, false);
+
+                    return Ok(v);
+                },
+
+                Err(Error::Done) => {
+                    entry.sending = false;
+                    self.sync(handle, false);
+                },
+
+                Err(e) => {
+                    error!("{} send failed: {:?}", entry.conn.trace_id(), e);
+
+                    // Keep the connection in the queue, so that the
+                    // CONNECTION_CLOSE frame can be sent.
+                    match entry.conn.close(false, 0x1, b"") {
+                        Ok(_) => self.send_queue.push_back(handle),
+
+                        Err(_) => entry.sending = false,
+                    }
+
+                    self.sync(handle, false);
+                },
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the amount of time until the next timeout event.
+    ///
+    /// Once the given duration has elapsed, the [`on_timeout()`] method should
+    /// be called. A timeout of `None` means that the timer should be disarmed.
+    ///
+    /// [`on_timeout()`]: struct.Endpoint.html#method.on_timeout
+    pub fn timeout(&self) -> Option<time::Duration> {
+        // The earliest timer might be stale, in which case calling
+        // `on_timeout()` early is harmless.
+        self.timers.peek().map(|cmp::Reverse((t, _))| {
+            t.saturating_duration_since(time::Instant::now())
+        })
+    }
+
+    /// Processes a timeout event.
+    ///
+    /// The timers of all the connections that expired are processed, and
+    /// connections that were closed as a result are removed.
+    pub fn on_timeout(&mut self) {
+        let now = time::Instant::now();
+
+        let mut expired = Vec::new();
+
+        while let Some(cmp::Reverse((t, handle))) = self.timers.peek().copied() {
+            if t > now {
+                break;
+            }
+
+            self.timers.pop();
+
+            // Skip timers that were replaced by a different one.
+            match self.conns.get_mut(&handle) {
+                Some(entry) if entry.timeout == Some(t) => {
+                    entry.timeout = None;
+
+                    expired.push(handle);
+                },
+
+                _ => (),
+            }
+        }
+
+        for handle in expired {
+            if let Some(entry) = self.conns.get_mut(&handle) {
+                entry.conn.on_timeout();
+            }
+
+            self.mark_sending(handle);
+            self.sync(handle, true);
+        }
+    }
+
+    /// Returns the next event about the endpoint's connections, if any.
+    ///
+    /// An `Updated` event is only reported once until it's returned by this
+    /// method, even if the connection was updated multiple times. Note that
+    /// the connection might have been closed since then.
+    pub fn event_next(&mut self) -> Option<EndpointEvent> {
+        let ev = self.events.pop_front()?;
+
+        if let EndpointEvent::Updated(handle) = ev {
+            if let Some(entry) = self.conns.get_mut(&handle) {
+                entry.updated = false;
+            }
+        }
+
+        Some(ev)
+    }
+
+    /// Returns a reference to the connection with the given handle, if it's
+    /// still open.
+    pub fn conn(&self, handle: ConnectionHandle) -> Option<&Connection> {
+        self.conns.get(&handle).map(|e| &*e.conn)
+    }
+
+    /// Returns a mutable reference to the connection with the given handle,
+    /// if it's still open.
+    ///
+    /// The connection is assumed to have new packets to send, e.g. because
+    /// the application wrote stream data, so it's polled by the next call to
+    /// [`send()`].
+    ///
+    /// [`send()`]: struct.Endpoint.html#method.send
+    pub fn conn_mut(
+        &mut self, handle: ConnectionHandle,
+    ) -> Option<&mut Connection> {
+        self.mark_sending(handle);
+
+        self.conns.get_mut(&handle).map(|e| &mut *e.conn)
+    }
+
+    /// Returns an iterator over the handles of all the open connections.
+    pub fn handles(&self) -> impl Iterator<Item = ConnectionHandle> + '_ {
+        self.conns.keys().copied()
+    }
+
+    /// Returns the number of open connections.
+    pub fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    /// Returns true if there are no open connections.
+    pub fn is_empty(&self) -> bool {
+        self.conns.is_empty()
+    }
+
+    /// Creates a new connection for a packet that isn't routed to any
+    /// existing one.
+    ///
+    /// Returns `None` if a stateless packet was queued in response instead.
+    fn accept(
+        &mut self, hdr: &Header, len: usize, info: RecvInfo,
+    ) -> Result<Option<ConnectionHandle>> {
+        if hdr.ty == Type::Short {
+            // The connection's state might have been lost, so let the peer
+            // know with a stateless reset.
+            self.stateless_reset(&hdr.dcid, len, info)?;
+
+            return Ok(None);
+        }
+
+        // Clients need to pad datagrams carrying Initial packets, which also
+        // prevents stateless packets from being used for amplification.
+        if hdr.ty != Type::Initial || len < crate::MIN_CLIENT_INITIAL_LEN {
+            return Err(Error::InvalidPacket);
+        }
+
+        if !crate::version_is_supported(hdr.version) {
+            let mut out = vec![0; crate::MIN_CLIENT_INITIAL_LEN];
+
+            let written =
+                packet::negotiate_version(&hdr.scid, &hdr.dcid, &mut out)?;
+
+            self.queue_stateless(out, written, info);
+
+            return Ok(None);
+        }
+
+        let token = hdr.token.as_deref().unwrap_or_default();
+
+        let mut odcid = None;
+
+        if self.retry {
+            match self.token_key.validate(token, &info.from) {
+                // The client's Initial packet must use the connection ID
+                // sent in the Retry packet.
+                Ok(AddressToken::Retry(_))
+                    if hdr.dcid.len() != crate::MAX_CONN_ID_LEN =>
+                    return Err(Error::InvalidPacket),
+
+                Ok(AddressToken::Retry(v)) => odcid = Some(v),
+
+                // The connection validates the token itself.
+                Ok(AddressToken::NewToken) => (),
+
+                Err(_) => {
+                    let scid = new_cid();
+
+                    let token =
+                        self.token_key.retry_token(&hdr.dcid, &info.from)?;
+
+                    let mut out = vec![0; crate::MIN_CLIENT_INITIAL_LEN];
+
+                    let written = packet::retry(
+                        &hdr.scid,
+                        &hdr.dcid,
+                        &scid,
+                        &token,
+                        hdr.version,
+                        &mut out,
+                    )?;
+
+                    self.queue_stateless(out, written, info);
+
+                    return Ok(None);
+                },
+            }
+        }
+
+        // After a retry, the connection ID sent in the Retry packet is used,
+        // otherwise a new one is picked and the client's initial one is
+        // routed as well until the handshake completes.
+        let (scid, initial_dcid) = if odcid.is_some() {
+            (hdr.dcid.clone().into_owned(), None)
+        } else {
+            (new_cid(), Some(hdr.dcid.clone().into_owned()))
+        };
+
+        let conn = crate::accept(
+            &scid,
+            odcid.as_ref(),
+            info.to,
+            info.from,
+            &mut self.config,
+        )?;
+
+        let handle = ConnectionHandle(self.next_handle);
+        self.next_handle += 1;
+
+        if let Some(ref dcid) = initial_dcid {
+            self.routes.insert(dcid.clone(), handle);
+        }
+
+        self.conns.insert(handle, Entry {
+            conn,
+
+            cids: Vec::new(),
+
+            initial_dcid,
+
+            timeout: None,
+
+            sending: false,
+
+            updated: false,
+        });
+
+        self.events.push_back(EndpointEvent::New(handle));
+
+        self.sync(handle, false);
+
+        Ok(Some(handle))
+    }
+
+    fn stateless_reset(
+        &mut self, dcid: &ConnectionId, len: usize, info: RecvInfo,
+    ) -> Result<()> {
+        let secret = match self.config.stateless_reset_secret {
+            Some(ref v) => v,
+
+            None => return Err(Error::InvalidPacket),
+        };
+
+        let token = crypto::derive_stateless_reset_token(secret, dcid);
+
+        // The reset needs to be smaller than the received packet, to prevent
+        // two endpoints from exchanging stateless resets endlessly.
+        let mut out = vec![0; cmp::min(len - 1, crate::MIN_CLIENT_INITIAL_LEN)];
+
+        let written = packet::stateless_reset(token, &mut out)
+            .map_err(|_| Error::InvalidPacket)?;
+
+        self.queue_stateless(out, written, info);
+
+        Ok(())
+    }
+
+    fn queue_stateless(&mut self, mut out: Vec<u8>, len: usize, info: RecvInfo) {
+        if self.stateless.len() >= MAX_STATELESS_QUEUE {
+            return;
+        }
+
+        out.truncate(len);
+
+        let send_info = SendInfo {
+            from: info.to,
+            to: info.from,
+            at: time::Instant::now(),
+            ecn: 0,
+        };
+
+        self.stateless.push_back((out, send_info));
+    }
+
+    /// Puts the connection in the send queue, unless it's already there.
+    fn mark_sending(&mut self, handle: ConnectionHandle) {
+        if let Some(entry) = self.conns.get_mut(&handle) {
+            if !entry.sending {
+                entry.sending = true;
+
+                self.send_queue.push_back(handle);
+            }
+        }
+    }
+
+    /// Updates the routes and timer of the connection after its state might
+    /// have changed, or removes it if it was closed.
+    fn sync(&mut self, handle: ConnectionHandle, updated: bool) {
+        let entry = match self.conns.get_mut(&handle) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if entry.conn.is_closed() {
+            let entry = self.conns.remove(&handle).unwrap();
+
+            for cid in entry.cids.iter().chain(entry.initial_dcid.iter()) {
+                if self.routes.get(cid) == Some(&handle) {
+                    self.routes.remove(cid);
+                }
+            }
+
+            self.events
+                .push_back(EndpointEvent::Closed(handle, entry.conn));
+
+            return;
+        }
+
+        // Route new connection IDs, and stop routing retired ones.
+        let cids: Vec<ConnectionId<'static>> =
+            entry.conn.source_ids().cloned().collect();
+
+        for cid in &entry.cids {
+            if !cids.contains(cid) && self.routes.get(cid) == Some(&handle) {
+                self.routes.remove(cid);
+            }
+        }
+
+        for cid in &cids {
+            // Short header packets are parsed assuming the length of the
+            // connection IDs generated by the endpoint, so connection IDs of
+            // other lengths can't be routed.
+            if cid.len() != crate::MAX_CONN_ID_LEN {
+                if !entry.cids.contains(cid) {
+                    error!(
+                        "{} connection ID {:?} not routed, invalid length {}",
+                        entry.conn.trace_id(),
+                        cid,
+                        cid.len()
+                    );
+                }
+
+                continue;
+            }
+
+            self.routes.insert(cid.clone(), handle);
+        }
+
+        entry.cids = cids;
+
+        if entry.conn.is_established() {
+            if let Some(dcid) = entry.initial_dcid.take() {
+                if self.routes.get(&dcid) == Some(&handle) {
+                    self.routes.remove(&dcid);
+                }
+            }
+        }
+
+        let timeout = entry.conn.timeout_instant();
+
+        if timeout != entry.timeout {
+            entry.timeout = timeout;
+
+            if let Some(t) = timeout {
+                self.timers.push(cmp::Reverse((t, handle)));
+            }
+        }
+
+        if updated && !entry.updated {
+            entry.updated = true;
+
+            self.events.push_back(EndpointEvent::Updated(handle));
+        }
+
+        // Timers are replaced frequently, so drop the stale ones once there
+        // are too many of them.
+        if self.timers.len() > 2 * self.conns.len() + 64 {
+            self.timers = self
+                .conns
+                .iter()
+                .filter_map(|(h, e)| e.timeout.map(|t| cmp::Reverse((t, *h))))
+                .collect();
+        }
+    }
+}
+
+fn new_cid() -> ConnectionId<'static> {
+    let mut cid = vec![0; crate::MAX_CONN_ID_LEN];
+    rand::rand_bytes(&mut cid);
+
+    cid.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        config
+    }
+
+    fn client(config: &mut Config) -> Pin<Box<Connection>> {
+        let mut scid = [0; 16];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
+
+        let client_addr = "127.0.0.1:1234".parse().unwrap();
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+
+        crate::connect(Some("quic.tech"), &scid, client_addr, server_addr, config)
+            .unwrap()
+    }
+
+    fn recv_info(info: SendInfo) -> RecvInfo {
+        RecvInfo {
+            from: info.from,
+            to: info.to,
+            ecn: 0,
+        }
+    }
+
+    /// Exchanges packets between the client and the endpoint until there
+    /// are none left.
+    fn advance(client: &mut Connection, endpoint: &mut Endpoint) {
+        let mut buf = [0; 65535];
+
+        loop {
+            let mut sent = false;
+
+            while let Ok((len, info)) = client.send(&mut buf) {
+                endpoint.recv(&mut buf[..len], recv_info(info)).ok();
+                sent = true;
+            }
+
+            while let Ok((len, info)) = endpoint.send(&mut buf) {
+                client.recv(&mut buf[..len], recv_info(info)).ok();
+                sent = true;
+            }
+
+            if !sent {
+                break;
+            }
+        }
+    }
+
+    fn events(endpoint: &mut Endpoint) -> Vec<EndpointEvent> {
+        std::iter::from_fn(|| endpoint.event_next()).collect()
+    }
+
+    #[test]
+    fn handshake() {
+        let mut endpoint = Endpoint::new(config());
+        let mut client = client(&mut config());
+
+        advance(&mut client, &mut endpoint);
+        assert!(client.is_established());
+        assert_eq!(endpoint.len(), 1);
+
+        let h = endpoint.handles().next().unwrap();
+
+        let ev = events(&mut endpoint);
+        assert!(
+            matches!(ev[..], [EndpointEvent::New(a), EndpointEvent::Updated(b)] if a == h && b == h)
+        );
+
+        assert!(endpoint.conn(h).unwrap().is_established());
+        assert!(endpoint.timeout().is_some());
+
+        // The client's initial destination connection ID is not routed
+        // anymore.
+        assert_eq!(endpoint.routes.len(), 1);
+
+        assert_eq!(client.stream_send(4, b"hello", true), Ok(5));
+        advance(&mut client, &mut endpoint);
+
+        let mut b = [0; 15];
+        assert_eq!(
+            endpoint.conn_mut(h).unwrap().stream_recv(4, &mut b),
+            Ok((5, true))
+        );
+        assert_eq!(&b[..5], b"hello");
+    }
+
+    #[test]
+    fn retry() {
+        let mut buf = [0; 65535];
+
+        let mut endpoint = Endpoint::new(config());
+        endpoint.set_retry(true);
+
+        let mut client = client(&mut config());
+
+        let (len, info) = client.send(&mut buf).unwrap();
+        assert_eq!(endpoint.recv(&mut buf[..len], recv_info(info)), Ok(len));
+        assert!(endpoint.is_empty());
+
+        let (len, info) = endpoint.send(&mut buf).unwrap();
+        let hdr = Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.ty, Type::Retry);
+
+        assert_eq!(client.recv(&mut buf[..len], recv_info(info)), Ok(len));
+
+        advance(&mut client, &mut endpoint);
+        assert!(client.is_established());
+        assert_eq!(endpoint.len(), 1);
+    }
+
+    #[test]
+    fn version_negotiation() {
+        let mut buf = [0; 65535];
+
+        let mut endpoint = Endpoint::new(config());
+
+        let mut client_config = Config::new(0xbabababa).unwrap();
+        client_config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        client_config.verify_peer(false);
+
+        let mut client = client(&mut client_config);
+
+        let (len, info) = client.send(&mut buf).unwrap();
+        assert_eq!(endpoint.recv(&mut buf[..len], recv_info(info)), Ok(len));
+        assert!(endpoint.is_empty());
+
+        let (len, _) = endpoint.send(&mut buf).unwrap();
+        let hdr = Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.ty, Type::VersionNegotiation);
+
+        assert_eq!(endpoint.send(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    fn stateless_reset() {
+        let mut buf = [0; 65535];
+
+        let info = RecvInfo {
+            from: "127.0.0.1:1234".parse().unwrap(),
+            to: "127.0.0.1:4321".parse().unwrap(),
+            ecn: 0,
+        };
+
+        // Short header packet for an unknown connection.
+        let mut pkt = [0x40; 100];
+        let dcid = ConnectionId::from_ref(&pkt[1..1 + crate::MAX_CONN_ID_LEN])
+            .into_owned();
+
+        let mut endpoint = Endpoint::new(config());
+        assert_eq!(endpoint.recv(&mut pkt, info), Err(Error::InvalidPacket));
+        assert_eq!(endpoint.send(&mut buf), Err(Error::Done));
+
+        let mut config = config();
+        config.set_stateless_reset_secret(&[0xba; 32]);
+
+        let mut endpoint = Endpoint::new(config);
+        assert_eq!(endpoint.recv(&mut pkt, info), Ok(100));
+
+        let token = crate::stateless_reset_token(&[0xba; 32], &dcid);
+
+        let (len, send_info) = endpoint.send(&mut buf).unwrap();
+        assert_eq!(len, 99);
+        assert_eq!(send_info.to, info.from);
+        assert_eq!(buf[len - 16..len], token.to_be_bytes());
+
+        // Initial packets in small datagrams are dropped.
+        let mut pkt = [0xc0; 100];
+        assert_eq!(endpoint.recv(&mut pkt, info), Err(Error::InvalidPacket));
+    }
+
+    #[test]
+    fn route_new_cid_and_close() {
+        let mut endpoint = Endpoint::new(config());
+        let mut client = client(&mut config());
+
+        advance(&mut client, &mut endpoint);
+        assert!(client.is_established());
+
+        let h = endpoint.handles().next().unwrap();
+        events(&mut endpoint);
+
+        let cid = ConnectionId::from_ref(&[0xbb; crate::MAX_CONN_ID_LEN]);
+
+        let conn = endpoint.conn_mut(h).unwrap();
+        assert_eq!(conn.new_source_cid(&cid, 0xbb, false), Ok(1));
+
+        advance(&mut client, &mut endpoint);
+        assert_eq!(endpoint.routes.get(&cid), Some(&h));
+        assert_eq!(endpoint.routes.len(), 2);
+
+        assert_eq!(endpoint.conn_mut(h).unwrap().close(true, 0, b""), Ok(()));
+        advance(&mut client, &mut endpoint);
+        assert!(client.is_draining());
+
+        // Pretend the draining period is over.
+        let entry = endpoint.conns.get_mut(&h).unwrap();
+        entry.conn.draining_timer = Some(time::Instant::now());
+        endpoint.sync(h, false);
+
+        endpoint.on_timeout();
+
+        let ev = events(&mut endpoint);
+        assert!(
+            matches!(ev[..], [EndpointEvent::Updated(_), EndpointEvent::Closed(a, _)] if a == h)
+        );
+
+        assert!(endpoint.is_empty());
+        assert!(endpoint.routes.is_empty());
+        assert!(endpoint.conn(h).is_none());
+    }
+
+    #[test]
+    fn new_cid_invalid_length() {
+        let mut endpoint = Endpoint::new(config());
+        let mut client = client(&mut config());
+
+        advance(&mut client, &mut endpoint);
+        assert!(client.is_established());
+
+        let h = endpoint.handles().next().unwrap();
+        events(&mut endpoint);
+
+        let cid = ConnectionId::from_ref(&[0xbb; 8]);
+
+        let conn = endpoint.conn_mut(h).unwrap();
+        assert_eq!(conn.new_source_cid(&cid, 0xbb, false), Ok(1));
+
+        advance(&mut client, &mut endpoint);
+        assert_eq!(endpoint.routes.get(&cid), None);
+        assert_eq!(endpoint.routes.len(), 1);
+    }
+}
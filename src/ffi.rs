@@ -264,6 +264,41 @@ pub extern fn quiche_config_set_disable_active_migration(
     config.set_disable_active_migration(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_preferred_address(
+    config: &mut Config, v4: *const sockaddr, v4_len: socklen_t,
+    v6: *const sockaddr, v6_len: socklen_t,
+) {
+    let v4 = if !v4.is_null() {
+        match std_addr_from_c(unsafe { &*v4 }, v4_len) {
+            SocketAddr::V4(addr) => Some(addr),
+
+            SocketAddr::V6(_) => None,
+        }
+    } else {
+        None
+    };
+
+    let v6 = if !v6.is_null() {
+        match std_addr_from_c(unsafe { &*v6 }, v6_len) {
+            SocketAddr::V6(addr) => Some(addr),
+
+            SocketAddr::V4(_) => None,
+        }
+    } else {
+        None
+    };
+
+    config.set_preferred_address(v4, v6);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_active_connection_id_limit(
+    config: &mut Config, v: u64,
+) {
+    config.set_active_connection_id_limit(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_set_cc_algorithm_name(
     config: &mut Config, name: *const c_char,
@@ -288,6 +323,16 @@ pub extern fn quiche_config_enable_hystart(config: &mut Config, v: bool) {
     config.enable_hystart(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_enable_ecn(config: &mut Config, v: bool) {
+    config.enable_ecn(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_discover_pmtu(config: &mut Config, v: bool) {
+    config.discover_pmtu(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_config_enable_dgram(
     config: &mut Config, enabled: bool, recv_queue_len: size_t,
@@ -303,6 +348,44 @@ pub extern fn quiche_config_set_max_send_udp_payload_size(
     config.set_max_send_udp_payload_size(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_stateless_reset_secret(
+    config: &mut Config, secret: *const u8, secret_len: size_t,
+) {
+    let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
+
+    config.set_stateless_reset_secret(secret);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_key_update_threshold(
+    config: &mut Config, v: u64,
+) {
+    config.set_key_update_threshold(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_address_token_secret(
+    config: &mut Config, secret: *const u8, secret_len: size_t,
+) {
+    let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
+
+    config.set_address_token_key(AddressTokenKey::new(secret));
+}
+
+#[no_mangle]
+pub extern fn quiche_config_set_compatible_versions(
+    config: &mut Config, versions: *const u32, versions_len: size_t,
+) -> c_int {
+    let versions = unsafe { slice::from_raw_parts(versions, versions_len) };
+
+    match config.set_compatible_versions(versions) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_config_free(config: *mut Config) {
     unsafe { Box::from_raw(config) };
@@ -376,7 +459,8 @@ pub extern fn quiche_header_info(
 #[no_mangle]
 pub extern fn quiche_accept(
     scid: *const u8, scid_len: size_t, odcid: *const u8, odcid_len: size_t,
-    from: &sockaddr, from_len: socklen_t, config: &mut Config,
+    local: &sockaddr, local_len: socklen_t, peer: &sockaddr, peer_len: socklen_t,
+    config: &mut Config,
 ) -> *mut Connection {
     let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
     let scid = ConnectionId::from_ref(scid);
@@ -389,9 +473,10 @@ pub extern fn quiche_accept(
         None
     };
 
-    let from = std_addr_from_c(from, from_len);
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
 
-    match accept(&scid, odcid.as_ref(), from, config) {
+    match accept(&scid, odcid.as_ref(), local, peer, config) {
         Ok(c) => Box::into_raw(Pin::into_inner(c)),
 
         Err(_) => ptr::null_mut(),
@@ -400,8 +485,9 @@ pub extern fn quiche_accept(
 
 #[no_mangle]
 pub extern fn quiche_connect(
-    server_name: *const c_char, scid: *const u8, scid_len: size_t, to: &sockaddr,
-    to_len: socklen_t, config: &mut Config,
+    server_name: *const c_char, scid: *const u8, scid_len: size_t,
+    local: &sockaddr, local_len: socklen_t, peer: &sockaddr, peer_len: socklen_t,
+    config: &mut Config,
 ) -> *mut Connection {
     let server_name = if server_name.is_null() {
         None
@@ -412,9 +498,41 @@ pub extern fn quiche_connect(
     let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
     let scid = ConnectionId::from_ref(scid);
 
-    let to = std_addr_from_c(to, to_len);
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
 
-    match connect(server_name, &scid, to, config) {
+    match connect(server_name, &scid, local, peer, config) {
+        Ok(c) => Box::into_raw(Pin::into_inner(c)),
+
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_connect_with_token(
+    server_name: *const c_char, scid: *const u8, scid_len: size_t,
+    token: *const u8, token_len: size_t, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t, config: &mut Config,
+) -> *mut Connection {
+    let server_name = if server_name.is_null() {
+        None
+    } else {
+        Some(unsafe { ffi::CStr::from_ptr(server_name).to_str().unwrap() })
+    };
+
+    let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
+    let scid = ConnectionId::from_ref(scid);
+
+    let token = if token.is_null() {
+        None
+    } else {
+        Some(unsafe { slice::from_raw_parts(token, token_len) })
+    };
+
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match connect_with_token(server_name, &scid, token, local, peer, config) {
         Ok(c) => Box::into_raw(Pin::into_inner(c)),
 
         Err(_) => ptr::null_mut(),
@@ -471,11 +589,44 @@ pub extern fn quiche_retry(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_stateless_reset(
+    reset_token: *const u8, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    let reset_token = unsafe { slice::from_raw_parts(reset_token, 16) };
+    let mut token = [0; 16];
+    token.copy_from_slice(reset_token);
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match stateless_reset(u128::from_be_bytes(token), out) {
+        Ok(v) => v as ssize_t,
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_stateless_reset_token(
+    secret: *const u8, secret_len: size_t, cid: *const u8, cid_len: size_t,
+    out: *mut u8,
+) {
+    let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
+
+    let cid = unsafe { slice::from_raw_parts(cid, cid_len) };
+    let cid = ConnectionId::from_ref(cid);
+
+    let out = unsafe { slice::from_raw_parts_mut(out, 16) };
+
+    let token = stateless_reset_token(secret, &cid);
+    out.copy_from_slice(&token.to_be_bytes());
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_new_with_tls(
     scid: *const u8, scid_len: size_t, odcid: *const u8, odcid_len: size_t,
-    peer: &sockaddr, peer_len: socklen_t, config: &mut Config, ssl: *mut c_void,
-    is_server: bool,
+    local: &sockaddr, local_len: socklen_t, peer: &sockaddr, peer_len: socklen_t,
+    config: &mut Config, ssl: *mut c_void, is_server: bool,
 ) -> *mut Connection {
     let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
     let scid = ConnectionId::from_ref(scid);
@@ -488,6 +639,7 @@ pub extern fn quiche_conn_new_with_tls(
         None
     };
 
+    let local = std_addr_from_c(local, local_len);
     let peer = std_addr_from_c(peer, peer_len);
 
     let tls = unsafe { tls::Handshake::from_ptr(ssl) };
@@ -495,6 +647,7 @@ pub extern fn quiche_conn_new_with_tls(
     match Connection::with_tls(
         &scid,
         odcid.as_ref(),
+        local,
         peer,
         config,
         tls,
@@ -604,12 +757,17 @@ pub extern fn quiche_conn_set_session(
 pub struct RecvInfo<'a> {
     from: &'a sockaddr,
     from_len: socklen_t,
+    to: &'a sockaddr,
+    to_len: socklen_t,
+    ecn: u8,
 }
 
 impl<'a> From<&RecvInfo<'a>> for crate::RecvInfo {
     fn from(info: &RecvInfo) -> crate::RecvInfo {
         crate::RecvInfo {
             from: std_addr_from_c(info.from, info.from_len),
+            to: std_addr_from_c(info.to, info.to_len),
+            ecn: info.ecn,
         }
     }
 }
@@ -633,10 +791,14 @@ pub extern fn quiche_conn_recv(
 
 #[repr(C)]
 pub struct SendInfo {
+    from: sockaddr_storage,
+    from_len: socklen_t,
     to: sockaddr_storage,
     to_len: socklen_t,
 
     at: timespec,
+
+    ecn: u8,
 }
 
 #[no_mangle]
@@ -651,10 +813,42 @@ pub extern fn quiche_conn_send(
 
     match conn.send(out) {
         Ok((v, info)) => {
+            out_info.from_len = std_addr_to_c(&info.from, &mut out_info.from);
+            out_info.to_len = std_addr_to_c(&info.to, &mut out_info.to);
+
+            std_time_to_c(&info.at, &mut out_info.at);
+
+            out_info.ecn = info.ecn;
+
+            v as ssize_t
+        },
+
+        Err(e) => e.to_c(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_batch(
+    conn: &mut Connection, out: *mut u8, out_len: size_t, max_segments: size_t,
+    segment_size: &mut size_t, out_info: &mut SendInfo,
+) -> ssize_t {
+    if out_len > <ssize_t>::max_value() as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    match conn.send_batch(out, max_segments) {
+        Ok((v, size, info)) => {
+            *segment_size = size;
+
+            out_info.from_len = std_addr_to_c(&info.from, &mut out_info.from);
             out_info.to_len = std_addr_to_c(&info.to, &mut out_info.to);
 
             std_time_to_c(&info.at, &mut out_info.at);
 
+            out_info.ecn = info.ecn;
+
             v as ssize_t
         },
 
@@ -863,6 +1057,139 @@ pub extern fn quiche_conn_destination_id(
     *out_len = id.len();
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_new_source_cid(
+    conn: &mut Connection, scid: *const u8, scid_len: size_t,
+    reset_token: *const u8, retire_if_needed: bool,
+) -> i64 {
+    let scid = unsafe { slice::from_raw_parts(scid, scid_len) };
+    let scid = ConnectionId::from_ref(scid);
+
+    let reset_token = unsafe { slice::from_raw_parts(reset_token, 16) };
+    let mut token = [0; 16];
+    token.copy_from_slice(reset_token);
+
+    match conn.new_source_cid(&scid, u128::from_be_bytes(token), retire_if_needed)
+    {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_probe_path(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t,
+) -> i64 {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.probe_path(local, peer) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_migrate(
+    conn: &mut Connection, local: &sockaddr, local_len: socklen_t,
+    peer: &sockaddr, peer_len: socklen_t,
+) -> i64 {
+    let local = std_addr_from_c(local, local_len);
+    let peer = std_addr_from_c(peer, peer_len);
+
+    match conn.migrate(local, peer) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_initiate_key_update(conn: &mut Connection) -> c_int {
+    match conn.initiate_key_update() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_send_new_token(conn: &mut Connection) -> c_int {
+    match conn.send_new_token() {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_new_token_next(
+    conn: &mut Connection, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    if out_len > <ssize_t>::max_value() as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    // Leave the token in the queue if it doesn't fit in the buffer.
+    let token = match conn.received_tokens.front() {
+        Some(v) => v,
+
+        None => return Error::Done.to_c(),
+    };
+
+    if token.len() > out.len() {
+        return Error::BufferTooShort.to_c();
+    }
+
+    out[..token.len()].copy_from_slice(token);
+
+    let len = token.len();
+
+    conn.received_tokens.pop_front();
+
+    len as ssize_t
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_source_cids_left(conn: &Connection) -> size_t {
+    conn.source_cids_left()
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_retired_scid_next(
+    conn: &mut Connection, out: *mut u8, out_len: size_t,
+) -> ssize_t {
+    if out_len > <ssize_t>::max_value() as usize {
+        panic!("The provided buffer is too large");
+    }
+
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+
+    let scid = match conn.retired_scid_next() {
+        Some(v) => v,
+
+        None => return Error::Done.to_c(),
+    };
+
+    if scid.len() > out.len() {
+        return Error::BufferTooShort.to_c();
+    }
+
+    out[..scid.len()].copy_from_slice(&scid);
+
+    scid.len() as ssize_t
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_version(conn: &mut Connection) -> u32 {
+    conn.version()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_application_proto(
     conn: &mut Connection, out: &mut *const u8, out_len: &mut size_t,
@@ -948,6 +1275,7 @@ pub struct Stats {
     recv: usize,
     sent: usize,
     lost: usize,
+    spurious_lost: usize,
     retrans: usize,
     rtt: u64,
     cwnd: usize,
@@ -957,6 +1285,11 @@ pub struct Stats {
     stream_retrans_bytes: u64,
     pmtu: usize,
     delivery_rate: u64,
+    ecn_sent_ect0: usize,
+    ecn_recv_ect0: u64,
+    ecn_recv_ect1: u64,
+    ecn_recv_ce: u64,
+    ecn_peer_ce: u64,
     peer_max_idle_timeout: u64,
     peer_max_udp_payload_size: u64,
     peer_initial_max_data: u64,
@@ -979,6 +1312,7 @@ pub extern fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
     out.recv = stats.recv;
     out.sent = stats.sent;
     out.lost = stats.lost;
+    out.spurious_lost = stats.spurious_lost;
     out.retrans = stats.retrans;
     out.rtt = stats.rtt.as_nanos() as u64;
     out.cwnd = stats.cwnd;
@@ -988,6 +1322,11 @@ pub extern fn quiche_conn_stats(conn: &Connection, out: &mut Stats) {
     out.stream_retrans_bytes = stats.stream_retrans_bytes;
     out.pmtu = stats.pmtu;
     out.delivery_rate = stats.delivery_rate;
+    out.ecn_sent_ect0 = stats.ecn_sent_ect0;
+    out.ecn_recv_ect0 = stats.ecn_recv_ect0;
+    out.ecn_recv_ect1 = stats.ecn_recv_ect1;
+    out.ecn_recv_ce = stats.ecn_recv_ce;
+    out.ecn_peer_ce = stats.ecn_peer_ce;
     out.peer_max_idle_timeout = stats.peer_max_idle_timeout;
     out.peer_max_udp_payload_size = stats.peer_max_udp_payload_size;
     out.peer_initial_max_data = stats.peer_initial_max_data;
@@ -37,6 +37,13 @@ pub const MAX_DGRAM_OVERHEAD: usize = 2;
 pub const MAX_STREAM_OVERHEAD: usize = 12;
 pub const MAX_STREAM_SIZE: u64 = 1 << 62;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct EcnCounts {
+    pub ect0_count: u64,
+    pub ect1_count: u64,
+    pub ecn_ce_count: u64,
+}
+
 #[derive(Clone, PartialEq)]
 pub enum Frame {
     Padding {
@@ -48,6 +55,7 @@ pub enum Frame {
     ACK {
         ack_delay: u64,
         ranges: ranges::RangeSet,
+        ecn_counts: Option<EcnCounts>,
     },
 
     ResetStream {
@@ -180,7 +188,7 @@ impl Frame {
 
             0x01 => Frame::Ping,
 
-            0x02 => parse_ack_frame(frame_type, b)?,
+            0x02..=0x03 => parse_ack_frame(frame_type, b)?,
 
             0x04 => Frame::ResetStream {
                 stream_id: b.get_varint()?,
@@ -201,8 +209,17 @@ impl Frame {
                 Frame::Crypto { data }
             },
 
-            0x07 => Frame::NewToken {
-                token: b.get_bytes_with_varint_length()?.to_vec(),
+            0x07 => {
+                let token = b.get_bytes_with_varint_length()?;
+
+                // Tokens must not be empty.
+                if token.cap() == 0 {
+                    return Err(Error::InvalidFrame);
+                }
+
+                Frame::NewToken {
+                    token: token.to_vec(),
+                }
             },
 
             0x08..=0x0f => parse_stream_frame(frame_type, b)?,
@@ -241,11 +258,28 @@ impl Frame {
                 limit: b.get_varint()?,
             },
 
-            0x18 => Frame::NewConnectionId {
-                seq_num: b.get_varint()?,
-                retire_prior_to: b.get_varint()?,
-                conn_id: b.get_bytes_with_u8_length()?.to_vec(),
-                reset_token: b.get_bytes(16)?.to_vec(),
+            0x18 => {
+                let seq_num = b.get_varint()?;
+                let retire_prior_to = b.get_varint()?;
+                let conn_id = b.get_bytes_with_u8_length()?.to_vec();
+                let reset_token = b.get_bytes(16)?.to_vec();
+
+                if retire_prior_to > seq_num {
+                    return Err(Error::InvalidFrame);
+                }
+
+                if conn_id.is_empty() ||
+                    conn_id.len() > packet::MAX_CID_LEN as usize
+                {
+                    return Err(Error::InvalidFrame);
+                }
+
+                Frame::NewConnectionId {
+                    seq_num,
+                    retire_prior_to,
+                    conn_id,
+                    reset_token,
+                }
             },
 
             0x19 => Frame::RetireConnectionId {
@@ -331,8 +365,16 @@ impl Frame {
                 b.put_varint(0x01)?;
             },
 
-            Frame::ACK { ack_delay, ranges } => {
-                b.put_varint(0x02)?;
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                if ecn_counts.is_some() {
+                    b.put_varint(0x03)?;
+                } else {
+                    b.put_varint(0x02)?;
+                }
 
                 let mut it = ranges.iter().rev();
 
@@ -355,6 +397,12 @@ impl Frame {
 
                     smallest_ack = block.start;
                 }
+
+                if let Some(ecn) = ecn_counts {
+                    b.put_varint(ecn.ect0_count)?;
+                    b.put_varint(ecn.ect1_count)?;
+                    b.put_varint(ecn.ecn_ce_count)?;
+                }
             },
 
             Frame::ResetStream {
@@ -538,7 +586,11 @@ impl Frame {
 
             Frame::Ping => 1,
 
-            Frame::ACK { ack_delay, ranges } => {
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
                 let mut it = ranges.iter().rev();
 
                 let first = it.next().unwrap();
@@ -562,6 +614,12 @@ impl Frame {
                     smallest_ack = block.start;
                 }
 
+                if let Some(ecn) = ecn_counts {
+                    len += octets::varint_len(ecn.ect0_count) + // ect0_count
+                        octets::varint_len(ecn.ect1_count) + // ect1_count
+                        octets::varint_len(ecn.ecn_ce_count); // ecn_ce_count
+                }
+
                 len
             },
 
@@ -740,6 +798,16 @@ impl Frame {
         )
     }
 
+    pub fn probing(&self) -> bool {
+        matches!(
+            self,
+            Frame::Padding { .. } |
+                Frame::NewConnectionId { .. } |
+                Frame::PathChallenge { .. } |
+                Frame::PathResponse { .. }
+        )
+    }
+
     pub fn shrink_for_retransmission(&mut self) {
         if let Frame::Datagram { data } = self {
             *data = Vec::new();
@@ -753,15 +821,19 @@ impl Frame {
 
             Frame::Ping { .. } => qlog::QuicFrame::ping(),
 
-            Frame::ACK { ack_delay, ranges } => {
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
                 let ack_ranges =
                     ranges.iter().map(|r| (r.start, r.end - 1)).collect();
                 qlog::QuicFrame::ack(
                     Some(ack_delay.to_string()),
                     Some(ack_ranges),
-                    None,
-                    None,
-                    None,
+                    ecn_counts.map(|ecn| ecn.ect1_count.to_string()),
+                    ecn_counts.map(|ecn| ecn.ect0_count.to_string()),
+                    ecn_counts.map(|ecn| ecn.ecn_ce_count.to_string()),
                 )
             },
 
@@ -925,8 +997,16 @@ impl std::fmt::Debug for Frame {
                 write!(f, "PING")?;
             },
 
-            Frame::ACK { ack_delay, ranges } => {
-                write!(f, "ACK delay={} blocks={:?}", ack_delay, ranges)?;
+            Frame::ACK {
+                ack_delay,
+                ranges,
+                ecn_counts,
+            } => {
+                write!(
+                    f,
+                    "ACK delay={} blocks={:?} ecn_counts={:?}",
+                    ack_delay, ranges, ecn_counts
+                )?;
             },
 
             Frame::ResetStream {
@@ -960,8 +1040,8 @@ impl std::fmt::Debug for Frame {
                 write!(f, "CRYPTO off={} len={}", offset, length)?;
             },
 
-            Frame::NewToken { .. } => {
-                write!(f, "NEW_TOKEN (TODO)")?;
+            Frame::NewToken { token } => {
+                write!(f, "NEW_TOKEN len={}", token.len())?;
             },
 
             Frame::Stream { stream_id, data } => {
@@ -1024,12 +1104,21 @@ impl std::fmt::Debug for Frame {
                 write!(f, "STREAMS_BLOCKED type=uni limit={}", limit)?;
             },
 
-            Frame::NewConnectionId { .. } => {
-                write!(f, "NEW_CONNECTION_ID (TODO)")?;
+            Frame::NewConnectionId {
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                write!(
+                    f,
+                    "NEW_CONNECTION_ID seq_num={} retire_prior_to={} conn_id={:02x?} reset_token={:02x?}",
+                    seq_num, retire_prior_to, conn_id, reset_token,
+                )?;
             },
 
-            Frame::RetireConnectionId { .. } => {
-                write!(f, "RETIRE_CONNECTION_ID (TODO)")?;
+            Frame::RetireConnectionId { seq_num } => {
+                write!(f, "RETIRE_CONNECTION_ID seq_num={}", seq_num)?;
             },
 
             Frame::PathChallenge { data } => {
@@ -1073,7 +1162,7 @@ impl std::fmt::Debug for Frame {
     }
 }
 
-fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
+fn parse_ack_frame(ty: u64, b: &mut octets::Octets) -> Result<Frame> {
     let largest_ack = b.get_varint()?;
     let ack_delay = b.get_varint()?;
     let block_count = b.get_varint()?;
@@ -1110,7 +1199,21 @@ fn parse_ack_frame(_ty: u64, b: &mut octets::Octets) -> Result<Frame> {
         ranges.insert(smallest_ack..largest_ack + 1);
     }
 
-    Ok(Frame::ACK { ack_delay, ranges })
+    let ecn_counts = if ty & 0x01 != 0 {
+        Some(EcnCounts {
+            ect0_count: b.get_varint()?,
+            ect1_count: b.get_varint()?,
+            ecn_ce_count: b.get_varint()?,
+        })
+    } else {
+        None
+    };
+
+    Ok(Frame::ACK {
+        ack_delay,
+        ranges,
+        ecn_counts,
+    })
 }
 
 pub fn encode_crypto_header(
@@ -1268,6 +1371,7 @@ mod tests {
         let frame = Frame::ACK {
             ack_delay: 874_656_534,
             ranges,
+            ecn_counts: None,
         };
 
         let wire_len = {
@@ -1290,6 +1394,49 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
     }
 
+    #[test]
+    fn ack_ecn() {
+        let mut d = [42; 128];
+
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(4..7);
+        ranges.insert(9..12);
+        ranges.insert(15..19);
+        ranges.insert(3000..5000);
+
+        let ecn_counts = Some(EcnCounts {
+            ect0_count: 100,
+            ect1_count: 200,
+            ecn_ce_count: 300,
+        });
+
+        let frame = Frame::ACK {
+            ack_delay: 874_656_534,
+            ranges,
+            ecn_counts,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 23);
+        assert_eq!(d[0], 0x03);
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_ok());
+    }
+
     #[test]
     fn reset_stream() {
         let mut d = [42; 128];
@@ -68,6 +68,20 @@ pub extern fn quiche_h3_config_set_qpack_blocked_streams(
     config.set_qpack_blocked_streams(v);
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_config_enable_extended_connect(
+    config: &mut h3::Config, v: bool,
+) {
+    config.enable_extended_connect(v);
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_config_enable_webtransport(
+    config: &mut h3::Config, v: bool,
+) {
+    config.enable_webtransport(v);
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_config_free(config: *mut h3::Config) {
     unsafe { Box::from_raw(config) };
@@ -116,6 +130,16 @@ pub extern fn quiche_h3_event_type(ev: &h3::Event) -> u32 {
         h3::Event::GoAway { .. } => 4,
 
         h3::Event::Reset { .. } => 5,
+
+        h3::Event::PushPromise { .. } => 6,
+
+        h3::Event::CancelPush { .. } => 7,
+
+        h3::Event::PriorityUpdate { .. } => 8,
+
+        h3::Event::WebTransportStream { .. } => 9,
+
+        h3::Event::WebTransportSessionClosed { .. } => 10,
     }
 }
 
@@ -134,7 +158,7 @@ pub extern fn quiche_h3_event_for_each_header(
     argp: *mut c_void,
 ) -> c_int {
     match ev {
-        h3::Event::Headers { list, .. } =>
+        h3::Event::Headers { list, .. } | h3::Event::PushPromise { list, .. } =>
             for h in list {
                 let rc = cb(
                     h.name().as_ptr(),
@@ -164,6 +188,57 @@ pub extern fn quiche_h3_event_headers_has_body(ev: &h3::Event) -> bool {
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_event_push_promise_id(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::PushPromise { push_id, .. } => *push_id,
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_event_priority_update(
+    ev: &h3::Event, urgency: &mut u8, incremental: &mut bool,
+) {
+    match ev {
+        h3::Event::PriorityUpdate(priority) => {
+            *urgency = priority.urgency();
+            *incremental = priority.incremental();
+        },
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_event_webtransport_session_id(ev: &h3::Event) -> u64 {
+    match ev {
+        h3::Event::WebTransportStream { session_id } => *session_id,
+
+        _ => unreachable!(),
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_event_webtransport_session_closed(
+    ev: &h3::Event, error_code: &mut u32, reason: &mut *const u8,
+    reason_len: &mut size_t,
+) {
+    match ev {
+        h3::Event::WebTransportSessionClosed {
+            error_code: code,
+            reason: r,
+        } => {
+            *error_code = *code;
+            *reason = r.as_ptr();
+            *reason_len = r.len();
+        },
+
+        _ => unreachable!(),
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_event_free(ev: *mut h3::Event) {
     unsafe { Box::from_raw(ev) };
@@ -192,6 +267,23 @@ pub extern fn quiche_h3_send_request(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_send_request_with_priority(
+    conn: &mut h3::Connection, quic_conn: &mut Connection,
+    headers: *const Header, headers_len: size_t, urgency: u8, incremental: bool,
+    fin: bool,
+) -> i64 {
+    let req_headers = headers_from_ptr(headers, headers_len);
+    let priority = h3::Priority::new(urgency, incremental);
+
+    match conn.send_request_with_priority(quic_conn, &req_headers, priority, fin)
+    {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_send_response(
     conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
@@ -228,6 +320,48 @@ pub extern fn quiche_h3_send_response_with_priority(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_set_stream_priority(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    urgency: u8, incremental: bool,
+) -> c_int {
+    let priority = h3::Priority::new(urgency, incremental);
+
+    match conn.set_stream_priority(quic_conn, stream_id, priority) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_priority_update_for_request(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    urgency: u8, incremental: bool,
+) -> c_int {
+    let priority = h3::Priority::new(urgency, incremental);
+
+    match conn.send_priority_update_for_request(quic_conn, stream_id, priority) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_priority_update_for_push(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+    urgency: u8, incremental: bool,
+) -> c_int {
+    let priority = h3::Priority::new(urgency, incremental);
+
+    match conn.send_priority_update_for_push(quic_conn, push_id, priority) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_send_body(
     conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
@@ -264,6 +398,142 @@ pub extern fn quiche_h3_recv_body(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_h3_send_max_push_id(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.send_max_push_id(quic_conn, push_id) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_push_promise(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, stream_id: u64,
+    headers: *const Header, headers_len: size_t,
+) -> i64 {
+    let req_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_push_promise(quic_conn, stream_id, &req_headers) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_push_response(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+    headers: *const Header, headers_len: size_t, fin: bool,
+) -> i64 {
+    let resp_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_push_response(quic_conn, push_id, &resp_headers, fin) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_cancel_push(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, push_id: u64,
+) -> c_int {
+    match conn.cancel_push(quic_conn, push_id) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_push_id(conn: &h3::Connection, stream_id: u64) -> i64 {
+    match conn.push_id(stream_id) {
+        Some(v) => v as i64,
+
+        None => h3::Error::Done.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_extended_connect_enabled_by_peer(
+    conn: &h3::Connection,
+) -> bool {
+    conn.extended_connect_enabled_by_peer()
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_webtransport_enabled_by_peer(
+    conn: &h3::Connection, quic_conn: &Connection,
+) -> bool {
+    conn.webtransport_enabled_by_peer(quic_conn)
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_send_webtransport_connect(
+    conn: &mut h3::Connection, quic_conn: &mut Connection,
+    headers: *const Header, headers_len: size_t,
+) -> i64 {
+    let req_headers = headers_from_ptr(headers, headers_len);
+
+    match conn.send_webtransport_connect(quic_conn, &req_headers) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_open_webtransport_uni_stream(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, session_id: u64,
+) -> i64 {
+    match conn.open_webtransport_uni_stream(quic_conn, session_id) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_open_webtransport_bidi_stream(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, session_id: u64,
+) -> i64 {
+    match conn.open_webtransport_bidi_stream(quic_conn, session_id) {
+        Ok(v) => v as i64,
+
+        Err(e) => e.to_c() as i64,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_close_webtransport_session(
+    conn: &mut h3::Connection, quic_conn: &mut Connection, session_id: u64,
+    error_code: u32, reason: *const u8, reason_len: size_t,
+) -> c_int {
+    let reason = unsafe { slice::from_raw_parts(reason, reason_len) };
+
+    match conn
+        .close_webtransport_session(quic_conn, session_id, error_code, reason)
+    {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_h3_webtransport_session_id(
+    conn: &h3::Connection, stream_id: u64,
+) -> i64 {
+    match conn.webtransport_session_id(stream_id) {
+        Some(v) => v as i64,
+
+        None => h3::Error::Done.to_c() as i64,
+    }
+}
+
 #[no_mangle]
 pub extern fn quiche_h3_dgram_enabled_by_peer(
     conn: &h3::Connection, quic_conn: &Connection,
@@ -35,11 +35,18 @@ pub const SETTINGS_FRAME_TYPE_ID: u64 = 0x4;
 pub const PUSH_PROMISE_FRAME_TYPE_ID: u64 = 0x5;
 pub const GOAWAY_FRAME_TYPE_ID: u64 = 0x6;
 pub const MAX_PUSH_FRAME_TYPE_ID: u64 = 0xD;
+pub const PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID: u64 = 0xF0700;
+pub const PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID: u64 = 0xF0701;
+pub const WEBTRANSPORT_STREAM_FRAME_TYPE_ID: u64 = 0x41;
+
+pub const CLOSE_WEBTRANSPORT_SESSION_CAPSULE_TYPE_ID: u64 = 0x2843;
 
 const SETTINGS_QPACK_MAX_TABLE_CAPACITY: u64 = 0x1;
 const SETTINGS_MAX_HEADER_LIST_SIZE: u64 = 0x6;
 const SETTINGS_QPACK_BLOCKED_STREAMS: u64 = 0x7;
+const SETTINGS_ENABLE_CONNECT_PROTOCOL: u64 = 0x8;
 const SETTINGS_H3_DATAGRAM: u64 = 0x276;
+const SETTINGS_ENABLE_WEBTRANSPORT: u64 = 0x2b60_3742;
 
 // Permit between 16 maximally-encoded and 128 minimally-encoded SETTINGS.
 const MAX_SETTINGS_PAYLOAD_SIZE: usize = 256;
@@ -63,6 +70,8 @@ pub enum Frame {
         qpack_max_table_capacity: Option<u64>,
         qpack_blocked_streams: Option<u64>,
         h3_datagram: Option<u64>,
+        connect_protocol_enabled: Option<u64>,
+        webtransport_enabled: Option<u64>,
         grease: Option<(u64, u64)>,
     },
 
@@ -79,6 +88,16 @@ pub enum Frame {
         push_id: u64,
     },
 
+    PriorityUpdateRequest {
+        prioritized_element_id: u64,
+        priority_field_value: Vec<u8>,
+    },
+
+    PriorityUpdatePush {
+        prioritized_element_id: u64,
+        priority_field_value: Vec<u8>,
+    },
+
     Unknown,
 }
 
@@ -116,6 +135,10 @@ impl Frame {
                 push_id: b.get_varint()?,
             },
 
+            PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID |
+            PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID =>
+                parse_priority_update(frame_type, payload_length, &mut b)?,
+
             _ => Frame::Unknown,
         };
 
@@ -152,6 +175,8 @@ impl Frame {
                 qpack_max_table_capacity,
                 qpack_blocked_streams,
                 h3_datagram,
+                connect_protocol_enabled,
+                webtransport_enabled,
                 grease,
             } => {
                 let mut len = 0;
@@ -176,6 +201,16 @@ impl Frame {
                     len += octets::varint_len(*val);
                 }
 
+                if let Some(val) = connect_protocol_enabled {
+                    len += octets::varint_len(SETTINGS_ENABLE_CONNECT_PROTOCOL);
+                    len += octets::varint_len(*val);
+                }
+
+                if let Some(val) = webtransport_enabled {
+                    len += octets::varint_len(SETTINGS_ENABLE_WEBTRANSPORT);
+                    len += octets::varint_len(*val);
+                }
+
                 if let Some(val) = grease {
                     len += octets::varint_len(val.0);
                     len += octets::varint_len(val.1);
@@ -204,6 +239,16 @@ impl Frame {
                     b.put_varint(*val as u64)?;
                 }
 
+                if let Some(val) = connect_protocol_enabled {
+                    b.put_varint(SETTINGS_ENABLE_CONNECT_PROTOCOL)?;
+                    b.put_varint(*val)?;
+                }
+
+                if let Some(val) = webtransport_enabled {
+                    b.put_varint(SETTINGS_ENABLE_WEBTRANSPORT)?;
+                    b.put_varint(*val)?;
+                }
+
                 if let Some(val) = grease {
                     b.put_varint(val.0)?;
                     b.put_varint(val.1)?;
@@ -236,6 +281,34 @@ impl Frame {
                 b.put_varint(*push_id)?;
             },
 
+            Frame::PriorityUpdateRequest {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                let len = octets::varint_len(*prioritized_element_id) +
+                    priority_field_value.len();
+
+                b.put_varint(PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID)?;
+                b.put_varint(len as u64)?;
+
+                b.put_varint(*prioritized_element_id)?;
+                b.put_bytes(priority_field_value)?;
+            },
+
+            Frame::PriorityUpdatePush {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                let len = octets::varint_len(*prioritized_element_id) +
+                    priority_field_value.len();
+
+                b.put_varint(PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID)?;
+                b.put_varint(len as u64)?;
+
+                b.put_varint(*prioritized_element_id)?;
+                b.put_bytes(priority_field_value)?;
+            },
+
             Frame::Unknown => unreachable!(),
         }
 
@@ -287,6 +360,30 @@ impl std::fmt::Debug for Frame {
                 write!(f, "MAX_PUSH_ID push_id={}", push_id)?;
             },
 
+            Frame::PriorityUpdateRequest {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                write!(
+                    f,
+                    "PRIORITY_UPDATE request_stream_id={}, priority_field_len={}",
+                    prioritized_element_id,
+                    priority_field_value.len()
+                )?;
+            },
+
+            Frame::PriorityUpdatePush {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                write!(
+                    f,
+                    "PRIORITY_UPDATE push_id={}, priority_field_len={}",
+                    prioritized_element_id,
+                    priority_field_value.len()
+                )?;
+            },
+
             Frame::Unknown => {
                 write!(f, "UNKNOWN")?;
             },
@@ -303,6 +400,8 @@ fn parse_settings_frame(
     let mut qpack_max_table_capacity = None;
     let mut qpack_blocked_streams = None;
     let mut h3_datagram = None;
+    let mut connect_protocol_enabled = None;
+    let mut webtransport_enabled = None;
 
     // Reject SETTINGS frames that are too long.
     if settings_length > MAX_SETTINGS_PAYLOAD_SIZE {
@@ -334,6 +433,22 @@ fn parse_settings_frame(
                 h3_datagram = Some(settings_val);
             },
 
+            SETTINGS_ENABLE_CONNECT_PROTOCOL => {
+                if settings_val > 1 {
+                    return Err(super::Error::SettingsError);
+                }
+
+                connect_protocol_enabled = Some(settings_val);
+            },
+
+            SETTINGS_ENABLE_WEBTRANSPORT => {
+                if settings_val > 1 {
+                    return Err(super::Error::SettingsError);
+                }
+
+                webtransport_enabled = Some(settings_val);
+            },
+
             // Reserved values overlap with HTTP/2 and MUST be rejected
             0x0 | 0x2 | 0x3 | 0x4 | 0x5 =>
                 return Err(super::Error::SettingsError),
@@ -348,6 +463,8 @@ fn parse_settings_frame(
         qpack_max_table_capacity,
         qpack_blocked_streams,
         h3_datagram,
+        connect_protocol_enabled,
+        webtransport_enabled,
         grease: None,
     })
 }
@@ -365,6 +482,32 @@ fn parse_push_promise(
     })
 }
 
+fn parse_priority_update(
+    frame_type: u64, payload_length: u64, b: &mut octets::Octets,
+) -> Result<Frame> {
+    let prioritized_element_id = b.get_varint()?;
+    let priority_field_value_length = payload_length
+        .checked_sub(octets::varint_len(prioritized_element_id) as u64)
+        .ok_or(super::Error::FrameError)?;
+    let priority_field_value =
+        b.get_bytes(priority_field_value_length as usize)?.to_vec();
+
+    match frame_type {
+        PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID =>
+            Ok(Frame::PriorityUpdateRequest {
+                prioritized_element_id,
+                priority_field_value,
+            }),
+
+        PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID => Ok(Frame::PriorityUpdatePush {
+            prioritized_element_id,
+            priority_field_value,
+        }),
+
+        _ => unreachable!(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -461,6 +604,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: Some(0),
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -494,6 +639,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: Some(0),
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: Some((33, 33)),
         };
 
@@ -503,6 +650,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: Some(0),
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -536,6 +685,8 @@ mod tests {
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
             h3_datagram: None,
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -569,6 +720,8 @@ mod tests {
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
             h3_datagram: Some(1),
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -602,6 +755,8 @@ mod tests {
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
             h3_datagram: Some(5),
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -625,6 +780,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn settings_webtransport_only() {
+        let mut d = [42; 128];
+
+        let frame = Frame::Settings {
+            max_header_list_size: None,
+            qpack_max_table_capacity: None,
+            qpack_blocked_streams: None,
+            h3_datagram: None,
+            connect_protocol_enabled: Some(1),
+            webtransport_enabled: Some(1),
+            grease: None,
+        };
+
+        let frame_payload_len = 7;
+        let frame_header_len = 2;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, frame_header_len + frame_payload_len);
+
+        assert_eq!(
+            Frame::from_bytes(
+                SETTINGS_FRAME_TYPE_ID,
+                frame_payload_len as u64,
+                &d[frame_header_len..]
+            )
+            .unwrap(),
+            frame
+        );
+    }
+
+    #[test]
+    fn settings_connect_protocol_bad() {
+        let mut d = [42; 128];
+
+        let frame = Frame::Settings {
+            max_header_list_size: None,
+            qpack_max_table_capacity: None,
+            qpack_blocked_streams: None,
+            h3_datagram: None,
+            connect_protocol_enabled: Some(2),
+            webtransport_enabled: None,
+            grease: None,
+        };
+
+        let frame_payload_len = 2;
+        let frame_header_len = 2;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, frame_header_len + frame_payload_len);
+
+        assert_eq!(
+            Frame::from_bytes(
+                SETTINGS_FRAME_TYPE_ID,
+                frame_payload_len as u64,
+                &d[frame_header_len..]
+            ),
+            Err(crate::h3::Error::SettingsError)
+        );
+    }
+
     #[test]
     fn settings_qpack_only() {
         let mut d = [42; 128];
@@ -634,6 +858,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: None,
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -836,6 +1062,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn priority_update_request() {
+        let mut d = [42; 128];
+
+        let prioritized_element_id = 4;
+        let priority_field_value = b"u=3, i".to_vec();
+
+        let frame = Frame::PriorityUpdateRequest {
+            prioritized_element_id,
+            priority_field_value,
+        };
+
+        let frame_payload_len = 7;
+        let frame_header_len = 5;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, frame_header_len + frame_payload_len);
+
+        assert_eq!(
+            Frame::from_bytes(
+                PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID,
+                frame_payload_len as u64,
+                &d[frame_header_len..]
+            )
+            .unwrap(),
+            frame
+        );
+    }
+
+    #[test]
+    fn priority_update_push() {
+        let mut d = [42; 128];
+
+        let prioritized_element_id = 6;
+        let priority_field_value = b"u=1".to_vec();
+
+        let frame = Frame::PriorityUpdatePush {
+            prioritized_element_id,
+            priority_field_value,
+        };
+
+        let frame_payload_len = 4;
+        let frame_header_len = 5;
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, frame_header_len + frame_payload_len);
+
+        assert_eq!(
+            Frame::from_bytes(
+                PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID,
+                frame_payload_len as u64,
+                &d[frame_header_len..]
+            )
+            .unwrap(),
+            frame
+        );
+    }
+
     #[test]
     fn unknown_type() {
         let d = [42; 12];
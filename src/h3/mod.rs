@@ -60,8 +60,9 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config).unwrap();
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! let h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! # Ok::<(), quiche::h3::Error>(())
@@ -76,8 +77,9 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let to = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! let req = vec![
@@ -98,8 +100,9 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let to = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! let req = vec![
@@ -129,8 +132,9 @@
 //!
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config).unwrap();
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! loop {
@@ -174,6 +178,20 @@
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+//!
+//!         Ok((push_id, quiche::h3::Event::CancelPush)) => {
+//!              // Peer cancelled a push, handle it.
+//!         },
+//!
+//!         Ok((stream_id, quiche::h3::Event::PriorityUpdate(priority))) => {
+//!             h3_conn.set_stream_priority(&mut conn, stream_id, priority)?;
+//!         },
+//!
+//!         Ok((_, quiche::h3::Event::WebTransportStream { .. })) => (),
+//!
+//!         Ok((_, quiche::h3::Event::WebTransportSessionClosed { .. })) => (),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -195,8 +213,9 @@
 //!
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let to = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::connect(None, &scid, to, &mut config).unwrap();
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
 //! # let h3_config = quiche::h3::Config::new()?;
 //! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
 //! loop {
@@ -234,6 +253,20 @@
 //!              // Peer signalled it is going away, handle it.
 //!         },
 //!
+//!         Ok((stream_id, quiche::h3::Event::PushPromise { push_id, list })) => {
+//!             // Server promised to push a response, handle it.
+//!         },
+//!
+//!         Ok((push_id, quiche::h3::Event::CancelPush)) => {
+//!              // Peer cancelled a push, handle it.
+//!         },
+//!
+//!         Ok((_, quiche::h3::Event::PriorityUpdate(_))) => (),
+//!
+//!         Ok((_, quiche::h3::Event::WebTransportStream { .. })) => (),
+//!
+//!         Ok((_, quiche::h3::Event::WebTransportSessionClosed { .. })) => (),
+//!
 //!         Err(quiche::h3::Error::Done) => {
 //!             // Done reading.
 //!             break;
@@ -255,6 +288,134 @@
 //! repeatedly will generate an [`Event`] for each of these. The application may
 //! use these event to do additional HTTP semantic validation.
 //!
+//! ## Server push
+//!
+//! A server can only push responses once the client allows it to, by calling
+//! the connection's [`send_max_push_id()`] method.
+//!
+//! The server then promises a response on a request stream with
+//! [`send_push_promise()`], and sends it on a newly opened push stream with
+//! [`send_push_response()`] and [`send_body()`]:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config).unwrap();
+//! # let h3_config = quiche::h3::Config::new()?;
+//! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//! # let stream_id = 0;
+//! let req = vec![
+//!     quiche::h3::Header::new(b":method", b"GET"),
+//!     quiche::h3::Header::new(b":scheme", b"https"),
+//!     quiche::h3::Header::new(b":authority", b"quic.tech"),
+//!     quiche::h3::Header::new(b":path", b"/style.css"),
+//! ];
+//!
+//! let push_id = h3_conn.send_push_promise(&mut conn, stream_id, &req)?;
+//!
+//! let resp = vec![
+//!     quiche::h3::Header::new(b":status", b"200"),
+//!     quiche::h3::Header::new(b"server", b"quiche"),
+//! ];
+//!
+//! let push_stream_id =
+//!     h3_conn.send_push_response(&mut conn, push_id, &resp, false)?;
+//! h3_conn.send_body(&mut conn, push_stream_id, b"body {}", true)?;
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
+//! The client is notified of promises with the [`PushPromise`] event, and
+//! receives pushed responses like any other response, on the push stream.
+//! Either side can cancel a push using [`cancel_push()`].
+//!
+//! ## Prioritization
+//!
+//! A client can signal the priority of the response to a request, using the
+//! [`send_request_with_priority()`] method, and later change it using
+//! [`send_priority_update_for_request()`]:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
+//! # let h3_config = quiche::h3::Config::new()?;
+//! # let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//! let req = vec![
+//!     quiche::h3::Header::new(b":method", b"GET"),
+//!     quiche::h3::Header::new(b":scheme", b"https"),
+//!     quiche::h3::Header::new(b":authority", b"quic.tech"),
+//!     quiche::h3::Header::new(b":path", b"/"),
+//! ];
+//!
+//! let priority = quiche::h3::Priority::new(5, true);
+//! let stream_id =
+//!     h3_conn.send_request_with_priority(&mut conn, &req, priority, true)?;
+//!
+//! let priority = quiche::h3::Priority::new(1, false);
+//! h3_conn.send_priority_update_for_request(&mut conn, stream_id, priority)?;
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
+//! The server uses the priority signaled by the client when calling
+//! [`send_response()`]. Updates received afterwards are reported with the
+//! [`PriorityUpdate`] event, and can be applied using
+//! [`set_stream_priority()`].
+//!
+//! ## WebTransport
+//!
+//! Endpoints that enable WebTransport using the configuration's
+//! [`enable_webtransport()`] method can establish WebTransport sessions once
+//! the peer's SETTINGS have been received, and both endpoints enabled HTTP/3
+//! DATAGRAMs. A client creates a session with an extended CONNECT request,
+//! using [`send_webtransport_connect()`]:
+//!
+//! ```no_run
+//! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::connect(None, &scid, local, peer, &mut config).unwrap();
+//! let mut h3_config = quiche::h3::Config::new()?;
+//! h3_config.enable_webtransport(true);
+//!
+//! let mut h3_conn = quiche::h3::Connection::with_transport(&mut conn, &h3_config)?;
+//!
+//! let req = vec![
+//!     quiche::h3::Header::new(b":method", b"CONNECT"),
+//!     quiche::h3::Header::new(b":protocol", b"webtransport"),
+//!     quiche::h3::Header::new(b":scheme", b"https"),
+//!     quiche::h3::Header::new(b":authority", b"quic.tech"),
+//!     quiche::h3::Header::new(b":path", b"/wt"),
+//! ];
+//!
+//! let session_id = h3_conn.send_webtransport_connect(&mut conn, &req)?;
+//!
+//! let stream_id = h3_conn.open_webtransport_uni_stream(&mut conn, session_id)?;
+//! conn.stream_send(stream_id, b"hello", true)?;
+//! # Ok::<(), quiche::h3::Error>(())
+//! ```
+//!
+//! The server receives the request as a [`Headers`] event, and accepts the
+//! session by sending a 2xx response without finishing the stream.
+//!
+//! Either endpoint can then open streams associated with the session with
+//! [`open_webtransport_uni_stream()`] and [`open_webtransport_bidi_stream()`],
+//! and read and write their data directly on the QUIC connection. Streams
+//! opened by the peer are reported with the [`WebTransportStream`] event.
+//! Streams received before their session is established are buffered, up to
+//! a limit, and reported once a 2xx response is sent or received. They are
+//! rejected if the session is refused or terminated instead.
+//! Datagrams are sent and received with [`send_dgram()`] and [`recv_dgram()`],
+//! using the session ID divided by 4 as flow ID.
+//!
+//! A session is closed with [`close_webtransport_session()`], which the peer
+//! is notified of with the [`WebTransportSessionClosed`] event. Both endpoints
+//! then reset the streams associated with the session.
+//!
 //! ## HTTP/3 protocol errors
 //!
 //! Quiche is responsible for managing the HTTP/3 connection, ensuring it is in
@@ -276,6 +437,32 @@
 //! [`send_request()`]: struct.Connection.html#method.send_response
 //! [`send_response()`]: struct.Connection.html#method.send_response
 //! [`send_body()`]: struct.Connection.html#method.send_body
+//! [`send_max_push_id()`]: struct.Connection.html#method.send_max_push_id
+//! [`send_push_promise()`]: struct.Connection.html#method.send_push_promise
+//! [`send_push_response()`]: struct.Connection.html#method.send_push_response
+//! [`cancel_push()`]: struct.Connection.html#method.cancel_push
+//! [`PushPromise`]: enum.Event.html#variant.PushPromise
+//! [`send_request_with_priority()`]:
+//! struct.Connection.html#method.send_request_with_priority
+//! [`send_priority_update_for_request()`]:
+//! struct.Connection.html#method.send_priority_update_for_request
+//! [`set_stream_priority()`]: struct.Connection.html#method.set_stream_priority
+//! [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+//! [`enable_webtransport()`]: struct.Config.html#method.enable_webtransport
+//! [`send_webtransport_connect()`]:
+//! struct.Connection.html#method.send_webtransport_connect
+//! [`Headers`]: enum.Event.html#variant.Headers
+//! [`open_webtransport_uni_stream()`]:
+//! struct.Connection.html#method.open_webtransport_uni_stream
+//! [`open_webtransport_bidi_stream()`]:
+//! struct.Connection.html#method.open_webtransport_bidi_stream
+//! [`WebTransportStream`]: enum.Event.html#variant.WebTransportStream
+//! [`send_dgram()`]: struct.Connection.html#method.send_dgram
+//! [`recv_dgram()`]: struct.Connection.html#method.recv_dgram
+//! [`close_webtransport_session()`]:
+//! struct.Connection.html#method.close_webtransport_session
+//! [`WebTransportSessionClosed`]:
+//! enum.Event.html#variant.WebTransportSessionClosed
 
 use std::collections::HashMap;
 use std::collections::VecDeque;
@@ -294,6 +481,26 @@ pub const APPLICATION_PROTOCOL: &[u8] = b"\x02h3\x05h3-29\x05h3-28\x05h3-27";
 // The offset used when converting HTTP/3 urgency to quiche urgency.
 const PRIORITY_URGENCY_OFFSET: u8 = 124;
 
+// The maximum number of PRIORITY_UPDATE frames remembered for requests that
+// weren't received yet.
+const MAX_PENDING_PRIORITY_UPDATES: usize = 16;
+
+// The error code used to reject buffered WebTransport streams.
+const WEBTRANSPORT_BUFFERED_STREAM_REJECTED: u64 = 0x3994_bd84;
+
+// The error code used to reset the streams of terminated WebTransport sessions.
+const WEBTRANSPORT_SESSION_GONE: u64 = 0x170d_7b68;
+
+// The maximum number of WebTransport streams buffered while waiting for their
+// session to be established.
+const MAX_BUFFERED_WEBTRANSPORT_STREAMS: usize = 16;
+
+// The maximum length of the reason of a CLOSE_WEBTRANSPORT_SESSION capsule.
+const MAX_WEBTRANSPORT_CLOSE_REASON_LEN: usize = 1024;
+
+// The maximum amount of incomplete capsule data buffered per session.
+const MAX_CAPSULE_BUF_SIZE: usize = 65536;
+
 /// A specialized [`Result`] type for quiche HTTP/3 operations.
 ///
 /// This type is used throughout quiche's HTTP/3 public API for any operation
@@ -342,6 +549,14 @@ pub enum Error {
     /// QPACK Header block decompression failure.
     QpackDecompressionFailed,
 
+    /// Error interpreting the instructions received on the QPACK encoder
+    /// stream.
+    QpackEncoderStreamError,
+
+    /// Error interpreting the instructions received on the QPACK decoder
+    /// stream.
+    QpackDecoderStreamError,
+
     /// Error originated from the transport layer.
     TransportError(crate::Error),
 
@@ -387,6 +602,8 @@ impl Error {
             Error::IdError => 0x108,
             Error::MissingSettings => 0x10A,
             Error::QpackDecompressionFailed => 0x200,
+            Error::QpackEncoderStreamError => 0x201,
+            Error::QpackDecoderStreamError => 0x202,
             Error::BufferTooShort => 0x999,
             Error::TransportError { .. } => 0xFF,
             Error::StreamBlocked => 0xFF,
@@ -423,6 +640,8 @@ impl Error {
             Error::MessageError => -18,
             Error::ConnectError => -19,
             Error::VersionFallback => -20,
+            Error::QpackEncoderStreamError => -21,
+            Error::QpackDecoderStreamError => -22,
         }
     }
 }
@@ -460,6 +679,9 @@ pub struct Config {
     max_header_list_size: Option<u64>,
     qpack_max_table_capacity: Option<u64>,
     qpack_blocked_streams: Option<u64>,
+    qpack_indexing_policy: fn(&[u8], &[u8]) -> qpack::Indexing,
+    extended_connect: bool,
+    webtransport: bool,
 }
 
 impl Config {
@@ -469,6 +691,9 @@ impl Config {
             max_header_list_size: None,
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
+            qpack_indexing_policy: qpack::default_indexing_policy,
+            extended_connect: false,
+            webtransport: false,
         })
     }
 
@@ -498,6 +723,58 @@ impl Config {
     pub fn set_qpack_blocked_streams(&mut self, v: u64) {
         self.qpack_blocked_streams = Some(v);
     }
+
+    /// Sets the `SETTINGS_ENABLE_CONNECT_PROTOCOL` setting, allowing the peer
+    /// to send extended CONNECT requests.
+    ///
+    /// The default value is `false`.
+    pub fn enable_extended_connect(&mut self, v: bool) {
+        self.extended_connect = v;
+    }
+
+    /// Sets the `SETTINGS_ENABLE_WEBTRANSPORT` setting, allowing the peer to
+    /// establish WebTransport sessions.
+    ///
+    /// This also enables extended CONNECT. WebTransport additionally requires
+    /// DATAGRAM support to be enabled on the QUIC connection.
+    ///
+    /// The default value is `false`.
+    pub fn enable_webtransport(&mut self, v: bool) {
+        self.webtransport = v;
+    }
+
+    /// Sets the policy deciding which headers are inserted in the QPACK
+    /// dynamic table, when the peer allows the encoder to use it.
+    ///
+    /// The function is called with the name and value of each header that
+    /// doesn't fully match a static table entry.
+    ///
+    /// The default is [`qpack::default_indexing_policy()`], which never
+    /// indexes credentials.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// use quiche::h3::qpack::Indexing;
+    ///
+    /// let mut config = quiche::h3::Config::new()?;
+    ///
+    /// config.set_qpack_indexing_policy(|name, value| {
+    ///     if name == b"x-secret" {
+    ///         return Indexing::NeverIndex;
+    ///     }
+    ///
+    ///     quiche::h3::qpack::default_indexing_policy(name, value)
+    /// });
+    /// # Ok::<(), quiche::h3::Error>(())
+    /// ```
+    ///
+    /// [`qpack::default_indexing_policy()`]: qpack/fn.default_indexing_policy.html
+    pub fn set_qpack_indexing_policy(
+        &mut self, policy: fn(&[u8], &[u8]) -> qpack::Indexing,
+    ) {
+        self.qpack_indexing_policy = policy;
+    }
 }
 
 /// A trait for types with associated string name and value.
@@ -553,6 +830,104 @@ impl<'a> NameValue for HeaderRef<'a> {
     }
 }
 
+/// An HTTP/3 priority, as defined by the Extensible Priority Scheme for HTTP.
+///
+/// Priorities are signaled using the `priority` request header, or the value
+/// of PRIORITY_UPDATE frames, and are made of an urgency between 0 (highest)
+/// and 7 (lowest), and an incremental flag.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Priority {
+    urgency: u8,
+    incremental: bool,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority {
+            urgency: 3,
+            incremental: false,
+        }
+    }
+}
+
+impl Priority {
+    /// Creates a new priority.
+    ///
+    /// Urgency values larger than 7 are clamped to 7.
+    pub fn new(urgency: u8, incremental: bool) -> Self {
+        Priority {
+            urgency: std::cmp::min(urgency, 7),
+            incremental,
+        }
+    }
+
+    /// Parses a priority from the value of a `priority` header or a
+    /// PRIORITY_UPDATE frame.
+    ///
+    /// Parameters that are missing or unknown are ignored, in which case the
+    /// default values are used.
+    pub fn parse(value: &[u8]) -> Self {
+        let mut priority = Priority::default();
+
+        let value = match std::str::from_utf8(value) {
+            Ok(v) => v,
+
+            Err(_) => return priority,
+        };
+
+        for param in value.split(',') {
+            let param = param.trim();
+
+            match param {
+                "i" | "i=?1" => priority.incremental = true,
+
+                "i=?0" => priority.incremental = false,
+
+                _ if param.starts_with("u=") => {
+                    // u is an sh-integer (an i64) but it has a constrained
+                    // range of 0-7. So detect anything outside that range and
+                    // clamp it to the lowest urgency in order to avoid it
+                    // interfering with valid items.
+                    //
+                    // TODO: this also detects when u is not an sh-integer and
+                    // clamps it in the same way. A real structured header
+                    // parser would actually fail to parse.
+                    let u = param[2..].parse::<i64>().unwrap_or(7);
+
+                    priority.urgency =
+                        if (0..=7).contains(&u) { u as u8 } else { 7 };
+                },
+
+                _ => (),
+            }
+        }
+
+        priority
+    }
+
+    /// Returns the urgency.
+    pub fn urgency(&self) -> u8 {
+        self.urgency
+    }
+
+    /// Returns whether the response can be processed incrementally.
+    pub fn incremental(&self) -> bool {
+        self.incremental
+    }
+
+    /// Serializes the priority as a `priority` header or PRIORITY_UPDATE
+    /// frame value.
+    fn to_field_value(self) -> Vec<u8> {
+        let mut value = format!("u={}", self.urgency);
+
+        if self.incremental {
+            value.push_str(", i");
+        }
+
+        value.into_bytes()
+    }
+}
+
 /// An HTTP/3 connection event.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Event {
@@ -602,6 +977,66 @@ pub enum Event {
 
     /// GOAWAY was received.
     GoAway,
+
+    /// PUSH_PROMISE was received.
+    ///
+    /// This indicates that the server intends to push a response to the
+    /// promised request on a push stream. Once the push stream is received,
+    /// its response is reported using the [`Headers`], [`Data`] and
+    /// [`Finished`] events, and its push ID can be retrieved with the
+    /// [`push_id()`] method.
+    ///
+    /// [`Headers`]: enum.Event.html#variant.Headers
+    /// [`Data`]: enum.Event.html#variant.Data
+    /// [`Finished`]: enum.Event.html#variant.Finished
+    /// [`push_id()`]: struct.Connection.html#method.push_id
+    PushPromise {
+        /// The push ID of the promised response.
+        push_id: u64,
+
+        /// The list of header fields of the promised request.
+        list: Vec<Header>,
+    },
+
+    /// CANCEL_PUSH was received.
+    CancelPush,
+
+    /// PRIORITY_UPDATE was received.
+    ///
+    /// This indicates that the client changed the priority of the request or
+    /// pushed response carried by the stream the event is returned with. The
+    /// new priority can be applied using the [`set_stream_priority()`]
+    /// method.
+    ///
+    /// Updates for requests that weren't received yet are not reported, and
+    /// are instead used as the request's priority once it is received.
+    ///
+    /// [`set_stream_priority()`]: struct.Connection.html#method.set_stream_priority
+    PriorityUpdate(Priority),
+
+    /// A WebTransport stream was received.
+    ///
+    /// This is returned with the ID of the new stream, whose data can then be
+    /// read directly using the transport's [`stream_recv()`] method, once the
+    /// session is established.
+    ///
+    /// [`stream_recv()`]: ../struct.Connection.html#method.stream_recv
+    WebTransportStream {
+        /// The ID of the session the stream belongs to.
+        session_id: u64,
+    },
+
+    /// A WebTransport session was closed by the peer.
+    ///
+    /// This is returned with the session ID, when a CLOSE_WEBTRANSPORT_SESSION
+    /// capsule is received.
+    WebTransportSessionClosed {
+        /// The application error code sent by the peer.
+        error_code: u32,
+
+        /// The reason sent by the peer.
+        reason: Vec<u8>,
+    },
 }
 
 struct ConnectionSettings {
@@ -609,6 +1044,8 @@ struct ConnectionSettings {
     pub qpack_max_table_capacity: Option<u64>,
     pub qpack_blocked_streams: Option<u64>,
     pub h3_datagram: Option<u64>,
+    pub connect_protocol_enabled: Option<u64>,
+    pub webtransport_enabled: Option<u64>,
 }
 
 struct QpackStreams {
@@ -616,6 +1053,29 @@ struct QpackStreams {
     pub decoder_stream_id: Option<u64>,
 }
 
+/// The state of a server push.
+#[derive(Default)]
+struct Push {
+    /// The push stream carrying the pushed response, once opened.
+    stream_id: Option<u64>,
+
+    /// Whether the push was cancelled by either endpoint.
+    cancelled: bool,
+
+    /// The priority signaled by the client for the pushed response, if any.
+    priority: Option<Priority>,
+}
+
+/// The state of a WebTransport session.
+#[derive(Default)]
+struct WebTransportSession {
+    /// Whether a 2xx response to the CONNECT request was sent or received.
+    established: bool,
+
+    /// Capsule data received on the CONNECT stream, not yet processed.
+    capsule_buf: Vec<u8>,
+}
+
 /// An HTTP/3 connection.
 pub struct Connection {
     is_server: bool,
@@ -634,11 +1094,34 @@ pub struct Connection {
     qpack_encoder: qpack::Encoder,
     qpack_decoder: qpack::Decoder,
 
-    #[allow(dead_code)]
     local_qpack_streams: QpackStreams,
     peer_qpack_streams: QpackStreams,
 
-    max_push_id: u64,
+    /// Header blocks that can't be decoded until more QPACK encoder
+    /// instructions are received, by stream ID, along with the push ID of
+    /// the PUSH_PROMISE frame that carried them, if any.
+    blocked_streams: HashMap<u64, (Vec<u8>, Option<u64>)>,
+
+    local_max_push_id: Option<u64>,
+    peer_max_push_id: Option<u64>,
+
+    next_push_id: u64,
+
+    pushes: HashMap<u64, Push>,
+
+    /// Priorities signaled with PRIORITY_UPDATE frames for requests that
+    /// weren't received yet, by stream ID.
+    pending_priorities: HashMap<u64, Priority>,
+
+    webtransport_sessions: HashMap<u64, WebTransportSession>,
+
+    /// WebTransport streams received before their session was established,
+    /// along with the session ID.
+    webtransport_buffered_streams: HashMap<u64, u64>,
+
+    /// Buffered WebTransport streams whose session was established, not yet
+    /// reported to the application.
+    webtransport_ready_streams: VecDeque<(u64, u64)>,
 
     finished_streams: VecDeque<u64>,
 
@@ -655,13 +1138,28 @@ impl Connection {
     fn new(
         config: &Config, is_server: bool, enable_dgram: bool,
     ) -> Result<Connection> {
+        let initial_bidi_stream_id = if is_server { 0x1 } else { 0x0 };
         let initial_uni_stream_id = if is_server { 0x3 } else { 0x2 };
         let h3_datagram = if enable_dgram { Some(1) } else { None };
 
+        let connect_protocol_enabled =
+            if config.extended_connect || config.webtransport {
+                Some(1)
+            } else {
+                None
+            };
+
+        let webtransport_enabled =
+            if config.webtransport { Some(1) } else { None };
+
+        let mut qpack_encoder = qpack::Encoder::new();
+        qpack_encoder.set_indexing_policy(config.qpack_indexing_policy);
+
         Ok(Connection {
             is_server,
 
-            next_request_stream_id: 0,
+            // Servers only open bidirectional streams for WebTransport.
+            next_request_stream_id: initial_bidi_stream_id,
 
             next_uni_stream_id: initial_uni_stream_id,
 
@@ -672,6 +1170,8 @@ impl Connection {
                 qpack_max_table_capacity: config.qpack_max_table_capacity,
                 qpack_blocked_streams: config.qpack_blocked_streams,
                 h3_datagram,
+                connect_protocol_enabled,
+                webtransport_enabled,
             },
 
             peer_settings: ConnectionSettings {
@@ -679,13 +1179,17 @@ impl Connection {
                 qpack_max_table_capacity: None,
                 qpack_blocked_streams: None,
                 h3_datagram: None,
+                connect_protocol_enabled: None,
+                webtransport_enabled: None,
             },
 
             control_stream_id: None,
             peer_control_stream_id: None,
 
-            qpack_encoder: qpack::Encoder::new(),
-            qpack_decoder: qpack::Decoder::new(),
+            qpack_encoder,
+            qpack_decoder: qpack::Decoder::with_max_table_capacity(
+                config.qpack_max_table_capacity.unwrap_or(0),
+            ),
 
             local_qpack_streams: QpackStreams {
                 encoder_stream_id: None,
@@ -697,7 +1201,22 @@ impl Connection {
                 decoder_stream_id: None,
             },
 
-            max_push_id: 0,
+            blocked_streams: HashMap::new(),
+
+            local_max_push_id: None,
+            peer_max_push_id: None,
+
+            next_push_id: 0,
+
+            pushes: HashMap::new(),
+
+            pending_priorities: HashMap::new(),
+
+            webtransport_sessions: HashMap::new(),
+
+            webtransport_buffered_streams: HashMap::new(),
+
+            webtransport_ready_streams: VecDeque::new(),
 
             finished_streams: VecDeque::new(),
 
@@ -775,6 +1294,13 @@ impl Connection {
             return Err(Error::FrameUnexpected);
         }
 
+        // Extended CONNECT can only be used if the peer allows it.
+        if headers.iter().any(|h| h.name() == b":protocol") &&
+            !self.extended_connect_enabled_by_peer()
+        {
+            return Err(Error::MessageError);
+        }
+
         let stream_id = self.next_request_stream_id;
 
         self.streams
@@ -802,7 +1328,39 @@ impl Connection {
         Ok(stream_id)
     }
 
-    /// Sends an HTTP/3 response on the specified stream with default priority.
+    /// Sends an HTTP/3 request with the specified priority.
+    ///
+    /// This works like [`send_request()`], but also signals the priority of
+    /// the response to the server using the `priority` header, which replaces
+    /// any such header in `headers`. The header is omitted when the priority
+    /// is the default one.
+    ///
+    /// [`send_request()`]: struct.Connection.html#method.send_request
+    pub fn send_request_with_priority<T: NameValue>(
+        &mut self, conn: &mut super::Connection, headers: &[T],
+        priority: Priority, fin: bool,
+    ) -> Result<u64> {
+        let value = priority.to_field_value();
+
+        let mut req: Vec<HeaderRef> = headers
+            .iter()
+            .filter(|h| h.name() != b"priority")
+            .map(|h| HeaderRef::new(h.name(), h.value()))
+            .collect();
+
+        if priority != Priority::default() {
+            req.push(HeaderRef::new(b"priority", &value));
+        }
+
+        self.send_request(conn, &req, fin)
+    }
+
+    /// Sends an HTTP/3 response on the specified stream with the priority
+    /// signaled by the client.
+    ///
+    /// The priority is taken from the latest PRIORITY_UPDATE frame received
+    /// for the stream, or from the request's `priority` header. The default
+    /// priority is used if the client didn't signal any.
     ///
     /// This method sends the provided `headers` without a body. To include a
     /// body, set `fin` as `false` and subsequently call [`send_body()`] with
@@ -819,13 +1377,13 @@ impl Connection {
         &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
         fin: bool,
     ) -> Result<()> {
-        let priority = "u=3";
-
-        self.send_response_with_priority(
-            conn, stream_id, headers, priority, fin,
-        )?;
+        let priority = self
+            .streams
+            .get(&stream_id)
+            .and_then(|s| s.priority())
+            .unwrap_or_default();
 
-        Ok(())
+        self.send_prioritized_response(conn, stream_id, headers, priority, fin)
     }
 
     /// Sends an HTTP/3 response on the specified stream with specified
@@ -840,55 +1398,53 @@ impl Connection {
     pub fn send_response_with_priority<T: NameValue>(
         &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
         priority: &str, fin: bool,
+    ) -> Result<()> {
+        let priority = Priority::parse(priority.as_bytes());
+
+        self.send_prioritized_response(conn, stream_id, headers, priority, fin)
+    }
+
+    /// Applies the specified priority to the stream.
+    ///
+    /// This can be used by servers to apply the priority carried by a
+    /// [`PriorityUpdate`] event to a response that is already being sent.
+    /// Nothing is done for streams that are not known.
+    ///
+    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    pub fn set_stream_priority(
+        &self, conn: &mut super::Connection, stream_id: u64, priority: Priority,
     ) -> Result<()> {
         if !self.streams.contains_key(&stream_id) {
-            return Err(Error::FrameUnexpected);
+            return Ok(());
         }
 
-        let mut urgency = 3u8.saturating_add(PRIORITY_URGENCY_OFFSET);
-        let mut incremental = false;
+        // The HTTP/3 urgency needs to be shifted into the quiche urgency range.
+        let urgency = priority.urgency().saturating_add(PRIORITY_URGENCY_OFFSET);
 
-        for param in priority.split(',') {
-            if param.trim() == "i" {
-                incremental = true;
-                continue;
-            }
+        conn.stream_priority(stream_id, urgency, priority.incremental())?;
 
-            if param.trim().starts_with("u=") {
-                // u is an sh-integer (an i64) but it has a constrained range of
-                // 0-7. So detect anything outside that range and clamp it to
-                // the lowest urgency in order to avoid it interfering with
-                // valid items.
-                //
-                // TODO: this also detects when u is not an sh-integer and
-                // clamps it in the same way. A real structured header parser
-                // would actually fail to parse.
-                let mut u = param
-                    .rsplit('=')
-                    .next()
-                    .unwrap()
-                    .parse::<i64>()
-                    .unwrap_or(7);
-
-                if !(0..=7).contains(&u) {
-                    u = 7;
-                }
+        Ok(())
+    }
 
-                // The HTTP/3 urgency needs to be shifted into the quiche
-                // urgency range.
-                urgency = (u as u8).saturating_add(PRIORITY_URGENCY_OFFSET);
-            }
+    fn send_prioritized_response<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+        priority: Priority, fin: bool,
+    ) -> Result<()> {
+        if !self.streams.contains_key(&stream_id) {
+            return Err(Error::FrameUnexpected);
         }
 
-        conn.stream_priority(stream_id, urgency, incremental)?;
+        self.set_stream_priority(conn, stream_id, priority)?;
 
         self.send_headers(conn, stream_id, headers, fin)?;
 
+        self.process_webtransport_response(conn, stream_id, headers);
+
         Ok(())
     }
 
     fn encode_header_block<T: NameValue>(
-        &mut self, headers: &[T],
+        &mut self, stream_id: u64, headers: &[T],
     ) -> Result<Vec<u8>> {
         let headers_len = headers
             .iter()
@@ -897,7 +1453,7 @@ impl Connection {
         let mut header_block = vec![0; headers_len];
         let len = self
             .qpack_encoder
-            .encode(&headers, &mut header_block)
+            .encode_stream(stream_id, headers, &mut header_block)
             .map_err(|_| Error::InternalError)?;
 
         header_block.truncate(len);
@@ -929,15 +1485,22 @@ impl Connection {
             },
         };
 
-        let header_block = self.encode_header_block(headers)?;
+        let header_block = self.encode_header_block(stream_id, headers)?;
 
         let overhead = octets::varint_len(frame::HEADERS_FRAME_TYPE_ID) +
             octets::varint_len(header_block.len() as u64);
 
         if stream_cap < overhead + header_block.len() {
+            // The header block won't be sent, so it can't be acknowledged.
+            self.qpack_encoder.discard_last_section(stream_id);
+
             return Err(Error::StreamBlocked);
         }
 
+        // Entries inserted in the dynamic table need to reach the peer for the
+        // header block to be decoded.
+        self.send_qpack_instructions(conn)?;
+
         trace!(
             "{} tx frm HEADERS stream={} len={} fin={}",
             conn.trace_id(),
@@ -986,8 +1549,11 @@ impl Connection {
         let mut d = [42; 10];
         let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        // Validate that it is sane to send data on the stream.
-        if stream_id % 4 != 0 {
+        // Validate that it is sane to send data on the stream, which can be
+        // a request stream or, for servers, a push stream.
+        let is_push_stream = self.is_server && stream_id % 4 == 3;
+
+        if stream_id % 4 != 0 && !is_push_stream {
             return Err(Error::FrameUnexpected);
         }
 
@@ -1075,6 +1641,31 @@ impl Connection {
             conn.dgram_max_writable_len().is_some()
     }
 
+    /// Returns whether the peer enabled extended CONNECT support.
+    ///
+    /// Support is signalled by the peer's SETTINGS, so this method always
+    /// returns false until they have been processed using the [`poll()`]
+    /// method.
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    pub fn extended_connect_enabled_by_peer(&self) -> bool {
+        self.peer_settings.connect_protocol_enabled == Some(1)
+    }
+
+    /// Returns whether the peer enabled WebTransport support.
+    ///
+    /// This requires the peer to also support extended CONNECT and HTTP/3
+    /// DATAGRAM frames. Support is signalled by the peer's SETTINGS, so this
+    /// method always returns false until they have been processed using the
+    /// [`poll()`] method.
+    ///
+    /// [`poll()`]: struct.Connection.html#method.poll
+    pub fn webtransport_enabled_by_peer(&self, conn: &super::Connection) -> bool {
+        self.peer_settings.webtransport_enabled == Some(1) &&
+            self.extended_connect_enabled_by_peer() &&
+            self.dgram_enabled_by_peer(conn)
+    }
+
     /// Sends an HTTP/3 DATAGRAM with the specified flow ID.
     pub fn send_dgram(
         &mut self, conn: &mut super::Connection, flow_id: u64, buf: &[u8],
@@ -1218,7 +1809,7 @@ impl Connection {
         // While body is being received, the stream is marked as finished only
         // when all data is read by the application.
         if conn.stream_finished(stream_id) {
-            self.process_finished_stream(stream_id);
+            self.process_finished_stream(conn, stream_id);
         }
 
         if total == 0 {
@@ -1244,6 +1835,16 @@ impl Connection {
     /// The event [`Datagram`] returns a dummy value of `0`, this should be
     /// ignored by the application.
     ///
+    /// The event [`PushPromise`] returns the ID of the request stream the push
+    /// is associated with, while the event [`CancelPush`] returns the ID of the
+    /// cancelled push.
+    ///
+    /// The event [`PriorityUpdate`] returns the ID of the stream whose
+    /// priority was changed.
+    ///
+    /// The event [`WebTransportStream`] returns the ID of the new stream, while
+    /// the event [`WebTransportSessionClosed`] returns the ID of the session.
+    ///
     /// The event [`GoAway`] returns an ID that depends on the connection role.
     /// A client receives the largest processed stream ID. A server receives the
     /// the largest permitted push ID.
@@ -1258,6 +1859,12 @@ impl Connection {
     /// [`Finished`]: enum.Event.html#variant.Finished
     /// [`Datagram`]: enum.Event.html#variant.Datagram
     /// [`GoAway`]: enum.Event.html#variant.GoAWay
+    /// [`PushPromise`]: enum.Event.html#variant.PushPromise
+    /// [`CancelPush`]: enum.Event.html#variant.CancelPush
+    /// [`PriorityUpdate`]: enum.Event.html#variant.PriorityUpdate
+    /// [`WebTransportStream`]: enum.Event.html#variant.WebTransportStream
+    /// [`WebTransportSessionClosed`]:
+    /// enum.Event.html#variant.WebTransportSessionClosed
     /// [`recv_body()`]: struct.Connection.html#method.recv_body
     /// [`send_response()`]: struct.Connection.html#method.send_response
     /// [`send_body()`]: struct.Connection.html#method.send_body
@@ -1302,11 +1909,28 @@ impl Connection {
             };
         }
 
+        // Process streams that might have been unblocked by new QPACK encoder
+        // instructions.
+        match self.process_blocked_streams(conn) {
+            Ok(ev) => return Ok(ev),
+
+            Err(Error::Done) => (),
+
+            Err(e) => return Err(e),
+        };
+
         // Process finished streams list.
         if let Some(finished) = self.finished_streams.pop_front() {
             return Ok((finished, Event::Finished));
         }
 
+        // Report buffered WebTransport streams whose session was established.
+        if let Some((stream_id, session_id)) =
+            self.webtransport_ready_streams.pop_front()
+        {
+            return Ok((stream_id, Event::WebTransportStream { session_id }));
+        }
+
         // Process queued DATAGRAMs if the poll threshold allows it.
         match self.process_dgrams(conn) {
             Ok(v) => return Ok(v),
@@ -1327,14 +1951,19 @@ impl Connection {
 
                 // Return early if the stream was reset, to avoid returning
                 // a Finished event later as well.
-                Err(Error::TransportError(crate::Error::StreamReset(e))) =>
-                    return Ok((s, Event::Reset(e))),
+                Err(Error::TransportError(crate::Error::StreamReset(e))) => {
+                    // Resetting the CONNECT stream terminates the WebTransport
+                    // session.
+                    self.remove_webtransport_session(conn, s);
+
+                    return Ok((s, Event::Reset(e)));
+                },
 
                 Err(e) => return Err(e),
             };
 
             if conn.stream_finished(s) {
-                self.process_finished_stream(s);
+                self.process_finished_stream(conn, s);
             }
 
             // TODO: check if stream is completed so it can be freed
@@ -1357,8 +1986,10 @@ impl Connection {
     ///
     /// When quiche is used in the server role, the `id` parameter is the stream
     /// ID of the highest processed request. This can be any valid ID between 0
-    /// and 2^62-4. However, the ID cannot be increased. Failure to satisfy
-    /// these conditions will return an error.
+    /// and 2^62-4. When used in the client role, the `id` parameter is the
+    /// push ID from which pushes will not be accepted anymore. In both cases
+    /// the ID cannot be increased. Failure to satisfy these conditions will
+    /// return an error.
     ///
     /// This method does not close the QUIC connection. Applications are
     /// required to call [`close()`] themselves.
@@ -1367,15 +1998,6 @@ impl Connection {
     pub fn send_goaway(
         &mut self, conn: &mut super::Connection, id: u64,
     ) -> Result<()> {
-        let mut id = id;
-
-        // TODO: server push
-        //
-        // In the meantime always send 0 from client.
-        if !self.is_server {
-            id = 0;
-        }
-
         if self.is_server && id % 4 != 0 {
             return Err(Error::IdError);
         }
@@ -1386,23 +2008,8 @@ impl Connection {
             }
         }
 
-        if let Some(stream_id) = self.control_stream_id {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
-
-            let frame = frame::Frame::GoAway { id };
-
-            let wire_len = frame.to_bytes(&mut b)?;
-            let stream_cap = conn.stream_capacity(stream_id)?;
-
-            if stream_cap < wire_len {
-                return Err(Error::StreamBlocked);
-            }
-
-            trace!("{} tx frm {:?}", conn.trace_id(), frame);
-
-            let off = b.off();
-            conn.stream_send(stream_id, &d[..off], false)?;
+        if self.control_stream_id.is_some() {
+            self.send_control_frame(conn, frame::Frame::GoAway { id })?;
 
             self.local_goaway_id = Some(id);
         }
@@ -1410,1049 +2017,2678 @@ impl Connection {
         Ok(())
     }
 
-    fn open_uni_stream(
-        &mut self, conn: &mut super::Connection, ty: u64,
-    ) -> Result<u64> {
-        let stream_id = self.next_uni_stream_id;
-
-        let mut d = [0; 8];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+    /// Sends a PRIORITY_UPDATE frame to change the priority of a request.
+    ///
+    /// The `stream_id` parameter is the ID of a request stream that was
+    /// previously opened with [`send_request()`], otherwise the [`IdError`]
+    /// error is returned.
+    ///
+    /// This method can only be used in the client role, otherwise the
+    /// [`FrameUnexpected`] error is returned.
+    ///
+    /// [`send_request()`]: struct.Connection.html#method.send_request
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    pub fn send_priority_update_for_request(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        priority: Priority,
+    ) -> Result<()> {
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
 
-        match ty {
-            // Control and QPACK streams are the most important to schedule.
-            stream::HTTP3_CONTROL_STREAM_TYPE_ID |
-            stream::QPACK_ENCODER_STREAM_TYPE_ID |
-            stream::QPACK_DECODER_STREAM_TYPE_ID => {
-                conn.stream_priority(stream_id, 0, true)?;
-            },
+        if !crate::stream::is_bidi(stream_id) ||
+            !crate::stream::is_local(stream_id, false) ||
+            stream_id >= self.next_request_stream_id
+        {
+            return Err(Error::IdError);
+        }
 
-            // TODO: Server push
-            stream::HTTP3_PUSH_STREAM_TYPE_ID => (),
+        self.send_control_frame(conn, frame::Frame::PriorityUpdateRequest {
+            prioritized_element_id: stream_id,
+            priority_field_value: priority.to_field_value(),
+        })
+    }
 
-            // Anything else is a GREASE stream, so make it the least important.
-            _ => {
-                conn.stream_priority(stream_id, 255, true)?;
-            },
+    /// Sends a PRIORITY_UPDATE frame to change the priority of a pushed
+    /// response.
+    ///
+    /// The `push_id` parameter must not be larger than the limit previously
+    /// sent with [`send_max_push_id()`], otherwise the [`IdError`] error is
+    /// returned.
+    ///
+    /// This method can only be used in the client role, otherwise the
+    /// [`FrameUnexpected`] error is returned.
+    ///
+    /// [`send_max_push_id()`]: struct.Connection.html#method.send_max_push_id
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    pub fn send_priority_update_for_push(
+        &mut self, conn: &mut super::Connection, push_id: u64, priority: Priority,
+    ) -> Result<()> {
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
         }
 
-        conn.stream_send(stream_id, b.put_varint(ty)?, false)?;
-
-        // To avoid skipping stream IDs, we only calculate the next available
-        // stream ID when data has been successfully buffered.
-        self.next_uni_stream_id = self
-            .next_uni_stream_id
-            .checked_add(4)
-            .ok_or(Error::IdError)?;
+        if self.local_max_push_id.is_none_or(|max| push_id > max) {
+            return Err(Error::IdError);
+        }
 
-        Ok(stream_id)
+        self.send_control_frame(conn, frame::Frame::PriorityUpdatePush {
+            prioritized_element_id: push_id,
+            priority_field_value: priority.to_field_value(),
+        })
     }
 
-    fn open_qpack_encoder_stream(
-        &mut self, conn: &mut super::Connection,
+    /// Sends a MAX_PUSH_ID frame to allow the server to push responses.
+    ///
+    /// The `push_id` parameter is the largest push ID that the server is
+    /// allowed to use. No pushes are allowed until this method is called, and
+    /// the limit cannot be reduced afterwards, in which case the [`IdError`]
+    /// error is returned.
+    ///
+    /// This method can only be used in the client role, otherwise the
+    /// [`FrameUnexpected`] error is returned.
+    ///
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    pub fn send_max_push_id(
+        &mut self, conn: &mut super::Connection, push_id: u64,
     ) -> Result<()> {
-        self.local_qpack_streams.encoder_stream_id = Some(
-            self.open_uni_stream(conn, stream::QPACK_ENCODER_STREAM_TYPE_ID)?,
-        );
+        if self.is_server {
+            return Err(Error::FrameUnexpected);
+        }
 
-        Ok(())
-    }
+        if let Some(max_push_id) = self.local_max_push_id {
+            if push_id < max_push_id {
+                return Err(Error::IdError);
+            }
+        }
 
-    fn open_qpack_decoder_stream(
-        &mut self, conn: &mut super::Connection,
-    ) -> Result<()> {
-        self.local_qpack_streams.decoder_stream_id = Some(
-            self.open_uni_stream(conn, stream::QPACK_DECODER_STREAM_TYPE_ID)?,
-        );
+        self.send_control_frame(conn, frame::Frame::MaxPushId { push_id })?;
+
+        self.local_max_push_id = Some(push_id);
 
         Ok(())
     }
 
-    /// Send GREASE frames on the provided stream ID.
-    fn send_grease_frames(
-        &mut self, conn: &mut super::Connection, stream_id: u64,
-    ) -> Result<()> {
-        let mut d = [0; 8];
+    /// Sends a PUSH_PROMISE frame on the specified request stream.
+    ///
+    /// The provided `headers` are the ones of the request the server intends
+    /// to push a response to, which can then be sent using
+    /// [`send_push_response()`].
+    ///
+    /// On success the newly allocated push ID is returned.
+    ///
+    /// The [`IdError`] error is returned when the client didn't allow more
+    /// pushes using MAX_PUSH_ID, and the [`FrameUnexpected`] error when the
+    /// client sent a GOAWAY frame preventing new pushes, or when not used in
+    /// the server role on a request stream.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`send_push_response()`]: struct.Connection.html#method.send_push_response
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn send_push_promise<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+    ) -> Result<u64> {
+        if !self.is_server ||
+            !crate::stream::is_bidi(stream_id) ||
+            !self.streams.contains_key(&stream_id)
+        {
+            return Err(Error::FrameUnexpected);
+        }
 
-        let stream_cap = match conn.stream_capacity(stream_id) {
-            Ok(v) => v,
+        let push_id = self.next_push_id;
 
-            Err(e) => {
-                if conn.stream_finished(stream_id) {
-                    self.streams.remove(&stream_id);
-                }
+        match self.peer_max_push_id {
+            Some(max_push_id) if push_id <= max_push_id => (),
 
-                return Err(e.into());
-            },
-        };
+            _ => return Err(Error::IdError),
+        }
 
-        let grease_frame1 = grease_value();
-        let grease_frame2 = grease_value();
-        let grease_payload = b"GREASE is the word";
+        if let Some(goaway_id) = self.peer_goaway_id {
+            if push_id >= goaway_id {
+                return Err(Error::FrameUnexpected);
+            }
+        }
 
-        let overhead = octets::varint_len(grease_frame1) + // frame type
-            1 + // payload len
-            octets::varint_len(grease_frame2) + // frame type
-            1 + // payload len
-            grease_payload.len(); // payload
+        let stream_cap = conn.stream_capacity(stream_id)?;
 
-        // Don't send GREASE if there is not enough capacity for it. Greasing
-        // will _not_ be attempted again later on.
-        if stream_cap < overhead {
-            return Ok(());
-        }
+        let header_block = self.encode_header_block(stream_id, headers)?;
 
-        trace!("{} tx frm GREASE stream={}", conn.trace_id(), stream_id);
+        // Frame type, length and push ID, followed by the header block.
+        let mut d = vec![0; 24 + header_block.len()];
 
-        // Empty GREASE frame.
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(grease_frame1)?, false)?;
+        let frame = frame::Frame::PushPromise {
+            push_id,
+            header_block,
+        };
 
         let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(0)?, false)?;
 
-        // GREASE frame with payload.
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(grease_frame2)?, false)?;
+        let wire_len = frame.to_bytes(&mut b)?;
 
-        let mut b = octets::OctetsMut::with_slice(&mut d);
-        conn.stream_send(stream_id, b.put_varint(18)?, false)?;
+        if stream_cap < wire_len {
+            // The header block won't be sent, so it can't be acknowledged.
+            self.qpack_encoder.discard_last_section(stream_id);
 
-        conn.stream_send(stream_id, grease_payload, false)?;
+            return Err(Error::StreamBlocked);
+        }
 
-        Ok(())
+        self.send_qpack_instructions(conn)?;
+
+        trace!(
+            "{} tx frm PUSH_PROMISE stream={} push_id={}",
+            conn.trace_id(),
+            stream_id,
+            push_id
+        );
+
+        conn.stream_send(stream_id, &d[..wire_len], false)?;
+
+        self.pushes.insert(push_id, Push::default());
+
+        self.next_push_id += 1;
+
+        Ok(push_id)
     }
 
-    /// Opens a new unidirectional stream with a GREASE type and sends some
-    /// unframed payload.
-    fn open_grease_stream(&mut self, conn: &mut super::Connection) -> Result<()> {
-        match self.open_uni_stream(conn, grease_value()) {
-            Ok(stream_id) => {
-                trace!("{} open GREASE stream {}", conn.trace_id(), stream_id);
+    /// Sends a pushed response for a previously promised push ID.
+    ///
+    /// This opens a new push stream and sends the provided `headers` on it.
+    /// To include a body, set `fin` as `false` and subsequently call
+    /// [`send_body()`] with the returned stream ID.
+    ///
+    /// On success the push stream's ID is returned.
+    ///
+    /// The [`IdError`] error is returned when the push ID was not promised,
+    /// the [`FrameUnexpected`] error when a response was already sent for it,
+    /// and the [`RequestCancelled`] error when the push was cancelled.
+    ///
+    /// [`send_body()`]: struct.Connection.html#method.send_body
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`FrameUnexpected`]: enum.Error.html#variant.FrameUnexpected
+    /// [`RequestCancelled`]: enum.Error.html#variant.RequestCancelled
+    pub fn send_push_response<T: NameValue>(
+        &mut self, conn: &mut super::Connection, push_id: u64, headers: &[T],
+        fin: bool,
+    ) -> Result<u64> {
+        match self.pushes.get(&push_id) {
+            Some(push) if push.cancelled => return Err(Error::RequestCancelled),
 
-                conn.stream_send(stream_id, b"GREASE is the word", true)?;
-            },
+            Some(push) if push.stream_id.is_some() =>
+                return Err(Error::FrameUnexpected),
 
-            Err(Error::IdError) => {
-                trace!("{} GREASE stream blocked", conn.trace_id(),);
+            Some(_) => (),
 
-                return Ok(());
-            },
+            None => return Err(Error::IdError),
+        }
 
-            Err(e) => return Err(e),
-        };
+        let stream_id =
+            self.open_uni_stream(conn, stream::HTTP3_PUSH_STREAM_TYPE_ID)?;
 
-        Ok(())
-    }
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-    /// Sends SETTINGS frame based on HTTP/3 configuration.
-    fn send_settings(&mut self, conn: &mut super::Connection) -> Result<()> {
-        self.control_stream_id = Some(
-            self.open_uni_stream(conn, stream::HTTP3_CONTROL_STREAM_TYPE_ID)?,
+        conn.stream_send(stream_id, b.put_varint(push_id)?, false)?;
+
+        trace!(
+            "{} open push stream {} push_id={}",
+            conn.trace_id(),
+            stream_id,
+            push_id
         );
 
-        let grease = if conn.grease {
-            Some((grease_value(), grease_value()))
-        } else {
-            None
-        };
+        let mut stream = stream::Stream::new(stream_id, true);
+        stream.set_ty(stream::Type::Push)?;
+        stream.set_push_id(push_id)?;
 
-        let frame = frame::Frame::Settings {
-            max_header_list_size: self.local_settings.max_header_list_size,
-            qpack_max_table_capacity: self
-                .local_settings
-                .qpack_max_table_capacity,
-            qpack_blocked_streams: self.local_settings.qpack_blocked_streams,
-            h3_datagram: self.local_settings.h3_datagram,
-            grease,
-        };
+        self.streams.insert(stream_id, stream);
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        let mut priority = None;
 
-        frame.to_bytes(&mut b)?;
+        if let Some(push) = self.pushes.get_mut(&push_id) {
+            push.stream_id = Some(stream_id);
 
-        let off = b.off();
+            priority = push.priority;
+        }
 
-        if let Some(id) = self.control_stream_id {
-            conn.stream_send(id, &d[..off], false)?;
+        if let Some(priority) = priority {
+            self.set_stream_priority(conn, stream_id, priority)?;
         }
 
-        Ok(())
+        self.send_headers(conn, stream_id, headers, fin)?;
+
+        Ok(stream_id)
     }
 
-    fn process_control_stream(
-        &mut self, conn: &mut super::Connection, stream_id: u64,
-    ) -> Result<(u64, Event)> {
-        if conn.stream_finished(stream_id) {
-            conn.close(
-                true,
-                Error::ClosedCriticalStream.to_wire(),
-                b"Critical stream closed.",
-            )?;
+    /// Cancels a server push.
+    ///
+    /// When quiche is used in the client role, this tells the server that
+    /// the pushed response is not wanted, and stops reading the push stream
+    /// if it was already received.
+    ///
+    /// When used in the server role, this tells the client that the promised
+    /// response won't be sent, or resets the push stream if it was already
+    /// opened.
+    ///
+    /// The [`IdError`] error is returned when the push ID is not one that
+    /// could have been promised.
+    ///
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn cancel_push(
+        &mut self, conn: &mut super::Connection, push_id: u64,
+    ) -> Result<()> {
+        let valid = if self.is_server {
+            push_id < self.next_push_id
+        } else {
+            self.local_max_push_id.is_some_and(|max| push_id <= max)
+        };
 
-            return Err(Error::ClosedCriticalStream);
+        if !valid {
+            return Err(Error::IdError);
         }
 
-        match self.process_readable_stream(conn, stream_id, true) {
-            Ok(ev) => return Ok(ev),
+        let push = self.pushes.entry(push_id).or_default();
 
-            Err(Error::Done) => (),
+        if push.cancelled {
+            return Ok(());
+        }
 
-            Err(e) => return Err(e),
-        };
+        push.cancelled = true;
+
+        match (self.is_server, push.stream_id) {
+            // The push stream was already opened, so reset it instead of
+            // sending CANCEL_PUSH.
+            (true, Some(stream_id)) => {
+                // The stream might have been completed already.
+                conn.stream_shutdown(
+                    stream_id,
+                    crate::Shutdown::Write,
+                    Error::RequestCancelled.to_wire(),
+                )
+                .ok();
 
-        if conn.stream_finished(stream_id) {
-            conn.close(
-                true,
-                Error::ClosedCriticalStream.to_wire(),
-                b"Critical stream closed.",
-            )?;
+                self.streams.remove(&stream_id);
+            },
 
-            return Err(Error::ClosedCriticalStream);
+            (true, None) => {
+                self.send_control_frame(conn, frame::Frame::CancelPush {
+                    push_id,
+                })?;
+            },
+
+            (false, stream_id) => {
+                if let Some(stream_id) = stream_id {
+                    // The stream might have been completed already.
+                    conn.stream_shutdown(
+                        stream_id,
+                        crate::Shutdown::Read,
+                        Error::RequestCancelled.to_wire(),
+                    )
+                    .ok();
+                }
+
+                self.send_control_frame(conn, frame::Frame::CancelPush {
+                    push_id,
+                })?;
+            },
         }
 
-        Err(Error::Done)
+        Ok(())
     }
 
-    fn process_readable_stream(
-        &mut self, conn: &mut super::Connection, stream_id: u64, polling: bool,
-    ) -> Result<(u64, Event)> {
-        self.streams
-            .entry(stream_id)
-            .or_insert_with(|| stream::Stream::new(stream_id, false));
-
-        // We need to get a fresh reference to the stream for each
-        // iteration, to avoid borrowing `self` for the entire duration
-        // of the loop, because we'll need to borrow it again in the
-        // `State::FramePayload` case below.
-        while let Some(stream) = self.streams.get_mut(&stream_id) {
-            match stream.state() {
-                stream::State::StreamType => {
-                    stream.try_fill_buffer(conn)?;
+    /// Returns the push ID of the given push stream.
+    ///
+    /// This can be used by clients to match the response received on a push
+    /// stream with the corresponding [`PushPromise`] event. `None` is
+    /// returned if the stream is not a push stream, or if its push ID has not
+    /// been received yet.
+    ///
+    /// [`PushPromise`]: enum.Event.html#variant.PushPromise
+    pub fn push_id(&self, stream_id: u64) -> Option<u64> {
+        self.streams.get(&stream_id).and_then(|s| s.push_id())
+    }
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+    /// Sends an extended CONNECT request establishing a WebTransport session.
+    ///
+    /// The `headers` must contain the `:method` pseudo-header set to `CONNECT`
+    /// and the `:protocol` pseudo-header set to `webtransport`, otherwise the
+    /// [`MessageError`] error is returned. The same error is returned if the
+    /// peer didn't enable WebTransport.
+    ///
+    /// On success the ID of the request stream is returned, which is also the
+    /// session ID. The session is established once a 2xx response is
+    /// received.
+    ///
+    /// [`MessageError`]: enum.Error.html#variant.MessageError
+    pub fn send_webtransport_connect<T: NameValue>(
+        &mut self, conn: &mut super::Connection, headers: &[T],
+    ) -> Result<u64> {
+        let has_header = |name: &[u8], value: &[u8]| {
+            headers
+                .iter()
+                .any(|h| h.name() == name && h.value() == value)
+        };
 
-                        Err(_) => continue,
-                    };
+        if !has_header(b":method", b"CONNECT") ||
+            !has_header(b":protocol", b"webtransport")
+        {
+            return Err(Error::MessageError);
+        }
 
-                    let ty = stream::Type::deserialize(varint)?;
+        if !self.webtransport_enabled_by_peer(conn) {
+            return Err(Error::MessageError);
+        }
 
-                    if let Err(e) = stream.set_ty(ty) {
-                        conn.close(true, e.to_wire(), b"")?;
-                        return Err(e);
-                    }
+        let session_id = self.send_request(conn, headers, false)?;
 
-                    match &ty {
-                        stream::Type::Control => {
-                            // Only one control stream allowed.
-                            if self.peer_control_stream_id.is_some() {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Received multiple control streams",
-                                )?;
+        self.webtransport_sessions
+            .insert(session_id, WebTransportSession::default());
 
-                                return Err(Error::StreamCreationError);
-                            }
+        Ok(session_id)
+    }
 
-                            trace!(
-                                "{} open peer's control stream {}",
-                                conn.trace_id(),
-                                stream_id
-                            );
+    /// Opens a unidirectional WebTransport stream for the given session.
+    ///
+    /// On success the stream ID is returned, and data can be sent on the
+    /// stream directly using the transport's [`stream_send()`] method.
+    ///
+    /// The [`IdError`] error is returned if the session is not known, or if
+    /// it wasn't accepted with a 2xx response yet when used by a server.
+    ///
+    /// [`stream_send()`]: ../struct.Connection.html#method.stream_send
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn open_webtransport_uni_stream(
+        &mut self, conn: &mut super::Connection, session_id: u64,
+    ) -> Result<u64> {
+        if !self.webtransport_session_usable(session_id) {
+            return Err(Error::IdError);
+        }
 
-                            self.peer_control_stream_id = Some(stream_id);
-                        },
+        let stream_id =
+            self.open_uni_stream(conn, stream::WEBTRANSPORT_UNI_STREAM_TYPE_ID)?;
 
-                        stream::Type::Push => {
-                            // Only clients can receive push stream.
-                            if self.is_server {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Server received push stream.",
-                                )?;
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                                return Err(Error::StreamCreationError);
-                            }
-                        },
+        conn.stream_send(stream_id, b.put_varint(session_id)?, false)?;
 
-                        stream::Type::QpackEncoder => {
-                            // Only one qpack encoder stream allowed.
-                            if self.peer_qpack_streams.encoder_stream_id.is_some()
-                            {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Received multiple QPACK encoder streams",
-                                )?;
+        // Track the stream so it can be reset when the session terminates.
+        let mut stream = stream::Stream::new(stream_id, true);
+        stream.initialize_webtransport(session_id);
 
-                                return Err(Error::StreamCreationError);
-                            }
+        self.streams.insert(stream_id, stream);
 
-                            self.peer_qpack_streams.encoder_stream_id =
-                                Some(stream_id);
-                        },
+        Ok(stream_id)
+    }
 
-                        stream::Type::QpackDecoder => {
-                            // Only one qpack decoder allowed.
-                            if self.peer_qpack_streams.decoder_stream_id.is_some()
-                            {
-                                conn.close(
-                                    true,
-                                    Error::StreamCreationError.to_wire(),
-                                    b"Received multiple QPACK decoder streams",
-                                )?;
+    /// Opens a bidirectional WebTransport stream for the given session.
+    ///
+    /// On success the stream ID is returned, and data can be sent and
+    /// received on the stream directly using the transport's
+    /// [`stream_send()`] and [`stream_recv()`] methods.
+    ///
+    /// The [`IdError`] error is returned if the session is not known, or if
+    /// it wasn't accepted with a 2xx response yet when used by a server.
+    ///
+    /// [`stream_send()`]: ../struct.Connection.html#method.stream_send
+    /// [`stream_recv()`]: ../struct.Connection.html#method.stream_recv
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    pub fn open_webtransport_bidi_stream(
+        &mut self, conn: &mut super::Connection, session_id: u64,
+    ) -> Result<u64> {
+        if !self.webtransport_session_usable(session_id) {
+            return Err(Error::IdError);
+        }
 
-                                return Err(Error::StreamCreationError);
-                            }
+        let stream_id = self.next_request_stream_id;
 
-                            self.peer_qpack_streams.decoder_stream_id =
-                                Some(stream_id);
-                        },
+        let mut d = [0; 16];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                        stream::Type::Unknown => {
-                            // Unknown stream types are ignored.
-                            // TODO: we MAY send STOP_SENDING
-                        },
+        b.put_varint(frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID)?;
+        b.put_varint(session_id)?;
 
-                        stream::Type::Request => unreachable!(),
-                    }
-                },
+        let off = b.off();
 
-                stream::State::PushId => {
-                    stream.try_fill_buffer(conn)?;
+        conn.stream_send(stream_id, &d[..off], false)?;
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+        let mut stream = stream::Stream::new(stream_id, true);
+        stream.initialize_webtransport(session_id);
 
-                        Err(_) => continue,
-                    };
+        self.streams.insert(stream_id, stream);
 
-                    if let Err(e) = stream.set_push_id(varint) {
-                        conn.close(true, e.to_wire(), b"")?;
-                        return Err(e);
-                    }
-                },
+        // To avoid skipping stream IDs, we only calculate the next available
+        // stream ID when data has been successfully buffered.
+        self.next_request_stream_id = self
+            .next_request_stream_id
+            .checked_add(4)
+            .ok_or(Error::IdError)?;
 
-                stream::State::FrameType => {
-                    stream.try_fill_buffer(conn)?;
+        Ok(stream_id)
+    }
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+    /// Closes a WebTransport session.
+    ///
+    /// This sends a CLOSE_WEBTRANSPORT_SESSION capsule with the given error
+    /// code and reason, and finishes the session's CONNECT stream.
+    ///
+    /// The [`IdError`] error is returned if the session is not known, or if
+    /// it wasn't accepted with a 2xx response yet when used by a server. The
+    /// [`MessageError`] error is returned if the reason is longer than 1024
+    /// bytes.
+    ///
+    /// The [`StreamBlocked`] error is returned when the underlying QUIC stream
+    /// doesn't have enough capacity for the operation to complete. When this
+    /// happens the application should retry the operation once the stream is
+    /// reported as writable again.
+    ///
+    /// [`IdError`]: enum.Error.html#variant.IdError
+    /// [`MessageError`]: enum.Error.html#variant.MessageError
+    /// [`StreamBlocked`]: enum.Error.html#variant.StreamBlocked
+    pub fn close_webtransport_session(
+        &mut self, conn: &mut super::Connection, session_id: u64,
+        error_code: u32, reason: &[u8],
+    ) -> Result<()> {
+        if !self.webtransport_session_usable(session_id) {
+            return Err(Error::IdError);
+        }
 
-                        Err(_) => continue,
-                    };
+        if reason.len() > MAX_WEBTRANSPORT_CLOSE_REASON_LEN {
+            return Err(Error::MessageError);
+        }
 
-                    match stream.set_frame_type(varint) {
-                        Err(Error::FrameUnexpected) => {
-                            let msg = format!("Unexpected frame type {}", varint);
+        let capsule_len = 4 + reason.len();
 
-                            conn.close(
-                                true,
-                                Error::FrameUnexpected.to_wire(),
-                                msg.as_bytes(),
-                            )?;
+        let mut d = vec![0; 16 + capsule_len];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                            return Err(Error::FrameUnexpected);
-                        },
+        b.put_varint(frame::CLOSE_WEBTRANSPORT_SESSION_CAPSULE_TYPE_ID)?;
+        b.put_varint(capsule_len as u64)?;
+        b.put_u32(error_code)?;
+        b.put_bytes(reason)?;
 
-                        Err(e) => {
-                            conn.close(
-                                true,
-                                e.to_wire(),
-                                b"Error handling frame.",
-                            )?;
+        let off = b.off();
 
-                            return Err(e);
-                        },
+        let overhead = octets::varint_len(frame::DATA_FRAME_TYPE_ID) +
+            octets::varint_len(off as u64);
 
-                        _ => (),
-                    }
-                },
+        if conn.stream_capacity(session_id)? < overhead + off {
+            return Err(Error::StreamBlocked);
+        }
 
-                stream::State::FramePayloadLen => {
-                    stream.try_fill_buffer(conn)?;
+        self.send_body(conn, session_id, &d[..off], true)?;
 
-                    let varint = match stream.try_consume_varint() {
-                        Ok(v) => v,
+        self.remove_webtransport_session(conn, session_id);
 
-                        Err(_) => continue,
-                    };
+        Ok(())
+    }
 
-                    if let Err(e) = stream.set_frame_payload_len(varint) {
-                        conn.close(true, e.to_wire(), b"")?;
-                        return Err(e);
-                    }
-                },
+    /// Returns the WebTransport session ID of the given stream.
+    ///
+    /// `None` is returned if the stream is not a WebTransport stream, or if
+    /// its session ID has not been received yet.
+    pub fn webtransport_session_id(&self, stream_id: u64) -> Option<u64> {
+        self.streams
+            .get(&stream_id)
+            .and_then(|s| s.webtransport_session_id())
+    }
 
-                stream::State::FramePayload => {
-                    // Do not emit events when not polling.
-                    if !polling {
-                        break;
-                    }
+    /// Sends a frame on the local control stream.
+    fn send_control_frame(
+        &mut self, conn: &mut super::Connection, frame: frame::Frame,
+    ) -> Result<()> {
+        let stream_id = self.control_stream_id.ok_or(Error::InternalError)?;
 
-                    stream.try_fill_buffer(conn)?;
+        let mut d = [42; 32];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                    let frame = match stream.try_consume_frame() {
-                        Ok(frame) => frame,
+        let wire_len = frame.to_bytes(&mut b)?;
+        let stream_cap = conn.stream_capacity(stream_id)?;
 
-                        Err(Error::Done) => return Err(Error::Done),
+        if stream_cap < wire_len {
+            return Err(Error::StreamBlocked);
+        }
 
-                        Err(e) => {
-                            conn.close(
-                                true,
-                                e.to_wire(),
-                                b"Error handling frame.",
-                            )?;
+        trace!("{} tx frm {:?}", conn.trace_id(), frame);
 
-                            return Err(e);
-                        },
-                    };
+        conn.stream_send(stream_id, &d[..wire_len], false)?;
 
-                    match self.process_frame(conn, stream_id, frame) {
-                        Ok(ev) => return Ok(ev),
+        Ok(())
+    }
 
-                        Err(Error::Done) => (),
+    fn open_uni_stream(
+        &mut self, conn: &mut super::Connection, ty: u64,
+    ) -> Result<u64> {
+        let stream_id = self.next_uni_stream_id;
 
-                        Err(e) => return Err(e),
-                    };
-                },
+        let mut d = [0; 8];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-                stream::State::Data => {
-                    // Do not emit events when not polling.
-                    if !polling {
-                        break;
-                    }
+        match ty {
+            // Control and QPACK streams are the most important to schedule.
+            stream::HTTP3_CONTROL_STREAM_TYPE_ID |
+            stream::QPACK_ENCODER_STREAM_TYPE_ID |
+            stream::QPACK_DECODER_STREAM_TYPE_ID => {
+                conn.stream_priority(stream_id, 0, true)?;
+            },
 
-                    if !stream.try_trigger_data_event() {
-                        break;
-                    }
+            // Push and WebTransport streams use the default priority, like
+            // request streams.
+            stream::HTTP3_PUSH_STREAM_TYPE_ID |
+            stream::WEBTRANSPORT_UNI_STREAM_TYPE_ID => (),
 
-                    return Ok((stream_id, Event::Data));
-                },
+            // Anything else is a GREASE stream, so make it the least important.
+            _ => {
+                conn.stream_priority(stream_id, 255, true)?;
+            },
+        }
 
-                stream::State::QpackInstruction => {
-                    let mut d = [0; 4096];
+        conn.stream_send(stream_id, b.put_varint(ty)?, false)?;
 
-                    // Read data from the stream and discard immediately.
-                    loop {
-                        conn.stream_recv(stream_id, &mut d)?;
-                    }
-                },
+        // To avoid skipping stream IDs, we only calculate the next available
+        // stream ID when data has been successfully buffered.
+        self.next_uni_stream_id = self
+            .next_uni_stream_id
+            .checked_add(4)
+            .ok_or(Error::IdError)?;
 
-                stream::State::Drain => {
-                    // Discard incoming data on the stream.
-                    conn.stream_shutdown(
-                        stream_id,
-                        crate::Shutdown::Read,
-                        0x100,
-                    )?;
+        Ok(stream_id)
+    }
 
-                    break;
-                },
+    fn open_qpack_encoder_stream(
+        &mut self, conn: &mut super::Connection,
+    ) -> Result<()> {
+        self.local_qpack_streams.encoder_stream_id = Some(
+            self.open_uni_stream(conn, stream::QPACK_ENCODER_STREAM_TYPE_ID)?,
+        );
 
-                stream::State::Finished => break,
-            }
-        }
+        Ok(())
+    }
 
-        Err(Error::Done)
+    fn open_qpack_decoder_stream(
+        &mut self, conn: &mut super::Connection,
+    ) -> Result<()> {
+        self.local_qpack_streams.decoder_stream_id = Some(
+            self.open_uni_stream(conn, stream::QPACK_DECODER_STREAM_TYPE_ID)?,
+        );
+
+        Ok(())
     }
 
-    fn process_finished_stream(&mut self, stream_id: u64) {
-        let stream = match self.streams.get_mut(&stream_id) {
-            Some(v) => v,
+    /// Sends pending QPACK instructions on the local encoder and decoder
+    /// streams.
+    ///
+    /// Instructions that don't fit in the streams' capacity are kept until
+    /// the next call.
+    fn send_qpack_instructions(
+        &mut self, conn: &mut super::Connection,
+    ) -> Result<()> {
+        if let Some(stream_id) = self.local_qpack_streams.encoder_stream_id {
+            let pending = self.qpack_encoder.pending_instructions();
 
-            None => return,
-        };
+            if !pending.is_empty() {
+                let written = send_instructions(conn, stream_id, pending)?;
 
-        if stream.state() == stream::State::Finished {
-            return;
+                self.qpack_encoder.consume_instructions(written);
+            }
         }
 
-        match stream.ty() {
-            Some(stream::Type::Request) | Some(stream::Type::Push) => {
-                stream.finished();
+        if let Some(stream_id) = self.local_qpack_streams.decoder_stream_id {
+            let pending = self.qpack_decoder.pending_instructions();
 
-                self.finished_streams.push_back(stream_id);
-            },
+            if !pending.is_empty() {
+                let written = send_instructions(conn, stream_id, pending)?;
 
-            _ => (),
-        };
+                self.qpack_decoder.consume_instructions(written);
+            }
+        }
+
+        Ok(())
     }
 
-    fn process_frame(
+    /// Send GREASE frames on the provided stream ID.
+    fn send_grease_frames(
         &mut self, conn: &mut super::Connection, stream_id: u64,
-        frame: frame::Frame,
-    ) -> Result<(u64, Event)> {
-        trace!(
-            "{} rx frm {:?} stream={}",
-            conn.trace_id(),
-            frame,
-            stream_id
-        );
-
-        match frame {
-            frame::Frame::Settings {
-                max_header_list_size,
-                qpack_max_table_capacity,
-                qpack_blocked_streams,
-                h3_datagram,
-                ..
-            } => {
-                self.peer_settings = ConnectionSettings {
-                    max_header_list_size,
-                    qpack_max_table_capacity,
-                    qpack_blocked_streams,
-                    h3_datagram,
-                };
+    ) -> Result<()> {
+        let mut d = [0; 8];
 
-                if let Some(1) = h3_datagram {
-                    // The peer MUST have also enabled DATAGRAM with a TP
-                    if conn.dgram_max_writable_len().is_none() {
-                        conn.close(
-                            true,
-                            Error::SettingsError.to_wire(),
-                            b"H3_DATAGRAM sent with value 1 but max_datagram_frame_size TP not set.",
-                        )?;
+        let stream_cap = match conn.stream_capacity(stream_id) {
+            Ok(v) => v,
 
-                        return Err(Error::SettingsError);
-                    }
+            Err(e) => {
+                if conn.stream_finished(stream_id) {
+                    self.streams.remove(&stream_id);
                 }
-            },
 
-            frame::Frame::Headers { header_block } => {
-                if Some(stream_id) == self.peer_control_stream_id {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"HEADERS received on control stream",
-                    )?;
+                return Err(e.into());
+            },
+        };
 
-                    return Err(Error::FrameUnexpected);
-                }
+        let grease_frame1 = grease_value();
+        let grease_frame2 = grease_value();
+        let grease_payload = b"GREASE is the word";
 
-                // Use "infinite" as default value for max_header_list_size if
-                // it is not configured by the application.
-                let max_size = self
-                    .local_settings
-                    .max_header_list_size
-                    .unwrap_or(std::u64::MAX);
+        let overhead = octets::varint_len(grease_frame1) + // frame type
+            1 + // payload len
+            octets::varint_len(grease_frame2) + // frame type
+            1 + // payload len
+            grease_payload.len(); // payload
 
-                let headers = match self
-                    .qpack_decoder
-                    .decode(&header_block[..], max_size)
-                {
-                    Ok(v) => v,
+        // Don't send GREASE if there is not enough capacity for it. Greasing
+        // will _not_ be attempted again later on.
+        if stream_cap < overhead {
+            return Ok(());
+        }
 
-                    Err(e) => {
-                        let e = match e {
-                            qpack::Error::HeaderListTooLarge =>
-                                Error::ExcessiveLoad,
+        trace!("{} tx frm GREASE stream={}", conn.trace_id(), stream_id);
 
-                            _ => Error::QpackDecompressionFailed,
-                        };
+        // Empty GREASE frame.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(grease_frame1)?, false)?;
 
-                        conn.close(true, e.to_wire(), b"Error parsing headers.")?;
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(0)?, false)?;
 
-                        return Err(e);
-                    },
-                };
+        // GREASE frame with payload.
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(grease_frame2)?, false)?;
 
-                let has_body = !conn.stream_finished(stream_id);
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        conn.stream_send(stream_id, b.put_varint(18)?, false)?;
 
-                return Ok((stream_id, Event::Headers {
-                    list: headers,
-                    has_body,
-                }));
-            },
+        conn.stream_send(stream_id, grease_payload, false)?;
 
-            frame::Frame::Data { .. } => {
-                if Some(stream_id) == self.peer_control_stream_id {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"DATA received on control stream",
-                    )?;
+        Ok(())
+    }
 
-                    return Err(Error::FrameUnexpected);
-                }
+    /// Opens a new unidirectional stream with a GREASE type and sends some
+    /// unframed payload.
+    fn open_grease_stream(&mut self, conn: &mut super::Connection) -> Result<()> {
+        match self.open_uni_stream(conn, grease_value()) {
+            Ok(stream_id) => {
+                trace!("{} open GREASE stream {}", conn.trace_id(), stream_id);
 
-                // Do nothing. The Data event is returned separately.
+                conn.stream_send(stream_id, b"GREASE is the word", true)?;
             },
 
-            frame::Frame::GoAway { id } => {
-                if Some(stream_id) != self.peer_control_stream_id {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"GOAWAY received on non-control stream",
-                    )?;
+            Err(Error::IdError) => {
+                trace!("{} GREASE stream blocked", conn.trace_id(),);
 
-                    return Err(Error::FrameUnexpected);
-                }
+                return Ok(());
+            },
 
-                if !self.is_server && id % 4 != 0 {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"GOAWAY received with ID of non-request stream",
-                    )?;
+            Err(e) => return Err(e),
+        };
 
-                    return Err(Error::IdError);
-                }
+        Ok(())
+    }
 
-                if let Some(received_id) = self.peer_goaway_id {
-                    if id > received_id {
-                        conn.close(
-                            true,
-                            Error::IdError.to_wire(),
-                            b"GOAWAY received with ID larger than previously received",
-                        )?;
+    /// Sends SETTINGS frame based on HTTP/3 configuration.
+    fn send_settings(&mut self, conn: &mut super::Connection) -> Result<()> {
+        self.control_stream_id = Some(
+            self.open_uni_stream(conn, stream::HTTP3_CONTROL_STREAM_TYPE_ID)?,
+        );
 
-                        return Err(Error::IdError);
-                    }
-                }
+        let grease = if conn.grease {
+            Some((grease_value(), grease_value()))
+        } else {
+            None
+        };
 
-                self.peer_goaway_id = Some(id);
+        let frame = frame::Frame::Settings {
+            max_header_list_size: self.local_settings.max_header_list_size,
+            qpack_max_table_capacity: self
+                .local_settings
+                .qpack_max_table_capacity,
+            qpack_blocked_streams: self.local_settings.qpack_blocked_streams,
+            h3_datagram: self.local_settings.h3_datagram,
+            connect_protocol_enabled: self
+                .local_settings
+                .connect_protocol_enabled,
+            webtransport_enabled: self.local_settings.webtransport_enabled,
+            grease,
+        };
 
-                return Ok((id, Event::GoAway));
-            },
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
 
-            frame::Frame::MaxPushId { push_id } => {
-                if Some(stream_id) != self.peer_control_stream_id {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"MAX_PUSH_ID received on non-control stream",
-                    )?;
+        frame.to_bytes(&mut b)?;
 
-                    return Err(Error::FrameUnexpected);
-                }
+        let off = b.off();
 
-                if !self.is_server {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"MAX_PUSH_ID received by client",
-                    )?;
+        if let Some(id) = self.control_stream_id {
+            conn.stream_send(id, &d[..off], false)?;
+        }
 
-                    return Err(Error::FrameUnexpected);
-                }
+        Ok(())
+    }
 
-                if push_id < self.max_push_id {
-                    conn.close(
-                        true,
-                        Error::IdError.to_wire(),
-                        b"MAX_PUSH_ID reduced limit",
-                    )?;
+    fn process_control_stream(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+    ) -> Result<(u64, Event)> {
+        if conn.stream_finished(stream_id) {
+            conn.close(
+                true,
+                Error::ClosedCriticalStream.to_wire(),
+                b"Critical stream closed.",
+            )?;
+
+            return Err(Error::ClosedCriticalStream);
+        }
+
+        match self.process_readable_stream(conn, stream_id, true) {
+            Ok(ev) => return Ok(ev),
+
+            Err(Error::Done) => (),
+
+            Err(e) => return Err(e),
+        };
+
+        if conn.stream_finished(stream_id) {
+            conn.close(
+                true,
+                Error::ClosedCriticalStream.to_wire(),
+                b"Critical stream closed.",
+            )?;
+
+            return Err(Error::ClosedCriticalStream);
+        }
+
+        Err(Error::Done)
+    }
+
+    fn process_readable_stream(
+        &mut self, conn: &mut super::Connection, stream_id: u64, polling: bool,
+    ) -> Result<(u64, Event)> {
+        // Blocked streams are not read until their headers can be decoded, as
+        // events need to be delivered in order.
+        if self.blocked_streams.contains_key(&stream_id) {
+            // A stream reset by the peer will never be unblocked, so stop
+            // tracking it and let the encoder know its header block won't be
+            // decoded.
+            if let Err(crate::Error::StreamReset(e)) =
+                conn.stream_recv(stream_id, &mut [])
+            {
+                self.blocked_streams.remove(&stream_id);
+
+                self.qpack_decoder
+                    .cancel_stream(stream_id)
+                    .map_err(|_| Error::InternalError)?;
+
+                self.send_qpack_instructions(conn)?;
+
+                return Err(Error::TransportError(crate::Error::StreamReset(e)));
+            }
+
+            return Err(Error::Done);
+        }
+
+        self.streams
+            .entry(stream_id)
+            .or_insert_with(|| stream::Stream::new(stream_id, false));
+
+        // We need to get a fresh reference to the stream for each
+        // iteration, to avoid borrowing `self` for the entire duration
+        // of the loop, because we'll need to borrow it again in the
+        // `State::FramePayload` case below.
+        while let Some(stream) = self.streams.get_mut(&stream_id) {
+            match stream.state() {
+                stream::State::StreamType => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    let ty = stream::Type::deserialize(varint)?;
+
+                    if let Err(e) = stream.set_ty(ty) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+
+                    match &ty {
+                        stream::Type::Control => {
+                            // Only one control stream allowed.
+                            if self.peer_control_stream_id.is_some() {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Received multiple control streams",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+
+                            trace!(
+                                "{} open peer's control stream {}",
+                                conn.trace_id(),
+                                stream_id
+                            );
+
+                            self.peer_control_stream_id = Some(stream_id);
+                        },
+
+                        stream::Type::Push => {
+                            // Only clients can receive push stream.
+                            if self.is_server {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Server received push stream.",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+                        },
+
+                        stream::Type::QpackEncoder => {
+                            // Only one qpack encoder stream allowed.
+                            if self.peer_qpack_streams.encoder_stream_id.is_some()
+                            {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Received multiple QPACK encoder streams",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+
+                            self.peer_qpack_streams.encoder_stream_id =
+                                Some(stream_id);
+                        },
+
+                        stream::Type::QpackDecoder => {
+                            // Only one qpack decoder allowed.
+                            if self.peer_qpack_streams.decoder_stream_id.is_some()
+                            {
+                                conn.close(
+                                    true,
+                                    Error::StreamCreationError.to_wire(),
+                                    b"Received multiple QPACK decoder streams",
+                                )?;
+
+                                return Err(Error::StreamCreationError);
+                            }
+
+                            self.peer_qpack_streams.decoder_stream_id =
+                                Some(stream_id);
+                        },
+
+                        stream::Type::WebTransport => (),
+
+                        stream::Type::Unknown => {
+                            // Unknown stream types are ignored.
+                            // TODO: we MAY send STOP_SENDING
+                        },
+
+                        stream::Type::Request => unreachable!(),
+                    }
+                },
+
+                stream::State::PushId => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    if let Err(e) = stream.set_push_id(varint) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+
+                    self.process_push_stream(conn, stream_id, varint)?;
+                },
+
+                stream::State::WebTransportSessionId => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    if let Err(e) = stream.set_webtransport_session_id(varint) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+
+                    let session = self.webtransport_sessions.get(&varint);
+
+                    if session.is_some_and(|s| s.established) {
+                        return Ok((stream_id, Event::WebTransportStream {
+                            session_id: varint,
+                        }));
+                    }
+
+                    // Buffer the stream until the session is established, as
+                    // it might arrive before the CONNECT stream. Clients only
+                    // wait for sessions they requested.
+                    if (self.is_server || session.is_some()) &&
+                        self.webtransport_buffered_streams.len() <
+                            MAX_BUFFERED_WEBTRANSPORT_STREAMS
+                    {
+                        trace!(
+                            "{} buffer WebTransport stream {}",
+                            conn.trace_id(),
+                            stream_id
+                        );
+
+                        self.webtransport_buffered_streams
+                            .insert(stream_id, varint);
+                    } else {
+                        self.reset_webtransport_stream(
+                            conn,
+                            stream_id,
+                            WEBTRANSPORT_BUFFERED_STREAM_REJECTED,
+                        );
+                    }
+
+                    break;
+                },
+
+                // WebTransport data is read by the application directly.
+                stream::State::WebTransportData => {
+                    // Buffered streams reset by the peer no longer need to
+                    // be reported.
+                    if self.webtransport_buffered_streams.contains_key(&stream_id)
+                    {
+                        if let Err(crate::Error::StreamReset(_)) =
+                            conn.stream_recv(stream_id, &mut [])
+                        {
+                            self.webtransport_buffered_streams.remove(&stream_id);
+                            self.streams.remove(&stream_id);
+                        }
+                    }
+
+                    break;
+                },
+
+                stream::State::FrameType => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    match stream.set_frame_type(varint) {
+                        Err(Error::FrameUnexpected) => {
+                            let msg = format!("Unexpected frame type {}", varint);
+
+                            conn.close(
+                                true,
+                                Error::FrameUnexpected.to_wire(),
+                                msg.as_bytes(),
+                            )?;
+
+                            return Err(Error::FrameUnexpected);
+                        },
+
+                        Err(e) => {
+                            conn.close(
+                                true,
+                                e.to_wire(),
+                                b"Error handling frame.",
+                            )?;
+
+                            return Err(e);
+                        },
+
+                        _ => (),
+                    }
+                },
+
+                stream::State::FramePayloadLen => {
+                    stream.try_fill_buffer(conn)?;
+
+                    let varint = match stream.try_consume_varint() {
+                        Ok(v) => v,
+
+                        Err(_) => continue,
+                    };
+
+                    if let Err(e) = stream.set_frame_payload_len(varint) {
+                        conn.close(true, e.to_wire(), b"")?;
+                        return Err(e);
+                    }
+                },
+
+                stream::State::FramePayload => {
+                    // Do not emit events when not polling.
+                    if !polling {
+                        break;
+                    }
+
+                    stream.try_fill_buffer(conn)?;
+
+                    let frame = match stream.try_consume_frame() {
+                        Ok(frame) => frame,
+
+                        Err(Error::Done) => return Err(Error::Done),
+
+                        Err(e) => {
+                            conn.close(
+                                true,
+                                e.to_wire(),
+                                b"Error handling frame.",
+                            )?;
+
+                            return Err(e);
+                        },
+                    };
+
+                    match self.process_frame(conn, stream_id, frame) {
+                        Ok(ev) => return Ok(ev),
+
+                        Err(Error::Done) => (),
+
+                        Err(e) => return Err(e),
+                    };
+                },
+
+                stream::State::Data => {
+                    // Do not emit events when not polling.
+                    if !polling {
+                        break;
+                    }
+
+                    if !stream.try_trigger_data_event() {
+                        break;
+                    }
+
+                    // The body of WebTransport sessions' CONNECT streams is
+                    // made of capsules, which are processed internally.
+                    if self.webtransport_sessions.contains_key(&stream_id) {
+                        return self.process_capsules(conn, stream_id);
+                    }
+
+                    return Ok((stream_id, Event::Data));
+                },
+
+                stream::State::QpackInstruction => {
+                    let mut d = [0; 4096];
+
+                    loop {
+                        let (read, _) = conn.stream_recv(stream_id, &mut d)?;
+
+                        let res = if Some(stream_id) ==
+                            self.peer_qpack_streams.encoder_stream_id
+                        {
+                            self.qpack_decoder
+                                .control(&d[..read])
+                                .map_err(|e| (e, Error::QpackEncoderStreamError))
+                        } else {
+                            self.qpack_encoder
+                                .control(&d[..read])
+                                .map_err(|e| (e, Error::QpackDecoderStreamError))
+                        };
+
+                        if let Err((e, err)) = res {
+                            trace!(
+                                "{} invalid QPACK instructions: {:?}",
+                                conn.trace_id(),
+                                e
+                            );
+
+                            conn.close(
+                                true,
+                                err.to_wire(),
+                                b"Error processing QPACK instructions.",
+                            )?;
+
+                            return Err(err);
+                        }
+
+                        self.send_qpack_instructions(conn)?;
+                    }
+                },
+
+                stream::State::Drain => {
+                    // Discard incoming data on the stream.
+                    conn.stream_shutdown(
+                        stream_id,
+                        crate::Shutdown::Read,
+                        0x100,
+                    )?;
+
+                    break;
+                },
+
+                stream::State::Finished => break,
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Validates the push ID of a push stream received from the server.
+    fn process_push_stream(
+        &mut self, conn: &mut super::Connection, stream_id: u64, push_id: u64,
+    ) -> Result<()> {
+        if self.local_max_push_id.is_none_or(|max| push_id > max) {
+            conn.close(
+                true,
+                Error::IdError.to_wire(),
+                b"Push stream received with ID larger than MAX_PUSH_ID",
+            )?;
+
+            return Err(Error::IdError);
+        }
+
+        let push = self.pushes.entry(push_id).or_default();
+
+        if push.stream_id.is_some() {
+            conn.close(
+                true,
+                Error::IdError.to_wire(),
+                b"Multiple push streams received with the same push ID",
+            )?;
+
+            return Err(Error::IdError);
+        }
+
+        push.stream_id = Some(stream_id);
+
+        // The push was cancelled before its stream was received, so don't
+        // bother reading it.
+        if push.cancelled {
+            conn.stream_shutdown(
+                stream_id,
+                crate::Shutdown::Read,
+                Error::RequestCancelled.to_wire(),
+            )?;
+
+            self.streams.remove(&stream_id);
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether streams and capsules can be sent for the given
+    /// WebTransport session.
+    ///
+    /// Clients can do so as soon as the CONNECT request is sent, while
+    /// servers need to accept the session with a 2xx response first.
+    fn webtransport_session_usable(&self, session_id: u64) -> bool {
+        self.webtransport_sessions
+            .get(&session_id)
+            .is_some_and(|s| s.established || !self.is_server)
+    }
+
+    /// Aborts both directions of a WebTransport stream with the given error
+    /// code, and stops tracking it.
+    fn reset_webtransport_stream(
+        &mut self, conn: &mut super::Connection, stream_id: u64, error_code: u64,
+    ) {
+        trace!(
+            "{} reset WebTransport stream {} error_code={:x}",
+            conn.trace_id(),
+            stream_id,
+            error_code
+        );
+
+        let is_bidi = crate::stream::is_bidi(stream_id);
+        let is_local = crate::stream::is_local(stream_id, self.is_server);
+
+        // The stream might have been completed already.
+        if is_bidi || !is_local {
+            conn.stream_shutdown(stream_id, crate::Shutdown::Read, error_code)
+                .ok();
+        }
+
+        if is_bidi || is_local {
+            conn.stream_shutdown(stream_id, crate::Shutdown::Write, error_code)
+                .ok();
+        }
+
+        self.streams.remove(&stream_id);
+    }
+
+    /// Updates the state of the pending WebTransport session of the given
+    /// stream, if any, once the response to its CONNECT request is sent or
+    /// received.
+    fn process_webtransport_response<T: NameValue>(
+        &mut self, conn: &mut super::Connection, stream_id: u64, headers: &[T],
+    ) {
+        match self.webtransport_sessions.get(&stream_id) {
+            Some(session) if !session.established => (),
+
+            _ => return,
+        };
+
+        let status = headers
+            .iter()
+            .find(|h| h.name() == b":status")
+            .and_then(|h| std::str::from_utf8(h.value()).ok())
+            .and_then(|v| v.parse::<u16>().ok());
+
+        match status {
+            Some(200..=299) => {
+                if let Some(session) =
+                    self.webtransport_sessions.get_mut(&stream_id)
+                {
+                    session.established = true;
+                }
+
+                let mut ready: Vec<u64> = self
+                    .webtransport_buffered_streams
+                    .iter()
+                    .filter(|(_, session_id)| **session_id == stream_id)
+                    .map(|(id, _)| *id)
+                    .collect();
+
+                ready.sort_unstable();
+
+                for id in ready {
+                    self.webtransport_buffered_streams.remove(&id);
+                    self.webtransport_ready_streams.push_back((id, stream_id));
+                }
+            },
+
+            // Interim responses don't affect the session.
+            Some(100..=199) => (),
+
+            _ => self.remove_webtransport_session(conn, stream_id),
+        }
+    }
+
+    /// Terminates the given WebTransport session, if any, rejecting the
+    /// streams buffered for it and resetting the ones already in use.
+    fn remove_webtransport_session(
+        &mut self, conn: &mut super::Connection, session_id: u64,
+    ) {
+        self.webtransport_sessions.remove(&session_id);
+
+        let mut rejected: Vec<u64> = self
+            .webtransport_buffered_streams
+            .iter()
+            .filter(|(_, id)| **id == session_id)
+            .map(|(stream_id, _)| *stream_id)
+            .collect();
+
+        rejected.sort_unstable();
+
+        for stream_id in rejected {
+            self.webtransport_buffered_streams.remove(&stream_id);
+            self.reset_webtransport_stream(
+                conn,
+                stream_id,
+                WEBTRANSPORT_BUFFERED_STREAM_REJECTED,
+            );
+        }
+
+        let mut gone: Vec<u64> = self
+            .streams
+            .iter()
+            .filter(|(_, s)| s.webtransport_session_id() == Some(session_id))
+            .map(|(stream_id, _)| *stream_id)
+            .collect();
+
+        gone.sort_unstable();
+
+        for stream_id in gone {
+            self.reset_webtransport_stream(
+                conn,
+                stream_id,
+                WEBTRANSPORT_SESSION_GONE,
+            );
+        }
+    }
+
+    /// Reads and processes the capsules received on the CONNECT stream of a
+    /// WebTransport session.
+    fn process_capsules(
+        &mut self, conn: &mut super::Connection, session_id: u64,
+    ) -> Result<(u64, Event)> {
+        // The session is taken out of the map while the body is read, as
+        // reading the end of the stream would otherwise terminate it before
+        // its last capsules are processed.
+        let mut session = match self.webtransport_sessions.remove(&session_id) {
+            Some(v) => v,
+
+            None => return Err(Error::Done),
+        };
+
+        let mut d = [0; 4096];
+
+        loop {
+            let read = match self.recv_body(conn, session_id, &mut d) {
+                Ok(v) => v,
+
+                Err(Error::Done) => break,
+
+                Err(e) => {
+                    self.remove_webtransport_session(conn, session_id);
+
+                    return Err(e);
+                },
+            };
+
+            session.capsule_buf.extend_from_slice(&d[..read]);
+
+            if session.capsule_buf.len() > MAX_CAPSULE_BUF_SIZE {
+                conn.close(
+                    true,
+                    Error::ExcessiveLoad.to_wire(),
+                    b"Capsule too large.",
+                )?;
+
+                return Err(Error::ExcessiveLoad);
+            }
+        }
+
+        loop {
+            let mut b = octets::Octets::with_slice(&session.capsule_buf);
+
+            let (ty, value) = match (b.get_varint(), b.get_varint()) {
+                (Ok(ty), Ok(len)) => match b.get_bytes(len as usize) {
+                    Ok(value) => (ty, value),
+
+                    // Wait for the rest of the capsule.
+                    Err(_) => break,
+                },
+
+                _ => break,
+            };
+
+            if ty == frame::CLOSE_WEBTRANSPORT_SESSION_CAPSULE_TYPE_ID {
+                let mut value = value;
+
+                let error_code = match value.get_u32() {
+                    Ok(v) if value.cap() <= MAX_WEBTRANSPORT_CLOSE_REASON_LEN =>
+                        v,
+
+                    _ => {
+                        conn.close(
+                            true,
+                            Error::MessageError.to_wire(),
+                            b"Invalid CLOSE_WEBTRANSPORT_SESSION capsule.",
+                        )?;
+
+                        return Err(Error::MessageError);
+                    },
+                };
+
+                let reason = value.to_vec();
+
+                self.remove_webtransport_session(conn, session_id);
+
+                return Ok((session_id, Event::WebTransportSessionClosed {
+                    error_code,
+                    reason,
+                }));
+            }
+
+            // Other capsules are ignored.
+            let off = b.off();
+
+            session.capsule_buf.drain(..off);
+        }
+
+        self.webtransport_sessions.insert(session_id, session);
+
+        // Finishing the CONNECT stream terminates the session.
+        if conn.stream_finished(session_id) {
+            self.remove_webtransport_session(conn, session_id);
+        }
+
+        Err(Error::Done)
+    }
+
+    fn process_finished_stream(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+    ) {
+        // The stream will be finished once its headers are decoded.
+        if self.blocked_streams.contains_key(&stream_id) {
+            return;
+        }
+
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if stream.state() == stream::State::Finished {
+            return;
+        }
+
+        match stream.ty() {
+            Some(stream::Type::Request) | Some(stream::Type::Push) => {
+                stream.finished();
+
+                self.finished_streams.push_back(stream_id);
+            },
+
+            _ => (),
+        };
+
+        // Finishing the CONNECT stream terminates the WebTransport session.
+        self.remove_webtransport_session(conn, stream_id);
+    }
+
+    /// Decodes a header block, returning a `Headers` event, or a
+    /// `PushPromise` event if a push ID is given.
+    ///
+    /// If the header block can't be decoded until more QPACK encoder
+    /// instructions are received, the stream is marked as blocked and `Done`
+    /// is returned.
+    fn process_headers(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        header_block: Vec<u8>, push_id: Option<u64>,
+    ) -> Result<(u64, Event)> {
+        // Use "infinite" as default value for max_header_list_size if
+        // it is not configured by the application.
+        let max_size = self
+            .local_settings
+            .max_header_list_size
+            .unwrap_or(std::u64::MAX);
+
+        let headers = match self.qpack_decoder.decode_stream(
+            stream_id,
+            &header_block[..],
+            max_size,
+        ) {
+            Ok(v) => v,
+
+            Err(qpack::Error::Blocked) => {
+                let max_blocked =
+                    self.local_settings.qpack_blocked_streams.unwrap_or(0);
+
+                if self.blocked_streams.len() as u64 >= max_blocked {
+                    conn.close(
+                        true,
+                        Error::QpackDecompressionFailed.to_wire(),
+                        b"Too many blocked streams.",
+                    )?;
+
+                    return Err(Error::QpackDecompressionFailed);
+                }
+
+                trace!("{} stream {} blocked", conn.trace_id(), stream_id);
+
+                self.blocked_streams
+                    .insert(stream_id, (header_block, push_id));
+
+                return Err(Error::Done);
+            },
+
+            Err(e) => {
+                let e = match e {
+                    qpack::Error::HeaderListTooLarge => Error::ExcessiveLoad,
+
+                    _ => Error::QpackDecompressionFailed,
+                };
+
+                conn.close(true, e.to_wire(), b"Error parsing headers.")?;
+
+                return Err(e);
+            },
+        };
+
+        self.send_qpack_instructions(conn)?;
+
+        if let Some(push_id) = push_id {
+            return Ok((stream_id, Event::PushPromise {
+                push_id,
+                list: headers,
+            }));
+        }
+
+        if self.is_server {
+            self.process_extended_connect(conn, stream_id, &headers)?;
+
+            // Streams buffered for a request that didn't establish a session
+            // are rejected.
+            if !self.webtransport_sessions.contains_key(&stream_id) {
+                self.remove_webtransport_session(conn, stream_id);
+            }
+        } else {
+            self.process_webtransport_response(conn, stream_id, &headers);
+        }
+
+        // PRIORITY_UPDATE frames take precedence over the request header, so
+        // only use the latter if no frame was received yet.
+        if self.is_server {
+            let pending = self.pending_priorities.remove(&stream_id);
+
+            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                let priority = headers
+                    .iter()
+                    .find(|h| h.name() == b"priority")
+                    .map(|h| Priority::parse(h.value()));
+
+                if let Some(priority) = pending {
+                    stream.set_priority(priority);
+                } else if let (None, Some(priority)) =
+                    (stream.priority(), priority)
+                {
+                    stream.set_priority(priority);
+                }
+            }
+        }
+
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            stream.set_headers_received();
+        }
+
+        let has_body = !conn.stream_finished(stream_id);
+
+        Ok((stream_id, Event::Headers {
+            list: headers,
+            has_body,
+        }))
+    }
+
+    /// Validates an extended CONNECT request received by a server, and
+    /// tracks the WebTransport session it establishes, if any.
+    fn process_extended_connect(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        headers: &[Header],
+    ) -> Result<()> {
+        let protocol = match headers.iter().find(|h| h.name() == b":protocol") {
+            Some(v) => v,
+
+            None => return Ok(()),
+        };
+
+        if self.local_settings.connect_protocol_enabled != Some(1) {
+            conn.close(
+                true,
+                Error::MessageError.to_wire(),
+                b"Extended CONNECT received but not enabled.",
+            )?;
+
+            return Err(Error::MessageError);
+        }
+
+        let is_connect = headers
+            .iter()
+            .any(|h| h.name() == b":method" && h.value() == b"CONNECT");
+
+        if is_connect &&
+            protocol.value() == b"webtransport" &&
+            self.local_settings.webtransport_enabled == Some(1)
+        {
+            // The session is established once a 2xx response is sent.
+            self.webtransport_sessions.entry(stream_id).or_default();
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the header block of a previously blocked stream, if the
+    /// required QPACK encoder instructions were received.
+    fn process_blocked_streams(
+        &mut self, conn: &mut super::Connection,
+    ) -> Result<(u64, Event)> {
+        if self.blocked_streams.is_empty() {
+            return Err(Error::Done);
+        }
+
+        let blocked: Vec<u64> = self.blocked_streams.keys().copied().collect();
+
+        for stream_id in blocked {
+            let (header_block, push_id) =
+                match self.blocked_streams.remove(&stream_id) {
+                    Some(v) => v,
+
+                    None => continue,
+                };
+
+            match self.process_headers(conn, stream_id, header_block, push_id) {
+                Ok(ev) => {
+                    if conn.stream_finished(stream_id) {
+                        self.process_finished_stream(conn, stream_id);
+                    }
+
+                    return Ok(ev);
+                },
+
+                // Still blocked.
+                Err(Error::Done) => (),
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Validates that a PRIORITY_UPDATE frame was received by a server on
+    /// the peer's control stream, closing the connection otherwise.
+    fn check_priority_update(
+        &self, conn: &mut super::Connection, stream_id: u64,
+    ) -> Result<()> {
+        if Some(stream_id) != self.peer_control_stream_id {
+            conn.close(
+                true,
+                Error::FrameUnexpected.to_wire(),
+                b"PRIORITY_UPDATE received on non-control stream",
+            )?;
+
+            return Err(Error::FrameUnexpected);
+        }
+
+        if !self.is_server {
+            conn.close(
+                true,
+                Error::FrameUnexpected.to_wire(),
+                b"PRIORITY_UPDATE received by client",
+            )?;
+
+            return Err(Error::FrameUnexpected);
+        }
+
+        Ok(())
+    }
+
+    fn process_frame(
+        &mut self, conn: &mut super::Connection, stream_id: u64,
+        frame: frame::Frame,
+    ) -> Result<(u64, Event)> {
+        trace!(
+            "{} rx frm {:?} stream={}",
+            conn.trace_id(),
+            frame,
+            stream_id
+        );
+
+        match frame {
+            frame::Frame::Settings {
+                max_header_list_size,
+                qpack_max_table_capacity,
+                qpack_blocked_streams,
+                h3_datagram,
+                connect_protocol_enabled,
+                webtransport_enabled,
+                ..
+            } => {
+                self.peer_settings = ConnectionSettings {
+                    max_header_list_size,
+                    qpack_max_table_capacity,
+                    qpack_blocked_streams,
+                    h3_datagram,
+                    connect_protocol_enabled,
+                    webtransport_enabled,
+                };
+
+                self.qpack_encoder
+                    .set_max_table_capacity(qpack_max_table_capacity.unwrap_or(0))
+                    .map_err(|_| Error::InternalError)?;
+
+                self.qpack_encoder
+                    .set_max_blocked_streams(qpack_blocked_streams.unwrap_or(0));
+
+                self.send_qpack_instructions(conn)?;
+
+                if let Some(1) = h3_datagram {
+                    // The peer MUST have also enabled DATAGRAM with a TP
+                    if conn.dgram_max_writable_len().is_none() {
+                        conn.close(
+                            true,
+                            Error::SettingsError.to_wire(),
+                            b"H3_DATAGRAM sent with value 1 but max_datagram_frame_size TP not set.",
+                        )?;
+
+                        return Err(Error::SettingsError);
+                    }
+                }
+            },
+
+            frame::Frame::Headers { header_block } => {
+                if Some(stream_id) == self.peer_control_stream_id {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"HEADERS received on control stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                return self.process_headers(conn, stream_id, header_block, None);
+            },
+
+            frame::Frame::Data { .. } => {
+                if Some(stream_id) == self.peer_control_stream_id {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"DATA received on control stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                // Do nothing. The Data event is returned separately.
+            },
+
+            frame::Frame::GoAway { id } => {
+                if Some(stream_id) != self.peer_control_stream_id {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"GOAWAY received on non-control stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if !self.is_server && id % 4 != 0 {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"GOAWAY received with ID of non-request stream",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                if let Some(received_id) = self.peer_goaway_id {
+                    if id > received_id {
+                        conn.close(
+                            true,
+                            Error::IdError.to_wire(),
+                            b"GOAWAY received with ID larger than previously received",
+                        )?;
+
+                        return Err(Error::IdError);
+                    }
+                }
+
+                self.peer_goaway_id = Some(id);
+
+                return Ok((id, Event::GoAway));
+            },
+
+            frame::Frame::MaxPushId { push_id } => {
+                if Some(stream_id) != self.peer_control_stream_id {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"MAX_PUSH_ID received on non-control stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if !self.is_server {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"MAX_PUSH_ID received by client",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if self.peer_max_push_id.is_some_and(|max| push_id < max) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"MAX_PUSH_ID reduced limit",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                self.peer_max_push_id = Some(push_id);
+            },
+
+            frame::Frame::PushPromise {
+                push_id,
+                header_block,
+            } => {
+                if self.is_server {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PUSH_PROMISE received by server",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if stream_id % 4 != 0 {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"PUSH_PROMISE received on non-request stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                if self.local_max_push_id.is_none_or(|max| push_id > max) {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PUSH_PROMISE received with ID larger than MAX_PUSH_ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                self.pushes.entry(push_id).or_default();
+
+                return self.process_headers(
+                    conn,
+                    stream_id,
+                    header_block,
+                    Some(push_id),
+                );
+            },
+
+            frame::Frame::CancelPush { push_id } => {
+                if Some(stream_id) != self.peer_control_stream_id {
+                    conn.close(
+                        true,
+                        Error::FrameUnexpected.to_wire(),
+                        b"CANCEL_PUSH received on non-control stream",
+                    )?;
+
+                    return Err(Error::FrameUnexpected);
+                }
+
+                // Servers can only receive CANCEL_PUSH for pushes that were
+                // promised, while clients might receive it before the
+                // corresponding PUSH_PROMISE.
+                let valid = if self.is_server {
+                    push_id < self.next_push_id
+                } else {
+                    self.local_max_push_id.is_some_and(|max| push_id <= max)
+                };
+
+                if !valid {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"CANCEL_PUSH received with invalid push ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                let push = self.pushes.entry(push_id).or_default();
+
+                push.cancelled = true;
+
+                // Stop sending the pushed response if it was already started.
+                if let (true, Some(push_stream_id)) =
+                    (self.is_server, push.stream_id)
+                {
+                    // The stream might have been completed already.
+                    conn.stream_shutdown(
+                        push_stream_id,
+                        crate::Shutdown::Write,
+                        Error::RequestCancelled.to_wire(),
+                    )
+                    .ok();
+
+                    self.streams.remove(&push_stream_id);
+                }
+
+                return Ok((push_id, Event::CancelPush));
+            },
+
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                self.check_priority_update(conn, stream_id)?;
+
+                // Only client-initiated bidirectional streams carry requests.
+                if !crate::stream::is_bidi(prioritized_element_id) ||
+                    crate::stream::is_local(prioritized_element_id, true)
+                {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PRIORITY_UPDATE received with ID of non-request stream",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                // The request stream can't be beyond the limit advertised to
+                // the peer.
+                if prioritized_element_id / 4 >= conn.streams.max_streams_bidi() {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PRIORITY_UPDATE received with ID above stream limit",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                let priority = Priority::parse(&priority_field_value);
+
+                match self.streams.get_mut(&prioritized_element_id) {
+                    Some(stream) if stream.headers_received() => {
+                        stream.set_priority(priority);
+
+                        return Ok((
+                            prioritized_element_id,
+                            Event::PriorityUpdate(priority),
+                        ));
+                    },
+
+                    // Updates for requests whose headers weren't received yet
+                    // are remembered, up to a limit, and applied once they
+                    // are, without being reported.
+                    _ => {
+                        if self.pending_priorities.len() <
+                            MAX_PENDING_PRIORITY_UPDATES ||
+                            self.pending_priorities
+                                .contains_key(&prioritized_element_id)
+                        {
+                            self.pending_priorities
+                                .insert(prioritized_element_id, priority);
+                        }
+                    },
+                }
+            },
+
+            frame::Frame::PriorityUpdatePush {
+                prioritized_element_id,
+                priority_field_value,
+            } => {
+                self.check_priority_update(conn, stream_id)?;
+
+                if prioritized_element_id >= self.next_push_id {
+                    conn.close(
+                        true,
+                        Error::IdError.to_wire(),
+                        b"PRIORITY_UPDATE received with invalid push ID",
+                    )?;
+
+                    return Err(Error::IdError);
+                }
+
+                let priority = Priority::parse(&priority_field_value);
+
+                let push = self.pushes.entry(prioritized_element_id).or_default();
+
+                push.priority = Some(priority);
+
+                // The priority is applied when the push stream is opened, so
+                // only report updates for push streams that already are.
+                if let Some(push_stream_id) = push.stream_id {
+                    return Ok((push_stream_id, Event::PriorityUpdate(priority)));
+                }
+            },
+
+            frame::Frame::Unknown => (),
+        }
+
+        Err(Error::Done)
+    }
+}
+
+/// Sends QPACK instructions on the given stream, returning how many bytes
+/// were written.
+fn send_instructions(
+    conn: &mut super::Connection, stream_id: u64, buf: &[u8],
+) -> Result<usize> {
+    match conn.stream_send(stream_id, buf, false) {
+        Ok(v) => Ok(v),
+
+        Err(super::Error::Done) => Ok(0),
+
+        Err(e) => Err(Error::TransportError(e)),
+    }
+}
+
+/// Generates an HTTP/3 GREASE variable length integer.
+fn grease_value() -> u64 {
+    let n = super::rand::rand_u64_uniform(148_764_065_110_560_899);
+    31 * n + 33
+}
+
+#[doc(hidden)]
+pub mod testing {
+    use super::*;
+
+    use crate::testing;
+
+    /// Session is an HTTP/3 test helper structure. It holds a client, server
+    /// and pipe that allows them to communicate.
+    ///
+    /// `default()` creates a session with some sensible default
+    /// configuration. `with_configs()` allows for providing a specific
+    /// configuration.
+    ///
+    /// `handshake()` performs all the steps needed to establish an HTTP/3
+    /// connection.
+    ///
+    /// Some utility functions are provided that make it less verbose to send
+    /// request, responses and individual headers. The full quiche API remains
+    /// available for any test that need to do unconventional things (such as
+    /// bad behaviour that triggers errors).
+    pub struct Session {
+        pub pipe: testing::Pipe,
+        pub client: Connection,
+        pub server: Connection,
+    }
+
+    impl Session {
+        pub fn default() -> Result<Session> {
+            let h3_config = Config::new()?;
+            Session::with_h3_config(&h3_config)
+        }
+
+        /// Returns the transport configuration used by default sessions.
+        pub fn default_config() -> Result<crate::Config> {
+            let mut config = crate::Config::new(crate::PROTOCOL_VERSION)?;
+            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
+            config.load_priv_key_from_pem_file("examples/cert.key")?;
+            config.set_application_protos(b"\x02h3")?;
+            config.set_initial_max_data(1500);
+            config.set_initial_max_stream_data_bidi_local(150);
+            config.set_initial_max_stream_data_bidi_remote(150);
+            config.set_initial_max_stream_data_uni(150);
+            config.set_initial_max_streams_bidi(5);
+            config.set_initial_max_streams_uni(5);
+            config.verify_peer(false);
+            config.enable_dgram(true, 3, 3);
+            config.set_ack_delay_exponent(8);
+
+            Ok(config)
+        }
+
+        /// Creates a session using the default transport configuration and
+        /// the given HTTP/3 configuration.
+        pub fn with_h3_config(h3_config: &Config) -> Result<Session> {
+            let mut config = Session::default_config()?;
+            Session::with_configs(&mut config, h3_config)
+        }
+
+        pub fn with_configs(
+            config: &mut crate::Config, h3_config: &Config,
+        ) -> Result<Session> {
+            let pipe = testing::Pipe::with_config(config)?;
+            let client_dgram = pipe.client.dgram_enabled();
+            let server_dgram = pipe.server.dgram_enabled();
+            Ok(Session {
+                pipe,
+                client: Connection::new(&h3_config, false, client_dgram)?,
+                server: Connection::new(&h3_config, true, server_dgram)?,
+            })
+        }
+
+        /// Do the HTTP/3 handshake so both ends are in sane initial state.
+        pub fn handshake(&mut self) -> Result<()> {
+            self.pipe.handshake()?;
+
+            // Client streams.
+            self.client.send_settings(&mut self.pipe.client)?;
+            self.pipe.advance().ok();
+
+            self.client
+                .open_qpack_encoder_stream(&mut self.pipe.client)?;
+            self.pipe.advance().ok();
+
+            self.client
+                .open_qpack_decoder_stream(&mut self.pipe.client)?;
+            self.pipe.advance().ok();
+
+            if self.pipe.client.grease {
+                self.client.open_grease_stream(&mut self.pipe.client)?;
+            }
+
+            self.pipe.advance().ok();
+
+            // Server streams.
+            self.server.send_settings(&mut self.pipe.server)?;
+            self.pipe.advance().ok();
+
+            self.server
+                .open_qpack_encoder_stream(&mut self.pipe.server)?;
+            self.pipe.advance().ok();
+
+            self.server
+                .open_qpack_decoder_stream(&mut self.pipe.server)?;
+            self.pipe.advance().ok();
+
+            if self.pipe.server.grease {
+                self.server.open_grease_stream(&mut self.pipe.server)?;
+            }
+
+            self.advance().ok();
+
+            while self.client.poll(&mut self.pipe.client).is_ok() {
+                // Do nothing.
+            }
+
+            while self.server.poll(&mut self.pipe.server).is_ok() {
+                // Do nothing.
+            }
+
+            Ok(())
+        }
+
+        /// Advances the session pipe over the buffer.
+        pub fn advance(&mut self) -> crate::Result<()> {
+            self.pipe.advance()
+        }
+
+        /// Polls the client for events.
+        pub fn poll_client(&mut self) -> Result<(u64, Event)> {
+            self.client.poll(&mut self.pipe.client)
+        }
+
+        /// Polls the server for events.
+        pub fn poll_server(&mut self) -> Result<(u64, Event)> {
+            self.server.poll(&mut self.pipe.server)
+        }
+
+        /// Sends a request from client with default headers.
+        ///
+        /// On success it returns the newly allocated stream and the headers.
+        pub fn send_request(&mut self, fin: bool) -> Result<(u64, Vec<Header>)> {
+            let req = vec![
+                Header::new(b":method", b"GET"),
+                Header::new(b":scheme", b"https"),
+                Header::new(b":authority", b"quic.tech"),
+                Header::new(b":path", b"/test"),
+                Header::new(b"user-agent", b"quiche-test"),
+            ];
+
+            let stream =
+                self.client.send_request(&mut self.pipe.client, &req, fin)?;
+
+            self.advance().ok();
+
+            Ok((stream, req))
+        }
+
+        /// Sends a response from server with default headers.
+        ///
+        /// On success it returns the headers.
+        pub fn send_response(
+            &mut self, stream: u64, fin: bool,
+        ) -> Result<Vec<Header>> {
+            let resp = vec![
+                Header::new(b":status", b"200"),
+                Header::new(b"server", b"quiche-test"),
+            ];
+
+            self.server.send_response(
+                &mut self.pipe.server,
+                stream,
+                &resp,
+                fin,
+            )?;
+
+            self.advance().ok();
+
+            Ok(resp)
+        }
+
+        /// Sends some default payload from client.
+        ///
+        /// On success it returns the payload.
+        pub fn send_body_client(
+            &mut self, stream: u64, fin: bool,
+        ) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+            self.client
+                .send_body(&mut self.pipe.client, stream, &bytes, fin)?;
+
+            self.advance().ok();
+
+            Ok(bytes)
+        }
+
+        /// Fetches DATA payload from the server.
+        ///
+        /// On success it returns the number of bytes received.
+        pub fn recv_body_client(
+            &mut self, stream: u64, buf: &mut [u8],
+        ) -> Result<usize> {
+            self.client.recv_body(&mut self.pipe.client, stream, buf)
+        }
+
+        /// Sends some default payload from server.
+        ///
+        /// On success it returns the payload.
+        pub fn send_body_server(
+            &mut self, stream: u64, fin: bool,
+        ) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+            self.server
+                .send_body(&mut self.pipe.server, stream, &bytes, fin)?;
+
+            self.advance().ok();
+
+            Ok(bytes)
+        }
+
+        /// Fetches DATA payload from the client.
+        ///
+        /// On success it returns the number of bytes received.
+        pub fn recv_body_server(
+            &mut self, stream: u64, buf: &mut [u8],
+        ) -> Result<usize> {
+            self.server.recv_body(&mut self.pipe.server, stream, buf)
+        }
+
+        /// Sends a single HTTP/3 frame from the client.
+        pub fn send_frame_client(
+            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            let mut d = [42; 65535];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            frame.to_bytes(&mut b)?;
+
+            let off = b.off();
+            self.pipe.client.stream_send(stream_id, &d[..off], fin)?;
+
+            self.advance().ok();
 
-                    return Err(Error::IdError);
-                }
+            Ok(())
+        }
 
-                self.max_push_id = push_id;
-            },
+        /// Send an HTTP/3 DATAGRAM with default data from the client.
+        ///
+        /// On success it returns the data.
+        pub fn send_dgram_client(&mut self, flow_id: u64) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
 
-            frame::Frame::PushPromise { .. } => {
-                if self.is_server {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PUSH_PROMISE received by server",
-                    )?;
+            self.client
+                .send_dgram(&mut self.pipe.client, flow_id, &bytes)?;
 
-                    return Err(Error::FrameUnexpected);
-                }
+            self.advance().ok();
 
-                if stream_id % 4 != 0 {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"PUSH_PROMISE received on non-request stream",
-                    )?;
+            Ok(bytes)
+        }
 
-                    return Err(Error::FrameUnexpected);
-                }
+        /// Receives an HTTP/3 DATAGRAM from the server.
+        ///
+        /// On success it returns the DATAGRAM length, flow ID and flow ID
+        /// length.
+        pub fn recv_dgram_client(
+            &mut self, buf: &mut [u8],
+        ) -> Result<(usize, u64, usize)> {
+            self.client.recv_dgram(&mut self.pipe.client, buf)
+        }
 
-                // TODO: implement more checks and PUSH_PROMISE event
-            },
+        /// Send an HTTP/3 DATAGRAM with default data from the server
+        ///
+        /// On success it returns the data.
+        pub fn send_dgram_server(&mut self, flow_id: u64) -> Result<Vec<u8>> {
+            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
 
-            frame::Frame::CancelPush { .. } => {
-                if Some(stream_id) != self.peer_control_stream_id {
-                    conn.close(
-                        true,
-                        Error::FrameUnexpected.to_wire(),
-                        b"CANCEL_PUSH received on non-control stream",
-                    )?;
+            self.server
+                .send_dgram(&mut self.pipe.server, flow_id, &bytes)?;
 
-                    return Err(Error::FrameUnexpected);
-                }
+            self.advance().ok();
 
-                // TODO: implement CANCEL_PUSH frame
-            },
+            Ok(bytes)
+        }
 
-            frame::Frame::Unknown => (),
+        /// Receives an HTTP/3 DATAGRAM from the client.
+        ///
+        /// On success it returns the DATAGRAM length, flow ID and flow ID
+        /// length.
+        pub fn recv_dgram_server(
+            &mut self, buf: &mut [u8],
+        ) -> Result<(usize, u64, usize)> {
+            self.server.recv_dgram(&mut self.pipe.server, buf)
         }
 
-        Err(Error::Done)
-    }
-}
+        /// Sends a single HTTP/3 frame from the server.
+        pub fn send_frame_server(
+            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            let mut d = [42; 65535];
 
-/// Generates an HTTP/3 GREASE variable length integer.
-fn grease_value() -> u64 {
-    let n = super::rand::rand_u64_uniform(148_764_065_110_560_899);
-    31 * n + 33
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+
+            frame.to_bytes(&mut b)?;
+
+            let off = b.off();
+            self.pipe.server.stream_send(stream_id, &d[..off], fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+
+        /// Sends an arbitrary buffer of HTTP/3 stream data from the client.
+        pub fn send_arbitrary_stream_data_client(
+            &mut self, data: &[u8], stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            self.pipe.client.stream_send(stream_id, data, fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+
+        /// Sends an arbitrary buffer of HTTP/3 stream data from the server.
+        pub fn send_arbitrary_stream_data_server(
+            &mut self, data: &[u8], stream_id: u64, fin: bool,
+        ) -> Result<()> {
+            self.pipe.server.stream_send(stream_id, data, fin)?;
+
+            self.advance().ok();
+
+            Ok(())
+        }
+    }
 }
 
-#[doc(hidden)]
-pub mod testing {
+#[cfg(test)]
+mod tests {
     use super::*;
 
-    use crate::testing;
+    use super::testing::*;
 
-    /// Session is an HTTP/3 test helper structure. It holds a client, server
-    /// and pipe that allows them to communicate.
-    ///
-    /// `default()` creates a session with some sensible default
-    /// configuration. `with_configs()` allows for providing a specific
-    /// configuration.
-    ///
-    /// `handshake()` performs all the steps needed to establish an HTTP/3
-    /// connection.
-    ///
-    /// Some utility functions are provided that make it less verbose to send
-    /// request, responses and individual headers. The full quiche API remains
-    /// available for any test that need to do unconventional things (such as
-    /// bad behaviour that triggers errors).
-    pub struct Session {
-        pub pipe: testing::Pipe,
-        pub client: Connection,
-        pub server: Connection,
+    #[test]
+    /// Make sure that random GREASE values is within the specified limit.
+    fn grease_value_in_varint_limit() {
+        assert!(grease_value() < 2u64.pow(62) - 1);
     }
 
-    impl Session {
-        pub fn default() -> Result<Session> {
-            let mut config = crate::Config::new(crate::PROTOCOL_VERSION)?;
-            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
-            config.load_priv_key_from_pem_file("examples/cert.key")?;
-            config.set_application_protos(b"\x02h3")?;
-            config.set_initial_max_data(1500);
-            config.set_initial_max_stream_data_bidi_local(150);
-            config.set_initial_max_stream_data_bidi_remote(150);
-            config.set_initial_max_stream_data_uni(150);
-            config.set_initial_max_streams_bidi(5);
-            config.set_initial_max_streams_uni(5);
-            config.verify_peer(false);
-            config.enable_dgram(true, 3, 3);
-            config.set_ack_delay_exponent(8);
+    #[test]
+    /// Send a request with no body, get a response with no body.
+    fn request_no_body_response_no_body() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-            let h3_config = Config::new()?;
-            Session::with_configs(&mut config, &h3_config)
-        }
+        let (stream, req) = s.send_request(true).unwrap();
 
-        pub fn with_configs(
-            config: &mut crate::Config, h3_config: &Config,
-        ) -> Result<Session> {
-            let pipe = testing::Pipe::with_config(config)?;
-            let client_dgram = pipe.client.dgram_enabled();
-            let server_dgram = pipe.server.dgram_enabled();
-            Ok(Session {
-                pipe,
-                client: Connection::new(&h3_config, false, client_dgram)?,
-                server: Connection::new(&h3_config, true, server_dgram)?,
-            })
-        }
+        assert_eq!(stream, 0);
 
-        /// Do the HTTP/3 handshake so both ends are in sane initial state.
-        pub fn handshake(&mut self) -> Result<()> {
-            self.pipe.handshake()?;
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
 
-            // Client streams.
-            self.client.send_settings(&mut self.pipe.client)?;
-            self.pipe.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-            self.client
-                .open_qpack_encoder_stream(&mut self.pipe.client)?;
-            self.pipe.advance().ok();
+        let resp = s.send_response(stream, true).unwrap();
 
-            self.client
-                .open_qpack_decoder_stream(&mut self.pipe.client)?;
-            self.pipe.advance().ok();
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
 
-            if self.pipe.client.grease {
-                self.client.open_grease_stream(&mut self.pipe.client)?;
-            }
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-            self.pipe.advance().ok();
+    #[test]
+    /// Send a request with no body, get a response with one DATA frame.
+    fn request_no_body_response_one_chunk() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-            // Server streams.
-            self.server.send_settings(&mut self.pipe.server)?;
-            self.pipe.advance().ok();
+        let (stream, req) = s.send_request(true).unwrap();
+        assert_eq!(stream, 0);
 
-            self.server
-                .open_qpack_encoder_stream(&mut self.pipe.server)?;
-            self.pipe.advance().ok();
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a request with no body, get a response with multiple DATA frames.
+    fn request_no_body_response_many_chunks() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-            self.server
-                .open_qpack_decoder_stream(&mut self.pipe.server)?;
-            self.pipe.advance().ok();
+        let (stream, req) = s.send_request(true).unwrap();
 
-            if self.pipe.server.grease {
-                self.server.open_grease_stream(&mut self.pipe.server)?;
-            }
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
 
-            self.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-            while self.client.poll(&mut self.pipe.client).is_ok() {
-                // Do nothing.
-            }
+        let total_data_frames = 4;
 
-            while self.server.poll(&mut self.pipe.server).is_ok() {
-                // Do nothing.
-            }
+        let resp = s.send_response(stream, false).unwrap();
 
-            Ok(())
+        for _ in 0..total_data_frames - 1 {
+            s.send_body_server(stream, false).unwrap();
         }
 
-        /// Advances the session pipe over the buffer.
-        pub fn advance(&mut self) -> crate::Result<()> {
-            self.pipe.advance()
-        }
+        let body = s.send_body_server(stream, true).unwrap();
 
-        /// Polls the client for events.
-        pub fn poll_client(&mut self) -> Result<(u64, Event)> {
-            self.client.poll(&mut self.pipe.client)
-        }
+        let mut recv_buf = vec![0; body.len()];
 
-        /// Polls the server for events.
-        pub fn poll_server(&mut self) -> Result<(u64, Event)> {
-            self.server.poll(&mut self.pipe.server)
-        }
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
 
-        /// Sends a request from client with default headers.
-        ///
-        /// On success it returns the newly allocated stream and the headers.
-        pub fn send_request(&mut self, fin: bool) -> Result<(u64, Vec<Header>)> {
-            let req = vec![
-                Header::new(b":method", b"GET"),
-                Header::new(b":scheme", b"https"),
-                Header::new(b":authority", b"quic.tech"),
-                Header::new(b":path", b"/test"),
-                Header::new(b"user-agent", b"quiche-test"),
-            ];
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
 
-            let stream =
-                self.client.send_request(&mut self.pipe.client, &req, fin)?;
+        for _ in 0..total_data_frames {
+            assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        }
 
-            self.advance().ok();
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-            Ok((stream, req))
-        }
+    #[test]
+    /// Send a request with one DATA frame, get a response with no body.
+    fn request_one_chunk_response_no_body() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        /// Sends a response from server with default headers.
-        ///
-        /// On success it returns the headers.
-        pub fn send_response(
-            &mut self, stream: u64, fin: bool,
-        ) -> Result<Vec<Header>> {
-            let resp = vec![
-                Header::new(b":status", b"200"),
-                Header::new(b"server", b"quiche-test"),
-            ];
+        let (stream, req) = s.send_request(false).unwrap();
 
-            self.server.send_response(
-                &mut self.pipe.server,
-                stream,
-                &resp,
-                fin,
-            )?;
+        let body = s.send_body_client(stream, true).unwrap();
 
-            self.advance().ok();
+        let mut recv_buf = vec![0; body.len()];
 
-            Ok(resp)
-        }
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
 
-        /// Sends some default payload from client.
-        ///
-        /// On success it returns the payload.
-        pub fn send_body_client(
-            &mut self, stream: u64, fin: bool,
-        ) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
 
-            self.client
-                .send_body(&mut self.pipe.client, stream, &bytes, fin)?;
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
 
-            self.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-            Ok(bytes)
-        }
+        let resp = s.send_response(stream, true).unwrap();
 
-        /// Fetches DATA payload from the server.
-        ///
-        /// On success it returns the number of bytes received.
-        pub fn recv_body_client(
-            &mut self, stream: u64, buf: &mut [u8],
-        ) -> Result<usize> {
-            self.client.recv_body(&mut self.pipe.client, stream, buf)
-        }
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
 
-        /// Sends some default payload from server.
-        ///
-        /// On success it returns the payload.
-        pub fn send_body_server(
-            &mut self, stream: u64, fin: bool,
-        ) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+    }
 
-            self.server
-                .send_body(&mut self.pipe.server, stream, &bytes, fin)?;
+    #[test]
+    /// Send a request with multiple DATA frames, get a response with no body.
+    fn request_many_chunks_response_no_body() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-            self.advance().ok();
+        let (stream, req) = s.send_request(false).unwrap();
 
-            Ok(bytes)
-        }
+        let total_data_frames = 4;
 
-        /// Fetches DATA payload from the client.
-        ///
-        /// On success it returns the number of bytes received.
-        pub fn recv_body_server(
-            &mut self, stream: u64, buf: &mut [u8],
-        ) -> Result<usize> {
-            self.server.recv_body(&mut self.pipe.server, stream, buf)
+        for _ in 0..total_data_frames - 1 {
+            s.send_body_client(stream, false).unwrap();
         }
 
-        /// Sends a single HTTP/3 frame from the client.
-        pub fn send_frame_client(
-            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            let mut d = [42; 65535];
-
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        let body = s.send_body_client(stream, true).unwrap();
 
-            frame.to_bytes(&mut b)?;
+        let mut recv_buf = vec![0; body.len()];
 
-            let off = b.off();
-            self.pipe.client.stream_send(stream_id, &d[..off], fin)?;
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
 
-            self.advance().ok();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-            Ok(())
+        for _ in 0..total_data_frames {
+            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
         }
 
-        /// Send an HTTP/3 DATAGRAM with default data from the client.
-        ///
-        /// On success it returns the data.
-        pub fn send_dgram_client(&mut self, flow_id: u64) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
-
-            self.client
-                .send_dgram(&mut self.pipe.client, flow_id, &bytes)?;
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-            self.advance().ok();
+        let resp = s.send_response(stream, true).unwrap();
 
-            Ok(bytes)
-        }
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
 
-        /// Receives an HTTP/3 DATAGRAM from the server.
-        ///
-        /// On success it returns the DATAGRAM length, flow ID and flow ID
-        /// length.
-        pub fn recv_dgram_client(
-            &mut self, buf: &mut [u8],
-        ) -> Result<(usize, u64, usize)> {
-            self.client.recv_dgram(&mut self.pipe.client, buf)
-        }
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+    }
 
-        /// Send an HTTP/3 DATAGRAM with default data from the server
-        ///
-        /// On success it returns the data.
-        pub fn send_dgram_server(&mut self, flow_id: u64) -> Result<Vec<u8>> {
-            let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    #[test]
+    /// Send a request with multiple DATA frames, get a response with one DATA
+    /// frame.
+    fn many_requests_many_chunks_response_one_chunk() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-            self.server
-                .send_dgram(&mut self.pipe.server, flow_id, &bytes)?;
+        let mut reqs = Vec::new();
 
-            self.advance().ok();
+        let (stream1, req1) = s.send_request(false).unwrap();
+        assert_eq!(stream1, 0);
+        reqs.push(req1);
 
-            Ok(bytes)
-        }
+        let (stream2, req2) = s.send_request(false).unwrap();
+        assert_eq!(stream2, 4);
+        reqs.push(req2);
 
-        /// Receives an HTTP/3 DATAGRAM from the client.
-        ///
-        /// On success it returns the DATAGRAM length, flow ID and flow ID
-        /// length.
-        pub fn recv_dgram_server(
-            &mut self, buf: &mut [u8],
-        ) -> Result<(usize, u64, usize)> {
-            self.server.recv_dgram(&mut self.pipe.server, buf)
-        }
+        let (stream3, req3) = s.send_request(false).unwrap();
+        assert_eq!(stream3, 8);
+        reqs.push(req3);
 
-        /// Sends a single HTTP/3 frame from the server.
-        pub fn send_frame_server(
-            &mut self, frame: frame::Frame, stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            let mut d = [42; 65535];
+        let body = s.send_body_client(stream1, false).unwrap();
+        s.send_body_client(stream2, false).unwrap();
+        s.send_body_client(stream3, false).unwrap();
 
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        let mut recv_buf = vec![0; body.len()];
 
-            frame.to_bytes(&mut b)?;
+        // Reverse order of writes.
 
-            let off = b.off();
-            self.pipe.server.stream_send(stream_id, &d[..off], fin)?;
+        s.send_body_client(stream3, true).unwrap();
+        s.send_body_client(stream2, true).unwrap();
+        s.send_body_client(stream1, true).unwrap();
 
-            self.advance().ok();
+        for _ in 0..reqs.len() {
+            let (stream, ev) = s.poll_server().unwrap();
+            let ev_headers = Event::Headers {
+                list: reqs[(stream / 4) as usize].clone(),
+                has_body: true,
+            };
+            assert_eq!(ev, ev_headers);
+            assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+            assert_eq!(s.poll_client(), Err(Error::Done));
 
-            Ok(())
+            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+            assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         }
 
-        /// Sends an arbitrary buffer of HTTP/3 stream data from the client.
-        pub fn send_arbitrary_stream_data_client(
-            &mut self, data: &[u8], stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            self.pipe.client.stream_send(stream_id, data, fin)?;
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-            self.advance().ok();
+        let mut resps = Vec::new();
 
-            Ok(())
-        }
+        let resp1 = s.send_response(stream1, true).unwrap();
+        resps.push(resp1);
 
-        /// Sends an arbitrary buffer of HTTP/3 stream data from the server.
-        pub fn send_arbitrary_stream_data_server(
-            &mut self, data: &[u8], stream_id: u64, fin: bool,
-        ) -> Result<()> {
-            self.pipe.server.stream_send(stream_id, data, fin)?;
+        let resp2 = s.send_response(stream2, true).unwrap();
+        resps.push(resp2);
 
-            self.advance().ok();
+        let resp3 = s.send_response(stream3, true).unwrap();
+        resps.push(resp3);
 
-            Ok(())
+        for _ in 0..resps.len() {
+            let (stream, ev) = s.poll_client().unwrap();
+            let ev_headers = Event::Headers {
+                list: resps[(stream / 4) as usize].clone(),
+                has_body: false,
+            };
+            assert_eq!(ev, ev_headers);
+            assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
         }
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    use super::testing::*;
 
-    #[test]
-    /// Make sure that random GREASE values is within the specified limit.
-    fn grease_value_in_varint_limit() {
-        assert!(grease_value() < 2u64.pow(62) - 1);
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Send a request with no body, get a response with no body.
-    fn request_no_body_response_no_body() {
+    /// Send a request with no body, get a response with one DATA frame and an
+    /// empty FIN after reception from the client.
+    fn request_no_body_response_one_chunk_empty_fin() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(true).unwrap();
 
-        assert_eq!(stream, 0);
-
         let ev_headers = Event::Headers {
             list: req,
             has_body: false,
@@ -2461,21 +4697,32 @@ mod tests {
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        let resp = s.send_response(stream, true).unwrap();
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, false).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
 
         let ev_headers = Event::Headers {
             list: resp,
-            has_body: false,
+            has_body: true,
         };
 
         assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.pipe.server.stream_send(stream, &[], true), Ok(0));
+        s.advance().ok();
+
         assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Send a request with no body, get a response with one DATA frame.
-    fn request_no_body_response_one_chunk() {
+    /// Try to send DATA frames before HEADERS.
+    fn body_response_before_headers() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
@@ -2491,82 +4738,217 @@ mod tests {
 
         assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        let resp = s.send_response(stream, false).unwrap();
+        assert_eq!(
+            s.send_body_server(stream, true),
+            Err(Error::FrameUnexpected)
+        );
 
-        let body = s.send_body_server(stream, true).unwrap();
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-        let mut recv_buf = vec![0; body.len()];
+    #[test]
+    /// Try to send DATA frames on wrong streams, ensure the API returns an
+    /// error before anything hits the transport layer.
+    fn send_body_invalid_client_stream() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: true,
-        };
+        assert_eq!(s.send_body_client(0, true), Err(Error::FrameUnexpected));
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(
+            s.send_body_client(s.client.control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(
+            s.send_body_client(
+                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(
+            s.send_body_client(
+                s.client.local_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(s.client.peer_control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(
+                s.client.peer_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_client(
+                s.client.peer_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
     }
 
     #[test]
-    /// Send a request with no body, get a response with multiple DATA frames.
-    fn request_no_body_response_many_chunks() {
+    /// Try to send DATA frames on wrong streams, ensure the API returns an
+    /// error before anything hits the transport layer.
+    fn send_body_invalid_server_stream() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(true).unwrap();
+        assert_eq!(s.send_body_server(0, true), Err(Error::FrameUnexpected));
+
+        assert_eq!(
+            s.send_body_server(s.server.control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.local_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.local_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(s.server.peer_control_stream_id.unwrap(), true),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.peer_qpack_streams.encoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+
+        assert_eq!(
+            s.send_body_server(
+                s.server.peer_qpack_streams.decoder_stream_id.unwrap(),
+                true
+            ),
+            Err(Error::FrameUnexpected)
+        );
+    }
+
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the client on a valid stream.
+    fn max_push_id_from_client_good() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the client on an invalid stream.
+    fn max_push_id_from_client_bad_stream() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 2 },
+            stream,
+            false,
+        )
+        .unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
-            has_body: false,
+            has_body: true,
         };
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        let total_data_frames = 4;
-
-        let resp = s.send_response(stream, false).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-        for _ in 0..total_data_frames - 1 {
-            s.send_body_server(stream, false).unwrap();
-        }
+    #[test]
+    /// Send a sequence of MAX_PUSH_ID frames from the client that attempt to
+    /// reduce the limit.
+    fn max_push_id_from_client_limit_reduction() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        let body = s.send_body_server(stream, true).unwrap();
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 2 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        let mut recv_buf = vec![0; body.len()];
+        s.send_frame_client(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: true,
-        };
+        assert_eq!(s.poll_server(), Err(Error::IdError));
+    }
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+    #[test]
+    /// Send a MAX_PUSH_ID frame from the server, which is forbidden.
+    fn max_push_id_from_server() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        for _ in 0..total_data_frames {
-            assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
-        }
+        s.send_frame_server(
+            frame::Frame::MaxPushId { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
     }
 
     #[test]
-    /// Send a request with one DATA frame, get a response with no body.
-    fn request_one_chunk_response_no_body() {
+    /// Send a PUSH_PROMISE frame from the client, which is forbidden.
+    fn push_promise_from_client() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(false).unwrap();
 
-        let body = s.send_body_client(stream, true).unwrap();
+        let header_block = s.client.encode_header_block(stream, &req).unwrap();
 
-        let mut recv_buf = vec![0; body.len()];
+        s.send_frame_client(
+            frame::Frame::PushPromise {
+                push_id: 1,
+                header_block,
+            },
+            stream,
+            false,
+        )
+        .unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
@@ -2574,40 +4956,40 @@ mod tests {
         };
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        let resp = s.send_response(stream, true).unwrap();
+    #[test]
+    /// Send a CANCEL_PUSH frame from the client for a push that was not
+    /// promised.
+    fn cancel_push_from_client() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: false,
-        };
+        s.send_frame_client(
+            frame::Frame::CancelPush { push_id: 1 },
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::IdError));
     }
 
     #[test]
-    /// Send a request with multiple DATA frames, get a response with no body.
-    fn request_many_chunks_response_no_body() {
+    /// Send a CANCEL_PUSH frame from the client on an invalid stream.
+    fn cancel_push_from_client_bad_stream() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(false).unwrap();
 
-        let total_data_frames = 4;
-
-        for _ in 0..total_data_frames - 1 {
-            s.send_body_client(stream, false).unwrap();
-        }
-
-        let body = s.send_body_client(stream, true).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
+        s.send_frame_client(
+            frame::Frame::CancelPush { push_id: 2 },
+            stream,
+            false,
+        )
+        .unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
@@ -2615,326 +4997,580 @@ mod tests {
         };
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+    }
 
-        for _ in 0..total_data_frames {
-            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        }
+    #[test]
+    /// Send a CANCEL_PUSH frame from the server.
+    fn cancel_push_from_server() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+        s.advance().ok();
 
-        let resp = s.send_response(stream, true).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: false,
-        };
+        s.send_frame_server(
+            frame::Frame::CancelPush { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Ok((1, Event::CancelPush)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Send a request with multiple DATA frames, get a response with one DATA
-    /// frame.
-    fn many_requests_many_chunks_response_one_chunk() {
+    /// Send a CANCEL_PUSH frame from the server with a push ID larger than
+    /// the client's MAX_PUSH_ID.
+    fn cancel_push_from_server_bad_id() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let mut reqs = Vec::new();
+        s.send_frame_server(
+            frame::Frame::CancelPush { push_id: 1 },
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        let (stream1, req1) = s.send_request(false).unwrap();
-        assert_eq!(stream1, 0);
-        reqs.push(req1);
+        assert_eq!(s.poll_client(), Err(Error::IdError));
+    }
 
-        let (stream2, req2) = s.send_request(false).unwrap();
-        assert_eq!(stream2, 4);
-        reqs.push(req2);
+    #[test]
+    /// Client updates MAX_PUSH_ID, which can't be reduced.
+    fn send_max_push_id() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        let (stream3, req3) = s.send_request(false).unwrap();
-        assert_eq!(stream3, 8);
-        reqs.push(req3);
+        assert_eq!(s.client.send_max_push_id(&mut s.pipe.client, 2), Ok(()));
+        assert_eq!(s.client.send_max_push_id(&mut s.pipe.client, 4), Ok(()));
+        assert_eq!(
+            s.client.send_max_push_id(&mut s.pipe.client, 3),
+            Err(Error::IdError)
+        );
 
-        let body = s.send_body_client(stream1, false).unwrap();
-        s.send_body_client(stream2, false).unwrap();
-        s.send_body_client(stream3, false).unwrap();
+        assert_eq!(
+            s.server.send_max_push_id(&mut s.pipe.server, 1),
+            Err(Error::FrameUnexpected)
+        );
 
-        let mut recv_buf = vec![0; body.len()];
+        s.advance().ok();
 
-        // Reverse order of writes.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.server.peer_max_push_id, Some(4));
+    }
 
-        s.send_body_client(stream3, true).unwrap();
-        s.send_body_client(stream2, true).unwrap();
-        s.send_body_client(stream1, true).unwrap();
+    #[test]
+    /// Server pushes a response to the client.
+    fn server_push() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        for _ in 0..reqs.len() {
-            let (stream, ev) = s.poll_server().unwrap();
-            let ev_headers = Event::Headers {
-                list: reqs[(stream / 4) as usize].clone(),
-                has_body: true,
-            };
-            assert_eq!(ev, ev_headers);
-            assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-            assert_eq!(s.poll_client(), Err(Error::Done));
+        s.client.send_max_push_id(&mut s.pipe.client, 0).unwrap();
 
-            assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-            assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        }
+        let (stream, req) = s.send_request(true).unwrap();
 
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req,
+                has_body: false
+            }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let mut resps = Vec::new();
-
-        let resp1 = s.send_response(stream1, true).unwrap();
-        resps.push(resp1);
+        let push_req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/style.css"),
+        ];
 
-        let resp2 = s.send_response(stream2, true).unwrap();
-        resps.push(resp2);
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Ok(0)
+        );
 
-        let resp3 = s.send_response(stream3, true).unwrap();
-        resps.push(resp3);
+        // No more push IDs are available.
+        assert_eq!(
+            s.server
+                .send_push_promise(&mut s.pipe.server, stream, &push_req),
+            Err(Error::IdError)
+        );
 
-        for _ in 0..resps.len() {
-            let (stream, ev) = s.poll_client().unwrap();
-            let ev_headers = Event::Headers {
-                list: resps[(stream / 4) as usize].clone(),
-                has_body: false,
-            };
-            assert_eq!(ev, ev_headers);
-            assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        }
+        s.advance().ok();
 
+        assert_eq!(
+            s.poll_client(),
+            Ok((stream, Event::PushPromise {
+                push_id: 0,
+                list: push_req,
+            }))
+        );
         assert_eq!(s.poll_client(), Err(Error::Done));
-    }
-
-    #[test]
-    /// Send a request with no body, get a response with one DATA frame and an
-    /// empty FIN after reception from the client.
-    fn request_no_body_response_one_chunk_empty_fin() {
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
-
-        let (stream, req) = s.send_request(true).unwrap();
-
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: false,
-        };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        let resp = vec![
+            Header::new(b":status", b"200"),
+            Header::new(b"server", b"quiche-test"),
+        ];
 
-        let resp = s.send_response(stream, false).unwrap();
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, 0, &resp, false)
+            .unwrap();
 
-        let body = s.send_body_server(stream, false).unwrap();
+        assert_eq!(push_stream % 4, 3);
 
-        let mut recv_buf = vec![0; body.len()];
+        // A single response can be pushed.
+        assert_eq!(
+            s.server
+                .send_push_response(&mut s.pipe.server, 0, &resp, false),
+            Err(Error::FrameUnexpected)
+        );
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: true,
-        };
+        let body = b"body {}";
+        assert_eq!(
+            s.server
+                .send_body(&mut s.pipe.server, push_stream, body, true),
+            Ok(body.len())
+        );
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        s.advance().ok();
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(
+            s.poll_client(),
+            Ok((push_stream, Event::Headers {
+                list: resp,
+                has_body: true
+            }))
+        );
+        assert_eq!(s.client.push_id(push_stream), Some(0));
 
-        assert_eq!(s.pipe.server.stream_send(stream, &[], true), Ok(0));
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Data)));
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        let mut recv_buf = vec![0; body.len()];
+        assert_eq!(
+            s.client
+                .recv_body(&mut s.pipe.client, push_stream, &mut recv_buf),
+            Ok(body.len())
+        );
+        assert_eq!(&recv_buf, body);
+
+        assert_eq!(s.poll_client(), Ok((push_stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Try to send DATA frames before HEADERS.
-    fn body_response_before_headers() {
+    /// Server tries to push without the client allowing it.
+    fn server_push_not_allowed() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(true).unwrap();
-        assert_eq!(stream, 0);
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: false,
-        };
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req.clone(),
+                has_body: false
+            }))
+        );
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Err(Error::IdError)
+        );
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(
+            s.server
+                .send_push_response(&mut s.pipe.server, 0, &req, true),
+            Err(Error::IdError)
+        );
 
+        // Clients can't push.
         assert_eq!(
-            s.send_body_server(stream, true),
+            s.client.send_push_promise(&mut s.pipe.client, stream, &req),
             Err(Error::FrameUnexpected)
         );
+    }
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+    #[test]
+    /// Server sends PUSH_PROMISE with a push ID larger than the client's
+    /// MAX_PUSH_ID.
+    fn push_promise_bad_id() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
+
+        let header_block = s.server.encode_header_block(stream, &req).unwrap();
+
+        s.send_frame_server(
+            frame::Frame::PushPromise {
+                push_id: 2,
+                header_block,
+            },
+            stream,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_client(), Err(Error::IdError));
     }
 
     #[test]
-    /// Try to send DATA frames on wrong streams, ensure the API returns an
-    /// error before anything hits the transport layer.
-    fn send_body_invalid_client_stream() {
+    /// Client cancels a promised push.
+    fn cancel_push_from_client_promised() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        assert_eq!(s.send_body_client(0, true), Err(Error::FrameUnexpected));
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
 
-        assert_eq!(
-            s.send_body_client(s.client.control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
-        );
+        let (stream, req) = s.send_request(true).unwrap();
 
         assert_eq!(
-            s.send_body_client(
-                s.client.local_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req.clone(),
+                has_body: false
+            }))
         );
 
         assert_eq!(
-            s.send_body_client(
-                s.client.local_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Ok(0)
         );
 
+        s.advance().ok();
+
         assert_eq!(
-            s.send_body_client(s.client.peer_control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            s.poll_client(),
+            Ok((stream, Event::PushPromise {
+                push_id: 0,
+                list: req.clone(),
+            }))
         );
 
+        assert_eq!(s.client.cancel_push(&mut s.pipe.client, 0), Ok(()));
         assert_eq!(
-            s.send_body_client(
-                s.client.peer_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.client.cancel_push(&mut s.pipe.client, 2),
+            Err(Error::IdError)
         );
 
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::CancelPush)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
         assert_eq!(
-            s.send_body_client(
-                s.client.peer_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.server
+                .send_push_response(&mut s.pipe.server, 0, &req, true),
+            Err(Error::RequestCancelled)
         );
     }
 
     #[test]
-    /// Try to send DATA frames on wrong streams, ensure the API returns an
-    /// error before anything hits the transport layer.
-    fn send_body_invalid_server_stream() {
+    /// Server cancels a promised push before sending the response.
+    fn cancel_push_from_server_promised() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        assert_eq!(s.send_body_server(0, true), Err(Error::FrameUnexpected));
+        s.client.send_max_push_id(&mut s.pipe.client, 1).unwrap();
+
+        let (stream, req) = s.send_request(true).unwrap();
 
         assert_eq!(
-            s.send_body_server(s.server.control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req.clone(),
+                has_body: false
+            }))
         );
 
         assert_eq!(
-            s.send_body_server(
-                s.server.local_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.server.cancel_push(&mut s.pipe.server, 0),
+            Err(Error::IdError)
         );
 
         assert_eq!(
-            s.send_body_server(
-                s.server.local_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Ok(0)
         );
+        assert_eq!(s.server.cancel_push(&mut s.pipe.server, 0), Ok(()));
+
+        s.advance().ok();
 
+        assert_eq!(s.poll_client(), Ok((0, Event::CancelPush)));
         assert_eq!(
-            s.send_body_server(s.server.peer_control_stream_id.unwrap(), true),
-            Err(Error::FrameUnexpected)
+            s.poll_client(),
+            Ok((stream, Event::PushPromise {
+                push_id: 0,
+                list: req,
+            }))
         );
+    }
+
+    #[test]
+    /// Send a GOAWAY frame from the client.
+    fn goaway_from_client_good() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_goaway(&mut s.pipe.client, 100).unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((100, Event::GoAway)));
+    }
 
+    #[test]
+    /// Server can't push with push IDs at or above the client's GOAWAY ID.
+    fn goaway_from_client_push() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        s.client.send_max_push_id(&mut s.pipe.client, 10).unwrap();
+        s.client.send_goaway(&mut s.pipe.client, 1).unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((1, Event::GoAway)));
         assert_eq!(
-            s.send_body_server(
-                s.server.peer_qpack_streams.encoder_stream_id.unwrap(),
-                true
-            ),
-            Err(Error::FrameUnexpected)
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req.clone(),
+                has_body: true
+            }))
         );
 
         assert_eq!(
-            s.send_body_server(
-                s.server.peer_qpack_streams.decoder_stream_id.unwrap(),
-                true
-            ),
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
+            Ok(0)
+        );
+        assert_eq!(
+            s.server.send_push_promise(&mut s.pipe.server, stream, &req),
             Err(Error::FrameUnexpected)
         );
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the client on a valid stream.
-    fn max_push_id_from_client_good() {
+    fn priority_parse() {
+        assert_eq!(Priority::parse(b"u=5, i"), Priority::new(5, true));
+        assert_eq!(Priority::parse(b"i=?0,u=1"), Priority::new(1, false));
+        assert_eq!(Priority::parse(b"i=?1"), Priority::new(3, true));
+
+        // Out of range urgency is clamped to the lowest.
+        assert_eq!(Priority::parse(b"u=9"), Priority::new(7, false));
+        assert_eq!(Priority::parse(b"u=-1"), Priority::new(7, false));
+        assert_eq!(Priority::parse(b"u=a"), Priority::new(7, false));
+
+        assert_eq!(Priority::parse(b"x=1"), Priority::default());
+        assert_eq!(Priority::parse(b"\xff"), Priority::default());
+    }
+
+    #[test]
+    /// Client sends a request with a priority header, which the server uses
+    /// for the response.
+    fn request_with_priority() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+            Header::new(b"priority", b"u=2"),
+        ];
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let stream = s
+            .client
+            .send_request_with_priority(
+                &mut s.pipe.client,
+                &req,
+                Priority::new(5, true),
+                true,
+            )
+            .unwrap();
+
+        s.advance().ok();
+
+        let mut expected = req[..4].to_vec();
+        expected.push(Header::new(b"priority", b"u=5, i"));
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: expected,
+                has_body: false
+            }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        s.send_response(stream, false).unwrap();
+
+        let quic_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(quic_stream.urgency, 5 + PRIORITY_URGENCY_OFFSET);
+        assert!(quic_stream.incremental);
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the client on an invalid stream.
-    fn max_push_id_from_client_bad_stream() {
+    /// Client changes the priority of a request using PRIORITY_UPDATE.
+    fn priority_update_request() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        let (stream, req) = s.send_request(true).unwrap();
 
-        s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 2 },
-            stream,
-            false,
-        )
-        .unwrap();
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req,
+                has_body: false
+            }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
+        let priority = Priority::new(1, true);
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        assert_eq!(
+            s.client.send_priority_update_for_request(
+                &mut s.pipe.client,
+                stream,
+                priority
+            ),
+            Ok(())
+        );
+
+        s.advance().ok();
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::PriorityUpdate(priority)))
+        );
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.send_response(stream, false).unwrap();
+
+        let quic_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(quic_stream.urgency, 1 + PRIORITY_URGENCY_OFFSET);
+        assert!(quic_stream.incremental);
+
+        // The priority can be changed again while the response is sent.
+        let priority = Priority::new(6, false);
+
+        s.client
+            .send_priority_update_for_request(
+                &mut s.pipe.client,
+                stream,
+                priority,
+            )
+            .unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::PriorityUpdate(priority)))
+        );
+
+        s.server
+            .set_stream_priority(&mut s.pipe.server, stream, priority)
+            .unwrap();
+
+        let quic_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(quic_stream.urgency, 6 + PRIORITY_URGENCY_OFFSET);
+        assert!(!quic_stream.incremental);
     }
 
     #[test]
-    /// Send a sequence of MAX_PUSH_ID frames from the client that attempt to
-    /// reduce the limit.
-    fn max_push_id_from_client_limit_reduction() {
+    /// Server processes a PRIORITY_UPDATE frame before the headers of the
+    /// request it applies to.
+    fn priority_update_before_request() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
+        let stream = s.client.next_request_stream_id;
+
         s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 2 },
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: stream,
+                priority_field_value: b"u=1, i".to_vec(),
+            },
             s.client.control_stream_id.unwrap(),
             false,
         )
         .unwrap();
 
+        // The update is not reported for a request the server hasn't seen.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(
+            s.server.set_stream_priority(
+                &mut s.pipe.server,
+                stream,
+                Priority::new(1, true)
+            ),
+            Ok(())
+        );
+
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
+
+        s.client
+            .send_request_with_priority(
+                &mut s.pipe.client,
+                &req,
+                Priority::new(5, false),
+                true,
+            )
+            .unwrap();
+        s.advance().ok();
+
+        let mut expected = req.clone();
+        expected.push(Header::new(b"priority", b"u=5"));
+
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: expected,
+                has_body: false
+            }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // The update takes precedence over the request header.
+        s.send_response(stream, false).unwrap();
+
+        let quic_stream = s.pipe.server.streams.get(stream).unwrap();
+        assert_eq!(quic_stream.urgency, 1 + PRIORITY_URGENCY_OFFSET);
+        assert!(quic_stream.incremental);
+    }
+
+    #[test]
+    /// Client sends a PRIORITY_UPDATE frame for a request stream above the
+    /// stream limit.
+    fn priority_update_above_stream_limit() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
         s.send_frame_client(
-            frame::Frame::MaxPushId { push_id: 1 },
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: 4 * 5,
+                priority_field_value: b"u=1".to_vec(),
+            },
             s.client.control_stream_id.unwrap(),
             false,
         )
@@ -2944,118 +5580,163 @@ mod tests {
     }
 
     #[test]
-    /// Send a MAX_PUSH_ID frame from the server, which is forbidden.
-    fn max_push_id_from_server() {
+    /// PRIORITY_UPDATE frames can only be sent by clients, for requests that
+    /// were sent.
+    fn send_priority_update_invalid() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::MaxPushId { push_id: 1 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        let (stream, _) = s.send_request(true).unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
+        assert_eq!(
+            s.client.send_priority_update_for_request(
+                &mut s.pipe.client,
+                stream + 4,
+                Priority::default()
+            ),
+            Err(Error::IdError)
+        );
+
+        assert_eq!(
+            s.client.send_priority_update_for_push(
+                &mut s.pipe.client,
+                0,
+                Priority::default()
+            ),
+            Err(Error::IdError)
+        );
+
+        assert_eq!(
+            s.server.send_priority_update_for_request(
+                &mut s.pipe.server,
+                stream,
+                Priority::default()
+            ),
+            Err(Error::FrameUnexpected)
+        );
     }
 
     #[test]
-    /// Send a PUSH_PROMISE frame from the client, which is forbidden.
-    fn push_promise_from_client() {
+    /// Client sends a PRIORITY_UPDATE frame for a non-request stream.
+    fn priority_update_bad_id() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
-
-        let header_block = s.client.encode_header_block(&req).unwrap();
-
         s.send_frame_client(
-            frame::Frame::PushPromise {
-                push_id: 1,
-                header_block,
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: 1,
+                priority_field_value: b"u=1".to_vec(),
             },
-            stream,
+            s.client.control_stream_id.unwrap(),
             false,
         )
         .unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
-
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
+        assert_eq!(s.poll_server(), Err(Error::IdError));
     }
 
     #[test]
-    /// Send a CANCEL_PUSH frame from the client.
-    fn cancel_push_from_client() {
+    /// Server sends a PRIORITY_UPDATE frame.
+    fn priority_update_from_server() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_client(
-            frame::Frame::CancelPush { push_id: 1 },
-            s.client.control_stream_id.unwrap(),
+        s.send_frame_server(
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: 0,
+                priority_field_value: b"u=1".to_vec(),
+            },
+            s.server.control_stream_id.unwrap(),
             false,
         )
         .unwrap();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.poll_client(), Err(Error::FrameUnexpected));
     }
 
     #[test]
-    /// Send a CANCEL_PUSH frame from the client on an invalid stream.
-    fn cancel_push_from_client_bad_stream() {
+    /// Client sends a PRIORITY_UPDATE frame on a request stream.
+    fn priority_update_on_request_stream() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
         let (stream, req) = s.send_request(false).unwrap();
 
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req,
+                has_body: true
+            }))
+        );
+
         s.send_frame_client(
-            frame::Frame::CancelPush { push_id: 2 },
+            frame::Frame::PriorityUpdateRequest {
+                prioritized_element_id: stream,
+                priority_field_value: b"u=1".to_vec(),
+            },
             stream,
             false,
         )
         .unwrap();
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
-
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Err(Error::FrameUnexpected));
     }
 
     #[test]
-    /// Send a CANCEL_PUSH frame from the client.
-    fn cancel_push_from_server() {
+    /// Client changes the priority of a pushed response before it is sent.
+    fn priority_update_push() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        s.send_frame_server(
-            frame::Frame::CancelPush { push_id: 1 },
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        s.client.send_max_push_id(&mut s.pipe.client, 0).unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
-    }
+        let (stream, req) = s.send_request(true).unwrap();
 
-    #[test]
-    /// Send a GOAWAY frame from the client.
-    fn goaway_from_client_good() {
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream, Event::Headers {
+                list: req.clone(),
+                has_body: false
+            }))
+        );
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        s.client.send_goaway(&mut s.pipe.client, 100).unwrap();
+        let push_id = s
+            .server
+            .send_push_promise(&mut s.pipe.server, stream, &req)
+            .unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(
+            s.poll_client(),
+            Ok((stream, Event::PushPromise { push_id, list: req }))
+        );
+
+        s.client
+            .send_priority_update_for_push(
+                &mut s.pipe.client,
+                push_id,
+                Priority::new(6, true),
+            )
+            .unwrap();
 
         s.advance().ok();
 
-        // TODO: server push
-        assert_eq!(s.poll_server(), Ok((0, Event::GoAway)));
+        // The push stream isn't open yet.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let resp = vec![Header::new(b":status", b"200")];
+
+        let push_stream = s
+            .server
+            .send_push_response(&mut s.pipe.server, push_id, &resp, true)
+            .unwrap();
+
+        let quic_stream = s.pipe.server.streams.get(push_stream).unwrap();
+        assert_eq!(quic_stream.urgency, 6 + PRIORITY_URGENCY_OFFSET);
+        assert!(quic_stream.incremental);
     }
 
     #[test]
@@ -3206,67 +5887,295 @@ mod tests {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let mut qpack_stream_closed = false;
+        let mut qpack_stream_closed = false;
+
+        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
+
+        // Set Dynamic Table Capacity=0 instruction.
+        let d = [0x20; 1];
+
+        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
+        s.pipe.client.stream_send(stream_id, &d, true).unwrap();
+
+        s.advance().ok();
+
+        loop {
+            match s.server.poll(&mut s.pipe.server) {
+                Ok(_) => (),
+
+                Err(Error::Done) => {
+                    break;
+                },
+
+                Err(Error::ClosedCriticalStream) => {
+                    qpack_stream_closed = true;
+                    break;
+                },
+
+                Err(_) => (),
+            }
+        }
+
+        assert!(qpack_stream_closed);
+    }
+
+    #[test]
+    /// Client sends QPACK data.
+    fn qpack_data() {
+        // The dynamic table is disabled, so only instructions that don't
+        // reference it are sent, and we just test that the data is safely
+        // processed.
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let e_stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
+        let d_stream_id = s.client.local_qpack_streams.decoder_stream_id.unwrap();
+
+        // Set Dynamic Table Capacity instructions, within the limit of 0, and
+        // Stream Cancellation instructions.
+        let e = [0x20; 20];
+        let d = [0x40; 20];
+
+        s.pipe.client.stream_send(e_stream_id, &e, false).unwrap();
+        s.advance().ok();
+
+        s.pipe.client.stream_send(d_stream_id, &d, false).unwrap();
+        s.advance().ok();
+
+        loop {
+            match s.server.poll(&mut s.pipe.server) {
+                Ok(_) => (),
+
+                Err(Error::Done) => {
+                    break;
+                },
+
+                Err(_) => {
+                    panic!();
+                },
+            }
+        }
+    }
+
+    #[test]
+    /// Client sends invalid QPACK encoder instructions.
+    fn qpack_encoder_stream_error() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let e_stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
+
+        // Duplicate instruction referencing an entry that doesn't exist.
+        s.pipe
+            .client
+            .stream_send(e_stream_id, &[0x00], false)
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::QpackEncoderStreamError));
+    }
+
+    /// Creates a session with the given QPACK dynamic table settings, and
+    /// completes the handshake.
+    fn qpack_session(max_table_capacity: u64, blocked_streams: u64) -> Session {
+        let mut h3_config = Config::new().unwrap();
+        h3_config.set_qpack_max_table_capacity(max_table_capacity);
+        h3_config.set_qpack_blocked_streams(blocked_streams);
+
+        let mut s = Session::with_h3_config(&h3_config).unwrap();
+        s.handshake().unwrap();
+
+        s
+    }
+
+    #[test]
+    /// Client sends headers referencing the QPACK dynamic table, before the
+    /// encoder instructions inserting the entries.
+    fn qpack_dynamic_table_blocked() {
+        let mut s = qpack_session(220, 1);
+
+        let e_stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
+
+        // Required Insert Count = 2, Base = 0, followed by two Indexed Field
+        // Lines With Post-Base Index.
+        let header_block = vec![0x03, 0x81, 0x10, 0x11];
+
+        s.send_frame_client(frame::Frame::Headers { header_block }, 0, true)
+            .unwrap();
+
+        // The stream is blocked until the dynamic table entries are received.
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Set Dynamic Table Capacity=220, followed by two Insert With Name
+        // Reference instructions.
+        let instructions = [
+            0x3f, 0xbd, 0x01, 0xc0, 0x0f, 0x77, 0x77, 0x77, 0x2e, 0x65, 0x78,
+            0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0xc1, 0x0c,
+            0x2f, 0x73, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2f, 0x70, 0x61, 0x74,
+            0x68,
+        ];
+
+        s.pipe
+            .client
+            .stream_send(e_stream_id, &instructions, false)
+            .unwrap();
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: vec![
+                Header::new(b":authority", b"www.example.com"),
+                Header::new(b":path", b"/sample/path"),
+            ],
+            has_body: false,
+        };
+
+        assert_eq!(s.poll_server(), Ok((0, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((0, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.advance().ok();
+
+        // The server sent an Insert Count Increment and a Section
+        // Acknowledgment on its decoder stream.
+        let d_stream_id = s.server.local_qpack_streams.decoder_stream_id.unwrap();
+
+        let mut b = [0; 10];
+        assert_eq!(
+            s.pipe.client.stream_recv(d_stream_id, &mut b),
+            Ok((2, false))
+        );
+        assert_eq!(&b[..2], &[0x02, 0x80]);
+    }
+
+    #[test]
+    /// Client exceeds the QPACK blocked streams limit.
+    fn qpack_blocked_streams_limit() {
+        let mut s = qpack_session(220, 1);
+
+        let header_block = vec![0x03, 0x81, 0x10, 0x11];
+
+        s.send_frame_client(
+            frame::Frame::Headers {
+                header_block: header_block.clone(),
+            },
+            0,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.send_frame_client(frame::Frame::Headers { header_block }, 4, true)
+            .unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::QpackDecompressionFailed));
+    }
+
+    #[test]
+    /// Client resets a blocked stream, which frees its blocked stream slot.
+    fn qpack_blocked_stream_reset() {
+        let mut buf = [0; 65535];
+        let mut s = qpack_session(220, 1);
+
+        let header_block = vec![0x03, 0x81, 0x10, 0x11];
+
+        s.send_frame_client(
+            frame::Frame::Headers {
+                header_block: header_block.clone(),
+            },
+            0,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        s.pipe
+            .client
+            .stream_shutdown(0, crate::Shutdown::Write, 42)
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(s.server.blocked_streams.is_empty());
+
+        // The server sent a Stream Cancellation instruction.
+        s.advance().ok();
+
+        let d_stream_id = s.server.local_qpack_streams.decoder_stream_id.unwrap();
+        assert_eq!(
+            s.pipe.client.stream_recv(d_stream_id, &mut buf),
+            Ok((1, false))
+        );
+        assert_eq!(buf[0], 0x40);
+
+        // Another stream can now be blocked.
+        s.send_frame_client(frame::Frame::Headers { header_block }, 4, true)
+            .unwrap();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(s.server.blocked_streams.contains_key(&4));
+    }
+
+    #[test]
+    /// Client uses the QPACK dynamic table to encode request headers.
+    fn qpack_dynamic_table_encoder() {
+        let mut s = qpack_session(4096, 1);
+
+        let (stream, req) = s.send_request(true).unwrap();
 
-        let stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
-        let d = [0; 1];
+        let ev_headers = Event::Headers {
+            list: req.clone(),
+            has_body: false,
+        };
 
-        s.pipe.client.stream_send(stream_id, &d, false).unwrap();
-        s.pipe.client.stream_send(stream_id, &d, true).unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers.clone())));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
+        // The server's decoder received the entries inserted by the client.
+        assert!(s.server.qpack_decoder.insert_count() > 0);
+
+        // The client processes the server's acknowledgments.
         s.advance().ok();
+        assert_eq!(s.poll_client(), Err(Error::Done));
 
-        loop {
-            match s.server.poll(&mut s.pipe.server) {
-                Ok(_) => (),
+        // The same headers are encoded with references to the dynamic table
+        // only, making the header block smaller.
+        let header_block = s.client.encode_header_block(stream, &req).unwrap();
+        s.client.qpack_encoder.discard_last_section(stream);
 
-                Err(Error::Done) => {
-                    break;
-                },
+        assert!(s.client.qpack_encoder.pending_instructions().is_empty());
 
-                Err(Error::ClosedCriticalStream) => {
-                    qpack_stream_closed = true;
-                    break;
-                },
+        let mut static_block = [0; 256];
+        let len = qpack::Encoder::new()
+            .encode(&req, &mut static_block)
+            .unwrap();
+        assert!(header_block.len() < len);
 
-                Err(_) => (),
-            }
-        }
+        let (stream, _) = s.send_request(true).unwrap();
 
-        assert!(qpack_stream_closed);
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Client sends QPACK data.
-    fn qpack_data() {
-        // TODO: QPACK instructions are ignored until dynamic table support is
-        // added so we just test that the data is safely ignored.
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
-
-        let e_stream_id = s.client.local_qpack_streams.encoder_stream_id.unwrap();
-        let d_stream_id = s.client.local_qpack_streams.decoder_stream_id.unwrap();
-        let d = [0; 20];
+    /// Server sends invalid instructions on its QPACK decoder stream.
+    fn qpack_decoder_stream_error() {
+        let mut s = qpack_session(220, 1);
 
-        s.pipe.client.stream_send(e_stream_id, &d, false).unwrap();
-        s.advance().ok();
+        let d_stream_id = s.server.local_qpack_streams.decoder_stream_id.unwrap();
 
-        s.pipe.client.stream_send(d_stream_id, &d, false).unwrap();
+        // Insert Count Increment of 0.
+        s.pipe
+            .server
+            .stream_send(d_stream_id, &[0x00], false)
+            .unwrap();
         s.advance().ok();
 
-        loop {
-            match s.server.poll(&mut s.pipe.server) {
-                Ok(_) => (),
-
-                Err(Error::Done) => {
-                    break;
-                },
-
-                Err(_) => {
-                    panic!();
-                },
-            }
-        }
+        assert_eq!(s.poll_client(), Err(Error::QpackDecoderStreamError));
     }
 
     #[test]
@@ -3484,53 +6393,298 @@ mod tests {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let frame_type = b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+
+        let frame_len = b.put_varint(5).unwrap();
+        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+
+        s.pipe.client.stream_send(0, b"hello", false).unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(
+            s.server.poll(&mut s.pipe.server),
+            Err(Error::FrameUnexpected)
+        );
+    }
+
+    #[test]
+    /// Tests that calling poll() after an error occured does nothing.
+    fn poll_after_error() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
+        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+
+        let frame_len = b.put_varint(1 << 24).unwrap();
+        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+
+        s.pipe.client.stream_send(0, &d, false).unwrap();
+
+        s.advance().ok();
+
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::InternalError));
+
+        // Try to call poll() again after an error occurred.
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
+    }
+
+    #[test]
+    /// Tests that we limit sending HEADERS based on the stream capacity.
+    fn headers_blocked() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(b"\x02h3").unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+
+        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
+
+        s.handshake().unwrap();
+
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
+
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
+
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Err(Error::StreamBlocked)
+        );
+
+        s.advance().ok();
+
+        // Once the server gives flow control credits back, we can send the
+        // request.
+        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(4));
+    }
+
+    #[test]
+    /// Test handling of 0-length DATA writes with and without fin.
+    fn zero_length_data() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, 0, b"", false),
+            Err(Error::Done)
+        );
+        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
+
+        s.advance().ok();
+
+        let mut recv_buf = vec![0; 100];
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let resp = s.send_response(stream, false).unwrap();
+
+        assert_eq!(
+            s.server.send_body(&mut s.pipe.server, 0, b"", false),
+            Err(Error::Done)
+        );
+        assert_eq!(s.server.send_body(&mut s.pipe.server, 0, b"", true), Ok(0));
+
+        s.advance().ok();
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Err(Error::Done));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
+
+    #[test]
+    /// Tests that blocked 0-length DATA writes are reported correctly.
+    fn zero_length_data_blocked() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(b"\x02h3").unwrap();
+        config.set_initial_max_data(69);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
+
+        let mut h3_config = Config::new().unwrap();
+
+        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
+
+        s.handshake().unwrap();
+
+        let req = vec![
+            Header::new(b":method", b"GET"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/test"),
+        ];
+
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, false),
+            Ok(0)
+        );
+
+        assert_eq!(
+            s.client.send_body(&mut s.pipe.client, 0, b"", true),
+            Err(Error::Done)
+        );
+
+        s.advance().ok();
+
+        // Once the server gives flow control credits back, we can send the body.
+        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
+    }
+
+    #[test]
+    /// Tests that receiving a H3_DATAGRAM setting is ok.
+    fn dgram_setting() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(b"\x02h3").unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.enable_dgram(true, 1000, 1000);
+        config.verify_peer(false);
+
+        let h3_config = Config::new().unwrap();
 
-        let frame_type = b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        let frame_len = b.put_varint(5).unwrap();
-        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+        s.client.send_settings(&mut s.pipe.client).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        s.pipe.client.stream_send(0, b"hello", false).unwrap();
+        // Before processing SETTINGS (via poll), HTTP/3 DATAGRAMS are not
+        // enabled.
+        assert!(!s.server.dgram_enabled_by_peer(&s.pipe.server));
 
-        s.advance().ok();
+        // When everything is ok, poll returns Done and DATAGRAM is enabled.
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
+        assert!(s.server.dgram_enabled_by_peer(&s.pipe.server));
 
-        assert_eq!(
-            s.server.poll(&mut s.pipe.server),
-            Err(Error::FrameUnexpected)
-        );
+        // Now detect things on the client
+        s.server.send_settings(&mut s.pipe.server).unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
+        assert!(!s.client.dgram_enabled_by_peer(&s.pipe.client));
+        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::Done));
+        assert!(s.client.dgram_enabled_by_peer(&s.pipe.client));
     }
 
     #[test]
-    /// Tests that calling poll() after an error occured does nothing.
-    fn poll_after_error() {
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+    /// Tests that receiving a H3_DATAGRAM setting when no TP is set generates
+    /// an error.
+    fn dgram_setting_no_tp() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config.set_application_protos(b"\x02h3").unwrap();
+        config.set_initial_max_data(70);
+        config.set_initial_max_stream_data_bidi_local(150);
+        config.set_initial_max_stream_data_bidi_remote(150);
+        config.set_initial_max_stream_data_uni(150);
+        config.set_initial_max_streams_bidi(100);
+        config.set_initial_max_streams_uni(5);
+        config.verify_peer(false);
 
-        let mut d = [42; 128];
-        let mut b = octets::OctetsMut::with_slice(&mut d);
+        let h3_config = Config::new().unwrap();
 
-        let frame_type = b.put_varint(148_764_065_110_560_899).unwrap();
-        s.pipe.client.stream_send(0, frame_type, false).unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        let frame_len = b.put_varint(1 << 24).unwrap();
-        s.pipe.client.stream_send(0, frame_len, false).unwrap();
+        s.client.control_stream_id = Some(
+            s.client
+                .open_uni_stream(
+                    &mut s.pipe.client,
+                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
+                )
+                .unwrap(),
+        );
 
-        s.pipe.client.stream_send(0, &d, false).unwrap();
+        let settings = frame::Frame::Settings {
+            max_header_list_size: None,
+            qpack_max_table_capacity: None,
+            qpack_blocked_streams: None,
+            h3_datagram: Some(1),
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
+            grease: None,
+        };
 
-        s.advance().ok();
+        s.send_frame_client(settings, s.client.control_stream_id.unwrap(), false)
+            .unwrap();
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::InternalError));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Try to call poll() again after an error occurred.
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
     }
 
     #[test]
-    /// Tests that we limit sending HEADERS based on the stream capacity.
-    fn headers_blocked() {
+    /// Tests that receiving SETTINGS with prohibited values generates an error.
+    fn settings_h2_prohibited() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -3547,91 +6701,152 @@ mod tests {
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
 
-        let mut h3_config = Config::new().unwrap();
+        let h3_config = Config::new().unwrap();
 
-        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        assert_eq!(s.pipe.handshake(), Ok(()));
 
-        s.handshake().unwrap();
+        s.client.control_stream_id = Some(
+            s.client
+                .open_uni_stream(
+                    &mut s.pipe.client,
+                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
+                )
+                .unwrap(),
+        );
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
+        s.server.control_stream_id = Some(
+            s.server
+                .open_uni_stream(
+                    &mut s.pipe.server,
+                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
+                )
+                .unwrap(),
+        );
+
+        let frame_payload_len = 2u64;
+        let settings = [
+            frame::SETTINGS_FRAME_TYPE_ID as u8,
+            frame_payload_len as u8,
+            0x2, // 0x2 is a reserved setting type
+            1,
         ];
 
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(0));
+        s.send_arbitrary_stream_data_client(
+            &settings,
+            s.client.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, true),
-            Err(Error::StreamBlocked)
-        );
+        s.send_arbitrary_stream_data_server(
+            &settings,
+            s.server.control_stream_id.unwrap(),
+            false,
+        )
+        .unwrap();
 
-        s.advance().ok();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Once the server gives flow control credits back, we can send the
-        // request.
-        assert_eq!(s.client.send_request(&mut s.pipe.client, &req, true), Ok(4));
+        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
+
+        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::SettingsError));
     }
 
     #[test]
-    /// Test handling of 0-length DATA writes with and without fin.
-    fn zero_length_data() {
+    /// Send a single DATAGRAM.
+    fn single_dgram() {
+        let mut buf = [0; 65535];
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
 
-        assert_eq!(
-            s.client.send_body(&mut s.pipe.client, 0, b"", false),
-            Err(Error::Done)
-        );
-        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
+        s.send_dgram_client(0).unwrap();
 
-        s.advance().ok();
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let mut recv_buf = vec![0; 100];
+        s.send_dgram_server(0).unwrap();
+        assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+    }
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
+    #[test]
+    /// Send multiple DATAGRAMs.
+    fn multiple_dgram() {
+        let mut buf = [0; 65535];
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let resp = s.send_response(stream, false).unwrap();
-
-        assert_eq!(
-            s.server.send_body(&mut s.pipe.server, 0, b"", false),
-            Err(Error::Done)
-        );
-        assert_eq!(s.server.send_body(&mut s.pipe.server, 0, b"", true), Ok(0));
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
 
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Err(Error::Done));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: true,
-        };
+    #[test]
+    /// Send more DATAGRAMs than the send queue allows.
+    fn multiple_dgram_overflow() {
+        let mut buf = [0; 65535];
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Err(Error::Done));
+        // Five DATAGRAMs
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Only 3 independent DATAGRAM events will fire.
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that blocked 0-length DATA writes are reported correctly.
-    fn zero_length_data_blocked() {
+    /// Send a single DATAGRAM and request. Ensure that poll continuously cycles
+    /// between the two types if the data is not read.
+    fn poll_yield_cycling() {
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -3640,46 +6855,46 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(69);
+        config.set_initial_max_data(1500);
         config.set_initial_max_stream_data_bidi_local(150);
         config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
+        config.enable_dgram(true, 100, 100);
 
         let mut h3_config = Config::new().unwrap();
-
         let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
-
         s.handshake().unwrap();
 
-        let req = vec![
-            Header::new(b":method", b"GET"),
-            Header::new(b":scheme", b"https"),
-            Header::new(b":authority", b"quic.tech"),
-            Header::new(b":path", b"/test"),
-        ];
+        // Send request followed by DATAGRAM on client side.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        assert_eq!(
-            s.client.send_request(&mut s.pipe.client, &req, false),
-            Ok(0)
-        );
+        s.send_body_client(stream, true).unwrap();
 
-        assert_eq!(
-            s.client.send_body(&mut s.pipe.client, 0, b"", true),
-            Err(Error::Done)
-        );
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
 
-        s.advance().ok();
+        s.send_dgram_client(0).unwrap();
 
-        // Once the server gives flow control credits back, we can send the body.
-        assert_eq!(s.client.send_body(&mut s.pipe.client, 0, b"", true), Ok(0));
+        // Now let's test the poll counts and yielding.
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that receiving a H3_DATAGRAM setting is ok.
-    fn dgram_setting() {
+    /// Send a single DATAGRAM and request. Ensure that poll
+    /// yield cycles and cleanly exits if data is read.
+    fn poll_yield_single_read() {
+        let mut buf = [0; 65535];
+
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -3688,92 +6903,90 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(70);
+        config.set_initial_max_data(1500);
         config.set_initial_max_stream_data_bidi_local(150);
         config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
-        config.enable_dgram(true, 1000, 1000);
         config.verify_peer(false);
+        config.enable_dgram(true, 100, 100);
 
-        let h3_config = Config::new().unwrap();
+        let mut h3_config = Config::new().unwrap();
+        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
+        s.handshake().unwrap();
 
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
+        // We'll send default data of 10 bytes on flow ID 0.
+        let result = (11, 0, 1);
 
-        s.client.send_settings(&mut s.pipe.client).unwrap();
-        assert_eq!(s.pipe.advance(), Ok(()));
+        // Send request followed by DATAGRAM on client side.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        // Before processing SETTINGS (via poll), HTTP/3 DATAGRAMS are not
-        // enabled.
-        assert!(!s.server.dgram_enabled_by_peer(&s.pipe.server));
+        let body = s.send_body_client(stream, true).unwrap();
 
-        // When everything is ok, poll returns Done and DATAGRAM is enabled.
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::Done));
-        assert!(s.server.dgram_enabled_by_peer(&s.pipe.server));
+        let mut recv_buf = vec![0; body.len()];
 
-        // Now detect things on the client
-        s.server.send_settings(&mut s.pipe.server).unwrap();
-        assert_eq!(s.pipe.advance(), Ok(()));
-        assert!(!s.client.dgram_enabled_by_peer(&s.pipe.client));
-        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::Done));
-        assert!(s.client.dgram_enabled_by_peer(&s.pipe.client));
-    }
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
 
-    #[test]
-    /// Tests that receiving a H3_DATAGRAM setting when no TP is set generates
-    /// an error.
-    fn dgram_setting_no_tp() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(70);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
+        s.send_dgram_client(0).unwrap();
 
-        let h3_config = Config::new().unwrap();
+        // Now let's test the poll counts and yielding.
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
 
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        s.client.control_stream_id = Some(
-            s.client
-                .open_uni_stream(
-                    &mut s.pipe.client,
-                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
-                )
-                .unwrap(),
-        );
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let settings = frame::Frame::Settings {
-            max_header_list_size: None,
-            qpack_max_table_capacity: None,
-            qpack_blocked_streams: None,
-            h3_datagram: Some(1),
-            grease: None,
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Send response followed by DATAGRAM on server side
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
         };
 
-        s.send_frame_client(settings, s.client.control_stream_id.unwrap(), false)
-            .unwrap();
+        s.send_dgram_server(0).unwrap();
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        // Now let's test the poll counts and yielding.
+        assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that receiving SETTINGS with prohibited values generates an error.
-    fn settings_h2_prohibited() {
+    /// Send a multiple DATAGRAMs and requests. Ensure that poll
+    /// yield cycles and cleanly exits if data is read.
+    fn poll_yield_multi_read() {
+        let mut buf = [0; 65535];
+
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         config
             .load_cert_chain_from_pem_file("examples/cert.crt")
@@ -3782,298 +6995,348 @@ mod tests {
             .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(70);
+        config.set_initial_max_data(1500);
         config.set_initial_max_stream_data_bidi_local(150);
         config.set_initial_max_stream_data_bidi_remote(150);
         config.set_initial_max_stream_data_uni(150);
         config.set_initial_max_streams_bidi(100);
         config.set_initial_max_streams_uni(5);
         config.verify_peer(false);
+        config.enable_dgram(true, 100, 100);
 
-        let h3_config = Config::new().unwrap();
-
-        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
-        assert_eq!(s.pipe.handshake(), Ok(()));
-
-        s.client.control_stream_id = Some(
-            s.client
-                .open_uni_stream(
-                    &mut s.pipe.client,
-                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
-                )
-                .unwrap(),
-        );
-
-        s.server.control_stream_id = Some(
-            s.server
-                .open_uni_stream(
-                    &mut s.pipe.server,
-                    stream::HTTP3_CONTROL_STREAM_TYPE_ID,
-                )
-                .unwrap(),
-        );
-
-        let frame_payload_len = 2u64;
-        let settings = [
-            frame::SETTINGS_FRAME_TYPE_ID as u8,
-            frame_payload_len as u8,
-            0x2, // 0x2 is a reserved setting type
-            1,
-        ];
+        let mut h3_config = Config::new().unwrap();
+        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
+        s.handshake().unwrap();
 
-        s.send_arbitrary_stream_data_client(
-            &settings,
-            s.client.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        // 10 bytes on flow ID 0 and 2.
+        let flow_0_result = (11, 0, 1);
+        let flow_2_result = (11, 2, 1);
 
-        s.send_arbitrary_stream_data_server(
-            &settings,
-            s.server.control_stream_id.unwrap(),
-            false,
-        )
-        .unwrap();
+        // Send requests followed by DATAGRAMs on client side.
+        let (stream, req) = s.send_request(false).unwrap();
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        let body = s.send_body_client(stream, true).unwrap();
 
-        assert_eq!(s.server.poll(&mut s.pipe.server), Err(Error::SettingsError));
+        let mut recv_buf = vec![0; body.len()];
 
-        assert_eq!(s.client.poll(&mut s.pipe.client), Err(Error::SettingsError));
-    }
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
 
-    #[test]
-    /// Send a single DATAGRAM.
-    fn single_dgram() {
-        let mut buf = [0; 65535];
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
+        s.send_dgram_client(2).unwrap();
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        // Now let's test the poll counts and yielding.
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
 
-        s.send_dgram_client(0).unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        s.send_dgram_server(0).unwrap();
-        assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
-    }
-
-    #[test]
-    /// Send multiple DATAGRAMs.
-    fn multiple_dgram() {
-        let mut buf = [0; 65535];
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+        // Second cycle, start to read
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
 
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        // Third cycle.
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Send response followed by DATAGRAM on server side
+        let resp = s.send_response(stream, false).unwrap();
+
+        let body = s.send_body_server(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: true,
+        };
 
         s.send_dgram_server(0).unwrap();
         s.send_dgram_server(0).unwrap();
         s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(0).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
+        s.send_dgram_server(2).unwrap();
 
         assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+
         assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+
+        // Second cycle, start to read
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+
+        assert_eq!(s.poll_client(), Err(Error::Done));
+
+        // Third cycle.
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
         assert_eq!(s.poll_client(), Err(Error::Done));
     }
 
     #[test]
-    /// Send more DATAGRAMs than the send queue allows.
-    fn multiple_dgram_overflow() {
-        let mut buf = [0; 65535];
+    /// Tests that the Finished event is not issued for streams of unknown type
+    /// (e.g. GREASE).
+    fn finished_is_for_requests() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Five DATAGRAMs
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
+        assert_eq!(s.client.open_grease_stream(&mut s.pipe.client), Ok(()));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Only 3 independent DATAGRAM events will fire.
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.poll_client(), Err(Error::Done));
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    /// Tests that streams are marked as finished only once.
+    fn finished_once() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let (stream, req) = s.send_request(false).unwrap();
+        let body = s.send_body_client(stream, true).unwrap();
+
+        let mut recv_buf = vec![0; body.len()];
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
+
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
         assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Send a single DATAGRAM and request. Ensure that poll continuously cycles
-    /// between the two types if the data is not read.
-    fn poll_yield_cycling() {
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
-            .unwrap();
-        config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
+    /// Tests that the Data event is properly re-armed.
+    fn data_event_rearm() {
+        let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
 
-        let mut h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
+        let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        // Send request followed by DATAGRAM on client side.
         let (stream, req) = s.send_request(false).unwrap();
 
-        s.send_body_client(stream, true).unwrap();
+        let mut recv_buf = vec![0; bytes.len()];
 
         let ev_headers = Event::Headers {
             list: req,
             has_body: true,
         };
 
-        s.send_dgram_client(0).unwrap();
+        // Manually send an incomplete DATA frame (i.e. the frame size is longer
+        // than the actual data sent).
+        {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        // Now let's test the poll counts and yielding.
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+            b.put_varint(bytes.len() as u64).unwrap();
+            let off = b.off();
+            s.pipe.client.stream_send(stream, &d[..off], false).unwrap();
+
+            assert_eq!(
+                s.pipe.client.stream_send(stream, &bytes[..5], false),
+                Ok(5)
+            );
+
+            s.advance().ok();
+        }
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Read the available body data.
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
+
+        // Send the remaining DATA payload.
+        assert_eq!(s.pipe.client.stream_send(stream, &bytes[5..], false), Ok(5));
+        s.advance().ok();
 
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
         assert_eq!(s.poll_server(), Err(Error::Done));
-    }
 
-    #[test]
-    /// Send a single DATAGRAM and request. Ensure that poll
-    /// yield cycles and cleanly exits if data is read.
-    fn poll_yield_single_read() {
-        let mut buf = [0; 65535];
+        // Read the rest of the body data.
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
+        // Send more data.
+        let body = s.send_body_client(stream, false).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+
+        // Send more data, then HEADERS, then more data.
+        let body = s.send_body_client(stream, false).unwrap();
+
+        let trailers = vec![Header::new(b"hello", b"world")];
+
+        s.client
+            .send_headers(&mut s.pipe.client, stream, &trailers, false)
             .unwrap();
-        config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
 
-        let mut h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
-        s.handshake().unwrap();
+        let ev_trailers = Event::Headers {
+            list: trailers,
+            has_body: true,
+        };
+
+        s.advance().ok();
 
-        // We'll send default data of 10 bytes on flow ID 0.
-        let result = (11, 0, 1);
+        s.send_body_client(stream, false).unwrap();
 
-        // Send request followed by DATAGRAM on client side.
-        let (stream, req) = s.send_request(false).unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
 
-        let body = s.send_body_client(stream, true).unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
 
-        let mut recv_buf = vec![0; body.len()];
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+
+        let (stream, req) = s.send_request(false).unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
             has_body: true,
         };
 
-        s.send_dgram_client(0).unwrap();
+        // Manually send an incomplete DATA frame (i.e. only the header is sent).
+        {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        // Now let's test the poll counts and yielding.
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+            b.put_varint(bytes.len() as u64).unwrap();
+            let off = b.off();
+            s.pipe.client.stream_send(stream, &d[..off], false).unwrap();
+
+            s.advance().ok();
+        }
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(result));
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.pipe.client.stream_send(stream, &bytes[..5], false), Ok(5));
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.advance().ok();
 
-        // Send response followed by DATAGRAM on server side
-        let resp = s.send_response(stream, false).unwrap();
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let body = s.send_body_server(stream, true).unwrap();
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
 
-        let mut recv_buf = vec![0; body.len()];
+        assert_eq!(s.pipe.client.stream_send(stream, &bytes[5..], false), Ok(5));
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: true,
-        };
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        s.send_dgram_server(0).unwrap();
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
 
-        // Now let's test the poll counts and yielding.
-        assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
+        // Buffer multiple data frames.
+        let body = s.send_body_client(stream, false).unwrap();
+        s.send_body_client(stream, false).unwrap();
+        s.send_body_client(stream, false).unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        {
+            let mut d = [42; 10];
+            let mut b = octets::OctetsMut::with_slice(&mut d);
 
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(result));
+            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
+            b.put_varint(0).unwrap();
+            let off = b.off();
+            s.pipe.client.stream_send(stream, &d[..off], true).unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+            s.advance().ok();
+        }
 
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
+        let mut recv_buf = vec![0; bytes.len() * 3];
 
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(
+            s.recv_body_server(stream, &mut recv_buf),
+            Ok(body.len() * 3)
+        );
     }
 
     #[test]
-    /// Send a multiple DATAGRAMs and requests. Ensure that poll
-    /// yield cycles and cleanly exits if data is read.
-    fn poll_yield_multi_read() {
+    /// Tests that the Datagram event is properly re-armed.
+    fn dgram_event_rearm() {
         let mut buf = [0; 65535];
 
         let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -4115,571 +7378,827 @@ mod tests {
 
         s.send_dgram_client(0).unwrap();
         s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
         s.send_dgram_client(2).unwrap();
         s.send_dgram_client(2).unwrap();
 
-        // Now let's test the poll counts and yielding.
         assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
 
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
         assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        // Second cycle, start to read
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
         assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-
-        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Third cycle.
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
         assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+
         assert_eq!(s.poll_server(), Err(Error::Done));
         assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+
         assert_eq!(s.poll_server(), Err(Error::Done));
         assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+
+        s.send_dgram_client(0).unwrap();
+        s.send_dgram_client(2).unwrap();
+
+        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
+
         assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Send response followed by DATAGRAM on server side
-        let resp = s.send_response(stream, false).unwrap();
+        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+    }
 
-        let body = s.send_body_server(stream, true).unwrap();
+    #[test]
+    fn reset_stream() {
+        let mut buf = [0; 65535];
 
-        let mut recv_buf = vec![0; body.len()];
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        // Client sends request.
+        let (stream, req) = s.send_request(false).unwrap();
 
         let ev_headers = Event::Headers {
-            list: resp,
+            list: req,
             has_body: true,
         };
 
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(0).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
-        s.send_dgram_server(2).unwrap();
+        // Server sends response and closes stream.
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.poll_client(), Ok((0, Event::Datagram)));
+        let resp = s.send_response(stream, true).unwrap();
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Data)));
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
 
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_client(), Err(Error::Done));
 
-        // Second cycle, start to read
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Client sends RESET_STREAM, closing stream.
+        let frames = [crate::frame::Frame::ResetStream {
+            stream_id: stream,
+            error_code: 42,
+            final_size: 68,
+        }];
 
-        assert_eq!(s.recv_body_client(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        let pkt_type = crate::packet::Type::Short;
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39)
+        );
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Server issues Reset event for the stream.
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        // Third cycle.
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_client(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Sending RESET_STREAM again shouldn't trigger another Reset event.
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39)
+        );
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that the Finished event is not issued for streams of unknown type
-    /// (e.g. GREASE).
-    fn finished_is_for_requests() {
+    fn reset_finished_at_server() {
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // Client sends HEADERS and doesn't fin
+        let (stream, _req) = s.send_request(false).unwrap();
+
+        // ..then Client sends RESET_STREAM
+        assert_eq!(
+            s.pipe.client.stream_shutdown(0, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
-        assert_eq!(s.client.open_grease_stream(&mut s.pipe.client), Ok(()));
         assert_eq!(s.pipe.advance(), Ok(()));
 
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        // Server receives just a reset
+        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(0))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // Client sends HEADERS and fin
+        let (stream, req) = s.send_request(true).unwrap();
+
+        // ..then Client sends RESET_STREAM
+        assert_eq!(
+            s.pipe.client.stream_shutdown(4, crate::Shutdown::Write, 0),
+            Ok(())
+        );
+
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
+
+        // Server receives headers and fin.
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
     }
 
     #[test]
-    /// Tests that streams are marked as finished only once.
-    fn finished_once() {
+    fn reset_finished_at_client() {
+        let mut buf = [0; 65535];
         let mut s = Session::default().unwrap();
         s.handshake().unwrap();
 
+        // Client sends HEADERS and doesn't fin
         let (stream, req) = s.send_request(false).unwrap();
-        let body = s.send_body_client(stream, true).unwrap();
-
-        let mut recv_buf = vec![0; body.len()];
 
         let ev_headers = Event::Headers {
             list: req,
             has_body: true,
         };
 
+        // Server receives headers.
         assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        // Server sends response and doesn't fin
+        s.send_response(stream, false).unwrap();
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-    }
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-    #[test]
-    /// Tests that the Data event is properly re-armed.
-    fn data_event_rearm() {
-        let bytes = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        // .. then Server sends RESET_STREAM
+        assert_eq!(
+            s.pipe
+                .server
+                .stream_shutdown(stream, crate::Shutdown::Write, 0),
+            Ok(())
+        );
 
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        let (stream, req) = s.send_request(false).unwrap();
+        // Client receives Reset only
+        assert_eq!(s.poll_client(), Ok((stream, Event::Reset(0))));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let mut recv_buf = vec![0; bytes.len()];
+        // Client sends headers and fin.
+        let (stream, req) = s.send_request(true).unwrap();
 
         let ev_headers = Event::Headers {
             list: req,
-            has_body: true,
+            has_body: false,
         };
 
-        // Manually send an incomplete DATA frame (i.e. the frame size is longer
-        // than the actual data sent).
-        {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        // Server receives headers and fin.
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-            b.put_varint(bytes.len() as u64).unwrap();
-            let off = b.off();
-            s.pipe.client.stream_send(stream, &d[..off], false).unwrap();
+        // Server sends response and fin
+        let resp = s.send_response(stream, true).unwrap();
 
-            assert_eq!(
-                s.pipe.client.stream_send(stream, &bytes[..5], false),
-                Ok(5)
-            );
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-            s.advance().ok();
-        }
+        // ..then Server sends RESET_STREAM
+        let frames = [crate::frame::Frame::ResetStream {
+            stream_id: stream,
+            error_code: 42,
+            final_size: 68,
+        }];
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let pkt_type = crate::packet::Type::Short;
+        assert_eq!(
+            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Ok(39)
+        );
 
-        // Read the available body data.
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
+        assert_eq!(s.pipe.advance(), Ok(()));
 
-        // Send the remaining DATA payload.
-        assert_eq!(s.pipe.client.stream_send(stream, &bytes[5..], false), Ok(5));
-        s.advance().ok();
+        let ev_headers = Event::Headers {
+            list: resp,
+            has_body: false,
+        };
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // Client receives headers and fin.
+        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+    }
 
-        // Read the rest of the body data.
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+    /// Creates a session with WebTransport enabled on both endpoints, and
+    /// completes the handshake.
+    fn webtransport_session() -> Session {
+        let mut h3_config = Config::new().unwrap();
+        h3_config.enable_webtransport(true);
 
-        // Send more data.
-        let body = s.send_body_client(stream, false).unwrap();
+        let mut s = Session::with_h3_config(&h3_config).unwrap();
+        s.handshake().unwrap();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s
+    }
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+    /// Establishes a WebTransport session, returning its ID.
+    fn webtransport_connect(s: &mut Session) -> u64 {
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"webtransport"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
 
-        // Send more data, then HEADERS, then more data.
-        let body = s.send_body_client(stream, false).unwrap();
+        let session_id = s
+            .client
+            .send_webtransport_connect(&mut s.pipe.client, &req)
+            .unwrap();
+        s.advance().ok();
 
-        let trailers = vec![Header::new(b"hello", b"world")];
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: true,
+        };
 
-        s.client
-            .send_headers(&mut s.pipe.client, stream, &trailers, false)
-            .unwrap();
+        assert_eq!(s.poll_server(), Ok((session_id, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let ev_trailers = Event::Headers {
-            list: trailers,
+        let resp = s.send_response(session_id, false).unwrap();
+
+        let ev_headers = Event::Headers {
+            list: resp,
             has_body: true,
         };
 
-        s.advance().ok();
+        assert_eq!(s.poll_client(), Ok((session_id, ev_headers)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
 
-        s.send_body_client(stream, false).unwrap();
+        session_id
+    }
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+    #[test]
+    /// Both endpoints advertise extended CONNECT and WebTransport support.
+    fn webtransport_settings() {
+        let s = webtransport_session();
+
+        assert!(s.client.extended_connect_enabled_by_peer());
+        assert!(s.server.extended_connect_enabled_by_peer());
+        assert!(s.client.webtransport_enabled_by_peer(&s.pipe.client));
+        assert!(s.server.webtransport_enabled_by_peer(&s.pipe.server));
+
+        let s = {
+            let mut s = Session::default().unwrap();
+            s.handshake().unwrap();
+            s
+        };
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_trailers)));
+        assert!(!s.client.extended_connect_enabled_by_peer());
+        assert!(!s.client.webtransport_enabled_by_peer(&s.pipe.client));
+    }
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
+    #[test]
+    /// Client establishes a session and exchanges datagrams on it.
+    fn webtransport_connect_and_dgram() {
+        let mut buf = [0; 65535];
+        let mut s = webtransport_session();
 
-        let (stream, req) = s.send_request(false).unwrap();
+        let session_id = webtransport_connect(&mut s);
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
+        // Datagrams use the session's flow ID.
+        let flow_id = session_id / 4;
 
-        // Manually send an incomplete DATA frame (i.e. only the header is sent).
-        {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        s.send_dgram_client(flow_id).unwrap();
 
-            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-            b.put_varint(bytes.len() as u64).unwrap();
-            let off = b.off();
-            s.pipe.client.stream_send(stream, &d[..off], false).unwrap();
+        assert_eq!(s.poll_server(), Ok((flow_id, Event::Datagram)));
+        assert_eq!(s.recv_dgram_server(&mut buf), Ok((11, flow_id, 1)));
+    }
 
-            s.advance().ok();
-        }
+    #[test]
+    /// Sending an extended CONNECT request for something other than
+    /// WebTransport, or when the peer doesn't support it, fails.
+    fn webtransport_connect_invalid() {
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"websocket"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let mut s = webtransport_session();
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Err(Error::Done));
+        assert_eq!(
+            s.client.send_webtransport_connect(&mut s.pipe.client, &req),
+            Err(Error::MessageError)
+        );
 
-        assert_eq!(s.pipe.client.stream_send(stream, &bytes[..5], false), Ok(5));
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        assert_eq!(
+            s.client.send_request(&mut s.pipe.client, &req, true),
+            Err(Error::MessageError)
+        );
+    }
+
+    #[test]
+    /// Server receives an extended CONNECT request without enabling it.
+    fn extended_connect_not_enabled() {
+        let mut s = Session::default().unwrap();
+        s.handshake().unwrap();
+
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"webtransport"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
 
+        let mut d = [42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let header_block = s.client.encode_header_block(0, &req).unwrap();
+
+        let frame = frame::Frame::Headers { header_block };
+        frame.to_bytes(&mut b).unwrap();
+
+        let off = b.off();
+        s.pipe.client.stream_send(0, &d[..off], false).unwrap();
         s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Err(Error::MessageError));
+    }
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
+    #[test]
+    /// Both endpoints open unidirectional and bidirectional WebTransport
+    /// streams.
+    fn webtransport_streams() {
+        let mut buf = [0; 65535];
+        let mut s = webtransport_session();
 
-        assert_eq!(s.pipe.client.stream_send(stream, &bytes[5..], false), Ok(5));
+        let session_id = webtransport_connect(&mut s);
+
+        let ev = Event::WebTransportStream { session_id };
+
+        // Client opens a unidirectional stream.
+        let uni = s
+            .client
+            .open_webtransport_uni_stream(&mut s.pipe.client, session_id)
+            .unwrap();
+        s.pipe.client.stream_send(uni, b"hello", false).unwrap();
         s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Ok((uni, ev.clone())));
         assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.server.webtransport_session_id(uni), Some(session_id));
+        assert_eq!(s.pipe.server.stream_recv(uni, &mut buf), Ok((5, false)));
+        assert_eq!(&buf[..5], b"hello");
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(5));
+        // Client opens a bidirectional stream.
+        let bidi = s
+            .client
+            .open_webtransport_bidi_stream(&mut s.pipe.client, session_id)
+            .unwrap();
+        assert_eq!(bidi, 4);
 
-        // Buffer multiple data frames.
-        let body = s.send_body_client(stream, false).unwrap();
-        s.send_body_client(stream, false).unwrap();
-        s.send_body_client(stream, false).unwrap();
+        s.pipe.client.stream_send(bidi, b"ping", false).unwrap();
+        s.advance().ok();
 
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        assert_eq!(s.poll_server(), Ok((bidi, ev.clone())));
         assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.pipe.server.stream_recv(bidi, &mut buf), Ok((4, false)));
+        assert_eq!(&buf[..4], b"ping");
 
-        {
-            let mut d = [42; 10];
-            let mut b = octets::OctetsMut::with_slice(&mut d);
+        s.pipe.server.stream_send(bidi, b"pong", true).unwrap();
+        s.advance().ok();
 
-            b.put_varint(frame::DATA_FRAME_TYPE_ID).unwrap();
-            b.put_varint(0).unwrap();
-            let off = b.off();
-            s.pipe.client.stream_send(stream, &d[..off], true).unwrap();
+        assert_eq!(s.pipe.client.stream_recv(bidi, &mut buf), Ok((4, true)));
+        assert_eq!(&buf[..4], b"pong");
 
-            s.advance().ok();
-        }
+        // Server opens a bidirectional stream.
+        let bidi = s
+            .server
+            .open_webtransport_bidi_stream(&mut s.pipe.server, session_id)
+            .unwrap();
+        assert_eq!(bidi, 1);
 
-        let mut recv_buf = vec![0; bytes.len() * 3];
+        s.pipe.server.stream_send(bidi, b"hi", false).unwrap();
+        s.advance().ok();
 
+        assert_eq!(s.poll_client(), Ok((bidi, ev)));
+        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.pipe.client.stream_recv(bidi, &mut buf), Ok((2, false)));
+        assert_eq!(&buf[..2], b"hi");
+
+        // Streams can't be opened for unknown sessions.
         assert_eq!(
-            s.recv_body_server(stream, &mut recv_buf),
-            Ok(body.len() * 3)
+            s.client.open_webtransport_uni_stream(&mut s.pipe.client, 8),
+            Err(Error::IdError)
+        );
+        assert_eq!(
+            s.client
+                .open_webtransport_bidi_stream(&mut s.pipe.client, 8),
+            Err(Error::IdError)
         );
     }
 
-    #[test]
-    /// Tests that the Datagram event is properly re-armed.
-    fn dgram_event_rearm() {
-        let mut buf = [0; 65535];
+    /// Sends a WebTransport unidirectional stream header for the given session
+    /// from the client, without checking the session, returning the stream ID.
+    fn webtransport_raw_uni_stream(s: &mut Session, session_id: u64) -> u64 {
+        let stream_id = s.client.next_uni_stream_id;
+        s.client.next_uni_stream_id += 4;
 
-        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        config
-            .load_cert_chain_from_pem_file("examples/cert.crt")
-            .unwrap();
-        config
-            .load_priv_key_from_pem_file("examples/cert.key")
+        let mut d = [42; 16];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        b.put_varint(stream::WEBTRANSPORT_UNI_STREAM_TYPE_ID)
             .unwrap();
-        config.set_application_protos(b"\x02h3").unwrap();
-        config.set_initial_max_data(1500);
-        config.set_initial_max_stream_data_bidi_local(150);
-        config.set_initial_max_stream_data_bidi_remote(150);
-        config.set_initial_max_stream_data_uni(150);
-        config.set_initial_max_streams_bidi(100);
-        config.set_initial_max_streams_uni(5);
-        config.verify_peer(false);
-        config.enable_dgram(true, 100, 100);
+        b.put_varint(session_id).unwrap();
+
+        let off = b.off();
+        s.pipe
+            .client
+            .stream_send(stream_id, &d[..off], false)
+            .unwrap();
+        s.advance().ok();
 
-        let mut h3_config = Config::new().unwrap();
-        let mut s = Session::with_configs(&mut config, &mut h3_config).unwrap();
-        s.handshake().unwrap();
+        stream_id
+    }
 
-        // 10 bytes on flow ID 0 and 2.
-        let flow_0_result = (11, 0, 1);
-        let flow_2_result = (11, 2, 1);
+    /// Returns whether the server aborted the given stream with STOP_SENDING
+    /// because it was rejected.
+    fn webtransport_stream_rejected(s: &Session, stream_id: u64) -> bool {
+        s.pipe.server.streams.stopped().any(|(id, e)| {
+            *id == stream_id && *e == WEBTRANSPORT_BUFFERED_STREAM_REJECTED
+        })
+    }
 
-        // Send requests followed by DATAGRAMs on client side.
-        let (stream, req) = s.send_request(false).unwrap();
+    #[test]
+    /// Streams received before their CONNECT request are buffered until the
+    /// session is established.
+    fn webtransport_stream_buffered() {
+        let mut s = webtransport_session();
 
-        let body = s.send_body_client(stream, true).unwrap();
+        let session_id = s.client.next_request_stream_id;
 
-        let mut recv_buf = vec![0; body.len()];
+        let stream_id = webtransport_raw_uni_stream(&mut s, session_id);
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"webtransport"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
+
+        assert_eq!(
+            s.client.send_webtransport_connect(&mut s.pipe.client, &req),
+            Ok(session_id)
+        );
+        s.advance().ok();
 
         let ev_headers = Event::Headers {
             list: req,
             has_body: true,
         };
 
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(2).unwrap();
-        s.send_dgram_client(2).unwrap();
+        assert_eq!(s.poll_server(), Ok((session_id, ev_headers)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
 
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
+        // The server can't use the session before accepting it.
+        assert_eq!(
+            s.server
+                .open_webtransport_uni_stream(&mut s.pipe.server, session_id),
+            Err(Error::IdError)
+        );
 
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Data)));
+        s.send_response(session_id, false).unwrap();
 
+        assert_eq!(
+            s.poll_server(),
+            Ok((stream_id, Event::WebTransportStream { session_id }))
+        );
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+        assert!(!webtransport_stream_rejected(&s, stream_id));
+    }
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
+    #[test]
+    /// Streams buffered for a request that doesn't establish a session are
+    /// rejected.
+    fn webtransport_stream_unknown_session() {
+        let mut s = webtransport_session();
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        let stream_id = webtransport_raw_uni_stream(&mut s, 0);
 
         assert_eq!(s.poll_server(), Err(Error::Done));
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
+        assert!(!webtransport_stream_rejected(&s, stream_id));
 
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        let (stream, req) = s.send_request(true).unwrap();
+        assert_eq!(stream, 0);
 
-        s.send_dgram_client(0).unwrap();
-        s.send_dgram_client(2).unwrap();
+        let ev_headers = Event::Headers {
+            list: req,
+            has_body: false,
+        };
 
-        assert_eq!(s.poll_server(), Ok((0, Event::Datagram)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.server.webtransport_session_id(stream_id), None);
 
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_0_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        // The stream is aborted with STOP_SENDING.
+        assert!(webtransport_stream_rejected(&s, stream_id));
+    }
 
-        assert_eq!(s.recv_dgram_server(&mut buf), Ok(flow_2_result));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+    #[test]
+    /// Only a limited number of streams are buffered.
+    fn webtransport_stream_buffer_limit() {
+        let mut config = Session::default_config().unwrap();
+        config.set_initial_max_streams_uni(32);
 
-        assert_eq!(s.recv_body_server(stream, &mut recv_buf), Ok(body.len()));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        let mut h3_config = Config::new().unwrap();
+        h3_config.enable_webtransport(true);
+
+        let mut s = Session::with_configs(&mut config, &h3_config).unwrap();
+        s.handshake().unwrap();
+
+        for _ in 0..MAX_BUFFERED_WEBTRANSPORT_STREAMS {
+            let stream_id = webtransport_raw_uni_stream(&mut s, 0);
+
+            assert_eq!(s.poll_server(), Err(Error::Done));
+            assert!(!webtransport_stream_rejected(&s, stream_id));
+        }
+
+        let stream_id = webtransport_raw_uni_stream(&mut s, 0);
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(webtransport_stream_rejected(&s, stream_id));
     }
 
     #[test]
-    fn reset_stream() {
-        let mut buf = [0; 65535];
+    /// A session refused with a non-2xx response is not established, and its
+    /// buffered streams are rejected.
+    fn webtransport_session_refused() {
+        let mut s = webtransport_session();
 
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+        let session_id = s.client.next_request_stream_id;
 
-        // Client sends request.
-        let (stream, req) = s.send_request(false).unwrap();
+        let stream_id = webtransport_raw_uni_stream(&mut s, session_id);
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        let req = vec![
+            Header::new(b":method", b"CONNECT"),
+            Header::new(b":protocol", b"webtransport"),
+            Header::new(b":scheme", b"https"),
+            Header::new(b":authority", b"quic.tech"),
+            Header::new(b":path", b"/wt"),
+        ];
+
+        s.client
+            .send_webtransport_connect(&mut s.pipe.client, &req)
+            .unwrap();
+        s.advance().ok();
 
         let ev_headers = Event::Headers {
             list: req,
             has_body: true,
         };
 
-        // Server sends response and closes stream.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
+        assert_eq!(s.poll_server(), Ok((session_id, ev_headers)));
         assert_eq!(s.poll_server(), Err(Error::Done));
 
-        let resp = s.send_response(stream, true).unwrap();
+        let resp = vec![Header::new(b":status", b"404")];
+
+        s.server
+            .send_response(&mut s.pipe.server, session_id, &resp, true)
+            .unwrap();
+
+        assert!(webtransport_stream_rejected(&s, stream_id));
+
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Err(Error::Done));
+        assert!(s.server.webtransport_sessions.is_empty());
 
         let ev_headers = Event::Headers {
             list: resp,
             has_body: false,
         };
 
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
-
-        // Client sends RESET_STREAM, closing stream.
-        let frames = [crate::frame::Frame::ResetStream {
-            stream_id: stream,
-            error_code: 42,
-            final_size: 68,
-        }];
-
-        let pkt_type = crate::packet::Type::Short;
+        assert_eq!(s.poll_client(), Ok((session_id, ev_headers)));
+        assert!(s.client.webtransport_sessions.is_empty());
         assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39)
+            s.client
+                .open_webtransport_uni_stream(&mut s.pipe.client, session_id),
+            Err(Error::IdError)
         );
+    }
 
-        // Server issues Reset event for the stream.
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(42))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+    #[test]
+    /// Resetting the CONNECT stream terminates the session.
+    fn webtransport_session_reset() {
+        let mut s = webtransport_session();
 
-        // Sending RESET_STREAM again shouldn't trigger another Reset event.
+        let session_id = webtransport_connect(&mut s);
+
+        s.pipe
+            .client
+            .stream_shutdown(session_id, crate::Shutdown::Write, 42)
+            .unwrap();
+        s.advance().ok();
+
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Reset(42))));
+        assert!(s.server.webtransport_sessions.is_empty());
         assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39)
+            s.server
+                .open_webtransport_uni_stream(&mut s.pipe.server, session_id),
+            Err(Error::IdError)
         );
+    }
+
+    /// Opens a unidirectional and a bidirectional WebTransport stream from
+    /// the client, returning their IDs once the server received them.
+    fn webtransport_client_streams(
+        s: &mut Session, session_id: u64,
+    ) -> (u64, u64) {
+        let ev = Event::WebTransportStream { session_id };
+
+        let uni = s
+            .client
+            .open_webtransport_uni_stream(&mut s.pipe.client, session_id)
+            .unwrap();
+        s.pipe.client.stream_send(uni, b"hello", false).unwrap();
+
+        let bidi = s
+            .client
+            .open_webtransport_bidi_stream(&mut s.pipe.client, session_id)
+            .unwrap();
+        s.pipe.client.stream_send(bidi, b"ping", false).unwrap();
+        s.advance().ok();
+
+        let mut events = vec![s.poll_server().unwrap(), s.poll_server().unwrap()];
+        events.sort_by_key(|(id, _)| *id);
 
+        assert_eq!(events, vec![(bidi, ev.clone()), (uni, ev)]);
         assert_eq!(s.poll_server(), Err(Error::Done));
+
+        (uni, bidi)
     }
 
     #[test]
-    fn reset_finished_at_server() {
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+    /// Closing a session resets the streams associated with it on both
+    /// endpoints.
+    fn webtransport_session_close_resets_streams() {
+        let mut buf = [0; 65535];
+        let mut s = webtransport_session();
 
-        // Client sends HEADERS and doesn't fin
-        let (stream, _req) = s.send_request(false).unwrap();
+        let session_id = webtransport_connect(&mut s);
 
-        // ..then Client sends RESET_STREAM
-        assert_eq!(
-            s.pipe.client.stream_shutdown(0, crate::Shutdown::Write, 0),
-            Ok(())
-        );
+        let (uni, bidi) = webtransport_client_streams(&mut s, session_id);
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        s.pipe.server.stream_send(bidi, b"pong", false).unwrap();
+        s.advance().ok();
 
-        // Server receives just a reset
-        assert_eq!(s.poll_server(), Ok((stream, Event::Reset(0))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.client
+            .close_webtransport_session(&mut s.pipe.client, session_id, 0, b"")
+            .unwrap();
 
-        // Client sends HEADERS and fin
-        let (stream, req) = s.send_request(true).unwrap();
+        // The client aborts its streams.
+        assert!(s.pipe.client.streams.reset().any(|(id, (e, _))| {
+            *id == uni && *e == WEBTRANSPORT_SESSION_GONE
+        }));
+        assert!(s
+            .pipe
+            .client
+            .streams
+            .stopped()
+            .any(|(id, e)| { *id == bidi && *e == WEBTRANSPORT_SESSION_GONE }));
+        assert_eq!(s.client.webtransport_session_id(bidi), None);
+
+        s.advance().ok();
 
-        // ..then Client sends RESET_STREAM
         assert_eq!(
-            s.pipe.client.stream_shutdown(4, crate::Shutdown::Write, 0),
-            Ok(())
+            s.pipe.server.stream_recv(uni, &mut buf),
+            Err(crate::Error::StreamReset(WEBTRANSPORT_SESSION_GONE))
         );
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: false,
+        let ev = Event::WebTransportSessionClosed {
+            error_code: 0,
+            reason: vec![],
         };
 
-        // Server receives headers and fin.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
+        assert_eq!(s.poll_server(), Ok((session_id, ev)));
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Finished)));
         assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // The server stops tracking the streams as well.
+        assert_eq!(s.server.webtransport_session_id(uni), None);
+        assert_eq!(s.server.webtransport_session_id(bidi), None);
     }
 
     #[test]
-    fn reset_finished_at_client() {
+    /// Streams are reset when the peer closes the session.
+    fn webtransport_session_closed_by_peer_resets_streams() {
         let mut buf = [0; 65535];
-        let mut s = Session::default().unwrap();
-        s.handshake().unwrap();
+        let mut s = webtransport_session();
 
-        // Client sends HEADERS and doesn't fin
-        let (stream, req) = s.send_request(false).unwrap();
+        let session_id = webtransport_connect(&mut s);
 
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: true,
-        };
+        let (uni, bidi) = webtransport_client_streams(&mut s, session_id);
 
-        // Server receives headers.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
+        s.server
+            .close_webtransport_session(&mut s.pipe.server, session_id, 0, b"")
+            .unwrap();
 
-        // Server sends response and doesn't fin
-        s.send_response(stream, false).unwrap();
+        // The server stops the client's unidirectional stream, and aborts
+        // both sides of the bidirectional one.
+        assert!(s
+            .pipe
+            .server
+            .streams
+            .stopped()
+            .any(|(id, e)| { *id == uni && *e == WEBTRANSPORT_SESSION_GONE }));
+        assert!(s.pipe.server.streams.reset().any(|(id, (e, _))| {
+            *id == bidi && *e == WEBTRANSPORT_SESSION_GONE
+        }));
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        s.advance().ok();
 
-        // .. then Server sends RESET_STREAM
         assert_eq!(
-            s.pipe
-                .server
-                .stream_shutdown(stream, crate::Shutdown::Write, 0),
-            Ok(())
+            s.pipe.client.stream_recv(bidi, &mut buf),
+            Err(crate::Error::StreamReset(WEBTRANSPORT_SESSION_GONE))
         );
 
-        assert_eq!(s.pipe.advance(), Ok(()));
-
-        // Client receives Reset only
-        assert_eq!(s.poll_client(), Ok((stream, Event::Reset(0))));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        // Client sends headers and fin.
-        let (stream, req) = s.send_request(true).unwrap();
-
-        let ev_headers = Event::Headers {
-            list: req,
-            has_body: false,
+        let ev = Event::WebTransportSessionClosed {
+            error_code: 0,
+            reason: vec![],
         };
 
-        // Server receives headers and fin.
-        assert_eq!(s.poll_server(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_server(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_server(), Err(Error::Done));
-
-        // Server sends response and fin
-        let resp = s.send_response(stream, true).unwrap();
+        assert_eq!(s.poll_client(), Ok((session_id, ev)));
+        assert_eq!(s.client.webtransport_session_id(uni), None);
+        assert_eq!(s.client.webtransport_session_id(bidi), None);
+    }
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+    #[test]
+    /// Client closes a session with a CLOSE_WEBTRANSPORT_SESSION capsule.
+    fn webtransport_session_close() {
+        let mut s = webtransport_session();
 
-        // ..then Server sends RESET_STREAM
-        let frames = [crate::frame::Frame::ResetStream {
-            stream_id: stream,
-            error_code: 42,
-            final_size: 68,
-        }];
+        let session_id = webtransport_connect(&mut s);
 
-        let pkt_type = crate::packet::Type::Short;
         assert_eq!(
-            s.pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Ok(39)
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                42,
+                &[b'x'; MAX_WEBTRANSPORT_CLOSE_REASON_LEN + 1],
+            ),
+            Err(Error::MessageError)
         );
 
-        assert_eq!(s.pipe.advance(), Ok(()));
+        assert_eq!(
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                42,
+                b"bye"
+            ),
+            Ok(())
+        );
+        s.advance().ok();
 
-        let ev_headers = Event::Headers {
-            list: resp,
-            has_body: false,
+        let ev = Event::WebTransportSessionClosed {
+            error_code: 42,
+            reason: b"bye".to_vec(),
         };
 
-        // Client receives headers and fin.
-        assert_eq!(s.poll_client(), Ok((stream, ev_headers)));
-        assert_eq!(s.poll_client(), Ok((stream, Event::Finished)));
-        assert_eq!(s.poll_client(), Err(Error::Done));
+        assert_eq!(s.poll_server(), Ok((session_id, ev)));
+        assert_eq!(s.poll_server(), Ok((session_id, Event::Finished)));
+        assert_eq!(s.poll_server(), Err(Error::Done));
+
+        // The session is no longer known.
+        assert_eq!(
+            s.server
+                .open_webtransport_uni_stream(&mut s.pipe.server, session_id),
+            Err(Error::IdError)
+        );
+        assert_eq!(
+            s.client.close_webtransport_session(
+                &mut s.pipe.client,
+                session_id,
+                0,
+                b""
+            ),
+            Err(Error::IdError)
+        );
     }
 }
 
@@ -24,11 +24,16 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::cmp;
+
 use crate::octets;
 
 use super::Error;
 use super::Result;
 
+use super::dynamic_table::DynamicTable;
+use super::encoder::encode_int;
+
 use crate::h3::Header;
 
 use super::INDEXED;
@@ -36,6 +41,13 @@ use super::INDEXED_WITH_POST_BASE;
 use super::LITERAL;
 use super::LITERAL_WITH_NAME_REF;
 
+use super::INSERT_COUNT_INCREMENT;
+use super::INSERT_WITH_LITERAL_NAME;
+use super::INSERT_WITH_NAME_REF;
+use super::SECTION_ACKNOWLEDGMENT;
+use super::SET_DYNAMIC_TABLE_CAPACITY;
+use super::STREAM_CANCELLATION;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum Representation {
     Indexed,
@@ -68,39 +80,197 @@ impl Representation {
 }
 
 /// A QPACK decoder.
-pub struct Decoder {}
+pub struct Decoder {
+    table: DynamicTable,
+
+    /// Insert count known to have been received by the encoder.
+    known_received_count: u64,
+
+    /// Encoder instructions that were only partially received.
+    control_buf: Vec<u8>,
+
+    /// Decoder instructions that need to be sent to the encoder.
+    instructions: Vec<u8>,
+}
 
 impl Default for Decoder {
     fn default() -> Decoder {
-        Decoder {}
+        Decoder::with_max_table_capacity(0)
     }
 }
 
 impl Decoder {
     /// Creates a new QPACK decoder.
+    ///
+    /// The dynamic table is not used, as its maximum capacity is 0.
     pub fn new() -> Decoder {
         Decoder::default()
     }
 
+    /// Creates a new QPACK decoder, whose dynamic table can hold up to
+    /// `max_table_capacity` bytes.
+    ///
+    /// This should match the `SETTINGS_QPACK_MAX_TABLE_CAPACITY` setting sent
+    /// to the peer.
+    pub fn with_max_table_capacity(max_table_capacity: u64) -> Decoder {
+        Decoder {
+            table: DynamicTable::new(max_table_capacity),
+
+            known_received_count: 0,
+
+            control_buf: Vec::new(),
+
+            instructions: Vec::new(),
+        }
+    }
+
+    /// Returns the number of entries inserted in the dynamic table.
+    pub fn insert_count(&self) -> u64 {
+        self.table.insert_count()
+    }
+
     /// Processes control instructions from the encoder.
-    pub fn control(&mut self, _buf: &mut [u8]) -> Result<()> {
-        // TODO: process control instructions
+    ///
+    /// The data doesn't need to contain complete instructions, incomplete
+    /// ones are buffered until the rest is received.
+    pub fn control(&mut self, buf: &[u8]) -> Result<()> {
+        self.control_buf.extend_from_slice(buf);
+
+        let mut b = octets::Octets::with_slice(&self.control_buf);
+
+        let mut consumed = 0;
+
+        loop {
+            match parse_encoder_instruction(&mut b, &mut self.table) {
+                Ok(()) => consumed = b.off(),
+
+                Err(Error::BufferTooShort) => break,
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.control_buf.drain(..consumed);
+
+        // An instruction can't be larger than the entry it inserts, even when
+        // accounting for the worst case huffman encoding.
+        if self.control_buf.len() as u64 > 4 * self.table.max_capacity() + 64 {
+            return Err(Error::InvalidTableCapacity);
+        }
+
+        // Let the encoder know about the new entries, so it can reference them
+        // without risking blocking streams.
+        let increment = self.table.insert_count() - self.known_received_count;
+
+        if increment > 0 {
+            self.push_instruction(increment, INSERT_COUNT_INCREMENT, 6)?;
+
+            self.known_received_count = self.table.insert_count();
+        }
+
         Ok(())
     }
 
     /// Decodes a QPACK header block into a list of headers.
+    ///
+    /// The [`Blocked`] error is returned when the header block references
+    /// dynamic table entries that were not received yet, in which case it
+    /// should be decoded again after more encoder instructions are processed.
+    ///
+    /// [`Blocked`]: enum.Error.html#variant.Blocked
     pub fn decode(&mut self, buf: &[u8], max_size: u64) -> Result<Vec<Header>> {
+        self.decode_block(buf, max_size).map(|(headers, _)| headers)
+    }
+
+    /// Decodes a QPACK header block received on the given stream into a list
+    /// of headers.
+    ///
+    /// This is the same as [`decode()`], but also acknowledges the header
+    /// block to the encoder when it references the dynamic table.
+    ///
+    /// [`decode()`]: struct.Decoder.html#method.decode
+    pub fn decode_stream(
+        &mut self, stream_id: u64, buf: &[u8], max_size: u64,
+    ) -> Result<Vec<Header>> {
+        let (headers, req_insert_count) = self.decode_block(buf, max_size)?;
+
+        if req_insert_count > 0 {
+            self.push_instruction(stream_id, SECTION_ACKNOWLEDGMENT, 7)?;
+
+            self.known_received_count =
+                cmp::max(self.known_received_count, req_insert_count);
+        }
+
+        Ok(headers)
+    }
+
+    /// Notifies the encoder that the header block of the given stream will
+    /// not be decoded, e.g. because the stream was reset while blocked.
+    ///
+    /// This should only be called for header blocks referencing the dynamic
+    /// table.
+    pub fn cancel_stream(&mut self, stream_id: u64) -> Result<()> {
+        self.push_instruction(stream_id, STREAM_CANCELLATION, 6)
+    }
+
+    /// Returns the decoder instructions that need to be sent to the encoder
+    /// on the QPACK decoder stream.
+    pub fn pending_instructions(&self) -> &[u8] {
+        &self.instructions
+    }
+
+    /// Removes the given number of bytes from the pending decoder
+    /// instructions, after they were sent.
+    pub fn consume_instructions(&mut self, len: usize) {
+        self.instructions.drain(..len);
+    }
+
+    fn push_instruction(
+        &mut self, v: u64, first: u8, prefix: usize,
+    ) -> Result<()> {
+        let mut d = [0; 10];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        encode_int(v, first, prefix, &mut b)?;
+
+        let len = b.off();
+        self.instructions.extend_from_slice(&d[..len]);
+
+        Ok(())
+    }
+
+    fn decode_block(
+        &mut self, buf: &[u8], max_size: u64,
+    ) -> Result<(Vec<Header>, u64)> {
         let mut b = octets::Octets::with_slice(buf);
 
         let mut out = Vec::new();
 
         let mut left = max_size;
 
-        let req_insert_count = decode_int(&mut b, 8)?;
-        let base = decode_int(&mut b, 7)?;
+        let encoded_insert_count = decode_int(&mut b, 8)?;
+        let req_insert_count = self.decode_insert_count(encoded_insert_count)?;
+
+        let sign = b.peek_u8()? & 0x80 == 0x80;
+        let delta_base = decode_int(&mut b, 7)?;
+
+        let base = if sign {
+            req_insert_count
+                .checked_sub(delta_base)
+                .and_then(|v| v.checked_sub(1))
+                .ok_or(Error::InvalidDynamicTableIndex)?
+        } else {
+            req_insert_count
+                .checked_add(delta_base)
+                .ok_or(Error::InvalidDynamicTableIndex)?
+        };
 
         trace!("Header count={} base={}", req_insert_count, base);
 
+        if req_insert_count > self.table.insert_count() {
+            return Err(Error::Blocked);
+        }
+
         while b.cap() > 0 {
             let first = b.peek_u8()?;
 
@@ -113,12 +283,16 @@ impl Decoder {
 
                     trace!("Indexed index={} static={}", index, s);
 
-                    if !s {
-                        // TODO: implement dynamic table
-                        return Err(Error::InvalidHeaderValue);
-                    }
+                    let (name, value) = if s {
+                        lookup_static(index)?
+                    } else {
+                        let index = base
+                            .checked_sub(index)
+                            .and_then(|v| v.checked_sub(1))
+                            .ok_or(Error::InvalidDynamicTableIndex)?;
 
-                    let (name, value) = lookup_static(index)?;
+                        self.lookup_dynamic(index, req_insert_count)?
+                    };
 
                     left = left
                         .checked_sub((name.len() + value.len()) as u64)
@@ -133,24 +307,24 @@ impl Decoder {
 
                     trace!("Indexed With Post Base index={}", index);
 
-                    // TODO: implement dynamic table
-                    return Err(Error::InvalidHeaderValue);
-                },
+                    let index = base
+                        .checked_add(index)
+                        .ok_or(Error::InvalidDynamicTableIndex)?;
 
-                Representation::Literal => {
-                    let name_huff = b.as_ref()[0] & 0x08 == 0x08;
-                    let name_len = decode_int(&mut b, 3)? as usize;
+                    let (name, value) =
+                        self.lookup_dynamic(index, req_insert_count)?;
 
-                    let mut name = b.get_bytes(name_len)?;
+                    left = left
+                        .checked_sub((name.len() + value.len()) as u64)
+                        .ok_or(Error::HeaderListTooLarge)?;
 
-                    let name = if name_huff {
-                        super::huffman::decode(&mut name)?
-                    } else {
-                        name.to_vec()
-                    };
+                    let hdr = Header::new(name, value);
+                    out.push(hdr);
+                },
 
-                    let name = name.to_vec();
-                    let value = decode_str(&mut b)?;
+                Representation::Literal => {
+                    let name = decode_str(&mut b, 3)?;
+                    let value = decode_str(&mut b, 7)?;
 
                     trace!(
                         "Literal Without Name Reference name={:?} value={:?}",
@@ -173,7 +347,7 @@ impl Decoder {
 
                     let s = first & STATIC == STATIC;
                     let name_idx = decode_int(&mut b, 4)?;
-                    let value = decode_str(&mut b)?;
+                    let value = decode_str(&mut b, 7)?;
 
                     trace!(
                         "Literal name_idx={} static={} value={:?}",
@@ -182,12 +356,16 @@ impl Decoder {
                         value
                     );
 
-                    if !s {
-                        // TODO: implement dynamic table
-                        return Err(Error::InvalidHeaderValue);
-                    }
+                    let name = if s {
+                        lookup_static(name_idx)?.0
+                    } else {
+                        let index = base
+                            .checked_sub(name_idx)
+                            .and_then(|v| v.checked_sub(1))
+                            .ok_or(Error::InvalidDynamicTableIndex)?;
 
-                    let (name, _) = lookup_static(name_idx)?;
+                        self.lookup_dynamic(index, req_insert_count)?.0
+                    };
 
                     left = left
                         .checked_sub((name.len() + value.len()) as u64)
@@ -201,16 +379,146 @@ impl Decoder {
                 },
 
                 Representation::LiteralWithPostBase => {
-                    trace!("Literal With Post Base");
+                    let name_idx = decode_int(&mut b, 3)?;
+                    let value = decode_str(&mut b, 7)?;
+
+                    trace!(
+                        "Literal With Post Base name_idx={} value={:?}",
+                        name_idx,
+                        value
+                    );
+
+                    let index = base
+                        .checked_add(name_idx)
+                        .ok_or(Error::InvalidDynamicTableIndex)?;
+
+                    let (name, _) =
+                        self.lookup_dynamic(index, req_insert_count)?;
 
-                    // TODO: implement dynamic table
-                    return Err(Error::InvalidHeaderValue);
+                    left = left
+                        .checked_sub((name.len() + value.len()) as u64)
+                        .ok_or(Error::HeaderListTooLarge)?;
+
+                    let hdr = Header(name.to_vec(), value);
+                    out.push(hdr);
                 },
             }
         }
 
-        Ok(out)
+        Ok((out, req_insert_count))
     }
+
+    /// Reconstructs the Required Insert Count from its encoded value, as
+    /// described in RFC 9204 Section 4.5.1.1.
+    fn decode_insert_count(&self, encoded: u64) -> Result<u64> {
+        if encoded == 0 {
+            return Ok(0);
+        }
+
+        let max_entries = self.table.max_entries();
+        let full_range = 2 * max_entries;
+
+        if encoded > full_range {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
+        let max_value = self.table.insert_count() + max_entries;
+        let max_wrapped = (max_value / full_range) * full_range;
+
+        let mut req_insert_count = max_wrapped + encoded - 1;
+
+        if req_insert_count > max_value {
+            if req_insert_count <= full_range {
+                return Err(Error::InvalidDynamicTableIndex);
+            }
+
+            req_insert_count -= full_range;
+        }
+
+        if req_insert_count == 0 {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
+        Ok(req_insert_count)
+    }
+
+    fn lookup_dynamic(
+        &self, index: u64, req_insert_count: u64,
+    ) -> Result<(&[u8], &[u8])> {
+        // Header blocks can't reference entries beyond their Required Insert
+        // Count.
+        if index >= req_insert_count {
+            return Err(Error::InvalidDynamicTableIndex);
+        }
+
+        self.table.get(index).ok_or(Error::InvalidDynamicTableIndex)
+    }
+}
+
+/// Parses a single encoder instruction and applies it to the dynamic table.
+fn parse_encoder_instruction(
+    b: &mut octets::Octets, table: &mut DynamicTable,
+) -> Result<()> {
+    let first = b.peek_u8()?;
+
+    if first & INSERT_WITH_NAME_REF == INSERT_WITH_NAME_REF {
+        const STATIC: u8 = 0x40;
+
+        let s = first & STATIC == STATIC;
+        let name_idx = decode_int(b, 6)?;
+        let value = decode_str(b, 7)?;
+
+        trace!(
+            "Insert With Name Reference name_idx={} static={} value={:?}",
+            name_idx,
+            s,
+            value
+        );
+
+        let name = if s {
+            lookup_static(name_idx)?.0.to_vec()
+        } else {
+            let index = table.relative_to_absolute(name_idx)?;
+
+            table
+                .get(index)
+                .ok_or(Error::InvalidDynamicTableIndex)?
+                .0
+                .to_vec()
+        };
+
+        return table.insert(name, value);
+    }
+
+    if first & INSERT_WITH_LITERAL_NAME == INSERT_WITH_LITERAL_NAME {
+        let name = decode_str(b, 5)?;
+        let value = decode_str(b, 7)?;
+
+        trace!("Insert With Literal Name name={:?} value={:?}", name, value);
+
+        return table.insert(name, value);
+    }
+
+    if first & SET_DYNAMIC_TABLE_CAPACITY == SET_DYNAMIC_TABLE_CAPACITY {
+        let capacity = decode_int(b, 5)?;
+
+        trace!("Set Dynamic Table Capacity capacity={}", capacity);
+
+        return table.set_capacity(capacity);
+    }
+
+    // Duplicate.
+    let index = decode_int(b, 5)?;
+
+    trace!("Duplicate index={}", index);
+
+    let index = table.relative_to_absolute(index)?;
+
+    let (name, value) =
+        table.get(index).ok_or(Error::InvalidDynamicTableIndex)?;
+    let (name, value) = (name.to_vec(), value.to_vec());
+
+    table.insert(name, value)
 }
 
 fn lookup_static(idx: u64) -> Result<(&'static [u8], &'static [u8])> {
@@ -221,7 +529,7 @@ fn lookup_static(idx: u64) -> Result<(&'static [u8], &'static [u8])> {
     Ok(super::static_table::STATIC_TABLE[idx as usize])
 }
 
-fn decode_int(b: &mut octets::Octets, prefix: usize) -> Result<u64> {
+pub fn decode_int(b: &mut octets::Octets, prefix: usize) -> Result<u64> {
     let mask = 2u64.pow(prefix as u32) - 1;
 
     let mut val = u64::from(b.get_u8()?);
@@ -252,12 +560,13 @@ fn decode_int(b: &mut octets::Octets, prefix: usize) -> Result<u64> {
     Err(Error::BufferTooShort)
 }
 
-fn decode_str(b: &mut octets::Octets) -> Result<Vec<u8>> {
+fn decode_str(b: &mut octets::Octets, prefix: usize) -> Result<Vec<u8>> {
     let first = b.peek_u8()?;
 
-    let huff = first & 0x80 == 0x80;
+    // The huffman flag is the bit right before the length's prefix.
+    let huff = first & (1 << prefix) != 0;
 
-    let len = decode_int(b, 7)? as usize;
+    let len = decode_int(b, prefix)? as usize;
 
     let mut val = b.get_bytes(len)?;
 
@@ -299,4 +608,154 @@ mod tests {
 
         assert_eq!(decode_int(&mut b, 8), Ok(42));
     }
+
+    #[test]
+    fn dynamic_table() {
+        // Examples from RFC 9204 Appendix B.
+        let mut dec = Decoder::with_max_table_capacity(220);
+
+        // Stream 4 references entries that were not received yet.
+        let block = [0x03, 0x81, 0x10, 0x11];
+        assert_eq!(dec.decode_stream(4, &block, u64::MAX), Err(Error::Blocked));
+
+        // Set Dynamic Table Capacity=220, Insert With Name Reference (static
+        // table).
+        let instructions = [
+            0x3f, 0xbd, 0x01, 0xc0, 0x0f, 0x77, 0x77, 0x77, 0x2e, 0x65, 0x78,
+            0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0xc1, 0x0c,
+            0x2f, 0x73, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2f, 0x70, 0x61, 0x74,
+            0x68,
+        ];
+
+        // Instructions can be split at any point.
+        assert_eq!(dec.control(&instructions[..10]), Ok(()));
+        assert_eq!(dec.insert_count(), 0);
+        assert_eq!(dec.control(&instructions[10..]), Ok(()));
+        assert_eq!(dec.insert_count(), 2);
+
+        assert_eq!(
+            dec.decode_stream(4, &block, u64::MAX),
+            Ok(vec![
+                Header::new(b":authority", b"www.example.com"),
+                Header::new(b":path", b"/sample/path"),
+            ])
+        );
+
+        // Insert Count Increment=2, Section Acknowledgment stream=4.
+        assert_eq!(dec.pending_instructions(), &[0x02, 0x84]);
+        dec.consume_instructions(2);
+
+        // Insert With Literal Name.
+        let instructions = [
+            0x4a, 0x63, 0x75, 0x73, 0x74, 0x6f, 0x6d, 0x2d, 0x6b, 0x65, 0x79,
+            0x0c, 0x63, 0x75, 0x73, 0x74, 0x6f, 0x6d, 0x2d, 0x76, 0x61, 0x6c,
+            0x75, 0x65,
+        ];
+        assert_eq!(dec.control(&instructions), Ok(()));
+        assert_eq!(dec.pending_instructions(), &[0x01]);
+        dec.consume_instructions(1);
+
+        // Duplicate.
+        assert_eq!(dec.control(&[0x02]), Ok(()));
+        assert_eq!(dec.insert_count(), 4);
+
+        // Required Insert Count=4, Base=4.
+        let block = [0x05, 0x00, 0x80, 0xc1, 0x81];
+        assert_eq!(
+            dec.decode_stream(8, &block, u64::MAX),
+            Ok(vec![
+                Header::new(b":authority", b"www.example.com"),
+                Header::new(b":path", b"/"),
+                Header::new(b"custom-key", b"custom-value"),
+            ])
+        );
+        assert_eq!(dec.pending_instructions(), &[0x01, 0x88]);
+        dec.consume_instructions(2);
+
+        // Insert With Name Reference (dynamic table), which evicts the first
+        // entry.
+        let instructions = [
+            0x81, 0x0d, 0x63, 0x75, 0x73, 0x74, 0x6f, 0x6d, 0x2d, 0x76, 0x61,
+            0x6c, 0x75, 0x65, 0x32,
+        ];
+        assert_eq!(dec.control(&instructions), Ok(()));
+        assert_eq!(dec.insert_count(), 5);
+        assert_eq!(dec.table.get(0), None);
+        assert_eq!(
+            dec.table.get(4),
+            Some((&b"custom-key"[..], &b"custom-value2"[..]))
+        );
+
+        // The evicted entry can't be referenced anymore.
+        let block = [0x05, 0x00, 0x83];
+        assert_eq!(
+            dec.decode(&block, u64::MAX),
+            Err(Error::InvalidDynamicTableIndex)
+        );
+
+        // Literal With Post-Base Name Reference, Required Insert Count=5,
+        // Base=4.
+        let block = [0x06, 0x80, 0x00, 0x01, 0x61];
+        assert_eq!(
+            dec.decode(&block, u64::MAX),
+            Ok(vec![Header::new(b"custom-key", b"a")])
+        );
+
+        // Acknowledgments are only sent by decode_stream().
+        assert_eq!(dec.pending_instructions(), &[0x01]);
+    }
+
+    #[test]
+    fn stream_cancellation() {
+        let mut dec = Decoder::with_max_table_capacity(100);
+
+        assert_eq!(dec.cancel_stream(4), Ok(()));
+        assert_eq!(dec.cancel_stream(100), Ok(()));
+        assert_eq!(dec.pending_instructions(), &[0x44, 0x7f, 0x25]);
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        let mut dec = Decoder::with_max_table_capacity(100);
+
+        // Capacity above the maximum.
+        assert_eq!(dec.control(&[0x3f, 0x46]), Err(Error::InvalidTableCapacity));
+
+        // Entry larger than the capacity.
+        let mut dec = Decoder::with_max_table_capacity(100);
+        assert_eq!(dec.control(&[0x3f, 0x01]), Ok(()));
+        assert_eq!(
+            dec.control(&[0xc0, 0x01, 0x61]),
+            Err(Error::InvalidTableCapacity)
+        );
+
+        // Duplicate of a missing entry.
+        let mut dec = Decoder::with_max_table_capacity(100);
+        assert_eq!(dec.control(&[0x00]), Err(Error::InvalidDynamicTableIndex));
+    }
+
+    #[test]
+    fn invalid_required_insert_count() {
+        // The dynamic table is disabled.
+        let mut dec = Decoder::new();
+        assert_eq!(
+            dec.decode(&[0x02, 0x00], u64::MAX),
+            Err(Error::InvalidDynamicTableIndex)
+        );
+
+        let mut dec = Decoder::with_max_table_capacity(220);
+
+        // Encoded value larger than twice the maximum number of entries.
+        assert_eq!(
+            dec.decode(&[0x0d, 0x00], u64::MAX),
+            Err(Error::InvalidDynamicTableIndex)
+        );
+
+        // References beyond the Required Insert Count.
+        assert_eq!(dec.control(&[0x3f, 0xbd, 0x01, 0xc1, 0x01, 0x61]), Ok(()));
+        assert_eq!(
+            dec.decode(&[0x02, 0x00, 0x10], u64::MAX),
+            Err(Error::InvalidDynamicTableIndex)
+        );
+    }
 }
@@ -0,0 +1,281 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! QPACK dynamic table.
+
+use std::collections::VecDeque;
+
+use super::Error;
+use super::Result;
+
+/// Size overhead of each dynamic table entry, as defined by RFC 9204.
+pub const ENTRY_OVERHEAD: u64 = 32;
+
+/// Returns the size of a dynamic table entry with the given name and value.
+pub fn entry_size(name: &[u8], value: &[u8]) -> u64 {
+    name.len() as u64 + value.len() as u64 + ENTRY_OVERHEAD
+}
+
+/// A QPACK dynamic table.
+///
+/// Entries are addressed by their absolute index, i.e. the number of entries
+/// inserted before them.
+pub struct DynamicTable {
+    /// Entries currently in the table, from oldest to newest.
+    entries: VecDeque<(Vec<u8>, Vec<u8>)>,
+
+    size: u64,
+
+    capacity: u64,
+
+    max_capacity: u64,
+
+    /// Total number of entries inserted, including evicted ones.
+    insert_count: u64,
+}
+
+impl DynamicTable {
+    /// Creates a new empty table, whose capacity can be raised up to
+    /// `max_capacity`.
+    pub fn new(max_capacity: u64) -> DynamicTable {
+        DynamicTable {
+            entries: VecDeque::new(),
+
+            size: 0,
+
+            capacity: 0,
+
+            max_capacity,
+
+            insert_count: 0,
+        }
+    }
+
+    pub fn max_capacity(&self) -> u64 {
+        self.max_capacity
+    }
+
+    pub fn insert_count(&self) -> u64 {
+        self.insert_count
+    }
+
+    /// Returns the maximum number of entries the table can hold.
+    pub fn max_entries(&self) -> u64 {
+        self.max_capacity / ENTRY_OVERHEAD
+    }
+
+    /// Sets the table's capacity, evicting entries as needed.
+    pub fn set_capacity(&mut self, v: u64) -> Result<()> {
+        if v > self.max_capacity {
+            return Err(Error::InvalidTableCapacity);
+        }
+
+        self.capacity = v;
+
+        self.evict_to(v);
+
+        Ok(())
+    }
+
+    /// Inserts a new entry, evicting older entries as needed.
+    pub fn insert(&mut self, name: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        let size = entry_size(&name, &value);
+
+        if size > self.capacity {
+            return Err(Error::InvalidTableCapacity);
+        }
+
+        self.evict_to(self.capacity - size);
+
+        self.size += size;
+        self.insert_count += 1;
+
+        self.entries.push_back((name, value));
+
+        Ok(())
+    }
+
+    /// Returns the entry with the given absolute index, unless it was
+    /// evicted or not inserted yet.
+    pub fn get(&self, index: u64) -> Option<(&[u8], &[u8])> {
+        let (name, value) = self
+            .entries
+            .get(index.checked_sub(self.evicted())? as usize)?;
+
+        Some((name, value))
+    }
+
+    /// Looks up the most recent entries matching the given header, returning
+    /// the absolute index of an entry matching both name and value, and of
+    /// one only matching the name.
+    pub fn find(&self, name: &[u8], value: &[u8]) -> (Option<u64>, Option<u64>) {
+        let mut name_match = None;
+
+        for (i, (n, v)) in self.entries.iter().enumerate().rev() {
+            if !n.eq_ignore_ascii_case(name) {
+                continue;
+            }
+
+            let index = self.evicted() + i as u64;
+
+            if v == value {
+                return (Some(index), name_match.or(Some(index)));
+            }
+
+            name_match = name_match.or(Some(index));
+        }
+
+        (None, name_match)
+    }
+
+    /// Returns true if an entry of the given size can be inserted by only
+    /// evicting entries whose absolute index is lower than `evictable`.
+    pub fn can_insert(&self, size: u64, evictable: u64) -> bool {
+        if size > self.capacity {
+            return false;
+        }
+
+        let mut available = self.capacity - self.size;
+
+        for (i, (name, value)) in self.entries.iter().enumerate() {
+            if available >= size {
+                break;
+            }
+
+            if self.evicted() + i as u64 >= evictable {
+                return false;
+            }
+
+            available += entry_size(name, value);
+        }
+
+        available >= size
+    }
+
+    /// Converts an index relative to the most recent entry into an absolute
+    /// index, as used by encoder instructions.
+    pub fn relative_to_absolute(&self, index: u64) -> Result<u64> {
+        self.insert_count
+            .checked_sub(index)
+            .and_then(|v| v.checked_sub(1))
+            .ok_or(Error::InvalidDynamicTableIndex)
+    }
+
+    /// Returns the number of entries evicted so far.
+    fn evicted(&self) -> u64 {
+        self.insert_count - self.entries.len() as u64
+    }
+
+    fn evict_to(&mut self, max_size: u64) {
+        while self.size > max_size {
+            let (name, value) = match self.entries.pop_front() {
+                Some(v) => v,
+
+                None => break,
+            };
+
+            self.size -= entry_size(&name, &value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_evict() {
+        let mut table = DynamicTable::new(100);
+
+        assert_eq!(table.set_capacity(101), Err(Error::InvalidTableCapacity));
+        assert_eq!(table.set_capacity(100), Ok(()));
+
+        assert_eq!(table.insert(b"a".to_vec(), b"b".to_vec()), Ok(()));
+        assert_eq!(table.insert(b"c".to_vec(), b"d".to_vec()), Ok(()));
+        assert_eq!(table.size, 68);
+        assert_eq!(table.insert_count(), 2);
+
+        assert_eq!(table.get(0), Some((&b"a"[..], &b"b"[..])));
+        assert_eq!(table.get(1), Some((&b"c"[..], &b"d"[..])));
+        assert_eq!(table.get(2), None);
+        assert_eq!(table.relative_to_absolute(0), Ok(1));
+        assert_eq!(table.relative_to_absolute(1), Ok(0));
+        assert_eq!(
+            table.relative_to_absolute(2),
+            Err(Error::InvalidDynamicTableIndex)
+        );
+
+        // The oldest entry is evicted to make room.
+        assert_eq!(table.insert(b"e".to_vec(), b"f".to_vec()), Ok(()));
+        assert_eq!(table.get(0), None);
+        assert_eq!(table.get(2), Some((&b"e"[..], &b"f"[..])));
+        assert_eq!(table.size, 68);
+
+        // Entries larger than the capacity can't be inserted.
+        assert_eq!(
+            table.insert(vec![0; 50], vec![0; 50]),
+            Err(Error::InvalidTableCapacity)
+        );
+
+        // Reducing the capacity evicts entries.
+        assert_eq!(table.set_capacity(40), Ok(()));
+        assert_eq!(table.get(1), None);
+        assert_eq!(table.get(2), Some((&b"e"[..], &b"f"[..])));
+        assert_eq!(table.size, 34);
+
+        assert_eq!(table.find(b"e", b"f"), (Some(2), Some(2)));
+        assert_eq!(table.find(b"E", b"g"), (None, Some(2)));
+        assert_eq!(table.find(b"a", b"b"), (None, None));
+
+        assert_eq!(table.set_capacity(0), Ok(()));
+        assert_eq!(table.get(2), None);
+        assert_eq!(table.size, 0);
+        assert_eq!(table.insert_count(), 3);
+    }
+
+    #[test]
+    fn can_insert() {
+        let mut table = DynamicTable::new(100);
+        assert_eq!(table.set_capacity(100), Ok(()));
+
+        assert!(!table.can_insert(101, 0));
+        assert!(table.can_insert(100, 0));
+
+        assert_eq!(table.insert(b"a".to_vec(), b"b".to_vec()), Ok(()));
+        assert_eq!(table.insert(b"c".to_vec(), b"d".to_vec()), Ok(()));
+
+        // Fits in the space left.
+        assert!(table.can_insert(32, 0));
+
+        // Requires evicting the first entry.
+        assert!(!table.can_insert(34, 0));
+        assert!(table.can_insert(34, 1));
+
+        // Requires evicting both entries.
+        assert!(!table.can_insert(67, 1));
+        assert!(table.can_insert(67, 2));
+    }
+}
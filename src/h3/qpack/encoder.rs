@@ -24,32 +24,233 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::cmp;
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use super::Error;
 use super::Result;
 
 use crate::octets;
 
 use crate::h3::NameValue;
 
+use super::decoder::decode_int;
+use super::dynamic_table::entry_size;
+use super::dynamic_table::DynamicTable;
+
 use super::INDEXED;
 use super::LITERAL;
 use super::LITERAL_WITH_NAME_REF;
 
+use super::INSERT_WITH_LITERAL_NAME;
+use super::INSERT_WITH_NAME_REF;
+use super::SET_DYNAMIC_TABLE_CAPACITY;
+
+use super::SECTION_ACKNOWLEDGMENT;
+use super::STREAM_CANCELLATION;
+
+/// The largest dynamic table capacity used by the encoder, regardless of the
+/// peer's limit, to bound memory usage.
+const MAX_TABLE_CAPACITY: u64 = 16384;
+
+/// How a header should be treated by the encoder's dynamic table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Indexing {
+    /// The header can be inserted in the dynamic table.
+    Index,
+
+    /// The header is not inserted in the dynamic table, but existing entries
+    /// can still be referenced.
+    NoIndex,
+
+    /// The header's value is always encoded as a literal, and intermediaries
+    /// are asked to do the same, e.g. for sensitive values.
+    NeverIndex,
+}
+
+/// The default indexing policy.
+///
+/// Credentials and short cookies are never indexed, as they could be guessed
+/// by observing the size of the compressed headers, while headers whose value
+/// rarely repeats are not inserted in the dynamic table.
+pub fn default_indexing_policy(name: &[u8], value: &[u8]) -> Indexing {
+    let eq = |v: &[u8]| name.eq_ignore_ascii_case(v);
+
+    if eq(b"authorization") || eq(b"proxy-authorization") {
+        return Indexing::NeverIndex;
+    }
+
+    if (eq(b"cookie") || eq(b"set-cookie")) && value.len() < 20 {
+        return Indexing::NeverIndex;
+    }
+
+    if eq(b":path") ||
+        eq(b"content-length") ||
+        eq(b"date") ||
+        eq(b"etag") ||
+        eq(b"if-modified-since") ||
+        eq(b"if-none-match") ||
+        eq(b"last-modified") ||
+        eq(b"location")
+    {
+        return Indexing::NoIndex;
+    }
+
+    Indexing::Index
+}
+
+/// A header block that wasn't acknowledged by the decoder yet.
+struct Section {
+    req_insert_count: u64,
+
+    /// The lowest absolute index referenced by the header block.
+    min_index: u64,
+}
+
+/// The representation chosen for a header.
+enum Line<'a> {
+    Static(u64),
+
+    StaticNameRef(u64, &'a [u8], bool),
+
+    Dynamic(u64),
+
+    DynamicNameRef(u64, &'a [u8], bool),
+
+    Literal(&'a [u8], &'a [u8], bool),
+}
+
 /// A QPACK encoder.
-pub struct Encoder {}
+pub struct Encoder {
+    table: DynamicTable,
+
+    max_blocked_streams: u64,
+
+    /// Insert count known to have been received by the decoder.
+    known_received_count: u64,
+
+    /// Header blocks not acknowledged yet, by stream ID.
+    sections: HashMap<u64, VecDeque<Section>>,
+
+    indexing_policy: fn(&[u8], &[u8]) -> Indexing,
+
+    /// Decoder instructions that were only partially received.
+    control_buf: Vec<u8>,
+
+    /// Encoder instructions that need to be sent to the decoder.
+    instructions: Vec<u8>,
+}
 
 impl Default for Encoder {
     fn default() -> Encoder {
-        Encoder {}
+        Encoder {
+            table: DynamicTable::new(0),
+
+            max_blocked_streams: 0,
+
+            known_received_count: 0,
+
+            sections: HashMap::new(),
+
+            indexing_policy: default_indexing_policy,
+
+            control_buf: Vec::new(),
+
+            instructions: Vec::new(),
+        }
     }
 }
 
 impl Encoder {
     /// Creates a new QPACK encoder.
+    ///
+    /// The dynamic table is not used until the peer's maximum table capacity
+    /// is set with [`set_max_table_capacity()`].
+    ///
+    /// [`set_max_table_capacity()`]: struct.Encoder.html#method.set_max_table_capacity
     pub fn new() -> Encoder {
         Encoder::default()
     }
 
+    /// Sets the maximum capacity of the dynamic table, as advertised by the
+    /// peer with the `SETTINGS_QPACK_MAX_TABLE_CAPACITY` setting.
+    ///
+    /// This needs to be called before any header is encoded, and queues the
+    /// encoder instruction setting the table's capacity.
+    pub fn set_max_table_capacity(&mut self, v: u64) -> Result<()> {
+        let capacity = cmp::min(v, MAX_TABLE_CAPACITY);
+
+        self.table = DynamicTable::new(v);
+        self.table.set_capacity(capacity)?;
+
+        if capacity > 0 {
+            self.push_instruction(
+                |b| encode_int(capacity, SET_DYNAMIC_TABLE_CAPACITY, 5, b),
+                10,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets the maximum number of streams that can be blocked waiting for
+    /// encoder instructions, as advertised by the peer with the
+    /// `SETTINGS_QPACK_BLOCKED_STREAMS` setting.
+    pub fn set_max_blocked_streams(&mut self, v: u64) {
+        self.max_blocked_streams = v;
+    }
+
+    /// Sets the policy deciding whether headers are inserted in the dynamic
+    /// table.
+    ///
+    /// The default is [`default_indexing_policy()`].
+    ///
+    /// [`default_indexing_policy()`]: fn.default_indexing_policy.html
+    pub fn set_indexing_policy(&mut self, policy: fn(&[u8], &[u8]) -> Indexing) {
+        self.indexing_policy = policy;
+    }
+
+    /// Processes control instructions from the decoder.
+    ///
+    /// The data doesn't need to contain complete instructions, incomplete
+    /// ones are buffered until the rest is received.
+    pub fn control(&mut self, buf: &[u8]) -> Result<()> {
+        self.control_buf.extend_from_slice(buf);
+
+        let mut control_buf = std::mem::take(&mut self.control_buf);
+
+        let mut b = octets::Octets::with_slice(&control_buf);
+
+        let mut consumed = 0;
+
+        let res = loop {
+            match self.parse_decoder_instruction(&mut b) {
+                Ok(()) => consumed = b.off(),
+
+                Err(Error::BufferTooShort) => break Ok(()),
+
+                Err(e) => break Err(e),
+            }
+        };
+
+        control_buf.drain(..consumed);
+
+        // Decoder instructions only contain a single integer.
+        if control_buf.len() > 16 {
+            return Err(Error::InvalidDecoderInstruction);
+        }
+
+        self.control_buf = control_buf;
+
+        res
+    }
+
     /// Encodes a list of headers into a QPACK header block.
+    ///
+    /// Only the static table is used, as the header block can't be
+    /// acknowledged by the decoder.
     pub fn encode<T: NameValue>(
         &mut self, headers: &[T], out: &mut [u8],
     ) -> Result<usize> {
@@ -62,38 +263,372 @@ impl Encoder {
         encode_int(0, 0, 7, &mut b)?;
 
         for h in headers {
-            match lookup_static(h) {
-                Some((idx, true)) => {
-                    const STATIC: u8 = 0x40;
+            let line = match lookup_static(h) {
+                Some((idx, true)) => Line::Static(idx),
 
-                    // Encode as statically indexed.
-                    encode_int(idx, INDEXED | STATIC, 6, &mut b)?;
-                },
+                Some((idx, false)) => Line::StaticNameRef(idx, h.value(), false),
 
-                Some((idx, false)) => {
-                    const STATIC: u8 = 0x10;
+                None => Line::Literal(h.name(), h.value(), false),
+            };
 
-                    // Encode value as literal with static name reference.
-                    encode_int(idx, LITERAL_WITH_NAME_REF | STATIC, 4, &mut b)?;
-                    encode_str(h.value(), 7, &mut b)?;
-                },
+            encode_line(&line, 0, &mut b)?;
+        }
+
+        Ok(b.off())
+    }
+
+    /// Encodes a list of headers to be sent on the given stream into a QPACK
+    /// header block.
+    ///
+    /// The dynamic table is used when enabled, in which case new entries
+    /// might be inserted, and the corresponding encoder instructions queued.
+    pub fn encode_stream<T: NameValue>(
+        &mut self, stream_id: u64, headers: &[T], out: &mut [u8],
+    ) -> Result<usize> {
+        if self.table.max_entries() == 0 {
+            return self.encode(headers, out);
+        }
+
+        // Whether the header block can reference entries that the decoder
+        // might not have received yet.
+        let can_block = self.is_blocking(stream_id) ||
+            (self.blocking_streams() as u64) < self.max_blocked_streams;
+
+        let known_received_count = self.known_received_count;
+
+        let mut lines = Vec::with_capacity(headers.len());
 
-                None => {
-                    // Encode as fully literal.
-                    let name_len =
-                        super::huffman::encode_output_length(h.name(), true)?;
+        let mut req_insert_count = 0;
+        let mut min_index = u64::MAX;
 
-                    encode_int(name_len as u64, LITERAL | 0x08, 3, &mut b)?;
+        for h in headers {
+            let (name, value) = (h.name(), h.value());
+
+            let static_match = lookup_static(h);
+
+            if let Some((idx, true)) = static_match {
+                lines.push(Line::Static(idx));
+                continue;
+            }
 
-                    super::huffman::encode(h.name(), &mut b, true)?;
+            let indexing = (self.indexing_policy)(name, value);
+            let never = indexing == Indexing::NeverIndex;
 
-                    encode_str(h.value(), 7, &mut b)?;
+            let usable = |index: u64| index < known_received_count || can_block;
+
+            let (mut exact_match, name_match) = self.table.find(name, value);
+
+            if never {
+                exact_match = None;
+            }
+
+            if exact_match.is_none() && indexing == Indexing::Index {
+                let static_name = static_match.map(|(idx, _)| idx);
+
+                // Entries referenced by the header block being encoded can't
+                // be evicted either.
+                let evictable = cmp::min(self.evictable(), min_index);
+
+                exact_match =
+                    self.insert(name, value, static_name, name_match, evictable)?;
+            }
+
+            // The entry matching the name might have been evicted to make
+            // room for the new one.
+            let name_match = name_match.filter(|i| self.table.get(*i).is_some());
+
+            let line = match exact_match {
+                Some(index) if usable(index) => Line::Dynamic(index),
+
+                _ => match (static_match, name_match) {
+                    (Some((idx, _)), _) => Line::StaticNameRef(idx, value, never),
+
+                    (None, Some(index)) if usable(index) =>
+                        Line::DynamicNameRef(index, value, never),
+
+                    _ => Line::Literal(name, value, never),
                 },
             };
+
+            if let Line::Dynamic(index) | Line::DynamicNameRef(index, ..) = line {
+                req_insert_count = cmp::max(req_insert_count, index + 1);
+                min_index = cmp::min(min_index, index);
+            }
+
+            lines.push(line);
+        }
+
+        // All references are relative to the base, so entries inserted while
+        // encoding can be referenced.
+        let base = self.table.insert_count();
+
+        let mut b = octets::OctetsMut::with_slice(out);
+
+        let encoded_insert_count = if req_insert_count > 0 {
+            req_insert_count % (2 * self.table.max_entries()) + 1
+        } else {
+            0
+        };
+
+        // Required Insert Count.
+        encode_int(encoded_insert_count, 0, 8, &mut b)?;
+
+        // Delta Base, always positive.
+        let delta_base = if req_insert_count > 0 {
+            base - req_insert_count
+        } else {
+            0
+        };
+
+        encode_int(delta_base, 0, 7, &mut b)?;
+
+        for line in &lines {
+            encode_line(line, base, &mut b)?;
+        }
+
+        if req_insert_count > 0 {
+            self.sections
+                .entry(stream_id)
+                .or_default()
+                .push_back(Section {
+                    req_insert_count,
+                    min_index,
+                });
         }
 
         Ok(b.off())
     }
+
+    /// Forgets the last header block encoded for the given stream, e.g.
+    /// because it couldn't be sent.
+    pub fn discard_last_section(&mut self, stream_id: u64) {
+        if let Some(sections) = self.sections.get_mut(&stream_id) {
+            sections.pop_back();
+
+            if sections.is_empty() {
+                self.sections.remove(&stream_id);
+            }
+        }
+    }
+
+    /// Returns the encoder instructions that need to be sent to the decoder
+    /// on the QPACK encoder stream.
+    pub fn pending_instructions(&self) -> &[u8] {
+        &self.instructions
+    }
+
+    /// Removes the given number of bytes from the pending encoder
+    /// instructions, after they were sent.
+    pub fn consume_instructions(&mut self, len: usize) {
+        self.instructions.drain(..len);
+    }
+
+    /// Returns true if the given stream has header blocks that reference
+    /// entries not acknowledged by the decoder yet.
+    fn is_blocking(&self, stream_id: u64) -> bool {
+        self.sections.get(&stream_id).is_some_and(|sections| {
+            sections
+                .iter()
+                .any(|s| s.req_insert_count > self.known_received_count)
+        })
+    }
+
+    fn blocking_streams(&self) -> usize {
+        self.sections
+            .keys()
+            .filter(|stream_id| self.is_blocking(**stream_id))
+            .count()
+    }
+
+    /// Returns the absolute index below which entries can be evicted, as they
+    /// were acknowledged and are not referenced by unacknowledged header
+    /// blocks.
+    fn evictable(&self) -> u64 {
+        self.sections
+            .values()
+            .flatten()
+            .map(|s| s.min_index)
+            .fold(self.known_received_count, cmp::min)
+    }
+
+    /// Inserts a new entry in the dynamic table, if there is enough space
+    /// without evicting entries from `evictable` onwards, returning its
+    /// absolute index.
+    fn insert(
+        &mut self, name: &[u8], value: &[u8], static_name: Option<u64>,
+        dynamic_name: Option<u64>, evictable: u64,
+    ) -> Result<Option<u64>> {
+        if !self.table.can_insert(entry_size(name, value), evictable) {
+            return Ok(None);
+        }
+
+        let max_len = 4 * (name.len() + value.len()) + 32;
+
+        match (static_name, dynamic_name) {
+            (Some(idx), _) => {
+                const STATIC: u8 = 0x40;
+
+                self.push_instruction(
+                    |b| {
+                        encode_int(idx, INSERT_WITH_NAME_REF | STATIC, 6, b)?;
+                        encode_str(value, 7, b)
+                    },
+                    max_len,
+                )?;
+            },
+
+            (None, Some(index)) => {
+                let idx = self.table.insert_count() - index - 1;
+
+                self.push_instruction(
+                    |b| {
+                        encode_int(idx, INSERT_WITH_NAME_REF, 6, b)?;
+                        encode_str(value, 7, b)
+                    },
+                    max_len,
+                )?;
+            },
+
+            (None, None) => {
+                self.push_instruction(
+                    |b| {
+                        encode_name(name, INSERT_WITH_LITERAL_NAME, 5, b)?;
+                        encode_str(value, 7, b)
+                    },
+                    max_len,
+                )?;
+            },
+        }
+
+        self.table
+            .insert(name.to_ascii_lowercase(), value.to_vec())?;
+
+        Ok(Some(self.table.insert_count() - 1))
+    }
+
+    fn push_instruction(
+        &mut self, f: impl FnOnce(&mut octets::OctetsMut) -> Result<()>,
+        max_len: usize,
+    ) -> Result<()> {
+        let mut d = vec![0; max_len];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        f(&mut b)?;
+
+        let len = b.off();
+        self.instructions.extend_from_slice(&d[..len]);
+
+        Ok(())
+    }
+
+    fn parse_decoder_instruction(
+        &mut self, b: &mut octets::Octets,
+    ) -> Result<()> {
+        let first = b.peek_u8()?;
+
+        if first & SECTION_ACKNOWLEDGMENT == SECTION_ACKNOWLEDGMENT {
+            let stream_id = decode_int(b, 7)?;
+
+            trace!("Section Acknowledgment stream={}", stream_id);
+
+            let sections = self
+                .sections
+                .get_mut(&stream_id)
+                .ok_or(Error::InvalidDecoderInstruction)?;
+
+            // Header blocks are acknowledged in order.
+            let section = sections
+                .pop_front()
+                .ok_or(Error::InvalidDecoderInstruction)?;
+
+            if sections.is_empty() {
+                self.sections.remove(&stream_id);
+            }
+
+            self.known_received_count =
+                cmp::max(self.known_received_count, section.req_insert_count);
+
+            return Ok(());
+        }
+
+        if first & STREAM_CANCELLATION == STREAM_CANCELLATION {
+            let stream_id = decode_int(b, 6)?;
+
+            trace!("Stream Cancellation stream={}", stream_id);
+
+            self.sections.remove(&stream_id);
+
+            return Ok(());
+        }
+
+        // Insert Count Increment.
+        let increment = decode_int(b, 6)?;
+
+        trace!("Insert Count Increment increment={}", increment);
+
+        let known_received_count = self
+            .known_received_count
+            .checked_add(increment)
+            .ok_or(Error::InvalidDecoderInstruction)?;
+
+        if increment == 0 || known_received_count > self.table.insert_count() {
+            return Err(Error::InvalidDecoderInstruction);
+        }
+
+        self.known_received_count = known_received_count;
+
+        Ok(())
+    }
+}
+
+/// Encodes a single field line, with dynamic table references relative to
+/// the given base.
+fn encode_line(line: &Line, base: u64, b: &mut octets::OctetsMut) -> Result<()> {
+    const NEVER_INDEX: u8 = 0x20;
+
+    match *line {
+        Line::Static(idx) => {
+            const STATIC: u8 = 0x40;
+
+            // Encode as statically indexed.
+            encode_int(idx, INDEXED | STATIC, 6, b)?;
+        },
+
+        Line::StaticNameRef(idx, value, never) => {
+            const STATIC: u8 = 0x10;
+
+            let first = if never { NEVER_INDEX } else { 0 };
+
+            // Encode value as literal with static name reference.
+            encode_int(idx, LITERAL_WITH_NAME_REF | STATIC | first, 4, b)?;
+            encode_str(value, 7, b)?;
+        },
+
+        Line::Dynamic(index) => {
+            // Encode as dynamically indexed.
+            encode_int(base - index - 1, INDEXED, 6, b)?;
+        },
+
+        Line::DynamicNameRef(index, value, never) => {
+            let first = if never { NEVER_INDEX } else { 0 };
+
+            // Encode value as literal with dynamic name reference.
+            encode_int(base - index - 1, LITERAL_WITH_NAME_REF | first, 4, b)?;
+            encode_str(value, 7, b)?;
+        },
+
+        Line::Literal(name, value, never) => {
+            const NEVER_INDEX: u8 = 0x10;
+
+            let first = if never { NEVER_INDEX } else { 0 };
+
+            // Encode as fully literal.
+            encode_name(name, LITERAL | first, 3, b)?;
+            encode_str(value, 7, b)?;
+        },
+    }
+
+    Ok(())
 }
 
 fn lookup_static<T: NameValue>(h: &T) -> Option<(u64, bool)> {
@@ -120,7 +655,7 @@ fn lookup_static<T: NameValue>(h: &T) -> Option<(u64, bool)> {
     name_match
 }
 
-fn encode_int(
+pub fn encode_int(
     mut v: u64, first: u8, prefix: usize, b: &mut octets::OctetsMut,
 ) -> Result<()> {
     let mask = 2u64.pow(prefix as u32) - 1;
@@ -149,6 +684,20 @@ fn encode_int(
     Ok(())
 }
 
+/// Encodes a header name as a lowercase huffman string, with the huffman flag
+/// right before the length's prefix.
+fn encode_name(
+    name: &[u8], first: u8, prefix: usize, b: &mut octets::OctetsMut,
+) -> Result<()> {
+    let len = super::huffman::encode_output_length(name, true)?;
+
+    encode_int(len as u64, first | (1 << prefix), prefix, b)?;
+
+    super::huffman::encode(name, b, true)?;
+
+    Ok(())
+}
+
 fn encode_str(v: &[u8], prefix: usize, b: &mut octets::OctetsMut) -> Result<()> {
     let len = super::huffman::encode_output_length(v, false)?;
 
@@ -163,8 +712,11 @@ fn encode_str(v: &[u8], prefix: usize, b: &mut octets::OctetsMut) -> Result<()>
 mod tests {
     use super::*;
 
+    use crate::h3;
     use crate::octets;
 
+    use super::super::Decoder;
+
     #[test]
     fn encode_int1() {
         let expected = [0b01010];
@@ -197,4 +749,149 @@ mod tests {
 
         assert_eq!(expected, encoded);
     }
+
+    #[test]
+    fn dynamic_table() {
+        let headers = vec![
+            h3::Header::new(b":method", b"GET"),
+            h3::Header::new(b":authority", b"quic.tech"),
+            h3::Header::new(b"x-custom", b"custom-value"),
+        ];
+
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+        enc.set_max_blocked_streams(1);
+
+        let mut dec = Decoder::with_max_table_capacity(220);
+
+        let mut out = [0; 64];
+
+        let len = enc.encode_stream(0, &headers, &mut out).unwrap();
+
+        // The header block references entries that were not received yet.
+        assert_eq!(
+            dec.decode_stream(0, &out[..len], u64::MAX),
+            Err(Error::Blocked)
+        );
+
+        assert_eq!(dec.control(enc.pending_instructions()), Ok(()));
+        enc.consume_instructions(enc.pending_instructions().len());
+
+        assert_eq!(
+            dec.decode_stream(0, &out[..len], u64::MAX),
+            Ok(headers.clone())
+        );
+
+        assert_eq!(enc.control(dec.pending_instructions()), Ok(()));
+        dec.consume_instructions(dec.pending_instructions().len());
+
+        assert_eq!(enc.known_received_count, 2);
+        assert!(enc.sections.is_empty());
+
+        // The same headers don't need new entries to be inserted.
+        let len2 = enc.encode_stream(4, &headers, &mut out).unwrap();
+        assert_eq!(len2, len);
+        assert!(enc.pending_instructions().is_empty());
+
+        assert_eq!(dec.decode_stream(4, &out[..len2], u64::MAX), Ok(headers));
+    }
+
+    #[test]
+    fn never_index() {
+        let headers = vec![
+            h3::Header::new(b"authorization", b"secret"),
+            h3::Header::new(b"cookie", b"id=1"),
+        ];
+
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+        enc.set_max_blocked_streams(1);
+        enc.consume_instructions(enc.pending_instructions().len());
+
+        let mut out = [0; 64];
+
+        let len = enc.encode_stream(0, &headers, &mut out).unwrap();
+
+        // No entry was inserted.
+        assert!(enc.pending_instructions().is_empty());
+        assert_eq!(enc.table.find(b"authorization", b"secret"), (None, None));
+
+        // Both lines are literals with static name reference and the N bit.
+        assert_eq!(out[..2], [0, 0]);
+        assert_eq!(out[2] & 0xf0, LITERAL_WITH_NAME_REF | 0x30);
+
+        let mut dec = Decoder::new();
+        assert_eq!(dec.decode(&out[..len], u64::MAX), Ok(headers.clone()));
+
+        // The policy can be changed.
+        enc.set_indexing_policy(|_, _| Indexing::Index);
+
+        enc.encode_stream(4, &headers, &mut out).unwrap();
+        assert!(!enc.pending_instructions().is_empty());
+        assert_eq!(
+            enc.table.find(b"authorization", b"secret"),
+            (Some(0), Some(0))
+        );
+    }
+
+    #[test]
+    fn blocked_streams_limit() {
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+        enc.set_max_blocked_streams(1);
+
+        let mut out = [0; 64];
+
+        let headers = [h3::Header::new(b"x-custom", b"foo")];
+        enc.encode_stream(0, &headers, &mut out).unwrap();
+
+        // Stream 0 is blocking.
+        assert_ne!(out[0], 0);
+
+        // Stream 4 can't block, so the new entry is not referenced.
+        let headers = [h3::Header::new(b"x-custom", b"bar")];
+        enc.encode_stream(4, &headers, &mut out).unwrap();
+        assert_eq!(out[..2], [0, 0]);
+
+        // Stream 0 can keep referencing unacknowledged entries.
+        enc.encode_stream(0, &headers, &mut out).unwrap();
+        assert_ne!(out[0], 0);
+
+        assert_eq!(enc.blocking_streams(), 1);
+
+        // Once the peer cancels stream 0, stream 4 can block.
+        assert_eq!(enc.control(&[STREAM_CANCELLATION]), Ok(()));
+        assert_eq!(enc.blocking_streams(), 0);
+
+        enc.encode_stream(4, &headers, &mut out).unwrap();
+        assert_ne!(out[0], 0);
+    }
+
+    #[test]
+    fn invalid_decoder_instructions() {
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+
+        // Insert Count Increment of 0.
+        assert_eq!(enc.control(&[0x00]), Err(Error::InvalidDecoderInstruction));
+
+        // Insert Count Increment beyond the number of inserted entries.
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+        assert_eq!(enc.control(&[0x01]), Err(Error::InvalidDecoderInstruction));
+
+        // Section Acknowledgment for a stream without header blocks.
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+        assert_eq!(
+            enc.control(&[SECTION_ACKNOWLEDGMENT | 4]),
+            Err(Error::InvalidDecoderInstruction)
+        );
+
+        // Incomplete instructions are buffered.
+        let mut enc = Encoder::new();
+        enc.set_max_table_capacity(220).unwrap();
+        assert_eq!(enc.control(&[0x3f]), Ok(()));
+        assert_eq!(enc.control_buf, [0x3f]);
+    }
 }
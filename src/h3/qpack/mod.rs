@@ -31,6 +31,16 @@ const INDEXED_WITH_POST_BASE: u8 = 0b0001_0000;
 const LITERAL: u8 = 0b0010_0000;
 const LITERAL_WITH_NAME_REF: u8 = 0b0100_0000;
 
+// Encoder instructions.
+const SET_DYNAMIC_TABLE_CAPACITY: u8 = 0b0010_0000;
+const INSERT_WITH_NAME_REF: u8 = 0b1000_0000;
+const INSERT_WITH_LITERAL_NAME: u8 = 0b0100_0000;
+
+// Decoder instructions.
+const SECTION_ACKNOWLEDGMENT: u8 = 0b1000_0000;
+const STREAM_CANCELLATION: u8 = 0b0100_0000;
+const INSERT_COUNT_INCREMENT: u8 = 0b0000_0000;
+
 /// A specialized [`Result`] type for quiche QPACK operations.
 ///
 /// This type is used throughout quiche's QPACK public API for any operation
@@ -56,6 +66,23 @@ pub enum Error {
 
     /// The decoded header list exceeded the size limit.
     HeaderListTooLarge,
+
+    /// The QPACK dynamic table index provided doesn't exist, or the entry was
+    /// already evicted.
+    InvalidDynamicTableIndex,
+
+    /// The QPACK dynamic table capacity was set above the allowed maximum, or
+    /// an entry doesn't fit in it.
+    InvalidTableCapacity,
+
+    /// The QPACK header block references dynamic table entries that were not
+    /// received yet, so it can't be decoded until more encoder instructions
+    /// are processed.
+    Blocked,
+
+    /// The QPACK decoder instruction is not valid, e.g. it acknowledges a
+    /// header block that was never sent.
+    InvalidDecoderInstruction,
 }
 
 impl std::fmt::Display for Error {
@@ -154,9 +181,12 @@ mod tests {
 }
 
 pub use decoder::Decoder;
+pub use encoder::default_indexing_policy;
 pub use encoder::Encoder;
+pub use encoder::Indexing;
 
 mod decoder;
+mod dynamic_table;
 mod encoder;
 mod huffman;
 mod static_table;
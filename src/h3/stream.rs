@@ -25,6 +25,7 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use super::Error;
+use super::Priority;
 use super::Result;
 
 use crate::octets;
@@ -35,6 +36,7 @@ pub const HTTP3_CONTROL_STREAM_TYPE_ID: u64 = 0x0;
 pub const HTTP3_PUSH_STREAM_TYPE_ID: u64 = 0x1;
 pub const QPACK_ENCODER_STREAM_TYPE_ID: u64 = 0x2;
 pub const QPACK_DECODER_STREAM_TYPE_ID: u64 = 0x3;
+pub const WEBTRANSPORT_UNI_STREAM_TYPE_ID: u64 = 0x54;
 
 const MAX_STATE_BUF_SIZE: usize = (1 << 24) - 1;
 
@@ -45,6 +47,7 @@ pub enum Type {
     Push,
     QpackEncoder,
     QpackDecoder,
+    WebTransport,
     Unknown,
 }
 
@@ -71,6 +74,12 @@ pub enum State {
     /// Reading a QPACK instruction.
     QpackInstruction,
 
+    /// Reading the WebTransport session ID.
+    WebTransportSessionId,
+
+    /// Reading WebTransport data, which is left to the application.
+    WebTransportData,
+
     /// Reading and discarding data.
     Drain,
 
@@ -85,6 +94,7 @@ impl Type {
             HTTP3_PUSH_STREAM_TYPE_ID => Ok(Type::Push),
             QPACK_ENCODER_STREAM_TYPE_ID => Ok(Type::QpackEncoder),
             QPACK_DECODER_STREAM_TYPE_ID => Ok(Type::QpackDecoder),
+            WEBTRANSPORT_UNI_STREAM_TYPE_ID => Ok(Type::WebTransport),
 
             _ => Ok(Type::Unknown),
         }
@@ -130,6 +140,18 @@ pub struct Stream {
     /// The type of the frame currently being parsed.
     frame_type: Option<u64>,
 
+    /// The push ID carried by a push stream.
+    push_id: Option<u64>,
+
+    /// The priority signaled by the peer for the stream, if any.
+    priority: Option<Priority>,
+
+    /// Whether a header block was received and reported on the stream.
+    headers_received: bool,
+
+    /// The session ID carried by a WebTransport stream.
+    webtransport_session_id: Option<u64>,
+
     /// Whether the stream was created locally, or by the peer.
     is_local: bool,
 
@@ -174,6 +196,14 @@ impl Stream {
 
             frame_type: None,
 
+            push_id: None,
+
+            priority: None,
+
+            headers_received: false,
+
+            webtransport_session_id: None,
+
             is_local,
             remote_initialized: false,
             local_initialized: false,
@@ -207,6 +237,8 @@ impl Stream {
                 State::QpackInstruction
             },
 
+            Type::WebTransport => State::WebTransportSessionId,
+
             Type::Unknown => State::Drain,
         };
 
@@ -216,16 +248,67 @@ impl Stream {
     }
 
     /// Sets the push ID and transitions to the next state.
-    pub fn set_push_id(&mut self, _id: u64) -> Result<()> {
+    pub fn set_push_id(&mut self, id: u64) -> Result<()> {
         assert_eq!(self.state, State::PushId);
 
-        // TODO: implement push ID.
+        self.push_id = Some(id);
 
         self.state_transition(State::FrameType, 1, true)?;
 
         Ok(())
     }
 
+    /// Returns the push ID of a push stream, once it has been read.
+    pub fn push_id(&self) -> Option<u64> {
+        self.push_id
+    }
+
+    /// Sets the priority signaled by the peer.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = Some(priority);
+    }
+
+    /// Returns the priority signaled by the peer, if any.
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    /// Marks the stream as having received a header block.
+    pub fn set_headers_received(&mut self) {
+        self.headers_received = true;
+    }
+
+    /// Returns true if a header block was received on the stream.
+    pub fn headers_received(&self) -> bool {
+        self.headers_received
+    }
+
+    /// Sets the WebTransport session ID and transitions to the next state.
+    pub fn set_webtransport_session_id(&mut self, id: u64) -> Result<()> {
+        assert_eq!(self.state, State::WebTransportSessionId);
+
+        self.webtransport_session_id = Some(id);
+
+        self.state_transition(State::WebTransportData, 0, false)?;
+
+        Ok(())
+    }
+
+    /// Marks a locally opened bidirectional stream as belonging to a
+    /// WebTransport session.
+    pub fn initialize_webtransport(&mut self, session_id: u64) {
+        self.ty = Some(Type::WebTransport);
+        self.webtransport_session_id = Some(session_id);
+
+        let _ = self.state_transition(State::WebTransportData, 0, false);
+    }
+
+    /// Returns the session ID of a WebTransport stream, once it has been
+    /// read.
+    pub fn webtransport_session_id(&self) -> Option<u64> {
+        self.webtransport_session_id
+    }
+
     /// Sets the frame type and transitions to the next state.
     pub fn set_frame_type(&mut self, ty: u64) -> Result<()> {
         assert_eq!(self.state, State::FrameType);
@@ -273,6 +356,19 @@ impl Stream {
                         (frame::HEADERS_FRAME_TYPE_ID, false) =>
                             self.remote_initialized = true,
 
+                        // A bidirectional WebTransport stream is signaled
+                        // before any frame, and carries no frames at all.
+                        (frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID, false) => {
+                            self.ty = Some(Type::WebTransport);
+                            self.remote_initialized = true;
+
+                            return self.state_transition(
+                                State::WebTransportSessionId,
+                                1,
+                                true,
+                            );
+                        },
+
                         (frame::DATA_FRAME_TYPE_ID, false) =>
                             return Err(Error::FrameUnexpected),
 
@@ -288,6 +384,12 @@ impl Stream {
                         (frame::MAX_PUSH_FRAME_TYPE_ID, _) =>
                             return Err(Error::FrameUnexpected),
 
+                        (frame::PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID, _) =>
+                            return Err(Error::FrameUnexpected),
+
+                        (frame::PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID, _) =>
+                            return Err(Error::FrameUnexpected),
+
                         // All other frames can be ignored regardless of stream
                         // state.
                         _ => (),
@@ -297,7 +399,15 @@ impl Stream {
 
             Some(Type::Push) => {
                 match ty {
-                    // Frames that can never be received on request streams.
+                    // Push stream starts uninitialized and only HEADERS is
+                    // accepted.
+                    frame::HEADERS_FRAME_TYPE_ID if !self.remote_initialized =>
+                        self.remote_initialized = true,
+
+                    frame::DATA_FRAME_TYPE_ID if !self.remote_initialized =>
+                        return Err(Error::FrameUnexpected),
+
+                    // Frames that can never be received on push streams.
                     frame::CANCEL_PUSH_FRAME_TYPE_ID =>
                         return Err(Error::FrameUnexpected),
 
@@ -313,6 +423,12 @@ impl Stream {
                     frame::MAX_PUSH_FRAME_TYPE_ID =>
                         return Err(Error::FrameUnexpected),
 
+                    frame::PRIORITY_UPDATE_FRAME_REQUEST_TYPE_ID =>
+                        return Err(Error::FrameUnexpected),
+
+                    frame::PRIORITY_UPDATE_FRAME_PUSH_TYPE_ID =>
+                        return Err(Error::FrameUnexpected),
+
                     _ => (),
                 }
             },
@@ -584,6 +700,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: None,
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -640,6 +758,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: None,
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -705,6 +825,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: None,
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -748,6 +870,8 @@ mod tests {
             qpack_max_table_capacity: Some(0),
             qpack_blocked_streams: Some(0),
             h3_datagram: None,
+            connect_protocol_enabled: None,
+            webtransport_enabled: None,
             grease: None,
         };
 
@@ -908,6 +1032,7 @@ mod tests {
 
         stream.set_push_id(push_id).unwrap();
         assert_eq!(stream.state, State::FrameType);
+        assert_eq!(stream.push_id(), Some(1));
 
         // Parse the HEADERS frame type.
         stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
@@ -984,6 +1109,78 @@ mod tests {
         assert_eq!(stream.state, State::Drain);
     }
 
+    #[test]
+    fn webtransport_uni() {
+        let mut stream = Stream::new(2, false);
+
+        let mut d = vec![42; 20];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        b.put_varint(WEBTRANSPORT_UNI_STREAM_TYPE_ID).unwrap();
+        b.put_varint(4).unwrap();
+
+        let mut cursor = std::io::Cursor::new(d);
+
+        // Parse stream type, which is encoded on two bytes.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+        assert_eq!(stream.try_consume_varint(), Err(Error::Done));
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let stream_ty = stream.try_consume_varint().unwrap();
+        assert_eq!(stream_ty, WEBTRANSPORT_UNI_STREAM_TYPE_ID);
+        stream
+            .set_ty(Type::deserialize(stream_ty).unwrap())
+            .unwrap();
+        assert_eq!(stream.state, State::WebTransportSessionId);
+
+        // Parse session ID.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let session_id = stream.try_consume_varint().unwrap();
+        assert_eq!(session_id, 4);
+
+        stream.set_webtransport_session_id(session_id).unwrap();
+        assert_eq!(stream.state, State::WebTransportData);
+        assert_eq!(stream.webtransport_session_id(), Some(4));
+    }
+
+    #[test]
+    fn webtransport_bidi() {
+        let mut stream = Stream::new(4, false);
+        assert_eq!(stream.ty, Some(Type::Request));
+
+        let mut d = vec![42; 20];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        b.put_varint(frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID)
+            .unwrap();
+        b.put_varint(0).unwrap();
+
+        let mut cursor = std::io::Cursor::new(d);
+
+        // Parse signal value, which is encoded on two bytes.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+        assert_eq!(stream.try_consume_varint(), Err(Error::Done));
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let frame_ty = stream.try_consume_varint().unwrap();
+        assert_eq!(frame_ty, frame::WEBTRANSPORT_STREAM_FRAME_TYPE_ID);
+
+        stream.set_frame_type(frame_ty).unwrap();
+        assert_eq!(stream.ty, Some(Type::WebTransport));
+        assert_eq!(stream.state, State::WebTransportSessionId);
+
+        // Parse session ID.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let session_id = stream.try_consume_varint().unwrap();
+        assert_eq!(session_id, 0);
+
+        stream.set_webtransport_session_id(session_id).unwrap();
+        assert_eq!(stream.state, State::WebTransportData);
+        assert_eq!(stream.webtransport_session_id(), Some(0));
+    }
+
     #[test]
     fn data_before_headers() {
         let mut stream = Stream::new(0, false);
@@ -1007,4 +1204,44 @@ mod tests {
 
         assert_eq!(stream.set_frame_type(frame_ty), Err(Error::FrameUnexpected));
     }
+
+    #[test]
+    fn push_data_before_headers() {
+        let mut stream = Stream::new(3, false);
+
+        let mut d = vec![42; 128];
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+
+        let data = frame::Frame::Data {
+            payload: vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        };
+
+        b.put_varint(HTTP3_PUSH_STREAM_TYPE_ID).unwrap();
+        b.put_varint(0).unwrap();
+        data.to_bytes(&mut b).unwrap();
+
+        let mut cursor = std::io::Cursor::new(d);
+
+        // Parse stream type.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let stream_ty = stream.try_consume_varint().unwrap();
+        stream
+            .set_ty(Type::deserialize(stream_ty).unwrap())
+            .unwrap();
+
+        // Parse push ID.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let push_id = stream.try_consume_varint().unwrap();
+        stream.set_push_id(push_id).unwrap();
+
+        // Parse the DATA frame type.
+        stream.try_fill_buffer_for_tests(&mut cursor).unwrap();
+
+        let frame_ty = stream.try_consume_varint().unwrap();
+        assert_eq!(frame_ty, frame::DATA_FRAME_TYPE_ID);
+
+        assert_eq!(stream.set_frame_type(frame_ty), Err(Error::FrameUnexpected));
+    }
 }
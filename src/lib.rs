@@ -55,23 +55,25 @@
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let server_name = "quic.tech";
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let to = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
 //! // Client connection.
-//! let conn = quiche::connect(Some(&server_name), &scid, to, &mut config)?;
+//! let conn =
+//!     quiche::connect(Some(&server_name), &scid, local, peer, &mut config)?;
 //!
 //! // Server connection.
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! let conn = quiche::accept(&scid, None, from, &mut config)?;
+//! let conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! # Ok::<(), quiche::Error>(())
 //! ```
 //!
 //! In both cases, the application is responsible for generating a new source
 //! connection ID that will be used to identify the new connection.
 //!
-//! The application also need to pass the address of the remote peer of the
-//! connection: in the case of a client that would be the address of the server
-//! it is trying to connect to, and for a server that is the address of the
-//! client that initiated the connection.
+//! The application also need to pass the local address of the socket used by
+//! the connection, as well as the address of the remote peer: in the case of a
+//! client that would be the address of the server it is trying to connect to,
+//! and for a server that is the address of the client that initiated the
+//! connection.
 //!
 //! ## Handling incoming packets
 //!
@@ -83,12 +85,17 @@
 //! # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! loop {
 //!     let (read, from) = socket.recv_from(&mut buf).unwrap();
 //!
-//!     let recv_info = quiche::RecvInfo { from };
+//!     let recv_info = quiche::RecvInfo {
+//!         from,
+//!         to: socket.local_addr().unwrap(),
+//!         ecn: 0,
+//!     };
 //!
 //!     let read = match conn.recv(&mut buf[..read], recv_info) {
 //!         Ok(v) => v,
@@ -109,7 +116,7 @@
 //!
 //! The application has to pass a [`RecvInfo`] structure in order to provide
 //! additional information about the received packet (such as the address it
-//! was received from).
+//! was received from, and the local address it was received on).
 //!
 //! ## Generating outgoing packets
 //!
@@ -121,8 +128,9 @@
 //! # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! loop {
 //!     let (write, send_info) = match conn.send(&mut out) {
 //!         Ok(v) => v,
@@ -154,8 +162,9 @@
 //! ```
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! let timeout = conn.timeout();
 //! # Ok::<(), quiche::Error>(())
 //! ```
@@ -170,8 +179,9 @@
 //! # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! // Timeout expired, handle it.
 //! conn.on_timeout();
 //!
@@ -206,8 +216,9 @@
 //! ```no_run
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! if conn.is_established() {
 //!     // Handshake completed, send some data on stream 0.
 //!     conn.stream_send(0, b"hello", true)?;
@@ -226,8 +237,9 @@
 //! # let mut buf = [0; 512];
 //! # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
 //! # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-//! # let from = "127.0.0.1:1234".parse().unwrap();
-//! # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+//! # let peer = "127.0.0.1:1234".parse().unwrap();
+//! # let local = "127.0.0.1:4321".parse().unwrap();
+//! # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 //! if conn.is_established() {
 //!     // Iterate over readable streams.
 //!     for stream_id in conn.readable() {
@@ -285,6 +297,13 @@
 //! Note that the CC algorithm should be configured before calling [`connect()`]
 //! or [`accept()`]. Otherwise the connection will use a default CC algorithm.
 //!
+//! Applications can also provide their own algorithm by implementing the
+//! [`CongestionController`] trait, and registering it with
+//! [`set_congestion_controller_factory()`].
+//!
+//! [`CongestionController`]: trait.CongestionController.html
+//! [`set_congestion_controller_factory()`]:
+//!     struct.Config.html#method.set_congestion_controller_factory
 //! [`CongestionControlAlgorithm`]: enum.CongestionControlAlgorithm.html
 
 #![allow(improper_ctypes)]
@@ -296,13 +315,19 @@
 extern crate log;
 
 use std::cmp;
+use std::convert::TryInto;
 use std::time;
 
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::SocketAddr;
+use std::net::SocketAddrV4;
+use std::net::SocketAddrV6;
 
 use std::pin::Pin;
 use std::str::FromStr;
 
+use std::sync::Arc;
 use std::sync::Mutex;
 
 use std::collections::VecDeque;
@@ -318,6 +343,9 @@ const PROTOCOL_VERSION_DRAFT27: u32 = 0xff00_001b;
 const PROTOCOL_VERSION_DRAFT28: u32 = 0xff00_001c;
 const PROTOCOL_VERSION_DRAFT29: u32 = 0xff00_001d;
 
+/// QUIC version 2, as per RFC 9369.
+pub const PROTOCOL_VERSION_V2: u32 = 0x6b33_43cf;
+
 /// The maximum length of a connection ID.
 pub const MAX_CONN_ID_LEN: usize = crate::packet::MAX_CID_LEN as usize;
 
@@ -359,6 +387,10 @@ const PAYLOAD_LENGTH_LEN: usize = 2;
 // The number of undecryptable that can be buffered.
 const MAX_UNDECRYPTABLE_PACKETS: usize = 10;
 
+// The number of received address validation tokens that are kept until the
+// application reads them. Older tokens are dropped first.
+const MAX_RECEIVED_TOKENS: usize = 4;
+
 const RESERVED_VERSION_MASK: u32 = 0xfafafafa;
 
 /// A specialized [`Result`] type for quiche operations.
@@ -430,6 +462,26 @@ pub enum Error {
 
     /// Error in congestion control.
     CongestionControl,
+
+    /// Too many identifiers were provided.
+    IdLimit,
+
+    /// Not enough available identifiers.
+    OutOfIdentifiers,
+
+    /// The peer initiated a key update before the previous one was
+    /// acknowledged.
+    KeyUpdate,
+
+    /// The connection was reset by the peer with a stateless reset.
+    StatelessReset,
+
+    /// The provided address validation token is invalid or expired.
+    InvalidToken,
+
+    /// The peer's version information is inconsistent with the version
+    /// negotiation that took place.
+    VersionNegotiation,
 }
 
 impl Error {
@@ -442,6 +494,10 @@ impl Error {
             Error::FlowControl => 0x3,
             Error::StreamLimit => 0x4,
             Error::FinalSize => 0x6,
+            Error::IdLimit => 0x9,
+            Error::InvalidToken => 0xb,
+            Error::KeyUpdate => 0xe,
+            Error::VersionNegotiation => 0x11,
             _ => 0xa,
         }
     }
@@ -465,6 +521,12 @@ impl Error {
             Error::CongestionControl => -14,
             Error::StreamStopped { .. } => -15,
             Error::StreamReset { .. } => -16,
+            Error::IdLimit => -17,
+            Error::OutOfIdentifiers => -18,
+            Error::KeyUpdate => -19,
+            Error::StatelessReset => -20,
+            Error::InvalidToken => -21,
+            Error::VersionNegotiation => -22,
         }
     }
 }
@@ -492,16 +554,34 @@ impl std::convert::From<octets::BufferTooShortError> for Error {
 pub struct RecvInfo {
     /// The address the packet was received from.
     pub from: SocketAddr,
+
+    /// The address the packet was received on.
+    pub to: SocketAddr,
+
+    /// The ECN codepoint the packet was marked with.
+    ///
+    /// This is the value of the two least significant bits of the IP TOS or
+    /// Traffic Class field, or `0` (Not-ECT) if it is not known.
+    pub ecn: u8,
 }
 
 /// Ancillary information about outgoing packets.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct SendInfo {
+    /// The local address the packet should be sent from.
+    pub from: SocketAddr,
+
     /// The address the packet should be sent to.
     pub to: SocketAddr,
 
     /// The time to send the packet out.
     pub at: time::Instant,
+
+    /// The ECN codepoint the packet should be marked with.
+    ///
+    /// This needs to be set in the two least significant bits of the IP TOS
+    /// or Traffic Class field of the outgoing packet.
+    pub ecn: u8,
 }
 
 /// Represents information carried by `CONNECTION_CLOSE` frames.
@@ -549,12 +629,31 @@ pub struct Config {
 
     cc_algorithm: CongestionControlAlgorithm,
 
+    cc_factory: Option<Arc<recovery::CongestionControllerFactory>>,
+
+    stream_scheduler_factory: Option<Arc<stream::StreamSchedulerFactory>>,
+
     hystart: bool,
 
+    ecn: bool,
+
+    pmtud: bool,
+
     dgram_recv_max_queue_len: usize,
     dgram_send_max_queue_len: usize,
 
     max_send_udp_payload_size: usize,
+
+    preferred_address_v4: Option<SocketAddrV4>,
+    preferred_address_v6: Option<SocketAddrV6>,
+
+    key_update_threshold: u64,
+
+    stateless_reset_secret: Option<Vec<u8>>,
+
+    address_token_key: Option<AddressTokenKey>,
+
+    compatible_versions: Vec<u32>,
 }
 
 // See https://quicwg.org/base-drafts/rfc9000.html#section-15
@@ -585,12 +684,29 @@ impl Config {
             application_protos: Vec::new(),
             grease: true,
             cc_algorithm: CongestionControlAlgorithm::CUBIC,
+            cc_factory: None,
+            stream_scheduler_factory: None,
             hystart: true,
 
+            ecn: false,
+
+            pmtud: false,
+
             dgram_recv_max_queue_len: DEFAULT_MAX_DGRAM_QUEUE_LEN,
             dgram_send_max_queue_len: DEFAULT_MAX_DGRAM_QUEUE_LEN,
 
             max_send_udp_payload_size: MAX_SEND_UDP_PAYLOAD_SIZE,
+
+            preferred_address_v4: None,
+            preferred_address_v6: None,
+
+            key_update_threshold: std::u64::MAX,
+
+            stateless_reset_secret: None,
+
+            address_token_key: None,
+
+            compatible_versions: Vec::new(),
         })
     }
 
@@ -879,6 +995,51 @@ impl Config {
         self.local_transport_params.disable_active_migration = v;
     }
 
+    /// Sets the addresses advertised in the `preferred_address` transport
+    /// parameter.
+    ///
+    /// This is only used by servers, and lets clients migrate to one of the
+    /// given addresses once the handshake is confirmed (e.g. to move away from
+    /// an anycast address). The connection ID and stateless reset token sent
+    /// alongside the addresses are generated for each connection, and the
+    /// former can be retrieved with [`source_ids()`].
+    ///
+    /// The parameter is not sent when both addresses are `None`, or when the
+    /// server uses zero-length connection IDs.
+    ///
+    /// By default no preferred address is advertised.
+    ///
+    /// [`source_ids()`]: struct.Connection.html#method.source_ids
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// let v4 = "192.0.2.1:443".parse().unwrap();
+    ///
+    /// config.set_preferred_address(Some(v4), None);
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_preferred_address(
+        &mut self, v4: Option<SocketAddrV4>, v6: Option<SocketAddrV6>,
+    ) {
+        self.preferred_address_v4 = v4;
+        self.preferred_address_v6 = v6;
+    }
+
+    /// Sets the `active_connection_id_limit` transport parameter.
+    ///
+    /// This is the maximum number of connection IDs provided by the peer
+    /// that the connection is willing to store. Values lower than `2` are
+    /// ignored.
+    ///
+    /// The default value is `2`.
+    pub fn set_active_connection_id_limit(&mut self, v: u64) {
+        if v >= 2 {
+            self.local_transport_params.active_conn_id_limit = v;
+        }
+    }
+
     /// Sets the congestion control algorithm used by string.
     ///
     /// The default value is `cubic`. On error `Error::CongestionControl`
@@ -904,6 +1065,108 @@ impl Config {
         self.cc_algorithm = algo;
     }
 
+    /// Sets a factory for application-provided congestion controllers.
+    ///
+    /// The factory is called to create a [`CongestionController`] for each
+    /// path of each new connection, and takes precedence over the algorithm
+    /// configured with [`set_cc_algorithm()`].
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # use std::time::Instant;
+    /// struct FixedWindow(usize);
+    ///
+    /// impl quiche::CongestionController for FixedWindow {
+    ///     fn on_packet_sent(
+    ///         &mut self, _: usize, _: Instant, _: &quiche::RecoveryView,
+    ///     ) {
+    ///     }
+    ///
+    ///     fn on_packets_acked(
+    ///         &mut self, _: &[quiche::Acked], _: Instant, _: &quiche::RecoveryView,
+    ///     ) {
+    ///     }
+    ///
+    ///     fn congestion_event(
+    ///         &mut self, _: usize, _: Instant, _: Instant, _: &quiche::RecoveryView,
+    ///     ) {
+    ///     }
+    ///
+    ///     fn collapse_cwnd(&mut self, _: &quiche::RecoveryView) {}
+    ///
+    ///     fn cwnd(&self) -> usize {
+    ///         self.0
+    ///     }
+    /// }
+    ///
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_congestion_controller_factory(|r| {
+    ///     Box::new(FixedWindow(r.max_datagram_size * 100))
+    /// });
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`CongestionController`]: trait.CongestionController.html
+    /// [`set_cc_algorithm()`]: struct.Config.html#method.set_cc_algorithm
+    pub fn set_congestion_controller_factory<F>(&mut self, f: F)
+    where
+        F: Fn(&RecoveryView) -> Box<dyn CongestionController>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.cc_factory = Some(Arc::new(f));
+    }
+
+    /// Sets a factory for application-provided stream schedulers.
+    ///
+    /// The factory is called to create a [`StreamScheduler`] for each new
+    /// connection, which then decides the order in which streams with data
+    /// ready to be sent are flushed. By default the [`PriorityScheduler`] is
+    /// used.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// use std::collections::VecDeque;
+    ///
+    /// /// Sends streams in a round-robin fashion, ignoring their priority.
+    /// #[derive(Default)]
+    /// struct RoundRobin(VecDeque<u64>);
+    ///
+    /// impl quiche::StreamScheduler for RoundRobin {
+    ///     fn push(&mut self, stream_id: u64, _: u8, _: bool) {
+    ///         self.0.push_back(stream_id);
+    ///     }
+    ///
+    ///     fn remove(&mut self, stream_id: u64, _: u8, _: bool) {
+    ///         self.0.retain(|id| *id != stream_id);
+    ///     }
+    ///
+    ///     fn pop(&mut self) -> Option<u64> {
+    ///         self.0.pop_front()
+    ///     }
+    ///
+    ///     fn is_empty(&self) -> bool {
+    ///         self.0.is_empty()
+    ///     }
+    /// }
+    ///
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_stream_scheduler_factory(|| Box::new(RoundRobin::default()));
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`StreamScheduler`]: trait.StreamScheduler.html
+    /// [`PriorityScheduler`]: struct.PriorityScheduler.html
+    pub fn set_stream_scheduler_factory<F>(&mut self, f: F)
+    where
+        F: Fn() -> Box<dyn StreamScheduler> + Send + Sync + 'static,
+    {
+        self.stream_scheduler_factory = Some(Arc::new(f));
+    }
+
     /// Configures whether to enable HyStart++.
     ///
     /// The default value is `true`.
@@ -911,6 +1174,39 @@ impl Config {
         self.hystart = v;
     }
 
+    /// Configures whether to mark outgoing packets as ECN-capable.
+    ///
+    /// When enabled, the ECN codepoint of outgoing packets is returned in
+    /// [`SendInfo`], and the application is responsible for setting it on the
+    /// socket. Marking stops if the network path or the peer turn out not to
+    /// support ECN.
+    ///
+    /// The ECN codepoint of incoming packets is always reported to the peer
+    /// when provided in [`RecvInfo`], regardless of this setting.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`RecvInfo`]: struct.RecvInfo.html
+    pub fn enable_ecn(&mut self, v: bool) {
+        self.ecn = v;
+    }
+
+    /// Configures whether to perform path MTU discovery.
+    ///
+    /// When enabled, connections start sending datagrams of the minimum QUIC
+    /// size and probe the network path for larger sizes, up to the maximum
+    /// outgoing UDP payload size (as configured with
+    /// [`set_max_send_udp_payload_size()`]) and the peer's limit.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`set_max_send_udp_payload_size()`]:
+    ///     struct.Config.html#method.set_max_send_udp_payload_size
+    pub fn discover_pmtu(&mut self, v: bool) {
+        self.pmtud = v;
+    }
+
     /// Configures whether to enable receiving DATAGRAM frames.
     ///
     /// When enabled, the `max_datagram_frame_size` transport parameter is set
@@ -928,6 +1224,80 @@ impl Config {
         self.dgram_recv_max_queue_len = recv_queue_len;
         self.dgram_send_max_queue_len = send_queue_len;
     }
+
+    /// Sets the number of packets that can be protected with the same 1-RTT
+    /// keys before a key update is automatically initiated.
+    ///
+    /// The confidentiality limit of the negotiated AEAD is always enforced, so
+    /// values above it have no effect.
+    ///
+    /// The default is the confidentiality limit of the negotiated AEAD.
+    pub fn set_key_update_threshold(&mut self, v: u64) {
+        self.key_update_threshold = v;
+    }
+
+    /// Sets the secret used to derive the stateless reset token of the
+    /// initial connection ID, and of the one sent alongside the preferred
+    /// address.
+    ///
+    /// This is only used by servers, which should use the same secret with
+    /// [`stateless_reset_token()`] when sending a stateless reset. The secret
+    /// needs to be kept across restarts, and must not be shared with other
+    /// deployments.
+    ///
+    /// By default no stateless reset token is advertised for the initial
+    /// connection ID.
+    ///
+    /// [`stateless_reset_token()`]: fn.stateless_reset_token.html
+    pub fn set_stateless_reset_secret(&mut self, secret: &[u8]) {
+        self.stateless_reset_secret = Some(secret.to_vec());
+    }
+
+    /// Sets the key used to issue and validate address validation tokens.
+    ///
+    /// This is only used by servers. When set, tokens can be sent to clients
+    /// in NEW_TOKEN frames with [`send_new_token()`], and valid tokens
+    /// received in a client's first Initial packet lift the
+    /// anti-amplification limit of the new connection.
+    ///
+    /// [`send_new_token()`]: struct.Connection.html#method.send_new_token
+    pub fn set_address_token_key(&mut self, key: AddressTokenKey) {
+        self.address_token_key = Some(key);
+    }
+
+    /// Sets the QUIC versions, in order of preference, that a connection can
+    /// be switched to with compatible version negotiation, as per RFC 9368.
+    ///
+    /// Clients advertise these versions to the server, which can then switch
+    /// the connection to one of them without an additional round-trip.
+    /// Servers switch to the first of these versions that is also supported
+    /// by the client.
+    ///
+    /// Currently only [`PROTOCOL_VERSION`] and [`PROTOCOL_VERSION_V2`] are
+    /// compatible with each other.
+    ///
+    /// Returns [`UnknownVersion`] if any of the versions is not supported.
+    ///
+    /// [`PROTOCOL_VERSION`]: constant.PROTOCOL_VERSION.html
+    /// [`PROTOCOL_VERSION_V2`]: constant.PROTOCOL_VERSION_V2.html
+    /// [`UnknownVersion`]: enum.Error.html#variant.UnknownVersion
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// config.set_compatible_versions(&[quiche::PROTOCOL_VERSION_V2])?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_compatible_versions(&mut self, versions: &[u32]) -> Result<()> {
+        if !versions.iter().all(|&v| version_is_supported(v)) {
+            return Err(Error::UnknownVersion);
+        }
+
+        self.compatible_versions = versions.to_vec();
+
+        Ok(())
+    }
 }
 
 /// A QUIC connection.
@@ -935,11 +1305,24 @@ pub struct Connection {
     /// QUIC wire version used for the connection.
     version: u32,
 
-    /// Peer's connection ID.
-    dcid: ConnectionId<'static>,
+    /// QUIC wire version of the first flight of Initial packets, before any
+    /// compatible version negotiation.
+    original_version: u32,
+
+    /// Versions the connection can be switched to with compatible version
+    /// negotiation.
+    compatible_versions: Vec<u32>,
 
-    /// Local connection ID.
-    scid: ConnectionId<'static>,
+    /// Initial packet protection key of the original version, used by the
+    /// server to open Initial packets the client still sends in that version
+    /// after switching to a compatible one.
+    original_version_open: Option<crypto::Open>,
+
+    /// Source and destination connection IDs.
+    ids: cid::ConnectionIdentifiers,
+
+    /// Sequence number of the source connection ID currently in use.
+    active_scid_seq: u64,
 
     /// Unique opaque ID for the connection that can be used for logging.
     trace_id: String,
@@ -947,6 +1330,18 @@ pub struct Connection {
     /// Packet number spaces.
     pkt_num_spaces: [packet::PktNumSpace; packet::EPOCH_COUNT],
 
+    /// Current 1-RTT key phase.
+    key_phase: bool,
+
+    /// Number of 1-RTT key updates performed so far.
+    key_generation: u64,
+
+    /// Number of packets protected with the current 1-RTT keys.
+    key_phase_pkt_count: u64,
+
+    /// Number of packets after which a key update is initiated.
+    key_update_threshold: u64,
+
     /// Peer's transport parameters.
     peer_transport_params: TransportParams,
 
@@ -966,10 +1361,16 @@ pub struct Connection {
     /// client. On the server this is empty.
     session: Option<Vec<u8>>,
 
-    /// Loss recovery and congestion control state.
-    recovery: recovery::Recovery,
+    /// Network paths, each with its own loss recovery and congestion control
+    /// state.
+    paths: path::PathMap,
 
-    peer_addr: SocketAddr,
+    /// Loss recovery configuration, used to set up new paths.
+    recovery_config: recovery::RecoveryConfig,
+
+    /// The path towards the server's preferred address, while it is being
+    /// validated. Only relevant for client connections.
+    preferred_path_id: Option<usize>,
 
     /// List of supported application protocols.
     application_protos: Vec<Vec<u8>>,
@@ -1004,10 +1405,6 @@ pub struct Connection {
     /// Peer's flow control limit for the connection.
     max_tx_data: u64,
 
-    /// Total number of bytes the server can send before the peer's address
-    /// is verified.
-    max_send_bytes: usize,
-
     /// Total number of bytes retransmitted over the connection.
     /// This counts only STREAM and CRYPTO data.
     stream_retrans_bytes: u64,
@@ -1032,6 +1429,15 @@ pub struct Connection {
     /// Received address verification token.
     token: Option<Vec<u8>>,
 
+    /// Key used to issue and validate address validation tokens.
+    address_token_key: Option<AddressTokenKey>,
+
+    /// Address validation tokens to be sent in NEW_TOKEN frames.
+    new_tokens: VecDeque<Vec<u8>>,
+
+    /// Address validation tokens received in NEW_TOKEN frames.
+    received_tokens: VecDeque<Vec<u8>>,
+
     /// Error code and reason to be sent to the peer in a CONNECTION_CLOSE
     /// frame.
     local_error: Option<ConnectionError>,
@@ -1040,9 +1446,6 @@ pub struct Connection {
     /// frame.
     peer_error: Option<ConnectionError>,
 
-    /// Received path challenge.
-    challenge: Option<Vec<u8>>,
-
     /// The connection-level limit at which send blocking occurred.
     blocked_limit: Option<u64>,
 
@@ -1071,12 +1474,13 @@ pub struct Connection {
     /// Whether stateless retry has been performed.
     did_retry: bool,
 
+    /// Whether the server's preferred address has already been considered
+    /// for migration. Only relevant for client connections.
+    did_probe_preferred_address: bool,
+
     /// Whether the peer already updated its connection ID.
     got_peer_conn_id: bool,
 
-    /// Whether the peer's address has been verified.
-    verified_peer_address: bool,
-
     /// Whether the peer has verified our address.
     peer_verified_address: bool,
 
@@ -1128,6 +1532,9 @@ pub struct Connection {
 /// client sent before a stateless retry (this is only required when using
 /// the [`retry()`] function).
 ///
+/// The `local` and `peer` parameters are the addresses of the server's socket
+/// and of the client, respectively.
+///
 /// [`retry()`]: fn.retry.html
 ///
 /// ## Examples:
@@ -1135,16 +1542,17 @@ pub struct Connection {
 /// ```no_run
 /// # let mut config = quiche::Config::new(0xbabababa)?;
 /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-/// # let from = "127.0.0.1:1234".parse().unwrap();
-/// let conn = quiche::accept(&scid, None, from, &mut config)?;
+/// # let local = "127.0.0.1:0".parse().unwrap();
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// let conn = quiche::accept(&scid, None, local, peer, &mut config)?;
 /// # Ok::<(), quiche::Error>(())
 /// ```
 #[inline]
 pub fn accept(
-    scid: &ConnectionId, odcid: Option<&ConnectionId>, from: SocketAddr,
-    config: &mut Config,
+    scid: &ConnectionId, odcid: Option<&ConnectionId>, local: SocketAddr,
+    peer: SocketAddr, config: &mut Config,
 ) -> Result<Pin<Box<Connection>>> {
-    let conn = Connection::new(scid, odcid, from, config, true)?;
+    let conn = Connection::new(scid, odcid, local, peer, config, true)?;
 
     Ok(conn)
 }
@@ -1155,27 +1563,72 @@ pub fn accept(
 /// while the optional `server_name` parameter is used to verify the peer's
 /// certificate.
 ///
+/// The `local` and `peer` parameters are the addresses of the client's socket
+/// and of the server, respectively.
+///
 /// ## Examples:
 ///
 /// ```no_run
 /// # let mut config = quiche::Config::new(0xbabababa)?;
 /// # let server_name = "quic.tech";
 /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-/// # let to = "127.0.0.1:1234".parse().unwrap();
-/// let conn = quiche::connect(Some(&server_name), &scid, to, &mut config)?;
+/// # let local = "127.0.0.1:0".parse().unwrap();
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// let conn =
+///     quiche::connect(Some(&server_name), &scid, local, peer, &mut config)?;
 /// # Ok::<(), quiche::Error>(())
 /// ```
 #[inline]
 pub fn connect(
-    server_name: Option<&str>, scid: &ConnectionId, to: SocketAddr,
-    config: &mut Config,
+    server_name: Option<&str>, scid: &ConnectionId, local: SocketAddr,
+    peer: SocketAddr, config: &mut Config,
+) -> Result<Pin<Box<Connection>>> {
+    connect_with_token(server_name, scid, None, local, peer, config)
+}
+
+/// Creates a new client-side connection using an address validation token.
+///
+/// The `token` parameter is a token previously received from the same server
+/// in a NEW_TOKEN frame (see [`new_token_next()`]). It is sent in the
+/// connection's Initial packets, allowing the server to skip address
+/// validation.
+///
+/// The other parameters are the same as in [`connect()`].
+///
+/// [`new_token_next()`]: struct.Connection.html#method.new_token_next
+/// [`connect()`]: fn.connect.html
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// # let server_name = "quic.tech";
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let local = "127.0.0.1:0".parse().unwrap();
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// # let token = vec![0xba; 32];
+/// let conn = quiche::connect_with_token(
+///     Some(&server_name),
+///     &scid,
+///     Some(&token),
+///     local,
+///     peer,
+///     &mut config,
+/// )?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub fn connect_with_token(
+    server_name: Option<&str>, scid: &ConnectionId, token: Option<&[u8]>,
+    local: SocketAddr, peer: SocketAddr, config: &mut Config,
 ) -> Result<Pin<Box<Connection>>> {
-    let conn = Connection::new(scid, None, to, config, false)?;
+    let mut conn = Connection::new(scid, None, local, peer, config, false)?;
 
     if let Some(server_name) = server_name {
         conn.handshake.lock().unwrap().set_host_name(server_name)?;
     }
 
+    conn.token = token.map(|t| t.to_vec());
+
     Ok(conn)
 }
 
@@ -1264,7 +1717,8 @@ pub fn negotiate_version(
 ///     return Ok(());
 /// }
 ///
-/// let conn = quiche::accept(&scid, odcid.as_ref(), src, &mut config)?;
+/// let local = socket.local_addr().unwrap();
+/// let conn = quiche::accept(&scid, odcid.as_ref(), local, src, &mut config)?;
 /// # Ok::<(), quiche::Error>(())
 /// ```
 #[inline]
@@ -1275,18 +1729,85 @@ pub fn retry(
     packet::retry(scid, dcid, new_scid, token, version, out)
 }
 
+/// Writes a stateless reset packet.
+///
+/// The `token` parameter is the stateless reset token associated with the
+/// destination connection ID of the packet that couldn't be processed (e.g.
+/// because the connection state was lost), as returned by
+/// [`stateless_reset_token()`].
+///
+/// The whole `out` buffer is used, so it should be smaller than the received
+/// packet, to prevent two endpoints from exchanging stateless resets
+/// endlessly. The [`BufferTooShort`] error is returned when it's too small to
+/// hold a stateless reset.
+///
+/// [`stateless_reset_token()`]: fn.stateless_reset_token.html
+/// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut buf = [0; 512];
+/// # let mut out = [0; 512];
+/// # let secret = [0xba; 32];
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let (len, src) = socket.recv_from(&mut buf).unwrap();
+///
+/// let hdr =
+///     quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)?;
+///
+/// // No connection matches the packet's destination connection ID.
+/// if hdr.ty == quiche::Type::Short {
+///     let token = quiche::stateless_reset_token(&secret, &hdr.dcid);
+///
+///     let len = quiche::stateless_reset(token, &mut out[..len - 1])?;
+///     socket.send_to(&out[..len], &src).unwrap();
+/// }
+/// # Ok::<(), quiche::Error>(())
+/// ```
+#[inline]
+pub fn stateless_reset(token: u128, out: &mut [u8]) -> Result<usize> {
+    packet::stateless_reset(token, out)
+}
+
+/// Derives the stateless reset token of a connection ID.
+///
+/// The token is computed from the given static `secret` and connection ID, so
+/// that it can be generated again after the connection state was lost. The
+/// same secret should be passed to [`set_stateless_reset_secret()`].
+///
+/// [`set_stateless_reset_secret()`]:
+///     struct.Config.html#method.set_stateless_reset_secret
+#[inline]
+pub fn stateless_reset_token(secret: &[u8], cid: &ConnectionId) -> u128 {
+    crypto::derive_stateless_reset_token(secret, cid)
+}
+
 /// Returns true if the given protocol version is supported.
 #[inline]
 pub fn version_is_supported(version: u32) -> bool {
     matches!(
         version,
         PROTOCOL_VERSION_V1 |
+            PROTOCOL_VERSION_V2 |
             PROTOCOL_VERSION_DRAFT27 |
             PROTOCOL_VERSION_DRAFT28 |
             PROTOCOL_VERSION_DRAFT29
     )
 }
 
+// Draft versions use the legacy codepoint for the QUIC transport parameters
+// TLS extension.
+fn uses_legacy_codepoint(version: u32) -> bool {
+    !matches!(version, PROTOCOL_VERSION_V1 | PROTOCOL_VERSION_V2)
+}
+
+// Versions that a connection can be switched to with compatible version
+// negotiation, as per RFC 9368.
+fn versions_are_compatible(a: u32, b: u32) -> bool {
+    !uses_legacy_codepoint(a) && !uses_legacy_codepoint(b)
+}
+
 /// Pushes a frame to the output packet if there is enough space.
 ///
 /// Returns `true` on success, `false` otherwise. In case of failure it means
@@ -1325,27 +1846,62 @@ macro_rules! qlog_with {
 
 impl Connection {
     fn new(
-        scid: &ConnectionId, odcid: Option<&ConnectionId>, peer: SocketAddr,
-        config: &mut Config, is_server: bool,
+        scid: &ConnectionId, odcid: Option<&ConnectionId>, local: SocketAddr,
+        peer: SocketAddr, config: &mut Config, is_server: bool,
     ) -> Result<Pin<Box<Connection>>> {
         let tls = config.tls_ctx.lock().unwrap().new_handshake()?;
-        Connection::with_tls(scid, odcid, peer, config, tls, is_server)
+        Connection::with_tls(scid, odcid, local, peer, config, tls, is_server)
     }
 
     fn with_tls(
-        scid: &ConnectionId, odcid: Option<&ConnectionId>, peer: SocketAddr,
-        config: &mut Config, tls: tls::Handshake, is_server: bool,
+        scid: &ConnectionId, odcid: Option<&ConnectionId>, local: SocketAddr,
+        peer: SocketAddr, config: &mut Config, tls: tls::Handshake,
+        is_server: bool,
     ) -> Result<Pin<Box<Connection>>> {
         let max_rx_data = config.local_transport_params.initial_max_data;
 
         let scid_as_hex: Vec<String> =
             scid.iter().map(|b| format!("{:02x}", b)).collect();
 
+        let recovery_config = recovery::RecoveryConfig::from_config(config);
+
+        let mut path = path::Path::new(local, peer, &recovery_config, true);
+
+        // If we did stateless retry assume the peer's address is verified.
+        path.verified_peer_address = odcid.is_some();
+
+        // The number of paths tracked is bounded by the number of connection
+        // IDs we allow the peer to provide.
+        let paths = path::PathMap::new(
+            path,
+            config.local_transport_params.active_conn_id_limit as usize,
+        );
+
+        // Servers derive the stateless reset token of their initial connection
+        // ID, so it can be computed again if the connection state is lost.
+        let reset_token = match config.stateless_reset_secret {
+            Some(ref secret) if is_server && !scid.is_empty() =>
+                Some(crypto::derive_stateless_reset_token(secret, scid)),
+
+            _ => None,
+        };
+
         let mut conn = Box::pin(Connection {
             version: config.version,
 
-            dcid: ConnectionId::default(),
-            scid: scid.to_vec().into(),
+            original_version: config.version,
+
+            compatible_versions: config.compatible_versions.clone(),
+
+            original_version_open: None,
+
+            ids: cid::ConnectionIdentifiers::new(
+                config.local_transport_params.active_conn_id_limit,
+                scid,
+                reset_token,
+            ),
+
+            active_scid_seq: 0,
 
             trace_id: scid_as_hex.join(""),
 
@@ -1355,6 +1911,14 @@ impl Connection {
                 packet::PktNumSpace::new(),
             ],
 
+            key_phase: false,
+
+            key_generation: 0,
+
+            key_phase_pkt_count: 0,
+
+            key_update_threshold: config.key_update_threshold,
+
             peer_transport_params: TransportParams::default(),
 
             local_transport_params: config.local_transport_params.clone(),
@@ -1363,9 +1927,11 @@ impl Connection {
 
             session: None,
 
-            recovery: recovery::Recovery::new(&config),
+            paths,
 
-            peer_addr: peer,
+            recovery_config,
+
+            preferred_path_id: None,
 
             application_protos: config.application_protos.clone(),
 
@@ -1387,8 +1953,6 @@ impl Connection {
 
             stream_retrans_bytes: 0,
 
-            max_send_bytes: 0,
-
             streams: stream::StreamMap::new(
                 config.local_transport_params.initial_max_streams_bidi,
                 config.local_transport_params.initial_max_streams_uni,
@@ -1400,12 +1964,16 @@ impl Connection {
 
             token: None,
 
+            address_token_key: config.address_token_key.clone(),
+
+            new_tokens: VecDeque::new(),
+
+            received_tokens: VecDeque::new(),
+
             local_error: None,
 
             peer_error: None,
 
-            challenge: None,
-
             blocked_limit: None,
 
             idle_timer: None,
@@ -1424,10 +1992,9 @@ impl Connection {
 
             did_retry: false,
 
-            got_peer_conn_id: false,
+            did_probe_preferred_address: false,
 
-            // If we did stateless retry assume the peer's address is verified.
-            verified_peer_address: odcid.is_some(),
+            got_peer_conn_id: false,
 
             // Assume clients validate the server's address implicitly.
             peer_verified_address: is_server,
@@ -1478,12 +2045,55 @@ impl Connection {
         conn.local_transport_params.initial_source_connection_id =
             Some(scid.to_vec().into());
 
+        conn.local_transport_params.stateless_reset_token =
+            reset_token.map(|v| v.to_be_bytes().to_vec());
+
+        if let Some(f) = &config.stream_scheduler_factory {
+            conn.streams.set_scheduler(f());
+        }
+
+        // The connection ID advertised along with the preferred address gets
+        // sequence number 1, so it needs to be issued before any other.
+        let has_preferred_address = config.preferred_address_v4.is_some() ||
+            config.preferred_address_v6.is_some();
+
+        if is_server && has_preferred_address && !scid.is_empty() {
+            let mut cid = vec![0; scid.len()];
+            rand::rand_bytes(&mut cid[..]);
+            let cid: ConnectionId<'static> = cid.into();
+
+            let reset_token = match config.stateless_reset_secret {
+                Some(ref secret) =>
+                    crypto::derive_stateless_reset_token(secret, &cid),
+
+                None => {
+                    let mut reset_token = [0; 16];
+                    rand::rand_bytes(&mut reset_token[..]);
+                    u128::from_be_bytes(reset_token)
+                },
+            };
+
+            let seq = conn.ids.new_scid(cid.clone(), reset_token, false)?;
+
+            // The connection ID is sent in the transport parameters, rather
+            // than in a NEW_CONNECTION_ID frame.
+            conn.ids.mark_advertise_new_scid_seq(seq, false);
+
+            conn.local_transport_params.preferred_address =
+                Some(PreferredAddress {
+                    ipv4: config.preferred_address_v4,
+                    ipv6: config.preferred_address_v6,
+                    connection_id: cid,
+                    stateless_reset_token: reset_token,
+                });
+        }
+
         conn.handshake.lock().unwrap().init(&conn)?;
 
         conn.handshake
             .lock()
             .unwrap()
-            .use_legacy_codepoint(config.version != PROTOCOL_VERSION_V1);
+            .use_legacy_codepoint(uses_legacy_codepoint(config.version));
 
         conn.encode_transport_params()?;
 
@@ -1499,7 +2109,7 @@ impl Connection {
                 conn.is_server,
             )?;
 
-            conn.dcid = dcid.to_vec().into();
+            conn.ids.set_initial_dcid(dcid.to_vec().into(), None);
 
             conn.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_open =
                 Some(aead_open);
@@ -1634,12 +2244,17 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// loop {
     ///     let (read, from) = socket.recv_from(&mut buf).unwrap();
     ///
-    ///     let recv_info = quiche::RecvInfo { from };
+    ///     let recv_info = quiche::RecvInfo {
+    ///         from,
+    ///         to: socket.local_addr().unwrap(),
+    ///         ecn: 0,
+    ///     };
     ///
     ///     let read = match conn.recv(&mut buf[..read], recv_info) {
     ///         Ok(v) => v,
@@ -1666,9 +2281,22 @@ impl Connection {
         //
         // It doesn't matter if the packets received were valid or not, we only
         // need to track the total amount of bytes received.
-        if !self.verified_peer_address {
-            self.max_send_bytes += len * MAX_AMPLIFICATION_FACTOR;
-        }
+        //
+        // Packets received on a new path are accounted for once the path is
+        // created, as that only happens when they can be decrypted.
+        if let Some(path_id) =
+            self.paths.path_id_from_addrs(&(info.to, info.from))
+        {
+            let path = self.paths.get_mut(path_id)?;
+
+            if !path.verified_peer_address {
+                path.max_send_bytes += len * MAX_AMPLIFICATION_FACTOR;
+            }
+        }
+
+        // The datagram might be a stateless reset, whose token needs to be
+        // saved before packets are decrypted in place.
+        let reset_token = packet::peek_stateless_reset_token(buf);
 
         let mut done = 0;
         let mut left = len;
@@ -1678,6 +2306,23 @@ impl Connection {
             let read = match self.recv_single(&mut buf[len - left..len], &info) {
                 Ok(v) => v,
 
+                // A datagram that can't be processed at all might be a
+                // stateless reset sent by the peer after losing the connection
+                // state.
+                Err(Error::Done)
+                    if done == 0 &&
+                        !self.is_draining() &&
+                        self.ids.is_stateless_reset(reset_token) =>
+                {
+                    trace!("{} stateless reset received", self.trace_id);
+
+                    // Move to the draining state without sending anything.
+                    let pto = self.paths.get_active()?.recovery.pto();
+                    self.draining_timer = Some(time::Instant::now() + (pto * 3));
+
+                    return Err(Error::StatelessReset);
+                },
+
                 Err(Error::Done) => left,
 
                 Err(e) => {
@@ -1730,6 +2375,8 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        let buf_len = buf.len();
+
         if self.is_closed() || self.is_draining() {
             return Err(Error::Done);
         }
@@ -1742,8 +2389,8 @@ impl Connection {
 
         let mut b = octets::OctetsMut::with_slice(buf);
 
-        let mut hdr =
-            Header::from_bytes(&mut b, self.scid.len()).map_err(|e| {
+        let mut hdr = Header::from_bytes(&mut b, self.source_id().len())
+            .map_err(|e| {
                 drop_pkt_on_err(
                     e,
                     self.recv_count,
@@ -1769,11 +2416,11 @@ impl Connection {
                 return Err(Error::Done);
             }
 
-            if hdr.dcid != self.scid {
+            if hdr.dcid != self.source_id() {
                 return Err(Error::Done);
             }
 
-            if hdr.scid != self.dcid {
+            if hdr.scid != self.destination_id() {
                 return Err(Error::Done);
             }
 
@@ -1787,24 +2434,20 @@ impl Connection {
                 return Err(Error::Done);
             }
 
-            let supported_versions =
-                versions.iter().filter(|&&v| version_is_supported(v));
-
-            let mut found_version = false;
-
-            for &v in supported_versions {
-                found_version = true;
-
-                // The final version takes precedence over draft ones.
-                if v == PROTOCOL_VERSION_V1 {
-                    self.version = v;
-                    break;
-                }
+            // The final versions take precedence over draft ones, with version
+            // 1 being preferred.
+            let selected_version = versions
+                .iter()
+                .filter(|&&v| version_is_supported(v))
+                .max_by_key(|&&v| match v {
+                    PROTOCOL_VERSION_V1 => (2, 0),
+                    PROTOCOL_VERSION_V2 => (1, 0),
+                    _ => (0, v),
+                });
 
-                self.version = cmp::max(self.version, v);
-            }
+            self.version = match selected_version {
+                Some(&v) => v,
 
-            if !found_version {
                 // We don't support any of the versions offered.
                 //
                 // While a man-in-the-middle attacker might be able to
@@ -1812,14 +2455,16 @@ impl Connection {
                 // failure, the window of opportunity is very small and
                 // this error is quite useful for debugging, so don't just
                 // ignore the packet.
-                return Err(Error::UnknownVersion);
-            }
+                None => return Err(Error::UnknownVersion),
+            };
+
+            self.original_version = self.version;
 
             self.did_version_negotiation = true;
 
             // Derive Initial secrets based on the new version.
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
-                &self.dcid,
+                &self.destination_id(),
                 self.version,
                 self.is_server,
             )?;
@@ -1837,7 +2482,7 @@ impl Connection {
             self.handshake
                 .lock()
                 .unwrap()
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+                .use_legacy_codepoint(uses_legacy_codepoint(self.version));
 
             // Encode transport parameters again, as the new version might be
             // using a different format.
@@ -1858,8 +2503,12 @@ impl Connection {
             }
 
             // Check if Retry packet is valid.
-            if packet::verify_retry_integrity(&b, &self.dcid, self.version)
-                .is_err()
+            if packet::verify_retry_integrity(
+                &b,
+                &self.destination_id(),
+                self.version,
+            )
+            .is_err()
             {
                 return Err(Error::Done);
             }
@@ -1870,11 +2519,11 @@ impl Connection {
             self.did_retry = true;
 
             // Remember peer's new connection ID.
-            self.odcid = Some(self.dcid.clone());
+            self.odcid = Some(self.destination_id().into_owned());
 
-            self.dcid = hdr.scid.clone();
+            self.ids.set_initial_dcid(hdr.scid.to_vec().into(), None);
 
-            self.rscid = Some(self.dcid.clone());
+            self.rscid = Some(self.destination_id().into_owned());
 
             // Derive Initial secrets using the new connection ID.
             let (aead_open, aead_seal) = crypto::derive_initial_key_material(
@@ -1902,19 +2551,59 @@ impl Connection {
             }
 
             self.version = hdr.version;
+            self.original_version = hdr.version;
             self.did_version_negotiation = true;
 
             self.handshake
                 .lock()
                 .unwrap()
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+                .use_legacy_codepoint(uses_legacy_codepoint(self.version));
 
             // Encode transport parameters again, as the new version might be
             // using a different format.
             self.encode_transport_params()?;
         }
 
-        if hdr.ty != packet::Type::Short && hdr.version != self.version {
+        // The server switched the connection to a compatible version, so
+        // Initial keys need to be derived again.
+        if !self.is_server &&
+            !self.got_peer_conn_id &&
+            hdr.ty == packet::Type::Initial &&
+            hdr.version != self.version &&
+            versions_are_compatible(self.version, hdr.version) &&
+            self.compatible_versions.contains(&hdr.version)
+        {
+            trace!(
+                "{} switching to compatible version {:x}",
+                self.trace_id,
+                hdr.version
+            );
+
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &self.destination_id(),
+                hdr.version,
+                self.is_server,
+            )?;
+
+            self.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_open =
+                Some(aead_open);
+            self.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_seal =
+                Some(aead_seal);
+
+            self.version = hdr.version;
+        }
+
+        // The server keeps accepting Initial packets in the original version
+        // until the client starts using the compatible one.
+        let original_version_initial = self.is_server &&
+            hdr.ty == packet::Type::Initial &&
+            hdr.version == self.original_version &&
+            self.original_version_open.is_some();
+
+        if hdr.ty != packet::Type::Short &&
+            hdr.version != self.version &&
+            !original_version_initial
+        {
             // At this point version negotiation was already performed, so
             // ignore packets that don't match the connection's version.
             return Err(Error::Done);
@@ -1966,7 +2655,9 @@ impl Connection {
         let epoch = hdr.ty.to_epoch()?;
 
         // Select AEAD context used to open incoming packet.
-        let aead = if hdr.ty == packet::Type::ZeroRTT {
+        let aead = if original_version_initial {
+            self.original_version_open.as_ref()
+        } else if hdr.ty == packet::Type::ZeroRTT {
             // Only use 0-RTT key if incoming packet is 0-RTT.
             self.pkt_num_spaces[epoch].crypto_0rtt_open.as_ref()
         } else {
@@ -2055,6 +2746,34 @@ impl Connection {
             .ok();
         });
 
+        let mut aead_next = None;
+
+        // A short header packet with a different key phase is protected
+        // either with the previous keys (if it was reordered around a key
+        // update) or with the next ones (if the peer is updating its keys).
+        let aead =
+            if hdr.ty == packet::Type::Short && hdr.key_phase != self.key_phase {
+                match self.pkt_num_spaces[epoch].key_update {
+                    Some(ref ku) if pn < ku.pn_on_update => &ku.crypto_open,
+
+                    _ => {
+                        let seal = self.pkt_num_spaces[epoch]
+                            .crypto_seal
+                            .as_ref()
+                            .ok_or(Error::InvalidState)?;
+
+                        aead_next = Some((
+                            aead.derive_next_packet_key()?,
+                            seal.derive_next_packet_key()?,
+                        ));
+
+                        &aead_next.as_ref().unwrap().0
+                    },
+                }
+            } else {
+                aead
+            };
+
         let mut payload = packet::decrypt_pkt(
             &mut b,
             pn,
@@ -2071,6 +2790,33 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        if let Some((open_next, seal_next)) = aead_next {
+            // The peer must not initiate a new key update before it received
+            // an acknowledgement for the packet that triggered the previous
+            // one, which we would have sent with the current keys.
+            if let Some(ref ku) = self.pkt_num_spaces[epoch].key_update {
+                if ku.sent_pn_on_update >= self.pkt_num_spaces[epoch].next_pkt_num
+                {
+                    return Err(Error::KeyUpdate);
+                }
+            }
+
+            trace!("{} key update initiated by peer", self.trace_id);
+
+            self.update_keys(open_next, seal_next, Some(pn), now)?;
+        } else if hdr.ty == packet::Type::Short && hdr.key_phase == self.key_phase
+        {
+            let pto = self.paths.get_active()?.recovery.pto();
+
+            if let Some(ref mut ku) = self.pkt_num_spaces[epoch].key_update {
+                ku.pn_on_update = cmp::min(ku.pn_on_update, pn);
+
+                // Retain the old read key for some time after the peer
+                // started using the new keys.
+                ku.timer.get_or_insert(now + pto * 3);
+            }
+        }
+
         // Packets with no frames are invalid.
         if payload.cap() == 0 {
             return Err(Error::InvalidPacket);
@@ -2078,22 +2824,22 @@ impl Connection {
 
         if !self.is_server && !self.got_peer_conn_id {
             if self.odcid.is_none() {
-                self.odcid = Some(self.dcid.clone());
+                self.odcid = Some(self.destination_id().into_owned());
             }
 
             // Replace the randomly generated destination connection ID with
             // the one supplied by the server.
-            self.dcid = hdr.scid.clone();
+            self.ids.set_initial_dcid(hdr.scid.to_vec().into(), None);
 
             self.got_peer_conn_id = true;
         }
 
         if self.is_server && !self.got_peer_conn_id {
-            self.dcid = hdr.scid.clone();
+            self.ids.set_initial_dcid(hdr.scid.to_vec().into(), None);
 
             if !self.did_retry &&
                 (self.version >= PROTOCOL_VERSION_DRAFT28 ||
-                    self.version == PROTOCOL_VERSION_V1)
+                    !uses_legacy_codepoint(self.version))
             {
                 self.local_transport_params
                     .original_destination_connection_id =
@@ -2102,14 +2848,37 @@ impl Connection {
                 self.encode_transport_params()?;
             }
 
+            // A valid token received in a NEW_TOKEN frame on a previous
+            // connection validates the client's address. Invalid tokens are
+            // simply ignored, as the client might have received them from a
+            // different server.
+            if let (Some(key), Some(token)) =
+                (self.address_token_key.as_ref(), hdr.token.as_ref())
+            {
+                if !token.is_empty() &&
+                    key.validate(token, &info.from) ==
+                        Ok(AddressToken::NewToken)
+                {
+                    self.paths.get_active_mut()?.verified_peer_address = true;
+                }
+            }
+
             self.got_peer_conn_id = true;
         }
 
+        // Now that the packet was authenticated, find out which path it was
+        // received on.
+        let recv_pid = self.get_or_create_recv_path_id(&hdr, info, buf_len)?;
+
         // To avoid sending an ACK in response to an ACK-only packet, we need
         // to keep track of whether this packet contains any frame other than
         // ACK and PADDING.
         let mut ack_elicited = false;
 
+        // Whether the packet only contains probing frames, in which case it
+        // doesn't cause the peer's address to be migrated.
+        let mut probing = true;
+
         // Process packet payload.
         while payload.cap() > 0 {
             let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
@@ -2122,7 +2891,12 @@ impl Connection {
                 ack_elicited = true;
             }
 
-            if let Err(e) = self.process_frame(frame, epoch, now) {
+            if !frame.probing() {
+                probing = false;
+            }
+
+            if let Err(e) = self.process_frame(frame, &hdr, recv_pid, epoch, now)
+            {
                 qlog_with!(self.qlog_streamer, q, {
                     // Always conclude frame writing on error.
                     q.finish_frames().ok();
@@ -2137,9 +2911,42 @@ impl Connection {
             q.finish_frames().ok();
         });
 
+        // The connection was switched to a compatible version while
+        // processing the client's first flight, so the Initial packets sent
+        // from now on need to use the keys of the new version.
+        if self.is_server &&
+            hdr.ty == packet::Type::Initial &&
+            hdr.version != self.version &&
+            !original_version_initial
+        {
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &hdr.dcid,
+                self.version,
+                self.is_server,
+            )?;
+
+            self.original_version_open = self.pkt_num_spaces
+                [packet::EPOCH_INITIAL]
+                .crypto_open
+                .replace(aead_open);
+            self.pkt_num_spaces[packet::EPOCH_INITIAL].crypto_seal =
+                Some(aead_seal);
+        }
+
+        // Once the client uses the compatible version, packets in the
+        // original version are not accepted anymore.
+        if self.is_server &&
+            hdr.ty != packet::Type::Short &&
+            hdr.version == self.version
+        {
+            self.original_version_open = None;
+        }
+
         qlog_with!(self.qlog_streamer, q, {
-            let ev = self.recovery.to_qlog();
-            q.add_event_with_instant(ev, now).ok();
+            if let Ok(path) = self.paths.get_active() {
+                let ev = path.recovery.to_qlog();
+                q.add_event_with_instant(ev, now).ok();
+            }
         });
 
         // Only log the remote transport parameters once the connection is
@@ -2164,8 +2971,14 @@ impl Connection {
             });
         }
 
-        // Process acked frames.
-        for acked in self.recovery.acked[epoch].drain(..) {
+        // Process acked frames, which might have been sent on any path.
+        let acked_frames: Vec<frame::Frame> = self
+            .paths
+            .iter_mut()
+            .flat_map(|(_, p)| p.recovery.acked[epoch].drain(..))
+            .collect();
+
+        for acked in acked_frames {
             match acked {
                 frame::Frame::ACK { ranges, .. } => {
                     // Stop acknowledging packets less than or equal to the
@@ -2238,21 +3051,56 @@ impl Connection {
 
         self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
 
+        // Keep track of the ECN codepoints of received packets, to report them
+        // to the peer.
+        let ecn_counts = &mut self.pkt_num_spaces[epoch].ecn_counts;
+
+        match info.ecn & 0x03 {
+            recovery::ecn::ECT0 => ecn_counts.ect0_count += 1,
+
+            recovery::ecn::ECT1 => ecn_counts.ect1_count += 1,
+
+            recovery::ecn::CE => ecn_counts.ecn_ce_count += 1,
+
+            _ => (),
+        }
+
         self.pkt_num_spaces[epoch].ack_elicited =
             cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
 
         self.pkt_num_spaces[epoch].largest_rx_pkt_num =
             cmp::max(self.pkt_num_spaces[epoch].largest_rx_pkt_num, pn);
 
+        // A non-probing packet with the largest packet number received so far
+        // on a path other than the active one means that the peer migrated.
+        // Only servers follow such migrations, and only once the handshake is
+        // confirmed.
+        if self.is_server &&
+            !probing &&
+            self.handshake_confirmed &&
+            pn == self.pkt_num_spaces[epoch].largest_rx_pkt_num &&
+            self.paths.get_active_path_id() != Ok(recv_pid)
+        {
+            self.on_peer_migrated(recv_pid)?;
+        }
+
+        // Once the handshake is confirmed, clients start validating the
+        // server's preferred address, if any.
+        if !self.is_server &&
+            self.handshake_confirmed &&
+            !self.did_probe_preferred_address
+        {
+            self.did_probe_preferred_address = true;
+
+            self.probe_preferred_address();
+        }
+
         if let Some(idle_timeout) = self.idle_timeout() {
             self.idle_timer = Some(now + idle_timeout);
         }
 
         // Update send capacity.
-        self.tx_cap = cmp::min(
-            self.recovery.cwnd_available() as u64,
-            self.max_tx_data - self.tx_data,
-        ) as usize;
+        self.update_tx_cap();
 
         self.recv_count += 1;
 
@@ -2260,13 +3108,17 @@ impl Connection {
 
         self.recv_bytes += read as u64;
 
+        let recv_path = self.paths.get_mut(recv_pid)?;
+        recv_path.recv_count += 1;
+        recv_path.recv_bytes += read as u64;
+
         // An Handshake packet has been received from the client and has been
         // successfully processed, so we can drop the initial state and consider
         // the client's address to be verified.
         if self.is_server && hdr.ty == packet::Type::Handshake {
             self.drop_epoch_state(packet::EPOCH_INITIAL, now);
 
-            self.verified_peer_address = true;
+            self.paths.get_mut(recv_pid)?.verified_peer_address = true;
         }
 
         self.ack_eliciting_sent = false;
@@ -2305,8 +3157,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// loop {
     ///     let (write, send_info) = match conn.send(&mut out) {
     ///         Ok(v) => v,
@@ -2367,6 +3220,12 @@ impl Connection {
             return Err(Error::Done);
         }
 
+        let send_pid = self.get_send_path_id()?;
+
+        // All the packets coalesced in the same UDP datagram share the same
+        // ECN codepoint.
+        let ecn = self.paths.get(send_pid)?.recovery.ecn.codepoint();
+
         let mut has_initial = false;
 
         let mut done = 0;
@@ -2377,15 +3236,47 @@ impl Connection {
 
         // Limit data sent by the server based on the amount of data received
         // from the client before its address is validated.
-        if !self.verified_peer_address && self.is_server {
-            left = cmp::min(left, self.max_send_bytes);
+        let send_path = self.paths.get(send_pid)?;
+
+        if !send_path.verified_peer_address && self.is_server {
+            left = cmp::min(left, send_path.max_send_bytes);
+        }
+
+        // Send a PMTU probe if one is due. Probes are only sent on the active
+        // path once the handshake is confirmed, so they are never coalesced
+        // with other packets.
+        let pmtud_probe = {
+            let path = self.paths.get_mut(send_pid)?;
+
+            match path.recovery.pmtud.probe_size(time::Instant::now()) {
+                Some(size)
+                    if self.handshake_confirmed &&
+                        self.local_error.is_none() &&
+                        path.active() &&
+                        path.recovery.loss_probes
+                            [packet::EPOCH_APPLICATION] ==
+                            0 &&
+                        size <= out.len() &&
+                        size <= path.recovery.cwnd_available() =>
+                    Some(size),
+
+                _ => None,
+            }
+        };
+
+        if let Some(size) = pmtud_probe {
+            left = size;
         }
 
         // Generate coalesced packets.
         while left > 0 {
-            let (ty, written) = match self
-                .send_single(&mut out[done..done + left], has_initial)
-            {
+            let (ty, written) = match self.send_single(
+                &mut out[done..done + left],
+                send_pid,
+                has_initial,
+                ecn,
+                pmtud_probe.is_some(),
+            ) {
                 Ok(v) => v,
 
                 Err(Error::BufferTooShort) | Err(Error::Done) => break,
@@ -2408,7 +3299,7 @@ impl Connection {
             // When sending multiple PTO probes, don't coalesce them together,
             // so they are sent on separate UDP datagrams.
             if let Ok(epoch) = ty.to_epoch() {
-                if self.recovery.loss_probes[epoch] > 0 {
+                if self.paths.get(send_pid)?.recovery.loss_probes[epoch] > 0 {
                     break;
                 }
             }
@@ -2429,20 +3320,141 @@ impl Connection {
             done += pad_len;
         }
 
+        let send_path = self.paths.get(send_pid)?;
+
         let info = SendInfo {
-            to: self.peer_addr,
+            from: send_path.local_addr(),
+            to: send_path.peer_addr(),
 
-            at: self
+            at: send_path
                 .recovery
                 .get_packet_send_time()
                 .unwrap_or_else(time::Instant::now),
+
+            ecn,
         };
 
         Ok((done, info))
     }
 
+    /// Writes a batch of QUIC packets to be sent to the peer.
+    ///
+    /// This works like [`send()`], except that multiple packets are written
+    /// back-to-back in the provided buffer, so that they can be handed to the
+    /// kernel in a single system call (e.g. using `sendmsg()` with the
+    /// `UDP_SEGMENT` socket option on Linux).
+    ///
+    /// All the packets in the batch are sent to the same destination, and have
+    /// the same size, except for the last one which might be shorter. At most
+    /// `max_segments` packets are written. If the first packet is shorter than
+    /// the maximum packet size, it's returned on its own as a single-segment
+    /// batch.
+    ///
+    /// On success the total number of bytes written to the output buffer is
+    /// returned, along with the size of each segment and the [`SendInfo`] that
+    /// applies to all the packets in the batch. The `at` field refers to the
+    /// first packet of the batch.
+    ///
+    /// If no packets were written, [`Done`] is returned.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 65535];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// loop {
+    ///     let (write, segment_size, send_info) =
+    ///         match conn.send_batch(&mut out, 10) {
+    ///             Ok(v) => v,
+    ///
+    ///             Err(quiche::Error::Done) => {
+    ///                 // Done writing.
+    ///                 break;
+    ///             },
+    ///
+    ///             Err(e) => {
+    ///                 // An error occurred, handle it.
+    ///                 break;
+    ///             },
+    ///         };
+    ///
+    ///     for segment in out[..write].chunks(segment_size) {
+    ///         socket.send_to(segment, &send_info.to).unwrap();
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn send_batch(
+        &mut self, out: &mut [u8], max_segments: usize,
+    ) -> Result<(usize, usize, SendInfo)> {
+        if max_segments == 0 {
+            return Err(Error::BufferTooShort);
+        }
+
+        let max_packet_size =
+            cmp::min(out.len(), self.max_send_udp_payload_size());
+
+        let (segment_size, info) = self.send(out)?;
+
+        // The first packet determines the segment size of the whole batch, so
+        // if it's shorter than the maximum (e.g. an ACK-only packet) the batch
+        // ends right away, and following packets start a new batch.
+        if segment_size < max_packet_size {
+            return Ok((segment_size, segment_size, info));
+        }
+
+        let mut done = segment_size;
+        let mut segments = 1;
+
+        while segments < max_segments && out.len() - done >= segment_size {
+            // Only packets sent on the same path and with the same ECN
+            // codepoint can be part of the batch.
+            let send_path = match self.get_send_path_id() {
+                Ok(pid) => self.paths.get(pid)?,
+
+                Err(_) => break,
+            };
+
+            if send_path.local_addr() != info.from ||
+                send_path.peer_addr() != info.to ||
+                send_path.recovery.ecn.codepoint() != info.ecn
+            {
+                break;
+            }
+
+            let written = match self.send(&mut out[done..done + segment_size]) {
+                Ok((v, _)) => v,
+
+                // Errors are not returned here, as the packets already
+                // written would otherwise be lost. The error will be
+                // returned by the next call instead.
+                Err(_) => break,
+            };
+
+            done += written;
+            segments += 1;
+
+            // Only the last segment of the batch can be shorter.
+            if written < segment_size {
+                break;
+            }
+        }
+
+        Ok((done, segment_size, info))
+    }
+
     fn send_single(
-        &mut self, out: &mut [u8], has_initial: bool,
+        &mut self, out: &mut [u8], send_pid: usize, has_initial: bool, ecn: u8,
+        pmtud_probe: bool,
     ) -> Result<(packet::Type, usize)> {
         let now = time::Instant::now();
 
@@ -2458,14 +3470,23 @@ impl Connection {
 
         let mut b = octets::OctetsMut::with_slice(out);
 
-        let pkt_type = self.write_pkt_type()?;
+        let pkt_type = self.write_pkt_type(send_pid)?;
 
         let epoch = pkt_type.to_epoch()?;
 
         let stream_retrans_bytes = self.stream_retrans_bytes;
 
-        // Process lost frames.
-        for lost in self.recovery.lost[epoch].drain(..) {
+        // Process lost frames. Frames lost on any path are retransmitted on
+        // the path that is currently used to send packets.
+        let lost_frames: Vec<(usize, frame::Frame)> = self
+            .paths
+            .iter_mut()
+            .flat_map(|(pid, p)| {
+                p.recovery.lost[epoch].drain(..).map(move |f| (pid, f))
+            })
+            .collect();
+
+        for (lost_pid, lost) in lost_frames {
             match lost {
                 frame::Frame::CryptoHeader { offset, length } => {
                     self.pkt_num_spaces[epoch]
@@ -2530,6 +3551,10 @@ impl Connection {
                     self.handshake_done_sent = false;
                 },
 
+                frame::Frame::NewToken { token } => {
+                    self.new_tokens.push_back(token);
+                },
+
                 frame::Frame::MaxStreamData { stream_id, .. } => {
                     if self.streams.get(stream_id).is_some() {
                         self.streams.mark_almost_full(stream_id, true);
@@ -2540,6 +3565,20 @@ impl Connection {
                     self.almost_full = true;
                 },
 
+                frame::Frame::NewConnectionId { seq_num, .. } => {
+                    self.ids.mark_advertise_new_scid_seq(seq_num, true);
+                },
+
+                frame::Frame::RetireConnectionId { seq_num } => {
+                    self.ids.mark_retire_dcid_seq(seq_num, true).ok();
+                },
+
+                frame::Frame::PathChallenge { .. } => {
+                    if let Ok(p) = self.paths.get_mut(lost_pid) {
+                        p.on_challenge_lost();
+                    }
+                },
+
                 _ => (),
             }
         }
@@ -2550,8 +3589,27 @@ impl Connection {
 
         let mut left = b.cap();
 
+        let path = self.paths.get(send_pid)?;
+
+        // Only probing frames are sent on paths other than the active one.
+        let is_active = path.active();
+
         // Limit output packet size by congestion window size.
-        left = cmp::min(left, self.recovery.cwnd_available());
+        left = cmp::min(left, path.recovery.cwnd_available());
+
+        let dcid_seq = path.active_dcid_seq.ok_or(Error::InvalidState)?;
+
+        // Update the 1-RTT keys before they are used to protect too many
+        // packets.
+        if pkt_type == packet::Type::Short &&
+            self.key_phase_pkt_count >= self.key_update_limit()
+        {
+            match self.initiate_key_update() {
+                Ok(_) | Err(Error::InvalidState) => (),
+
+                Err(e) => return Err(e),
+            }
+        }
 
         let pn = self.pkt_num_spaces[epoch].next_pkt_num;
         let pn_len = packet::pkt_num_len(pn)?;
@@ -2566,8 +3624,11 @@ impl Connection {
 
             version: self.version,
 
-            dcid: ConnectionId::from_ref(&self.dcid),
-            scid: ConnectionId::from_ref(&self.scid),
+            dcid: ConnectionId::from_ref(
+                self.ids.get_dcid(dcid_seq)?.cid.as_ref(),
+            )
+            .into_owned(),
+            scid: self.source_id().into_owned(),
 
             pkt_num: 0,
             pkt_num_len: pn_len,
@@ -2582,7 +3643,7 @@ impl Connection {
             },
 
             versions: None,
-            key_phase: false,
+            key_phase: self.key_phase,
         };
 
         hdr.to_bytes(&mut b)?;
@@ -2608,14 +3669,20 @@ impl Connection {
                 // This usually happens when we try to send a new packet but
                 // failed because cwnd is almost full. In such case app_limited
                 // is set to false here to make cwnd grow when ACK is received.
-                self.recovery.update_app_limited(false);
+                self.paths
+                    .get_mut(send_pid)?
+                    .recovery
+                    .update_app_limited(false);
                 return Err(Error::Done);
             },
         }
 
         // Make sure there is enough space for the minimum payload length.
         if left < PAYLOAD_MIN_LEN {
-            self.recovery.update_app_limited(false);
+            self.paths
+                .get_mut(send_pid)?
+                .recovery
+                .update_app_limited(false);
             return Err(Error::Done);
         }
 
@@ -2642,8 +3709,9 @@ impl Connection {
         // Create ACK frame.
         if self.pkt_num_spaces[epoch].recv_pkt_need_ack.len() > 0 &&
             (self.pkt_num_spaces[epoch].ack_elicited ||
-                self.recovery.loss_probes[epoch] > 0) &&
-            !is_closing
+                self.paths.get(send_pid)?.recovery.loss_probes[epoch] > 0) &&
+            !is_closing &&
+            is_active
         {
             let ack_delay =
                 self.pkt_num_spaces[epoch].largest_rx_pkt_time.elapsed();
@@ -2652,9 +3720,19 @@ impl Connection {
                 2_u64
                     .pow(self.local_transport_params.ack_delay_exponent as u32);
 
+            let ecn_counts = self.pkt_num_spaces[epoch].ecn_counts;
+
+            // Only use ACK_ECN frames once ECN marked packets were received.
+            let ecn_counts = if ecn_counts != frame::EcnCounts::default() {
+                Some(ecn_counts)
+            } else {
+                None
+            };
+
             let frame = frame::Frame::ACK {
                 ack_delay,
                 ranges: self.pkt_num_spaces[epoch].recv_pkt_need_ack.clone(),
+                ecn_counts,
             };
 
             if push_frame_to_pkt!(b, frames, frame, left) {
@@ -2662,7 +3740,25 @@ impl Connection {
             }
         }
 
-        if pkt_type == packet::Type::Short && !is_closing {
+        // PMTU probes only carry PING and PADDING frames, other than ACK, so
+        // their loss doesn't require retransmitting anything. The padding
+        // fills the whole packet, so no other frame is added to it.
+        if pmtud_probe && pkt_type == packet::Type::Short {
+            let frame = frame::Frame::Ping;
+
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                ack_eliciting = true;
+                in_flight = true;
+            }
+
+            let frame = frame::Frame::Padding { len: left };
+
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                in_flight = true;
+            }
+        }
+
+        if pkt_type == packet::Type::Short && !is_closing && is_active {
             // Create HANDSHAKE_DONE frame.
             if self.is_established() &&
                 !self.handshake_done_sent &&
@@ -2678,6 +3774,22 @@ impl Connection {
                 }
             }
 
+            // Create NEW_TOKEN frames.
+            while let Some(token) = self.new_tokens.front() {
+                let frame = frame::Frame::NewToken {
+                    token: token.clone(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.new_tokens.pop_front();
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    break;
+                }
+            }
+
             // Create MAX_STREAMS_BIDI frame.
             if self.streams.should_update_max_streams_bidi() {
                 let frame = frame::Frame::MaxStreamsBidi {
@@ -2825,10 +3937,51 @@ impl Connection {
                     in_flight = true;
                 }
             }
+
+            // Create NEW_CONNECTION_ID frames as needed.
+            while let Some(seq_num) = self.ids.next_advertise_new_scid_seq() {
+                let e = self.ids.get_scid(seq_num)?;
+
+                let frame = frame::Frame::NewConnectionId {
+                    seq_num,
+                    retire_prior_to: self.ids.retire_prior_to(),
+                    conn_id: e.cid.to_vec(),
+                    reset_token: e
+                        .reset_token
+                        .ok_or(Error::InvalidState)?
+                        .to_be_bytes()
+                        .to_vec(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.ids.mark_advertise_new_scid_seq(seq_num, false);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    break;
+                }
+            }
+
+            // Create RETIRE_CONNECTION_ID frames as needed.
+            while let Some(seq_num) = self.ids.next_retire_dcid_seq() {
+                let frame = frame::Frame::RetireConnectionId { seq_num };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.ids.mark_retire_dcid_seq(seq_num, false)?;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    break;
+                }
+            }
         }
 
         // Create CONNECTION_CLOSE frame.
-        if let Some(conn_err) = self.local_error.as_ref() {
+        if let (Some(conn_err), true) = (self.local_error.as_ref(), is_active) {
+            let pto = self.paths.get(send_pid)?.recovery.pto();
+
             if conn_err.is_app {
                 // Create ApplicationClose frame.
                 if pkt_type == packet::Type::Short {
@@ -2838,8 +3991,7 @@ impl Connection {
                     };
 
                     if push_frame_to_pkt!(b, frames, frame, left) {
-                        self.draining_timer =
-                            Some(now + (self.recovery.pto() * 3));
+                        self.draining_timer = Some(now + (pto * 3));
 
                         ack_eliciting = true;
                         in_flight = true;
@@ -2854,7 +4006,7 @@ impl Connection {
                 };
 
                 if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.draining_timer = Some(now + (self.recovery.pto() * 3));
+                    self.draining_timer = Some(now + (pto * 3));
 
                     ack_eliciting = true;
                     in_flight = true;
@@ -2862,24 +4014,56 @@ impl Connection {
             }
         }
 
-        // Create PATH_RESPONSE frame.
-        if let Some(ref challenge) = self.challenge {
-            let frame = frame::Frame::PathResponse {
-                data: challenge.clone(),
-            };
+        // Whether the packet carries PATH_CHALLENGE or PATH_RESPONSE frames,
+        // in which case it needs to be padded.
+        let mut is_probe = false;
 
-            if push_frame_to_pkt!(b, frames, frame, left) {
-                self.challenge = None;
+        if pkt_type == packet::Type::Short && !is_closing {
+            let path = self.paths.get_mut(send_pid)?;
 
-                ack_eliciting = true;
-                in_flight = true;
+            // Create PATH_RESPONSE frames, echoing the challenges received on
+            // this path.
+            while let Some(data) = path.pop_received_challenge() {
+                let frame = frame::Frame::PathResponse {
+                    data: data.to_vec(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    ack_eliciting = true;
+                    in_flight = true;
+
+                    is_probe = true;
+                } else {
+                    path.on_challenge_received(data);
+                    break;
+                }
+            }
+
+            // Create PATH_CHALLENGE frame.
+            if path.validation_requested() {
+                let mut data = [0; 8];
+                rand::rand_bytes(&mut data[..]);
+
+                let frame = frame::Frame::PathChallenge {
+                    data: data.to_vec(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    path.on_challenge_sent(data, now);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+
+                    is_probe = true;
+                }
             }
         }
 
         // Create CRYPTO frame.
         if self.pkt_num_spaces[epoch].crypto_stream.is_flushable() &&
             left > frame::MAX_CRYPTO_OVERHEAD &&
-            !is_closing
+            !is_closing &&
+            is_active
         {
             let crypto_off =
                 self.pkt_num_spaces[epoch].crypto_stream.send.off_front();
@@ -2962,6 +4146,7 @@ impl Connection {
         if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
             left > frame::MAX_DGRAM_OVERHEAD &&
             !is_closing &&
+            is_active &&
             do_dgram
         {
             if let Some(max_dgram_payload) = self.dgram_max_writable_len() {
@@ -2995,6 +4180,7 @@ impl Connection {
         if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
             left > frame::MAX_STREAM_OVERHEAD &&
             !is_closing &&
+            is_active &&
             !dgram_emitted
         {
             while let Some(stream_id) = self.streams.pop_flushable() {
@@ -3079,11 +4265,15 @@ impl Connection {
                     has_data = true;
                 }
 
+                let flushable = stream.is_flushable();
+                let urgency = stream.urgency;
+                let incremental = stream.incremental;
+
+                self.streams.on_flushable_sent(stream_id, len);
+
                 // If the stream is still flushable, push it to the back of the
                 // queue again.
-                if stream.is_flushable() {
-                    let urgency = stream.urgency;
-                    let incremental = stream.incremental;
+                if flushable {
                     self.streams.push_flushable(stream_id, urgency, incremental);
                 }
 
@@ -3101,11 +4291,14 @@ impl Connection {
         // Alternate trying to send DATAGRAMs next time.
         self.emit_dgram = !dgram_emitted;
 
+        let path = self.paths.get_mut(send_pid)?;
+
         // Create PING for PTO probe if no other ack-elicitng frame is sent.
-        if self.recovery.loss_probes[epoch] > 0 &&
+        if path.recovery.loss_probes[epoch] > 0 &&
             !ack_eliciting &&
             left >= 1 &&
-            !is_closing
+            !is_closing &&
+            is_active
         {
             let frame = frame::Frame::Ping;
 
@@ -3116,17 +4309,37 @@ impl Connection {
         }
 
         if ack_eliciting {
-            self.recovery.loss_probes[epoch] =
-                self.recovery.loss_probes[epoch].saturating_sub(1);
+            path.recovery.loss_probes[epoch] =
+                path.recovery.loss_probes[epoch].saturating_sub(1);
         }
 
         if frames.is_empty() {
             // When we reach this point we are not able to write more, so set
             // app_limited to false.
-            self.recovery.update_app_limited(false);
+            path.recovery.update_app_limited(false);
             return Err(Error::Done);
         }
 
+        // Datagrams carrying PATH_CHALLENGE and PATH_RESPONSE frames need to
+        // be expanded to the minimum QUIC datagram size, to make sure the path
+        // supports it. This is subject to the anti-amplification limit, which
+        // is already accounted for in the size of the output buffer.
+        if is_probe {
+            let pkt_len = b.off() + crypto_overhead;
+
+            if pkt_len < MIN_CLIENT_INITIAL_LEN {
+                let pad_len = cmp::min(left, MIN_CLIENT_INITIAL_LEN - pkt_len);
+
+                if pad_len > 0 {
+                    let frame = frame::Frame::Padding { len: pad_len };
+
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        in_flight = true;
+                    }
+                }
+            }
+        }
+
         // When coalescing a 1-RTT packet, we can't add padding in the UDP
         // datagram, so use PADDING frames instead.
         //
@@ -3239,37 +4452,50 @@ impl Connection {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data,
+            ecn_marked: ecn != recovery::ecn::NOT_ECT,
+            pmtud_probe: pmtud_probe && ack_eliciting,
         };
 
-        self.recovery.on_packet_sent(
+        let handshake_status = self.handshake_status();
+
+        let path = self.paths.get_mut(send_pid)?;
+
+        path.recovery.on_packet_sent(
             sent_pkt,
             epoch,
-            self.handshake_status(),
+            handshake_status,
             now,
             &self.trace_id,
         );
 
         qlog_with!(self.qlog_streamer, q, {
-            let ev = self.recovery.to_qlog();
+            let ev = path.recovery.to_qlog();
             q.add_event_with_instant(ev, now).ok();
         });
 
+        if self.dgram_send_queue.byte_size() > path.recovery.cwnd_available() {
+            path.recovery.update_app_limited(false);
+        }
+
+        path.max_send_bytes = path.max_send_bytes.saturating_sub(written);
+
+        path.sent_count += 1;
+        path.sent_bytes += written as u64;
+
+        if pkt_type == packet::Type::Short {
+            self.key_phase_pkt_count += 1;
+        }
+
         self.pkt_num_spaces[epoch].next_pkt_num += 1;
 
         self.sent_count += 1;
         self.sent_bytes += written as u64;
 
-        if self.dgram_send_queue.byte_size() > self.recovery.cwnd_available() {
-            self.recovery.update_app_limited(false);
-        }
-
         // On the client, drop initial state after sending an Handshake packet.
         if !self.is_server && hdr.ty == packet::Type::Handshake {
             self.drop_epoch_state(packet::EPOCH_INITIAL, now);
         }
 
-        self.max_send_bytes = self.max_send_bytes.saturating_sub(written);
-
         // (Re)start the idle timer if we are sending the first ack-eliciting
         // packet since last receiving a packet.
         if ack_eliciting && !self.ack_eliciting_sent {
@@ -3302,8 +4528,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// # let stream_id = 0;
     /// while let Ok((read, fin)) = conn.stream_recv(stream_id, &mut buf) {
     ///     println!("Got {} bytes on stream {}", read, stream_id);
@@ -3313,6 +4540,64 @@ impl Connection {
     pub fn stream_recv(
         &mut self, stream_id: u64, out: &mut [u8],
     ) -> Result<(usize, bool)> {
+        self.stream_recv_with(stream_id, |recv| recv.emit(out))
+    }
+
+    /// Reads contiguous data from a stream without copying it.
+    ///
+    /// Instead of copying data into an application buffer like
+    /// [`stream_recv()`] does, the provided function is called with each chunk
+    /// of buffered data in order, along with a flag indicating whether the
+    /// chunk ends at the stream's final offset.
+    ///
+    /// The function returns the number of bytes it consumed from the chunk.
+    /// If that is lower than the chunk's length (e.g. because the data is
+    /// forwarded to a socket that can't take more of it), no more chunks are
+    /// passed to the function, and the rest of the data can be read later on.
+    /// Flow control credit is issued to the peer in the same way as when
+    /// reading with [`stream_recv()`].
+    ///
+    /// On success the amount of bytes consumed and a flag indicating the fin
+    /// state is returned as a tuple, or [`Done`] if there is no data to read.
+    ///
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`Done`]: enum.Error.html#variant.Done
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// # let mut out = std::io::sink();
+    /// use std::io::Write;
+    ///
+    /// conn.stream_recv_chunks(stream_id, |chunk, _fin| {
+    ///     out.write(chunk).unwrap_or(0)
+    /// })?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_recv_chunks<F>(
+        &mut self, stream_id: u64, f: F,
+    ) -> Result<(usize, bool)>
+    where
+        F: FnMut(&[u8], bool) -> usize,
+    {
+        self.stream_recv_with(stream_id, |recv| recv.emit_chunks(f))
+    }
+
+    /// Reads data from a stream using the `emit` function, which is given the
+    /// stream's receive buffer.
+    fn stream_recv_with<F>(
+        &mut self, stream_id: u64, emit: F,
+    ) -> Result<(usize, bool)>
+    where
+        F: FnOnce(&mut stream::RecvBuf) -> Result<(usize, bool)>,
+    {
         // We can't read on our own unidirectional streams.
         if !stream::is_bidi(stream_id) &&
             stream::is_local(stream_id, self.is_server)
@@ -3334,7 +4619,7 @@ impl Connection {
         #[cfg(feature = "qlog")]
         let offset = stream.recv.off_front();
 
-        let (read, fin) = match stream.recv.emit(out) {
+        let (read, fin) = match emit(&mut stream.recv) {
             Ok(v) => v,
 
             Err(e) => {
@@ -3425,8 +4710,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// # let stream_id = 0;
     /// conn.stream_send(stream_id, b"hello", true)?;
     /// # Ok::<(), quiche::Error>(())
@@ -3434,6 +4720,69 @@ impl Connection {
     pub fn stream_send(
         &mut self, stream_id: u64, buf: &[u8], fin: bool,
     ) -> Result<usize> {
+        self.stream_send_with(stream_id, buf.len(), fin, |send, len, fin| {
+            send.write(&buf[..len], fin)
+        })
+    }
+
+    /// Writes data to a stream, taking ownership of the buffer holding it.
+    ///
+    /// This behaves like [`stream_send()`], except that the data is not
+    /// copied into the stream's send buffer. Instead the buffer is kept by
+    /// reference until the data is acknowledged by the peer, including when it
+    /// needs to be retransmitted.
+    ///
+    /// The buffer can be anything that converts into a [`SharedBuf`], such as
+    /// a `Vec<u8>`, or a `SharedBuf` wrapping another buffer type.
+    ///
+    /// In case of partial writes, the data that was not written is dropped
+    /// along with the buffer, so applications that might need to retry
+    /// should keep a clone of the `SharedBuf` (which doesn't copy the data),
+    /// and slice it based on the number of written bytes returned.
+    ///
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`SharedBuf`]: struct.SharedBuf.html
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// let body = quiche::SharedBuf::from(vec![42; 100_000]);
+    ///
+    /// let written = conn.stream_send_owned(stream_id, body.clone(), true)?;
+    ///
+    /// // Retry the rest of the body later on.
+    /// let rest = body.slice(written..);
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_send_owned<B>(
+        &mut self, stream_id: u64, buf: B, fin: bool,
+    ) -> Result<usize>
+    where
+        B: Into<SharedBuf>,
+    {
+        let buf = buf.into();
+
+        self.stream_send_with(stream_id, buf.len(), fin, |send, len, fin| {
+            send.write_shared(buf.slice(..len), fin)
+        })
+    }
+
+    /// Writes `buf_len` bytes of data to a stream using the `write` function,
+    /// which is given the stream's send buffer and the number of bytes to
+    /// write to it, after applying the connection's flow control limits.
+    fn stream_send_with<F>(
+        &mut self, stream_id: u64, buf_len: usize, fin: bool, write: F,
+    ) -> Result<usize>
+    where
+        F: FnOnce(&mut stream::SendBuf, usize, bool) -> Result<usize>,
+    {
         // We can't write on the peer's unidirectional streams.
         if !stream::is_bidi(stream_id) &&
             !stream::is_local(stream_id, self.is_server)
@@ -3446,7 +4795,7 @@ impl Connection {
         //
         // Note that this is separate from "send capacity" as that also takes
         // congestion control into consideration.
-        if self.max_tx_data - self.tx_data < buf.len() as u64 {
+        if self.max_tx_data - self.tx_data < buf_len as u64 {
             self.blocked_limit = Some(self.max_tx_data);
         }
 
@@ -3457,10 +4806,10 @@ impl Connection {
             return Err(Error::Done);
         }
 
-        let (buf, fin) = if cap < buf.len() {
-            (&buf[..cap], false)
+        let (buf_len, fin) = if cap < buf_len {
+            (cap, false)
         } else {
-            (buf, fin)
+            (buf_len, fin)
         };
 
         // Get existing stream or create a new one.
@@ -3471,7 +4820,7 @@ impl Connection {
 
         let was_flushable = stream.is_flushable();
 
-        let sent = match stream.send.write(buf, fin) {
+        let sent = match write(&mut stream.send, buf_len, fin) {
             Ok(v) => v,
 
             Err(e) => {
@@ -3487,9 +4836,9 @@ impl Connection {
 
         let writable = stream.is_writable();
 
-        let empty_fin = buf.is_empty() && fin;
+        let empty_fin = buf_len == 0 && fin;
 
-        if sent < buf.len() {
+        if sent < buf_len {
             let max_off = stream.send.max_off();
 
             self.streams.mark_blocked(stream_id, true, max_off);
@@ -3514,7 +4863,9 @@ impl Connection {
 
         self.tx_data += sent as u64;
 
-        self.recovery.rate_check_app_limited();
+        if let Ok(path) = self.paths.get_active_mut() {
+            path.recovery.rate_check_app_limited();
+        }
 
         qlog_with!(self.qlog_streamer, q, {
             let ev = qlog::event::Event::h3_data_moved(
@@ -3556,10 +4907,22 @@ impl Connection {
             return Ok(());
         }
 
+        let old_urgency = stream.urgency;
+        let old_incremental = stream.incremental;
+
         stream.urgency = urgency;
         stream.incremental = incremental;
 
-        // TODO: reprioritization
+        let flushable = stream.is_flushable();
+
+        // If the stream is already waiting to be flushed, move it to the queue
+        // corresponding to its new priority.
+        self.streams
+            .remove_flushable(stream_id, old_urgency, old_incremental);
+
+        if flushable {
+            self.streams.push_flushable(stream_id, urgency, incremental);
+        }
 
         Ok(())
     }
@@ -3746,8 +5109,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// // Iterate over readable streams.
     /// for stream_id in conn.readable() {
     ///     // Stream is readable, read until there's no more data.
@@ -3781,8 +5145,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// // Iterate over writable streams.
     /// for stream_id in conn.writable() {
     ///     // Stream is writable, write some data.
@@ -3817,10 +5182,17 @@ impl Connection {
     ///     struct.Config.html#method.set_max_send_udp_payload_size
     /// [`send()`]: struct.Connection.html#method.send
     pub fn max_send_udp_payload_size(&self) -> usize {
-        if self.is_established() {
+        let max_datagram_size = self
+            .paths
+            .get_active()
+            .map(|p| p.recovery.max_datagram_size());
+
+        if let (true, Ok(max_datagram_size)) =
+            (self.is_established(), max_datagram_size)
+        {
             // We cap the maximum packet size to 16KB or so, so that it can be
             // always encoded with a 2-byte varint.
-            cmp::min(16383, self.recovery.max_datagram_size())
+            cmp::min(16383, max_datagram_size)
         } else {
             // Allow for 1200 bytes (minimum QUIC packet size) during the
             // handshake.
@@ -3847,8 +5219,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// let mut dgram_buf = [0; 512];
     /// while let Ok((len)) = conn.dgram_recv(&mut dgram_buf) {
     ///     println!("Got {} bytes of DATAGRAM", len);
@@ -3945,8 +5318,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// conn.dgram_send(b"hello")?;
     /// # Ok::<(), quiche::Error>(())
     /// ```
@@ -3964,8 +5338,12 @@ impl Connection {
 
         self.dgram_send_queue.push(buf)?;
 
-        if self.dgram_send_queue.byte_size() > self.recovery.cwnd_available() {
-            self.recovery.update_app_limited(false);
+        let active_path = self.paths.get_active_mut()?;
+
+        if self.dgram_send_queue.byte_size() >
+            active_path.recovery.cwnd_available()
+        {
+            active_path.recovery.update_app_limited(false);
         }
 
         Ok(())
@@ -3980,8 +5358,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// conn.dgram_send(b"hello")?;
     /// conn.dgram_purge_outgoing(&|d: &[u8]| -> bool { d[0] == 0 });
     /// # Ok::<(), quiche::Error>(())
@@ -4003,8 +5382,9 @@ impl Connection {
     /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let from = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, from, &mut config)?;
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
     /// if let Some(payload_size) = conn.dgram_max_writable_len() {
     ///     if payload_size > 5 {
     ///         conn.dgram_send(b"hello")?;
@@ -4021,7 +5401,7 @@ impl Connection {
                 let mut max_len = self.max_send_udp_payload_size();
                 // ...subtract the Short packet header overhead...
                 // (1 byte of pkt_len + len of dcid)
-                max_len = max_len.saturating_sub(1 + self.dcid.len());
+                max_len = max_len.saturating_sub(1 + self.destination_id().len());
                 // ...subtract the packet number (max len)...
                 max_len = max_len.saturating_sub(packet::MAX_PKT_NUM_LEN);
                 // ...subtract the crypto overhead...
@@ -4051,36 +5431,48 @@ impl Connection {
     ///
     /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
     pub fn timeout(&self) -> Option<time::Duration> {
+        let timeout = self.timeout_instant()?;
+
+        let now = time::Instant::now();
+
+        if timeout <= now {
+            return Some(time::Duration::new(0, 0));
+        }
+
+        Some(timeout.duration_since(now))
+    }
+
+    /// Returns the point in time of the next timeout event.
+    pub(crate) fn timeout_instant(&self) -> Option<time::Instant> {
         if self.is_closed() {
             return None;
         }
 
-        let timeout = if self.is_draining() {
+        if self.is_draining() {
             // Draining timer takes precedence over all other timers. If it is
             // set it means the connection is closing so there's no point in
             // processing the other timers.
             self.draining_timer
         } else {
-            // Use the lowest timer value (i.e. "sooner") among idle and loss
-            // detection timers. If they are both unset (i.e. `None`) then the
-            // result is `None`, but if at least one of them is set then a
-            // `Some(...)` value is returned.
-            let timers = [self.idle_timer, self.recovery.loss_detection_timer()];
-
-            timers.iter().filter_map(|&x| x).min()
-        };
-
-        if let Some(timeout) = timeout {
-            let now = time::Instant::now();
+            // Use the lowest timer value (i.e. "sooner") among idle, loss
+            // detection, path validation and key update timers. If they are all
+            // unset (i.e. `None`) then the result is `None`, but if at least one
+            // of them is set then a `Some(...)` value is returned.
+            let path_timers = self.paths.iter().flat_map(|(_, p)| {
+                vec![p.recovery.loss_detection_timer(), p.validation_timer()]
+            });
 
-            if timeout <= now {
-                return Some(time::Duration::new(0, 0));
-            }
+            let key_update_timer = self.pkt_num_spaces[packet::EPOCH_APPLICATION]
+                .key_update
+                .as_ref()
+                .and_then(|ku| ku.timer);
 
-            return Some(timeout.duration_since(now));
+            path_timers
+                .chain(std::iter::once(self.idle_timer))
+                .chain(std::iter::once(key_update_timer))
+                .flatten()
+                .min()
         }
-
-        None
     }
 
     /// Processes a timeout event.
@@ -4119,24 +5511,61 @@ impl Connection {
             }
         }
 
-        if let Some(timer) = self.recovery.loss_detection_timer() {
+        let key_update_timer = self.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .key_update
+            .as_ref()
+            .and_then(|ku| ku.timer);
+
+        if let Some(timer) = key_update_timer {
             if timer <= now {
-                trace!("{} loss detection timeout expired", self.trace_id);
+                trace!("{} key update timeout expired", self.trace_id);
 
-                self.recovery.on_loss_detection_timeout(
-                    self.handshake_status(),
-                    now,
-                    &self.trace_id,
-                );
+                // Drop the old read key.
+                self.pkt_num_spaces[packet::EPOCH_APPLICATION].key_update = None;
+            }
+        }
 
-                qlog_with!(self.qlog_streamer, q, {
-                    let ev = self.recovery.to_qlog();
-                    q.add_event_with_instant(ev, now).ok();
-                });
+        let handshake_status = self.handshake_status();
 
-                return;
+        for (_, path) in self.paths.iter_mut() {
+            if let Some(timer) = path.recovery.loss_detection_timer() {
+                if timer <= now {
+                    trace!("{} loss detection timeout expired", self.trace_id);
+
+                    path.recovery.on_loss_detection_timeout(
+                        handshake_status,
+                        now,
+                        &self.trace_id,
+                    );
+
+                    qlog_with!(self.qlog_streamer, q, {
+                        if path.recovery.take_persistent_congestion() {
+                            let ev =
+                                qlog::event::Event::congestion_state_updated_min(
+                                    "persistent_congestion".to_string(),
+                                );
+                            q.add_event_with_instant(ev, now).ok();
+                        }
+
+                        let ev = path.recovery.to_qlog();
+                        q.add_event_with_instant(ev, now).ok();
+                    });
+                }
             }
         }
+
+        let expired_validations: Vec<usize> = self
+            .paths
+            .iter()
+            .filter(|(_, p)| matches!(p.validation_timer(), Some(t) if t <= now))
+            .map(|(pid, _)| pid)
+            .collect();
+
+        for pid in expired_validations {
+            trace!("{} path validation timeout expired", self.trace_id);
+
+            self.on_path_validation_failed(pid);
+        }
     }
 
     /// Closes the connection with the given error and reason.
@@ -4189,6 +5618,15 @@ impl Connection {
         &self.trace_id
     }
 
+    /// Returns the QUIC version used by the connection.
+    ///
+    /// This might change during the handshake, as a result of version
+    /// negotiation.
+    #[inline]
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
     /// Returns the negotiated ALPN protocol.
     ///
     /// If no protocol has been negotiated, the returned value is empty.
@@ -4220,7 +5658,25 @@ impl Connection {
     /// lifetime.
     #[inline]
     pub fn source_id(&self) -> ConnectionId {
-        ConnectionId::from_ref(self.scid.as_ref())
+        match self.ids.get_scid(self.active_scid_seq) {
+            Ok(e) => ConnectionId::from_ref(e.cid.as_ref()),
+
+            Err(_) => ConnectionId::default(),
+        }
+    }
+
+    /// Returns all the source connection IDs that are in use by the
+    /// connection.
+    ///
+    /// This includes IDs provided with [`new_source_cid()`] as well as the
+    /// initial one, as long as they were not retired by the peer. Servers
+    /// sitting behind a load balancer can use this to route all of them to
+    /// the same connection.
+    ///
+    /// [`new_source_cid()`]: struct.Connection.html#method.new_source_cid
+    #[inline]
+    pub fn source_ids(&self) -> impl Iterator<Item = &ConnectionId<'static>> {
+        self.ids.scids_iter()
     }
 
     /// Returns the destination connection ID.
@@ -4229,117 +5685,508 @@ impl Connection {
     /// lifetime.
     #[inline]
     pub fn destination_id(&self) -> ConnectionId {
-        ConnectionId::from_ref(self.dcid.as_ref())
+        let dcid_seq =
+            self.paths.get_active().ok().and_then(|p| p.active_dcid_seq);
+
+        match dcid_seq.map(|seq| self.ids.get_dcid(seq)) {
+            Some(Ok(e)) => ConnectionId::from_ref(e.cid.as_ref()),
+
+            _ => ConnectionId::default(),
+        }
     }
 
-    /// Returns true if the connection handshake is complete.
-    #[inline]
-    pub fn is_established(&self) -> bool {
-        self.handshake_completed
+    /// Provides a new source connection ID to the peer.
+    ///
+    /// The new connection ID is advertised to the peer with a
+    /// NEW_CONNECTION_ID frame along with the given stateless reset token,
+    /// and the application should start routing packets carrying it to this
+    /// connection.
+    ///
+    /// The number of connection IDs that can be provided is bounded by the
+    /// peer's `active_connection_id_limit` transport parameter (see
+    /// [`source_cids_left()`]). When the limit is reached and
+    /// `retire_if_needed` is true, the peer is asked to retire the oldest
+    /// connection ID, otherwise [`IdLimit`] is returned.
+    ///
+    /// If the connection uses zero-length source connection IDs, or `scid`
+    /// was already provided with a different reset token, [`InvalidState`]
+    /// is returned.
+    ///
+    /// On success the sequence number of the new connection ID is returned.
+    ///
+    /// [`source_cids_left()`]: struct.Connection.html#method.source_cids_left
+    /// [`IdLimit`]: enum.Error.html#variant.IdLimit
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let new_scid = quiche::ConnectionId::from_ref(&[0xbb; 16]);
+    /// # let reset_token = 0xbabababa;
+    /// if conn.source_cids_left() > 0 {
+    ///     conn.new_source_cid(&new_scid, reset_token, false)?;
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn new_source_cid(
+        &mut self, scid: &ConnectionId, reset_token: u128, retire_if_needed: bool,
+    ) -> Result<u64> {
+        let seq = self.ids.new_scid(
+            scid.to_vec().into(),
+            reset_token,
+            retire_if_needed,
+        )?;
+
+        // Stop using the connection ID in use if the peer was asked to retire
+        // it.
+        if self.active_scid_seq < self.ids.retire_prior_to() {
+            self.active_scid_seq = seq;
+        }
+
+        Ok(seq)
     }
 
-    /// Returns true if the connection is resumed.
+    /// Returns the number of source connection IDs that are active, that is,
+    /// that the peer was not asked to retire.
     #[inline]
-    pub fn is_resumed(&self) -> bool {
-        self.handshake.lock().unwrap().is_resumed()
+    pub fn active_source_cids(&self) -> usize {
+        self.ids.active_source_cids()
     }
 
-    /// Returns true if the connection has a pending handshake that has
-    /// progressed enough to send or receive early data.
+    /// Returns the number of source connection IDs that can still be
+    /// provided to the peer with [`new_source_cid()`] without exceeding its
+    /// limit.
+    ///
+    /// [`new_source_cid()`]: struct.Connection.html#method.new_source_cid
     #[inline]
-    pub fn is_in_early_data(&self) -> bool {
-        self.handshake.lock().unwrap().is_in_early_data()
+    pub fn source_cids_left(&self) -> usize {
+        self.ids.source_cids_left()
     }
 
-    /// Returns whether there is stream or DATAGRAM data available to read.
+    /// Returns the number of destination connection IDs provided by the peer
+    /// that are available for use.
     #[inline]
-    pub fn is_readable(&self) -> bool {
-        self.streams.has_readable() || self.dgram_recv_front_len().is_some()
+    pub fn available_dcids(&self) -> usize {
+        self.ids.available_dcids()
     }
 
-    /// Returns true if the connection is draining.
+    /// Returns the oldest source connection ID that was retired by the peer,
+    /// if any.
     ///
-    /// If this returns true, the connection object cannot yet be dropped, but
-    /// no new application data can be sent or received. An application should
-    /// continue calling the [`recv()`], [`send()`], [`timeout()`], and
-    /// [`on_timeout()`] methods as normal, until the [`is_closed()`] method
-    /// returns `true`.
+    /// Retired connection IDs are not used by the peer anymore, so the
+    /// application can stop routing packets carrying them to this connection.
+    /// Each ID is only returned once.
     ///
-    /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`send()`]: struct.Connection.html#method.send
-    /// [`timeout()`]: struct.Connection.html#method.timeout
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let mut routes = std::collections::HashMap::<Vec<u8>, usize>::new();
+    /// while let Some(retired_scid) = conn.retired_scid_next() {
+    ///     routes.remove(retired_scid.as_ref());
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
     #[inline]
-    pub fn is_draining(&self) -> bool {
-        self.draining_timer.is_some()
+    pub fn retired_scid_next(&mut self) -> Option<ConnectionId<'static>> {
+        self.ids.pop_retired_scid()
     }
 
-    /// Returns true if the connection is closed.
+    /// Returns the number of source connection IDs that were retired by the
+    /// peer and not yet returned by [`retired_scid_next()`].
     ///
-    /// If this returns true, the connection object can be dropped.
+    /// [`retired_scid_next()`]: struct.Connection.html#method.retired_scid_next
     #[inline]
-    pub fn is_closed(&self) -> bool {
-        self.closed
+    pub fn retired_scids(&self) -> usize {
+        self.ids.retired_scids()
     }
 
-    /// Returns the error received from the peer, if any.
+    /// Starts probing the network path between the given addresses.
     ///
-    /// The values contained in the tuple are symmetric with the [`close()`]
-    /// method.
+    /// A PATH_CHALLENGE frame is sent on the new path, the outcome of its
+    /// validation being reported with [`path_event_next()`]. Packets for the
+    /// path are returned by [`send()`] with the appropriate [`SendInfo`]
+    /// addresses, and the application needs to pass the addresses of packets
+    /// received on it in [`RecvInfo`].
     ///
-    /// Note that a `Some` return value does not necessarily imply
-    /// [`is_closed()`] or any other connection state.
+    /// Only clients can probe new paths, and only once the handshake is
+    /// confirmed. If the peer disabled active migration, [`InvalidState`] is
+    /// returned.
     ///
-    /// [`close()`]: struct.Connection.html#method.close
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    #[inline]
-    pub fn peer_error(&self) -> Option<&ConnectionError> {
-        self.peer_error.as_ref()
+    /// As each path requires its own destination connection ID, probing fails
+    /// with [`OutOfIdentifiers`] when there is no spare one, unless the peer
+    /// uses zero-length connection IDs.
+    ///
+    /// On success the sequence number of the destination connection ID used
+    /// on the path is returned.
+    ///
+    /// [`path_event_next()`]: struct.Connection.html#method.path_event_next
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`SendInfo`]: struct.SendInfo.html
+    /// [`RecvInfo`]: struct.RecvInfo.html
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`OutOfIdentifiers`]: enum.Error.html#variant.OutOfIdentifiers
+    pub fn probe_path(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<u64> {
+        let pid = match self.paths.path_id_from_addrs(&(local_addr, peer_addr)) {
+            Some(pid) => pid,
+
+            None => self.create_client_path(local_addr, peer_addr)?,
+        };
+
+        let path = self.paths.get_mut(pid)?;
+
+        path.request_validation();
+
+        path.active_dcid_seq.ok_or(Error::InvalidState)
     }
 
-    /// Collects and returns statistics about the connection.
-    #[inline]
-    pub fn stats(&self) -> Stats {
-        Stats {
-            recv: self.recv_count,
-            sent: self.sent_count,
-            lost: self.recovery.lost_count,
-            retrans: self.retrans_count,
-            cwnd: self.recovery.cwnd(),
-            rtt: self.recovery.rtt(),
-            sent_bytes: self.sent_bytes,
-            lost_bytes: self.recovery.bytes_lost,
-            recv_bytes: self.recv_bytes,
-            stream_retrans_bytes: self.stream_retrans_bytes,
-            pmtu: self.recovery.max_datagram_size(),
-            delivery_rate: self.recovery.delivery_rate(),
-            peer_max_idle_timeout: self.peer_transport_params.max_idle_timeout,
-            peer_max_udp_payload_size: self
-                .peer_transport_params
-                .max_udp_payload_size,
-            peer_initial_max_data: self.peer_transport_params.initial_max_data,
-            peer_initial_max_stream_data_bidi_local: self
-                .peer_transport_params
-                .initial_max_stream_data_bidi_local,
-            peer_initial_max_stream_data_bidi_remote: self
-                .peer_transport_params
-                .initial_max_stream_data_bidi_remote,
-            peer_initial_max_stream_data_uni: self
-                .peer_transport_params
-                .initial_max_stream_data_uni,
-            peer_initial_max_streams_bidi: self
-                .peer_transport_params
-                .initial_max_streams_bidi,
-            peer_initial_max_streams_uni: self
-                .peer_transport_params
-                .initial_max_streams_uni,
-            peer_ack_delay_exponent: self
-                .peer_transport_params
-                .ack_delay_exponent,
-            peer_max_ack_delay: self.peer_transport_params.max_ack_delay,
-            peer_disable_active_migration: self
-                .peer_transport_params
+    /// Initiates a 1-RTT key update.
+    ///
+    /// The next generation of keys is derived from the current one and used
+    /// to protect all the packets sent from now on, while the current read
+    /// key is retained for a while to decrypt reordered packets.
+    ///
+    /// Key updates are also initiated automatically once the number of
+    /// packets configured with [`set_key_update_threshold()`] is reached.
+    ///
+    /// Returns [`InvalidState`] if the handshake is not confirmed yet, or if
+    /// the previous key update was not acknowledged by the peer yet.
+    ///
+    /// [`set_key_update_threshold()`]:
+    ///     struct.Config.html#method.set_key_update_threshold
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn initiate_key_update(&mut self) -> Result<()> {
+        if !self.handshake_confirmed {
+            return Err(Error::InvalidState);
+        }
+
+        let space = &self.pkt_num_spaces[packet::EPOCH_APPLICATION];
+
+        if let Some(ref ku) = space.key_update {
+            if !ku.update_acked {
+                return Err(Error::InvalidState);
+            }
+        }
+
+        let open_next = match space.crypto_open {
+            Some(ref v) => v.derive_next_packet_key()?,
+
+            None => return Err(Error::InvalidState),
+        };
+
+        let seal_next = match space.crypto_seal {
+            Some(ref v) => v.derive_next_packet_key()?,
+
+            None => return Err(Error::InvalidState),
+        };
+
+        trace!("{} key update initiated", self.trace_id);
+
+        self.update_keys(open_next, seal_next, None, time::Instant::now())
+    }
+
+    /// Schedules a NEW_TOKEN frame to be sent to the client.
+    ///
+    /// The token is generated with the key configured with
+    /// [`set_address_token_key()`] and is bound to the client's current
+    /// address. The client can use it on a future connection to the same
+    /// server to skip address validation.
+    ///
+    /// Returns [`InvalidState`] if this is not a server-side connection, if
+    /// the handshake is not completed yet, or if no key was configured.
+    ///
+    /// [`set_address_token_key()`]:
+    ///     struct.Config.html#method.set_address_token_key
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn send_new_token(&mut self) -> Result<()> {
+        if !self.is_server || !self.is_established() {
+            return Err(Error::InvalidState);
+        }
+
+        let key = self.address_token_key.as_ref().ok_or(Error::InvalidState)?;
+
+        let token = key.new_token(&self.paths.get_active()?.peer_addr())?;
+
+        self.new_tokens.push_back(token);
+
+        Ok(())
+    }
+
+    /// Returns the next address validation token received from the server in
+    /// a NEW_TOKEN frame, if any.
+    ///
+    /// Tokens can be stored and passed to [`connect_with_token()`] when
+    /// establishing a future connection to the same server.
+    ///
+    /// Only the latest few tokens are kept, older ones are dropped when new
+    /// ones are received before they are read.
+    ///
+    /// [`connect_with_token()`]: fn.connect_with_token.html
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let local = "127.0.0.1:0".parse().unwrap();
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::connect(None, &scid, local, peer, &mut config)?;
+    /// let mut tokens = Vec::new();
+    ///
+    /// while let Some(token) = conn.new_token_next() {
+    ///     tokens.push(token);
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn new_token_next(&mut self) -> Option<Vec<u8>> {
+        self.received_tokens.pop_front()
+    }
+
+    /// Migrates the connection to the network path between the given
+    /// addresses.
+    ///
+    /// The path becomes the active one right away, so that all subsequent
+    /// packets are sent on it. If it wasn't validated yet, its validation is
+    /// started in the same way as with [`probe_path()`].
+    ///
+    /// The same restrictions as [`probe_path()`] apply: only clients can
+    /// migrate, once the handshake is confirmed and as long as the peer didn't
+    /// disable active migration.
+    ///
+    /// On success the sequence number of the destination connection ID used
+    /// on the path is returned.
+    ///
+    /// [`probe_path()`]: struct.Connection.html#method.probe_path
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::connect(None, &scid, local, peer, &mut config)?;
+    /// let new_socket = std::net::UdpSocket::bind("0.0.0.0:0").unwrap();
+    ///
+    /// conn.migrate(new_socket.local_addr().unwrap(), peer)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn migrate(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<u64> {
+        let pid = match self.paths.path_id_from_addrs(&(local_addr, peer_addr)) {
+            Some(pid) => pid,
+
+            None => self.create_client_path(local_addr, peer_addr)?,
+        };
+
+        let dcid_seq = self
+            .paths
+            .get(pid)?
+            .active_dcid_seq
+            .ok_or(Error::InvalidState)?;
+
+        self.paths.set_active_path(pid)?;
+
+        let path = self.paths.get_mut(pid)?;
+
+        path.request_validation();
+
+        trace!(
+            "{} migrated to path local={} peer={}",
+            self.trace_id,
+            local_addr,
+            peer_addr
+        );
+
+        Ok(dcid_seq)
+    }
+
+    /// Returns the next event related to the connection's network paths, if
+    /// any.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// while let Some(ev) = conn.path_event_next() {
+    ///     match ev {
+    ///         quiche::PathEvent::PeerMigrated(local, peer) => {
+    ///             // The client moved, update the address used for it.
+    ///         },
+    ///
+    ///         _ => (),
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[inline]
+    pub fn path_event_next(&mut self) -> Option<PathEvent> {
+        self.paths.pop_event()
+    }
+
+    /// Returns statistics about each of the connection's network paths.
+    pub fn path_stats(&self) -> impl Iterator<Item = PathStats> + '_ {
+        self.paths.iter().map(|(_, p)| p.stats())
+    }
+
+    /// Returns true if the connection handshake is complete.
+    #[inline]
+    pub fn is_established(&self) -> bool {
+        self.handshake_completed
+    }
+
+    /// Returns true if the connection is resumed.
+    #[inline]
+    pub fn is_resumed(&self) -> bool {
+        self.handshake.lock().unwrap().is_resumed()
+    }
+
+    /// Returns true if the connection has a pending handshake that has
+    /// progressed enough to send or receive early data.
+    #[inline]
+    pub fn is_in_early_data(&self) -> bool {
+        self.handshake.lock().unwrap().is_in_early_data()
+    }
+
+    /// Returns whether there is stream or DATAGRAM data available to read.
+    #[inline]
+    pub fn is_readable(&self) -> bool {
+        self.streams.has_readable() || self.dgram_recv_front_len().is_some()
+    }
+
+    /// Returns true if the connection is draining.
+    ///
+    /// If this returns true, the connection object cannot yet be dropped, but
+    /// no new application data can be sent or received. An application should
+    /// continue calling the [`recv()`], [`send()`], [`timeout()`], and
+    /// [`on_timeout()`] methods as normal, until the [`is_closed()`] method
+    /// returns `true`.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining_timer.is_some()
+    }
+
+    /// Returns true if the connection is closed.
+    ///
+    /// If this returns true, the connection object can be dropped.
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns the error received from the peer, if any.
+    ///
+    /// The values contained in the tuple are symmetric with the [`close()`]
+    /// method.
+    ///
+    /// Note that a `Some` return value does not necessarily imply
+    /// [`is_closed()`] or any other connection state.
+    ///
+    /// [`close()`]: struct.Connection.html#method.close
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    #[inline]
+    pub fn peer_error(&self) -> Option<&ConnectionError> {
+        self.peer_error.as_ref()
+    }
+
+    /// Collects and returns statistics about the connection.
+    #[inline]
+    pub fn stats(&self) -> Stats {
+        let active_path = self.paths.get_active().ok();
+
+        Stats {
+            recv: self.recv_count,
+            sent: self.sent_count,
+            lost: self.paths.iter().map(|(_, p)| p.recovery.lost_count).sum(),
+            spurious_lost: self
+                .paths
+                .iter()
+                .map(|(_, p)| p.recovery.spurious_lost_count)
+                .sum(),
+            retrans: self.retrans_count,
+            cwnd: active_path.map_or(0, |p| p.recovery.cwnd()),
+            rtt: active_path
+                .map_or(time::Duration::new(0, 0), |p| p.recovery.rtt()),
+            sent_bytes: self.sent_bytes,
+            lost_bytes: self
+                .paths
+                .iter()
+                .map(|(_, p)| p.recovery.bytes_lost)
+                .sum(),
+            recv_bytes: self.recv_bytes,
+            stream_retrans_bytes: self.stream_retrans_bytes,
+            pmtu: active_path.map_or(0, |p| p.recovery.max_datagram_size()),
+            delivery_rate: active_path.map_or(0, |p| p.recovery.delivery_rate()),
+            ecn_sent_ect0: self
+                .paths
+                .iter()
+                .map(|(_, p)| p.recovery.ecn.marked_sent())
+                .sum(),
+            ecn_recv_ect0: self
+                .pkt_num_spaces
+                .iter()
+                .map(|s| s.ecn_counts.ect0_count)
+                .sum(),
+            ecn_recv_ect1: self
+                .pkt_num_spaces
+                .iter()
+                .map(|s| s.ecn_counts.ect1_count)
+                .sum(),
+            ecn_recv_ce: self
+                .pkt_num_spaces
+                .iter()
+                .map(|s| s.ecn_counts.ecn_ce_count)
+                .sum(),
+            ecn_peer_ce: active_path
+                .map_or(0, |p| p.recovery.ecn.peer_ce_count()),
+            peer_max_idle_timeout: self.peer_transport_params.max_idle_timeout,
+            peer_max_udp_payload_size: self
+                .peer_transport_params
+                .max_udp_payload_size,
+            peer_initial_max_data: self.peer_transport_params.initial_max_data,
+            peer_initial_max_stream_data_bidi_local: self
+                .peer_transport_params
+                .initial_max_stream_data_bidi_local,
+            peer_initial_max_stream_data_bidi_remote: self
+                .peer_transport_params
+                .initial_max_stream_data_bidi_remote,
+            peer_initial_max_stream_data_uni: self
+                .peer_transport_params
+                .initial_max_stream_data_uni,
+            peer_initial_max_streams_bidi: self
+                .peer_transport_params
+                .initial_max_streams_bidi,
+            peer_initial_max_streams_uni: self
+                .peer_transport_params
+                .initial_max_streams_uni,
+            peer_ack_delay_exponent: self
+                .peer_transport_params
+                .ack_delay_exponent,
+            peer_max_ack_delay: self.peer_transport_params.max_ack_delay,
+            peer_disable_active_migration: self
+                .peer_transport_params
                 .disable_active_migration,
             peer_active_conn_id_limit: self
                 .peer_transport_params
@@ -4347,11 +6194,18 @@ impl Connection {
             peer_max_datagram_frame_size: self
                 .peer_transport_params
                 .max_datagram_frame_size,
+            peer_preferred_address: self
+                .peer_transport_params
+                .preferred_address
+                .clone(),
         }
     }
 
     fn encode_transport_params(&mut self) -> Result<()> {
-        let mut raw_params = [0; 128];
+        self.local_transport_params.version_information =
+            self.version_information();
+
+        let mut raw_params = [0; 256];
 
         let raw_params = TransportParams::encode(
             &self.local_transport_params,
@@ -4367,15 +6221,84 @@ impl Connection {
         Ok(())
     }
 
+    /// Returns the local `version_information` transport parameter, which
+    /// is only sent with the final QUIC versions.
+    fn version_information(&self) -> Option<VersionInformation> {
+        if uses_legacy_codepoint(self.version) {
+            return None;
+        }
+
+        let mut available_versions = vec![self.version];
+
+        available_versions.extend(self.compatible_versions.iter().filter(
+            |&&v| v != self.version && versions_are_compatible(self.version, v),
+        ));
+
+        Some(VersionInformation {
+            chosen_version: self.version,
+            available_versions,
+        })
+    }
+
+    /// Switches the connection to a version compatible with the client's, as
+    /// per RFC 9368, based on the client's transport parameters.
+    ///
+    /// This is called by the TLS stack as soon as the ClientHello is
+    /// received, so that the server's transport parameters can be updated
+    /// before they are sent.
+    fn negotiate_compatible_version(
+        &mut self, raw_params: &[u8], handshake: &tls::Handshake,
+    ) -> Result<()> {
+        let peer_params = TransportParams::decode(raw_params, self.is_server)?;
+
+        let available_versions = match peer_params.version_information {
+            Some(v) => v.available_versions,
+
+            None => return Ok(()),
+        };
+
+        let version = self.compatible_versions.iter().find(|&&v| {
+            versions_are_compatible(self.version, v) &&
+                available_versions.contains(&v)
+        });
+
+        let version = match version {
+            Some(&v) if v != self.version => v,
+
+            _ => return Ok(()),
+        };
+
+        trace!(
+            "{} switching to compatible version {:x}",
+            self.trace_id,
+            version
+        );
+
+        self.version = version;
+
+        self.local_transport_params.version_information =
+            self.version_information();
+
+        let mut raw_params = [0; 256];
+
+        let raw_params = TransportParams::encode(
+            &self.local_transport_params,
+            self.is_server,
+            &mut raw_params,
+        )?;
+
+        handshake.set_quic_transport_params(raw_params)
+    }
+
     fn parse_peer_transport_params(
         &mut self, peer_params: TransportParams,
     ) -> Result<()> {
         if self.version >= PROTOCOL_VERSION_DRAFT28 ||
-            self.version == PROTOCOL_VERSION_V1
+            !uses_legacy_codepoint(self.version)
         {
             // Validate initial_source_connection_id.
             match &peer_params.initial_source_connection_id {
-                Some(v) if v != &self.dcid =>
+                Some(v) if v != &self.destination_id() =>
                     return Err(Error::InvalidTransportParam),
 
                 Some(_) => (),
@@ -4425,6 +6348,58 @@ impl Connection {
             }
         }
 
+        // Validate the version information, to prevent version downgrades.
+        if !uses_legacy_codepoint(self.version) {
+            match &peer_params.version_information {
+                // The client's chosen version must be the one of its first
+                // Initial packets.
+                Some(v)
+                    if self.is_server &&
+                        v.chosen_version != self.original_version =>
+                    return Err(Error::VersionNegotiation),
+
+                // The server's chosen version must be the negotiated one.
+                Some(v)
+                    if !self.is_server && v.chosen_version != self.version =>
+                    return Err(Error::VersionNegotiation),
+
+                Some(_) => (),
+
+                // The version information must be sent by the server when it
+                // switched to a different version.
+                None if self.version != self.original_version =>
+                    return Err(Error::VersionNegotiation),
+
+                None => (),
+            }
+        }
+
+        // Only the server can provide a stateless reset token for the
+        // initial connection ID.
+        if let Some(token) = &peer_params.stateless_reset_token {
+            let token: [u8; 16] = token
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::InvalidTransportParam)?;
+
+            self.ids
+                .set_initial_dcid_reset_token(u128::from_be_bytes(token));
+        }
+
+        // The connection ID provided with the server's preferred address has
+        // sequence number 1. A server using a zero-length connection ID can't
+        // provide one.
+        if let Some(preferred_address) = &peer_params.preferred_address {
+            self.ids
+                .new_dcid(
+                    preferred_address.connection_id.clone(),
+                    1,
+                    preferred_address.stateless_reset_token,
+                    0,
+                )
+                .map_err(|_| Error::InvalidTransportParam)?;
+        }
+
         self.process_peer_transport_params(peer_params);
 
         self.parsed_peer_transport_params = true;
@@ -4436,21 +6411,24 @@ impl Connection {
         self.max_tx_data = peer_params.initial_max_data;
 
         // Update send capacity.
-        self.tx_cap = cmp::min(
-            self.recovery.cwnd_available() as u64,
-            self.max_tx_data - self.tx_data,
-        ) as usize;
+        self.update_tx_cap();
 
         self.streams
             .update_peer_max_streams_bidi(peer_params.initial_max_streams_bidi);
         self.streams
             .update_peer_max_streams_uni(peer_params.initial_max_streams_uni);
 
-        self.recovery.max_ack_delay =
-            time::Duration::from_millis(peer_params.max_ack_delay);
+        for (_, path) in self.paths.iter_mut() {
+            path.recovery.max_ack_delay =
+                time::Duration::from_millis(peer_params.max_ack_delay);
+
+            path.recovery.set_peer_max_udp_payload_size(
+                peer_params.max_udp_payload_size as usize,
+            );
+        }
 
-        self.recovery
-            .update_max_datagram_size(peer_params.max_udp_payload_size as usize);
+        self.ids
+            .set_source_conn_id_limit(peer_params.active_conn_id_limit);
 
         self.peer_transport_params = peer_params;
     }
@@ -4528,8 +6506,9 @@ impl Connection {
         Ok(())
     }
 
-    /// Selects the packet type for the next outgoing packet.
-    fn write_pkt_type(&self) -> Result<packet::Type> {
+    /// Selects the packet type for the next outgoing packet on the given
+    /// path.
+    fn write_pkt_type(&self, send_pid: usize) -> Result<packet::Type> {
         // On error send packet in the latest epoch available, but only send
         // 1-RTT ones when the handshake is completed.
         if self
@@ -4565,12 +6544,16 @@ impl Connection {
             }
 
             // There are lost frames in this packet number space.
-            if !self.recovery.lost[epoch].is_empty() {
+            if self
+                .paths
+                .iter()
+                .any(|(_, p)| !p.recovery.lost[epoch].is_empty())
+            {
                 return Ok(packet::Type::from_epoch(epoch));
             }
 
             // We need to send PTO probe packets.
-            if self.recovery.loss_probes[epoch] > 0 {
+            if self.paths.get(send_pid)?.recovery.loss_probes[epoch] > 0 {
                 return Ok(packet::Type::from_epoch(epoch));
             }
         }
@@ -4579,6 +6562,7 @@ impl Connection {
         // Application epoch.
         if (self.is_established() || self.is_in_early_data()) &&
             ((self.is_server && !self.handshake_done_sent) ||
+                !self.new_tokens.is_empty() ||
                 self.almost_full ||
                 self.blocked_limit.is_some() ||
                 self.dgram_send_queue.has_pending() ||
@@ -4591,7 +6575,10 @@ impl Connection {
                 self.streams.has_almost_full() ||
                 self.streams.has_blocked() ||
                 self.streams.has_reset() ||
-                self.streams.has_stopped())
+                self.streams.has_stopped() ||
+                self.ids.has_new_scids() ||
+                self.ids.has_retire_dcids() ||
+                self.paths.get(send_pid)?.probing_required())
         {
             if self.is_in_early_data() && !self.is_server {
                 return Ok(packet::Type::ZeroRTT);
@@ -4619,7 +6606,8 @@ impl Connection {
 
     /// Processes an incoming frame.
     fn process_frame(
-        &mut self, frame: frame::Frame, epoch: packet::Epoch, now: time::Instant,
+        &mut self, frame: frame::Frame, hdr: &Header, recv_path_id: usize,
+        epoch: packet::Epoch, now: time::Instant,
     ) -> Result<()> {
         trace!("{} rx frm {:?}", self.trace_id, frame);
 
@@ -4628,7 +6616,11 @@ impl Connection {
 
             frame::Frame::Ping => (),
 
-            frame::Frame::ACK { ranges, ack_delay } => {
+            frame::Frame::ACK {
+                ranges,
+                ack_delay,
+                ecn_counts,
+            } => {
                 let ack_delay = ack_delay
                     .checked_mul(2_u64.pow(
                         self.peer_transport_params.ack_delay_exponent as u32,
@@ -4647,18 +6639,55 @@ impl Connection {
                     self.handshake_confirmed = true;
                 }
 
-                self.recovery.on_ack_received(
-                    &ranges,
-                    ack_delay,
-                    epoch,
-                    self.handshake_status(),
-                    now,
-                    &self.trace_id,
-                )?;
+                // If the largest packet number acked exceeds any packet number
+                // we have sent, then the ACK is obviously invalid, so there's
+                // no need to continue further.
+                if ranges.last() >= Some(self.pkt_num_spaces[epoch].next_pkt_num)
+                {
+                    if cfg!(feature = "fuzzing") {
+                        return Ok(());
+                    }
 
-                // Once the handshake is confirmed, we can drop Handshake keys.
-                if self.handshake_confirmed {
-                    self.drop_epoch_state(packet::EPOCH_HANDSHAKE, now);
+                    return Err(Error::InvalidPacket);
+                }
+
+                let handshake_status = self.handshake_status();
+
+                // The key update is confirmed once a packet sent with the new
+                // keys is acknowledged.
+                if let Some(ref mut ku) = self.pkt_num_spaces[epoch].key_update {
+                    if ranges.last() >= Some(ku.sent_pn_on_update) {
+                        ku.update_acked = true;
+                    }
+                }
+
+                // Packet numbers are shared by all paths, so the ACK can
+                // acknowledge packets sent on any of them.
+                for (_, p) in self.paths.iter_mut() {
+                    p.recovery.on_ack_received(
+                        &ranges,
+                        ack_delay,
+                        ecn_counts,
+                        epoch,
+                        handshake_status,
+                        now,
+                        &self.trace_id,
+                    )?;
+
+                    qlog_with!(self.qlog_streamer, q, {
+                        if p.recovery.take_persistent_congestion() {
+                            let ev =
+                                qlog::event::Event::congestion_state_updated_min(
+                                    "persistent_congestion".to_string(),
+                                );
+                            q.add_event_with_instant(ev, now).ok();
+                        }
+                    });
+                }
+
+                // Once the handshake is confirmed, we can drop Handshake keys.
+                if self.handshake_confirmed {
+                    self.drop_epoch_state(packet::EPOCH_HANDSHAKE, now);
                 }
             },
 
@@ -4781,8 +6810,18 @@ impl Connection {
 
             frame::Frame::CryptoHeader { .. } => unreachable!(),
 
-            // TODO: implement stateless retry
-            frame::Frame::NewToken { .. } => (),
+            frame::Frame::NewToken { token } => {
+                // Only servers can send NEW_TOKEN frames.
+                if self.is_server {
+                    return Err(Error::InvalidPacket);
+                }
+
+                if self.received_tokens.len() >= MAX_RECEIVED_TOKENS {
+                    self.received_tokens.pop_front();
+                }
+
+                self.received_tokens.push_back(token);
+            },
 
             frame::Frame::Stream { stream_id, data } => {
                 // Peer can't send on our unidirectional streams.
@@ -4912,17 +6951,103 @@ impl Connection {
                     return Err(Error::InvalidFrame);
                 },
 
-            // TODO: implement connection migration
-            frame::Frame::NewConnectionId { .. } => (),
+            frame::Frame::NewConnectionId {
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                let reset_token: [u8; 16] = reset_token
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| Error::InvalidFrame)?;
+
+                let retired = self.ids.new_dcid(
+                    conn_id.into(),
+                    seq_num,
+                    u128::from_be_bytes(reset_token),
+                    retire_prior_to,
+                )?;
+
+                // Paths using a destination connection ID that was retired
+                // switch to the oldest one still available, while paths that
+                // were waiting for one can now use the new one.
+                let pids: Vec<usize> = self
+                    .paths
+                    .iter()
+                    .filter(|(_, p)| match p.active_dcid_seq {
+                        Some(seq) => retired.contains(&seq),
+
+                        None => p.usable(),
+                    })
+                    .map(|(pid, _)| pid)
+                    .collect();
+
+                for pid in pids {
+                    self.set_path_dcid(pid)?;
+                }
+
+                // The active path can't be left without a destination
+                // connection ID.
+                if self.paths.get_active()?.active_dcid_seq.is_none() {
+                    return Err(Error::InvalidState);
+                }
+            },
+
+            frame::Frame::RetireConnectionId { seq_num } => {
+                if self.ids.zero_length_scid() {
+                    return Err(Error::InvalidState);
+                }
 
-            // TODO: implement connection migration
-            frame::Frame::RetireConnectionId { .. } => (),
+                let retired = self.ids.retire_scid(seq_num, &hdr.dcid)?;
+
+                if retired.is_some() && seq_num == self.active_scid_seq {
+                    self.active_scid_seq = self
+                        .ids
+                        .lowest_active_scid_seq()
+                        .ok_or(Error::InvalidState)?;
+                }
+            },
 
             frame::Frame::PathChallenge { data } => {
-                self.challenge = Some(data);
+                let data: [u8; 8] = data
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| Error::InvalidFrame)?;
+
+                self.paths
+                    .get_mut(recv_path_id)?
+                    .on_challenge_received(data);
             },
 
-            frame::Frame::PathResponse { .. } => (),
+            frame::Frame::PathResponse { data } => {
+                // The response might be received on a different path than the
+                // one the challenge was sent on, in which case it's the latter
+                // that gets validated.
+                let validated = self.paths.iter_mut().find_map(|(pid, p)| {
+                    p.on_response_received(&data)
+                        .then(|| (pid, p.local_addr(), p.peer_addr()))
+                });
+
+                if let Some((pid, local, peer)) = validated {
+                    self.paths
+                        .notify_event(path::PathEvent::Validated(local, peer));
+
+                    // Now that the server's preferred address is validated,
+                    // migrate to it.
+                    if self.preferred_path_id == Some(pid) {
+                        self.preferred_path_id = None;
+
+                        self.paths.set_active_path(pid)?;
+
+                        trace!(
+                            "{} migrated to preferred address {}",
+                            self.trace_id,
+                            peer
+                        );
+                    }
+                }
+            },
 
             frame::Frame::ConnectionClose {
                 error_code, reason, ..
@@ -4932,7 +7057,9 @@ impl Connection {
                     error_code,
                     reason,
                 });
-                self.draining_timer = Some(now + (self.recovery.pto() * 3));
+
+                let pto = self.paths.get_active()?.recovery.pto();
+                self.draining_timer = Some(now + (pto * 3));
             },
 
             frame::Frame::ApplicationClose { error_code, reason } => {
@@ -4941,7 +7068,9 @@ impl Connection {
                     error_code,
                     reason,
                 });
-                self.draining_timer = Some(now + (self.recovery.pto() * 3));
+
+                let pto = self.paths.get_active()?.recovery.pto();
+                self.draining_timer = Some(now + (pto * 3));
             },
 
             frame::Frame::HandshakeDone => {
@@ -4978,6 +7107,83 @@ impl Connection {
         Ok(())
     }
 
+    /// Replaces the 1-RTT keys with the given next generation ones.
+    ///
+    /// The current read key is retained to decrypt reordered packets. The
+    /// `pn_on_update` argument is the number of the packet that triggered the
+    /// update, or `None` when the update was initiated locally.
+    fn update_keys(
+        &mut self, open_next: crypto::Open, seal_next: crypto::Seal,
+        pn_on_update: Option<u64>, now: time::Instant,
+    ) -> Result<()> {
+        let timer = match pn_on_update {
+            Some(_) => Some(now + self.paths.get_active()?.recovery.pto() * 3),
+
+            None => None,
+        };
+
+        let space = &mut self.pkt_num_spaces[packet::EPOCH_APPLICATION];
+
+        let crypto_open = space
+            .crypto_open
+            .replace(open_next)
+            .ok_or(Error::InvalidState)?;
+
+        space.crypto_seal = Some(seal_next);
+
+        space.key_update = Some(packet::KeyUpdate {
+            crypto_open,
+            pn_on_update: pn_on_update.unwrap_or(std::u64::MAX),
+            sent_pn_on_update: space.next_pkt_num,
+            update_acked: false,
+            timer,
+        });
+
+        self.key_phase = !self.key_phase;
+        self.key_generation += 1;
+        self.key_phase_pkt_count = 0;
+
+        qlog_with!(self.qlog_streamer, q, {
+            let key_types = if self.is_server {
+                [
+                    qlog::KeyType::Server1RttSecret,
+                    qlog::KeyType::Client1RttSecret,
+                ]
+            } else {
+                [
+                    qlog::KeyType::Client1RttSecret,
+                    qlog::KeyType::Server1RttSecret,
+                ]
+            };
+
+            for key_type in key_types.iter() {
+                let ev = qlog::event::Event::key_updated(
+                    key_type.clone(),
+                    None,
+                    String::new(),
+                    Some(self.key_generation),
+                );
+
+                q.add_event_with_instant(ev, now).ok();
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Returns the number of packets that can be protected with the current
+    /// 1-RTT keys before they need to be updated.
+    fn key_update_limit(&self) -> u64 {
+        let limit =
+            match self.pkt_num_spaces[packet::EPOCH_APPLICATION].crypto_seal {
+                Some(ref v) => v.alg().confidentiality_limit(),
+
+                None => return std::u64::MAX,
+            };
+
+        cmp::min(limit, self.key_update_threshold)
+    }
+
     /// Drops the keys and recovery state for the given epoch.
     fn drop_epoch_state(&mut self, epoch: packet::Epoch, now: time::Instant) {
         if self.pkt_num_spaces[epoch].crypto_open.is_none() {
@@ -4988,11 +7194,19 @@ impl Connection {
         self.pkt_num_spaces[epoch].crypto_seal = None;
         self.pkt_num_spaces[epoch].clear();
 
-        self.recovery.on_pkt_num_space_discarded(
-            epoch,
-            self.handshake_status(),
-            now,
-        );
+        if epoch == packet::EPOCH_INITIAL {
+            self.original_version_open = None;
+        }
+
+        let handshake_status = self.handshake_status();
+
+        for (_, path) in self.paths.iter_mut() {
+            path.recovery.on_pkt_num_space_discarded(
+                epoch,
+                handshake_status,
+                now,
+            );
+        }
 
         trace!("{} dropped epoch {} state", self.trace_id, epoch);
     }
@@ -5033,12 +7247,377 @@ impl Connection {
             )
         };
 
+        let path_pto = match self.paths.get_active() {
+            Ok(p) => p.recovery.pto(),
+
+            Err(_) => time::Duration::new(0, 0),
+        };
+
         let idle_timeout = time::Duration::from_millis(idle_timeout);
-        let idle_timeout = cmp::max(idle_timeout, 3 * self.recovery.pto());
+        let idle_timeout = cmp::max(idle_timeout, 3 * path_pto);
 
         Some(idle_timeout)
     }
 
+    /// Updates the send capacity based on the active path's congestion
+    /// window and the connection-level flow control limit.
+    fn update_tx_cap(&mut self) {
+        let cwnd_available = match self.paths.get_active() {
+            Ok(p) => p.recovery.cwnd_available() as u64,
+
+            Err(_) => 0,
+        };
+
+        self.tx_cap =
+            cmp::min(cwnd_available, self.max_tx_data - self.tx_data) as usize;
+    }
+
+    /// Creates a new path between the given addresses, taking into account
+    /// the peer's transport parameters.
+    fn new_path(&self, local: SocketAddr, peer: SocketAddr) -> path::Path {
+        let mut path = path::Path::new(local, peer, &self.recovery_config, false);
+
+        if self.parsed_peer_transport_params {
+            path.recovery.max_ack_delay = time::Duration::from_millis(
+                self.peer_transport_params.max_ack_delay,
+            );
+
+            path.recovery.set_peer_max_udp_payload_size(
+                self.peer_transport_params.max_udp_payload_size as usize,
+            );
+        }
+
+        path
+    }
+
+    /// Returns the identifier of the path the packet was received on.
+    ///
+    /// Servers create a new path when a packet is received on an unknown
+    /// 4-tuple, for example due to NAT rebinding or to the client probing a
+    /// new path. The new path needs to be validated, and the amount of data
+    /// sent on it is limited until then.
+    ///
+    /// [`Done`] is returned if the packet should be dropped.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    fn get_or_create_recv_path_id(
+        &mut self, hdr: &Header, info: &RecvInfo, buf_len: usize,
+    ) -> Result<usize> {
+        if let Some(pid) = self.paths.path_id_from_addrs(&(info.to, info.from)) {
+            return Ok(pid);
+        }
+
+        // Clients only use paths they explicitly probed, and the peer can't
+        // change its address during the handshake.
+        if !self.is_server || hdr.ty != packet::Type::Short {
+            return Err(Error::Done);
+        }
+
+        let mut path = self.new_path(info.to, info.from);
+
+        path.max_send_bytes = buf_len * MAX_AMPLIFICATION_FACTOR;
+
+        path.request_validation();
+
+        let (pid, closed) = self.paths.insert_path(path)?;
+
+        if let Some(closed) = closed {
+            self.on_path_closed(closed);
+        }
+
+        self.set_path_dcid(pid)?;
+
+        // If the peer didn't provide a spare connection ID, keep using the
+        // one of the active path, as this is most likely the result of a NAT
+        // rebinding.
+        if self.paths.get(pid)?.active_dcid_seq.is_none() {
+            let active_dcid_seq = self.paths.get_active()?.active_dcid_seq;
+
+            self.paths.get_mut(pid)?.active_dcid_seq = active_dcid_seq;
+        }
+
+        trace!(
+            "{} new path local={} peer={}",
+            self.trace_id,
+            info.to,
+            info.from
+        );
+
+        self.paths
+            .notify_event(path::PathEvent::New(info.to, info.from));
+
+        Ok(pid)
+    }
+
+    /// Creates a new client path between the given addresses.
+    fn create_client_path(
+        &mut self, local: SocketAddr, peer: SocketAddr,
+    ) -> Result<usize> {
+        if self.is_server {
+            return Err(Error::InvalidState);
+        }
+
+        // Migration is only allowed once the handshake is confirmed, and if
+        // the peer didn't disable it.
+        if !self.handshake_confirmed ||
+            self.peer_transport_params.disable_active_migration
+        {
+            return Err(Error::InvalidState);
+        }
+
+        self.insert_client_path(local, peer)
+    }
+
+    /// Inserts a new client path between the given addresses, regardless of
+    /// whether the peer allows active migration.
+    fn insert_client_path(
+        &mut self, local: SocketAddr, peer: SocketAddr,
+    ) -> Result<usize> {
+        // A new connection ID is needed for each path, unless the peer uses
+        // zero-length connection IDs.
+        if !self.ids.zero_length_dcid() &&
+            self.ids.lowest_available_dcid_seq().is_none()
+        {
+            return Err(Error::OutOfIdentifiers);
+        }
+
+        let path = self.new_path(local, peer);
+
+        let (pid, closed) = self.paths.insert_path(path)?;
+
+        if let Some(closed) = closed {
+            self.on_path_closed(closed);
+        }
+
+        self.set_path_dcid(pid)?;
+
+        Ok(pid)
+    }
+
+    /// Returns the identifier of the path to send the next packet on.
+    ///
+    /// Paths with pending probing frames take precedence over the active one.
+    fn get_send_path_id(&self) -> Result<usize> {
+        if self.is_established() && self.local_error.is_none() {
+            let probing = self.paths.iter().find(|(_, p)| {
+                !p.active() &&
+                    p.usable() &&
+                    p.active_dcid_seq.is_some() &&
+                    p.probing_required() &&
+                    (!self.is_server ||
+                        p.verified_peer_address ||
+                        p.max_send_bytes > 0)
+            });
+
+            if let Some((pid, _)) = probing {
+                return Ok(pid);
+            }
+        }
+
+        self.paths.get_active_path_id()
+    }
+
+    /// Links the lowest available destination connection ID to the given
+    /// path.
+    ///
+    /// If no connection ID is available the path is left without one, and
+    /// can't be used until the peer provides a new one.
+    fn set_path_dcid(&mut self, pid: usize) -> Result<()> {
+        let dcid_seq = match self.ids.lowest_available_dcid_seq() {
+            Some(seq) => {
+                self.ids.link_dcid_to_path(seq, Some(pid))?;
+
+                Some(seq)
+            },
+
+            // Peers using zero-length connection IDs use the same one on
+            // all paths.
+            None if self.ids.zero_length_dcid() => Some(0),
+
+            None => None,
+        };
+
+        self.paths.get_mut(pid)?.active_dcid_seq = dcid_seq;
+
+        Ok(())
+    }
+
+    /// Retires the given destination connection ID, unless it is still used
+    /// by a path.
+    fn retire_unused_dcid(&mut self, dcid_seq: u64) {
+        if self.ids.zero_length_dcid() {
+            return;
+        }
+
+        let user = self
+            .paths
+            .iter()
+            .find(|(_, p)| p.active_dcid_seq == Some(dcid_seq))
+            .map(|(pid, _)| pid);
+
+        match user {
+            Some(pid) => {
+                self.ids.link_dcid_to_path(dcid_seq, Some(pid)).ok();
+            },
+
+            None => {
+                self.ids.retire_dcid(dcid_seq).ok();
+            },
+        }
+    }
+
+    /// Releases the resources of a path that was removed.
+    fn on_path_closed(&mut self, closed: path::Path) {
+        trace!(
+            "{} closed path local={} peer={}",
+            self.trace_id,
+            closed.local_addr(),
+            closed.peer_addr()
+        );
+
+        if let Some(dcid_seq) = closed.active_dcid_seq {
+            self.retire_unused_dcid(dcid_seq);
+        }
+    }
+
+    /// Marks the validation of the given path as failed.
+    ///
+    /// If it was the active path, the connection falls back to the most
+    /// recent validated path, if any.
+    fn on_path_validation_failed(&mut self, pid: usize) {
+        let fallback = self
+            .paths
+            .iter()
+            .filter(|(id, p)| *id != pid && p.validated())
+            .map(|(id, _)| id)
+            .last();
+
+        let path = match self.paths.get_mut(pid) {
+            Ok(v) => v,
+
+            Err(_) => return,
+        };
+
+        path.on_failed_validation();
+
+        let (local, peer) = (path.local_addr(), path.peer_addr());
+
+        trace!(
+            "{} path validation failed local={} peer={}",
+            self.trace_id,
+            local,
+            peer
+        );
+
+        self.paths
+            .notify_event(path::PathEvent::FailedValidation(local, peer));
+
+        if self.paths.get_active_path_id() == Ok(pid) {
+            match fallback {
+                Some(fallback) => {
+                    self.paths.set_active_path(fallback).ok();
+                },
+
+                // There is no other path to fall back to, so keep using this
+                // one.
+                None => return,
+            }
+        }
+
+        let dcid_seq = self
+            .paths
+            .get_mut(pid)
+            .ok()
+            .and_then(|p| p.active_dcid_seq.take());
+
+        if let Some(dcid_seq) = dcid_seq {
+            self.retire_unused_dcid(dcid_seq);
+        }
+    }
+
+    /// Makes the given path the active one, as the peer migrated to it.
+    fn on_peer_migrated(&mut self, pid: usize) -> Result<()> {
+        let path = self.paths.get_mut(pid)?;
+
+        if !path.usable() || path.active_dcid_seq.is_none() {
+            return Ok(());
+        }
+
+        // The peer's address on the new path still needs to be validated, as
+        // it might be spoofed.
+        path.request_validation();
+
+        let (local, peer) = (path.local_addr(), path.peer_addr());
+
+        self.paths.set_active_path(pid)?;
+
+        trace!(
+            "{} peer migrated to path local={} peer={}",
+            self.trace_id,
+            local,
+            peer
+        );
+
+        self.paths
+            .notify_event(path::PathEvent::PeerMigrated(local, peer));
+
+        Ok(())
+    }
+
+    /// Starts validating the server's preferred address, if any.
+    ///
+    /// The address of the same family as the current server address is used,
+    /// and the client migrates to it once validated. Note that the server's
+    /// `disable_active_migration` transport parameter doesn't apply to its
+    /// preferred address.
+    fn probe_preferred_address(&mut self) {
+        let preferred_address =
+            match &self.peer_transport_params.preferred_address {
+                Some(v) => v,
+
+                None => return,
+            };
+
+        let (local, peer) = match self.paths.get_active() {
+            Ok(p) => (p.local_addr(), p.peer_addr()),
+
+            Err(_) => return,
+        };
+
+        let preferred_peer = match peer {
+            SocketAddr::V4(_) => preferred_address.ipv4.map(SocketAddr::V4),
+
+            SocketAddr::V6(_) => preferred_address.ipv6.map(SocketAddr::V6),
+        };
+
+        let preferred_peer = match preferred_peer {
+            Some(v) if v != peer => v,
+
+            _ => return,
+        };
+
+        let pid = match self.insert_client_path(local, preferred_peer) {
+            Ok(v) => v,
+
+            Err(e) => {
+                trace!(
+                    "{} can't probe preferred address {}: {:?}",
+                    self.trace_id,
+                    preferred_peer,
+                    e
+                );
+
+                return;
+            },
+        };
+
+        if let Ok(path) = self.paths.get_mut(pid) {
+            path.request_validation();
+
+            self.preferred_path_id = Some(pid);
+        }
+    }
+
     /// Returns the connection's handshake status for use in loss recovery.
     fn handshake_status(&self) -> recovery::HandshakeStatus {
         recovery::HandshakeStatus {
@@ -5103,6 +7682,10 @@ pub struct Stats {
     /// The number of QUIC packets that were lost.
     pub lost: usize,
 
+    /// The number of QUIC packets that were declared lost, but were
+    /// acknowledged by the peer afterwards.
+    pub spurious_lost: usize,
+
     /// The number of sent QUIC packets with retransmitted data.
     pub retrans: usize,
 
@@ -5130,6 +7713,22 @@ pub struct Stats {
     /// The most recent data delivery rate estimate in bytes/s.
     pub delivery_rate: u64,
 
+    /// The number of QUIC packets sent with the ECT(0) codepoint.
+    pub ecn_sent_ect0: usize,
+
+    /// The number of QUIC packets received with the ECT(0) codepoint.
+    pub ecn_recv_ect0: u64,
+
+    /// The number of QUIC packets received with the ECT(1) codepoint.
+    pub ecn_recv_ect1: u64,
+
+    /// The number of QUIC packets received with the CE codepoint.
+    pub ecn_recv_ce: u64,
+
+    /// The number of sent QUIC packets that the peer reported as received
+    /// with the CE codepoint.
+    pub ecn_peer_ce: u64,
+
     /// The maximum idle timeout.
     pub peer_max_idle_timeout: u64,
 
@@ -5168,6 +7767,9 @@ pub struct Stats {
 
     /// DATAGRAM frame extension parameter, if any.
     pub peer_max_datagram_frame_size: Option<u64>,
+
+    /// The server's preferred address, if any.
+    pub peer_preferred_address: Option<PreferredAddress>,
 }
 
 impl std::fmt::Debug for Stats {
@@ -5239,14 +7841,253 @@ impl std::fmt::Debug for Stats {
 
         write!(
             f,
-            " max_datagram_frame_size={:?}",
+            " max_datagram_frame_size={:?},",
             self.peer_max_datagram_frame_size,
         )?;
 
+        write!(f, " preferred_address={:?}", self.peer_preferred_address,)?;
+
         write!(f, " }}")
     }
 }
 
+/// Statistics about a network path of a connection.
+#[derive(Clone)]
+pub struct PathStats {
+    /// The local address of the path.
+    pub local_addr: SocketAddr,
+
+    /// The peer address of the path.
+    pub peer_addr: SocketAddr,
+
+    /// Whether the path was validated.
+    pub validated: bool,
+
+    /// Whether the path is the active one.
+    pub active: bool,
+
+    /// The number of QUIC packets received on this path.
+    pub recv: usize,
+
+    /// The number of QUIC packets sent on this path.
+    pub sent: usize,
+
+    /// The number of QUIC packets that were lost on this path.
+    pub lost: usize,
+
+    /// The estimated round-trip time of the path.
+    pub rtt: time::Duration,
+
+    /// The size of the path's congestion window in bytes.
+    pub cwnd: usize,
+
+    /// The number of sent bytes.
+    pub sent_bytes: u64,
+
+    /// The number of received bytes.
+    pub recv_bytes: u64,
+
+    /// The number of bytes lost.
+    pub lost_bytes: u64,
+
+    /// The current PMTU for the path.
+    pub pmtu: usize,
+
+    /// The most recent data delivery rate estimate in bytes/s.
+    pub delivery_rate: u64,
+
+    /// Whether ECN was validated on this path.
+    pub ecn_capable: bool,
+}
+
+impl std::fmt::Debug for PathStats {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "local_addr={} peer_addr={} validated={} active={} ",
+            self.local_addr, self.peer_addr, self.validated, self.active,
+        )?;
+
+        write!(
+            f,
+            "recv={} sent={} lost={} rtt={:?} cwnd={}",
+            self.recv, self.sent, self.lost, self.rtt, self.cwnd,
+        )
+    }
+}
+
+/// A server's preferred address, as advertised in the `preferred_address`
+/// transport parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreferredAddress {
+    /// The IPv4 address and port, if any.
+    pub ipv4: Option<SocketAddrV4>,
+
+    /// The IPv6 address and port, if any.
+    pub ipv6: Option<SocketAddrV6>,
+
+    /// The connection ID to use when sending to the preferred address. Its
+    /// sequence number is `1`.
+    pub connection_id: ConnectionId<'static>,
+
+    /// The stateless reset token associated with the connection ID.
+    pub stateless_reset_token: u128,
+}
+
+impl PreferredAddress {
+    fn decode(b: &mut octets::Octets) -> Result<PreferredAddress> {
+        let ip: [u8; 4] = b
+            .get_bytes(4)?
+            .buf()
+            .try_into()
+            .map_err(|_| Error::InvalidTransportParam)?;
+        let ip = Ipv4Addr::from(ip);
+        let port = b.get_u16()?;
+
+        // An unspecified address and port means the family is not provided.
+        let ipv4 = if ip.is_unspecified() && port == 0 {
+            None
+        } else {
+            Some(SocketAddrV4::new(ip, port))
+        };
+
+        let ip: [u8; 16] = b
+            .get_bytes(16)?
+            .buf()
+            .try_into()
+            .map_err(|_| Error::InvalidTransportParam)?;
+        let ip = Ipv6Addr::from(ip);
+        let port = b.get_u16()?;
+
+        let ipv6 = if ip.is_unspecified() && port == 0 {
+            None
+        } else {
+            Some(SocketAddrV6::new(ip, port, 0, 0))
+        };
+
+        let cid_len = b.get_u8()? as usize;
+
+        if cid_len == 0 || cid_len > MAX_CONN_ID_LEN {
+            return Err(Error::InvalidTransportParam);
+        }
+
+        let connection_id = b.get_bytes(cid_len)?.to_vec().into();
+
+        let token: [u8; 16] = b
+            .get_bytes(16)?
+            .buf()
+            .try_into()
+            .map_err(|_| Error::InvalidTransportParam)?;
+
+        Ok(PreferredAddress {
+            ipv4,
+            ipv6,
+            connection_id,
+            stateless_reset_token: u128::from_be_bytes(token),
+        })
+    }
+
+    fn encode(&self, b: &mut octets::OctetsMut) -> Result<()> {
+        let (ip, port) = match self.ipv4 {
+            Some(addr) => (*addr.ip(), addr.port()),
+
+            None => (Ipv4Addr::UNSPECIFIED, 0),
+        };
+
+        b.put_bytes(&ip.octets())?;
+        b.put_u16(port)?;
+
+        let (ip, port) = match self.ipv6 {
+            Some(addr) => (*addr.ip(), addr.port()),
+
+            None => (Ipv6Addr::UNSPECIFIED, 0),
+        };
+
+        b.put_bytes(&ip.octets())?;
+        b.put_u16(port)?;
+
+        b.put_u8(self.connection_id.len() as u8)?;
+        b.put_bytes(&self.connection_id)?;
+
+        b.put_bytes(&self.stateless_reset_token.to_be_bytes())?;
+
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        4 + 2 + 16 + 2 + 1 + self.connection_id.len() + 16
+    }
+
+    #[cfg(feature = "qlog")]
+    fn to_qlog(&self) -> qlog::PreferredAddress {
+        qlog::PreferredAddress {
+            ip_v4: self.ipv4.map(|a| a.ip().to_string()).unwrap_or_default(),
+            ip_v6: self.ipv6.map(|a| a.ip().to_string()).unwrap_or_default(),
+
+            port_v4: self.ipv4.map_or(0, |a| a.port().into()),
+            port_v6: self.ipv6.map_or(0, |a| a.port().into()),
+
+            connection_id: format!(
+                "{}",
+                qlog::HexSlice::new(&self.connection_id)
+            ),
+            stateless_reset_token: format!(
+                "{}",
+                qlog::HexSlice::new(&self.stateless_reset_token.to_be_bytes())
+            ),
+        }
+    }
+}
+
+/// The `version_information` transport parameter, as per RFC 9368.
+#[derive(Clone, Debug, PartialEq)]
+struct VersionInformation {
+    chosen_version: u32,
+    available_versions: Vec<u32>,
+}
+
+impl VersionInformation {
+    fn decode(b: &mut octets::Octets) -> Result<Self> {
+        let chosen_version = b.get_u32()?;
+
+        if chosen_version == 0 || b.cap() % 4 != 0 {
+            return Err(Error::InvalidTransportParam);
+        }
+
+        let mut available_versions = Vec::with_capacity(b.cap() / 4);
+
+        while b.cap() > 0 {
+            let version = b.get_u32()?;
+
+            if version == 0 {
+                return Err(Error::InvalidTransportParam);
+            }
+
+            available_versions.push(version);
+        }
+
+        Ok(VersionInformation {
+            chosen_version,
+            available_versions,
+        })
+    }
+
+    fn encode(&self, b: &mut octets::OctetsMut) -> Result<()> {
+        b.put_u32(self.chosen_version)?;
+
+        for &version in &self.available_versions {
+            b.put_u32(version)?;
+        }
+
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        4 + 4 * self.available_versions.len()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 struct TransportParams {
     pub original_destination_connection_id: Option<ConnectionId<'static>>,
@@ -5262,11 +8103,12 @@ struct TransportParams {
     pub ack_delay_exponent: u64,
     pub max_ack_delay: u64,
     pub disable_active_migration: bool,
-    // pub preferred_address: ...,
+    pub preferred_address: Option<PreferredAddress>,
     pub active_conn_id_limit: u64,
     pub initial_source_connection_id: Option<ConnectionId<'static>>,
     pub retry_source_connection_id: Option<ConnectionId<'static>>,
     pub max_datagram_frame_size: Option<u64>,
+    pub version_information: Option<VersionInformation>,
 }
 
 impl Default for TransportParams {
@@ -5285,10 +8127,12 @@ impl Default for TransportParams {
             ack_delay_exponent: 3,
             max_ack_delay: 25,
             disable_active_migration: false,
+            preferred_address: None,
             active_conn_id_limit: 2,
             initial_source_connection_id: None,
             retry_source_connection_id: None,
             max_datagram_frame_size: None,
+            version_information: None,
         }
     }
 }
@@ -5401,7 +8245,8 @@ impl TransportParams {
                         return Err(Error::InvalidTransportParam);
                     }
 
-                    // TODO: decode preferred_address
+                    tp.preferred_address =
+                        Some(PreferredAddress::decode(&mut val)?);
                 },
 
                 0x000e => {
@@ -5426,6 +8271,11 @@ impl TransportParams {
                     tp.retry_source_connection_id = Some(val.to_vec().into());
                 },
 
+                0x0011 => {
+                    tp.version_information =
+                        Some(VersionInformation::decode(&mut val)?);
+                },
+
                 0x0020 => {
                     tp.max_datagram_frame_size = Some(val.get_varint()?);
                 },
@@ -5560,7 +8410,16 @@ impl TransportParams {
             TransportParams::encode_param(&mut b, 0x000c, 0)?;
         }
 
-        // TODO: encode preferred_address
+        if is_server {
+            if let Some(preferred_address) = &tp.preferred_address {
+                TransportParams::encode_param(
+                    &mut b,
+                    0x000d,
+                    preferred_address.len(),
+                )?;
+                preferred_address.encode(&mut b)?;
+            }
+        }
 
         if tp.active_conn_id_limit != 2 {
             TransportParams::encode_param(
@@ -5583,6 +8442,15 @@ impl TransportParams {
             }
         }
 
+        if let Some(version_information) = &tp.version_information {
+            TransportParams::encode_param(
+                &mut b,
+                0x0011,
+                version_information.len(),
+            )?;
+            version_information.encode(&mut b)?;
+        }
+
         if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
             TransportParams::encode_param(
                 &mut b,
@@ -5630,7 +8498,7 @@ impl TransportParams {
             Some(self.initial_max_stream_data_uni.to_string()),
             Some(self.initial_max_streams_bidi.to_string()),
             Some(self.initial_max_streams_uni.to_string()),
-            None, // preferred address
+            self.preferred_address.as_ref().map(|v| v.to_qlog()),
         )
     }
 }
@@ -5679,9 +8547,16 @@ pub mod testing {
                     Some("quic.tech"),
                     &client_scid,
                     client_addr,
+                    server_addr,
+                    config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
                     config,
                 )?,
-                server: accept(&server_scid, None, server_addr, config)?,
             })
         }
 
@@ -5712,9 +8587,16 @@ pub mod testing {
                     Some("quic.tech"),
                     &client_scid,
                     client_addr,
+                    server_addr,
                     client_config,
                 )?,
-                server: accept(&server_scid, None, server_addr, &mut config)?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    &mut config,
+                )?,
             })
         }
 
@@ -5743,9 +8625,16 @@ pub mod testing {
                     Some("quic.tech"),
                     &client_scid,
                     client_addr,
+                    server_addr,
                     &mut config,
                 )?,
-                server: accept(&server_scid, None, server_addr, server_config)?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    server_config,
+                )?,
             })
         }
 
@@ -5787,17 +8676,13 @@ pub mod testing {
         }
 
         pub fn client_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let info = RecvInfo {
-                from: self.client.peer_addr,
-            };
+            let info = active_path_recv_info(&self.client)?;
 
             self.client.recv(buf, info)
         }
 
         pub fn server_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let info = RecvInfo {
-                from: self.server.peer_addr,
-            };
+            let info = active_path_recv_info(&self.server)?;
 
             self.server.recv(buf, info)
         }
@@ -5811,12 +8696,22 @@ pub mod testing {
         }
     }
 
+    /// Returns the `RecvInfo` of a packet received on the connection's
+    /// active path.
+    pub fn active_path_recv_info(conn: &Connection) -> Result<RecvInfo> {
+        let path = conn.paths.get_active()?;
+
+        Ok(RecvInfo {
+            from: path.peer_addr(),
+            to: path.local_addr(),
+            ecn: 0,
+        })
+    }
+
     pub fn recv_send(
         conn: &mut Connection, buf: &mut [u8], len: usize,
     ) -> Result<usize> {
-        let info = RecvInfo {
-            from: conn.peer_addr,
-        };
+        let info = active_path_recv_info(conn)?;
 
         conn.recv(&mut buf[..len], info)?;
 
@@ -5834,11 +8729,13 @@ pub mod testing {
     }
 
     pub fn process_flight(
-        conn: &mut Connection, flight: Vec<Vec<u8>>,
+        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
     ) -> Result<()> {
-        for mut pkt in flight {
+        for (mut pkt, si) in flight {
             let info = RecvInfo {
-                from: conn.peer_addr,
+                from: si.from,
+                to: si.to,
+                ecn: si.ecn,
             };
 
             conn.recv(&mut pkt, info)?;
@@ -5847,21 +8744,26 @@ pub mod testing {
         Ok(())
     }
 
-    pub fn emit_flight(conn: &mut Connection) -> Result<Vec<Vec<u8>>> {
+    pub fn emit_flight(
+        conn: &mut Connection,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
         let mut flight = Vec::new();
 
         loop {
             let mut out = vec![0u8; 65535];
 
-            match conn.send(&mut out) {
-                Ok((written, _)) => out.truncate(written),
+            let info = match conn.send(&mut out) {
+                Ok((written, info)) => {
+                    out.truncate(written);
+                    info
+                },
 
                 Err(Error::Done) => break,
 
                 Err(e) => return Err(e),
             };
 
-            flight.push(out);
+            flight.push((out, info));
         }
 
         if flight.is_empty() {
@@ -5879,6 +8781,9 @@ pub mod testing {
 
         let epoch = pkt_type.to_epoch()?;
 
+        let dcid = conn.destination_id().into_owned();
+        let scid = conn.source_id().into_owned();
+
         let space = &mut conn.pkt_num_spaces[epoch];
 
         let pn = space.next_pkt_num;
@@ -5887,13 +8792,13 @@ pub mod testing {
         let hdr = Header {
             ty: pkt_type,
             version: conn.version,
-            dcid: ConnectionId::from_ref(&conn.dcid),
-            scid: ConnectionId::from_ref(&conn.scid),
+            dcid,
+            scid,
             pkt_num: 0,
             pkt_num_len: pn_len,
             token: conn.token.clone(),
             versions: None,
-            key_phase: false,
+            key_phase: conn.key_phase,
         };
 
         hdr.to_bytes(&mut b)?;
@@ -5940,7 +8845,7 @@ pub mod testing {
     ) -> Result<Vec<frame::Frame>> {
         let mut b = octets::OctetsMut::with_slice(&mut buf[..len]);
 
-        let mut hdr = Header::from_bytes(&mut b, conn.scid.len()).unwrap();
+        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
 
         let epoch = hdr.ty.to_epoch()?;
 
@@ -5992,16 +8897,23 @@ mod tests {
             ack_delay_exponent: 20,
             max_ack_delay: 2_u64.pow(14) - 1,
             disable_active_migration: true,
+            preferred_address: Some(PreferredAddress {
+                ipv4: Some("192.0.2.1:443".parse().unwrap()),
+                ipv6: None,
+                connection_id: vec![0xab; 8].into(),
+                stateless_reset_token: 0xba,
+            }),
             active_conn_id_limit: 8,
             initial_source_connection_id: Some(b"woot woot".to_vec().into()),
             retry_source_connection_id: Some(b"retry".to_vec().into()),
             max_datagram_frame_size: Some(32),
+            version_information: None,
         };
 
         let mut raw_params = [42; 256];
         let raw_params =
             TransportParams::encode(&tp, true, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 94);
+        assert_eq!(raw_params.len(), 145);
 
         let new_tp = TransportParams::decode(&raw_params, false).unwrap();
 
@@ -6022,22 +8934,68 @@ mod tests {
             ack_delay_exponent: 20,
             max_ack_delay: 2_u64.pow(14) - 1,
             disable_active_migration: true,
+            preferred_address: None,
             active_conn_id_limit: 8,
             initial_source_connection_id: Some(b"woot woot".to_vec().into()),
             retry_source_connection_id: None,
             max_datagram_frame_size: Some(32),
+            version_information: Some(VersionInformation {
+                chosen_version: crate::PROTOCOL_VERSION_V1,
+                available_versions: vec![
+                    crate::PROTOCOL_VERSION_V1,
+                    crate::PROTOCOL_VERSION_V2,
+                ],
+            }),
         };
 
         let mut raw_params = [42; 256];
         let raw_params =
             TransportParams::encode(&tp, false, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 69);
+        assert_eq!(raw_params.len(), 83);
 
         let new_tp = TransportParams::decode(&raw_params, true).unwrap();
 
         assert_eq!(new_tp, tp);
     }
 
+    #[test]
+    fn transport_params_preferred_address() {
+        let mut tp = TransportParams {
+            preferred_address: Some(PreferredAddress {
+                ipv4: None,
+                ipv6: Some("[2001:db8::1]:443".parse().unwrap()),
+                connection_id: vec![0xab; 8].into(),
+                stateless_reset_token: 0xba,
+            }),
+            ..Default::default()
+        };
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
+
+        let new_tp = TransportParams::decode(raw_params, false).unwrap();
+        assert_eq!(new_tp, tp);
+
+        // Clients can't send a preferred address.
+        assert_eq!(
+            TransportParams::decode(raw_params, true),
+            Err(Error::InvalidTransportParam)
+        );
+
+        // The connection ID can't be empty.
+        tp.preferred_address.as_mut().unwrap().connection_id = vec![].into();
+
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
+
+        assert_eq!(
+            TransportParams::decode(raw_params, false),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
     #[test]
     fn unknown_version() {
         let mut config = Config::new(0xbabababa).unwrap();
@@ -6089,85 +9047,250 @@ mod tests {
         assert_eq!(pipe.server.version, PROTOCOL_VERSION);
     }
 
-    #[test]
-    fn verify_custom_root() {
-        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
-        config.verify_peer(true);
+    fn version_config(version: u32) -> Config {
+        let mut config = Config::new(version).unwrap();
         config
-            .load_verify_locations_from_file("examples/rootca.crt")
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
             .unwrap();
         config
             .set_application_protos(b"\x06proto1\x06proto2")
             .unwrap();
-
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+        config
     }
 
     #[test]
-    fn missing_initial_source_connection_id() {
+    fn handshake_v2() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::default().unwrap();
+        let mut config = version_config(PROTOCOL_VERSION_V2);
 
-        // Reset initial_source_connection_id.
-        pipe.client
-            .local_transport_params
-            .initial_source_connection_id = None;
-        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        assert_eq!(pipe.client.version(), PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version(), PROTOCOL_VERSION_V2);
 
-        // Server rejects transport parameters.
-        assert_eq!(
-            pipe.server_recv(&mut buf[..len]),
-            Err(Error::InvalidTransportParam)
-        );
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, true)));
     }
 
     #[test]
-    fn invalid_initial_source_connection_id() {
-        let mut buf = [0; 65535];
-
-        let mut pipe = testing::Pipe::default().unwrap();
-
-        // Scramble initial_source_connection_id.
-        pipe.client
-            .local_transport_params
-            .initial_source_connection_id = Some(b"bogus value".to_vec().into());
-        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+    fn compatible_version_negotiation() {
+        let mut config = version_config(PROTOCOL_VERSION_V1);
+        config
+            .set_compatible_versions(&[PROTOCOL_VERSION_V2])
+            .unwrap();
 
-        // Client sends initial flight.
-        let (len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        // Server rejects transport parameters.
-        assert_eq!(
-            pipe.server_recv(&mut buf[..len]),
-            Err(Error::InvalidTransportParam)
-        );
+        // The connection was upgraded to v2 without an extra round-trip.
+        assert_eq!(pipe.client.version(), PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version(), PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.client.original_version, PROTOCOL_VERSION_V1);
+        assert_eq!(pipe.server.original_version, PROTOCOL_VERSION_V1);
     }
 
     #[test]
-    fn handshake() {
-        let mut pipe = testing::Pipe::default().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
+    fn compatible_version_negotiation_split_first_flight() {
+        let mut client_config = version_config(PROTOCOL_VERSION_V1);
+        client_config
+            .set_compatible_versions(&[PROTOCOL_VERSION_V2])
+            .unwrap();
 
-        assert_eq!(
-            pipe.client.application_proto(),
-            pipe.server.application_proto()
-        );
-    }
+        // Offer enough application protocols for the ClientHello not to fit
+        // in a single packet.
+        let mut protos = Vec::new();
 
-    #[test]
-    fn handshake_done() {
-        let mut pipe = testing::Pipe::default().unwrap();
+        for i in 0..8 {
+            protos.push(200);
+            protos.extend_from_slice(&[b'a' + i; 200]);
+        }
 
-        // Disable session tickets on the server (SSL_OP_NO_TICKET) to avoid
-        // triggering 1-RTT packet send with a CRYPTO frame.
-        pipe.server
-            .handshake
-            .lock()
+        protos.extend_from_slice(b"\x06proto1");
+
+        client_config.set_application_protos(&protos).unwrap();
+
+        let mut server_config = version_config(PROTOCOL_VERSION_V1);
+        server_config
+            .set_compatible_versions(&[PROTOCOL_VERSION_V2])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_config(&mut client_config).unwrap();
+        pipe.server = accept(
+            &ConnectionId::from_ref(&[0xba; 16]),
+            None,
+            "127.0.0.1:4321".parse().unwrap(),
+            "127.0.0.1:1234".parse().unwrap(),
+            &mut server_config,
+        )
+        .unwrap();
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(flight.len(), 2);
+
+        // The server switches to v2 once the whole ClientHello is received.
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+        assert_eq!(pipe.server.version(), PROTOCOL_VERSION_V2);
+
+        let initial = &pipe.server.pkt_num_spaces[packet::EPOCH_INITIAL];
+        assert_eq!(initial.largest_rx_pkt_num, 1);
+
+        // The server's response is delayed, so the client retransmits its
+        // first flight in the original version.
+        let server_flight = testing::emit_flight(&mut pipe.server).unwrap();
+
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.client.on_timeout();
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(pipe.client.version(), PROTOCOL_VERSION_V1);
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        let initial = &pipe.server.pkt_num_spaces[packet::EPOCH_INITIAL];
+        assert!(initial.largest_rx_pkt_num > 1);
+
+        testing::process_flight(&mut pipe.client, server_flight).unwrap();
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version(), PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version(), PROTOCOL_VERSION_V2);
+
+        // Packets in the original version are not accepted anymore.
+        assert!(pipe.server.original_version_open.is_none());
+    }
+
+    #[test]
+    fn compatible_version_negotiation_not_offered() {
+        let mut client_config = version_config(PROTOCOL_VERSION_V1);
+
+        let mut server_config = version_config(PROTOCOL_VERSION_V1);
+        server_config
+            .set_compatible_versions(&[PROTOCOL_VERSION_V2])
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_config(&mut client_config).unwrap();
+        pipe.server = accept(
+            &ConnectionId::from_ref(&[0xba; 16]),
+            None,
+            "127.0.0.1:4321".parse().unwrap(),
+            "127.0.0.1:1234".parse().unwrap(),
+            &mut server_config,
+        )
+        .unwrap();
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The client didn't advertise v2, so v1 is kept.
+        assert_eq!(pipe.client.version(), PROTOCOL_VERSION_V1);
+        assert_eq!(pipe.server.version(), PROTOCOL_VERSION_V1);
+    }
+
+    #[test]
+    fn set_compatible_versions_unsupported() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(
+            config.set_compatible_versions(&[0xbabababa]),
+            Err(Error::UnknownVersion)
+        );
+    }
+
+    #[test]
+    fn verify_custom_root() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config.verify_peer(true);
+        config
+            .load_verify_locations_from_file("examples/rootca.crt")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[test]
+    fn missing_initial_source_connection_id() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        // Reset initial_source_connection_id.
+        pipe.client
+            .local_transport_params
+            .initial_source_connection_id = None;
+        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server rejects transport parameters.
+        assert_eq!(
+            pipe.server_recv(&mut buf[..len]),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn invalid_initial_source_connection_id() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        // Scramble initial_source_connection_id.
+        pipe.client
+            .local_transport_params
+            .initial_source_connection_id = Some(b"bogus value".to_vec().into());
+        assert_eq!(pipe.client.encode_transport_params(), Ok(()));
+
+        // Client sends initial flight.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        // Server rejects transport parameters.
+        assert_eq!(
+            pipe.server_recv(&mut buf[..len]),
+            Err(Error::InvalidTransportParam)
+        );
+    }
+
+    #[test]
+    fn handshake() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(
+            pipe.client.application_proto(),
+            pipe.server.application_proto()
+        );
+    }
+
+    #[test]
+    fn handshake_done() {
+        let mut pipe = testing::Pipe::default().unwrap();
+
+        // Disable session tickets on the server (SSL_OP_NO_TICKET) to avoid
+        // triggering 1-RTT packet send with a CRYPTO frame.
+        pipe.server
+            .handshake
+            .lock()
             .unwrap()
             .set_options(0x0000_4000);
 
@@ -6545,11 +9668,11 @@ mod tests {
         let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
         let flight = testing::emit_flight(&mut pipe.client).unwrap();
-        let client_sent = flight.iter().fold(0, |out, p| out + p.len());
+        let client_sent = flight.iter().fold(0, |out, p| out + p.0.len());
         testing::process_flight(&mut pipe.server, flight).unwrap();
 
         let flight = testing::emit_flight(&mut pipe.server).unwrap();
-        let server_sent = flight.iter().fold(0, |out, p| out + p.len());
+        let server_sent = flight.iter().fold(0, |out, p| out + p.0.len());
 
         assert_eq!(server_sent, client_sent * MAX_AMPLIFICATION_FACTOR);
     }
@@ -6635,6 +9758,80 @@ mod tests {
         assert_eq!(&b[..12], b"hello, world");
     }
 
+    #[test]
+    fn stream_send_owned() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let data = SharedBuf::from(b"hello, world, and more".to_vec());
+
+        // Only part of the buffer fits in the stream's flow control window.
+        assert_eq!(pipe.client.stream_send_owned(4, data.clone(), true), Ok(15));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 30];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((15, false)));
+        assert_eq!(&b[..15], b"hello, world, a");
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.stream_send_owned(4, data.slice(15..), true),
+            Ok(7)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((7, true)));
+        assert_eq!(&b[..7], b"nd more");
+
+        // Plain vectors can also be used.
+        assert_eq!(
+            pipe.client.stream_send_owned(8, b"hi".to_vec(), true),
+            Ok(2)
+        );
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(8, &mut b), Ok((2, true)));
+        assert_eq!(&b[..2], b"hi");
+    }
+
+    #[test]
+    fn stream_recv_chunks() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", true), Ok(12));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut out = Vec::new();
+
+        // Consume only the first 5 bytes.
+        assert_eq!(
+            pipe.server.stream_recv_chunks(4, |chunk, _| {
+                let len = std::cmp::min(chunk.len(), 5);
+                out.extend_from_slice(&chunk[..len]);
+                len
+            }),
+            Ok((5, false))
+        );
+        assert_eq!(&out, b"hello");
+        assert!(pipe.server.stream_readable(4));
+
+        assert_eq!(
+            pipe.server.stream_recv_chunks(4, |chunk, _| {
+                out.extend_from_slice(chunk);
+                chunk.len()
+            }),
+            Ok((7, true))
+        );
+        assert_eq!(&out, b"hello, world");
+
+        assert!(pipe.server.stream_finished(4));
+        assert_eq!(
+            pipe.server.stream_recv_chunks(4, |chunk, _| chunk.len()),
+            Err(Error::Done)
+        );
+    }
+
     #[test]
     fn stream_send_on_32bit_arch() {
         let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -6768,13 +9965,18 @@ mod tests {
                 .unwrap();
         assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
 
-        assert_eq!(pipe.server.max_send_bytes, 195);
+        assert_eq!(pipe.server.paths.get_active().unwrap().max_send_bytes, 195);
 
         // Force server to send a single PING frame.
-        pipe.server.recovery.loss_probes[packet::EPOCH_INITIAL] = 1;
+        pipe.server
+            .paths
+            .get_active_mut()
+            .unwrap()
+            .recovery
+            .loss_probes[packet::EPOCH_INITIAL] = 1;
 
         // Artifically limit the amount of bytes the server can send.
-        pipe.server.max_send_bytes = 60;
+        pipe.server.paths.get_active_mut().unwrap().max_send_bytes = 60;
 
         assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
     }
@@ -7463,73 +10665,817 @@ mod tests {
             },
         ];
 
-        let pkt_type = packet::Type::Short;
-        assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
+
+    #[test]
+    /// Tests that RESET_STREAM frames exceeding the stream-level flow control
+    /// limit cause an error.
+    fn reset_stream_flow_control_stream() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"a", 0, false),
+            },
+            frame::Frame::ResetStream {
+                stream_id: 4,
+                error_code: 0,
+                final_size: 16, // Past stream's flow control limit.
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::FlowControl),
+        );
+    }
+
+    #[test]
+    fn path_challenge() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::PathChallenge {
+            data: vec![0xba; 8],
+        }];
+
+        let pkt_type = packet::Type::Short;
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        assert!(len > 0);
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+        let mut iter = frames.iter();
+
+        // Ignore ACK.
+        iter.next().unwrap();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::PathResponse {
+                data: vec![0xba; 8],
+            })
+        );
+    }
+
+    #[test]
+    fn connection_id_rotation() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let initial_scid = pipe.server.source_id().into_owned();
+
+        assert_eq!(pipe.server.active_source_cids(), 1);
+        assert_eq!(pipe.server.source_cids_left(), 1);
+        assert_eq!(pipe.client.available_dcids(), 1);
+
+        let scid_1 = ConnectionId::from_vec(vec![0xb1; 16]);
+        assert_eq!(pipe.server.new_source_cid(&scid_1, 0x11, false), Ok(1));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.available_dcids(), 2);
+        assert_eq!(pipe.server.source_cids_left(), 0);
+
+        // The peer's limit was reached.
+        let scid_2 = ConnectionId::from_vec(vec![0xb2; 16]);
+        assert_eq!(
+            pipe.server.new_source_cid(&scid_2, 0x22, false),
+            Err(Error::IdLimit)
+        );
+
+        // Retiring the oldest connection ID makes room for the new one.
+        assert_eq!(pipe.server.new_source_cid(&scid_2, 0x22, true), Ok(2));
+        assert_eq!(pipe.server.source_id(), scid_2);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The client switched to the oldest connection ID still available,
+        // and retired the initial one.
+        assert_eq!(pipe.client.available_dcids(), 2);
+        assert_eq!(pipe.client.destination_id(), scid_1);
+
+        assert_eq!(pipe.server.retired_scids(), 1);
+        assert_eq!(pipe.server.retired_scid_next(), Some(initial_scid));
+        assert_eq!(pipe.server.retired_scid_next(), None);
+
+        let source_ids: Vec<_> = pipe.server.source_ids().cloned().collect();
+        assert_eq!(source_ids, vec![scid_1, scid_2]);
+
+        // Data can still flow.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+    }
+
+    #[test]
+    fn connection_id_limit_exceeded() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [
+            frame::Frame::NewConnectionId {
+                seq_num: 1,
+                retire_prior_to: 0,
+                conn_id: vec![0xb1; 16],
+                reset_token: vec![0x11; 16],
+            },
+            frame::Frame::NewConnectionId {
+                seq_num: 2,
+                retire_prior_to: 0,
+                conn_id: vec![0xb2; 16],
+                reset_token: vec![0x22; 16],
+            },
+        ];
+
+        let pkt_type = packet::Type::Short;
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::IdLimit)
+        );
+    }
+
+    #[test]
+    fn connection_id_retire_prior_to() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let frames = [frame::Frame::NewConnectionId {
+            seq_num: 2,
+            retire_prior_to: 1,
+            conn_id: vec![0xb2; 16],
+            reset_token: vec![0x22; 16],
+        }];
+
+        let pkt_type = packet::Type::Short;
+
+        let len = pipe
+            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+            .unwrap();
+
+        assert!(len > 0);
+
+        assert_eq!(pipe.server.available_dcids(), 1);
+        assert_eq!(pipe.server.destination_id().as_ref(), &[0xb2; 16]);
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
+
+        assert!(frames.contains(&frame::Frame::RetireConnectionId { seq_num: 0 }));
+    }
+
+    #[test]
+    fn retire_connection_id_invalid() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The sequence number was never issued.
+        let frames = [frame::Frame::RetireConnectionId { seq_num: 3 }];
+
+        let pkt_type = packet::Type::Short;
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidState)
+        );
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The connection ID being retired is the one the packet is sent to.
+        let frames = [frame::Frame::RetireConnectionId { seq_num: 0 }];
+
+        assert_eq!(
+            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    fn path_validation() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+        let client_addr = "127.0.0.1:1234".parse().unwrap();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        // Not enough connection IDs for a new path.
+        assert_eq!(
+            pipe.client.probe_path(client_addr_2, server_addr),
+            Err(Error::OutOfIdentifiers)
+        );
+
+        let scid_1 = ConnectionId::from_vec(vec![0xb1; 16]);
+        assert_eq!(pipe.server.new_source_cid(&scid_1, 0x11, false), Ok(1));
+
+        let scid_2 = ConnectionId::from_vec(vec![0xa1; 16]);
+        assert_eq!(pipe.client.new_source_cid(&scid_2, 0x22, false), Ok(1));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2))
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr_2, server_addr))
+        );
+        assert_eq!(pipe.client.path_event_next(), None);
+
+        // Probing didn't change the active path.
+        let path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(path.peer_addr(), client_addr);
+
+        let path = pipe.client.paths.get_active().unwrap();
+        assert_eq!(path.local_addr(), client_addr);
+
+        let stats: Vec<_> = pipe.client.path_stats().collect();
+        assert_eq!(stats.len(), 2);
+        assert!(stats.iter().all(|s| s.validated));
+    }
+
+    #[test]
+    fn connection_migration() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        let scid_1 = ConnectionId::from_vec(vec![0xb1; 16]);
+        assert_eq!(pipe.server.new_source_cid(&scid_1, 0x11, false), Ok(1));
+
+        let scid_2 = ConnectionId::from_vec(vec![0xa1; 16]);
+        assert_eq!(pipe.client.new_source_cid(&scid_2, 0x22, false), Ok(1));
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.migrate(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.client.destination_id(), scid_1);
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::PeerMigrated(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::Validated(server_addr, client_addr_2))
+        );
+
+        let path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(path.peer_addr(), client_addr_2);
+        assert!(path.validated());
+
+        let path = pipe.client.paths.get_active().unwrap();
+        assert_eq!(path.local_addr(), client_addr_2);
+        assert!(path.validated());
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+    }
+
+    #[test]
+    fn connection_migration_disabled() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_disable_active_migration(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        let scid_1 = ConnectionId::from_vec(vec![0xb1; 16]);
+        assert_eq!(pipe.server.new_source_cid(&scid_1, 0x11, false), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(
+            pipe.client.migrate(client_addr_2, server_addr),
+            Err(Error::InvalidState)
+        );
+        assert_eq!(
+            pipe.client.probe_path(client_addr_2, server_addr),
+            Err(Error::InvalidState)
+        );
+    }
+
+    #[test]
+    fn nat_rebinding() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let server_addr = "127.0.0.1:4321".parse().unwrap();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
+
+        // The client's packet is seen from a new address by the server.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+        for (_, si) in flight.iter_mut() {
+            si.from = client_addr_2;
+        }
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::New(server_addr, client_addr_2))
+        );
+        assert_eq!(
+            pipe.server.path_event_next(),
+            Some(PathEvent::PeerMigrated(server_addr, client_addr_2))
+        );
+        assert_eq!(pipe.server.path_event_next(), None);
+
+        // Without a spare connection ID, the one of the previous path is
+        // reused.
+        let path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(path.peer_addr(), client_addr_2);
+        assert_eq!(path.active_dcid_seq, Some(0));
+        assert!(!path.validated());
+
+        // Until the new address is validated, the server is limited by the
+        // anti-amplification limit.
+        let flight = testing::emit_flight(&mut pipe.server).unwrap();
+        let sent = flight.iter().fold(0, |out, p| out + p.0.len()) as u64;
+        let recv = pipe.server.paths.get_active().unwrap().recv_bytes;
+        assert!(sent <= recv * MAX_AMPLIFICATION_FACTOR as u64);
+        assert!(flight.iter().all(|(_, si)| si.to == client_addr_2));
+
+        let mut pkt = flight[0].0.clone();
+        let len = pkt.len();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.client, &mut pkt, len).unwrap();
+
+        assert!(frames
+            .iter()
+            .any(|f| matches!(f, frame::Frame::PathChallenge { .. })));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+    }
+
+    #[test]
+    fn preferred_address_migration() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.set_disable_active_migration(true);
+        config.verify_peer(false);
+
+        let preferred_addr = "127.0.0.1:8765".parse().unwrap();
+        config.set_preferred_address(Some(preferred_addr), None);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let client_addr = "127.0.0.1:1234".parse().unwrap();
+        let preferred_addr = SocketAddr::V4(preferred_addr);
+
+        // The preferred address' connection ID is issued by the server.
+        assert_eq!(pipe.server.active_source_cids(), 2);
+
+        let stats = pipe.client.stats();
+        let preferred_address = stats.peer_preferred_address.unwrap();
+        assert_eq!(
+            preferred_address.ipv4,
+            Some("127.0.0.1:8765".parse().unwrap())
+        );
+        assert_eq!(preferred_address.ipv6, None);
+
+        let source_ids: Vec<_> = pipe.server.source_ids().cloned().collect();
+        assert_eq!(source_ids[1], preferred_address.connection_id);
+
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The client validated the preferred address and migrated to it,
+        // despite active migration being disabled.
+        assert_eq!(
+            pipe.client.path_event_next(),
+            Some(PathEvent::Validated(client_addr, preferred_addr))
+        );
+
+        let path = pipe.client.paths.get_active().unwrap();
+        assert_eq!(path.peer_addr(), preferred_addr);
+        assert_eq!(
+            pipe.client.destination_id(),
+            preferred_address.connection_id
+        );
+
+        // The server follows the client to its preferred address.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(path.local_addr(), preferred_addr);
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(4, &mut b), Ok((5, true)));
+    }
+
+    #[test]
+    fn key_update() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert!(pipe.client.key_phase);
+
+        // A new key update can't be initiated until the current one is
+        // acknowledged.
+        assert_eq!(pipe.client.initiate_key_update(), Err(Error::InvalidState));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // The server updated its keys as well.
+        assert!(pipe.server.key_phase);
+        assert_eq!(pipe.server.key_generation, 1);
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, true)));
+
+        assert_eq!(pipe.server.stream_send(4, b"world", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.stream_recv(4, &mut buf), Ok((5, true)));
+
+        // Now the server initiates a key update.
+        assert_eq!(pipe.server.initiate_key_update(), Ok(()));
+
+        assert_eq!(pipe.server.stream_send(1, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert!(!pipe.client.key_phase);
+        assert_eq!(pipe.client.key_generation, 2);
+
+        assert_eq!(pipe.client.stream_recv(1, &mut buf), Ok((5, true)));
+    }
+
+    #[test]
+    fn key_update_reordered() {
+        let mut buf = [0; 65535];
+
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Packet sent with the old keys.
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+        let old_flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+
+        // Packet sent with the new keys.
+        assert_eq!(pipe.client.stream_send(8, b"world", true), Ok(5));
+        let new_flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        // The new keys are used as soon as they are received...
+        assert_eq!(
+            testing::process_flight(&mut pipe.server, new_flight),
+            Ok(())
+        );
+        assert!(pipe.server.key_phase);
+
+        // ...but reordered packets can still be decrypted with the old ones.
+        assert_eq!(
+            testing::process_flight(&mut pipe.server, old_flight),
+            Ok(())
+        );
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, true)));
+        assert_eq!(pipe.server.stream_recv(8, &mut buf), Ok((5, true)));
+
+        // The old keys are discarded after a while.
+        let timer = pipe.server.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .key_update
+            .as_ref()
+            .unwrap()
+            .timer;
+        assert!(timer.is_some());
+
+        let timeout = pipe.server.timeout().unwrap();
+        std::thread::sleep(timeout + time::Duration::from_millis(1));
+
+        pipe.server.on_timeout();
+
+        assert!(pipe.server.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .key_update
+            .is_none());
+    }
+
+    #[test]
+    fn key_update_before_ack() {
+        let mut pipe = testing::Pipe::default().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello", true), Ok(5));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+        assert_eq!(testing::process_flight(&mut pipe.server, flight), Ok(()));
+        assert!(pipe.server.key_phase);
+
+        // Force the client to update its keys once more, without waiting for
+        // the acknowledgement.
+        pipe.client.pkt_num_spaces[packet::EPOCH_APPLICATION]
+            .key_update
+            .as_mut()
+            .unwrap()
+            .update_acked = true;
+
+        assert_eq!(pipe.client.initiate_key_update(), Ok(()));
+        assert_eq!(pipe.client.stream_send(8, b"hello", true), Ok(5));
+
+        let flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        // The server didn't acknowledge the first update yet.
+        assert_eq!(
+            testing::process_flight(&mut pipe.server, flight),
+            Err(Error::KeyUpdate)
+        );
+    }
+
+    #[test]
+    fn stateless_reset() {
+        let secret = [0xba; 32];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_stateless_reset_secret(&secret);
+
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The server advertised the token of its initial connection ID.
+        let token = stateless_reset_token(&secret, &pipe.client.destination_id());
+        assert_eq!(
+            pipe.client.peer_transport_params.stateless_reset_token,
+            Some(token.to_be_bytes().to_vec())
+        );
+
+        // A reset with an unknown token is ignored.
+        let mut buf = [0; 43];
+        assert_eq!(crate::stateless_reset(0xba, &mut buf), Ok(43));
+        assert_eq!(pipe.client_recv(&mut buf), Ok(43));
+        assert!(!pipe.client.is_draining());
+
+        // The server lost its state and resets the connection.
+        assert_eq!(crate::stateless_reset(token, &mut buf), Ok(43));
+        assert_eq!(pipe.client_recv(&mut buf), Err(Error::StatelessReset));
+        assert!(pipe.client.is_draining());
+
+        let mut out = [0; 1200];
+        assert_eq!(pipe.client.send(&mut out), Err(Error::Done));
+    }
+
+    #[test]
+    fn new_token() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.verify_peer(false);
+        config.set_address_token_key(AddressTokenKey::new(&[0xba; 32]));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        assert_eq!(pipe.server.send_new_token(), Err(Error::InvalidState));
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Only servers can send tokens.
+        assert_eq!(pipe.client.send_new_token(), Err(Error::InvalidState));
+
+        assert_eq!(pipe.server.send_new_token(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let token = pipe.client.new_token_next().unwrap();
+        assert_eq!(pipe.client.new_token_next(), None);
+
+        // A new connection using the token has its address validated right
+        // away.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.client = connect_with_token(
+            Some("quic.tech"),
+            &pipe.client.source_id(),
+            Some(&token),
+            "127.0.0.1:1234".parse().unwrap(),
+            "127.0.0.1:4321".parse().unwrap(),
+            &mut config,
+        )
+        .unwrap();
+
+        let len = pipe.client.send(&mut buf).unwrap().0;
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert!(
+            pipe.server
+                .paths
+                .get_active()
+                .unwrap()
+                .verified_peer_address
+        );
+
+        assert_eq!(pipe.advance(), Ok(()));
+        assert!(pipe.client.is_established());
+
+        // Invalid tokens are ignored.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.client = connect_with_token(
+            Some("quic.tech"),
+            &pipe.client.source_id(),
+            Some(&[0xba; 64]),
+            "127.0.0.1:1234".parse().unwrap(),
+            "127.0.0.1:4321".parse().unwrap(),
+            &mut config,
+        )
+        .unwrap();
+
+        let len = pipe.client.send(&mut buf).unwrap().0;
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert!(
+            !pipe
+                .server
+                .paths
+                .get_active()
+                .unwrap()
+                .verified_peer_address
         );
+
+        assert_eq!(pipe.advance(), Ok(()));
+        assert!(pipe.client.is_established());
     }
 
     #[test]
-    /// Tests that RESET_STREAM frames exceeding the stream-level flow control
-    /// limit cause an error.
-    fn reset_stream_flow_control_stream() {
+    fn new_token_limit() {
         let mut buf = [0; 65535];
 
         let mut pipe = testing::Pipe::default().unwrap();
         assert_eq!(pipe.handshake(), Ok(()));
 
-        let frames = [
-            frame::Frame::Stream {
-                stream_id: 4,
-                data: stream::RangeBuf::from(b"a", 0, false),
-            },
-            frame::Frame::ResetStream {
-                stream_id: 4,
-                error_code: 0,
-                final_size: 16, // Past stream's flow control limit.
-            },
-        ];
+        let frames: Vec<frame::Frame> = (0..10)
+            .map(|i| frame::Frame::NewToken { token: vec![i; 16] })
+            .collect();
 
         let pkt_type = packet::Type::Short;
+        let len =
+            testing::encode_pkt(&mut pipe.server, pkt_type, &frames, &mut buf)
+                .unwrap();
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+
+        // Only the latest tokens are kept.
+        let tokens: Vec<Vec<u8>> =
+            std::iter::from_fn(|| pipe.client.new_token_next()).collect();
+        assert_eq!(tokens, (6..10).map(|i| vec![i; 16]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stateless_reset_token_derivation() {
+        let cid_1 = ConnectionId::from_ref(&[0xa1; 16]);
+        let cid_2 = ConnectionId::from_ref(&[0xa2; 16]);
+
         assert_eq!(
-            pipe.send_pkt_to_server(pkt_type, &frames, &mut buf),
-            Err(Error::FlowControl),
+            stateless_reset_token(b"secret", &cid_1),
+            stateless_reset_token(b"secret", &cid_1)
+        );
+        assert_ne!(
+            stateless_reset_token(b"secret", &cid_1),
+            stateless_reset_token(b"secret", &cid_2)
+        );
+        assert_ne!(
+            stateless_reset_token(b"secret", &cid_1),
+            stateless_reset_token(b"other", &cid_1)
         );
     }
 
     #[test]
-    fn path_challenge() {
+    fn key_update_threshold() {
         let mut buf = [0; 65535];
 
-        let mut pipe = testing::Pipe::default().unwrap();
-        assert_eq!(pipe.handshake(), Ok(()));
-
-        let frames = [frame::Frame::PathChallenge {
-            data: vec![0xba; 8],
-        }];
-
-        let pkt_type = packet::Type::Short;
-
-        let len = pipe
-            .send_pkt_to_server(pkt_type, &frames, &mut buf)
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
             .unwrap();
+        config.set_initial_max_data(1000);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(10);
+        config.set_key_update_threshold(5);
+        config.verify_peer(false);
 
-        assert!(len > 0);
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let frames =
-            testing::decode_pkt(&mut pipe.client, &mut buf, len).unwrap();
-        let mut iter = frames.iter();
+        for i in 0..10 {
+            let stream_id = i * 4;
 
-        // Ignore ACK.
-        iter.next().unwrap();
+            assert_eq!(pipe.client.stream_send(stream_id, b"hello", true), Ok(5));
+            assert_eq!(pipe.advance(), Ok(()));
 
-        assert_eq!(
-            iter.next(),
-            Some(&frame::Frame::PathResponse {
-                data: vec![0xba; 8],
-            })
-        );
+            assert_eq!(
+                pipe.server.stream_recv(stream_id, &mut buf),
+                Ok((5, true))
+            );
+        }
+
+        assert!(pipe.client.key_generation > 0);
+        assert_eq!(pipe.server.key_generation, pipe.client.key_generation);
     }
 
     #[test]
@@ -7696,6 +11642,7 @@ mod tests {
         let frames = [frame::Frame::ACK {
             ack_delay: 15,
             ranges,
+            ecn_counts: None,
         }];
 
         assert_eq!(pipe.send_pkt_to_server(pkt_type, &frames, &mut buf), Ok(0));
@@ -8324,8 +12271,8 @@ mod tests {
         let hdr = Header {
             ty: packet::Type::Initial,
             version: pipe.client.version,
-            dcid: ConnectionId::from_ref(&pipe.client.dcid),
-            scid: ConnectionId::from_ref(&pipe.client.scid),
+            dcid: pipe.client.destination_id().into_owned(),
+            scid: pipe.client.source_id().into_owned(),
             pkt_num: 0,
             pkt_num_len: pn_len,
             token: pipe.client.token.clone(),
@@ -8723,6 +12670,8 @@ mod tests {
         let mut config = Config::new(PROTOCOL_VERSION).unwrap();
 
         assert_eq!(config.set_cc_algorithm_name("reno"), Ok(()));
+        assert_eq!(config.set_cc_algorithm_name("bbr"), Ok(()));
+        assert_eq!(config.cc_algorithm, CongestionControlAlgorithm::BBR);
 
         // Unknown name.
         assert_eq!(
@@ -8731,6 +12680,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn congestion_controller_factory() {
+        struct FixedWindow(usize);
+
+        impl CongestionController for FixedWindow {
+            fn on_packet_sent(
+                &mut self, _: usize, _: time::Instant, _: &RecoveryView,
+            ) {
+            }
+
+            fn on_packets_acked(
+                &mut self, _: &[Acked], _: time::Instant, _: &RecoveryView,
+            ) {
+            }
+
+            fn congestion_event(
+                &mut self, _: usize, _: time::Instant, _: time::Instant,
+                _: &RecoveryView,
+            ) {
+            }
+
+            fn collapse_cwnd(&mut self, _: &RecoveryView) {}
+
+            fn cwnd(&self) -> usize {
+                self.0
+            }
+        }
+
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(1000000);
+        config.set_initial_max_stream_data_bidi_local(1000000);
+        config.set_initial_max_stream_data_bidi_remote(1000000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+        config.set_congestion_controller_factory(|r| {
+            Box::new(FixedWindow(r.max_datagram_size * 3))
+        });
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The send capacity is limited by the window.
+        assert_eq!(pipe.client.stream_send(0, &buf[..50000], true), Ok(3600));
+
+        // Only as much data as allowed by the window is sent.
+        let mut sent = 0;
+
+        while let Ok((len, _)) = pipe.client.send(&mut buf) {
+            sent += len;
+        }
+
+        assert!(sent <= 1200 * 3);
+        assert_eq!(pipe.client.path_stats().next().unwrap().cwnd, 1200 * 3);
+    }
+
     #[test]
     fn peer_cert() {
         let mut pipe = testing::Pipe::default().unwrap();
@@ -8794,7 +12809,8 @@ mod tests {
 
         // Server accepts connection.
         let from = "127.0.0.1:1234".parse().unwrap();
-        pipe.server = accept(&scid, Some(&odcid), from, &mut config).unwrap();
+        let to = "127.0.0.1:4321".parse().unwrap();
+        pipe.server = accept(&scid, Some(&odcid), to, from, &mut config).unwrap();
         assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
         assert_eq!(pipe.advance(), Ok(()));
@@ -8850,7 +12866,8 @@ mod tests {
         // Server accepts connection and send first flight. But original
         // destination connection ID is ignored.
         let from = "127.0.0.1:1234".parse().unwrap();
-        pipe.server = accept(&scid, None, from, &mut config).unwrap();
+        let to = "127.0.0.1:4321".parse().unwrap();
+        pipe.server = accept(&scid, None, to, from, &mut config).unwrap();
         assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
         let flight = testing::emit_flight(&mut pipe.server).unwrap();
@@ -8908,8 +12925,9 @@ mod tests {
         // Server accepts connection and send first flight. But original
         // destination connection ID is invalid.
         let from = "127.0.0.1:1234".parse().unwrap();
+        let to = "127.0.0.1:4321".parse().unwrap();
         let odcid = ConnectionId::from_ref(b"bogus value");
-        pipe.server = accept(&scid, Some(&odcid), from, &mut config).unwrap();
+        pipe.server = accept(&scid, Some(&odcid), to, from, &mut config).unwrap();
         assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
         let flight = testing::emit_flight(&mut pipe.server).unwrap();
@@ -9110,7 +13128,15 @@ mod tests {
         assert_eq!(pipe.advance(), Ok(()));
 
         // app_limited should be true because we send less than cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), true);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active_mut()
+                .unwrap()
+                .recovery
+                .app_limited(),
+            true
+        );
     }
 
     #[test]
@@ -9145,7 +13171,15 @@ mod tests {
 
         // We can't create a new packet header because there is no room by cwnd.
         // app_limited should be false because we can't send more by cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), false);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active_mut()
+                .unwrap()
+                .recovery
+                .app_limited(),
+            false
+        );
     }
 
     #[test]
@@ -9180,7 +13214,15 @@ mod tests {
 
         // We can't create a new packet header because there is no room by cwnd.
         // app_limited should be false because we can't send more by cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), false);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active_mut()
+                .unwrap()
+                .recovery
+                .app_limited(),
+            false
+        );
     }
 
     #[test]
@@ -9215,7 +13257,15 @@ mod tests {
 
         // We can't create a new frame because there is no room by cwnd.
         // app_limited should be false because we can't send more by cwnd.
-        assert_eq!(pipe.server.recovery.app_limited(), false);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active_mut()
+                .unwrap()
+                .recovery
+                .app_limited(),
+            false
+        );
     }
 
     #[test]
@@ -9244,13 +13294,29 @@ mod tests {
 
         // Client's app_limited is true because its bytes-in-flight
         // is much smaller than the current cwnd.
-        assert_eq!(pipe.client.recovery.app_limited(), true);
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active_mut()
+                .unwrap()
+                .recovery
+                .app_limited(),
+            true
+        );
 
         // Client has no new frames to send - returns Done.
         assert_eq!(testing::emit_flight(&mut pipe.client), Err(Error::Done));
 
         // Client's app_limited should remain the same.
-        assert_eq!(pipe.client.recovery.app_limited(), true);
+        assert_eq!(
+            pipe.client
+                .paths
+                .get_active_mut()
+                .unwrap()
+                .recovery
+                .app_limited(),
+            true
+        );
     }
 
     #[test]
@@ -9528,9 +13594,6 @@ mod tests {
 
     #[test]
     /// Tests that changing a stream's priority is correctly propagated.
-    ///
-    /// Re-prioritization is not supported, so this should fail.
-    #[should_panic]
     fn stream_reprioritize() {
         let mut buf = [0; 65535];
 
@@ -9646,6 +13709,156 @@ mod tests {
         assert_eq!(pipe.server.send(&mut buf), Err(Error::Done));
     }
 
+    #[test]
+    /// Tests that streams are scheduled by the application-provided scheduler.
+    fn stream_scheduler_factory() {
+        use std::sync::atomic;
+
+        struct Lifo(Vec<u64>, Arc<atomic::AtomicUsize>);
+
+        impl StreamScheduler for Lifo {
+            fn push(&mut self, stream_id: u64, _: u8, _: bool) {
+                self.0.push(stream_id);
+            }
+
+            fn remove(&mut self, stream_id: u64, _: u8, _: bool) {
+                self.0.retain(|id| *id != stream_id);
+            }
+
+            fn pop(&mut self) -> Option<u64> {
+                self.0.pop()
+            }
+
+            fn is_empty(&self) -> bool {
+                self.0.is_empty()
+            }
+
+            fn on_stream_sent(&mut self, _: u64, len: usize) {
+                self.1.fetch_add(len, atomic::Ordering::SeqCst);
+            }
+        }
+
+        let mut buf = [0; 65535];
+
+        let sent = Arc::new(atomic::AtomicUsize::new(0));
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
+
+        let scheduler_sent = sent.clone();
+        config.set_stream_scheduler_factory(move || {
+            Box::new(Lifo(Vec::new(), scheduler_sent.clone()))
+        });
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaa", true), Ok(5));
+        assert_eq!(pipe.client.stream_send(4, b"bbbbb", true), Ok(5));
+        assert_eq!(pipe.client.stream_send(8, b"ccccc", true), Ok(5));
+
+        // Streams are sent in the reverse order of when they became
+        // flushable.
+        for expected in &[8, 4, 0] {
+            let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+            let frames =
+                testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
+
+            assert!(frames.iter().any(|f| matches!(
+                f,
+                frame::Frame::Stream { stream_id, .. } if stream_id == expected
+            )));
+        }
+
+        assert_eq!(sent.load(atomic::Ordering::SeqCst), 15);
+    }
+
+    #[test]
+    /// Tests that changing the priority of a stream that is partially sent
+    /// takes effect immediately.
+    fn stream_reprioritize_mid_transfer() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(100000);
+        config.set_initial_max_stream_data_bidi_remote(100000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        let data = [42; 5000];
+
+        assert_eq!(pipe.client.stream_priority(0, 10, false), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, &data, true), Ok(5000));
+
+        assert_eq!(pipe.client.stream_priority(4, 20, false), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, &data, true), Ok(5000));
+
+        let mut next_stream = |pipe: &mut testing::Pipe| {
+            let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+            let frames =
+                testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
+
+            frames
+                .iter()
+                .find_map(|f| match f {
+                    frame::Frame::Stream { stream_id, .. } => Some(*stream_id),
+
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        assert_eq!(next_stream(&mut pipe), 0);
+
+        // Stream 4 is moved to a more urgent queue.
+        assert_eq!(pipe.client.stream_priority(4, 5, false), Ok(()));
+
+        assert_eq!(next_stream(&mut pipe), 4);
+        assert_eq!(next_stream(&mut pipe), 4);
+
+        // Within the same urgency, non-incremental streams go first.
+        assert_eq!(pipe.client.stream_priority(4, 10, true), Ok(()));
+
+        assert_eq!(next_stream(&mut pipe), 0);
+        assert_eq!(next_stream(&mut pipe), 0);
+
+        // Stream 0 becomes incremental too, so streams are round-robined.
+        assert_eq!(pipe.client.stream_priority(0, 10, true), Ok(()));
+
+        let first = next_stream(&mut pipe);
+        let second = next_stream(&mut pipe);
+
+        assert_ne!(first, second);
+    }
+
     #[test]
     /// Tests that streams and datagrams are correctly scheduled.
     fn stream_datagram_priority() {
@@ -9804,11 +14017,17 @@ mod tests {
         pipe.client.on_timeout();
 
         let epoch = packet::EPOCH_APPLICATION;
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 1);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            1
+        );
 
         // Client retransmits stream data in PTO probe.
         let (len, _) = pipe.client.send(&mut buf).unwrap();
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 0);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            0
+        );
 
         let frames =
             testing::decode_pkt(&mut pipe.server, &mut buf, len).unwrap();
@@ -9845,12 +14064,18 @@ mod tests {
         pipe.client.on_timeout();
 
         let epoch = packet::EPOCH_INITIAL;
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 1);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            1
+        );
 
         // Client sends PTO probe.
         let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(len, 1200);
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 0);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            0
+        );
 
         // Wait for PTO to expire.
         let timer = pipe.client.timeout().unwrap();
@@ -9858,17 +14083,26 @@ mod tests {
 
         pipe.client.on_timeout();
 
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 2);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            2
+        );
 
         // Client sends first PTO probe.
         let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(len, 1200);
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 1);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            1
+        );
 
         // Client sends second PTO probe.
         let (len, _) = pipe.client.send(&mut buf).unwrap();
         assert_eq!(len, 1200);
-        assert_eq!(pipe.client.recovery.loss_probes[epoch], 0);
+        assert_eq!(
+            pipe.client.paths.get_active().unwrap().recovery.loss_probes[epoch],
+            0
+        );
     }
 
     #[test]
@@ -9974,13 +14208,19 @@ mod tests {
         testing::process_flight(&mut pipe.client, flight).unwrap();
 
         // Client sends Initial packet with ACK.
-        let (ty, len) = pipe.client.send_single(&mut buf, false).unwrap();
+        let (ty, len) = pipe
+            .client
+            .send_single(&mut buf, 0, false, 0, false)
+            .unwrap();
         assert_eq!(ty, Type::Initial);
 
         assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
         // Client sends Handshake packet.
-        let (ty, len) = pipe.client.send_single(&mut buf, false).unwrap();
+        let (ty, len) = pipe
+            .client
+            .send_single(&mut buf, 0, false, 0, false)
+            .unwrap();
         assert_eq!(ty, Type::Handshake);
 
         // Packet type is corrupted to Initial.
@@ -10036,14 +14276,26 @@ mod tests {
             assert_eq!(pipe.client.dgram_send(&send_buf), Ok(()));
         }
 
-        assert!(!pipe.client.recovery.app_limited());
+        assert!(!pipe
+            .client
+            .paths
+            .get_active_mut()
+            .unwrap()
+            .recovery
+            .app_limited());
         assert_eq!(pipe.client.dgram_send_queue.byte_size(), 1_000_000);
 
         let (len, _) = pipe.client.send(&mut buf).unwrap();
 
         assert_ne!(pipe.client.dgram_send_queue.byte_size(), 0);
         assert_ne!(pipe.client.dgram_send_queue.byte_size(), 1_000_000);
-        assert!(!pipe.client.recovery.app_limited());
+        assert!(!pipe
+            .client
+            .paths
+            .get_active_mut()
+            .unwrap()
+            .recovery
+            .app_limited());
 
         assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
@@ -10056,7 +14308,13 @@ mod tests {
         assert_ne!(pipe.client.dgram_send_queue.byte_size(), 0);
         assert_ne!(pipe.client.dgram_send_queue.byte_size(), 1_000_000);
 
-        assert!(!pipe.client.recovery.app_limited());
+        assert!(!pipe
+            .client
+            .paths
+            .get_active_mut()
+            .unwrap()
+            .recovery
+            .app_limited());
     }
 
     #[test]
@@ -10524,22 +14782,48 @@ mod tests {
                 Some("quic.tech"),
                 &client_scid,
                 client_addr,
+                server_addr,
                 &mut client_config,
             )
             .unwrap(),
-            server: accept(&server_scid, None, server_addr, &mut server_config)
-                .unwrap(),
+            server: accept(
+                &server_scid,
+                None,
+                server_addr,
+                client_addr,
+                &mut server_config,
+            )
+            .unwrap(),
         };
 
         // Before handshake
-        assert_eq!(pipe.server.recovery.max_datagram_size(), 1500);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .unwrap()
+                .recovery
+                .max_datagram_size(),
+            1500
+        );
 
         assert_eq!(pipe.handshake(), Ok(()));
 
         // After handshake, max_datagram_size should match to client's
         // max_recv_udp_payload_size which is smaller
-        assert_eq!(pipe.server.recovery.max_datagram_size(), 1200);
-        assert_eq!(pipe.server.recovery.cwnd(), 12000);
+        assert_eq!(
+            pipe.server
+                .paths
+                .get_active()
+                .unwrap()
+                .recovery
+                .max_datagram_size(),
+            1200
+        );
+        assert_eq!(
+            pipe.server.paths.get_active().unwrap().recovery.cwnd(),
+            12000
+        );
     }
 
     #[test]
@@ -10606,18 +14890,382 @@ mod tests {
 
         assert_eq!(pipe.advance(), Ok(()));
     }
+
+    #[test]
+    fn send_batch() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, &[0; 5000], true), Ok(5000));
+
+        // The number of segments is limited.
+        let (written, segment_size, info) =
+            pipe.client.send_batch(&mut buf, 3).unwrap();
+        assert_eq!((written, segment_size), (3600, 1200));
+        assert_eq!(
+            info.to,
+            pipe.server.paths.get_active().unwrap().local_addr()
+        );
+
+        for segment in buf[..written].chunks_mut(segment_size) {
+            assert_eq!(pipe.server_recv(segment), Ok(segment.len()));
+        }
+
+        // The last segment is shorter.
+        let (written, segment_size, _) =
+            pipe.client.send_batch(&mut buf, 10).unwrap();
+        assert_eq!(segment_size, 1200);
+        assert!(written > segment_size && written < 2 * segment_size);
+
+        for segment in buf[..written].chunks_mut(segment_size) {
+            assert_eq!(pipe.server_recv(segment), Ok(segment.len()));
+        }
+
+        assert_eq!(pipe.client.send_batch(&mut buf, 10), Err(Error::Done));
+
+        let mut b = [0; 5000];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5000, true)));
+
+        // Segments don't fit in the buffer.
+        assert_eq!(pipe.server.stream_send(0, &[0; 5000], true), Ok(5000));
+        assert_eq!(
+            pipe.server.send_batch(&mut buf[..1500], 10).map(|v| v.0),
+            Ok(1200)
+        );
+
+        assert_eq!(
+            pipe.server.send_batch(&mut buf, 0),
+            Err(Error::BufferTooShort)
+        );
+    }
+
+    #[test]
+    fn send_batch_short_first_packet() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.enable_dgram(true, 10, 10);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The DATAGRAM frame is sent on its own in a short packet, followed
+        // by full-size packets carrying stream data.
+        assert_eq!(pipe.client.dgram_send(&[0; 100]), Ok(()));
+        assert_eq!(pipe.client.stream_send(0, &[0; 5000], true), Ok(5000));
+
+        // The short packet ends the batch.
+        let (written, segment_size, _) =
+            pipe.client.send_batch(&mut buf, 10).unwrap();
+        assert_eq!(written, segment_size);
+        assert!(segment_size < 1200);
+
+        assert_eq!(pipe.server_recv(&mut buf[..written]), Ok(written));
+
+        // The following packets use the full segment size.
+        let (written, segment_size, _) =
+            pipe.client.send_batch(&mut buf, 3).unwrap();
+        assert_eq!((written, segment_size), (3600, 1200));
+
+        for segment in buf[..written].chunks_mut(segment_size) {
+            assert_eq!(pipe.server_recv(segment), Ok(segment.len()));
+        }
+
+        assert_eq!(pipe.server.dgram_recv(&mut buf), Ok(100));
+    }
+
+    #[test]
+    fn ecn() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+        config.enable_ecn(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(0, b"hello!", true), Ok(6));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(0, &mut buf), Ok((6, true)));
+
+        let path = pipe.client.path_stats().next().unwrap();
+        assert!(path.ecn_capable);
+
+        let path = pipe.server.path_stats().next().unwrap();
+        assert!(path.ecn_capable);
+
+        let client_stats = pipe.client.stats();
+        let server_stats = pipe.server.stats();
+
+        assert!(client_stats.ecn_sent_ect0 > 0);
+        assert_eq!(
+            client_stats.ecn_sent_ect0 as u64,
+            server_stats.ecn_recv_ect0
+        );
+        assert_eq!(server_stats.ecn_recv_ce, 0);
+        assert_eq!(client_stats.ecn_peer_ce, 0);
+    }
+
+    #[test]
+    fn ecn_ce() {
+        let buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+        config.enable_ecn(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let cwnd = pipe.client.stats().cwnd;
+
+        assert_eq!(pipe.client.stream_send(0, &buf[..5000], true), Ok(5000));
+
+        // The network marks the client's packets with CE.
+        let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+        for (_, si) in flight.iter_mut() {
+            assert_eq!(si.ecn, recovery::ecn::ECT0);
+
+            si.ecn = recovery::ecn::CE;
+        }
+
+        let marked = flight.len() as u64;
+
+        testing::process_flight(&mut pipe.server, flight).unwrap();
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stats().ecn_recv_ce, marked);
+        assert_eq!(pipe.client.stats().ecn_peer_ce, marked);
+
+        // CE marks are handled as a congestion event.
+        assert!(pipe.client.stats().cwnd < cwnd);
+        assert!(pipe.client.path_stats().next().unwrap().ecn_capable);
+    }
+
+    #[test]
+    fn ecn_bleached() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.verify_peer(false);
+        config.enable_ecn(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        // The network clears the ECN codepoint of the client's packets.
+        while !pipe.client.is_established() || !pipe.server.is_established() {
+            let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+
+            for (_, si) in flight.iter_mut() {
+                si.ecn = recovery::ecn::NOT_ECT;
+            }
+
+            testing::process_flight(&mut pipe.server, flight).unwrap();
+
+            let flight = testing::emit_flight(&mut pipe.server).unwrap();
+            testing::process_flight(&mut pipe.client, flight).unwrap();
+        }
+
+        assert_eq!(pipe.client.stream_send(0, b"hello!", true), Ok(6));
+
+        let (_, si) = pipe.client.send(&mut buf).unwrap();
+
+        // Validation failed, so packets are not marked anymore.
+        assert_eq!(si.ecn, recovery::ecn::NOT_ECT);
+        assert!(!pipe.client.path_stats().next().unwrap().ecn_capable);
+
+        // The server's packets were not modified.
+        assert!(pipe.server.path_stats().next().unwrap().ecn_capable);
+    }
+
+    #[test]
+    fn pmtud() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(100000);
+        config.set_initial_max_stream_data_bidi_local(10000);
+        config.set_initial_max_stream_data_bidi_remote(10000);
+        config.set_initial_max_streams_bidi(10);
+        config.set_max_send_udp_payload_size(1500);
+        config.verify_peer(false);
+        config.discover_pmtu(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+
+        // Only the base PLPMTU is used until the path is probed.
+        assert_eq!(pipe.client.path_stats().next().unwrap().pmtu, 1200);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The probe is sent once the handshake is confirmed, and acknowledged.
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.client.path_stats().next().unwrap().pmtu, 1500);
+        assert_eq!(pipe.server.path_stats().next().unwrap().pmtu, 1500);
+
+        // Probes are not counted as lost packets.
+        assert_eq!(pipe.client.stats().lost, 0);
+    }
+
+    #[test]
+    fn pmtud_search() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(b"\x06proto1\x06proto2")
+            .unwrap();
+        config.set_initial_max_data(1000000);
+        config.set_initial_max_stream_data_bidi_local(1000000);
+        config.set_initial_max_stream_data_bidi_remote(1000000);
+        config.set_initial_max_streams_bidi(10);
+        config.set_max_send_udp_payload_size(1500);
+        config.verify_peer(false);
+        config.discover_pmtu(true);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // The path drops datagrams larger than 1400 bytes, so the client needs
+        // to search for a smaller size, while sending data in the meantime.
+        for _ in 0..50 {
+            assert_eq!(pipe.client.stream_send(0, &buf[..3000], false), Ok(3000));
+
+            let mut flight = testing::emit_flight(&mut pipe.client).unwrap();
+            flight.retain(|(pkt, _)| pkt.len() <= 1400);
+
+            testing::process_flight(&mut pipe.server, flight).unwrap();
+
+            let flight = testing::emit_flight(&mut pipe.server).unwrap();
+            testing::process_flight(&mut pipe.client, flight).unwrap();
+
+            while pipe.server.stream_recv(0, &mut buf).is_ok() {}
+        }
+
+        let pmtu = pipe.client.path_stats().next().unwrap().pmtu;
+        assert!(pmtu <= 1400);
+        assert!(pmtu > 1380);
+
+        assert_eq!(pipe.client.stats().lost, 0);
+    }
 }
 
+pub use crate::endpoint::ConnectionHandle;
+pub use crate::endpoint::Endpoint;
+pub use crate::endpoint::EndpointEvent;
+
 pub use crate::packet::ConnectionId;
 pub use crate::packet::Header;
 pub use crate::packet::Type;
 
+pub use crate::path::PathEvent;
+
+pub use crate::recovery::Acked;
 pub use crate::recovery::CongestionControlAlgorithm;
+pub use crate::recovery::CongestionController;
+pub use crate::recovery::RecoveryView;
 
+pub use crate::stream::PriorityScheduler;
+pub use crate::stream::SharedBuf;
 pub use crate::stream::StreamIter;
+pub use crate::stream::StreamScheduler;
+
+pub use crate::token::AddressToken;
+pub use crate::token::AddressTokenKey;
 
+mod cid;
 mod crypto;
 mod dgram;
+mod endpoint;
 #[cfg(feature = "ffi")]
 mod ffi;
 mod frame;
@@ -10625,8 +15273,10 @@ pub mod h3;
 mod minmax;
 mod octets;
 mod packet;
+mod path;
 mod rand;
 mod ranges;
 mod recovery;
 mod stream;
 mod tls;
+mod token;
@@ -32,6 +32,7 @@ use crate::Error;
 use crate::Result;
 
 use crate::crypto;
+use crate::frame;
 use crate::octets;
 use crate::rand;
 use crate::ranges;
@@ -48,6 +49,11 @@ pub const MAX_CID_LEN: u8 = 20;
 
 pub const MAX_PKT_NUM_LEN: usize = 4;
 
+pub const RESET_TOKEN_LEN: usize = 16;
+
+// A stateless reset needs at least 5 unpredictable bytes before the token.
+pub const MIN_STATELESS_RESET_LEN: usize = 5 + RESET_TOKEN_LEN;
+
 const SAMPLE_LEN: usize = 16;
 
 pub const EPOCH_INITIAL: usize = 0;
@@ -327,11 +333,15 @@ impl<'a> Header<'a> {
         let ty = if version == 0 {
             Type::VersionNegotiation
         } else {
-            match (first & TYPE_MASK) >> 4 {
-                0x00 => Type::Initial,
-                0x01 => Type::ZeroRTT,
-                0x02 => Type::Handshake,
-                0x03 => Type::Retry,
+            let ty = (first & TYPE_MASK) >> 4;
+
+            // QUIC version 2 uses different long header packet types, as per
+            // RFC 9369 Section 3.2.
+            match (version == crate::PROTOCOL_VERSION_V2, ty) {
+                (false, 0x00) | (true, 0x01) => Type::Initial,
+                (false, 0x01) | (true, 0x02) => Type::ZeroRTT,
+                (false, 0x02) | (true, 0x03) => Type::Handshake,
+                (false, 0x03) | (true, 0x00) => Type::Retry,
                 _ => return Err(Error::InvalidPacket),
             }
         };
@@ -431,6 +441,13 @@ impl<'a> Header<'a> {
             _ => return Err(Error::InvalidPacket),
         };
 
+        // QUIC version 2 rotates the long header packet types by one.
+        let ty = if self.version == crate::PROTOCOL_VERSION_V2 {
+            (ty + 1) & 0x03
+        } else {
+            ty
+        };
+
         first |= FORM_BIT | FIXED_BIT | (ty << 4);
 
         out.put_u8(first)?;
@@ -695,6 +712,7 @@ pub fn negotiate_version(
     b.put_u8(dcid.len() as u8)?;
     b.put_bytes(&dcid)?;
     b.put_u32(crate::PROTOCOL_VERSION_V1)?;
+    b.put_u32(crate::PROTOCOL_VERSION_V2)?;
     b.put_u32(crate::PROTOCOL_VERSION_DRAFT29)?;
     b.put_u32(crate::PROTOCOL_VERSION_DRAFT28)?;
     b.put_u32(crate::PROTOCOL_VERSION_DRAFT27)?;
@@ -733,6 +751,39 @@ pub fn retry(
     Ok(b.off())
 }
 
+/// Returns the stateless reset token carried by the given datagram, if it's
+/// large enough to be a stateless reset.
+pub fn peek_stateless_reset_token(buf: &[u8]) -> Option<u128> {
+    if buf.len() < MIN_STATELESS_RESET_LEN || buf[0] & FORM_BIT != 0 {
+        return None;
+    }
+
+    let mut token = [0; RESET_TOKEN_LEN];
+    token.copy_from_slice(&buf[buf.len() - RESET_TOKEN_LEN..]);
+
+    Some(u128::from_be_bytes(token))
+}
+
+pub fn stateless_reset(token: u128, out: &mut [u8]) -> Result<usize> {
+    let len = out.len();
+
+    if len < MIN_STATELESS_RESET_LEN {
+        return Err(Error::BufferTooShort);
+    }
+
+    let (unpredictable, reset_token) = out.split_at_mut(len - RESET_TOKEN_LEN);
+
+    rand::rand_bytes(unpredictable);
+
+    // The packet needs to look like a short header packet, so the form bit is
+    // cleared and the fixed bit is set.
+    unpredictable[0] = (unpredictable[0] & !FORM_BIT) | FIXED_BIT;
+
+    reset_token.copy_from_slice(&token.to_be_bytes());
+
+    Ok(len)
+}
+
 pub fn verify_retry_integrity(
     b: &octets::OctetsMut, odcid: &[u8], version: u32,
 ) -> Result<()> {
@@ -759,6 +810,15 @@ fn compute_retry_integrity_tag(
         0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
     ];
 
+    const RETRY_INTEGRITY_KEY_V2: [u8; 16] = [
+        0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce,
+        0xad, 0x7c, 0xcc, 0x92,
+    ];
+
+    const RETRY_INTEGRITY_NONCE_V2: [u8; aead::NONCE_LEN] = [
+        0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+    ];
+
     const RETRY_INTEGRITY_KEY_DRAFT29: [u8; 16] = [
         0xcc, 0xce, 0x18, 0x7e, 0xd0, 0x9a, 0x09, 0xd0, 0x57, 0x28, 0x15, 0x5a,
         0x6c, 0xb9, 0x6b, 0xe1,
@@ -784,6 +844,9 @@ fn compute_retry_integrity_tag(
         crate::PROTOCOL_VERSION_DRAFT29 =>
             (&RETRY_INTEGRITY_KEY_DRAFT29, RETRY_INTEGRITY_NONCE_DRAFT29),
 
+        crate::PROTOCOL_VERSION_V2 =>
+            (&RETRY_INTEGRITY_KEY_V2, RETRY_INTEGRITY_NONCE_V2),
+
         _ => (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
     };
 
@@ -810,6 +873,28 @@ fn compute_retry_integrity_tag(
         .map_err(|_| Error::CryptoFail)
 }
 
+/// State kept after a 1-RTT key update.
+pub struct KeyUpdate {
+    /// The read key used before the update, kept to decrypt reordered
+    /// packets.
+    pub crypto_open: crypto::Open,
+
+    /// The lowest packet number received with the new keys. Packets with the
+    /// previous key phase and a lower packet number are decrypted with the
+    /// old key.
+    pub pn_on_update: u64,
+
+    /// The first packet number sent with the new keys.
+    pub sent_pn_on_update: u64,
+
+    /// Whether a packet sent with the new keys was acknowledged.
+    pub update_acked: bool,
+
+    /// When the old read key can be discarded. This is only set once a packet
+    /// protected with the new keys was received.
+    pub timer: Option<time::Instant>,
+}
+
 pub struct PktNumSpace {
     pub largest_rx_pkt_num: u64,
 
@@ -823,6 +908,8 @@ pub struct PktNumSpace {
 
     pub ack_elicited: bool,
 
+    pub ecn_counts: frame::EcnCounts,
+
     pub crypto_open: Option<crypto::Open>,
     pub crypto_seal: Option<crypto::Seal>,
 
@@ -830,6 +917,8 @@ pub struct PktNumSpace {
     pub crypto_0rtt_seal: Option<crypto::Seal>,
 
     pub crypto_stream: stream::Stream,
+
+    pub key_update: Option<KeyUpdate>,
 }
 
 impl PktNumSpace {
@@ -847,6 +936,8 @@ impl PktNumSpace {
 
             ack_elicited: false,
 
+            ecn_counts: frame::EcnCounts::default(),
+
             crypto_open: None,
             crypto_seal: None,
 
@@ -859,6 +950,8 @@ impl PktNumSpace {
                 true,
                 true,
             ),
+
+            key_update: None,
         }
     }
 
@@ -987,6 +1080,70 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn initial_v2() {
+        let hdr = Header {
+            ty: Type::Initial,
+            version: crate::PROTOCOL_VERSION_V2,
+            dcid: vec![0xba, 0xba, 0xba, 0xba, 0xba, 0xba, 0xba, 0xba, 0xba]
+                .into(),
+            scid: vec![0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb, 0xbb].into(),
+            pkt_num: 0,
+            pkt_num_len: 0,
+            token: Some(vec![0x05, 0x06, 0x07, 0x08]),
+            versions: None,
+            key_phase: false,
+        };
+
+        let mut d = [0; 50];
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert!(hdr.to_bytes(&mut b).is_ok());
+
+        // Initial packets use the 0b01 type in version 2.
+        assert_eq!(d[0] & TYPE_MASK, 0x10);
+
+        let mut b = octets::OctetsMut::with_slice(&mut d);
+        assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
+    }
+
+    #[test]
+    fn retry_v2() {
+        let scid = [0xba; 9];
+        let odcid = [0xbb; 8];
+        let new_scid = [0xbc; 9];
+
+        let mut d = [0; 128];
+
+        let len = super::retry(
+            &scid,
+            &odcid,
+            &new_scid,
+            &[0xbd; 24],
+            crate::PROTOCOL_VERSION_V2,
+            &mut d,
+        )
+        .unwrap();
+
+        // Retry packets use the 0b00 type in version 2.
+        assert_eq!(d[0] & TYPE_MASK, 0x00);
+
+        let mut b = octets::OctetsMut::with_slice(&mut d[..len]);
+        let hdr = Header::from_bytes(&mut b, 9).unwrap();
+
+        assert_eq!(hdr.ty, Type::Retry);
+        assert_eq!(hdr.token, Some(vec![0xbd; 24]));
+
+        assert!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V2)
+                .is_ok()
+        );
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V1),
+            Err(Error::CryptoFail)
+        );
+    }
+
     #[test]
     fn initial_v1_dcid_too_long() {
         let hdr = Header {
@@ -1810,7 +1967,9 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Open::from_secret(alg, &secret).unwrap();
+        let aead =
+            crypto::Open::from_secret(alg, crate::PROTOCOL_VERSION, &secret)
+                .unwrap();
 
         let mut hdr = Header::from_bytes(&mut b, 0).unwrap();
         assert_eq!(hdr.ty, Type::Short);
@@ -2737,7 +2896,9 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Seal::from_secret(alg, &secret).unwrap();
+        let aead =
+            crypto::Seal::from_secret(alg, crate::PROTOCOL_VERSION, &secret)
+                .unwrap();
 
         let pn = 654_360_564;
         let pn_len = 3;
@@ -2791,4 +2952,25 @@ mod tests {
             Err(Error::InvalidPacket)
         );
     }
+
+    #[test]
+    fn stateless_reset() {
+        let mut d = [0; 50];
+
+        assert_eq!(
+            super::stateless_reset(0xba, &mut d[..20]),
+            Err(Error::BufferTooShort)
+        );
+
+        assert_eq!(super::stateless_reset(0xba, &mut d[..43]), Ok(43));
+
+        // The packet looks like a short header one.
+        let mut b = octets::OctetsMut::with_slice(&mut d[..43]);
+        let hdr = Header::from_bytes(&mut b, 16).unwrap();
+        assert_eq!(hdr.ty, Type::Short);
+
+        assert_eq!(&d[27..43], &0xba_u128.to_be_bytes());
+        assert_eq!(peek_stateless_reset_token(&d[..43]), Some(0xba));
+        assert_eq!(peek_stateless_reset_token(&d[..20]), None);
+    }
 }
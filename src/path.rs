@@ -0,0 +1,648 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::cmp;
+use std::time;
+
+use std::net::SocketAddr;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use crate::Error;
+use crate::Result;
+
+use crate::packet;
+use crate::recovery;
+
+/// The maximum number of PATH_CHALLENGE frames received on a path whose
+/// PATH_RESPONSE is still pending.
+const MAX_PENDING_RESPONSES: usize = 3;
+
+/// The state of a path with regard to address validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathState {
+    /// The path failed its validation.
+    Failed,
+
+    /// The path exists, but no validation was performed.
+    Unknown,
+
+    /// The path is being validated.
+    Validating,
+
+    /// The path was validated.
+    Validated,
+}
+
+/// An event related to a network path, as returned by
+/// [`Connection::path_event_next()`].
+///
+/// Each path is identified by its local and peer addresses.
+///
+/// [`Connection::path_event_next()`]: struct.Connection.html#method.path_event_next
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathEvent {
+    /// A packet was received on a new path. Servers can only send packets
+    /// on it once it is validated.
+    New(SocketAddr, SocketAddr),
+
+    /// The path was validated.
+    Validated(SocketAddr, SocketAddr),
+
+    /// The path failed its validation and can't be used anymore.
+    FailedValidation(SocketAddr, SocketAddr),
+
+    /// The path was closed and can't be used anymore.
+    Closed(SocketAddr, SocketAddr),
+
+    /// The peer migrated the connection to this path, which is now the
+    /// active one.
+    PeerMigrated(SocketAddr, SocketAddr),
+}
+
+/// A network path, identified by the local and peer addresses.
+pub struct Path {
+    /// The local address.
+    local_addr: SocketAddr,
+
+    /// The peer address.
+    peer_addr: SocketAddr,
+
+    /// Sequence number of the destination connection ID used on this path.
+    pub active_dcid_seq: Option<u64>,
+
+    /// Validation state of the path.
+    state: PathState,
+
+    /// Whether this is the path used to send non-probing packets.
+    active: bool,
+
+    /// Loss recovery and congestion control state.
+    pub recovery: recovery::Recovery,
+
+    /// Data of the PATH_CHALLENGE frames sent on this path, and not yet
+    /// acknowledged with a PATH_RESPONSE.
+    in_flight_challenges: VecDeque<[u8; 8]>,
+
+    /// Data of the PATH_CHALLENGE frames received on this path, to be echoed
+    /// back in PATH_RESPONSE frames.
+    received_challenges: VecDeque<[u8; 8]>,
+
+    /// Whether a PATH_CHALLENGE frame needs to be sent.
+    challenge_requested: bool,
+
+    /// Time after which the validation of the path is considered failed.
+    validation_deadline: Option<time::Instant>,
+
+    /// Total number of packets received on this path.
+    pub recv_count: usize,
+
+    /// Total number of packets sent on this path.
+    pub sent_count: usize,
+
+    /// Total number of bytes received on this path.
+    pub recv_bytes: u64,
+
+    /// Total number of bytes sent on this path.
+    pub sent_bytes: u64,
+
+    /// Whether the peer's address has been verified on this path.
+    pub verified_peer_address: bool,
+
+    /// Total number of bytes the server can send on this path before the
+    /// peer's address is verified.
+    pub max_send_bytes: usize,
+}
+
+impl Path {
+    /// Creates a new `Path` between the given addresses.
+    ///
+    /// The initial path, that is, the one the handshake is performed on, is
+    /// implicitly validated and active.
+    pub fn new(
+        local_addr: SocketAddr, peer_addr: SocketAddr,
+        recovery_config: &recovery::RecoveryConfig, is_initial: bool,
+    ) -> Self {
+        let (state, active_dcid_seq) = if is_initial {
+            (PathState::Validated, Some(0))
+        } else {
+            (PathState::Unknown, None)
+        };
+
+        Path {
+            local_addr,
+            peer_addr,
+            active_dcid_seq,
+            state,
+            active: is_initial,
+            recovery: recovery::Recovery::with_config(recovery_config),
+            in_flight_challenges: VecDeque::new(),
+            received_challenges: VecDeque::new(),
+            challenge_requested: false,
+            validation_deadline: None,
+            recv_count: 0,
+            sent_count: 0,
+            recv_bytes: 0,
+            sent_bytes: 0,
+            verified_peer_address: false,
+            max_send_bytes: 0,
+        }
+    }
+
+    /// Returns the local address of the path.
+    #[inline]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns the peer address of the path.
+    #[inline]
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Returns whether the path is the active one.
+    #[inline]
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Returns whether the path was validated.
+    #[inline]
+    pub fn validated(&self) -> bool {
+        self.state == PathState::Validated
+    }
+
+    /// Returns whether the path can be used to send packets.
+    #[inline]
+    pub fn usable(&self) -> bool {
+        self.state != PathState::Failed
+    }
+
+    /// Returns whether the path is being validated.
+    #[inline]
+    pub fn under_validation(&self) -> bool {
+        self.state == PathState::Validating
+    }
+
+    /// Requests the validation of the path, unless it is already validated.
+    pub fn request_validation(&mut self) {
+        if self.state == PathState::Validated {
+            return;
+        }
+
+        self.state = PathState::Validating;
+        self.challenge_requested = true;
+    }
+
+    /// Returns whether a PATH_CHALLENGE frame needs to be sent on the path.
+    ///
+    /// This is the case when validation was requested, but also when a probe
+    /// needs to be sent while the path is being validated, as the packet
+    /// carrying the previous challenge might have been lost.
+    #[inline]
+    pub fn validation_requested(&self) -> bool {
+        self.challenge_requested ||
+            (self.under_validation() &&
+                self.recovery.loss_probes[packet::EPOCH_APPLICATION] > 0)
+    }
+
+    /// Returns whether probing frames need to be sent on the path.
+    #[inline]
+    pub fn probing_required(&self) -> bool {
+        self.validation_requested() || self.has_pending_challenge()
+    }
+
+    /// Records that a PATH_CHALLENGE frame with the given data was sent.
+    pub fn on_challenge_sent(&mut self, data: [u8; 8], now: time::Instant) {
+        self.in_flight_challenges.push_back(data);
+        self.challenge_requested = false;
+
+        // The validation timer is only armed by the first challenge, further
+        // ones are retransmissions within the same validation attempt.
+        if self.validation_deadline.is_none() {
+            // Use the larger of the current PTO and the one computed using the
+            // initial RTT, as the path's RTT might not be known yet.
+            let pto =
+                cmp::max(self.recovery.pto(), recovery::Recovery::initial_pto());
+
+            self.validation_deadline = Some(now + 3 * pto);
+        }
+    }
+
+    /// Requests a new PATH_CHALLENGE frame to be sent, as a previous one was
+    /// lost, as long as the path is still being validated.
+    pub fn on_challenge_lost(&mut self) {
+        if self.under_validation() {
+            self.challenge_requested = true;
+        }
+    }
+
+    /// Processes a PATH_RESPONSE frame.
+    ///
+    /// Returns true if the response matches a challenge sent on the path,
+    /// in which case the path is now validated.
+    pub fn on_response_received(&mut self, data: &[u8]) -> bool {
+        if !self.in_flight_challenges.iter().any(|c| c == data) {
+            return false;
+        }
+
+        self.state = PathState::Validated;
+        self.verified_peer_address = true;
+
+        self.in_flight_challenges.clear();
+        self.challenge_requested = false;
+        self.validation_deadline = None;
+
+        true
+    }
+
+    /// Records a PATH_CHALLENGE frame received on the path.
+    pub fn on_challenge_received(&mut self, data: [u8; 8]) {
+        // Only keep the most recent challenges, as it's only required to
+        // respond to the latest one.
+        if self.received_challenges.len() >= MAX_PENDING_RESPONSES {
+            self.received_challenges.pop_front();
+        }
+
+        self.received_challenges.push_back(data);
+    }
+
+    /// Returns whether there are PATH_RESPONSE frames to be sent.
+    #[inline]
+    pub fn has_pending_challenge(&self) -> bool {
+        !self.received_challenges.is_empty()
+    }
+
+    /// Returns the data of the next PATH_RESPONSE frame to be sent.
+    #[inline]
+    pub fn pop_received_challenge(&mut self) -> Option<[u8; 8]> {
+        self.received_challenges.pop_front()
+    }
+
+    /// Returns the time after which the path validation fails, if any.
+    #[inline]
+    pub fn validation_timer(&self) -> Option<time::Instant> {
+        self.validation_deadline
+    }
+
+    /// Marks the path validation as failed.
+    pub fn on_failed_validation(&mut self) {
+        self.state = PathState::Failed;
+
+        self.in_flight_challenges.clear();
+        self.challenge_requested = false;
+        self.validation_deadline = None;
+    }
+
+    /// Returns a `PathStats` snapshot of the path.
+    pub fn stats(&self) -> crate::PathStats {
+        crate::PathStats {
+            local_addr: self.local_addr,
+            peer_addr: self.peer_addr,
+            validated: self.validated(),
+            active: self.active,
+            recv: self.recv_count,
+            sent: self.sent_count,
+            lost: self.recovery.lost_count,
+            rtt: self.recovery.rtt(),
+            cwnd: self.recovery.cwnd(),
+            sent_bytes: self.sent_bytes,
+            recv_bytes: self.recv_bytes,
+            lost_bytes: self.recovery.bytes_lost,
+            pmtu: self.recovery.max_datagram_size(),
+            delivery_rate: self.recovery.delivery_rate(),
+            ecn_capable: self.recovery.ecn.is_capable(),
+        }
+    }
+}
+
+/// Keeps track of all the network paths of a connection.
+pub struct PathMap {
+    /// All the paths, indexed by their identifier.
+    paths: BTreeMap<usize, Path>,
+
+    /// Maps the local and peer addresses to the path identifiers.
+    addrs_to_paths: HashMap<(SocketAddr, SocketAddr), usize>,
+
+    /// The identifier to be assigned to the next path.
+    next_path_id: usize,
+
+    /// Maximum number of paths tracked at the same time.
+    max_concurrent_paths: usize,
+
+    /// Path events to be reported to the application.
+    events: VecDeque<PathEvent>,
+}
+
+impl PathMap {
+    /// Creates a new `PathMap` containing the given initial path, which gets
+    /// identifier 0.
+    pub fn new(initial_path: Path, max_concurrent_paths: usize) -> Self {
+        let mut paths = BTreeMap::new();
+        let mut addrs_to_paths = HashMap::new();
+
+        addrs_to_paths
+            .insert((initial_path.local_addr, initial_path.peer_addr), 0);
+        paths.insert(0, initial_path);
+
+        PathMap {
+            paths,
+            addrs_to_paths,
+            next_path_id: 1,
+            max_concurrent_paths,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the path with the given identifier.
+    #[inline]
+    pub fn get(&self, path_id: usize) -> Result<&Path> {
+        self.paths.get(&path_id).ok_or(Error::InvalidState)
+    }
+
+    /// Returns the mutable path with the given identifier.
+    #[inline]
+    pub fn get_mut(&mut self, path_id: usize) -> Result<&mut Path> {
+        self.paths.get_mut(&path_id).ok_or(Error::InvalidState)
+    }
+
+    /// Returns the identifier of the active path.
+    #[inline]
+    pub fn get_active_path_id(&self) -> Result<usize> {
+        self.paths
+            .iter()
+            .find(|(_, p)| p.active)
+            .map(|(&id, _)| id)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the active path.
+    #[inline]
+    pub fn get_active(&self) -> Result<&Path> {
+        self.paths
+            .values()
+            .find(|p| p.active)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the mutable active path.
+    #[inline]
+    pub fn get_active_mut(&mut self) -> Result<&mut Path> {
+        self.paths
+            .values_mut()
+            .find(|p| p.active)
+            .ok_or(Error::InvalidState)
+    }
+
+    /// Returns the identifier of the path with the given addresses, if any.
+    #[inline]
+    pub fn path_id_from_addrs(
+        &self, addrs: &(SocketAddr, SocketAddr),
+    ) -> Option<usize> {
+        self.addrs_to_paths.get(addrs).copied()
+    }
+
+    /// Returns an iterator over all the paths, along with their identifier.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Path)> {
+        self.paths.iter().map(|(&id, p)| (id, p))
+    }
+
+    /// Returns a mutable iterator over all the paths, along with their
+    /// identifier.
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut Path)> {
+        self.paths.iter_mut().map(|(&id, p)| (id, p))
+    }
+
+    /// Inserts a new path, and returns its identifier.
+    ///
+    /// If the maximum number of concurrent paths is reached, the oldest path
+    /// that is neither active nor being validated is closed to make room for
+    /// the new one. If no such path exists, [`Done`] is returned.
+    ///
+    /// Returns the identifier of the new path, along with the path that was
+    /// closed, if any.
+    ///
+    /// [`Done`]: ../enum.Error.html#variant.Done
+    pub fn insert_path(&mut self, path: Path) -> Result<(usize, Option<Path>)> {
+        let mut closed = None;
+
+        if self.paths.len() >= self.max_concurrent_paths {
+            let evict_id = self
+                .paths
+                .iter()
+                .find(|(_, p)| !p.active && !p.under_validation())
+                .map(|(&id, _)| id)
+                .ok_or(Error::Done)?;
+
+            closed = self.remove(evict_id);
+        }
+
+        let path_id = self.next_path_id;
+        self.next_path_id += 1;
+
+        self.addrs_to_paths
+            .insert((path.local_addr, path.peer_addr), path_id);
+        self.paths.insert(path_id, path);
+
+        Ok((path_id, closed))
+    }
+
+    /// Removes the path with the given identifier, and notifies the
+    /// application that it was closed.
+    fn remove(&mut self, path_id: usize) -> Option<Path> {
+        let path = self.paths.remove(&path_id)?;
+
+        self.addrs_to_paths
+            .remove(&(path.local_addr, path.peer_addr));
+
+        self.notify_event(PathEvent::Closed(path.local_addr, path.peer_addr));
+
+        Some(path)
+    }
+
+    /// Makes the path with the given identifier the active one.
+    pub fn set_active_path(&mut self, path_id: usize) -> Result<()> {
+        if !self.get(path_id)?.usable() {
+            return Err(Error::InvalidState);
+        }
+
+        for (&id, p) in self.paths.iter_mut() {
+            p.active = id == path_id;
+        }
+
+        Ok(())
+    }
+
+    /// Queues a path event for the application.
+    #[inline]
+    pub fn notify_event(&mut self, ev: PathEvent) {
+        self.events.push_back(ev);
+    }
+
+    /// Returns the next path event, if any.
+    #[inline]
+    pub fn pop_event(&mut self) -> Option<PathEvent> {
+        self.events.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_path(local: &str, peer: &str, is_initial: bool) -> Path {
+        let config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        let recovery_config = recovery::RecoveryConfig::from_config(&config);
+
+        Path::new(
+            local.parse().unwrap(),
+            peer.parse().unwrap(),
+            &recovery_config,
+            is_initial,
+        )
+    }
+
+    #[test]
+    fn path_validation() {
+        let mut path = new_path("127.0.0.1:1234", "127.0.0.1:4321", false);
+
+        assert_eq!(path.state, PathState::Unknown);
+        assert!(!path.validation_requested());
+
+        path.request_validation();
+        assert_eq!(path.state, PathState::Validating);
+        assert!(path.validation_requested());
+
+        let now = time::Instant::now();
+
+        path.on_challenge_sent([0xba; 8], now);
+        assert!(!path.validation_requested());
+        assert!(path.validation_timer().is_some());
+
+        path.on_challenge_lost();
+        assert!(path.validation_requested());
+
+        path.on_challenge_sent([0xbb; 8], now);
+
+        assert!(!path.on_response_received(&[0xbc; 8]));
+        assert_eq!(path.state, PathState::Validating);
+
+        assert!(path.on_response_received(&[0xba; 8]));
+        assert_eq!(path.state, PathState::Validated);
+        assert!(path.verified_peer_address);
+        assert_eq!(path.validation_timer(), None);
+
+        // Lost challenges are not retransmitted once validated.
+        path.on_challenge_lost();
+        assert!(!path.validation_requested());
+    }
+
+    #[test]
+    fn validation_timer() {
+        let mut path = new_path("127.0.0.1:1234", "127.0.0.1:4321", false);
+
+        let now = time::Instant::now();
+
+        // The path's RTT is tiny, but the timer uses the PTO computed from
+        // the initial RTT.
+        path.recovery.update_rtt(
+            time::Duration::from_millis(1),
+            time::Duration::from_millis(0),
+            now,
+        );
+        assert!(path.recovery.pto() < recovery::Recovery::initial_pto());
+
+        path.request_validation();
+        path.on_challenge_sent([0xba; 8], now);
+
+        assert_eq!(
+            path.validation_timer(),
+            Some(now + 3 * recovery::Recovery::initial_pto())
+        );
+    }
+
+    #[test]
+    fn received_challenges() {
+        let mut path = new_path("127.0.0.1:1234", "127.0.0.1:4321", true);
+
+        assert!(!path.has_pending_challenge());
+
+        for i in 0..5 {
+            path.on_challenge_received([i; 8]);
+        }
+
+        // Only the most recent ones are kept.
+        assert_eq!(path.pop_received_challenge(), Some([2; 8]));
+        assert_eq!(path.pop_received_challenge(), Some([3; 8]));
+        assert_eq!(path.pop_received_challenge(), Some([4; 8]));
+        assert_eq!(path.pop_received_challenge(), None);
+    }
+
+    #[test]
+    fn path_map_eviction() {
+        let initial = new_path("127.0.0.1:1234", "127.0.0.1:4321", true);
+
+        let mut paths = PathMap::new(initial, 2);
+        assert_eq!(paths.get_active_path_id(), Ok(0));
+
+        let mut probed = new_path("127.0.0.1:1234", "127.0.0.1:5678", false);
+        probed.request_validation();
+
+        assert!(matches!(paths.insert_path(probed), Ok((1, None))));
+
+        // Neither the active path nor the one being validated can be evicted.
+        let other = new_path("127.0.0.1:1234", "127.0.0.1:9999", false);
+        assert!(matches!(paths.insert_path(other), Err(Error::Done)));
+
+        paths.get_mut(1).unwrap().on_failed_validation();
+
+        let other = new_path("127.0.0.1:1234", "127.0.0.1:9999", false);
+        assert!(matches!(paths.insert_path(other), Ok((2, Some(_)))));
+
+        assert_eq!(
+            paths.pop_event(),
+            Some(PathEvent::Closed(
+                "127.0.0.1:1234".parse().unwrap(),
+                "127.0.0.1:5678".parse().unwrap()
+            ))
+        );
+
+        let addrs = (
+            "127.0.0.1:1234".parse().unwrap(),
+            "127.0.0.1:9999".parse().unwrap(),
+        );
+        assert_eq!(paths.path_id_from_addrs(&addrs), Some(2));
+
+        assert_eq!(paths.set_active_path(2), Ok(()));
+        assert_eq!(paths.get_active_path_id(), Ok(2));
+        assert!(!paths.get(0).unwrap().active());
+    }
+}
@@ -0,0 +1,607 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! BBR Congestion Control
+//!
+//! This implementation is based on the following draft:
+//! <https://tools.ietf.org/html/draft-cardwell-iccrg-bbr-congestion-control-00>
+
+use crate::minmax::Minmax;
+use crate::packet;
+use crate::recovery::*;
+
+use std::time::Duration;
+use std::time::Instant;
+
+pub static BBR: CongestionControlOps = CongestionControlOps {
+    on_init,
+    on_packet_sent,
+    on_packets_acked,
+    congestion_event,
+    collapse_cwnd,
+    checkpoint,
+    rollback,
+    has_custom_pacing,
+};
+
+/// A constant specifying the length of the BBR.BtlBw max filter window for
+/// BBR.BtlBwFilter, BtlBwFilterLen is 10 packet-timed round trips.
+const BTLBW_FILTER_LEN: Duration = Duration::from_secs(10);
+
+/// A constant specifying the minimum time interval between ProbeRTT states.
+const PROBE_RTT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A constant specifying the length of the RTProp min filter window.
+const RTPROP_FILTER_LEN: Duration = PROBE_RTT_INTERVAL;
+
+/// A constant specifying the minimum gain value that will allow the sending
+/// rate to double each round (2/ln(2) ~= 2.89), used in Startup mode for both
+/// BBR.pacing_gain and BBR.cwnd_gain.
+const BBR_HIGH_GAIN: f64 = 2.89;
+
+/// The minimal cwnd value BBR tries to target using: 4 packets, or 4 * SMSS.
+const BBR_MIN_PIPE_CWND_PKTS: usize = 4;
+
+/// The number of phases in the BBR ProbeBW gain cycle: 8.
+const BBR_GAIN_CYCLE_LEN: usize = 8;
+
+/// A constant specifying the minimum duration for which ProbeRTT state holds
+/// inflight to BBRMinPipeCwnd or fewer packets: 200 ms.
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+
+/// Pacing Gain Cycle.
+const PACING_GAIN_CYCLE: [f64; BBR_GAIN_CYCLE_LEN] =
+    [5.0 / 4.0, 3.0 / 4.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0];
+
+/// A constant to check BBR.BtlBW is still growing.
+const BTLBW_GROWTH_TARGET: f64 = 1.25;
+
+/// BBR Internal State Machine.
+#[derive(Debug, PartialEq)]
+enum BBRStateMachine {
+    Startup,
+    Drain,
+    ProbeBW,
+    ProbeRTT,
+}
+
+/// BBR Specific State Variables.
+pub struct State {
+    // The current state of a BBR flow in the BBR state machine.
+    state: BBRStateMachine,
+
+    // BBR's estimated bottleneck bandwidth available to the transport flow,
+    // estimated from the maximum delivery rate sample in a sliding window.
+    btlbw: u64,
+
+    // The max filter used to estimate BBR.BtlBw.
+    btlbw_filter: Minmax<u64>,
+
+    // BBR's estimated two-way round-trip propagation delay of the path,
+    // estimated from the windowed minimum recent round-trip delay sample.
+    rtprop: Duration,
+
+    // The wall clock time at which the current BBR.RTProp sample was
+    // obtained.
+    rtprop_stamp: Instant,
+
+    // A boolean recording whether the BBR.RTprop has expired and is due for a
+    // refresh with an application idle period or a transition into ProbeRTT
+    // state.
+    rtprop_expired: bool,
+
+    // The dynamic gain factor used to scale BBR.BtlBw to produce
+    // BBR.pacing_rate.
+    pacing_gain: f64,
+
+    // The dynamic gain factor used to scale the estimated BDP to produce a
+    // congestion window (cwnd).
+    cwnd_gain: f64,
+
+    // The maximum size of a data aggregate scheduled and transmitted together.
+    send_quantum: usize,
+
+    // A boolean that records whether BBR estimates that it has ever fully
+    // utilized its available bandwidth ("filled the pipe").
+    filled_pipe: bool,
+
+    // Count of packet-timed round trips.
+    round_count: u64,
+
+    // A boolean that BBR sets to true once per packet-timed round trip,
+    // on ACKs that advance BBR.round_count.
+    round_start: bool,
+
+    // packet.delivered value denoting the end of a packet-timed round trip.
+    next_round_delivered: usize,
+
+    // Timestamp when ProbeRTT state ends.
+    probe_rtt_done_stamp: Option<Instant>,
+
+    // Checking if a roundtrip in ProbeRTT state ends.
+    probe_rtt_round_done: bool,
+
+    // Checking if in the packet conservation mode during recovery.
+    packet_conservation: bool,
+
+    // Saved cwnd before loss recovery or ProbeRTT.
+    prior_cwnd: usize,
+
+    // Checking if restarting from idle.
+    idle_restart: bool,
+
+    // Baseline level delivery rate for full pipe estimator.
+    full_bw: u64,
+
+    // The number of round for full pipe estimator without much growth.
+    full_bw_count: usize,
+
+    // Last time cycle_index is updated.
+    cycle_stamp: Instant,
+
+    // Current index of pacing_gain_cycle[].
+    cycle_index: usize,
+
+    // The upper bound on the volume of data BBR allows in flight.
+    target_cwnd: usize,
+
+    // Whether in the recovery episode.
+    in_recovery: bool,
+
+    // Start time of the connection.
+    start_time: Instant,
+
+    // Newly marked lost data size in bytes.
+    newly_lost_bytes: usize,
+
+    // Newly acked data size in bytes.
+    newly_acked_bytes: usize,
+
+    // bytes_in_flight before processing this ACK.
+    prior_bytes_in_flight: usize,
+}
+
+impl State {
+    pub fn new() -> Self {
+        let now = Instant::now();
+
+        State {
+            state: BBRStateMachine::Startup,
+
+            btlbw: 0,
+
+            btlbw_filter: Minmax::new(0),
+
+            rtprop: Duration::MAX,
+
+            rtprop_stamp: now,
+
+            rtprop_expired: false,
+
+            pacing_gain: 0.0,
+
+            cwnd_gain: 0.0,
+
+            send_quantum: 0,
+
+            filled_pipe: false,
+
+            round_count: 0,
+
+            round_start: false,
+
+            next_round_delivered: 0,
+
+            probe_rtt_done_stamp: None,
+
+            probe_rtt_round_done: false,
+
+            packet_conservation: false,
+
+            prior_cwnd: 0,
+
+            idle_restart: false,
+
+            full_bw: 0,
+
+            full_bw_count: 0,
+
+            cycle_stamp: now,
+
+            cycle_index: 0,
+
+            target_cwnd: 0,
+
+            in_recovery: false,
+
+            start_time: now,
+
+            newly_lost_bytes: 0,
+
+            newly_acked_bytes: 0,
+
+            prior_bytes_in_flight: 0,
+        }
+    }
+}
+
+// When entering the recovery episode.
+fn bbr_enter_recovery(r: &mut Recovery, now: Instant) {
+    r.bbr_state.prior_cwnd = per_ack::bbr_save_cwnd(r);
+
+    r.congestion_window = r.bytes_in_flight +
+        cmp::max(r.bbr_state.newly_acked_bytes, r.max_datagram_size);
+    r.congestion_recovery_start_time = Some(now);
+
+    r.bbr_state.packet_conservation = true;
+    r.bbr_state.in_recovery = true;
+
+    // Start round now.
+    r.bbr_state.next_round_delivered = r.delivery_rate.delivered();
+}
+
+// When exiting the recovery episode.
+fn bbr_exit_recovery(r: &mut Recovery) {
+    r.congestion_recovery_start_time = None;
+
+    r.bbr_state.packet_conservation = false;
+    r.bbr_state.in_recovery = false;
+
+    per_ack::bbr_restore_cwnd(r);
+}
+
+// Congestion Control Hooks.
+//
+fn on_init(r: &mut Recovery) {
+    init::bbr_init(r);
+}
+
+fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, _now: Instant) {
+    per_transmit::bbr_on_transmit(r);
+
+    r.bytes_in_flight += sent_bytes;
+}
+
+fn on_packets_acked(
+    r: &mut Recovery, packets: &[Acked], _epoch: packet::Epoch, now: Instant,
+) {
+    r.bbr_state.prior_bytes_in_flight = r.bytes_in_flight;
+
+    r.bbr_state.newly_acked_bytes = packets.iter().map(|p| p.size).sum();
+
+    r.bytes_in_flight = r
+        .bytes_in_flight
+        .saturating_sub(r.bbr_state.newly_acked_bytes);
+
+    // Exit the recovery episode once a packet sent after its start is acked.
+    if let Some(pkt) = packets.last() {
+        if r.bbr_state.in_recovery && !r.in_congestion_recovery(pkt.time_sent) {
+            bbr_exit_recovery(r);
+        }
+    }
+
+    per_ack::bbr_update_model_and_state(r, now);
+
+    per_ack::bbr_update_control_parameters(r);
+
+    r.bbr_state.newly_lost_bytes = 0;
+}
+
+fn congestion_event(
+    r: &mut Recovery, lost_bytes: usize, time_sent: Instant,
+    _epoch: packet::Epoch, now: Instant,
+) {
+    // Lost bytes are taken into account when the next ACK is processed.
+    r.bbr_state.newly_lost_bytes += lost_bytes;
+
+    // Upon entering Fast Recovery.
+    if !r.in_congestion_recovery(time_sent) {
+        bbr_enter_recovery(r, now);
+    }
+}
+
+fn collapse_cwnd(r: &mut Recovery) {
+    r.bbr_state.prior_cwnd = per_ack::bbr_save_cwnd(r);
+
+    reno::collapse_cwnd(r);
+}
+
+fn checkpoint(_r: &mut Recovery) {}
+
+fn rollback(_r: &mut Recovery) {}
+
+fn has_custom_pacing() -> bool {
+    true
+}
+
+impl std::fmt::Debug for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "btlbw={} ", self.btlbw)?;
+        write!(f, "rtprop={:?} ", self.rtprop)?;
+        write!(f, "pacing_gain={} ", self.pacing_gain)?;
+        write!(f, "cwnd_gain={} ", self.cwnd_gain)?;
+        write!(f, "target_cwnd={} ", self.target_cwnd)?;
+        write!(f, "send_quantum={} ", self.send_quantum)?;
+        write!(f, "filled_pipe={} ", self.filled_pipe)?;
+        write!(f, "round_count={} ", self.round_count)?;
+        write!(f, "cycle_index={} ", self.cycle_index)?;
+        write!(f, "prior_cwnd={} ", self.prior_cwnd)?;
+        write!(f, "in_recovery={} ", self.in_recovery)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbr_recovery() -> Recovery {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        assert_eq!(cfg.set_cc_algorithm_name("bbr"), Ok(()));
+
+        Recovery::new(&cfg)
+    }
+
+    fn sent(pkt_num: u64, size: usize, now: Instant) -> Sent {
+        Sent {
+            pkt_num,
+            frames: vec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            recent_delivered_packet_sent_time: now,
+            is_app_limited: false,
+            has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
+        }
+    }
+
+    // Sends packets starting from `pn`, until the congestion window is full,
+    // and acknowledges all of them one RTT later.
+    fn send_and_ack(
+        r: &mut Recovery, pn: &mut u64, now: &mut Instant, rtt: Duration,
+    ) {
+        let start = *pn;
+        let mss = r.max_datagram_size;
+
+        while r.cwnd_available() >= mss {
+            r.on_packet_sent(
+                sent(*pn, mss, *now),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                *now,
+                "",
+            );
+
+            *pn += 1;
+        }
+
+        *now += rtt;
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(start..*pn);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                *now,
+                "",
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn bbr_init() {
+        let r = bbr_recovery();
+
+        // on_init() is called when the recovery is created.
+        assert_eq!(r.cwnd(), r.max_datagram_size * INITIAL_WINDOW_PACKETS);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        assert_eq!(r.bbr_state.state, BBRStateMachine::Startup);
+        assert_eq!(r.bbr_state.pacing_gain, BBR_HIGH_GAIN);
+        assert_eq!(r.bbr_state.rtprop, Duration::MAX);
+        assert!(r.pacing_rate > 0);
+        assert!((r.cc_ops.has_custom_pacing)());
+    }
+
+    #[test]
+    fn bbr_send() {
+        let mut r = bbr_recovery();
+        let now = Instant::now();
+
+        r.on_packet_sent(
+            sent(0, 1000, now),
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+
+        assert_eq!(r.bytes_in_flight, 1000);
+    }
+
+    #[test]
+    fn bbr_startup() {
+        let mut r = bbr_recovery();
+        let mut now = Instant::now();
+        let mut pn = 0;
+        let rtt = Duration::from_millis(50);
+
+        let cwnd_prev = r.cwnd();
+
+        send_and_ack(&mut r, &mut pn, &mut now, rtt);
+
+        assert_eq!(r.bbr_state.state, BBRStateMachine::Startup);
+        assert_eq!(r.bbr_state.round_count, 1);
+        assert_eq!(r.bbr_state.rtprop, rtt);
+        assert_eq!(r.bytes_in_flight, 0);
+
+        // The window grows by the amount of acked data during Startup.
+        assert_eq!(r.cwnd(), cwnd_prev * 2);
+
+        send_and_ack(&mut r, &mut pn, &mut now, rtt);
+
+        assert_eq!(r.bbr_state.state, BBRStateMachine::Startup);
+        assert_eq!(r.bbr_state.round_count, 2);
+        assert!(r.bbr_state.btlbw > 0);
+        assert_eq!(r.cwnd(), cwnd_prev * 4);
+    }
+
+    #[test]
+    fn bbr_congestion_event() {
+        let mut r = bbr_recovery();
+        let now = Instant::now();
+        let mss = r.max_datagram_size;
+
+        for pn in 0..5 {
+            r.on_packet_sent(
+                sent(pn, mss, now),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        let now = now + Duration::from_millis(50);
+
+        // Acknowledging the last packet declares the first 2 lost.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(4..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok(())
+        );
+
+        assert_eq!(r.lost_count, 2);
+        assert!(r.bbr_state.in_recovery);
+        assert!(r.bbr_state.packet_conservation);
+        assert_eq!(r.bbr_state.prior_cwnd, mss * INITIAL_WINDOW_PACKETS);
+
+        // Packet conservation: the window allows sending as much as was acked.
+        assert_eq!(r.bytes_in_flight, mss * 2);
+        assert_eq!(r.cwnd(), r.bytes_in_flight + mss);
+    }
+
+    #[test]
+    fn bbr_drain_and_probe_bw() {
+        let mut r = bbr_recovery();
+        let mut now = Instant::now();
+        let mut pn = 0;
+        let rtt = Duration::from_millis(50);
+
+        // The congestion window is capped so the delivery rate stops growing.
+        let cwnd = r.max_datagram_size * 20;
+
+        for _ in 0..10 {
+            send_and_ack(&mut r, &mut pn, &mut now, rtt);
+
+            r.congestion_window = cmp::min(r.congestion_window, cwnd);
+
+            if r.bbr_state.filled_pipe {
+                break;
+            }
+        }
+
+        assert!(r.bbr_state.filled_pipe);
+
+        // Everything was acked, so Drain is exited right away.
+        assert_eq!(r.bbr_state.state, BBRStateMachine::ProbeBW);
+        assert_ne!(r.bbr_state.cycle_index, 1);
+        assert_eq!(r.bbr_state.cwnd_gain, 2.0);
+    }
+
+    #[test]
+    fn bbr_probe_rtt() {
+        let mut r = bbr_recovery();
+        let mut now = Instant::now();
+        let mut pn = 0;
+        let rtt = Duration::from_millis(50);
+
+        let cwnd = r.max_datagram_size * 20;
+
+        send_and_ack(&mut r, &mut pn, &mut now, rtt);
+
+        // The RTT increases, so BBR.RTprop expires after RTPROP_FILTER_LEN.
+        for _ in 0..200 {
+            send_and_ack(&mut r, &mut pn, &mut now, rtt * 2);
+
+            r.congestion_window = cmp::min(r.congestion_window, cwnd);
+
+            if r.bbr_state.state == BBRStateMachine::ProbeRTT {
+                break;
+            }
+        }
+
+        assert_eq!(r.bbr_state.state, BBRStateMachine::ProbeRTT);
+        assert_eq!(r.bbr_state.rtprop, rtt * 2);
+        assert_eq!(r.bbr_state.prior_cwnd, cwnd);
+
+        // Inflight is limited to the minimum pipe cwnd.
+        assert_eq!(r.cwnd(), r.max_datagram_size * BBR_MIN_PIPE_CWND_PKTS);
+
+        // ProbeRTT lasts for at least PROBE_RTT_DURATION and a round trip.
+        send_and_ack(&mut r, &mut pn, &mut now, rtt * 2);
+        assert_eq!(r.bbr_state.state, BBRStateMachine::ProbeRTT);
+
+        send_and_ack(&mut r, &mut pn, &mut now, rtt * 2);
+        send_and_ack(&mut r, &mut pn, &mut now, rtt * 2);
+        send_and_ack(&mut r, &mut pn, &mut now, rtt * 2);
+
+        assert_ne!(r.bbr_state.state, BBRStateMachine::ProbeRTT);
+
+        // The window from before ProbeRTT is restored.
+        assert!(r.cwnd() >= cwnd);
+    }
+}
+
+mod init;
+mod pacing;
+mod per_ack;
+mod per_transmit;
@@ -74,6 +74,13 @@ impl Rate {
         self.delivered += pkt.size;
         self.delivered_time = Some(now);
 
+        // The most recently sent packet provides the RTT and the app-limited
+        // state of the sample.
+        if pkt.delivered >= self.rate_sample.prior_delivered {
+            self.rate_sample.rtt = now.saturating_duration_since(pkt.time_sent);
+            self.rate_sample.is_app_limited = pkt.is_app_limited;
+        }
+
         if pkt.delivered > self.rate_sample.prior_delivered {
             self.rate_sample.prior_delivered = pkt.delivered;
 
@@ -124,6 +131,33 @@ impl Rate {
     pub fn delivery_rate(&self) -> u64 {
         self.rate_sample.delivery_rate
     }
+
+    /// Returns whether the connection is currently application limited.
+    pub fn app_limited(&self) -> bool {
+        self.app_limited_at_pkt > 0
+    }
+
+    /// Returns the total number of bytes delivered so far.
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    /// Returns the number of bytes delivered when the most recently sent
+    /// packet of the sample was sent.
+    pub fn sample_prior_delivered(&self) -> usize {
+        self.rate_sample.prior_delivered
+    }
+
+    /// Returns the RTT measured from the most recently sent packet of the
+    /// sample.
+    pub fn sample_rtt(&self) -> Duration {
+        self.rate_sample.rtt
+    }
+
+    /// Returns whether the sample was taken while application limited.
+    pub fn sample_is_app_limited(&self) -> bool {
+        self.rate_sample.is_app_limited
+    }
 }
 
 impl std::fmt::Debug for Rate {
@@ -159,6 +193,10 @@ struct RateSample {
     send_elapsed: Duration,
 
     ack_elapsed: Duration,
+
+    rtt: Duration,
+
+    is_app_limited: bool,
 }
 
 impl std::fmt::Debug for RateSample {
@@ -171,7 +209,9 @@ impl std::fmt::Debug for RateSample {
         if let Some(t) = self.prior_time {
             write!(f, "prior_time={:?} ", t.elapsed())?;
         }
-        write!(f, "ack_elapsed={:?}", self.ack_elapsed)?;
+        write!(f, "ack_elapsed={:?} ", self.ack_elapsed)?;
+        write!(f, "rtt={:?} ", self.rtt)?;
+        write!(f, "is_app_limited={}", self.is_app_limited)?;
 
         Ok(())
     }
@@ -202,6 +242,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         recovery
@@ -226,6 +268,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         recovery
@@ -259,6 +303,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         recvry
@@ -281,6 +327,8 @@ mod tests {
             recent_delivered_packet_sent_time: Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         recvry.app_limited = true;
@@ -0,0 +1,322 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! ECN validation.
+//!
+//! This implements the validation process described in RFC 9000 Section
+//! 13.4.2, which makes sure that both the network path and the peer support
+//! ECN before reacting to the congestion signals it carries.
+
+use crate::frame;
+use crate::packet;
+
+/// Not ECN-Capable Transport.
+pub const NOT_ECT: u8 = 0b00;
+
+/// ECN-Capable Transport, ECT(1).
+pub const ECT1: u8 = 0b01;
+
+/// ECN-Capable Transport, ECT(0).
+pub const ECT0: u8 = 0b10;
+
+/// Congestion Experienced.
+pub const CE: u8 = 0b11;
+
+/// Number of ECT(0) marked packets sent before waiting for the peer to
+/// validate them.
+const TESTING_PACKETS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// ECN is disabled locally.
+    Disabled,
+
+    /// The first packets are being sent marked.
+    Testing,
+
+    /// Marking has stopped until the testing packets are acknowledged.
+    Unknown,
+
+    /// ECN counts were validated, packets are marked.
+    Capable,
+
+    /// Validation failed, packets are not marked anymore.
+    Failed,
+}
+
+pub struct Ecn {
+    state: State,
+
+    testing_sent: usize,
+
+    testing_lost: usize,
+
+    marked_sent: usize,
+
+    peer_counts: [frame::EcnCounts; packet::EPOCH_COUNT],
+}
+
+impl Ecn {
+    pub fn new(enabled: bool) -> Self {
+        Ecn {
+            state: if enabled {
+                State::Testing
+            } else {
+                State::Disabled
+            },
+
+            testing_sent: 0,
+
+            testing_lost: 0,
+
+            marked_sent: 0,
+
+            peer_counts: [frame::EcnCounts::default(); packet::EPOCH_COUNT],
+        }
+    }
+
+    /// Returns the ECN codepoint that outgoing packets should be marked with.
+    pub fn codepoint(&self) -> u8 {
+        match self.state {
+            State::Testing | State::Capable => ECT0,
+
+            _ => NOT_ECT,
+        }
+    }
+
+    /// Returns true if ECN validation succeeded.
+    pub fn is_capable(&self) -> bool {
+        self.state == State::Capable
+    }
+
+    /// Returns the number of packets sent marked with ECT(0).
+    pub fn marked_sent(&self) -> usize {
+        self.marked_sent
+    }
+
+    /// Returns the number of CE marks reported by the peer.
+    pub fn peer_ce_count(&self) -> u64 {
+        self.peer_counts.iter().map(|c| c.ecn_ce_count).sum()
+    }
+
+    pub fn on_packet_sent(&mut self, ecn_marked: bool) {
+        if !ecn_marked {
+            return;
+        }
+
+        self.marked_sent += 1;
+
+        if self.state == State::Testing {
+            self.testing_sent += 1;
+
+            if self.testing_sent >= TESTING_PACKETS {
+                self.state = State::Unknown;
+            }
+        }
+    }
+
+    pub fn on_packet_lost(&mut self, ecn_marked: bool) {
+        if !ecn_marked {
+            return;
+        }
+
+        if self.state == State::Testing || self.state == State::Unknown {
+            self.testing_lost += 1;
+
+            // All the testing packets were lost, which might mean that the
+            // network path drops ECN marked packets.
+            if self.state == State::Unknown &&
+                self.testing_lost >= self.testing_sent
+            {
+                self.state = State::Failed;
+            }
+        }
+    }
+
+    /// Validates the ECN counts carried by an ACK frame, given the number of
+    /// newly acknowledged packets that were marked.
+    ///
+    /// Returns true if the peer reported new CE marks, which should be treated
+    /// as a congestion event.
+    pub fn on_ack_received(
+        &mut self, epoch: packet::Epoch, newly_acked_marked: usize,
+        ecn_counts: Option<frame::EcnCounts>, validate: bool,
+    ) -> bool {
+        if self.state == State::Disabled || self.state == State::Failed {
+            return false;
+        }
+
+        let prev = self.peer_counts[epoch];
+
+        let counts = match ecn_counts {
+            Some(v) => v,
+
+            None => {
+                if validate && newly_acked_marked > 0 {
+                    // Marked packets were acknowledged without ECN counts, so
+                    // either the path or the peer cleared the marks.
+                    self.state = State::Failed;
+                }
+
+                return false;
+            },
+        };
+
+        // Counts can't decrease, unless the ACK frame was reordered, in which
+        // case it is simply ignored.
+        if counts.ect0_count < prev.ect0_count ||
+            counts.ect1_count < prev.ect1_count ||
+            counts.ecn_ce_count < prev.ecn_ce_count
+        {
+            return false;
+        }
+
+        self.peer_counts[epoch] = counts;
+
+        if !validate {
+            return false;
+        }
+
+        let newly_ect0_or_ce = (counts.ect0_count - prev.ect0_count) +
+            (counts.ecn_ce_count - prev.ecn_ce_count);
+
+        // Only ECT(0) is ever sent, so any ECT(1) count means that marks were
+        // modified on the path, and all newly acknowledged marked packets
+        // need to be accounted for.
+        if counts.ect1_count > 0 || newly_ect0_or_ce < newly_acked_marked as u64 {
+            self.state = State::Failed;
+
+            return false;
+        }
+
+        if newly_acked_marked > 0 &&
+            (self.state == State::Testing || self.state == State::Unknown)
+        {
+            self.state = State::Capable;
+        }
+
+        counts.ecn_ce_count > prev.ecn_ce_count
+    }
+}
+
+impl std::fmt::Debug for Ecn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "marked_sent={} ", self.marked_sent)?;
+        write!(f, "peer_counts={:?} ", self.peer_counts)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(ect0: u64, ce: u64) -> Option<frame::EcnCounts> {
+        Some(frame::EcnCounts {
+            ect0_count: ect0,
+            ect1_count: 0,
+            ecn_ce_count: ce,
+        })
+    }
+
+    #[test]
+    fn disabled() {
+        let ecn = Ecn::new(false);
+        assert_eq!(ecn.codepoint(), NOT_ECT);
+    }
+
+    #[test]
+    fn validation() {
+        let epoch = packet::EPOCH_APPLICATION;
+
+        let mut ecn = Ecn::new(true);
+        assert_eq!(ecn.codepoint(), ECT0);
+
+        for _ in 0..TESTING_PACKETS {
+            ecn.on_packet_sent(true);
+        }
+
+        // Stop marking until the testing packets are acknowledged.
+        assert_eq!(ecn.codepoint(), NOT_ECT);
+        assert!(!ecn.is_capable());
+
+        assert!(!ecn.on_ack_received(epoch, 5, counts(5, 0), true));
+        assert!(ecn.is_capable());
+        assert_eq!(ecn.codepoint(), ECT0);
+
+        // New CE marks are reported.
+        assert!(ecn.on_ack_received(epoch, 5, counts(8, 2), true));
+        assert!(ecn.is_capable());
+        assert_eq!(ecn.peer_ce_count(), 2);
+
+        // Reordered ACK frame is ignored.
+        assert!(!ecn.on_ack_received(epoch, 0, counts(4, 0), true));
+        assert!(ecn.is_capable());
+    }
+
+    #[test]
+    fn validation_missing_counts() {
+        let mut ecn = Ecn::new(true);
+
+        ecn.on_packet_sent(true);
+
+        assert!(!ecn.on_ack_received(packet::EPOCH_INITIAL, 1, None, true));
+        assert_eq!(ecn.codepoint(), NOT_ECT);
+    }
+
+    #[test]
+    fn validation_bleached_marks() {
+        let mut ecn = Ecn::new(true);
+
+        ecn.on_packet_sent(true);
+        ecn.on_packet_sent(true);
+
+        assert!(!ecn.on_ack_received(
+            packet::EPOCH_INITIAL,
+            2,
+            counts(1, 0),
+            true
+        ));
+        assert_eq!(ecn.codepoint(), NOT_ECT);
+    }
+
+    #[test]
+    fn validation_all_lost() {
+        let mut ecn = Ecn::new(true);
+
+        for _ in 0..TESTING_PACKETS {
+            ecn.on_packet_sent(true);
+        }
+
+        for _ in 0..TESTING_PACKETS {
+            ecn.on_packet_lost(true);
+        }
+
+        assert_eq!(ecn.state, State::Failed);
+    }
+}
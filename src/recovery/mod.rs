@@ -33,8 +33,9 @@ use std::time::Instant;
 
 use std::collections::VecDeque;
 
+use std::sync::Arc;
+
 use crate::Config;
-use crate::Error;
 use crate::Result;
 
 use crate::frame;
@@ -47,6 +48,16 @@ const PACKET_THRESHOLD: u64 = 3;
 
 const TIME_THRESHOLD: f64 = 9.0 / 8.0;
 
+// Upper bounds of the reordering thresholds, when adapted after spurious
+// losses.
+const MAX_PACKET_THRESHOLD: u64 = 20;
+
+const MAX_TIME_THRESHOLD: f64 = 2.0;
+
+// Number of PTOs during which lost packets are remembered, in order to detect
+// spurious losses.
+const LOST_HISTORY_PTO_COUNT: u32 = 3;
+
 const GRANULARITY: Duration = Duration::from_millis(1);
 
 const INITIAL_RTT: Duration = Duration::from_millis(333);
@@ -73,8 +84,6 @@ pub struct Recovery {
 
     largest_acked_pkt: [u64; packet::EPOCH_COUNT],
 
-    largest_sent_pkt: [u64; packet::EPOCH_COUNT],
-
     latest_rtt: Duration,
 
     smoothed_rtt: Option<Duration>,
@@ -85,6 +94,8 @@ pub struct Recovery {
 
     min_rtt: Duration,
 
+    first_rtt_sample: Option<Instant>,
+
     pub max_ack_delay: Duration,
 
     loss_time: [Option<Instant>; packet::EPOCH_COUNT],
@@ -97,6 +108,16 @@ pub struct Recovery {
 
     pub lost_count: usize,
 
+    // Packets recently declared lost, in case they get acknowledged later.
+    lost_history: [VecDeque<LostPacket>; packet::EPOCH_COUNT],
+
+    pub spurious_lost_count: usize,
+
+    // Reordering thresholds used for loss detection.
+    pkt_thresh: u64,
+
+    time_thresh: f64,
+
     pub loss_probes: [usize; packet::EPOCH_COUNT],
 
     in_flight_count: [usize; packet::EPOCH_COUNT],
@@ -108,6 +129,9 @@ pub struct Recovery {
     // Congestion control.
     cc_ops: &'static CongestionControlOps,
 
+    // Application-provided congestion controller, used instead of `cc_ops`.
+    custom_cc: Option<Box<dyn CongestionController>>,
+
     congestion_window: usize,
 
     bytes_in_flight: usize,
@@ -124,10 +148,31 @@ pub struct Recovery {
 
     congestion_recovery_start_time: Option<Instant>,
 
+    // The congestion recovery start time preceding the last congestion event.
+    prior_recovery_start_time: Option<Instant>,
+
+    // Identifies the current congestion recovery episode.
+    congestion_episode: u64,
+
+    // Number of packets lost during the current episode that were not found
+    // to be spurious losses yet.
+    episode_lost: usize,
+
+    // Whether the current episode can still be undone.
+    episode_undo: bool,
+
+    // Whether persistent congestion was detected since it was last reported.
+    persistent_congestion: bool,
+
     max_datagram_size: usize,
 
+    reno_state: reno::State,
+
     cubic_state: cubic::State,
 
+    // BBR.
+    bbr_state: bbr::State,
+
     // HyStart++.
     hystart: hystart::Hystart,
 
@@ -138,11 +183,54 @@ pub struct Recovery {
 
     // RFC6937 PRR.
     prr: prr::PRR,
+
+    // ECN validation.
+    pub ecn: ecn::Ecn,
+
+    // Path MTU discovery.
+    pub pmtud: pmtud::Pmtud,
+}
+
+/// The subset of the configuration used by loss recovery and congestion
+/// control, so that new paths can be set up after the connection is created.
+#[derive(Clone)]
+pub struct RecoveryConfig {
+    max_send_udp_payload_size: usize,
+    cc_algorithm: CongestionControlAlgorithm,
+    cc_factory: Option<Arc<CongestionControllerFactory>>,
+    hystart: bool,
+    ecn: bool,
+    pmtud: bool,
+}
+
+impl RecoveryConfig {
+    pub fn from_config(config: &Config) -> Self {
+        RecoveryConfig {
+            max_send_udp_payload_size: config.max_send_udp_payload_size,
+            cc_algorithm: config.cc_algorithm,
+            cc_factory: config.cc_factory.clone(),
+            hystart: config.hystart,
+            ecn: config.ecn,
+            pmtud: config.pmtud,
+        }
+    }
 }
 
 impl Recovery {
     pub fn new(config: &Config) -> Self {
-        Recovery {
+        Self::with_config(&RecoveryConfig::from_config(config))
+    }
+
+    pub fn with_config(config: &RecoveryConfig) -> Self {
+        // When path MTU discovery is enabled, the configured size is only used
+        // once validated.
+        let max_datagram_size = if config.pmtud {
+            cmp::min(pmtud::BASE_PLPMTU, config.max_send_udp_payload_size)
+        } else {
+            config.max_send_udp_payload_size
+        };
+
+        let mut r = Recovery {
             loss_detection_timer: None,
 
             pto_count: 0,
@@ -151,8 +239,6 @@ impl Recovery {
 
             largest_acked_pkt: [std::u64::MAX; packet::EPOCH_COUNT],
 
-            largest_sent_pkt: [0; packet::EPOCH_COUNT],
-
             latest_rtt: Duration::new(0, 0),
 
             // This field should be initialized to `INITIAL_RTT` for the initial
@@ -165,6 +251,8 @@ impl Recovery {
 
             min_rtt: Duration::new(0, 0),
 
+            first_rtt_sample: None,
+
             rttvar: INITIAL_RTT / 2,
 
             max_ack_delay: Duration::new(0, 0),
@@ -179,12 +267,19 @@ impl Recovery {
 
             lost_count: 0,
 
+            lost_history: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+
+            spurious_lost_count: 0,
+
+            pkt_thresh: PACKET_THRESHOLD,
+
+            time_thresh: TIME_THRESHOLD,
+
             loss_probes: [0; packet::EPOCH_COUNT],
 
             in_flight_count: [0; packet::EPOCH_COUNT],
 
-            congestion_window: config.max_send_udp_payload_size *
-                INITIAL_WINDOW_PACKETS,
+            congestion_window: max_datagram_size * INITIAL_WINDOW_PACKETS,
 
             bytes_in_flight: 0,
 
@@ -200,14 +295,30 @@ impl Recovery {
 
             congestion_recovery_start_time: None,
 
-            max_datagram_size: config.max_send_udp_payload_size,
+            prior_recovery_start_time: None,
+
+            congestion_episode: 0,
+
+            episode_lost: 0,
+
+            episode_undo: false,
+
+            persistent_congestion: false,
+
+            max_datagram_size,
 
             cc_ops: config.cc_algorithm.into(),
 
+            custom_cc: None,
+
             delivery_rate: delivery_rate::Rate::default(),
 
+            reno_state: reno::State::default(),
+
             cubic_state: cubic::State::default(),
 
+            bbr_state: bbr::State::new(),
+
             app_limited: false,
 
             hystart: hystart::Hystart::new(config.hystart),
@@ -217,7 +328,25 @@ impl Recovery {
             last_packet_scheduled_time: None,
 
             prr: prr::PRR::default(),
+
+            ecn: ecn::Ecn::new(config.ecn),
+
+            pmtud: pmtud::Pmtud::new(
+                config.pmtud,
+                config.max_send_udp_payload_size,
+            ),
+        };
+
+        match &config.cc_factory {
+            Some(factory) => {
+                r.custom_cc = Some(factory(&r.view()));
+                r.sync_custom_cc();
+            },
+
+            None => (r.cc_ops.on_init)(&mut r),
         }
+
+        r
     }
 
     pub fn on_packet_sent(
@@ -229,10 +358,13 @@ impl Recovery {
         let sent_bytes = pkt.size;
         let pkt_num = pkt.pkt_num;
 
-        self.delivery_rate.on_packet_sent(&mut pkt, now);
+        self.ecn.on_packet_sent(pkt.ecn_marked);
+
+        if pkt.pmtud_probe {
+            self.pmtud.on_probe_sent();
+        }
 
-        self.largest_sent_pkt[epoch] =
-            cmp::max(self.largest_sent_pkt[epoch], pkt_num);
+        self.delivery_rate.on_packet_sent(&mut pkt, now);
 
         self.sent[epoch].push_back(pkt);
 
@@ -262,7 +394,7 @@ impl Recovery {
         }
 
         // Pacing: Set the pacing rate if CC doesn't do its own.
-        if !(self.cc_ops.has_custom_pacing)() {
+        if !self.has_custom_pacing() {
             if let Some(srtt) = self.smoothed_rtt {
                 let rate = (self.congestion_window as u64 * 1000000) /
                     srtt.as_micros() as u64;
@@ -277,9 +409,62 @@ impl Recovery {
     }
 
     fn on_packet_sent_cc(&mut self, sent_bytes: usize, now: Instant) {
+        if self.custom_cc.is_some() {
+            self.bytes_in_flight += sent_bytes;
+
+            let view = self.view();
+
+            if let Some(cc) = &mut self.custom_cc {
+                cc.on_packet_sent(sent_bytes, now, &view);
+            }
+
+            self.sync_custom_cc();
+
+            return;
+        }
+
         (self.cc_ops.on_packet_sent)(self, sent_bytes, now);
     }
 
+    fn has_custom_pacing(&self) -> bool {
+        match &self.custom_cc {
+            Some(cc) => cc.pacing_rate().is_some(),
+
+            None => (self.cc_ops.has_custom_pacing)(),
+        }
+    }
+
+    /// Returns a snapshot of the state exposed to congestion controllers.
+    fn view(&self) -> RecoveryView {
+        RecoveryView {
+            latest_rtt: self.latest_rtt,
+            smoothed_rtt: self.rtt(),
+            min_rtt: self.min_rtt,
+            rttvar: self.rttvar,
+            max_datagram_size: self.max_datagram_size,
+            bytes_in_flight: self.bytes_in_flight,
+            app_limited: self.app_limited,
+            delivery_rate: self.delivery_rate.delivery_rate(),
+            delivered: self.delivery_rate.delivered(),
+            delivery_rate_sample_rtt: self.delivery_rate.sample_rtt(),
+            delivery_rate_sample_app_limited: self
+                .delivery_rate
+                .sample_is_app_limited(),
+        }
+    }
+
+    /// Applies the window and pacing rate of the application-provided
+    /// congestion controller, if any.
+    fn sync_custom_cc(&mut self) {
+        if let Some(cc) = &self.custom_cc {
+            self.congestion_window = cc.cwnd();
+
+            if let Some(rate) = cc.pacing_rate() {
+                self.set_pacing_rate(rate);
+            }
+        }
+    }
+
     pub fn set_pacing_rate(&mut self, rate: u64) {
         if rate != 0 {
             self.pacing_rate = rate;
@@ -319,32 +504,19 @@ impl Recovery {
         };
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn on_ack_received(
         &mut self, ranges: &ranges::RangeSet, ack_delay: u64,
-        epoch: packet::Epoch, handshake_status: HandshakeStatus, now: Instant,
-        trace_id: &str,
+        ecn_counts: Option<frame::EcnCounts>, epoch: packet::Epoch,
+        handshake_status: HandshakeStatus, now: Instant, trace_id: &str,
     ) -> Result<()> {
         let largest_acked = ranges.last().unwrap();
 
-        // If the largest packet number acked exceeds any packet number we have
-        // sent, then the ACK is obviously invalid, so there's no need to
-        // continue further.
-        if largest_acked > self.largest_sent_pkt[epoch] {
-            if cfg!(feature = "fuzzing") {
-                return Ok(());
-            }
-
-            return Err(Error::InvalidPacket);
-        }
+        let mut has_ack_eliciting = false;
 
-        if self.largest_acked_pkt[epoch] == std::u64::MAX {
-            self.largest_acked_pkt[epoch] = largest_acked;
-        } else {
-            self.largest_acked_pkt[epoch] =
-                cmp::max(self.largest_acked_pkt[epoch], largest_acked);
-        }
+        let mut newly_acked_ecn_marked = 0;
 
-        let mut has_ack_eliciting = false;
+        let mut pmtud_probe_acked = None;
 
         let mut largest_newly_acked_pkt_num = 0;
         let mut largest_newly_acked_sent_time = now;
@@ -373,6 +545,16 @@ impl Recovery {
                     has_ack_eliciting = true;
                 }
 
+                if unacked.ecn_marked {
+                    newly_acked_ecn_marked += 1;
+                }
+
+                if unacked.pmtud_probe {
+                    pmtud_probe_acked = Some(unacked.size);
+                } else {
+                    self.pmtud.on_packet_acked(unacked.size);
+                }
+
                 largest_newly_acked_pkt_num = unacked.pkt_num;
                 largest_newly_acked_sent_time = unacked.time_sent;
 
@@ -399,10 +581,37 @@ impl Recovery {
 
         self.delivery_rate.estimate();
 
+        self.detect_spurious_losses(ranges, epoch, now, trace_id);
+
+        // An acknowledged probe validates a larger datagram size.
+        if let Some(size) = pmtud_probe_acked {
+            if self.pmtud.on_probe_acked(size, now) {
+                trace!("{} PMTU probe of size {} acked", trace_id, size);
+
+                self.update_max_datagram_size(self.pmtud.plpmtu());
+            }
+        }
+
         if newly_acked.is_empty() {
+            // Still keep track of the ECN counts, as they are shared by all
+            // the paths.
+            self.ecn.on_ack_received(epoch, 0, ecn_counts, false);
+
             return Ok(());
         }
 
+        // Packet numbers are shared by all the paths of a connection, so only
+        // packets that were sent through this recovery instance are taken
+        // into account when looking for the largest acked packet.
+        if self.largest_acked_pkt[epoch] == std::u64::MAX {
+            self.largest_acked_pkt[epoch] = largest_newly_acked_pkt_num;
+        } else {
+            self.largest_acked_pkt[epoch] = cmp::max(
+                self.largest_acked_pkt[epoch],
+                largest_newly_acked_pkt_num,
+            );
+        }
+
         if largest_newly_acked_pkt_num == largest_acked && has_ack_eliciting {
             let latest_rtt = now - largest_newly_acked_sent_time;
 
@@ -415,6 +624,19 @@ impl Recovery {
             self.update_rtt(latest_rtt, ack_delay, now);
         }
 
+        // An increase in the CE count reported by the peer signals congestion
+        // in the same way as a lost packet would.
+        if self.ecn.on_ack_received(
+            epoch,
+            newly_acked_ecn_marked,
+            ecn_counts,
+            true,
+        ) {
+            trace!("{} ECN-CE reported on epoch {}", trace_id, epoch);
+
+            self.congestion_event(0, largest_newly_acked_sent_time, epoch, now);
+        }
+
         // Detect and mark lost packets without removing them from the sent
         // packets list.
         self.detect_lost_packets(epoch, now, trace_id);
@@ -507,6 +729,7 @@ impl Recovery {
         self.bytes_in_flight = self.bytes_in_flight.saturating_sub(unacked_bytes);
 
         self.sent[epoch].clear();
+        self.lost_history[epoch].clear();
         self.lost[epoch].clear();
         self.acked[epoch].clear();
 
@@ -545,6 +768,12 @@ impl Recovery {
         self.rtt() + cmp::max(self.rttvar * 4, GRANULARITY)
     }
 
+    /// Returns the PTO computed using the initial RTT, as used before any
+    /// RTT sample is available.
+    pub fn initial_pto() -> Duration {
+        INITIAL_RTT + cmp::max(INITIAL_RTT / 2 * 4, GRANULARITY)
+    }
+
     pub fn delivery_rate(&self) -> u64 {
         self.delivery_rate.delivery_rate()
     }
@@ -553,10 +782,7 @@ impl Recovery {
         self.max_datagram_size
     }
 
-    pub fn update_max_datagram_size(&mut self, new_max_datagram_size: usize) {
-        let max_datagram_size =
-            cmp::min(self.max_datagram_size, new_max_datagram_size);
-
+    pub fn update_max_datagram_size(&mut self, max_datagram_size: usize) {
         // Congestion Window is updated only when it's not updated already.
         if self.congestion_window ==
             self.max_datagram_size * INITIAL_WINDOW_PACKETS
@@ -567,7 +793,17 @@ impl Recovery {
         self.max_datagram_size = max_datagram_size;
     }
 
-    fn update_rtt(
+    /// Applies the limit advertised in the peer's `max_udp_payload_size`
+    /// transport parameter.
+    pub fn set_peer_max_udp_payload_size(&mut self, v: usize) {
+        self.pmtud.set_max_plpmtu(v);
+
+        let max_datagram_size = cmp::min(self.max_datagram_size, v);
+
+        self.update_max_datagram_size(max_datagram_size);
+    }
+
+    pub fn update_rtt(
         &mut self, latest_rtt: Duration, ack_delay: Duration, now: Instant,
     ) {
         self.latest_rtt = latest_rtt;
@@ -575,6 +811,8 @@ impl Recovery {
         match self.smoothed_rtt {
             // First RTT sample.
             None => {
+                self.first_rtt_sample = Some(now);
+
                 self.min_rtt = self.minmax_filter.reset(now, latest_rtt);
 
                 self.smoothed_rtt = Some(latest_rtt);
@@ -697,7 +935,7 @@ impl Recovery {
         self.loss_time[epoch] = None;
 
         let loss_delay =
-            cmp::max(self.latest_rtt, self.rtt()).mul_f64(TIME_THRESHOLD);
+            cmp::max(self.latest_rtt, self.rtt()).mul_f64(self.time_thresh);
 
         // Minimum time of kGranularity before packets are deemed lost.
         let loss_delay = cmp::max(loss_delay, GRANULARITY);
@@ -709,6 +947,12 @@ impl Recovery {
 
         let mut largest_lost_pkt = None;
 
+        let mut pmtud_black_hole = false;
+
+        let mut newly_lost = Vec::new();
+
+        let pkt_thresh = self.pkt_thresh;
+
         let unacked_iter = self.sent[epoch]
             .iter_mut()
             // Skip packets that follow the largest acked packet.
@@ -719,12 +963,39 @@ impl Recovery {
         for unacked in unacked_iter {
             // Mark packet as lost, or set time when it should be marked.
             if unacked.time_sent <= lost_send_time ||
-                largest_acked >= unacked.pkt_num + PACKET_THRESHOLD
+                largest_acked >= unacked.pkt_num + pkt_thresh
             {
                 self.lost[epoch].append(&mut unacked.frames);
 
                 unacked.time_lost = Some(now);
 
+                self.ecn.on_packet_lost(unacked.ecn_marked);
+
+                // The loss of a PMTU probe doesn't signal congestion, so
+                // it is only removed from the bytes in flight.
+                if unacked.pmtud_probe {
+                    self.pmtud.on_probe_lost(unacked.size, now);
+
+                    self.bytes_in_flight =
+                        self.bytes_in_flight.saturating_sub(unacked.size);
+
+                    self.in_flight_count[epoch] =
+                        self.in_flight_count[epoch].saturating_sub(1);
+
+                    trace!(
+                        "{} PMTU probe {} of size {} lost",
+                        trace_id,
+                        unacked.pkt_num,
+                        unacked.size
+                    );
+
+                    continue;
+                }
+
+                if self.pmtud.on_packet_lost(unacked.size, now) {
+                    pmtud_black_hole = true;
+                }
+
                 if unacked.in_flight {
                     lost_bytes += unacked.size;
 
@@ -732,6 +1003,24 @@ impl Recovery {
                     // cloning the whole packet should be relatively cheap.
                     largest_lost_pkt = Some(unacked.clone());
 
+                    newly_lost.push(LostPacket {
+                        pkt_num: unacked.pkt_num,
+
+                        time_sent: unacked.time_sent,
+
+                        time_lost: now,
+
+                        reordering: if largest_acked >=
+                            unacked.pkt_num + pkt_thresh
+                        {
+                            Some(largest_acked - unacked.pkt_num)
+                        } else {
+                            None
+                        },
+
+                        episode: 0,
+                    });
+
                     self.in_flight_count[epoch] =
                         self.in_flight_count[epoch].saturating_sub(1);
 
@@ -758,13 +1047,98 @@ impl Recovery {
 
         self.bytes_lost += lost_bytes as u64;
 
+        if pmtud_black_hole {
+            trace!("{} PMTU black hole detected", trace_id);
+
+            self.update_max_datagram_size(self.pmtud.plpmtu());
+        }
+
         if let Some(pkt) = largest_lost_pkt {
-            self.on_packets_lost(lost_bytes, &pkt, epoch, now);
+            self.on_packets_lost(lost_bytes, &pkt, &newly_lost, epoch, now);
+        }
+
+        // The losses are attributed to the congestion recovery episode they
+        // caused or happened during, so that it can be undone if they all turn
+        // out to be spurious.
+        self.episode_lost += newly_lost.len();
+
+        for mut lost in newly_lost {
+            lost.episode = self.congestion_episode;
+
+            self.lost_history[epoch].push_back(lost);
         }
 
         self.drain_packets(epoch);
     }
 
+    fn detect_spurious_losses(
+        &mut self, ranges: &ranges::RangeSet, epoch: packet::Epoch, now: Instant,
+        trace_id: &str,
+    ) {
+        // Forget about packets that were lost long enough ago.
+        let retention = self.pto() * LOST_HISTORY_PTO_COUNT;
+
+        while let Some(lost) = self.lost_history[epoch].front() {
+            if lost.time_lost + retention >= now {
+                break;
+            }
+
+            self.lost_history[epoch].pop_front();
+        }
+
+        let mut spurious = Vec::new();
+
+        self.lost_history[epoch].retain(|lost| {
+            if ranges.iter().any(|r| r.contains(&lost.pkt_num)) {
+                spurious.push(*lost);
+
+                return false;
+            }
+
+            true
+        });
+
+        for lost in spurious {
+            trace!(
+                "{} packet {} spuriously lost on epoch {}",
+                trace_id,
+                lost.pkt_num,
+                epoch
+            );
+
+            self.spurious_lost_count += 1;
+
+            // Adapt the reordering threshold that caused the packet to be
+            // declared lost, so that it wouldn't have been.
+            if let Some(reordering) = lost.reordering {
+                self.pkt_thresh = reordering
+                    .saturating_add(1)
+                    .max(self.pkt_thresh)
+                    .min(MAX_PACKET_THRESHOLD);
+            } else {
+                let rtt = cmp::max(self.latest_rtt, self.rtt());
+
+                let time_thresh =
+                    (now - lost.time_sent).as_secs_f64() / rtt.as_secs_f64();
+
+                self.time_thresh =
+                    time_thresh.max(self.time_thresh).min(MAX_TIME_THRESHOLD);
+            }
+
+            if lost.episode != self.congestion_episode || !self.episode_undo {
+                continue;
+            }
+
+            self.episode_lost = self.episode_lost.saturating_sub(1);
+
+            if self.episode_lost == 0 {
+                trace!("{} spurious congestion event undone", trace_id);
+
+                self.undo_congestion_event();
+            }
+        }
+    }
+
     fn drain_packets(&mut self, epoch: packet::Epoch) {
         let mut lowest_non_expired_pkt_index = self.sent[epoch].len();
 
@@ -792,9 +1166,24 @@ impl Recovery {
     fn on_packets_acked(
         &mut self, acked: Vec<Acked>, epoch: packet::Epoch, now: Instant,
     ) {
-        for pkt in acked {
-            (self.cc_ops.on_packet_acked)(self, &pkt, epoch, now);
+        if self.custom_cc.is_some() {
+            for pkt in &acked {
+                self.bytes_in_flight =
+                    self.bytes_in_flight.saturating_sub(pkt.size);
+            }
+
+            let view = self.view();
+
+            if let Some(cc) = &mut self.custom_cc {
+                cc.on_packets_acked(&acked, now, &view);
+            }
+
+            self.sync_custom_cc();
+
+            return;
         }
+
+        (self.cc_ops.on_packets_acked)(self, &acked, epoch, now);
     }
 
     fn in_congestion_recovery(&self, sent_time: Instant) -> bool {
@@ -806,37 +1195,174 @@ impl Recovery {
         }
     }
 
-    fn in_persistent_congestion(&mut self, _largest_lost_pkt_num: u64) -> bool {
-        let _congestion_period = self.pto() * PERSISTENT_CONGESTION_THRESHOLD;
+    /// Returns the duration that the send times of lost packets need to span
+    /// for persistent congestion to be established.
+    fn persistent_congestion_duration(&self) -> Duration {
+        (self.rtt() + cmp::max(self.rttvar * 4, GRANULARITY) + self.max_ack_delay) *
+            PERSISTENT_CONGESTION_THRESHOLD
+    }
+
+    fn in_persistent_congestion(
+        &self, epoch: packet::Epoch, newly_lost: &[LostPacket],
+    ) -> bool {
+        // Persistent congestion can only be established based on packets sent
+        // after an RTT sample was taken.
+        let first_rtt_sample = match self.first_rtt_sample {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        let duration = self.persistent_congestion_duration();
+
+        // Packets of all packet number spaces are considered, as an
+        // acknowledgement in any of them shows that the path is not
+        // persistently congested.
+        let mut sent: Vec<(packet::Epoch, &Sent)> = self
+            .sent
+            .iter()
+            .enumerate()
+            .flat_map(|(e, pkts)| pkts.iter().map(move |pkt| (e, pkt)))
+            .filter(|(_, pkt)| pkt.time_sent > first_rtt_sample)
+            .collect();
+
+        sent.sort_by_key(|(_, pkt)| pkt.time_sent);
+
+        // Look for two lost ack-eliciting packets whose send times are far
+        // enough apart, without any acknowledged packet sent in between. At
+        // least one of the packets in the period must have just been declared
+        // lost, otherwise it was already accounted for.
+        let mut earliest_lost: Option<Instant> = None;
+        let mut has_newly_lost = false;
+
+        for (e, pkt) in sent {
+            if pkt.time_acked.is_some() {
+                earliest_lost = None;
+                has_newly_lost = false;
+                continue;
+            }
+
+            if pkt.time_lost.is_none() || !pkt.ack_eliciting || pkt.pmtud_probe {
+                continue;
+            }
+
+            let earliest_lost = *earliest_lost.get_or_insert(pkt.time_sent);
+
+            if e == epoch && newly_lost.iter().any(|l| l.pkt_num == pkt.pkt_num) {
+                has_newly_lost = true;
+            }
+
+            if has_newly_lost && pkt.time_sent - earliest_lost > duration {
+                return true;
+            }
+        }
 
-        // TODO: properly detect persistent congestion
         false
     }
 
     fn on_packets_lost(
         &mut self, lost_bytes: usize, largest_lost_pkt: &Sent,
-        epoch: packet::Epoch, now: Instant,
+        newly_lost: &[LostPacket], epoch: packet::Epoch, now: Instant,
     ) {
         self.bytes_in_flight = self.bytes_in_flight.saturating_sub(lost_bytes);
 
-        self.congestion_event(largest_lost_pkt.time_sent, epoch, now);
+        self.congestion_event(lost_bytes, largest_lost_pkt.time_sent, epoch, now);
+
+        if self.in_persistent_congestion(epoch, newly_lost) {
+            self.persistent_congestion = true;
+
+            self.episode_undo = false;
+
+            // The network conditions might have changed significantly, so the
+            // minimum RTT is reset to the latest sample.
+            self.min_rtt = self.minmax_filter.reset(now, self.latest_rtt);
 
-        if self.in_persistent_congestion(largest_lost_pkt.pkt_num) {
             self.collapse_cwnd();
         }
     }
 
+    /// Returns true if persistent congestion was detected since the last
+    /// time this was called.
+    pub fn take_persistent_congestion(&mut self) -> bool {
+        std::mem::replace(&mut self.persistent_congestion, false)
+    }
+
     fn congestion_event(
-        &mut self, time_sent: Instant, epoch: packet::Epoch, now: Instant,
+        &mut self, lost_bytes: usize, time_sent: Instant, epoch: packet::Epoch,
+        now: Instant,
     ) {
-        if !self.in_congestion_recovery(time_sent) {
+        let new_episode = !self.in_congestion_recovery(time_sent);
+
+        if new_episode {
+            self.prior_recovery_start_time = self.congestion_recovery_start_time;
+
+            self.congestion_episode += 1;
+            self.episode_lost = 0;
+
+            // Congestion signaled by ECN is never spurious.
+            self.episode_undo = lost_bytes > 0;
+        } else if lost_bytes == 0 {
+            self.episode_undo = false;
+        }
+
+        if self.custom_cc.is_some() {
+            if new_episode {
+                self.congestion_recovery_start_time = Some(now);
+
+                if let Some(cc) = &mut self.custom_cc {
+                    cc.checkpoint();
+                }
+            }
+
+            let view = self.view();
+
+            if let Some(cc) = &mut self.custom_cc {
+                cc.congestion_event(lost_bytes, time_sent, now, &view);
+            }
+
+            self.sync_custom_cc();
+
+            return;
+        }
+
+        if new_episode {
             (self.cc_ops.checkpoint)(self);
         }
 
-        (self.cc_ops.congestion_event)(self, time_sent, epoch, now);
+        (self.cc_ops.congestion_event)(self, lost_bytes, time_sent, epoch, now);
+    }
+
+    /// Restores the congestion controller state from before the current
+    /// congestion recovery episode.
+    fn undo_congestion_event(&mut self) {
+        self.episode_undo = false;
+
+        if self.custom_cc.is_some() {
+            if let Some(cc) = &mut self.custom_cc {
+                cc.rollback();
+            }
+
+            self.sync_custom_cc();
+        } else {
+            (self.cc_ops.rollback)(self);
+        }
+
+        self.congestion_recovery_start_time = self.prior_recovery_start_time;
     }
 
     fn collapse_cwnd(&mut self) {
+        if self.custom_cc.is_some() {
+            let view = self.view();
+
+            if let Some(cc) = &mut self.custom_cc {
+                cc.collapse_cwnd(&view);
+            }
+
+            self.sync_custom_cc();
+
+            return;
+        }
+
         (self.cc_ops.collapse_cwnd)(self);
     }
 
@@ -885,6 +1411,8 @@ pub enum CongestionControlAlgorithm {
     Reno  = 0,
     /// CUBIC congestion control algorithm (default). `cubic` in a string form.
     CUBIC = 1,
+    /// BBR congestion control algorithm. `bbr` in a string form.
+    BBR   = 2,
 }
 
 impl FromStr for CongestionControlAlgorithm {
@@ -897,39 +1425,162 @@ impl FromStr for CongestionControlAlgorithm {
         match name {
             "reno" => Ok(CongestionControlAlgorithm::Reno),
             "cubic" => Ok(CongestionControlAlgorithm::CUBIC),
+            "bbr" => Ok(CongestionControlAlgorithm::BBR),
 
             _ => Err(crate::Error::CongestionControl),
         }
     }
 }
 
-pub struct CongestionControlOps {
-    pub on_packet_sent: fn(r: &mut Recovery, sent_bytes: usize, now: Instant),
+/// A congestion controller.
+///
+/// Applications can provide their own congestion control algorithm by
+/// implementing this trait, and registering a factory for it with
+/// [`Config::set_congestion_controller_factory()`]. Each connection path gets
+/// its own instance.
+///
+/// Loss detection, RTT estimation, delivery rate estimation and the tracking
+/// of bytes in flight are handled by quiche, and exposed to the controller as
+/// a [`RecoveryView`].
+///
+/// [`Config::set_congestion_controller_factory()`]:
+///     struct.Config.html#method.set_congestion_controller_factory
+/// [`RecoveryView`]: struct.RecoveryView.html
+pub trait CongestionController: Send + Sync {
+    /// Called when a packet that counts towards bytes in flight is sent.
+    ///
+    /// `r.bytes_in_flight` already includes the packet.
+    fn on_packet_sent(
+        &mut self, sent_bytes: usize, now: Instant, r: &RecoveryView,
+    );
 
-    pub on_packet_acked:
-        fn(r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant),
+    /// Called when packets are newly acknowledged by the peer.
+    ///
+    /// `r.bytes_in_flight` no longer includes the packets.
+    fn on_packets_acked(
+        &mut self, packets: &[Acked], now: Instant, r: &RecoveryView,
+    );
 
-    pub congestion_event: fn(
-        r: &mut Recovery,
-        time_sent: Instant,
-        epoch: packet::Epoch,
-        now: Instant,
-    ),
+    /// Called when packets are declared lost, or when the peer reports
+    /// packets marked with ECN-CE, in which case `lost_bytes` is zero.
+    ///
+    /// `time_sent` is the time the most recent of the affected packets was
+    /// sent, which allows detecting whether the event belongs to an ongoing
+    /// recovery period.
+    fn congestion_event(
+        &mut self, lost_bytes: usize, time_sent: Instant, now: Instant,
+        r: &RecoveryView,
+    );
 
-    pub collapse_cwnd: fn(r: &mut Recovery),
+    /// Called when persistent congestion is detected, in which case the
+    /// congestion window should be reduced to its minimum.
+    fn collapse_cwnd(&mut self, r: &RecoveryView);
 
-    pub checkpoint: fn(r: &mut Recovery),
+    /// Returns the current congestion window, in bytes.
+    fn cwnd(&self) -> usize;
 
-    pub rollback: fn(r: &mut Recovery),
+    /// Returns the pacing rate in bytes per second.
+    ///
+    /// If `None` is returned, which is the default, quiche paces packets
+    /// based on the congestion window and the smoothed RTT.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
 
-    pub has_custom_pacing: fn() -> bool,
-}
+    /// Saves the controller's state before the start of a new recovery
+    /// period, so that it can be restored if the congestion event turns out
+    /// to be spurious.
+    fn checkpoint(&mut self) {}
+
+    /// Restores the state saved by the last call to [`checkpoint()`].
+    ///
+    /// [`checkpoint()`]: trait.CongestionController.html#method.checkpoint
+    fn rollback(&mut self) {}
+}
+
+/// A read-only snapshot of the loss recovery state of a path, as exposed to
+/// [`CongestionController`] implementations.
+///
+/// [`CongestionController`]: trait.CongestionController.html
+#[derive(Clone, Copy, Debug)]
+pub struct RecoveryView {
+    /// The most recent RTT sample.
+    pub latest_rtt: Duration,
+
+    /// The smoothed RTT, or the initial RTT if no sample was taken yet.
+    pub smoothed_rtt: Duration,
+
+    /// The minimum RTT observed.
+    pub min_rtt: Duration,
+
+    /// The RTT variation.
+    pub rttvar: Duration,
+
+    /// The maximum size of outgoing datagrams, in bytes.
+    pub max_datagram_size: usize,
+
+    /// The number of bytes sent but neither acknowledged nor declared lost.
+    pub bytes_in_flight: usize,
+
+    /// Whether the sender doesn't have enough data to fill the congestion
+    /// window.
+    pub app_limited: bool,
+
+    /// The most recent delivery rate estimate, in bytes per second.
+    pub delivery_rate: u64,
+
+    /// The total number of bytes delivered to the peer.
+    pub delivered: usize,
+
+    /// The RTT of the packet the most recent delivery rate estimate is based
+    /// on.
+    pub delivery_rate_sample_rtt: Duration,
+
+    /// Whether the most recent delivery rate estimate was taken while
+    /// application limited, in which case it likely underestimates the
+    /// available bandwidth.
+    pub delivery_rate_sample_app_limited: bool,
+}
+
+/// Creates a congestion controller for a new path.
+pub type CongestionControllerFactory =
+    dyn Fn(&RecoveryView) -> Box<dyn CongestionController> + Send + Sync;
+
+pub struct CongestionControlOps {
+    pub on_init: fn(r: &mut Recovery),
+
+    pub on_packet_sent: fn(r: &mut Recovery, sent_bytes: usize, now: Instant),
+
+    pub on_packets_acked: fn(
+        r: &mut Recovery,
+        packets: &[Acked],
+        epoch: packet::Epoch,
+        now: Instant,
+    ),
+
+    pub congestion_event: fn(
+        r: &mut Recovery,
+        lost_bytes: usize,
+        time_sent: Instant,
+        epoch: packet::Epoch,
+        now: Instant,
+    ),
+
+    pub collapse_cwnd: fn(r: &mut Recovery),
+
+    pub checkpoint: fn(r: &mut Recovery),
+
+    pub rollback: fn(r: &mut Recovery),
+
+    pub has_custom_pacing: fn() -> bool,
+}
 
 impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
     fn from(algo: CongestionControlAlgorithm) -> Self {
         match algo {
             CongestionControlAlgorithm::Reno => &reno::RENO,
             CongestionControlAlgorithm::CUBIC => &cubic::CUBIC,
+            CongestionControlAlgorithm::BBR => &bbr::BBR,
         }
     }
 }
@@ -980,6 +1631,12 @@ impl std::fmt::Debug for Recovery {
             write!(f, "hystart={:?} ", self.hystart)?;
         }
 
+        write!(f, "ecn={{ {:?}}} ", self.ecn)?;
+
+        if self.pmtud.enabled() {
+            write!(f, "pmtud={{ {:?}}} ", self.pmtud)?;
+        }
+
         Ok(())
     }
 }
@@ -1011,6 +1668,10 @@ pub struct Sent {
     pub is_app_limited: bool,
 
     pub has_data: bool,
+
+    pub ecn_marked: bool,
+
+    pub pmtud_probe: bool,
 }
 
 impl std::fmt::Debug for Sent {
@@ -1027,17 +1688,40 @@ impl std::fmt::Debug for Sent {
         )?;
         write!(f, "is_app_limited={} ", self.is_app_limited)?;
         write!(f, "has_data={} ", self.has_data)?;
+        write!(f, "ecn_marked={} ", self.ecn_marked)?;
+        write!(f, "pmtud_probe={} ", self.pmtud_probe)?;
 
         Ok(())
     }
 }
 
-#[derive(Clone)]
+/// A packet that was declared lost.
+#[derive(Clone, Copy)]
+struct LostPacket {
+    pkt_num: u64,
+
+    time_sent: Instant,
+
+    time_lost: Instant,
+
+    // The packet number distance to the largest acknowledged packet, if the
+    // packet was declared lost because of the packet reordering threshold.
+    reordering: Option<u64>,
+
+    // The congestion recovery episode during which the packet was lost.
+    episode: u64,
+}
+
+/// A packet newly acknowledged by the peer.
+#[derive(Clone, Debug)]
 pub struct Acked {
+    /// The packet number.
     pub pkt_num: u64,
 
+    /// The time the packet was sent.
     pub time_sent: Instant,
 
+    /// The size of the packet, in bytes.
     pub size: usize,
 }
 
@@ -1081,11 +1765,17 @@ mod tests {
         assert_eq!(algo, CongestionControlAlgorithm::Reno);
     }
 
+    #[test]
+    fn lookup_cc_algo_bbr() {
+        let algo = CongestionControlAlgorithm::from_str("bbr").unwrap();
+        assert_eq!(algo, CongestionControlAlgorithm::BBR);
+    }
+
     #[test]
     fn lookup_cc_algo_bad() {
         assert_eq!(
             CongestionControlAlgorithm::from_str("???"),
-            Err(Error::CongestionControl)
+            Err(crate::Error::CongestionControl)
         );
     }
 
@@ -1101,6 +1791,128 @@ mod tests {
         assert_eq!(r.cwnd(), r.max_datagram_size * MINIMUM_WINDOW_PACKETS);
     }
 
+    // A simple controller that grows the window by the acked bytes, and
+    // halves it on congestion events.
+    struct TestController {
+        cwnd: usize,
+
+        prior_cwnd: usize,
+    }
+
+    impl CongestionController for TestController {
+        fn on_packet_sent(
+            &mut self, sent_bytes: usize, _now: Instant, r: &RecoveryView,
+        ) {
+            assert!(r.bytes_in_flight >= sent_bytes);
+        }
+
+        fn on_packets_acked(
+            &mut self, packets: &[Acked], _now: Instant, _r: &RecoveryView,
+        ) {
+            self.cwnd += packets.iter().map(|p| p.size).sum::<usize>();
+        }
+
+        fn congestion_event(
+            &mut self, _lost_bytes: usize, _time_sent: Instant, _now: Instant,
+            _r: &RecoveryView,
+        ) {
+            self.cwnd /= 2;
+        }
+
+        fn collapse_cwnd(&mut self, r: &RecoveryView) {
+            self.cwnd = r.max_datagram_size * MINIMUM_WINDOW_PACKETS;
+        }
+
+        fn cwnd(&self) -> usize {
+            self.cwnd
+        }
+
+        fn pacing_rate(&self) -> Option<u64> {
+            Some(1_000_000)
+        }
+
+        fn checkpoint(&mut self) {
+            self.prior_cwnd = self.cwnd;
+        }
+
+        fn rollback(&mut self) {
+            self.cwnd = self.prior_cwnd;
+        }
+    }
+
+    #[test]
+    fn custom_congestion_controller() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_congestion_controller_factory(|r| {
+            Box::new(TestController {
+                cwnd: r.max_datagram_size * 5,
+                prior_cwnd: 0,
+            })
+        });
+
+        let mut r = Recovery::new(&cfg);
+        let mut now = Instant::now();
+
+        assert_eq!(r.cwnd(), r.max_datagram_size * 5);
+        assert_eq!(r.pacing_rate, 1_000_000);
+
+        for pn in 0..5 {
+            let p = Sent {
+                pkt_num: pn,
+                frames: vec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                recent_delivered_packet_sent_time: now,
+                is_app_limited: false,
+                has_data: false,
+                ecn_marked: false,
+                pmtud_probe: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        assert_eq!(r.bytes_in_flight, 5000);
+
+        now += Duration::from_millis(10);
+
+        // Packets 0 and 1 are lost, 4 is acked.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(4..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                ""
+            ),
+            Ok(())
+        );
+
+        assert_eq!(r.lost_count, 2);
+        assert_eq!(r.bytes_in_flight, 2000);
+        assert_eq!(r.cwnd(), r.max_datagram_size * 5 / 2 + 1000);
+
+        r.collapse_cwnd();
+        assert_eq!(r.cwnd(), r.max_datagram_size * MINIMUM_WINDOW_PACKETS);
+    }
+
     #[test]
     fn loss_on_pto() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -1127,6 +1939,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1153,6 +1967,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1179,6 +1995,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1205,6 +2023,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1228,6 +2048,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
@@ -1263,6 +2084,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1289,6 +2112,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1313,6 +2138,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
@@ -1353,6 +2179,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1379,6 +2207,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1405,6 +2235,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1431,6 +2263,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1455,6 +2289,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
@@ -1506,6 +2341,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1532,6 +2369,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1558,6 +2397,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1584,6 +2425,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1607,6 +2450,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
@@ -1624,6 +2468,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
@@ -1639,6 +2484,429 @@ mod tests {
         assert_eq!(r.lost_count, 1);
     }
 
+    fn test_sent(pkt_num: u64, time_sent: Instant) -> Sent {
+        Sent {
+            pkt_num,
+            frames: vec![],
+            time_sent,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: time_sent,
+            recent_delivered_packet_sent_time: time_sent,
+            is_app_limited: false,
+            has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
+        }
+    }
+
+    // Sends packets 1 to 6 spaced by 100ms, followed by packet 7 which is
+    // acknowledged along with `acked`, so that all the other packets are
+    // declared lost.
+    fn lose_packets(r: &mut Recovery, mut now: Instant, acked: Option<u64>) {
+        for pkt_num in 1..7 {
+            r.on_packet_sent(
+                test_sent(pkt_num, now),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+
+            now += Duration::from_millis(100);
+        }
+
+        r.on_packet_sent(
+            test_sent(7, now),
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+
+        now += Duration::from_millis(50);
+
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(7..8);
+
+        if let Some(pkt_num) = acked {
+            ranges.insert(pkt_num..pkt_num + 1);
+        }
+
+        assert_eq!(
+            r.on_ack_received(
+                &ranges,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                ""
+            ),
+            Ok(())
+        );
+    }
+
+    // Takes an RTT sample of 50ms, which makes the persistent congestion
+    // duration (50ms + 4 * 25ms) * 3 = 450ms.
+    fn take_rtt_sample(r: &mut Recovery, now: Instant) -> Instant {
+        r.on_packet_sent(
+            test_sent(0, now),
+            packet::EPOCH_APPLICATION,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+
+        let now = now + Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                ""
+            ),
+            Ok(())
+        );
+
+        assert_eq!(
+            r.persistent_congestion_duration(),
+            Duration::from_millis(450)
+        );
+
+        now
+    }
+
+    #[test]
+    fn persistent_congestion() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = take_rtt_sample(&mut r, Instant::now());
+
+        // The lost packets span 500ms.
+        lose_packets(&mut r, now, None);
+
+        assert_eq!(r.lost_count, 6);
+        assert_eq!(r.cwnd(), r.max_datagram_size * MINIMUM_WINDOW_PACKETS);
+
+        // Persistent congestion is only reported once.
+        assert!(r.take_persistent_congestion());
+        assert!(!r.take_persistent_congestion());
+    }
+
+    #[test]
+    fn persistent_congestion_interrupted_by_ack() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = take_rtt_sample(&mut r, Instant::now());
+
+        let cwnd_prev = r.cwnd();
+
+        // Packet 3 is acknowledged, so the longest span of consecutive lost
+        // packets is 200ms.
+        lose_packets(&mut r, now, Some(3));
+
+        assert_eq!(r.lost_count, 5);
+        assert_eq!(
+            r.cwnd(),
+            (cwnd_prev as f64 * LOSS_REDUCTION_FACTOR) as usize
+        );
+        assert!(!r.take_persistent_congestion());
+    }
+
+    #[test]
+    fn persistent_congestion_without_rtt_sample() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let cwnd_prev = r.cwnd();
+
+        // The packets were all sent before the first RTT sample was taken.
+        lose_packets(&mut r, Instant::now(), None);
+
+        assert_eq!(r.lost_count, 6);
+        assert_eq!(
+            r.cwnd(),
+            (cwnd_prev as f64 * LOSS_REDUCTION_FACTOR) as usize
+        );
+        assert!(!r.take_persistent_congestion());
+    }
+
+    #[test]
+    fn persistent_congestion_not_repeated() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = take_rtt_sample(&mut r, Instant::now());
+
+        lose_packets(&mut r, now, None);
+
+        assert!(r.take_persistent_congestion());
+
+        // The window grows again.
+        let mut now = now + Duration::from_millis(700);
+
+        send_packets(&mut r, 8..18, now);
+
+        now += Duration::from_millis(50);
+        ack_packets(&mut r, 8..18, now);
+
+        let cwnd_prev = r.cwnd();
+        assert!(cwnd_prev > r.max_datagram_size * MINIMUM_WINDOW_PACKETS);
+
+        // Packet 18 is declared lost due to reordering, which is a regular
+        // congestion event.
+        send_packets(&mut r, 18..22, now);
+
+        now += Duration::from_millis(50);
+        ack_packets(&mut r, 21..22, now);
+
+        assert_eq!(r.lost_count, 7);
+        assert_eq!(
+            r.cwnd(),
+            (cwnd_prev as f64 * LOSS_REDUCTION_FACTOR) as usize
+        );
+        assert!(!r.take_persistent_congestion());
+    }
+
+    #[test]
+    fn persistent_congestion_already_lost() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let now = take_rtt_sample(&mut r, Instant::now());
+
+        for pkt_num in 1..9 {
+            r.on_packet_sent(
+                test_sent(pkt_num, now + Duration::from_millis(pkt_num * 100)),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        // Packets 1 to 6 were lost in a previous loss event, but weren't
+        // drained yet, followed by acknowledged packet 7 and lost packet 8.
+        for pkt in r.sent[packet::EPOCH_APPLICATION].iter_mut() {
+            match pkt.pkt_num {
+                7 => pkt.time_acked = Some(now),
+
+                _ => pkt.time_lost = Some(now),
+            }
+        }
+
+        let lost = |pkt_num| LostPacket {
+            pkt_num,
+            time_sent: now,
+            time_lost: now,
+            reordering: None,
+            episode: 0,
+        };
+
+        // The period spanned by packets 1 to 6 was already accounted for.
+        assert!(
+            !r.in_persistent_congestion(packet::EPOCH_APPLICATION, &[lost(8)])
+        );
+        assert!(r.in_persistent_congestion(packet::EPOCH_APPLICATION, &[lost(6)]));
+    }
+
+    #[test]
+    fn persistent_congestion_across_spaces() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = take_rtt_sample(&mut r, Instant::now());
+
+        let cwnd_prev = r.cwnd();
+
+        for pkt_num in 1..8 {
+            r.on_packet_sent(
+                test_sent(pkt_num, now),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+
+            // A Handshake packet is sent in the middle of the period.
+            if pkt_num == 3 {
+                r.on_packet_sent(
+                    test_sent(0, now),
+                    packet::EPOCH_HANDSHAKE,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                );
+            }
+
+            now += Duration::from_millis(100);
+        }
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..1);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::EPOCH_HANDSHAKE,
+                HandshakeStatus::default(),
+                now,
+                ""
+            ),
+            Ok(())
+        );
+
+        // The acknowledged Handshake packet interrupts the period spanned by
+        // the lost packets.
+        ack_packets(&mut r, 7..8, now);
+
+        assert_eq!(r.lost_count, 6);
+        assert_eq!(
+            r.cwnd(),
+            (cwnd_prev as f64 * LOSS_REDUCTION_FACTOR) as usize
+        );
+        assert!(!r.take_persistent_congestion());
+    }
+
+    fn send_packets(r: &mut Recovery, pkts: std::ops::Range<u64>, now: Instant) {
+        for pkt_num in pkts {
+            r.on_packet_sent(
+                test_sent(pkt_num, now),
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+    }
+
+    fn ack_packets(r: &mut Recovery, pkts: std::ops::Range<u64>, now: Instant) {
+        let mut ranges = ranges::RangeSet::default();
+        ranges.insert(pkts);
+
+        assert_eq!(
+            r.on_ack_received(
+                &ranges,
+                0,
+                None,
+                packet::EPOCH_APPLICATION,
+                HandshakeStatus::default(),
+                now,
+                ""
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn spurious_loss_undo() {
+        let algos = [
+            CongestionControlAlgorithm::Reno,
+            CongestionControlAlgorithm::CUBIC,
+        ];
+
+        for algo in &algos {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(*algo);
+
+            let mut r = Recovery::new(&cfg);
+
+            let mut now = take_rtt_sample(&mut r, Instant::now());
+
+            let cwnd_prev = r.cwnd();
+
+            send_packets(&mut r, 1..6, now);
+
+            // Packets 1 and 2 are declared lost due to reordering.
+            now += Duration::from_millis(50);
+            ack_packets(&mut r, 4..6, now);
+
+            assert_eq!(r.lost_count, 2);
+            assert!(r.cwnd() < cwnd_prev);
+
+            // Packet 1 is acknowledged, but packet 2 might still be lost.
+            now += Duration::from_millis(1);
+            ack_packets(&mut r, 1..2, now);
+
+            assert_eq!(r.spurious_lost_count, 1);
+            assert!(r.cwnd() < cwnd_prev);
+
+            // All the lost packets are acknowledged, so the congestion event is
+            // undone.
+            now += Duration::from_millis(1);
+            ack_packets(&mut r, 2..3, now);
+
+            assert_eq!(r.spurious_lost_count, 2);
+            assert_eq!(r.cwnd(), cwnd_prev);
+            assert_eq!(r.congestion_recovery_start_time, None);
+
+            // The packet threshold is increased to avoid the same losses.
+            assert_eq!(r.pkt_thresh, 5);
+            assert_eq!(r.time_thresh, TIME_THRESHOLD);
+        }
+    }
+
+    #[test]
+    fn spurious_loss_time_threshold() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg);
+
+        let mut now = take_rtt_sample(&mut r, Instant::now());
+
+        let cwnd_prev = r.cwnd();
+
+        send_packets(&mut r, 1..2, now);
+
+        now += Duration::from_millis(100);
+        send_packets(&mut r, 2..3, now);
+
+        // Packet 1 is declared lost due to the time threshold.
+        now += Duration::from_millis(50);
+        ack_packets(&mut r, 2..3, now);
+
+        assert_eq!(r.lost_count, 1);
+        assert!(r.cwnd() < cwnd_prev);
+
+        now += Duration::from_millis(1);
+        ack_packets(&mut r, 1..2, now);
+
+        assert_eq!(r.spurious_lost_count, 1);
+        assert_eq!(r.cwnd(), cwnd_prev);
+
+        assert_eq!(r.pkt_thresh, PACKET_THRESHOLD);
+        assert_eq!(r.time_thresh, MAX_TIME_THRESHOLD);
+    }
+
     #[test]
     fn pacing() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -1665,6 +2933,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1692,6 +2962,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 10,
+                None,
                 packet::EPOCH_APPLICATION,
                 HandshakeStatus::default(),
                 now,
@@ -1719,6 +2990,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1750,6 +3023,8 @@ mod tests {
             recent_delivered_packet_sent_time: now,
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         r.on_packet_sent(
@@ -1776,8 +3051,11 @@ mod tests {
     }
 }
 
+mod bbr;
 mod cubic;
 mod delivery_rate;
+pub mod ecn;
 mod hystart;
+mod pmtud;
 mod prr;
 mod reno;
@@ -0,0 +1,403 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Datagram Packetization Layer Path MTU Discovery.
+//!
+//! This implementation is based on the following RFC:
+//!
+//! <https://datatracker.ietf.org/doc/html/rfc8899>
+
+use std::cmp;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The PLPMTU that is assumed to always work, which is the minimum size of
+/// QUIC datagrams.
+pub const BASE_PLPMTU: usize = 1200;
+
+/// Number of consecutive losses of a probe size before it is considered
+/// unsupported by the path.
+const MAX_PROBES: usize = 3;
+
+/// The search is complete once the PLPMTU is closer than this to the largest
+/// size that might be supported.
+const SEARCH_GRANULARITY: usize = 20;
+
+/// Time after which the search is restarted to find out whether the path
+/// supports a larger PLPMTU.
+const PMTU_RAISE_TIMER: Duration = Duration::from_secs(600);
+
+/// Number of consecutive losses of packets larger than the base PLPMTU that
+/// trigger black hole detection.
+const BLACK_HOLE_THRESHOLD: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Discovery is disabled, only the base PLPMTU is used.
+    Disabled,
+
+    /// Probes are sent to find a larger PLPMTU.
+    Searching,
+
+    /// No more probes are sent until the raise timer expires.
+    SearchComplete,
+}
+
+pub struct Pmtud {
+    state: State,
+
+    /// The largest size that was validated on the path.
+    plpmtu: usize,
+
+    /// The largest size that can be used, based on the local configuration
+    /// and the peer's limits.
+    max_plpmtu: usize,
+
+    /// The largest size that might still be supported by the path.
+    search_high: usize,
+
+    probe_size: usize,
+
+    probe_count: usize,
+
+    probe_in_flight: bool,
+
+    raise_time: Option<Instant>,
+
+    black_hole_lost: usize,
+}
+
+impl Pmtud {
+    pub fn new(enabled: bool, max_plpmtu: usize) -> Self {
+        let mut pmtud = Pmtud {
+            state: if enabled {
+                State::Searching
+            } else {
+                State::Disabled
+            },
+
+            plpmtu: BASE_PLPMTU,
+
+            max_plpmtu,
+
+            search_high: max_plpmtu,
+
+            // The first probe optimistically tries the largest size.
+            probe_size: max_plpmtu,
+
+            probe_count: 0,
+
+            probe_in_flight: false,
+
+            raise_time: None,
+
+            black_hole_lost: 0,
+        };
+
+        pmtud.update_search(None);
+
+        pmtud
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.state != State::Disabled
+    }
+
+    /// Returns the largest validated size.
+    pub fn plpmtu(&self) -> usize {
+        self.plpmtu
+    }
+
+    /// Lowers the upper bound of the search, e.g. once the peer's transport
+    /// parameters are known.
+    pub fn set_max_plpmtu(&mut self, v: usize) {
+        self.max_plpmtu = cmp::max(cmp::min(self.max_plpmtu, v), BASE_PLPMTU);
+        self.plpmtu = cmp::min(self.plpmtu, self.max_plpmtu);
+
+        self.search_high = cmp::min(self.search_high, self.max_plpmtu);
+        self.probe_size = cmp::min(self.probe_size, self.search_high);
+
+        self.update_search(None);
+    }
+
+    /// Returns the size of the next probe, if one should be sent now.
+    pub fn probe_size(&mut self, now: Instant) -> Option<usize> {
+        if self.probe_in_flight {
+            return None;
+        }
+
+        match self.state {
+            State::Disabled => None,
+
+            State::Searching => Some(self.probe_size),
+
+            State::SearchComplete => {
+                match self.raise_time {
+                    Some(raise_time) if raise_time <= now => {
+                        self.raise_time = None;
+
+                        // Try the largest size again, in case the path changed.
+                        if self.max_plpmtu > self.plpmtu {
+                            self.search_high = self.max_plpmtu;
+                            self.probe_size = self.max_plpmtu;
+                            self.probe_count = 0;
+
+                            self.state = State::Searching;
+                        }
+                    },
+
+                    _ => (),
+                }
+
+                if self.state == State::Searching {
+                    Some(self.probe_size)
+                } else {
+                    None
+                }
+            },
+        }
+    }
+
+    pub fn on_probe_sent(&mut self) {
+        self.probe_in_flight = true;
+    }
+
+    /// Returns true if the PLPMTU was raised.
+    pub fn on_probe_acked(&mut self, size: usize, now: Instant) -> bool {
+        self.probe_in_flight = false;
+        self.probe_count = 0;
+        self.black_hole_lost = 0;
+
+        if size <= self.plpmtu {
+            return false;
+        }
+
+        self.plpmtu = size;
+
+        self.probe_size = (self.plpmtu + self.search_high) / 2;
+
+        self.update_search(Some(now));
+
+        true
+    }
+
+    pub fn on_probe_lost(&mut self, size: usize, now: Instant) {
+        self.probe_in_flight = false;
+        self.probe_count += 1;
+
+        if self.probe_count < MAX_PROBES {
+            return;
+        }
+
+        // The probe size is not supported, so look for a smaller one.
+        self.probe_count = 0;
+        self.search_high = cmp::min(self.search_high, size - 1);
+        self.probe_size = (self.plpmtu + self.search_high) / 2;
+
+        self.update_search(Some(now));
+    }
+
+    pub fn on_packet_acked(&mut self, size: usize) {
+        if size > BASE_PLPMTU {
+            self.black_hole_lost = 0;
+        }
+    }
+
+    /// Returns true if a black hole was detected, in which case the PLPMTU is
+    /// reset to the base value.
+    pub fn on_packet_lost(&mut self, size: usize, now: Instant) -> bool {
+        if self.state == State::Disabled || size <= BASE_PLPMTU {
+            return false;
+        }
+
+        self.black_hole_lost += 1;
+
+        if self.black_hole_lost < BLACK_HOLE_THRESHOLD {
+            return false;
+        }
+
+        // Packets of the current PLPMTU are not getting through anymore, so
+        // fall back to the base PLPMTU and search again below it.
+        self.search_high = self.plpmtu - 1;
+        self.plpmtu = BASE_PLPMTU;
+        self.probe_size = (self.plpmtu + self.search_high) / 2;
+        self.probe_count = 0;
+        self.black_hole_lost = 0;
+
+        self.state = State::Searching;
+        self.update_search(Some(now));
+
+        true
+    }
+
+    fn update_search(&mut self, now: Option<Instant>) {
+        if self.state != State::Searching {
+            return;
+        }
+
+        if self.search_high < self.plpmtu + SEARCH_GRANULARITY ||
+            self.probe_size <= self.plpmtu
+        {
+            self.state = State::SearchComplete;
+            self.raise_time = now.map(|now| now + PMTU_RAISE_TIMER);
+        }
+    }
+}
+
+impl std::fmt::Debug for Pmtud {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "state={:?} ", self.state)?;
+        write!(f, "plpmtu={} ", self.plpmtu)?;
+        write!(f, "search_high={} ", self.search_high)?;
+        write!(f, "probe_size={} ", self.probe_size)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled() {
+        let mut pmtud = Pmtud::new(false, 1500);
+
+        assert_eq!(pmtud.probe_size(Instant::now()), None);
+        assert_eq!(pmtud.plpmtu(), BASE_PLPMTU);
+    }
+
+    #[test]
+    fn max_probe_acked() {
+        let now = Instant::now();
+
+        let mut pmtud = Pmtud::new(true, 1500);
+
+        assert_eq!(pmtud.probe_size(now), Some(1500));
+        pmtud.on_probe_sent();
+
+        // Only one probe is in flight at a time.
+        assert_eq!(pmtud.probe_size(now), None);
+
+        assert!(pmtud.on_probe_acked(1500, now));
+        assert_eq!(pmtud.plpmtu(), 1500);
+
+        // Search is complete.
+        assert_eq!(pmtud.probe_size(now), None);
+
+        // Search is restarted once the raise timer expires.
+        let now = now + PMTU_RAISE_TIMER;
+        assert_eq!(pmtud.probe_size(now), None);
+
+        pmtud.set_max_plpmtu(1400);
+        assert_eq!(pmtud.probe_size(now), None);
+    }
+
+    #[test]
+    fn search() {
+        let now = Instant::now();
+
+        let mut pmtud = Pmtud::new(true, 1500);
+
+        let path_mtu = 1400;
+
+        for _ in 0..100 {
+            let size = match pmtud.probe_size(now) {
+                Some(v) => v,
+
+                None => break,
+            };
+
+            pmtud.on_probe_sent();
+
+            if size <= path_mtu {
+                pmtud.on_probe_acked(size, now);
+            } else {
+                pmtud.on_probe_lost(size, now);
+            }
+        }
+
+        assert!(pmtud.plpmtu() <= path_mtu);
+        assert!(pmtud.plpmtu() > path_mtu - SEARCH_GRANULARITY);
+        assert_eq!(pmtud.state, State::SearchComplete);
+    }
+
+    #[test]
+    fn raise_timer() {
+        let now = Instant::now();
+
+        let mut pmtud = Pmtud::new(true, 1500);
+
+        for _ in 0..MAX_PROBES {
+            assert_eq!(pmtud.probe_size(now), Some(1500));
+            pmtud.on_probe_sent();
+            pmtud.on_probe_lost(1500, now);
+        }
+
+        assert_eq!(pmtud.probe_size(now), Some(1349));
+        pmtud.on_probe_sent();
+        assert!(pmtud.on_probe_acked(1349, now));
+
+        assert_eq!(pmtud.probe_size(now), Some(1424));
+
+        // Pretend the rest of the search completed.
+        pmtud.on_probe_sent();
+        pmtud.on_probe_acked(1490, now);
+        assert_eq!(pmtud.probe_size(now), None);
+
+        // The largest size is tried again once the raise timer expires.
+        let now = now + PMTU_RAISE_TIMER;
+        assert_eq!(pmtud.probe_size(now), Some(1500));
+    }
+
+    #[test]
+    fn black_hole() {
+        let now = Instant::now();
+
+        let mut pmtud = Pmtud::new(true, 1500);
+
+        assert_eq!(pmtud.probe_size(now), Some(1500));
+        pmtud.on_probe_sent();
+        assert!(pmtud.on_probe_acked(1500, now));
+
+        // Losses of small packets are ignored.
+        for _ in 0..BLACK_HOLE_THRESHOLD {
+            assert!(!pmtud.on_packet_lost(1000, now));
+        }
+
+        // Acked large packets reset the detection.
+        assert!(!pmtud.on_packet_lost(1500, now));
+        assert!(!pmtud.on_packet_lost(1500, now));
+        pmtud.on_packet_acked(1500);
+
+        assert!(!pmtud.on_packet_lost(1500, now));
+        assert!(!pmtud.on_packet_lost(1500, now));
+        assert!(pmtud.on_packet_lost(1500, now));
+
+        assert_eq!(pmtud.plpmtu(), BASE_PLPMTU);
+        assert_eq!(pmtud.probe_size(now), Some(1349));
+    }
+}
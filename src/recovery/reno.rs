@@ -39,8 +39,9 @@ use crate::recovery::CongestionControlOps;
 use crate::recovery::Recovery;
 
 pub static RENO: CongestionControlOps = CongestionControlOps {
+    on_init,
     on_packet_sent,
-    on_packet_acked,
+    on_packets_acked,
     congestion_event,
     collapse_cwnd,
     checkpoint,
@@ -48,10 +49,37 @@ pub static RENO: CongestionControlOps = CongestionControlOps {
     has_custom_pacing,
 };
 
+/// Reno State Variables.
+#[derive(Debug, Default)]
+pub struct State {
+    // Reno state checkpoint preceding the last congestion event.
+    prior: PriorState,
+}
+
+/// Stores the Reno state from before the last congestion event.
+#[derive(Debug, Default)]
+struct PriorState {
+    congestion_window: usize,
+
+    ssthresh: usize,
+
+    bytes_acked_ca: usize,
+}
+
+fn on_init(_r: &mut Recovery) {}
+
 pub fn on_packet_sent(r: &mut Recovery, sent_bytes: usize, _now: Instant) {
     r.bytes_in_flight += sent_bytes;
 }
 
+fn on_packets_acked(
+    r: &mut Recovery, packets: &[Acked], epoch: packet::Epoch, now: Instant,
+) {
+    for pkt in packets {
+        on_packet_acked(r, pkt, epoch, now);
+    }
+}
+
 fn on_packet_acked(
     r: &mut Recovery, packet: &Acked, epoch: packet::Epoch, now: Instant,
 ) {
@@ -116,7 +144,8 @@ fn on_packet_acked(
 }
 
 fn congestion_event(
-    r: &mut Recovery, time_sent: Instant, epoch: packet::Epoch, now: Instant,
+    r: &mut Recovery, _lost_bytes: usize, time_sent: Instant,
+    epoch: packet::Epoch, now: Instant,
 ) {
     // Start a new congestion event if packet was sent after the
     // start of the previous congestion recovery period.
@@ -149,9 +178,17 @@ pub fn collapse_cwnd(r: &mut Recovery) {
     r.bytes_acked_ca = 0;
 }
 
-fn checkpoint(_r: &mut Recovery) {}
+fn checkpoint(r: &mut Recovery) {
+    r.reno_state.prior.congestion_window = r.congestion_window;
+    r.reno_state.prior.ssthresh = r.ssthresh;
+    r.reno_state.prior.bytes_acked_ca = r.bytes_acked_ca;
+}
 
-fn rollback(_r: &mut Recovery) {}
+fn rollback(r: &mut Recovery) {
+    r.congestion_window = r.reno_state.prior.congestion_window;
+    r.ssthresh = r.reno_state.prior.ssthresh;
+    r.bytes_acked_ca = r.reno_state.prior.bytes_acked_ca;
+}
 
 fn has_custom_pacing() -> bool {
     false
@@ -211,6 +248,8 @@ mod tests {
             recent_delivered_packet_sent_time: std::time::Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -255,6 +294,8 @@ mod tests {
             recent_delivered_packet_sent_time: std::time::Instant::now(),
             is_app_limited: false,
             has_data: false,
+            ecn_marked: false,
+            pmtud_probe: false,
         };
 
         // Send initcwnd full MSS packets to become no longer app limited
@@ -299,7 +340,12 @@ mod tests {
 
         let now = Instant::now();
 
-        r.congestion_event(now, packet::EPOCH_APPLICATION, now);
+        r.congestion_event(
+            r.max_datagram_size,
+            now,
+            packet::EPOCH_APPLICATION,
+            now,
+        );
 
         // In Reno, after congestion event, cwnd will be cut in half.
         assert_eq!(prev_cwnd / 2, r.cwnd());
@@ -318,7 +364,12 @@ mod tests {
         r.on_packet_sent_cc(20000, now);
 
         // Trigger congestion event to update ssthresh
-        r.congestion_event(now, packet::EPOCH_APPLICATION, now);
+        r.congestion_event(
+            r.max_datagram_size,
+            now,
+            packet::EPOCH_APPLICATION,
+            now,
+        );
 
         // After congestion event, cwnd will be reduced.
         let cur_cwnd =
@@ -88,18 +88,10 @@ pub struct StreamMap {
     /// The total number of unidirectional streams opened by the local endpoint.
     local_opened_streams_uni: u64,
 
-    /// Queue of stream IDs corresponding to streams that have buffered data
-    /// ready to be sent to the peer. This also implies that the stream has
-    /// enough flow control credits to send at least some of that data.
-    ///
-    /// Streams are grouped by their priority, where each urgency level has two
-    /// queues, one for non-incremental streams and one for incremental ones.
-    ///
-    /// Streams with lower urgency level are scheduled first, and within the
-    /// same urgency level Non-incremental streams are scheduled first, in the
-    /// order of their stream IDs, and incremental streams are scheduled in a
-    /// round-robin fashion after all non-incremental streams have been flushed.
-    flushable: BTreeMap<u8, (BinaryHeap<std::cmp::Reverse<u64>>, VecDeque<u64>)>,
+    /// Scheduler of stream IDs corresponding to streams that have buffered
+    /// data ready to be sent to the peer. This also implies that the stream
+    /// has enough flow control credits to send at least some of that data.
+    flushable: Box<dyn StreamScheduler>,
 
     /// Set of stream IDs corresponding to streams that have outstanding data
     /// to read. This is used to generate a `StreamIter` of streams without
@@ -263,8 +255,15 @@ impl StreamMap {
         Ok(stream)
     }
 
-    /// Pushes the stream ID to the back of the flushable streams queue with
-    /// the specified urgency.
+    /// Replaces the scheduler used to order flushable streams.
+    ///
+    /// This must be called before any stream is made flushable.
+    pub fn set_scheduler(&mut self, scheduler: Box<dyn StreamScheduler>) {
+        self.flushable = scheduler;
+    }
+
+    /// Pushes the stream ID to the flushable streams scheduler with the
+    /// specified priority.
     ///
     /// Note that the caller is responsible for checking that the specified
     /// stream ID was not in the queue already before calling this.
@@ -273,57 +272,33 @@ impl StreamMap {
     /// unfairly scheduled more often than other streams, and might also cause
     /// spurious cycles through the queue, so it should be avoided.
     pub fn push_flushable(&mut self, stream_id: u64, urgency: u8, incr: bool) {
-        // Push the element to the back of the queue corresponding to the given
-        // urgency. If the queue doesn't exist yet, create it first.
-        let queues = self
-            .flushable
-            .entry(urgency)
-            .or_insert_with(|| (BinaryHeap::new(), VecDeque::new()));
+        self.flushable.push(stream_id, urgency, incr);
+    }
 
-        if !incr {
-            // Non-incremental streams are scheduled in order of their stream ID.
-            queues.0.push(std::cmp::Reverse(stream_id))
-        } else {
-            // Incremental streams are scheduled in a round-robin fashion.
-            queues.1.push_back(stream_id)
-        };
+    /// Removes the stream ID from the flushable streams scheduler, given the
+    /// priority it was pushed with.
+    ///
+    /// This is used when the priority of a stream changes, so that it can be
+    /// pushed back with its new priority.
+    ///
+    /// If the stream ID was not in the queue, this does nothing.
+    pub fn remove_flushable(&mut self, stream_id: u64, urgency: u8, incr: bool) {
+        self.flushable.remove(stream_id, urgency, incr);
     }
 
-    /// Removes and returns the first stream ID from the flushable streams
-    /// queue with the specified urgency.
+    /// Removes and returns the next stream ID to be sent from the flushable
+    /// streams scheduler.
     ///
     /// Note that if the stream is still flushable after sending some of its
     /// outstanding data, it needs to be added back to the queue.
     pub fn pop_flushable(&mut self) -> Option<u64> {
-        // Remove the first element from the queue corresponding to the lowest
-        // urgency that has elements.
-        let (node, clear) =
-            if let Some((urgency, queues)) = self.flushable.iter_mut().next() {
-                let node = if !queues.0.is_empty() {
-                    queues.0.pop().map(|x| x.0)
-                } else {
-                    queues.1.pop_front()
-                };
-
-                let clear = if queues.0.is_empty() && queues.1.is_empty() {
-                    Some(*urgency)
-                } else {
-                    None
-                };
-
-                (node, clear)
-            } else {
-                (None, None)
-            };
-
-        // Remove the queue from the list of queues if it is now empty, so that
-        // the next time `pop_flushable()` is called the next queue with elements
-        // is used.
-        if let Some(urgency) = &clear {
-            self.flushable.remove(urgency);
-        }
+        self.flushable.pop()
+    }
 
-        node
+    /// Notifies the flushable streams scheduler that `len` bytes of data
+    /// were sent on the stream.
+    pub fn on_flushable_sent(&mut self, stream_id: u64, len: usize) {
+        self.flushable.on_stream_sent(stream_id, len);
     }
 
     /// Adds or removes the stream ID to/from the readable streams set.
@@ -417,6 +392,11 @@ impl StreamMap {
         self.local_max_streams_bidi = self.local_max_streams_bidi_next;
     }
 
+    /// Returns the current max_streams_bidi limit.
+    pub fn max_streams_bidi(&self) -> u64 {
+        self.local_max_streams_bidi
+    }
+
     /// Returns the new max_streams_bidi limit.
     pub fn max_streams_bidi_next(&mut self) -> u64 {
         self.local_max_streams_bidi_next
@@ -549,6 +529,149 @@ impl StreamMap {
     }
 }
 
+/// A stream scheduler.
+///
+/// The scheduler keeps track of the streams that have buffered data ready to
+/// be sent to the peer, and decides in which order they are sent. Every time
+/// a STREAM frame is to be sent, the next stream is popped from the
+/// scheduler, and if the stream still has data to send afterwards it is
+/// pushed back.
+///
+/// Applications can provide their own scheduling policy by implementing this
+/// trait and registering a factory for it with
+/// [`Config::set_stream_scheduler_factory()`]. By default the
+/// [`PriorityScheduler`] is used.
+///
+/// [`Config::set_stream_scheduler_factory()`]:
+///     struct.Config.html#method.set_stream_scheduler_factory
+/// [`PriorityScheduler`]: struct.PriorityScheduler.html
+pub trait StreamScheduler: Send + Sync {
+    /// Adds the stream to the set of streams ready to be sent, with the
+    /// priority set by the application.
+    ///
+    /// A stream is never pushed while it is already scheduled.
+    fn push(&mut self, stream_id: u64, urgency: u8, incremental: bool);
+
+    /// Removes the stream from the set of streams ready to be sent, given the
+    /// priority it was pushed with.
+    ///
+    /// This is called when the priority of a scheduled stream changes, in
+    /// which case the stream is pushed again with its new priority. If the
+    /// stream is not scheduled, this should do nothing.
+    fn remove(&mut self, stream_id: u64, urgency: u8, incremental: bool);
+
+    /// Removes and returns the next stream to be sent.
+    fn pop(&mut self) -> Option<u64>;
+
+    /// Returns true if there are no streams ready to be sent.
+    fn is_empty(&self) -> bool;
+
+    /// Called when `len` bytes of data were sent on the stream after it was
+    /// popped.
+    ///
+    /// The default implementation does nothing.
+    fn on_stream_sent(&mut self, _stream_id: u64, _len: usize) {}
+}
+
+impl Default for Box<dyn StreamScheduler> {
+    fn default() -> Self {
+        Box::new(PriorityScheduler::default())
+    }
+}
+
+/// Creates a stream scheduler for a new connection.
+pub type StreamSchedulerFactory =
+    dyn Fn() -> Box<dyn StreamScheduler> + Send + Sync;
+
+/// The default stream scheduler, based on the Extensible Prioritization
+/// Scheme for HTTP.
+///
+/// Streams are grouped by their priority, where each urgency level has two
+/// queues, one for non-incremental streams and one for incremental ones.
+///
+/// Streams with lower urgency level are scheduled first, and within the same
+/// urgency level non-incremental streams are scheduled first, in the order of
+/// their stream IDs, and incremental streams are scheduled in a round-robin
+/// fashion after all non-incremental streams have been flushed.
+#[derive(Default)]
+pub struct PriorityScheduler {
+    queues: BTreeMap<u8, (BinaryHeap<std::cmp::Reverse<u64>>, VecDeque<u64>)>,
+}
+
+impl StreamScheduler for PriorityScheduler {
+    fn push(&mut self, stream_id: u64, urgency: u8, incremental: bool) {
+        // Push the element to the back of the queue corresponding to the given
+        // urgency. If the queue doesn't exist yet, create it first.
+        let queues = self
+            .queues
+            .entry(urgency)
+            .or_insert_with(|| (BinaryHeap::new(), VecDeque::new()));
+
+        if !incremental {
+            // Non-incremental streams are scheduled in order of their stream ID.
+            queues.0.push(std::cmp::Reverse(stream_id))
+        } else {
+            // Incremental streams are scheduled in a round-robin fashion.
+            queues.1.push_back(stream_id)
+        };
+    }
+
+    fn remove(&mut self, stream_id: u64, urgency: u8, incremental: bool) {
+        let queues = match self.queues.get_mut(&urgency) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        if !incremental {
+            queues.0 = queues.0.drain().filter(|id| id.0 != stream_id).collect();
+        } else {
+            queues.1.retain(|id| *id != stream_id);
+        }
+
+        // Remove the queue if it is now empty, as `pop()` expects all queues
+        // to have elements.
+        if queues.0.is_empty() && queues.1.is_empty() {
+            self.queues.remove(&urgency);
+        }
+    }
+
+    fn pop(&mut self) -> Option<u64> {
+        // Remove the first element from the queue corresponding to the lowest
+        // urgency that has elements.
+        let (node, clear) =
+            if let Some((urgency, queues)) = self.queues.iter_mut().next() {
+                let node = if !queues.0.is_empty() {
+                    queues.0.pop().map(|x| x.0)
+                } else {
+                    queues.1.pop_front()
+                };
+
+                let clear = if queues.0.is_empty() && queues.1.is_empty() {
+                    Some(*urgency)
+                } else {
+                    None
+                };
+
+                (node, clear)
+            } else {
+                (None, None)
+            };
+
+        // Remove the queue from the list of queues if it is now empty, so that
+        // the next time `pop()` is called the next queue with elements is used.
+        if let Some(urgency) = &clear {
+            self.queues.remove(urgency);
+        }
+
+        node
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
 /// A QUIC stream.
 #[derive(Default)]
 pub struct Stream {
@@ -873,6 +996,59 @@ impl RecvBuf {
         Ok((len, self.is_fin()))
     }
 
+    /// Passes contiguous data from the receive buffer to the given function,
+    /// without copying it.
+    ///
+    /// The function is called with each chunk of data in order, along with a
+    /// flag indicating whether the chunk ends at the stream's final offset, and
+    /// returns the number of bytes it consumed. If it consumes less than the
+    /// full chunk, no further chunks are passed to it.
+    ///
+    /// On success the amount of data consumed, and a flag indicating if there
+    /// is no more data in the buffer, are returned as a tuple.
+    pub fn emit_chunks<F>(&mut self, mut f: F) -> Result<(usize, bool)>
+    where
+        F: FnMut(&[u8], bool) -> usize,
+    {
+        let mut len = 0;
+
+        if !self.ready() {
+            return Err(Error::Done);
+        }
+
+        // The stream was reset, so return the error code instead.
+        if let Some(e) = self.error {
+            return Err(Error::StreamReset(e));
+        }
+
+        while self.ready() {
+            let mut buf = match self.data.peek_mut() {
+                Some(v) => v,
+
+                None => break,
+            };
+
+            let fin = self.fin_off == Some(buf.max_off());
+
+            let buf_len = cmp::min(f(&buf, fin), buf.len());
+
+            self.off += buf_len as u64;
+
+            len += buf_len;
+
+            if buf_len < buf.len() {
+                buf.consume(buf_len);
+
+                // The function didn't consume the whole chunk, so end here.
+                break;
+            }
+
+            std::collections::binary_heap::PeekMut::pop(buf);
+        }
+
+        Ok((len, self.is_fin()))
+    }
+
     /// Resets the stream at the given offset.
     pub fn reset(&mut self, error_code: u64, final_size: u64) -> Result<usize> {
         // Stream's size is already known, forbid changing it.
@@ -1031,21 +1207,47 @@ impl SendBuf {
     /// The number of bytes that were actually stored in the buffer is returned
     /// (this may be lower than the size of the input buffer, in case of partial
     /// writes).
-    pub fn write(&mut self, mut data: &[u8], mut fin: bool) -> Result<usize> {
-        let max_off = self.off + data.len() as u64;
+    pub fn write(&mut self, data: &[u8], fin: bool) -> Result<usize> {
+        self.append(data.len(), fin, |range, off, fin| {
+            RangeBuf::from(&data[range], off, fin)
+        })
+    }
+
+    /// Inserts the given shared buffer at the end of the buffer, without
+    /// copying its data.
+    ///
+    /// The number of bytes that were actually stored in the buffer is returned
+    /// (this may be lower than the size of the input buffer, in case of partial
+    /// writes).
+    pub fn write_shared(&mut self, data: SharedBuf, fin: bool) -> Result<usize> {
+        self.append(data.len(), fin, |range, off, fin| {
+            RangeBuf::from_shared(data.slice(range), off, fin)
+        })
+    }
+
+    /// Appends `len` bytes of data, split into buffers created by `new_buf`
+    /// from a range of the input data, their stream offset and fin flag.
+    fn append<F>(
+        &mut self, len: usize, mut fin: bool, new_buf: F,
+    ) -> Result<usize>
+    where
+        F: Fn(std::ops::Range<usize>, u64, bool) -> RangeBuf,
+    {
+        let max_off = self.off + len as u64;
 
         // Get the stream send capacity. This will return an error if the stream
         // was stopped.
         let capacity = self.cap()?;
 
-        if data.len() > capacity {
-            // Truncate the input buffer according to the stream's capacity.
-            let len = capacity;
-            data = &data[..len];
-
+        // Truncate the input data according to the stream's capacity.
+        let len = if len > capacity {
             // We are not buffering the full input, so clear the fin flag.
             fin = false;
-        }
+
+            capacity
+        } else {
+            len
+        };
 
         if let Some(fin_off) = self.fin_off {
             // Can't write past final offset.
@@ -1065,31 +1267,29 @@ impl SendBuf {
 
         // Don't queue data that was already fully acked.
         if self.ack_off() >= max_off {
-            return Ok(data.len());
+            return Ok(len);
         }
 
         // We already recorded the final offset, so we can just discard the
         // empty buffer now.
-        if data.is_empty() {
-            return Ok(data.len());
+        if len == 0 {
+            return Ok(len);
         }
 
-        let mut len = 0;
-
         // Split the remaining input data into consistently-sized buffers to
         // avoid fragmentation.
-        for chunk in data.chunks(SEND_BUFFER_SIZE) {
-            len += chunk.len();
+        for start in (0..len).step_by(SEND_BUFFER_SIZE) {
+            let end = cmp::min(start + SEND_BUFFER_SIZE, len);
 
-            let fin = len == data.len() && fin;
+            let fin = end == len && fin;
 
-            let buf = RangeBuf::from(chunk, self.off, fin);
+            let buf = new_buf(start..end, self.off, fin);
 
             // The new data can simply be appended at the end of the send buffer.
             self.data.push_back(buf);
 
-            self.off += chunk.len() as u64;
-            self.len += chunk.len() as u64;
+            self.off += (end - start) as u64;
+            self.len += (end - start) as u64;
         }
 
         Ok(len)
@@ -1383,28 +1583,135 @@ impl SendBuf {
     }
 }
 
+/// A reference-counted buffer of immutable data.
+///
+/// Cloning or slicing a `SharedBuf` doesn't copy the data it holds, which lets
+/// applications hand their buffers over to a stream with
+/// [`stream_send_owned()`], instead of having them copied.
+///
+/// Any type that can be borrowed as a byte slice can be used as storage, such
+/// as `Vec<u8>`, `Box<[u8]>` or `bytes::Bytes`.
+///
+/// [`stream_send_owned()`]: struct.Connection.html#method.stream_send_owned
+#[derive(Clone)]
+pub struct SharedBuf {
+    data: Arc<dyn AsRef<[u8]> + Send + Sync>,
+
+    start: usize,
+
+    end: usize,
+}
+
+impl SharedBuf {
+    /// Creates a new `SharedBuf` that takes ownership of the given buffer.
+    pub fn new<B>(buf: B) -> SharedBuf
+    where
+        B: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let end = buf.as_ref().len();
+
+        SharedBuf {
+            data: Arc::new(buf),
+            start: 0,
+            end,
+        }
+    }
+
+    /// Returns the length of the buffer.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Returns true if the buffer has a length of zero bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a buffer holding the given range of data, without copying it.
+    ///
+    /// ## Panics:
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn slice<R>(&self, range: R) -> SharedBuf
+    where
+        R: std::ops::RangeBounds<usize>,
+    {
+        use std::ops::Bound;
+
+        let start = match range.start_bound() {
+            Bound::Included(&v) => v,
+            Bound::Excluded(&v) => v + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&v) => v + 1,
+            Bound::Excluded(&v) => v,
+            Bound::Unbounded => self.len(),
+        };
+
+        if start > end || end > self.len() {
+            panic!(
+                "range {}..{} out of bounds for buffer of length {}",
+                start,
+                end,
+                self.len()
+            );
+        }
+
+        SharedBuf {
+            data: self.data.clone(),
+            start: self.start + start,
+            end: self.start + end,
+        }
+    }
+}
+
+impl std::ops::Deref for SharedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.data).as_ref()[self.start..self.end]
+    }
+}
+
+impl From<Vec<u8>> for SharedBuf {
+    fn from(v: Vec<u8>) -> Self {
+        SharedBuf::new(v)
+    }
+}
+
+impl std::fmt::Debug for SharedBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SharedBuf(len={})", self.len())
+    }
+}
+
 /// Buffer holding data at a specific offset.
 ///
-/// The data is stored in a `Vec<u8>` in such a way that it can be shared
-/// between multiple `RangeBuf` objects.
+/// The data is stored in a reference-counted buffer in such a way that it can
+/// be shared between multiple `RangeBuf` objects, as well as with the
+/// application (see [`SharedBuf`]).
 ///
 /// Each `RangeBuf` will have its own view of that buffer, where the `start`
-/// value indicates the initial offset within the `Vec`, and `len` indicates the
-/// number of bytes, starting from `start` that are included.
+/// value indicates the initial offset within the buffer, and `len` indicates
+/// the number of bytes, starting from `start` that are included.
 ///
-/// In addition, `pos` indicates the current offset within the `Vec`, starting
-/// from the very beginning of the `Vec`.
+/// In addition, `pos` indicates the current offset within the buffer, starting
+/// from the very beginning of the buffer.
 ///
 /// Finally, `off` is the starting offset for the specific `RangeBuf` within the
 /// stream the buffer belongs to.
-#[derive(Clone, Debug, Default, Eq)]
+///
+/// [`SharedBuf`]: struct.SharedBuf.html
+#[derive(Clone)]
 pub struct RangeBuf {
     /// The internal buffer holding the data.
     ///
     /// To avoid neeless allocations when a RangeBuf is split, this field is
     /// reference-counted and can be shared between multiple RangeBuf objects,
     /// and sliced using the `start` and `len` values.
-    data: Arc<Vec<u8>>,
+    data: Arc<dyn AsRef<[u8]> + Send + Sync>,
 
     /// The initial offset within the internal buffer.
     start: usize,
@@ -1435,6 +1742,19 @@ impl RangeBuf {
         }
     }
 
+    /// Creates a new `RangeBuf` from the given shared buffer, without copying
+    /// its data.
+    pub fn from_shared(buf: SharedBuf, off: u64, fin: bool) -> RangeBuf {
+        RangeBuf {
+            data: buf.data,
+            start: buf.start,
+            pos: buf.start,
+            len: buf.end - buf.start,
+            off,
+            fin,
+        }
+    }
+
     /// Returns whether `self` holds the final offset in the stream.
     pub fn fin(&self) -> bool {
         self.fin
@@ -1495,10 +1815,22 @@ impl std::ops::Deref for RangeBuf {
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        &self.data[self.pos..self.start + self.len]
+        &(*self.data).as_ref()[self.pos..self.start + self.len]
     }
 }
 
+impl std::fmt::Debug for RangeBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RangeBuf")
+            .field("data", &&self[..])
+            .field("off", &self.off())
+            .field("fin", &self.fin)
+            .finish()
+    }
+}
+
+impl Eq for RangeBuf {}
+
 impl Ord for RangeBuf {
     fn cmp(&self, other: &RangeBuf) -> cmp::Ordering {
         // Invert ordering to implement min-heap.
@@ -2277,6 +2609,49 @@ mod tests {
         assert_eq!(send.len, 0);
     }
 
+    #[test]
+    fn recv_emit_chunks() {
+        let mut recv = RecvBuf::new(100);
+
+        assert!(recv.write(RangeBuf::from(b"world", 5, false)).is_ok());
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+        assert!(recv.write(RangeBuf::from(b"!", 10, true)).is_ok());
+
+        let mut out = Vec::new();
+
+        // Only consume part of the data.
+        let (len, fin) = recv
+            .emit_chunks(|chunk, fin| {
+                assert!(!fin);
+
+                let len = cmp::min(chunk.len(), 7 - out.len());
+                out.extend_from_slice(&chunk[..len]);
+                len
+            })
+            .unwrap();
+
+        assert_eq!(len, 7);
+        assert!(!fin);
+        assert_eq!(&out, b"hellowo");
+
+        let mut fins = Vec::new();
+
+        let (len, fin) = recv
+            .emit_chunks(|chunk, fin| {
+                out.extend_from_slice(chunk);
+                fins.push(fin);
+                chunk.len()
+            })
+            .unwrap();
+
+        assert_eq!(len, 4);
+        assert!(fin);
+        assert_eq!(&out, b"helloworld!");
+        assert_eq!(fins, vec![false, true]);
+
+        assert_eq!(recv.emit_chunks(|chunk, _| chunk.len()), Err(Error::Done));
+    }
+
     #[test]
     fn recv_flow_control() {
         let mut stream = Stream::new(16, 0, true, true);
@@ -2940,6 +3315,73 @@ mod tests {
         assert_eq!(&buf[..2], b"ro");
     }
 
+    #[test]
+    fn shared_buf_slice() {
+        let buf = SharedBuf::from(b"helloworld".to_vec());
+        assert_eq!(buf.len(), 10);
+
+        let world = buf.slice(5..);
+        assert_eq!(&world[..], b"world");
+
+        let or = world.slice(1..=2);
+        assert_eq!(&or[..], b"or");
+
+        assert!(buf.slice(3..3).is_empty());
+        assert_eq!(&buf.slice(..)[..], b"helloworld");
+
+        // Slices share the same data.
+        assert_eq!(or.as_ptr(), buf[6..].as_ptr());
+    }
+
+    #[test]
+    #[should_panic]
+    fn shared_buf_slice_out_of_bounds() {
+        let buf = SharedBuf::from(b"hello".to_vec());
+
+        buf.slice(3..6);
+    }
+
+    #[test]
+    fn send_emit_retransmit_shared() {
+        let mut buf = [0; 5];
+
+        let mut stream = Stream::new(0, 20, true, true);
+
+        let data = SharedBuf::from(b"helloworldolleh".to_vec());
+
+        assert_eq!(stream.send.write_shared(data.clone(), false), Ok(15));
+
+        // The shared buffer is truncated to the stream's capacity.
+        assert_eq!(stream.send.write_shared(data.clone(), true), Ok(5));
+        assert_eq!(stream.send.data.len(), 4);
+
+        // The data is not copied.
+        assert_eq!(stream.send.data[1].as_ptr(), data[5..].as_ptr());
+        assert_eq!(stream.send.data[3].as_ptr(), data.as_ptr());
+
+        assert!(stream.send.ready());
+        assert_eq!(stream.send.emit(&mut buf[..4]), Ok((4, false)));
+        assert_eq!(&buf[..4], b"hell");
+
+        assert_eq!(stream.send.emit(&mut buf[..5]), Ok((5, false)));
+        assert_eq!(&buf[..5], b"oworl");
+
+        stream.send.retransmit(3, 4);
+        assert_eq!(stream.send.off_front(), 3);
+
+        assert_eq!(stream.send.emit(&mut buf[..4]), Ok((4, false)));
+        assert_eq!(&buf[..4], b"lowo");
+
+        // Retransmitted data still refers to the application's buffer.
+        assert_eq!(stream.send.data[1].as_ptr(), data[7..].as_ptr());
+
+        stream.send.ack_and_drop(0, 20);
+        assert!(stream.send.data.is_empty());
+
+        // Only the application holds the buffer now.
+        assert_eq!(Arc::strong_count(&data.data), 1);
+    }
+
     #[test]
     fn rangebuf_split_off() {
         let mut buf = RangeBuf::from(b"helloworld", 5, true);
@@ -3054,4 +3496,30 @@ mod tests {
 
         assert_eq!(&new_new_buf[..], b"");
     }
+
+    #[test]
+    fn remove_flushable() {
+        let mut streams = StreamMap::new(10, 10);
+
+        streams.push_flushable(0, 10, false);
+        streams.push_flushable(4, 10, false);
+        streams.push_flushable(8, 10, true);
+        streams.push_flushable(12, 10, true);
+        streams.push_flushable(16, 20, false);
+
+        streams.remove_flushable(4, 10, false);
+        streams.remove_flushable(8, 10, true);
+
+        // Removing a stream from the wrong queue does nothing.
+        streams.remove_flushable(0, 10, true);
+        streams.remove_flushable(12, 20, true);
+
+        assert_eq!(streams.pop_flushable(), Some(0));
+        assert_eq!(streams.pop_flushable(), Some(12));
+
+        // The empty queue is removed along with its last stream.
+        streams.remove_flushable(16, 20, false);
+        assert!(!streams.has_flushable());
+        assert_eq!(streams.pop_flushable(), None);
+    }
 }
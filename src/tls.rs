@@ -91,6 +91,13 @@ struct STACK_OF(c_void);
 #[repr(transparent)]
 struct CRYPTO_BUFFER(c_void);
 
+#[repr(C)]
+#[allow(non_camel_case_types)]
+struct SSL_CLIENT_HELLO {
+    ssl: *mut SSL,
+    // The remaining fields are only accessed through BoringSSL functions.
+}
+
 #[repr(C)]
 #[allow(non_camel_case_types)]
 struct SSL_QUIC_METHOD {
@@ -148,6 +155,8 @@ impl Context {
 
             ctx.set_session_callback();
 
+            ctx.set_select_certificate_callback();
+
             ctx.load_ca_certs()?;
 
             Ok(ctx)
@@ -266,6 +275,12 @@ impl Context {
         };
     }
 
+    fn set_select_certificate_callback(&mut self) {
+        unsafe {
+            SSL_CTX_set_select_certificate_cb(self.as_ptr(), select_certificate);
+        }
+    }
+
     pub fn set_verify(&mut self, verify: bool) {
         let mode = if verify {
             0x01 // SSL_VERIFY_PEER
@@ -668,7 +683,7 @@ extern fn set_read_secret(
     if level != crypto::Level::ZeroRTT || conn.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let open = match crypto::Open::from_secret(aead, &secret) {
+        let open = match crypto::Open::from_secret(aead, conn.version, &secret) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -718,7 +733,7 @@ extern fn set_write_secret(
     if level != crypto::Level::ZeroRTT || !conn.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let seal = match crypto::Seal::from_secret(aead, &secret) {
+        let seal = match crypto::Seal::from_secret(aead, conn.version, &secret) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -865,6 +880,70 @@ extern fn select_alpn(
     3 // SSL_TLSEXT_ERR_NOACK
 }
 
+extern fn select_certificate(client_hello: *const SSL_CLIENT_HELLO) -> c_int {
+    const QUIC_TRANSPORT_PARAMS_EXT: u16 = 0x0039;
+    const EARLY_DATA_EXT: u16 = 0x002a;
+
+    // ssl_select_cert_success
+    const SUCCESS: c_int = 1;
+
+    let ssl = unsafe { (*client_hello).ssl };
+
+    let conn =
+        match get_ex_data_from_ptr::<Connection>(ssl, *QUICHE_EX_DATA_INDEX) {
+            Some(v) => v,
+
+            None => return SUCCESS,
+        };
+
+    if !conn.is_server {
+        return SUCCESS;
+    }
+
+    let mut ptr: *const u8 = ptr::null();
+    let mut len: usize = 0;
+
+    // Early data would be sent with the client's original version, so don't
+    // switch to a different one.
+    if unsafe {
+        SSL_early_callback_ctx_extension_get(
+            client_hello,
+            EARLY_DATA_EXT,
+            &mut ptr,
+            &mut len,
+        )
+    } == 1
+    {
+        return SUCCESS;
+    }
+
+    if unsafe {
+        SSL_early_callback_ctx_extension_get(
+            client_hello,
+            QUIC_TRANSPORT_PARAMS_EXT,
+            &mut ptr,
+            &mut len,
+        )
+    } != 1
+    {
+        return SUCCESS;
+    }
+
+    let raw_params = unsafe { slice::from_raw_parts(ptr, len) };
+
+    let handshake = Handshake(ssl);
+
+    // Errors in the client's transport parameters are reported once the
+    // handshake makes progress.
+    conn.negotiate_compatible_version(raw_params, &handshake)
+        .ok();
+
+    // Prevent handshake from being freed, as we still need it.
+    std::mem::forget(handshake);
+
+    SUCCESS
+}
+
 #[no_mangle]
 extern fn new_session(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int {
     let conn =
@@ -1074,6 +1153,16 @@ extern {
         cb: extern fn(ssl: *mut SSL, session: *mut SSL_SESSION) -> c_int,
     );
 
+    fn SSL_CTX_set_select_certificate_cb(
+        ctx: *mut SSL_CTX,
+        cb: extern fn(client_hello: *const SSL_CLIENT_HELLO) -> c_int,
+    );
+
+    fn SSL_early_callback_ctx_extension_get(
+        client_hello: *const SSL_CLIENT_HELLO, extension_type: u16,
+        out_data: *mut *const u8, out_len: *mut usize,
+    ) -> c_int;
+
     // SSL
     fn SSL_get_ex_new_index(
         argl: c_long, argp: *const c_void, unused: *const c_void,
@@ -0,0 +1,338 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::sync::Arc;
+use std::time;
+
+use std::net::SocketAddr;
+
+use ring::aead;
+use ring::hkdf;
+
+use crate::Error;
+use crate::Result;
+
+use crate::octets;
+use crate::rand;
+
+use crate::packet::ConnectionId;
+
+const TOKEN_TYPE_RETRY: u8 = 0x00;
+const TOKEN_TYPE_NEW_TOKEN: u8 = 0x01;
+
+const TOKEN_KEY_LABEL: &[u8] = b"quiche address token";
+
+// Retry tokens are only used once, right after they are issued, so they
+// don't need to last long.
+const RETRY_TOKEN_LIFETIME: time::Duration = time::Duration::from_secs(10);
+
+const NEW_TOKEN_LIFETIME: time::Duration =
+    time::Duration::from_secs(24 * 60 * 60);
+
+/// The result of a successful address validation token validation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddressToken {
+    /// A token sent in a Retry packet, along with the original destination
+    /// connection ID of the client's first Initial packet.
+    Retry(ConnectionId<'static>),
+
+    /// A token sent in a NEW_TOKEN frame on a previous connection.
+    NewToken,
+}
+
+/// Key used to seal and open address validation tokens.
+///
+/// Tokens are authenticated and encrypted with AES-256-GCM, and are bound to
+/// the client's address. Retry tokens are bound to the full address, and
+/// expire after a few seconds, while tokens sent in NEW_TOKEN frames are only
+/// bound to the IP address, as clients usually use a different port for new
+/// connections, and expire after a day.
+///
+/// The key is cheap to clone, so that it can be shared between the server's
+/// [`Config`] and its packet processing loop.
+///
+/// [`Config`]: struct.Config.html
+#[derive(Clone)]
+pub struct AddressTokenKey {
+    key: Arc<aead::LessSafeKey>,
+}
+
+impl AddressTokenKey {
+    /// Creates a new key derived from the given secret.
+    ///
+    /// The same secret should be used by all the servers that might receive
+    /// tokens issued by each other, and must be kept private.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// let key = quiche::AddressTokenKey::new(&[0xba; 32]);
+    /// ```
+    pub fn new(secret: &[u8]) -> AddressTokenKey {
+        let salt = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]);
+        let prk = salt.extract(secret);
+
+        let okm = prk
+            .expand(&[TOKEN_KEY_LABEL], &aead::AES_256_GCM)
+            .expect("invalid key length");
+
+        AddressTokenKey {
+            key: Arc::new(aead::LessSafeKey::new(aead::UnboundKey::from(okm))),
+        }
+    }
+
+    /// Generates a token to be sent in a Retry packet to the client with the
+    /// given address.
+    ///
+    /// The `odcid` parameter is the destination connection ID of the client's
+    /// Initial packet, which is returned when the token is validated.
+    pub fn retry_token(
+        &self, odcid: &ConnectionId, addr: &SocketAddr,
+    ) -> Result<Vec<u8>> {
+        self.seal(TOKEN_TYPE_RETRY, odcid, addr, RETRY_TOKEN_LIFETIME)
+    }
+
+    /// Generates a token to be sent in a NEW_TOKEN frame to the client with
+    /// the given address.
+    pub fn new_token(&self, addr: &SocketAddr) -> Result<Vec<u8>> {
+        self.seal(TOKEN_TYPE_NEW_TOKEN, &[], addr, NEW_TOKEN_LIFETIME)
+    }
+
+    /// Validates a token received from the client with the given address.
+    ///
+    /// Returns [`InvalidToken`] if the token wasn't issued with this key for
+    /// the client's address, or if it expired.
+    ///
+    /// [`InvalidToken`]: enum.Error.html#variant.InvalidToken
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let key = quiche::AddressTokenKey::new(&[0xba; 32]);
+    /// let (len, src) = socket.recv_from(&mut buf).unwrap();
+    ///
+    /// let hdr = quiche::Header::from_slice(&mut buf[..len], 16)?;
+    /// let token = hdr.token.as_ref().unwrap();
+    ///
+    /// let odcid = match key.validate(token, &src) {
+    ///     Ok(quiche::AddressToken::Retry(odcid)) => Some(odcid),
+    ///
+    ///     // The connection validates NEW_TOKEN tokens itself.
+    ///     Ok(quiche::AddressToken::NewToken) => None,
+    ///
+    ///     Err(_) => return Ok(()),
+    /// };
+    ///
+    /// let local = socket.local_addr().unwrap();
+    /// let conn = quiche::accept(&scid, odcid.as_ref(), local, src, &mut config)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn validate(
+        &self, token: &[u8], addr: &SocketAddr,
+    ) -> Result<AddressToken> {
+        if token.len() < 1 + aead::NONCE_LEN + aead::AES_256_GCM.tag_len() {
+            return Err(Error::InvalidToken);
+        }
+
+        let ty = token[0];
+
+        if ty != TOKEN_TYPE_RETRY && ty != TOKEN_TYPE_NEW_TOKEN {
+            return Err(Error::InvalidToken);
+        }
+
+        let mut nonce = [0; aead::NONCE_LEN];
+        nonce.copy_from_slice(&token[1..1 + aead::NONCE_LEN]);
+        let nonce = aead::Nonce::assume_unique_for_key(nonce);
+
+        let aad = token_aad(ty, addr);
+
+        let mut ciphertext = token[1 + aead::NONCE_LEN..].to_vec();
+
+        let plaintext = self
+            .key
+            .open_in_place(nonce, aead::Aad::from(&aad), &mut ciphertext)
+            .map_err(|_| Error::InvalidToken)?;
+
+        let mut b = octets::Octets::with_slice(plaintext);
+
+        let expiry = b.get_u64().map_err(|_| Error::InvalidToken)?;
+
+        if now_as_secs() > expiry {
+            return Err(Error::InvalidToken);
+        }
+
+        match ty {
+            TOKEN_TYPE_RETRY => {
+                let odcid =
+                    b.get_bytes(b.cap()).map_err(|_| Error::InvalidToken)?;
+
+                Ok(AddressToken::Retry(odcid.to_vec().into()))
+            },
+
+            _ => Ok(AddressToken::NewToken),
+        }
+    }
+
+    fn seal(
+        &self, ty: u8, odcid: &[u8], addr: &SocketAddr, lifetime: time::Duration,
+    ) -> Result<Vec<u8>> {
+        let mut nonce = [0; aead::NONCE_LEN];
+        rand::rand_bytes(&mut nonce);
+
+        let mut token = Vec::with_capacity(
+            1 + aead::NONCE_LEN + 8 + odcid.len() + aead::AES_256_GCM.tag_len(),
+        );
+
+        token.push(ty);
+        token.extend_from_slice(&nonce);
+
+        let mut plaintext = Vec::with_capacity(8 + odcid.len());
+        plaintext.extend_from_slice(
+            &(now_as_secs() + lifetime.as_secs()).to_be_bytes(),
+        );
+        plaintext.extend_from_slice(odcid);
+
+        let aad = token_aad(ty, addr);
+
+        self.key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(&aad),
+                &mut plaintext,
+            )
+            .map_err(|_| Error::CryptoFail)?;
+
+        token.extend_from_slice(&plaintext);
+
+        Ok(token)
+    }
+}
+
+impl std::fmt::Debug for AddressTokenKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "AddressTokenKey")
+    }
+}
+
+/// Returns the additional data authenticated along with a token, which binds
+/// it to the client's address.
+fn token_aad(ty: u8, addr: &SocketAddr) -> Vec<u8> {
+    let mut aad = vec![ty];
+
+    match addr {
+        SocketAddr::V4(a) => aad.extend_from_slice(&a.ip().octets()),
+
+        SocketAddr::V6(a) => aad.extend_from_slice(&a.ip().octets()),
+    }
+
+    // NEW_TOKEN tokens are used by future connections, which are likely to
+    // use a different port.
+    if ty == TOKEN_TYPE_RETRY {
+        aad.extend_from_slice(&addr.port().to_be_bytes());
+    }
+
+    aad
+}
+
+fn now_as_secs() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_token() {
+        let key = AddressTokenKey::new(&[0xba; 32]);
+
+        let odcid = ConnectionId::from_ref(&[0xa1; 16]);
+        let addr = "127.0.0.1:1234".parse().unwrap();
+
+        let token = key.retry_token(&odcid, &addr).unwrap();
+
+        assert_eq!(
+            key.validate(&token, &addr),
+            Ok(AddressToken::Retry(odcid.into_owned()))
+        );
+
+        // Retry tokens are bound to the client's port.
+        let other_port = "127.0.0.1:5678".parse().unwrap();
+        assert_eq!(key.validate(&token, &other_port), Err(Error::InvalidToken));
+
+        let other_addr = "127.0.0.2:1234".parse().unwrap();
+        assert_eq!(key.validate(&token, &other_addr), Err(Error::InvalidToken));
+    }
+
+    #[test]
+    fn new_token() {
+        let key = AddressTokenKey::new(&[0xba; 32]);
+
+        let addr = "[::1]:1234".parse().unwrap();
+
+        let token = key.new_token(&addr).unwrap();
+
+        assert_eq!(key.validate(&token, &addr), Ok(AddressToken::NewToken));
+
+        // NEW_TOKEN tokens can be used from a different port.
+        let other_port = "[::1]:5678".parse().unwrap();
+        assert_eq!(
+            key.validate(&token, &other_port),
+            Ok(AddressToken::NewToken)
+        );
+
+        let other_addr = "[::2]:1234".parse().unwrap();
+        assert_eq!(key.validate(&token, &other_addr), Err(Error::InvalidToken));
+    }
+
+    #[test]
+    fn invalid_token() {
+        let key = AddressTokenKey::new(&[0xba; 32]);
+        let other_key = AddressTokenKey::new(&[0xbb; 32]);
+
+        let addr = "127.0.0.1:1234".parse().unwrap();
+
+        let mut token = key.new_token(&addr).unwrap();
+
+        assert_eq!(other_key.validate(&token, &addr), Err(Error::InvalidToken));
+
+        assert_eq!(key.validate(&token[..20], &addr), Err(Error::InvalidToken));
+
+        // Tampering with the token type is detected.
+        token[0] = TOKEN_TYPE_RETRY;
+        assert_eq!(key.validate(&token, &addr), Err(Error::InvalidToken));
+
+        token[0] = 0xff;
+        assert_eq!(key.validate(&token, &addr), Err(Error::InvalidToken));
+    }
+}
@@ -342,6 +342,7 @@ Options:
   --dgram-data DATA           Data to send for certain types of DATAGRAM application protocol [default: brrr].
   --cc-algorithm NAME         Specify which congestion control algorithm to use [default: cubic].
   --disable-hystart           Disable HyStart++.
+  --enable-gso                Send packets in batches using UDP GSO, if supported.
   -h --help                   Show this screen.
 ";
 
@@ -353,6 +354,7 @@ pub struct ServerArgs {
     pub index: String,
     pub cert: String,
     pub key: String,
+    pub enable_gso: bool,
 }
 
 impl Args for ServerArgs {
@@ -365,6 +367,7 @@ impl Args for ServerArgs {
         let index = args.get_str("--index").to_string();
         let cert = args.get_str("--cert").to_string();
         let key = args.get_str("--key").to_string();
+        let enable_gso = args.get_bool("--enable-gso");
 
         ServerArgs {
             listen,
@@ -373,6 +376,7 @@ impl Args for ServerArgs {
             index,
             cert,
             key,
+            enable_gso,
         }
     }
 }
@@ -43,13 +43,17 @@ use quiche_apps::args::*;
 
 use quiche_apps::common::*;
 
+use quiche_apps::sendto::*;
+
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
 const MAX_SEND_BURST_LIMIT: usize = MAX_DATAGRAM_SIZE * 10;
 
+const MAX_GSO_SEGMENTS: usize = MAX_SEND_BURST_LIMIT / MAX_DATAGRAM_SIZE;
+
 fn main() {
     let mut buf = [0; 65535];
-    let mut out = [0; MAX_DATAGRAM_SIZE];
+    let mut out = [0; MAX_SEND_BURST_LIMIT];
 
     env_logger::builder()
         .default_format_timestamp_nanos(true)
@@ -70,6 +74,21 @@ fn main() {
     info!("listening on {:}", socket.local_addr().unwrap());
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
+    let enable_gso = if args.enable_gso {
+        let supported = detect_gso(&socket);
+
+        if !supported {
+            warn!("UDP GSO is not supported, sending packets one by one");
+        }
+
+        supported
+    } else {
+        false
+    };
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -135,6 +154,13 @@ fn main() {
     let conn_id_seed =
         ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
 
+    let mut token_secret = [0; 32];
+    rng.fill(&mut token_secret).unwrap();
+
+    let token_key = quiche::AddressTokenKey::new(&token_secret);
+
+    config.set_address_token_key(token_key.clone());
+
     let mut clients = ClientMap::new();
 
     let mut pkt_count = 0;
@@ -260,7 +286,8 @@ fn main() {
                         warn!("Doing stateless retry");
 
                         let scid = quiche::ConnectionId::from_ref(&scid);
-                        let new_token = mint_token(&hdr, &from);
+                        let new_token =
+                            token_key.retry_token(&hdr.dcid, &from).unwrap();
 
                         let len = quiche::retry(
                             &hdr.scid,
@@ -285,23 +312,32 @@ fn main() {
                         continue 'read;
                     }
 
-                    odcid = validate_token(&from, token);
+                    match token_key.validate(token, &from) {
+                        Ok(quiche::AddressToken::Retry(id)) => {
+                            if scid.len() != hdr.dcid.len() {
+                                error!("Invalid destination connection ID");
+                                continue 'read;
+                            }
 
-                    // The token was not valid, meaning the retry failed, so
-                    // drop the packet.
-                    if odcid.is_none() {
-                        error!("Invalid address validation token");
-                        continue;
-                    }
+                            // Reuse the source connection ID we sent in the
+                            // Retry packet, instead of changing it again.
+                            scid.copy_from_slice(&hdr.dcid);
 
-                    if scid.len() != hdr.dcid.len() {
-                        error!("Invalid destination connection ID");
-                        continue 'read;
-                    }
+                            odcid = Some(id);
+                        },
 
-                    // Reuse the source connection ID we sent in the Retry
-                    // packet, instead of changing it again.
-                    scid.copy_from_slice(&hdr.dcid);
+                        // The token was sent in a NEW_TOKEN frame on a
+                        // previous connection, and is validated by the
+                        // connection itself.
+                        Ok(quiche::AddressToken::NewToken) => (),
+
+                        // The token was not valid, meaning the retry failed,
+                        // so drop the packet.
+                        Err(_) => {
+                            error!("Invalid address validation token");
+                            continue 'read;
+                        },
+                    }
                 }
 
                 let scid = quiche::ConnectionId::from_vec(scid.to_vec());
@@ -309,9 +345,14 @@ fn main() {
                 debug!("New connection: dcid={:?} scid={:?}", hdr.dcid, scid);
 
                 #[allow(unused_mut)]
-                let mut conn =
-                    quiche::accept(&scid, odcid.as_ref(), from, &mut config)
-                        .unwrap();
+                let mut conn = quiche::accept(
+                    &scid,
+                    odcid.as_ref(),
+                    local_addr,
+                    from,
+                    &mut config,
+                )
+                .unwrap();
 
                 if let Some(keylog) = &mut keylog {
                     if let Ok(keylog) = keylog.try_clone() {
@@ -341,6 +382,7 @@ fn main() {
                     partial_responses: HashMap::new(),
                     siduck_conn: None,
                     app_proto_selected: false,
+                    new_token_sent: false,
                     bytes_sent: 0,
                 };
 
@@ -355,7 +397,11 @@ fn main() {
                 }
             };
 
-            let recv_info = quiche::RecvInfo { from };
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
             // Process potentially coalesced packets.
             let read = match client.conn.recv(pkt_buf, recv_info) {
@@ -417,6 +463,15 @@ fn main() {
                 }
             }
 
+            // Allow the client to skip address validation when it connects
+            // again. The handshake is confirmed as soon as it completes on the
+            // server.
+            if !client.new_token_sent && client.conn.is_established() {
+                client.conn.send_new_token().ok();
+
+                client.new_token_sent = true;
+            }
+
             if client.http_conn.is_some() {
                 let conn = &mut client.conn;
                 let http_conn = client.http_conn.as_mut().unwrap();
@@ -459,7 +514,16 @@ fn main() {
         continue_write = false;
         for client in clients.values_mut() {
             loop {
-                let (write, send_info) = match client.conn.send(&mut out) {
+                let res = if enable_gso {
+                    client.conn.send_batch(&mut out, MAX_GSO_SEGMENTS)
+                } else {
+                    client
+                        .conn
+                        .send(&mut out[..MAX_DATAGRAM_SIZE])
+                        .map(|(write, send_info)| (write, write, send_info))
+                };
+
+                let (write, segment_size, send_info) = match res {
                     Ok(v) => v,
 
                     Err(quiche::Error::Done) => {
@@ -475,8 +539,13 @@ fn main() {
                     },
                 };
 
-                // TODO: coalesce packets.
-                if let Err(e) = socket.send_to(&out[..write], &send_info.to) {
+                if let Err(e) = send_to(
+                    &socket,
+                    &out[..write],
+                    &send_info.to,
+                    segment_size,
+                    enable_gso,
+                ) {
                     if e.kind() == std::io::ErrorKind::WouldBlock {
                         trace!("send() would block");
                         break;
@@ -519,59 +588,3 @@ fn main() {
         });
     }
 }
-
-/// Generate a stateless retry token.
-///
-/// The token includes the static string `"quiche"` followed by the IP address
-/// of the client and by the original destination connection ID generated by the
-/// client.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn mint_token(hdr: &quiche::Header, src: &net::SocketAddr) -> Vec<u8> {
-    let mut token = Vec::new();
-
-    token.extend_from_slice(b"quiche");
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    token.extend_from_slice(&addr);
-    token.extend_from_slice(&hdr.dcid);
-
-    token
-}
-
-/// Validates a stateless retry token.
-///
-/// This checks that the ticket includes the `"quiche"` static string, and that
-/// the client IP address matches the address stored in the ticket.
-///
-/// Note that this function is only an example and doesn't do any cryptographic
-/// authenticate of the token. *It should not be used in production system*.
-fn validate_token<'a>(
-    src: &net::SocketAddr, token: &'a [u8],
-) -> Option<quiche::ConnectionId<'a>> {
-    if token.len() < 6 {
-        return None;
-    }
-
-    if &token[..6] != b"quiche" {
-        return None;
-    }
-
-    let token = &token[6..];
-
-    let addr = match src.ip() {
-        std::net::IpAddr::V4(a) => a.octets().to_vec(),
-        std::net::IpAddr::V6(a) => a.octets().to_vec(),
-    };
-
-    if token.len() < addr.len() || &token[..addr.len()] != addr.as_slice() {
-        return None;
-    }
-
-    Some(quiche::ConnectionId::from_ref(&token[addr.len()..]))
-}
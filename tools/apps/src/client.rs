@@ -83,6 +83,9 @@ pub fn connect(
     let socket = std::net::UdpSocket::bind(bind_addr).unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -155,9 +158,14 @@ pub fn connect(
     let scid = quiche::ConnectionId::from_ref(&scid);
 
     // Create a QUIC connection and initiate handshake.
-    let mut conn =
-        quiche::connect(connect_url.domain(), &scid, peer_addr, &mut config)
-            .unwrap();
+    let mut conn = quiche::connect(
+        connect_url.domain(),
+        &scid,
+        local_addr,
+        peer_addr,
+        &mut config,
+    )
+    .unwrap();
 
     if let Some(keylog) = &mut keylog {
         if let Ok(keylog) = keylog.try_clone() {
@@ -188,9 +196,7 @@ pub fn connect(
 
     info!(
         "connecting to {:} from {:} with scid {:?}",
-        peer_addr,
-        socket.local_addr().unwrap(),
-        scid,
+        peer_addr, local_addr, scid,
     );
 
     let (write, send_info) = conn.send(&mut out).expect("initial send failed");
@@ -260,7 +266,11 @@ pub fn connect(
 
             pkt_count += 1;
 
-            let recv_info = quiche::RecvInfo { from };
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
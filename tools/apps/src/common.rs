@@ -91,6 +91,8 @@ pub struct Client {
 
     pub app_proto_selected: bool,
 
+    pub new_token_sent: bool,
+
     pub partial_requests: std::collections::HashMap<u64, PartialRequest>,
 
     pub partial_responses: std::collections::HashMap<u64, PartialResponse>,
@@ -1230,6 +1232,17 @@ impl HttpConn for Http3Conn {
                     );
                 },
 
+                Ok((_stream_id, quiche::h3::Event::PushPromise { .. })) => (),
+
+                Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                Ok((_, quiche::h3::Event::PriorityUpdate(_))) => (),
+
+                Ok((_, quiche::h3::Event::WebTransportStream { .. })) => (),
+
+                Ok((_, quiche::h3::Event::WebTransportSessionClosed { .. })) =>
+                    (),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
@@ -1384,6 +1397,20 @@ impl HttpConn for Http3Conn {
                         .send_goaway(conn, self.largest_processed_request)?;
                 },
 
+                Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                Ok((stream_id, quiche::h3::Event::PriorityUpdate(priority))) => {
+                    self.h3_conn
+                        .set_stream_priority(conn, stream_id, priority)?;
+                },
+
+                Ok((_, quiche::h3::Event::WebTransportStream { .. })) => (),
+
+                Ok((_, quiche::h3::Event::WebTransportSessionClosed { .. })) =>
+                    (),
+
                 Err(quiche::h3::Error::Done) => {
                     break;
                 },
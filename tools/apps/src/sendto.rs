@@ -0,0 +1,181 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! UDP send helpers.
+//!
+//! This module provides functions to send batches of QUIC packets using UDP
+//! Generic Segmentation Offload (GSO) when supported by the platform.
+
+use std::io;
+use std::net;
+
+/// Returns whether UDP GSO can be used on the given socket.
+#[cfg(target_os = "linux")]
+pub fn detect_gso(socket: &mio::net::UdpSocket) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let mut val: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_SEGMENT,
+            &mut val as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    rc == 0
+}
+
+/// Returns whether UDP GSO can be used on the given socket.
+#[cfg(not(target_os = "linux"))]
+pub fn detect_gso(_socket: &mio::net::UdpSocket) -> bool {
+    false
+}
+
+/// Sends a batch of packets to the given address.
+///
+/// All packets in `buf` are `segment_size` bytes long, except for the last one
+/// which can be shorter. When `enable_gso` is true the whole batch is handed
+/// to the kernel at once, otherwise `buf` must contain a single packet.
+pub fn send_to(
+    socket: &mio::net::UdpSocket, buf: &[u8], to: &net::SocketAddr,
+    segment_size: usize, enable_gso: bool,
+) -> io::Result<usize> {
+    if enable_gso {
+        return send_to_gso(socket, buf, to, segment_size);
+    }
+
+    socket.send_to(buf, to)
+}
+
+#[cfg(target_os = "linux")]
+fn send_to_gso(
+    socket: &mio::net::UdpSocket, buf: &[u8], to: &net::SocketAddr,
+    segment_size: usize,
+) -> io::Result<usize> {
+    use std::mem::size_of;
+    use std::os::unix::io::AsRawFd;
+
+    let (mut addr, addr_len) = std_addr_to_c(to);
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    // Large enough for a single control message carrying the segment size,
+    // and properly aligned for `cmsghdr`.
+    let mut control = [0u64; 4];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = addr_len;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen =
+        unsafe { libc::CMSG_SPACE(size_of::<u16>() as u32) } as _;
+
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+
+        (*cmsg).cmsg_level = libc::SOL_UDP;
+        (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u16>() as u32) as _;
+
+        std::ptr::write_unaligned(
+            libc::CMSG_DATA(cmsg) as *mut u16,
+            segment_size as u16,
+        );
+    }
+
+    let rc = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(rc as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_to_gso(
+    socket: &mio::net::UdpSocket, buf: &[u8], to: &net::SocketAddr,
+    segment_size: usize,
+) -> io::Result<usize> {
+    let mut written = 0;
+
+    for pkt in buf.chunks(segment_size) {
+        written += socket.send_to(pkt, to)?;
+    }
+
+    Ok(written)
+}
+
+#[cfg(target_os = "linux")]
+fn std_addr_to_c(
+    addr: &net::SocketAddr,
+) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+
+    let len = match addr {
+        net::SocketAddr::V4(addr) => {
+            let sin = unsafe {
+                &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in)
+            };
+
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr = libc::in_addr {
+                s_addr: u32::from_ne_bytes(addr.ip().octets()),
+            };
+
+            std::mem::size_of::<libc::sockaddr_in>()
+        },
+
+        net::SocketAddr::V6(addr) => {
+            let sin6 = unsafe {
+                &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6)
+            };
+
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr = libc::in6_addr {
+                s6_addr: addr.ip().octets(),
+            };
+            sin6.sin6_scope_id = addr.scope_id();
+
+            std::mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+
+    (storage, len as libc::socklen_t)
+}
@@ -75,6 +75,9 @@ pub fn run(
     let socket = std::net::UdpSocket::bind(bind_addr).unwrap();
 
     let socket = mio::net::UdpSocket::from_socket(socket).unwrap();
+
+    let local_addr = socket.local_addr().unwrap();
+
     poll.register(
         &socket,
         mio::Token(0),
@@ -123,7 +126,8 @@ pub fn run(
     let url = &test.endpoint();
 
     let mut conn =
-        quiche::connect(url.domain(), &scid, peer_addr, &mut config).unwrap();
+        quiche::connect(url.domain(), &scid, local_addr, peer_addr, &mut config)
+            .unwrap();
 
     if let Some(session_file) = &session_file {
         if let Ok(session) = std::fs::read(session_file) {
@@ -185,7 +189,11 @@ pub fn run(
 
             debug!("got {} bytes", len);
 
-            let recv_info = quiche::RecvInfo { from };
+            let recv_info = quiche::RecvInfo {
+                to: local_addr,
+                from,
+                ecn: 0,
+            };
 
             // Process potentially coalesced packets.
             let read = match conn.recv(&mut buf[..len], recv_info) {
@@ -339,6 +347,16 @@ pub fn run(
 
                     Ok((_goaway_id, quiche::h3::Event::GoAway)) => (),
 
+                    Ok((_, quiche::h3::Event::PushPromise { .. })) => (),
+
+                    Ok((_push_id, quiche::h3::Event::CancelPush)) => (),
+
+                    Ok((_, quiche::h3::Event::PriorityUpdate(_))) => (),
+
+                    Ok((_, quiche::h3::Event::WebTransportStream { .. })) => (),
+
+                    Ok((_, quiche::h3::Event::WebTransportSessionClosed { .. })) => (),
+
                     Err(quiche::h3::Error::Done) => {
                         break;
                     },
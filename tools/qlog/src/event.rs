@@ -443,6 +443,32 @@ impl Event {
         }
     }
 
+    // Security events.
+
+    /// Returns:
+    /// * `EventCategory`=`Security`
+    /// * `EventType`=`SecurityEventType::KeyUpdated`
+    /// * `EventData`=`KeyUpdated`.
+    pub fn key_updated(
+        key_type: KeyType, old: Option<String>, new: String,
+        generation: Option<u64>,
+    ) -> Self {
+        Event {
+            category: EventCategory::Security,
+            ty: EventType::SecurityEventType(SecurityEventType::KeyUpdated),
+            data: EventData::KeyUpdated {
+                key_type,
+                old,
+                new,
+                generation,
+            },
+        }
+    }
+
+    pub fn key_updated_min(key_type: KeyType, new: String) -> Self {
+        Event::key_updated(key_type, None, new, None)
+    }
+
     // Recovery events.
 
     /// Returns:
@@ -0,0 +1,426 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io;
+
+use std::future::poll_fn;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use tokio::net::ToSocketAddrs;
+
+use quiche::h3::NameValue;
+
+use crate::QuicConnection;
+use crate::QuicListener;
+
+/// An HTTP/3 request received by a server.
+#[derive(Clone, Debug)]
+pub struct Request {
+    /// The ID of the stream the request was received on, which is used to
+    /// send the response.
+    pub stream_id: u64,
+
+    /// The request's headers, including pseudo-headers.
+    pub headers: Vec<quiche::h3::Header>,
+
+    /// The request's body.
+    pub body: Vec<u8>,
+}
+
+/// An HTTP/3 response received by a client.
+#[derive(Clone, Debug)]
+pub struct Response {
+    /// The response's headers, including pseudo-headers.
+    pub headers: Vec<quiche::h3::Header>,
+
+    /// The response's body.
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// Returns the value of the `:status` pseudo-header, if any.
+    pub fn status(&self) -> Option<u16> {
+        self.headers
+            .iter()
+            .find(|h| h.name() == b":status")
+            .and_then(|h| std::str::from_utf8(h.value()).ok())
+            .and_then(|v| v.parse().ok())
+    }
+}
+
+#[derive(Default)]
+struct Stream {
+    headers: Option<Vec<quiche::h3::Header>>,
+
+    body: Vec<u8>,
+
+    finished: bool,
+
+    reset: Option<u64>,
+}
+
+struct State {
+    h3: quiche::h3::Connection,
+
+    is_server: bool,
+
+    /// Messages being received.
+    streams: HashMap<u64, Stream>,
+
+    /// Fully received requests, not yet returned by `next_request()`.
+    requests: VecDeque<u64>,
+}
+
+impl State {
+    /// Processes all the pending HTTP/3 events, buffering the messages
+    /// received on the request streams.
+    fn poll(&mut self, conn: &mut quiche::Connection) -> io::Result<()> {
+        let mut buf = [0; 65535];
+
+        loop {
+            let (stream_id, ev) = match self.h3.poll(conn) {
+                Ok(v) => v,
+
+                Err(quiche::h3::Error::Done) => break,
+
+                Err(e) => return Err(to_io_error(e)),
+            };
+
+            match ev {
+                quiche::h3::Event::Headers { list, .. } => {
+                    let stream = self.streams.entry(stream_id).or_default();
+
+                    // Trailers are not supported, only the first headers are
+                    // kept.
+                    if stream.headers.is_none() {
+                        stream.headers = Some(list);
+                    }
+                },
+
+                quiche::h3::Event::Data => {
+                    let stream = self.streams.entry(stream_id).or_default();
+
+                    while let Ok(read) =
+                        self.h3.recv_body(conn, stream_id, &mut buf)
+                    {
+                        stream.body.extend_from_slice(&buf[..read]);
+                    }
+                },
+
+                quiche::h3::Event::Finished => {
+                    self.streams.entry(stream_id).or_default().finished = true;
+
+                    if self.is_server {
+                        self.requests.push_back(stream_id);
+                    }
+                },
+
+                quiche::h3::Event::Reset(e) =>
+                    if self.is_server {
+                        self.streams.remove(&stream_id);
+                    } else {
+                        self.streams.entry(stream_id).or_default().reset =
+                            Some(e);
+                    },
+
+                quiche::h3::Event::Datagram => (),
+
+                quiche::h3::Event::GoAway => (),
+
+                quiche::h3::Event::PushPromise { .. } => (),
+
+                quiche::h3::Event::CancelPush => (),
+
+                quiche::h3::Event::PriorityUpdate(priority) => self
+                    .h3
+                    .set_stream_priority(conn, stream_id, priority)
+                    .map_err(to_io_error)?,
+
+                quiche::h3::Event::WebTransportStream { .. } => (),
+
+                quiche::h3::Event::WebTransportSessionClosed { .. } => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the given body, returning `Pending` until all of it was
+    /// written.
+    fn send_body(
+        &mut self, conn: &mut quiche::Connection, stream_id: u64, body: &[u8],
+        written: &mut usize,
+    ) -> Poll<io::Result<()>> {
+        while *written < body.len() {
+            match self.h3.send_body(conn, stream_id, &body[*written..], true) {
+                Ok(0) | Err(quiche::h3::Error::Done) => return Poll::Pending,
+
+                Ok(v) => *written += v,
+
+                Err(e) => return Poll::Ready(Err(to_io_error(e))),
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn to_io_error(e: quiche::h3::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// An HTTP/3 connection.
+///
+/// Clients send requests with [`send_request()`], while servers receive them
+/// with [`next_request()`] and reply with [`send_response()`]. Bodies are
+/// buffered in full in both directions.
+///
+/// The connection can be cloned to be used from multiple tasks, e.g. to send
+/// multiple requests concurrently.
+///
+/// [`send_request()`]: struct.Http3Connection.html#method.send_request
+/// [`next_request()`]: struct.Http3Connection.html#method.next_request
+/// [`send_response()`]: struct.Http3Connection.html#method.send_response
+#[derive(Clone)]
+pub struct Http3Connection {
+    quic: QuicConnection,
+
+    state: Arc<Mutex<State>>,
+}
+
+impl Http3Connection {
+    /// Connects to the given server address, and waits for the QUIC
+    /// handshake to complete.
+    ///
+    /// The configuration's application protocols should include HTTP/3.
+    pub async fn connect(
+        peer: SocketAddr, server_name: Option<&str>, config: &mut quiche::Config,
+    ) -> io::Result<Http3Connection> {
+        let quic = QuicConnection::connect(peer, server_name, config).await?;
+
+        Http3Connection::with_quic(quic)
+    }
+
+    /// Creates a new HTTP/3 connection on top of an established QUIC
+    /// connection.
+    pub fn with_quic(quic: QuicConnection) -> io::Result<Http3Connection> {
+        let h3_config = quiche::h3::Config::new().map_err(to_io_error)?;
+
+        let h3 = quic
+            .with_conn(|conn| {
+                quiche::h3::Connection::with_transport(conn, &h3_config)
+            })?
+            .map_err(to_io_error)?;
+
+        let state = State {
+            h3,
+            is_server: quic.is_server(),
+            streams: HashMap::new(),
+            requests: VecDeque::new(),
+        };
+
+        Ok(Http3Connection {
+            quic,
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    /// Returns the underlying QUIC connection.
+    pub fn quic(&self) -> &QuicConnection {
+        &self.quic
+    }
+
+    /// Sends a request with the given headers and body, and waits for the
+    /// response.
+    pub async fn send_request<T: NameValue>(
+        &self, headers: &[T], body: &[u8],
+    ) -> io::Result<Response> {
+        let mut stream_id = None;
+        let mut written = 0;
+
+        poll_fn(|cx| {
+            self.quic.poll_conn(cx, |conn| {
+                let mut state = self.state.lock().unwrap();
+
+                state.poll(conn)?;
+
+                let stream_id = match stream_id {
+                    Some(v) => v,
+
+                    None => {
+                        let v = match state.h3.send_request(
+                            conn,
+                            headers,
+                            body.is_empty(),
+                        ) {
+                            Ok(v) => v,
+
+                            Err(quiche::h3::Error::StreamBlocked) =>
+                                return Poll::Pending,
+
+                            Err(e) => return Poll::Ready(Err(to_io_error(e))),
+                        };
+
+                        state.streams.entry(v).or_default();
+
+                        *stream_id.insert(v)
+                    },
+                };
+
+                if let Poll::Ready(Err(e)) =
+                    state.send_body(conn, stream_id, body, &mut written)
+                {
+                    return Poll::Ready(Err(e));
+                }
+
+                let stream = state.streams.entry(stream_id).or_default();
+
+                if let Some(e) = stream.reset {
+                    state.streams.remove(&stream_id);
+
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        format!("stream reset with error code {}", e),
+                    )));
+                }
+
+                if !stream.finished {
+                    return Poll::Pending;
+                }
+
+                let stream = state.streams.remove(&stream_id).unwrap();
+
+                Poll::Ready(Ok(Response {
+                    headers: stream.headers.unwrap_or_default(),
+                    body: stream.body,
+                }))
+            })
+        })
+        .await
+    }
+
+    /// Waits for the next request to be fully received.
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn next_request(&self) -> Option<Request> {
+        poll_fn(|cx| {
+            self.quic.poll_conn(cx, |conn| {
+                let mut state = self.state.lock().unwrap();
+
+                state.poll(conn)?;
+
+                let stream_id = match state.requests.pop_front() {
+                    Some(v) => v,
+
+                    None => return Poll::Pending,
+                };
+
+                let stream = state.streams.remove(&stream_id).unwrap_or_default();
+
+                Poll::Ready(Ok(Request {
+                    stream_id,
+                    headers: stream.headers.unwrap_or_default(),
+                    body: stream.body,
+                }))
+            })
+        })
+        .await
+        .ok()
+    }
+
+    /// Sends a response with the given headers and body on the given
+    /// request stream, and waits for it to be fully written.
+    pub async fn send_response<T: NameValue>(
+        &self, stream_id: u64, headers: &[T], body: &[u8],
+    ) -> io::Result<()> {
+        let mut headers_sent = false;
+        let mut written = 0;
+
+        poll_fn(|cx| {
+            self.quic.poll_conn(cx, |conn| {
+                let mut state = self.state.lock().unwrap();
+
+                state.poll(conn)?;
+
+                if !headers_sent {
+                    match state.h3.send_response(
+                        conn,
+                        stream_id,
+                        headers,
+                        body.is_empty(),
+                    ) {
+                        Ok(()) => headers_sent = true,
+
+                        Err(quiche::h3::Error::StreamBlocked) =>
+                            return Poll::Pending,
+
+                        Err(e) => return Poll::Ready(Err(to_io_error(e))),
+                    }
+                }
+
+                state.send_body(conn, stream_id, body, &mut written)
+            })
+        })
+        .await
+    }
+}
+
+/// An HTTP/3 server, accepting incoming connections.
+pub struct Http3Listener {
+    quic: QuicListener,
+}
+
+impl Http3Listener {
+    /// Creates a new listener bound to the given address, accepting
+    /// connections with the given configuration.
+    ///
+    /// The configuration's application protocols should include HTTP/3.
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A, config: quiche::Config,
+    ) -> io::Result<Http3Listener> {
+        let quic = QuicListener::bind(addr, config).await?;
+
+        Ok(Http3Listener { quic })
+    }
+
+    /// Waits for a new connection to complete its handshake, and returns it.
+    pub async fn accept(&self) -> io::Result<Http3Connection> {
+        let quic = self.quic.accept().await?;
+
+        Http3Connection::with_quic(quic)
+    }
+
+    /// Returns the local address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.quic.local_addr()
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Tokio integration for quiche.
+//!
+//! This crate drives quiche connections on top of Tokio UDP sockets, so that
+//! applications don't need to take care of feeding packets to quiche, sending
+//! the generated ones and handling timers themselves.
+//!
+//! A [`QuicListener`] accepts incoming connections, while
+//! [`QuicConnection::connect()`] establishes outgoing ones. Streams are
+//! exposed as [`QuicStream`] objects implementing Tokio's [`AsyncRead`] and
+//! [`AsyncWrite`] traits.
+//!
+//! The [`Http3Listener`] and [`Http3Connection`] types provide a simple
+//! HTTP/3 client and server on top of those.
+//!
+//! ## Examples:
+//!
+//! ```no_run
+//! use tokio::io::AsyncReadExt;
+//! use tokio::io::AsyncWriteExt;
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+//! config.set_application_protos(b"\x05hello").unwrap();
+//!
+//! let peer = "127.0.0.1:4433".parse().unwrap();
+//!
+//! let conn = quiche_tokio::QuicConnection::connect(
+//!     peer,
+//!     Some("quic.tech"),
+//!     &mut config,
+//! )
+//! .await?;
+//!
+//! let mut stream = conn.open_bi()?;
+//! stream.write_all(b"hello").await?;
+//! stream.shutdown().await?;
+//!
+//! let mut reply = Vec::new();
+//! stream.read_to_end(&mut reply).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`QuicListener`]: struct.QuicListener.html
+//! [`QuicConnection::connect()`]: struct.QuicConnection.html#method.connect
+//! [`QuicStream`]: struct.QuicStream.html
+//! [`AsyncRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncRead.html
+//! [`AsyncWrite`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html
+//! [`Http3Listener`]: struct.Http3Listener.html
+//! [`Http3Connection`]: struct.Http3Connection.html
+
+#[macro_use]
+extern crate log;
+
+pub use crate::http3::Http3Connection;
+pub use crate::http3::Http3Listener;
+pub use crate::http3::Request;
+pub use crate::http3::Response;
+
+pub use crate::quic::QuicConnection;
+pub use crate::quic::QuicListener;
+pub use crate::quic::QuicStream;
+
+mod http3;
+mod quic;
@@ -0,0 +1,858 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::io;
+
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+use std::time::Duration;
+
+use std::net::SocketAddr;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+
+use ring::rand::SecureRandom;
+
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+
+use tokio::net::ToSocketAddrs;
+use tokio::net::UdpSocket;
+
+use tokio::sync::Notify;
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// How long the driver sleeps for when no timer is armed.
+const IDLE_SLEEP: Duration = Duration::from_secs(3600);
+
+/// Per-connection state used by the application's handles.
+struct Slot {
+    /// Tasks waiting for the connection's state to change.
+    wakers: Vec<Waker>,
+
+    /// Peer-initiated streams already returned by `accept_stream()`.
+    accepted_streams: HashSet<u64>,
+
+    next_bidi_stream: u64,
+
+    next_uni_stream: u64,
+
+    /// Whether the connection was queued to be returned by `accept()`.
+    queued: bool,
+}
+
+impl Slot {
+    fn new(is_server: bool) -> Slot {
+        Slot {
+            wakers: Vec::new(),
+
+            accepted_streams: HashSet::new(),
+
+            next_bidi_stream: is_server as u64,
+
+            next_uni_stream: 2 + is_server as u64,
+
+            queued: false,
+        }
+    }
+
+    fn register(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    fn wake(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+enum Kind {
+    Client(Pin<Box<quiche::Connection>>),
+
+    Server {
+        endpoint: Box<quiche::Endpoint>,
+
+        keys: HashMap<quiche::ConnectionHandle, u64>,
+
+        handles: HashMap<u64, quiche::ConnectionHandle>,
+    },
+}
+
+/// State shared between the driver task and the application's handles.
+struct Shared {
+    kind: Kind,
+
+    slots: HashMap<u64, Slot>,
+
+    next_key: u64,
+
+    /// Established connections waiting to be returned by `accept()`.
+    accept_queue: VecDeque<u64>,
+
+    accept_waker: Option<Waker>,
+
+    /// Whether the listener was dropped, or there is none.
+    listener_closed: bool,
+}
+
+impl Shared {
+    /// Returns the connection with the given key, unless it was closed.
+    fn get(&mut self, key: u64) -> Option<(&mut quiche::Connection, &mut Slot)> {
+        let slot = self.slots.get_mut(&key)?;
+
+        let conn = match &mut self.kind {
+            Kind::Client(conn) => &mut **conn,
+
+            Kind::Server {
+                endpoint, handles, ..
+            } => endpoint.conn_mut(*handles.get(&key)?)?,
+        };
+
+        if conn.is_closed() {
+            return None;
+        }
+
+        Some((conn, slot))
+    }
+
+    fn recv(&mut self, buf: &mut [u8], info: quiche::RecvInfo) {
+        let res = match &mut self.kind {
+            Kind::Client(conn) => conn.recv(buf, info),
+
+            Kind::Server { endpoint, .. } => endpoint.recv(buf, info),
+        };
+
+        if let Err(e) = res {
+            debug!("recv failed: {:?}", e);
+        }
+    }
+
+    fn send(
+        &mut self, out: &mut [u8],
+    ) -> quiche::Result<(usize, quiche::SendInfo)> {
+        match &mut self.kind {
+            Kind::Client(conn) => conn.send(out),
+
+            Kind::Server { endpoint, .. } => endpoint.send(out),
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        match &self.kind {
+            Kind::Client(conn) => conn.timeout(),
+
+            Kind::Server { endpoint, .. } => endpoint.timeout(),
+        }
+    }
+
+    fn on_timeout(&mut self) {
+        match &mut self.kind {
+            Kind::Client(conn) => conn.on_timeout(),
+
+            Kind::Server { endpoint, .. } => endpoint.on_timeout(),
+        }
+    }
+
+    /// Wakes the tasks waiting on connections whose state changed, after
+    /// packets were received or timers fired.
+    fn process(&mut self) {
+        let (endpoint, keys, handles) = match &mut self.kind {
+            Kind::Client(_) => {
+                self.slots.values_mut().for_each(|s| s.wake());
+
+                return;
+            },
+
+            Kind::Server {
+                endpoint,
+                keys,
+                handles,
+            } => (endpoint, keys, handles),
+        };
+
+        while let Some(ev) = endpoint.event_next() {
+            match ev {
+                quiche::EndpointEvent::New(handle) => {
+                    let key = self.next_key;
+                    self.next_key += 1;
+
+                    keys.insert(handle, key);
+                    handles.insert(key, handle);
+
+                    self.slots.insert(key, Slot::new(true));
+                },
+
+                quiche::EndpointEvent::Updated(handle) => {
+                    let key = match keys.get(&handle) {
+                        Some(v) => *v,
+
+                        None => continue,
+                    };
+
+                    let slot = match self.slots.get_mut(&key) {
+                        Some(v) => v,
+
+                        None => continue,
+                    };
+
+                    slot.wake();
+
+                    let established =
+                        endpoint.conn(handle).is_some_and(|c| c.is_established());
+
+                    if established && !slot.queued {
+                        slot.queued = true;
+
+                        self.accept_queue.push_back(key);
+
+                        if let Some(waker) = self.accept_waker.take() {
+                            waker.wake();
+                        }
+                    }
+                },
+
+                quiche::EndpointEvent::Closed(handle, conn) => {
+                    debug!(
+                        "{} connection collected {:?}",
+                        conn.trace_id(),
+                        conn.stats()
+                    );
+
+                    if let Some(key) = keys.remove(&handle) {
+                        handles.remove(&key);
+
+                        if let Some(mut slot) = self.slots.remove(&key) {
+                            slot.wake();
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns true when the driver has nothing left to do.
+    fn is_done(&self) -> bool {
+        match &self.kind {
+            Kind::Client(conn) => conn.is_closed(),
+
+            Kind::Server { endpoint, .. } =>
+                self.listener_closed && endpoint.is_empty(),
+        }
+    }
+}
+
+/// Feeds packets received on the socket to quiche, sends the packets it
+/// generates and handles its timers.
+async fn drive(
+    shared: Arc<Mutex<Shared>>, notify: Arc<Notify>, socket: UdpSocket,
+) {
+    let mut buf = vec![0; 65535];
+    let mut out = vec![0; MAX_DATAGRAM_SIZE];
+
+    let local = match socket.local_addr() {
+        Ok(v) => v,
+
+        Err(e) => {
+            error!("failed to get local address: {:?}", e);
+            return;
+        },
+    };
+
+    loop {
+        loop {
+            let res = shared.lock().unwrap().send(&mut out);
+
+            let (write, send_info) = match res {
+                Ok(v) => v,
+
+                Err(quiche::Error::Done) => break,
+
+                Err(e) => {
+                    error!("send failed: {:?}", e);
+                    break;
+                },
+            };
+
+            if let Err(e) = socket.send_to(&out[..write], send_info.to).await {
+                debug!("send_to() failed: {:?}", e);
+            }
+        }
+
+        let timeout = {
+            let shared = shared.lock().unwrap();
+
+            if shared.is_done() {
+                break;
+            }
+
+            shared.timeout().unwrap_or(IDLE_SLEEP)
+        };
+
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                let mut shared = shared.lock().unwrap();
+
+                let mut res = res;
+
+                // Read all the packets that are already queued on the socket.
+                while let Ok((len, from)) = res {
+                    let info = quiche::RecvInfo {
+                        from,
+                        to: local,
+                        ecn: 0,
+                    };
+
+                    shared.recv(&mut buf[..len], info);
+
+                    res = socket.try_recv_from(&mut buf);
+                }
+
+                shared.process();
+            },
+
+            _ = tokio::time::sleep(timeout) => {
+                let mut shared = shared.lock().unwrap();
+
+                shared.on_timeout();
+                shared.process();
+            },
+
+            // The application did something that might need packets to be
+            // sent.
+            _ = notify.notified() => (),
+        }
+    }
+}
+
+fn to_io_error(e: quiche::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn not_connected() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "connection closed")
+}
+
+/// A QUIC server, accepting incoming connections.
+///
+/// Packets are processed by a background task, which requires a Tokio
+/// runtime, and keeps running until the listener is dropped and all of its
+/// connections are closed.
+pub struct QuicListener {
+    shared: Arc<Mutex<Shared>>,
+
+    notify: Arc<Notify>,
+
+    local_addr: SocketAddr,
+}
+
+impl QuicListener {
+    /// Creates a new listener bound to the given address, accepting
+    /// connections with the given configuration.
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A, config: quiche::Config,
+    ) -> io::Result<QuicListener> {
+        QuicListener::bind_with_endpoint(addr, quiche::Endpoint::new(config))
+            .await
+    }
+
+    /// Creates a new listener bound to the given address, accepting
+    /// connections with the given endpoint.
+    ///
+    /// This can be used to configure the endpoint's policies, e.g. whether
+    /// stateless retry is used.
+    pub async fn bind_with_endpoint<A: ToSocketAddrs>(
+        addr: A, endpoint: quiche::Endpoint,
+    ) -> io::Result<QuicListener> {
+        let socket = UdpSocket::bind(addr).await?;
+        let local_addr = socket.local_addr()?;
+
+        let shared = Arc::new(Mutex::new(Shared {
+            kind: Kind::Server {
+                endpoint: Box::new(endpoint),
+                keys: HashMap::new(),
+                handles: HashMap::new(),
+            },
+
+            slots: HashMap::new(),
+
+            next_key: 0,
+
+            accept_queue: VecDeque::new(),
+
+            accept_waker: None,
+
+            listener_closed: false,
+        }));
+
+        let notify = Arc::new(Notify::new());
+
+        tokio::spawn(drive(shared.clone(), notify.clone(), socket));
+
+        Ok(QuicListener {
+            shared,
+            notify,
+            local_addr,
+        })
+    }
+
+    /// Waits for a new connection to complete its handshake, and returns it.
+    pub async fn accept(&self) -> io::Result<QuicConnection> {
+        poll_fn(|cx| {
+            let mut shared = self.shared.lock().unwrap();
+
+            while let Some(key) = shared.accept_queue.pop_front() {
+                // The connection might have been closed in the meantime.
+                if shared.slots.contains_key(&key) {
+                    return Poll::Ready(Ok(QuicConnection::new(
+                        self.shared.clone(),
+                        self.notify.clone(),
+                        key,
+                        true,
+                    )));
+                }
+            }
+
+            shared.accept_waker = Some(cx.waker().clone());
+
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Returns the local address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for QuicListener {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.listener_closed = true;
+        }
+
+        self.notify.notify_one();
+    }
+}
+
+struct ConnRef {
+    shared: Arc<Mutex<Shared>>,
+
+    notify: Arc<Notify>,
+
+    key: u64,
+
+    is_server: bool,
+}
+
+impl Drop for ConnRef {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            if let Some((conn, _)) = shared.get(self.key) {
+                conn.close(true, 0x0, b"").ok();
+            }
+        }
+
+        self.notify.notify_one();
+    }
+}
+
+/// A QUIC connection.
+///
+/// The connection can be cloned to be used from multiple tasks, and is closed
+/// with a `NO_ERROR` application error code once all of its clones and
+/// streams are dropped.
+#[derive(Clone)]
+pub struct QuicConnection {
+    inner: Arc<ConnRef>,
+}
+
+impl QuicConnection {
+    fn new(
+        shared: Arc<Mutex<Shared>>, notify: Arc<Notify>, key: u64,
+        is_server: bool,
+    ) -> QuicConnection {
+        QuicConnection {
+            inner: Arc::new(ConnRef {
+                shared,
+                notify,
+                key,
+                is_server,
+            }),
+        }
+    }
+
+    /// Connects to the given server address, and waits for the handshake to
+    /// complete.
+    ///
+    /// The `server_name` parameter is used to verify the peer's certificate.
+    /// A new UDP socket bound to an ephemeral port is used for the
+    /// connection.
+    pub async fn connect(
+        peer: SocketAddr, server_name: Option<&str>, config: &mut quiche::Config,
+    ) -> io::Result<QuicConnection> {
+        let bind_addr: SocketAddr = if peer.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        }
+        .parse()
+        .unwrap();
+
+        let socket = UdpSocket::bind(bind_addr).await?;
+        let local = socket.local_addr()?;
+
+        let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+        ring::rand::SystemRandom::new()
+            .fill(&mut scid[..])
+            .map_err(|_| io::Error::other("rand failed"))?;
+
+        let scid = quiche::ConnectionId::from_ref(&scid);
+
+        let conn = quiche::connect(server_name, &scid, local, peer, config)
+            .map_err(to_io_error)?;
+
+        let mut slots = HashMap::new();
+        slots.insert(0, Slot::new(false));
+
+        let shared = Arc::new(Mutex::new(Shared {
+            kind: Kind::Client(conn),
+
+            slots,
+
+            next_key: 1,
+
+            accept_queue: VecDeque::new(),
+
+            accept_waker: None,
+
+            listener_closed: true,
+        }));
+
+        let notify = Arc::new(Notify::new());
+
+        tokio::spawn(drive(shared.clone(), notify.clone(), socket));
+
+        let conn = QuicConnection::new(shared, notify, 0, false);
+
+        poll_fn(|cx| {
+            conn.poll_conn(cx, |conn| {
+                if conn.is_established() {
+                    return Poll::Ready(Ok(()));
+                }
+
+                Poll::Pending
+            })
+        })
+        .await?;
+
+        Ok(conn)
+    }
+
+    /// Opens a new bidirectional stream.
+    ///
+    /// The stream is only created on the wire once data is written to it.
+    pub fn open_bi(&self) -> io::Result<QuicStream> {
+        let mut shared = self.inner.shared.lock().unwrap();
+
+        let (_, slot) = shared.get(self.inner.key).ok_or_else(not_connected)?;
+
+        let stream_id = slot.next_bidi_stream;
+        slot.next_bidi_stream += 4;
+
+        Ok(QuicStream::new(self.clone(), stream_id))
+    }
+
+    /// Opens a new unidirectional stream.
+    ///
+    /// The stream is only created on the wire once data is written to it.
+    pub fn open_uni(&self) -> io::Result<QuicStream> {
+        let mut shared = self.inner.shared.lock().unwrap();
+
+        let (_, slot) = shared.get(self.inner.key).ok_or_else(not_connected)?;
+
+        let stream_id = slot.next_uni_stream;
+        slot.next_uni_stream += 4;
+
+        Ok(QuicStream::new(self.clone(), stream_id))
+    }
+
+    /// Waits for the peer to open a new stream, and returns it.
+    ///
+    /// Streams are reported once the first data (or `fin`) is received on
+    /// them.
+    pub async fn accept_stream(&self) -> io::Result<QuicStream> {
+        let is_server = self.inner.is_server;
+
+        let stream_id = poll_fn(|cx| {
+            self.poll_with(cx, |conn, slot| {
+                let peer_stream = conn.readable().find(|id| {
+                    (id & 0x1 == 0) == is_server &&
+                        slot.accepted_streams.insert(*id)
+                });
+
+                match peer_stream {
+                    Some(v) => Poll::Ready(Ok(v)),
+
+                    None => Poll::Pending,
+                }
+            })
+        })
+        .await?;
+
+        Ok(QuicStream::new(self.clone(), stream_id))
+    }
+
+    /// Closes the connection with the given application error code and
+    /// reason.
+    pub fn close(&self, err: u64, reason: &[u8]) -> io::Result<()> {
+        self.with_conn(|conn| conn.close(true, err, reason))?
+            .map_err(to_io_error)
+    }
+
+    /// Waits for the connection to be closed, either locally or by the peer.
+    pub async fn closed(&self) {
+        poll_fn(|cx| {
+            self.poll_with(cx, |_, _| Poll::<io::Result<()>>::Pending)
+                .map(|_| ())
+        })
+        .await
+    }
+
+    /// Calls the given function with the underlying quiche connection.
+    ///
+    /// This can be used to access parts of the quiche API that are not
+    /// exposed otherwise, e.g. connection statistics.
+    pub fn with_conn<R>(
+        &self, f: impl FnOnce(&mut quiche::Connection) -> R,
+    ) -> io::Result<R> {
+        let mut shared = self.inner.shared.lock().unwrap();
+
+        let (conn, _) = shared.get(self.inner.key).ok_or_else(not_connected)?;
+
+        let res = f(conn);
+
+        drop(shared);
+
+        self.inner.notify.notify_one();
+
+        Ok(res)
+    }
+
+    pub(crate) fn is_server(&self) -> bool {
+        self.inner.is_server
+    }
+
+    /// Calls the given function with the underlying quiche connection, and
+    /// registers the task to be woken up when the connection's state changes
+    /// if it returns `Pending`.
+    pub(crate) fn poll_conn<R>(
+        &self, cx: &mut Context,
+        f: impl FnOnce(&mut quiche::Connection) -> Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        self.poll_with(cx, |conn, _| f(conn))
+    }
+
+    fn poll_with<R>(
+        &self, cx: &mut Context,
+        f: impl FnOnce(&mut quiche::Connection, &mut Slot) -> Poll<io::Result<R>>,
+    ) -> Poll<io::Result<R>> {
+        let mut shared = self.inner.shared.lock().unwrap();
+
+        let (conn, slot) = match shared.get(self.inner.key) {
+            Some(v) => v,
+
+            None => return Poll::Ready(Err(not_connected())),
+        };
+
+        let res = f(conn, slot);
+
+        if res.is_pending() {
+            slot.register(cx.waker());
+        }
+
+        drop(shared);
+
+        // The operation might have generated packets to be sent, even if it
+        // is still pending.
+        self.inner.notify.notify_one();
+
+        res
+    }
+}
+
+/// A QUIC stream.
+///
+/// Data is read and written with the [`AsyncRead`] and [`AsyncWrite`]
+/// traits. Shutting down the stream for writing sends a `fin` to the peer.
+///
+/// [`AsyncRead`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncRead.html
+/// [`AsyncWrite`]: https://docs.rs/tokio/1/tokio/io/trait.AsyncWrite.html
+pub struct QuicStream {
+    conn: QuicConnection,
+
+    stream_id: u64,
+
+    read_fin: bool,
+
+    write_fin: bool,
+}
+
+impl QuicStream {
+    fn new(conn: QuicConnection, stream_id: u64) -> QuicStream {
+        QuicStream {
+            conn,
+            stream_id,
+            read_fin: false,
+            write_fin: false,
+        }
+    }
+
+    /// Returns the stream's ID.
+    pub fn id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Returns the connection the stream belongs to.
+    pub fn conn(&self) -> &QuicConnection {
+        &self.conn
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.read_fin || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let stream_id = this.stream_id;
+        let read_fin = &mut this.read_fin;
+
+        this.conn.poll_conn(cx, |conn| {
+            match conn.stream_recv(stream_id, buf.initialize_unfilled()) {
+                Ok((len, fin)) => {
+                    buf.advance(len);
+
+                    *read_fin = fin;
+
+                    Poll::Ready(Ok(()))
+                },
+
+                // Locally initiated streams don't exist until data is
+                // written to them.
+                Err(quiche::Error::Done) |
+                Err(quiche::Error::InvalidStreamState(_)) => Poll::Pending,
+
+                Err(quiche::Error::StreamReset(e)) =>
+                    Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::ConnectionReset,
+                        format!("stream reset with error code {}", e),
+                    ))),
+
+                Err(e) => Poll::Ready(Err(to_io_error(e))),
+            }
+        })
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        self: Pin<&mut Self>, cx: &mut Context, buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        let stream_id = self.stream_id;
+
+        self.conn.poll_conn(cx, |conn| {
+            match conn.stream_send(stream_id, buf, false) {
+                Ok(0) | Err(quiche::Error::Done) => Poll::Pending,
+
+                Ok(v) => Poll::Ready(Ok(v)),
+
+                Err(quiche::Error::StreamStopped(e)) =>
+                    Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        format!("stream stopped with error code {}", e),
+                    ))),
+
+                Err(e) => Poll::Ready(Err(to_io_error(e))),
+            }
+        })
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>, _cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        // Data is sent by the driver as soon as possible.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>, cx: &mut Context,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.write_fin {
+            return Poll::Ready(Ok(()));
+        }
+
+        let stream_id = this.stream_id;
+
+        let res = this.conn.poll_conn(cx, |conn| {
+            match conn.stream_send(stream_id, b"", true) {
+                Ok(_) => Poll::Ready(Ok(())),
+
+                Err(quiche::Error::Done) => Poll::Pending,
+
+                Err(e) => Poll::Ready(Err(to_io_error(e))),
+            }
+        });
+
+        if let Poll::Ready(Ok(())) = res {
+            this.write_fin = true;
+        }
+
+        res
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (C) 2021, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+
+use quiche::h3::NameValue;
+
+use quiche_tokio::Http3Connection;
+use quiche_tokio::Http3Listener;
+use quiche_tokio::QuicConnection;
+use quiche_tokio::QuicListener;
+
+const CERT: &str =
+    concat!(env!("CARGO_MANIFEST_DIR"), "/../../examples/cert.crt");
+const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../examples/cert.key");
+
+// Requests over loopback complete much faster than the idle timeout, so a
+// request that takes longer than this is stalled.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn config(alpn: &[u8], is_server: bool) -> quiche::Config {
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+    if is_server {
+        config.load_cert_chain_from_pem_file(CERT).unwrap();
+        config.load_priv_key_from_pem_file(KEY).unwrap();
+    } else {
+        config.verify_peer(false);
+    }
+
+    config.set_application_protos(alpn).unwrap();
+    config.set_max_idle_timeout(5000);
+    config.set_initial_max_data(10_000_000);
+    config.set_initial_max_stream_data_bidi_local(1_000_000);
+    config.set_initial_max_stream_data_bidi_remote(1_000_000);
+    config.set_initial_max_stream_data_uni(1_000_000);
+    config.set_initial_max_streams_bidi(100);
+    config.set_initial_max_streams_uni(100);
+
+    config
+}
+
+#[tokio::test]
+async fn stream_echo() {
+    let listener = QuicListener::bind("127.0.0.1:0", config(b"\x04echo", true))
+        .await
+        .unwrap();
+
+    let peer = listener.local_addr();
+
+    tokio::spawn(async move {
+        let conn = listener.accept().await.unwrap();
+
+        loop {
+            let mut stream = match conn.accept_stream().await {
+                Ok(v) => v,
+
+                Err(_) => break,
+            };
+
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await.unwrap();
+
+                stream.write_all(&buf).await.unwrap();
+                stream.shutdown().await.unwrap();
+            });
+        }
+    });
+
+    let mut config = config(b"\x04echo", false);
+    let conn = QuicConnection::connect(peer, None, &mut config)
+        .await
+        .unwrap();
+
+    // Large enough to span many packets and flow control updates.
+    let data: Vec<u8> = (0..3_000_000).map(|i| i as u8).collect();
+
+    for _ in 0..2 {
+        let mut stream = conn.open_bi().unwrap();
+
+        stream.write_all(&data).await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.unwrap();
+
+        assert_eq!(reply, data);
+    }
+
+    conn.close(0x0, b"bye").unwrap();
+    conn.closed().await;
+
+    assert!(conn.open_bi().is_err());
+}
+
+#[tokio::test]
+async fn http3_request() {
+    let listener = Http3Listener::bind("127.0.0.1:0", config(b"\x02h3", true))
+        .await
+        .unwrap();
+
+    let peer = listener.local_addr();
+
+    tokio::spawn(async move {
+        let conn = listener.accept().await.unwrap();
+
+        while let Some(req) = conn.next_request().await {
+            let conn = conn.clone();
+
+            tokio::spawn(async move {
+                let path = req
+                    .headers
+                    .iter()
+                    .find(|h| h.name() == b":path")
+                    .unwrap()
+                    .value()
+                    .to_vec();
+
+                let mut body = path;
+                body.extend_from_slice(&req.body);
+
+                let headers = [
+                    quiche::h3::Header::new(b":status", b"200"),
+                    quiche::h3::Header::new(
+                        b"content-length",
+                        body.len().to_string().as_bytes(),
+                    ),
+                ];
+
+                conn.send_response(req.stream_id, &headers, &body)
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    let mut config = config(b"\x02h3", false);
+    let conn = Http3Connection::connect(peer, None, &mut config)
+        .await
+        .unwrap();
+
+    let request = |method: &'static [u8], path: &'static [u8]| {
+        vec![
+            quiche::h3::Header::new(b":method", method),
+            quiche::h3::Header::new(b":scheme", b"https"),
+            quiche::h3::Header::new(b":authority", b"quic.tech"),
+            quiche::h3::Header::new(b":path", path),
+        ]
+    };
+
+    // Sequential requests, each of which waits for the response after
+    // queueing the request.
+    for _ in 0..20 {
+        let res = tokio::time::timeout(
+            REQUEST_TIMEOUT,
+            conn.send_request(&request(b"GET", b"/"), b""),
+        )
+        .await
+        .expect("request stalled")
+        .unwrap();
+
+        assert_eq!(res.status(), Some(200));
+        assert_eq!(res.body, b"/");
+    }
+
+    // Concurrent requests with bodies.
+    let post_body = vec![42; 100_000];
+
+    let req_a = request(b"POST", b"/a");
+    let req_b = request(b"POST", b"/b");
+
+    let (a, b) = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        tokio::join!(
+            conn.send_request(&req_a, &post_body),
+            conn.send_request(&req_b, b"hello"),
+        )
+    })
+    .await
+    .expect("requests stalled");
+
+    let a = a.unwrap();
+    assert_eq!(a.status(), Some(200));
+    assert_eq!(&a.body[..2], b"/a");
+    assert_eq!(&a.body[2..], &post_body[..]);
+
+    let b = b.unwrap();
+    assert_eq!(b.status(), Some(200));
+    assert_eq!(b.body, b"/bhello");
+
+    conn.quic().close(0x100, b"").unwrap();
+    conn.quic().closed().await;
+}